@@ -1,22 +1,42 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::config::{AddressType, NgAccountBackup, NgAccountConfig, NgDescriptor};
+use crate::DEFAULT_STOP_GAP;
+use crate::config::{AddressType, Birthday, NgAccountBackup, NgAccountConfig, NgDescriptor};
 use crate::db::RedbMetaStorage;
+use crate::dust::DustPolicy;
+use crate::error::{AccountError, MutexExt, RwLockExt, SyncError};
+use crate::events::{AccountEvent, EventBus};
+use crate::fee_rate::FeeRateSatPerKvb;
+use crate::fiat::FiatValue;
+use crate::journal;
+use crate::lazy::LazyWallets;
 use crate::ngwallet::NgWallet;
+#[cfg(feature = "envoy")]
+use crate::ngwallet::ProgressCallback;
+use crate::package;
+use crate::slip132;
+use crate::spk_index;
 use crate::store::MetaStorage;
-use crate::transaction::{BitcoinTransaction, Output};
+use crate::tags;
+use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output, TxType};
 use crate::utils;
 use crate::utils::get_address_type;
-use anyhow::{Context, Error, anyhow};
+use anyhow::{Context, Error};
 use bdk_wallet::bitcoin::address::{NetworkChecked, NetworkUnchecked};
-use bdk_wallet::bitcoin::{Address, Amount, Network, Psbt, Transaction, Txid};
-#[cfg(feature = "envoy")]
-use bdk_wallet::chain::spk_client::FullScanRequest;
+use bdk_wallet::bitcoin::hashes::{Hash, sha256};
+use bdk_wallet::bitcoin::{Address, Amount, BlockHash, Network, OutPoint, Psbt, Transaction, Txid};
 #[cfg(feature = "envoy")]
 use bdk_wallet::chain::spk_client::SyncRequest;
+#[cfg(feature = "envoy")]
+use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse};
+use bdk_wallet::miniscript::{DescriptorPublicKey, ForEachKey};
 use bdk_wallet::{AddressInfo, Balance, KeychainKind, Update, WalletPersister};
 use serde::{Deserialize, Serialize};
 
@@ -25,6 +45,13 @@ pub struct NgAccount<P: WalletPersister> {
     pub config: Arc<RwLock<NgAccountConfig>>,
     pub wallets: Arc<RwLock<Vec<NgWallet<P>>>>,
     pub meta_storage: Arc<dyn MetaStorage>,
+    pub(crate) events: EventBus,
+    /// Wallets opened on demand, `None` unless the account was opened
+    /// with [`Self::open_account_lazy`].
+    pub(crate) lazy: Arc<Mutex<Option<LazyWallets<P>>>>,
+    /// Set by [`Self::lock`], see [`crate::lock`].
+    pub(crate) locked: Arc<AtomicBool>,
+    pub(crate) query_cache: Arc<Mutex<AccountQueryCache>>,
 }
 
 impl<P: WalletPersister> Clone for NgAccount<P> {
@@ -33,10 +60,23 @@ impl<P: WalletPersister> Clone for NgAccount<P> {
             config: self.config.clone(),
             wallets: self.wallets.clone(),
             meta_storage: self.meta_storage.clone(),
+            events: self.events.clone(),
+            lazy: self.lazy.clone(),
+            locked: self.locked.clone(),
+            query_cache: self.query_cache.clone(),
         }
     }
 }
 
+/// Results of [`NgAccount::transactions`] and [`NgAccount::utxos`], with
+/// the generations of the wallet caches they were built from. A result is
+/// stale once any wallet cache is refreshed, or a wallet opened or closed.
+#[derive(Debug, Default)]
+pub(crate) struct AccountQueryCache {
+    transactions: Option<(Vec<(AddressType, u64)>, Vec<BitcoinTransaction>)>,
+    utxos: Option<(Vec<(AddressType, u64)>, Vec<Output>)>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Descriptor<P: WalletPersister> {
     pub internal: String,
@@ -57,6 +97,37 @@ impl<P: WalletPersister> fmt::Debug for Descriptor<P> {
     }
 }
 
+/// Number of the [`AppliedUpdate`]s an account keeps, the most recent by
+/// sequence. An older update replayed is rejected as not newer instead of
+/// skipped.
+pub const MAX_APPLIED_UPDATES: usize = 64;
+
+/// A [`RemoteUpdate`] applied by [`NgAccount::update`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedUpdate {
+    /// SHA-256 of the payload, hex encoded.
+    pub hash: String,
+    pub sequence: u64,
+    /// Unix time the update was applied, in seconds.
+    pub applied_at: u64,
+}
+
+/// Unspent outputs of an account by what keeps them from being spent, in
+/// sats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendableBalance {
+    /// Every unspent output, locked or not.
+    pub total: u64,
+    /// Outputs with their own do-not-spend flag set.
+    pub locked_by_do_not_spend: u64,
+    /// Outputs locked only by the do-not-spend policy of their tag.
+    pub locked_by_tag_policy: u64,
+    /// Unlocked outputs still in the mempool, spendable but not final.
+    pub unconfirmed: u64,
+    /// Everything not locked, what a spend can use.
+    pub spendable: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RemoteUpdate {
     /// Must match the target account's `id`. Prevents cross-account injection.
@@ -101,6 +172,56 @@ impl RemoteUpdate {
 }
 
 pub fn get_persister_file_name(internal: &str, external: Option<&str>) -> String {
+    persister_file_name(internal, external, true)
+}
+
+/// Name of the persister file of descriptors as written, used before
+/// [`get_persister_file_name`] normalized them.
+pub(crate) fn legacy_persister_file_name(internal: &str, external: Option<&str>) -> String {
+    persister_file_name(internal, external, false)
+}
+
+/// [`get_persister_file_name`] of the descriptors, after renaming their
+/// file in `account_path` from its [`legacy_persister_file_name`]. Wallets
+/// persisted before the names were normalized would otherwise open an empty
+/// database, so this must run before the file is opened.
+pub fn migrate_persister_file(
+    account_path: &str,
+    internal: &str,
+    external: Option<&str>,
+) -> anyhow::Result<String> {
+    let file_name = get_persister_file_name(internal, external);
+    let legacy = legacy_persister_file_name(internal, external);
+    let dir = Path::new(account_path);
+    if legacy != file_name && !dir.join(&file_name).exists() && dir.join(&legacy).exists() {
+        // the SQLite journals follow their database
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let from = dir.join(format!("{legacy}{suffix}"));
+            if from.exists() {
+                std::fs::rename(&from, dir.join(format!("{file_name}{suffix}")))
+                    .with_context(|| format!("Failed to rename {}", from.display()))?;
+            }
+        }
+    }
+    Ok(file_name)
+}
+
+fn persister_file_name(internal: &str, external: Option<&str>, normalized: bool) -> String {
+    // a multipath descriptor shares the file of its receive and change pair
+    if external.is_none()
+        && let Ok(Some((external, internal))) = utils::split_multipath_descriptor(internal)
+    {
+        return persister_file_name(&internal, Some(&external), normalized);
+    }
+    // the name ends with the checksums, so spellings of the same descriptor
+    // share a file
+    let normalize = |descriptor: &str| match normalized {
+        true => utils::normalize_descriptor(descriptor).unwrap_or(descriptor.to_string()),
+        false => descriptor.to_string(),
+    };
+    let internal = normalize(internal);
+    let external = external.map(normalize);
+    let (internal, external) = (internal.as_str(), external.as_deref());
     fn get_last_eight_chars(s: &str) -> Option<String> {
         if s.chars().count() >= 6 {
             Some(s.chars().skip(s.chars().count() - 6).collect())
@@ -157,6 +278,10 @@ impl<P: WalletPersister> NgAccount<P> {
             config: Arc::new(RwLock::new(account_config)),
             wallets: Arc::new(RwLock::new(wallets)),
             meta_storage: meta,
+            events: Default::default(),
+            lazy: Default::default(),
+            locked: Default::default(),
+            query_cache: Default::default(),
         })
     }
 
@@ -171,6 +296,20 @@ impl<P: WalletPersister> NgAccount<P> {
         Self::open_account(descriptors, Arc::new(meta_storage))
     }
 
+    /// Open an account built with
+    /// [`NgAccountBuilder::build_in_namespace`](crate::config::NgAccountBuilder::build_in_namespace).
+    pub fn open_account_in_namespace(
+        descriptors: Vec<Descriptor<P>>,
+        db_path: Option<String>,
+        namespace: &str,
+    ) -> anyhow::Result<Self>
+    where
+        <P as WalletPersister>::Error: Debug,
+    {
+        let meta_storage = RedbMetaStorage::from_file_in_namespace(db_path, namespace)?;
+        Self::open_account(descriptors, Arc::new(meta_storage))
+    }
+
     pub fn open_account_from_db(
         descriptors: Vec<Descriptor<P>>,
         db: redb::Database,
@@ -189,10 +328,12 @@ impl<P: WalletPersister> NgAccount<P> {
     where
         <P as WalletPersister>::Error: Debug,
     {
+        journal::recover_persist(meta_storage.as_ref(), &descriptors)
+            .with_context(|| "Failed to recover the last persist")?;
         let config = meta_storage
             .get_config()
             .with_context(|| "Failed to get load account config")?
-            .ok_or(anyhow::anyhow!("Account config not found"))?;
+            .ok_or(AccountError::ConfigNotFound)?;
 
         let mut wallets: Vec<NgWallet<P>> = vec![];
 
@@ -211,28 +352,43 @@ impl<P: WalletPersister> NgAccount<P> {
             config: Arc::new(RwLock::new(config)),
             wallets: Arc::new(RwLock::new(wallets)),
             meta_storage,
+            events: Default::default(),
+            lazy: Default::default(),
+            locked: Default::default(),
+            query_cache: Default::default(),
         })
     }
 
     pub fn rename(&self, name: &str) -> Result<(), Error> {
-        self.config.write().unwrap().name = name.to_string();
+        self.config.write_or_err()?.name = name.to_string();
         self.persist()
     }
 
     pub fn set_preferred_address_type(&self, address_type: AddressType) -> Result<(), Error> {
-        self.config.write().unwrap().preferred_address_type = address_type;
+        self.config.write_or_err()?.preferred_address_type = address_type;
         self.persist()
     }
 
+    /// Persist the wallets and the config, journaled so a persist
+    /// interrupted midway is finished when the account is opened again, see
+    /// [`crate::journal`].
     pub fn persist(&self) -> Result<(), Error> {
-        for wallet in self.wallets.read().unwrap().iter() {
-            wallet.persist()?;
+        let config = self.config.read_or_err()?.serialize();
+        {
+            let wallets = self.wallets.read_or_err()?;
+            self.begin_persist(&wallets, &config)?;
+            for wallet in wallets.iter() {
+                wallet.persist()?;
+            }
         }
+        self.commit_persist(&config)?;
 
-        let config = self.config.read().unwrap();
-        self.meta_storage
-            .set_config(config.serialize().as_str())
-            .map_err(|e| anyhow::anyhow!(e))
+        // the snapshot only speeds up the next start, so failing to record
+        // it doesn't fail the persist
+        if let Err(e) = self.record_snapshot() {
+            log::info!("Could not record the account snapshot: {e:?}");
+        }
+        Ok(())
     }
 
     pub fn add_new_descriptor(
@@ -241,14 +397,17 @@ impl<P: WalletPersister> NgAccount<P> {
         export_addr_hint: Option<AddressType>,
     ) -> Result<(), Error> {
         let address_type = get_address_type(&descriptor.internal);
+        let normalized = utils::normalize_descriptor(&descriptor.internal)?;
         {
-            let mut config = self.config.write().unwrap();
+            let mut config = self.config.write_or_err()?;
             for wallet_descriptor in &config.descriptors {
-                if wallet_descriptor.internal == descriptor.internal {
-                    return Err(anyhow::anyhow!("Descriptor already exists"));
+                if utils::normalize_descriptor(&wallet_descriptor.internal)
+                    .is_ok_and(|internal| internal == normalized)
+                {
+                    return Err(AccountError::DescriptorExists.into());
                 }
                 if address_type == wallet_descriptor.address_type {
-                    return Err(anyhow::anyhow!("Address type already exists"));
+                    return Err(AccountError::AddressTypeExists.into());
                 }
             }
             config.descriptors.push(NgDescriptor {
@@ -264,7 +423,7 @@ impl<P: WalletPersister> NgAccount<P> {
                 self.meta_storage.clone(),
                 descriptor.bdk_persister.clone(),
             )?;
-            self.wallets.write().unwrap().push(wallet);
+            self.wallets.write_or_err()?.push(wallet);
         }
 
         self.persist()?;
@@ -273,7 +432,7 @@ impl<P: WalletPersister> NgAccount<P> {
 
     pub fn get_backup_json(&self) -> Result<String, Error> {
         let config = {
-            let mut config = self.config.read().unwrap().clone();
+            let mut config = self.config.read_or_err()?.clone();
             if self.is_hot() {
                 config.descriptors = vec![];
             }
@@ -284,6 +443,7 @@ impl<P: WalletPersister> NgAccount<P> {
             let mut notes: HashMap<String, String> = HashMap::default();
             let mut tags: HashMap<String, String> = HashMap::default();
             let mut do_not_spend: HashMap<String, bool> = HashMap::default();
+            let mut fiat_values: HashMap<String, FiatValue> = HashMap::default();
             for utxo in utxos {
                 if utxo.do_not_spend {
                     do_not_spend.insert(utxo.get_id().to_string(), true);
@@ -293,6 +453,9 @@ impl<P: WalletPersister> NgAccount<P> {
                 }
             }
             for tx in transactions {
+                if let (Some(value), Some(currency)) = (tx.fiat_value, tx.fiat_currency) {
+                    fiat_values.insert(tx.tx_id.clone(), FiatValue { value, currency });
+                }
                 if tx.note.is_some() {
                     notes.insert(tx.tx_id, tx.note.clone().unwrap());
                 }
@@ -305,6 +468,8 @@ impl<P: WalletPersister> NgAccount<P> {
                 xfp: self.get_coordinator_wallet().get_xfp(),
                 tags,
                 do_not_spend,
+                fiat_values,
+                tag_infos: self.tag_infos()?,
             }
         };
         match serde_json::to_string(&config) {
@@ -313,26 +478,71 @@ impl<P: WalletPersister> NgAccount<P> {
         }
     }
 
+    /// Restore the notes, tags, tag infos, do-not-spend flags and fiat values of
+    /// `backup`, written to the metadata store in a single batch.
+    pub fn restore_metadata(&self, backup: &NgAccountBackup) -> anyhow::Result<()> {
+        let storage = self.meta_storage.as_ref();
+        crate::store::with_batch(storage, |storage| {
+            for (tx_id, note) in &backup.notes {
+                storage.set_note(tx_id, note)?;
+            }
+            for (output_id, tag) in &backup.tags {
+                storage.set_tag(output_id, tag)?;
+                storage.add_tag(tag)?;
+            }
+            for (output_id, do_not_spend) in &backup.do_not_spend {
+                storage.set_do_not_spend(output_id, *do_not_spend)?;
+            }
+            for (tx_id, fiat_value) in &backup.fiat_values {
+                storage.set_fiat_value(tx_id, fiat_value)?;
+            }
+            for info in &backup.tag_infos {
+                storage.set_tag_info(&tags::tag_key(&info.name), &serde_json::to_string(info)?)?;
+            }
+            Ok(())
+        })
+        .with_context(|| "Failed to restore metadata")?;
+        self.refresh();
+        Ok(())
+    }
+
+    /// Next receive address of every wallet. Revealed addresses that were
+    /// already paid are skipped, so an address is never handed out again
+    /// once it received funds.
     pub fn next_address(&self) -> anyhow::Result<Vec<(AddressInfo, AddressType)>> {
         let mut addresses = vec![];
-        for wallet in self.wallets.write().unwrap().iter_mut() {
-            let address: AddressInfo = wallet
-                .bdk_wallet
-                .lock()
-                .unwrap()
-                .next_unused_address(KeychainKind::External);
+        let mut revealed = vec![];
+        self.open_closed_wallets()?;
+        for wallet in self.wallets.write_or_err()?.iter_mut() {
+            let mut wallet_mut = wallet.bdk_wallet.lock_or_err()?;
+            let last_revealed = wallet_mut.derivation_index(KeychainKind::External);
+            let address: AddressInfo = wallet_mut.next_unused_address(KeychainKind::External);
+            if last_revealed.is_none_or(|index| address.index > index) {
+                revealed.push(AccountEvent::AddressRevealed {
+                    address: address.address.to_string(),
+                    address_type: wallet.address_type,
+                    index: address.index,
+                });
+            }
 
             addresses.push((address, wallet.address_type));
         }
         self.persist()?;
+        revealed
+            .into_iter()
+            .for_each(|event| self.events.emit(event));
         Ok(addresses)
     }
 
     pub fn balance(&self) -> anyhow::Result<Balance> {
+        if self.is_archived() && self.wallets.read_or_err()?.is_empty() {
+            let snapshot = self.meta_storage.get_balance_snapshot()?;
+            return Ok(snapshot.unwrap_or_default().into());
+        }
         let mut balance = Balance::default();
 
-        for wallet in self.wallets.read().unwrap().iter() {
-            let wallet_balance = wallet.bdk_wallet.lock().unwrap().balance();
+        for wallet in self.all_wallets()?.iter() {
+            let wallet_balance = wallet.bdk_wallet.lock_or_err()?.balance();
             balance.confirmed += wallet_balance.confirmed;
             balance.immature += wallet_balance.immature;
             balance.trusted_pending += wallet_balance.trusted_pending;
@@ -342,10 +552,31 @@ impl<P: WalletPersister> NgAccount<P> {
         Ok(balance)
     }
 
+    /// The balance split by [`Self::set_do_not_spend`] and
+    /// [`Self::set_tag_do_not_spend`] locks, for the amount available to
+    /// send.
+    pub fn spendable_balance(&self) -> anyhow::Result<SpendableBalance> {
+        let mut balance = SpendableBalance::default();
+        for utxo in self.utxos()? {
+            balance.total += utxo.amount;
+            if !utxo.do_not_spend {
+                balance.spendable += utxo.amount;
+                if !utxo.is_confirmed {
+                    balance.unconfirmed += utxo.amount;
+                }
+            } else if self.meta_storage.get_do_not_spend(&utxo.get_id())? {
+                balance.locked_by_do_not_spend += utxo.amount;
+            } else {
+                balance.locked_by_tag_policy += utxo.amount;
+            }
+        }
+        Ok(balance)
+    }
+
     pub fn wallet_balances(&self) -> anyhow::Result<Vec<(AddressType, Balance)>> {
         let mut balances: Vec<(AddressType, Balance)> = vec![];
-        for wallet in self.wallets.read().unwrap().iter() {
-            let wallet = wallet.bdk_wallet.lock().unwrap();
+        for wallet in self.all_wallets()?.iter() {
+            let wallet = wallet.bdk_wallet.lock_or_err()?;
             let balance = wallet.balance();
             balances.push((
                 get_address_type(&wallet.public_descriptor(KeychainKind::External).to_string()),
@@ -356,15 +587,41 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn transactions(&self) -> anyhow::Result<Vec<BitcoinTransaction>> {
+        let generations = self.cache_generations()?;
+        if let Some((cached, transactions)) = &self.query_cache.lock_or_recover().transactions
+            && *cached == generations
+        {
+            return Ok(transactions.clone());
+        }
+
+        let transactions = self.load_transactions()?;
+        // a refresh while loading leaves the result out of the cache
+        if self.cache_generations()? == generations {
+            self.query_cache.lock_or_recover().transactions =
+                Some((generations, transactions.clone()));
+        }
+        Ok(transactions)
+    }
+
+    // Generations of the query caches of the wallets, see AccountQueryCache.
+    fn cache_generations(&self) -> anyhow::Result<Vec<(AddressType, u64)>> {
+        Ok(self
+            .all_wallets()?
+            .iter()
+            .map(|wallet| (wallet.address_type, wallet.generation()))
+            .collect())
+    }
+
+    fn load_transactions(&self) -> anyhow::Result<Vec<BitcoinTransaction>> {
         let mut transactions: Vec<BitcoinTransaction> = vec![];
 
-        let config = self.config.read().unwrap();
+        let config = self.config.read_or_err()?;
 
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets()?.iter() {
             let wallet_txs = wallet.transactions().unwrap_or_default();
             for wallet_tx in wallet_txs {
                 let tx = {
-                    let bdk = wallet.bdk_wallet.lock().expect("Failed to lock wallet");
+                    let bdk = wallet.bdk_wallet.lock_or_err()?;
                     let tx = bdk
                         .get_tx(Txid::from_str(&wallet_tx.tx_id)?)
                         .with_context(|| "Failed to get transaction ".to_string())?;
@@ -372,9 +629,12 @@ impl<P: WalletPersister> NgAccount<P> {
                 };
                 //use account level sent and received amounts (all wallets)
                 let (sent, received) = self.sent_and_received(&tx);
+                let tx_type = self.tx_type(&tx, !wallet_tx.conflicts_with.is_empty())?;
                 let mut tx = wallet_tx.clone();
                 let amount: i64 = (received.to_sat() as i64) - (sent.to_sat() as i64);
                 tx.amount = amount;
+                tx.tx_type = tx_type;
+                self.resolve_inputs(&mut tx.inputs)?;
 
                 //since there can be multiple wallets with the same tx_id (self spend between wallets),
                 //we will keep outgoing transactions
@@ -403,6 +663,7 @@ impl<P: WalletPersister> NgAccount<P> {
                 tx
             })
             .collect();
+        package::set_packages(&mut transactions);
         // Sort transactions by date, most recent first
         transactions.sort_by(|a, b| match (a.date, b.date) {
             (Some(a_date), Some(b_date)) => b_date.cmp(&a_date),
@@ -414,11 +675,20 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn utxos(&self) -> anyhow::Result<Vec<Output>> {
+        let generations = self.cache_generations()?;
+        if let Some((cached, utxos)) = &self.query_cache.lock_or_recover().utxos
+            && *cached == generations
+        {
+            return Ok(utxos.clone());
+        }
+
         let mut utxos = vec![];
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets()?.iter() {
             utxos.extend(wallet.utxos()?);
         }
-
+        if self.cache_generations()? == generations {
+            self.query_cache.lock_or_recover().utxos = Some((generations, utxos.clone()));
+        }
         Ok(utxos)
     }
 
@@ -426,9 +696,20 @@ impl<P: WalletPersister> NgAccount<P> {
         self.meta_storage
             .set_note(tx_id, note)
             .with_context(|| "Could not set note")?;
+        self.refresh();
+        self.emit_metadata_changed(tx_id);
         Ok(true)
     }
 
+    /// Drop the cached transaction and UTXO lists of every wallet so the next
+    /// query is rebuilt from the wallets and the metadata store.
+    pub fn refresh(&self) {
+        for wallet in self.wallets.read_or_recover().iter() {
+            wallet.refresh();
+        }
+        *self.query_cache.lock_or_recover() = Default::default();
+    }
+
     //TODO: handle error
     pub fn get_xfp(&self) -> String {
         self.get_coordinator_wallet().get_xfp()
@@ -449,11 +730,101 @@ impl<P: WalletPersister> NgAccount<P> {
                 .add_tag(tag.to_string().as_str())
                 .with_context(|| "Could not add tag")?;
         }
+        self.refresh();
+        self.emit_metadata_changed(output_id);
         Ok(true)
     }
 
     pub fn set_do_not_spend(&self, output_id: &str, state: bool) -> anyhow::Result<()> {
-        self.meta_storage.set_do_not_spend(output_id, state)
+        self.meta_storage.set_do_not_spend(output_id, state)?;
+        self.refresh();
+        self.emit_metadata_changed(output_id);
+        Ok(())
+    }
+
+    /// Locks or unlocks every output carrying `tag`, including outputs that
+    /// get the tag later on. An output stays locked while either its own
+    /// do-not-spend flag or the policy of its tag is set.
+    pub fn set_tag_do_not_spend(&self, tag: &str, state: bool) -> anyhow::Result<()> {
+        self.meta_storage
+            .set_tag_do_not_spend(tag, state)
+            .with_context(|| "Could not set tag do not spend")?;
+        self.refresh();
+        self.emit_metadata_changed(tag);
+        Ok(())
+    }
+
+    pub fn get_tag_do_not_spend(&self, tag: &str) -> anyhow::Result<bool> {
+        self.meta_storage.get_tag_do_not_spend(tag)
+    }
+
+    /// [`Self::set_note`] of many transactions, written in a single
+    /// metadata store transaction. The result of each `(tx_id, note)` is
+    /// returned in order; items with an invalid txid are skipped.
+    pub fn set_notes(
+        &self,
+        notes: Vec<(String, String)>,
+    ) -> anyhow::Result<Vec<Result<(), AccountError>>> {
+        self.set_many(&notes, check_tx_id, |storage, tx_id, note| {
+            storage.set_note(tx_id, note)
+        })
+        .with_context(|| "Could not set notes")
+    }
+
+    /// [`Self::set_tag`] of many outputs, written in a single metadata
+    /// store transaction. The result of each `(output_id, tag)` is returned
+    /// in order; items with an invalid output id are skipped.
+    pub fn set_tags(
+        &self,
+        tags: Vec<(String, String)>,
+    ) -> anyhow::Result<Vec<Result<(), AccountError>>> {
+        let mut added = HashSet::new();
+        self.set_many(&tags, check_output_id, |storage, output_id, tag| {
+            storage.set_tag(output_id, tag)?;
+            if !tag.is_empty() && added.insert(tag.to_lowercase()) {
+                storage.add_tag(tag)?;
+            }
+            Ok(())
+        })
+        .with_context(|| "Could not set tags")
+    }
+
+    /// [`Self::set_do_not_spend`] of many outputs, written in a single
+    /// metadata store transaction. The result of each `(output_id, state)`
+    /// is returned in order; items with an invalid output id are skipped.
+    pub fn set_do_not_spend_many(
+        &self,
+        states: Vec<(String, bool)>,
+    ) -> anyhow::Result<Vec<Result<(), AccountError>>> {
+        self.set_many(&states, check_output_id, |storage, output_id, state| {
+            storage.set_do_not_spend(output_id, *state)
+        })
+        .with_context(|| "Could not set do not spend")
+    }
+
+    fn set_many<T>(
+        &self,
+        items: &[(String, T)],
+        check: fn(&str) -> Result<(), AccountError>,
+        mut set: impl FnMut(&dyn MetaStorage, &str, &T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Vec<Result<(), AccountError>>> {
+        let results: Vec<_> = items.iter().map(|(id, _)| check(id)).collect();
+        let storage = self.meta_storage.as_ref();
+        crate::store::with_batch(storage, |storage| {
+            for ((id, value), result) in items.iter().zip(&results) {
+                if result.is_ok() {
+                    set(storage, id, value)?;
+                }
+            }
+            Ok(())
+        })?;
+        self.refresh();
+        for ((id, _), result) in items.iter().zip(&results) {
+            if result.is_ok() {
+                self.emit_metadata_changed(id);
+            }
+        }
+        Ok(results)
     }
 
     #[cfg(feature = "envoy")]
@@ -461,32 +832,354 @@ impl<P: WalletPersister> NgAccount<P> {
         &self,
         address_type: AddressType,
     ) -> anyhow::Result<(AddressType, FullScanRequest<KeychainKind>), Error> {
+        self.open_wallet(address_type)?;
         match self
             .wallets
-            .read()
-            .unwrap()
+            .read_or_err()?
             .iter()
             .find(|ng_wallet| ng_wallet.address_type == address_type)
         {
-            None => Err(anyhow!("given address type doesnt exist in account")),
+            None => Err(AccountError::WalletNotFound(address_type).into()),
             Some(ng_wallet) => Ok((ng_wallet.address_type, ng_wallet.full_scan_request())),
         }
     }
 
-    pub fn apply(&self, update: (AddressType, Update)) -> anyhow::Result<()> {
+    /// Gap limit used by [`Self::full_scan`] for this account.
+    pub fn gap_limit(&self) -> u32 {
+        self.config
+            .read_or_recover()
+            .gap_limit
+            .unwrap_or(DEFAULT_STOP_GAP as u32)
+    }
+
+    pub fn set_gap_limit(&self, gap_limit: u32) -> Result<(), Error> {
+        self.config.write_or_err()?.gap_limit = Some(gap_limit);
+        self.persist()
+    }
+
+    /// Dust limits of the outputs of this account's transactions.
+    pub fn dust_policy(&self) -> DustPolicy {
+        let config = self.config.read_or_recover();
+        match config.dust_relay_fee {
+            Some(dust_relay_fee) => DustPolicy { dust_relay_fee },
+            None => DustPolicy::for_network(config.network),
+        }
+    }
+
+    /// `None` goes back to the default of the network. Transactions are
+    /// still built with the default limits, a lower rate doesn't let
+    /// smaller outputs through.
+    pub fn set_dust_relay_fee(
+        &self,
+        dust_relay_fee: Option<FeeRateSatPerKvb>,
+    ) -> Result<(), Error> {
+        self.config.write_or_err()?.dust_relay_fee = dust_relay_fee;
+        self.persist()
+    }
+
+    pub fn birthday(&self) -> Option<Birthday> {
+        self.config.read_or_recover().birthday
+    }
+
+    /// Set when the account was created. Later full scans drop the
+    /// transactions confirmed before it, see [`Self::rescan`] to scan again
+    /// from the new birthday.
+    pub fn set_birthday(&self, birthday: Option<Birthday>) -> Result<(), Error> {
+        self.config.write_or_err()?.birthday = birthday;
+        self.persist()
+    }
+
+    /// Height compact block filter clients start scanning from, `None` if
+    /// the birthday is unknown or a timestamp.
+    pub fn scan_start_height(&self) -> Option<u32> {
+        match self.birthday()? {
+            Birthday::Height(height) => Some(height),
+            Birthday::Timestamp(_) => None,
+        }
+    }
+
+    #[cfg(feature = "envoy")]
+    fn scan_update(&self, update: FullScanResponse<KeychainKind>) -> Update {
+        let mut update = Update::from(update);
+        if let Some(birthday) = self.birthday() {
+            birthday.prune(&mut update.tx_update);
+        }
+        update
+    }
+
+    /// Full scan of the wallet for `address_type`, stopping after
+    /// [`Self::gap_limit`] consecutive unused addresses. Transactions
+    /// confirmed before [`Self::birthday`] are left out.
+    ///
+    /// The update is returned rather than applied, see [`Self::apply`].
+    #[cfg(feature = "envoy")]
+    pub fn full_scan(
+        &self,
+        address_type: AddressType,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> anyhow::Result<(AddressType, Update)> {
+        let (address_type, request) = self.full_scan_request(address_type)?;
+        let update = NgWallet::<P>::scan(
+            request,
+            electrum_server,
+            socks_proxy,
+            Some(self.gap_limit() as usize),
+            validate_domain,
+        )?;
+        Ok((address_type, self.scan_update(update)))
+    }
+
+    /// Same as [`Self::full_scan`] with the gap limit raised by `extra_gap`,
+    /// for recovering wallets that left long runs of addresses unused.
+    #[cfg(feature = "envoy")]
+    pub fn extend_gap_scan(
+        &self,
+        address_type: AddressType,
+        extra_gap: u32,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> anyhow::Result<(AddressType, Update)> {
+        let (address_type, request) = self.full_scan_request(address_type)?;
+        let stop_gap = self.gap_limit().saturating_add(extra_gap);
+        let update = NgWallet::<P>::scan(
+            request,
+            electrum_server,
+            socks_proxy,
+            Some(stop_gap as usize),
+            validate_domain,
+        )?;
+        Ok((address_type, self.scan_update(update)))
+    }
+
+    /// Full scan of every wallet of the account, applying the updates. Used
+    /// after [`Self::set_birthday`] or when the history looks incomplete.
+    #[cfg(feature = "envoy")]
+    pub fn rescan(
+        &self,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> anyhow::Result<()> {
+        let address_types = self.address_types()?;
+        for address_type in address_types {
+            let update =
+                self.full_scan(address_type, electrum_server, socks_proxy, validate_domain)?;
+            self.apply(update)?;
+        }
+        Ok(())
+    }
+
+    /// Look for activity past the revealed addresses of every wallet,
+    /// `lookahead` addresses at a time, and reveal up to the last used one.
+    /// Fixes accounts restored from a backup whose derivation indexes are
+    /// behind, which can't see the funds received on the later addresses.
+    ///
+    /// Probing continues after each used address found, so runs of unused
+    /// addresses shorter than `lookahead` are crossed.
+    #[cfg(feature = "envoy")]
+    pub fn repair_indices(
+        &self,
+        lookahead: u32,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> anyhow::Result<IndexRepair> {
+        if lookahead == 0 {
+            anyhow::bail!("Lookahead must be positive");
+        }
+        let transactions = self.transactions()?.len();
+        let utxos = self.utxos()?.len();
+        let before = self.get_derivation_index();
+
+        let address_types = self.address_types()?;
+        for address_type in address_types {
+            self.open_wallet(address_type)?;
+            let wallet = self
+                .wallets
+                .read_or_err()?
+                .iter()
+                .find(|wallet| wallet.address_type == address_type)
+                .cloned()
+                .ok_or(AccountError::WalletNotFound(address_type))?;
+            let keychains: Vec<KeychainKind> = wallet
+                .bdk_wallet
+                .lock_or_err()?
+                .keychains()
+                .map(|(keychain, _)| keychain)
+                .collect();
+            for keychain in keychains {
+                loop {
+                    let spks = wallet.unrevealed_spks(keychain, lookahead);
+                    let tip = wallet.bdk_wallet.lock_or_err()?.local_chain().tip();
+                    let request = SyncRequest::builder()
+                        .chain_tip(tip)
+                        .spks_with_indexes(
+                            spks.iter()
+                                .map(|(index, spk)| ((keychain, *index), spk.clone())),
+                        )
+                        .build();
+                    let spks: HashMap<_, _> =
+                        spks.into_iter().map(|(index, spk)| (spk, index)).collect();
+                    let response = NgWallet::<P>::sync(
+                        request,
+                        electrum_server,
+                        socks_proxy,
+                        validate_domain,
+                    )?;
+                    let last_used = response
+                        .tx_update
+                        .txs
+                        .iter()
+                        .flat_map(|tx| &tx.output)
+                        .filter_map(|output| spks.get(&output.script_pubkey).copied())
+                        .max();
+                    let Some(last_used) = last_used else {
+                        break;
+                    };
+                    let _ = wallet
+                        .bdk_wallet
+                        .lock_or_err()?
+                        .reveal_addresses_to(keychain, last_used);
+                    wallet.persist()?;
+                    self.apply((address_type, Update::from(response)))?;
+                }
+            }
+        }
+
+        let after = self.get_derivation_index();
+        Ok(IndexRepair {
+            revealed: after
+                .into_iter()
+                .filter(|index| !before.contains(index))
+                .collect(),
+            new_transactions: self.transactions()?.len().saturating_sub(transactions),
+            new_utxos: self.utxos()?.len().saturating_sub(utxos),
+        })
+    }
+
+    #[cfg(feature = "envoy")]
+    pub fn full_scan_request_with_progress(
+        &self,
+        address_type: AddressType,
+        on_progress: ProgressCallback,
+    ) -> anyhow::Result<(AddressType, FullScanRequest<KeychainKind>)> {
+        self.open_wallet(address_type)?;
         match self
             .wallets
-            .read()
-            .unwrap()
+            .read_or_err()?
+            .iter()
+            .find(|ng_wallet| ng_wallet.address_type == address_type)
+        {
+            None => Err(AccountError::WalletNotFound(address_type).into()),
+            Some(ng_wallet) => Ok((
+                ng_wallet.address_type,
+                ng_wallet.full_scan_request_with_progress(on_progress),
+            )),
+        }
+    }
+
+    pub fn apply(&self, update: (AddressType, Update)) -> anyhow::Result<()> {
+        let before = match self.events.has_subscribers() {
+            true => Some(self.event_snapshot()?),
+            false => None,
+        };
+
+        self.open_wallet(update.0)?;
+        let reorg = match self
+            .wallets
+            .read_or_err()?
             .iter()
             .find(|ng_wallet| ng_wallet.address_type == update.0)
         {
-            None => Err(anyhow!("given address type doesnt exist in account")),
-            Some(ng_wallet) => {
-                ng_wallet.apply_update(update.1)?;
-                Ok(())
+            None => return Err(AccountError::WalletNotFound(update.0).into()),
+            Some(ng_wallet) => ng_wallet
+                .apply_update_with_reorg_check(update.1)
+                .map_err(SyncError::from)?,
+        };
+        // the checkpoint only helps planning the next sync, so failing to
+        // record it doesn't fail the update
+        if let Err(e) = self.record_sync_checkpoint(update.0) {
+            log::info!("Could not record the sync checkpoint: {e:?}");
+        }
+
+        if let Some(reorg) = reorg {
+            self.events.emit(AccountEvent::Reorg(reorg));
+        }
+
+        if let Some((transactions, balance)) = before {
+            let (new_transactions, new_balance) = self.event_snapshot()?;
+            // a replaced transaction leaves the list, so its conflicts are
+            // looked up in the wallets
+            for tx in transactions.iter().filter(|tx| !tx.is_confirmed) {
+                let conflicts_with: Vec<String> = self
+                    .conflicts_of(&tx.tx_id)?
+                    .into_iter()
+                    .filter(|other| !tx.conflicts_with.contains(other))
+                    .collect();
+                if !conflicts_with.is_empty() {
+                    self.events.emit(AccountEvent::DoubleSpendDetected {
+                        tx_id: tx.tx_id.clone(),
+                        conflicts_with,
+                    });
+                }
+            }
+            for tx in new_transactions {
+                match transactions.iter().find(|known| known.tx_id == tx.tx_id) {
+                    None => self.events.emit(AccountEvent::NewTransaction {
+                        tx_id: tx.tx_id.clone(),
+                    }),
+                    Some(known) if known.is_confirmed => continue,
+                    Some(_) => {}
+                }
+                if tx.is_confirmed {
+                    self.events.emit(AccountEvent::TransactionConfirmed {
+                        tx_id: tx.tx_id,
+                        block_height: tx.block_height,
+                    });
+                }
+            }
+            if new_balance != balance {
+                self.events.emit(AccountEvent::BalanceChanged(new_balance));
             }
         }
+        Ok(())
+    }
+
+    /// Receive an [`AccountEvent`] for every change made to this account or
+    /// its clones from now on. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<AccountEvent> {
+        self.events.subscribe()
+    }
+
+    // Transactions spending some of the outputs `tx_id` spends.
+    fn conflicts_of(&self, tx_id: &str) -> anyhow::Result<Vec<String>> {
+        let txid = Txid::from_str(tx_id)?;
+        let mut conflicts = vec![];
+        for wallet in self.all_wallets()?.iter() {
+            for other in wallet.conflicts_of(txid)? {
+                if !conflicts.contains(&other) {
+                    conflicts.push(other);
+                }
+            }
+        }
+        Ok(conflicts)
+    }
+
+    fn event_snapshot(&self) -> anyhow::Result<(Vec<BitcoinTransaction>, Balance)> {
+        Ok((self.transactions()?, self.balance()?))
+    }
+
+    pub(crate) fn emit_metadata_changed(&self, id: &str) {
+        self.events
+            .emit(AccountEvent::MetadataChanged { id: id.to_string() });
+    }
+
+    /// Height and hash of the latest block known to the account.
+    pub fn chain_tip(&self) -> (u32, BlockHash) {
+        self.get_coordinator_wallet().chain_tip()
     }
 
     #[cfg(feature = "envoy")]
@@ -494,21 +1187,120 @@ impl<P: WalletPersister> NgAccount<P> {
         &self,
         address_type: AddressType,
     ) -> anyhow::Result<(AddressType, SyncRequest<(KeychainKind, u32)>)> {
+        self.open_wallet(address_type)?;
         match self
             .wallets
-            .read()
-            .unwrap()
+            .read_or_err()?
             .iter()
             .find(|ng_wallet| ng_wallet.address_type == address_type)
         {
-            None => Err(anyhow!("given address type doesnt exist in account")),
+            None => Err(AccountError::WalletNotFound(address_type).into()),
             Some(ng_wallet) => Ok((ng_wallet.address_type, ng_wallet.sync_request())),
         }
     }
 
+    /// Script types synced last by [`Self::prioritized_sync`].
+    pub fn deferred_address_types(&self) -> Vec<AddressType> {
+        self.config.read_or_recover().deferred_address_types.clone()
+    }
+
+    pub fn set_deferred_address_types(&self, address_types: Vec<AddressType>) -> Result<(), Error> {
+        self.config.write_or_err()?.deferred_address_types = address_types;
+        self.persist()
+    }
+
+    /// Order of the wallets in [`Self::prioritized_sync`]: the preferred
+    /// address type, the other ones, then the deferred ones. The preferred
+    /// address type is never deferred.
+    pub fn sync_order(&self) -> anyhow::Result<Vec<AddressType>> {
+        let config = self.config.read_or_err()?;
+        let mut address_types = self.address_types()?;
+        // stable, so the wallets keep their order within each group
+        address_types.sort_by_key(|address_type| {
+            if *address_type == config.preferred_address_type {
+                0
+            } else if config.deferred_address_types.contains(address_type) {
+                2
+            } else {
+                1
+            }
+        });
+        Ok(address_types)
+    }
+
+    /// Sync the revealed addresses of each wallet in [`Self::sync_order`],
+    /// applying each update as it comes and reporting it to `on_synced`,
+    /// so the balance of the preferred address type shows before the
+    /// other wallets are done. The deferred address types are only synced
+    /// with `include_deferred`.
+    #[cfg(feature = "envoy")]
+    pub fn prioritized_sync(
+        &self,
+        include_deferred: bool,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+        on_synced: SyncedCallback,
+    ) -> anyhow::Result<()> {
+        let (preferred, deferred) = {
+            let config = self.config.read_or_err()?;
+            (
+                config.preferred_address_type,
+                config.deferred_address_types.clone(),
+            )
+        };
+        let address_types: Vec<AddressType> = self
+            .sync_order()?
+            .into_iter()
+            .filter(|address_type| {
+                include_deferred || *address_type == preferred || !deferred.contains(address_type)
+            })
+            .collect();
+
+        for (synced, address_type) in address_types.iter().enumerate() {
+            let (_, request) = self.sync_request(*address_type)?;
+            let response =
+                NgWallet::<P>::sync(request, electrum_server, socks_proxy, validate_domain)?;
+            self.apply((*address_type, Update::from(response)))?;
+
+            on_synced(SyncedWallet {
+                address_type: *address_type,
+                balance: self.balance()?,
+                transactions: self.transactions()?,
+                remaining: address_types.len() - synced - 1,
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "envoy")]
+    pub fn sync_request_with_progress(
+        &self,
+        address_type: AddressType,
+        on_progress: ProgressCallback,
+    ) -> anyhow::Result<(AddressType, SyncRequest<(KeychainKind, u32)>)> {
+        self.open_wallet(address_type)?;
+        match self
+            .wallets
+            .read_or_err()?
+            .iter()
+            .find(|ng_wallet| ng_wallet.address_type == address_type)
+        {
+            None => Err(AccountError::WalletNotFound(address_type).into()),
+            Some(ng_wallet) => Ok((
+                ng_wallet.address_type,
+                ng_wallet.sync_request_with_progress(on_progress),
+            )),
+        }
+    }
+
     pub fn get_coordinator_wallet(&self) -> NgWallet<P> {
-        let address_type = self.config.read().unwrap().preferred_address_type;
-        let wallets = self.wallets.read().unwrap();
+        let address_type = self.config.read_or_recover().preferred_address_type;
+        // the preferred address type may have changed to a closed wallet
+        if let Err(e) = self.open_wallet(address_type) {
+            log::warn!("Could not open the coordinator wallet: {e}");
+        }
+        let wallets = self.wallets.read_or_recover();
         for wallet in wallets.iter() {
             if wallet.address_type == address_type {
                 return wallet.clone();
@@ -518,10 +1310,8 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn non_coordinator_wallets(&self) -> Vec<NgWallet<P>> {
-        let address_type = self.config.read().unwrap().preferred_address_type;
-        self.wallets
-            .read()
-            .unwrap()
+        let address_type = self.config.read_or_recover().preferred_address_type;
+        self.all_wallets_or_recover()
             .iter()
             .filter(|wallet| wallet.address_type != address_type)
             .cloned()
@@ -530,8 +1320,8 @@ impl<P: WalletPersister> NgAccount<P> {
 
     pub fn get_derivation_index(&self) -> Vec<(AddressType, KeychainKind, u32)> {
         let mut derivation_index = vec![];
-        for wallet in self.wallets.read().unwrap().iter() {
-            let bdk_wallet = wallet.bdk_wallet.lock().unwrap();
+        for wallet in self.all_wallets_or_recover().iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_recover();
             let external_index = bdk_wallet
                 .derivation_index(KeychainKind::External)
                 .unwrap_or(0);
@@ -547,8 +1337,9 @@ impl<P: WalletPersister> NgAccount<P> {
     //Signs serialized PSBTs. returns the signed PSBT as serialized bytes.
     pub fn sign(&self, psbt: &[u8], options: bdk_wallet::SignOptions) -> anyhow::Result<Vec<u8>> {
         let mut psbt = Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT")?;
+        self.check_can_sign(&psbt)?;
 
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets()?.iter() {
             wallet.sign_psbt(&mut psbt, options.clone())?;
         }
 
@@ -557,7 +1348,7 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn cancel_tx(&self, psbt: Psbt) -> anyhow::Result<Vec<u8>> {
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets()?.iter() {
             wallet.cancel_tx(&psbt.unsigned_tx)?;
         }
         let encoded_psbt = psbt.serialize();
@@ -565,7 +1356,7 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn is_hot(&self) -> bool {
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.wallets.read_or_recover().iter() {
             if wallet.is_hot() {
                 return true;
             }
@@ -575,7 +1366,7 @@ impl<P: WalletPersister> NgAccount<P> {
     pub fn sent_and_received(&self, tx: &Transaction) -> (Amount, Amount) {
         let mut sent = Amount::from_sat(0);
         let mut received = Amount::from_sat(0);
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets_or_recover().iter() {
             let (_send, _received) = wallet.sent_and_received(tx);
             sent += _send;
             received += _received;
@@ -583,18 +1374,93 @@ impl<P: WalletPersister> NgAccount<P> {
         (sent, received)
     }
 
+    /// What `tx` does for the account, from what each of its wallets sends
+    /// and receives in it and the keychains of its outputs. `replaces` is
+    /// true if the transaction conflicts with another.
+    pub fn tx_type(&self, tx: &Transaction, replaces: bool) -> anyhow::Result<TxType> {
+        let mut spends = false;
+        let mut between_wallets = false;
+        let mut keychains: Vec<Option<KeyChain>> = vec![None; tx.output.len()];
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let (sent, received) = bdk_wallet.sent_and_received(tx);
+            spends |= sent > Amount::ZERO;
+            between_wallets |= sent == Amount::ZERO && received > Amount::ZERO;
+            for (keychain, output) in keychains.iter_mut().zip(&tx.output) {
+                if keychain.is_none()
+                    && let Some((kind, _)) =
+                        bdk_wallet.derivation_of_spk(output.script_pubkey.clone())
+                {
+                    *keychain = Some(KeyChain::from(kind));
+                }
+            }
+        }
+        Ok(TxType::classify(
+            tx,
+            spends,
+            &keychains,
+            between_wallets,
+            replaces,
+        ))
+    }
+
+    // Inputs spending outputs of the other wallets of the account are only
+    // known to those wallets.
+    fn resolve_inputs(&self, inputs: &mut [Input]) -> anyhow::Result<()> {
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            for input in inputs.iter_mut().filter(|input| !input.is_mine) {
+                let outpoint = OutPoint::new(Txid::from_str(&input.tx_id)?, input.vout);
+                let Some(prev_out) = bdk_wallet.tx_graph().get_txout(outpoint) else {
+                    continue;
+                };
+                if input.address.is_none() {
+                    input.amount = prev_out.value.to_sat();
+                    input.address = Some(utils::get_address_as_string(
+                        &prev_out.script_pubkey,
+                        bdk_wallet.network(),
+                    ));
+                }
+                if let Some((keychain, _)) =
+                    bdk_wallet.derivation_of_spk(prev_out.script_pubkey.clone())
+                {
+                    input.keychain = Some(KeyChain::from(keychain));
+                    input.is_mine = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn list_tags(&self) -> anyhow::Result<Vec<String>> {
         self.meta_storage.list_tags()
     }
 
     pub fn mark_utxo_as_used(&self, transaction: Transaction) {
-        for wallet in self.wallets.read().unwrap().iter() {
-            let mut wallet_mut = wallet.bdk_wallet.lock().unwrap();
-            for txout in &transaction.output {
-                if let Some((keychain, index)) =
-                    wallet_mut.derivation_of_spk(txout.script_pubkey.clone())
-                {
-                    wallet_mut.mark_used(keychain, index);
+        let wallets = self.all_wallets_or_recover();
+        for txout in &transaction.output {
+            match spk_index::lookup(self.meta_storage.as_ref(), &txout.script_pubkey) {
+                Ok(Some(derivation)) => {
+                    if let Some(wallet) = wallets
+                        .iter()
+                        .find(|wallet| wallet.address_type == derivation.address_type)
+                    {
+                        wallet
+                            .bdk_wallet
+                            .lock_or_recover()
+                            .mark_used(derivation.keychain, derivation.index);
+                    }
+                }
+                // not indexed, ask every wallet
+                _ => {
+                    for wallet in wallets.iter() {
+                        let mut wallet_mut = wallet.bdk_wallet.lock_or_recover();
+                        if let Some((keychain, index)) =
+                            wallet_mut.derivation_of_spk(txout.script_pubkey.clone())
+                        {
+                            wallet_mut.mark_used(keychain, index);
+                        }
+                    }
                 }
             }
         }
@@ -604,6 +1470,8 @@ impl<P: WalletPersister> NgAccount<P> {
         self.meta_storage
             .set_note(tx_id, note)
             .with_context(|| "Could not set note")?;
+        self.refresh();
+        self.emit_metadata_changed(tx_id);
         Ok(true)
     }
 
@@ -614,13 +1482,29 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     pub fn remove_tag(&self, target_tag: &str, rename_to: Option<&str>) -> anyhow::Result<()> {
+        let tag_do_not_spend = self.meta_storage.get_tag_do_not_spend(target_tag)?;
+        let tag_info = self.tag_info(target_tag)?;
         self.meta_storage.remove_tag(target_tag)?;
+        self.meta_storage.set_tag_do_not_spend(target_tag, false)?;
+        self.meta_storage
+            .remove_tag_info(&tags::tag_key(target_tag))?;
         let utxos = self.utxos()?;
         if let Some(new_tag) = rename_to
             && !new_tag.is_empty()
         {
             self.meta_storage.add_tag(new_tag)?;
+            //renamed tags keep their spending policy
+            if tag_do_not_spend {
+                self.meta_storage.set_tag_do_not_spend(new_tag, true)?;
+            }
+            // and how they are displayed
+            if let Some(mut info) = tag_info {
+                info.name = new_tag.to_string();
+                self.meta_storage
+                    .set_tag_info(&tags::tag_key(new_tag), &serde_json::to_string(&info)?)?;
+            }
         }
+        self.refresh();
         for output in utxos {
             match &output.tag {
                 None => {}
@@ -635,6 +1519,7 @@ impl<P: WalletPersister> NgAccount<P> {
                 }
             }
         }
+        self.emit_metadata_changed(target_tag);
 
         Ok(())
     }
@@ -642,11 +1527,10 @@ impl<P: WalletPersister> NgAccount<P> {
     pub fn get_external_public_descriptors(&self) -> Vec<(AddressType, String)> {
         let mut descriptors = vec![];
 
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets_or_recover().iter() {
             let external_pubkey = wallet
                 .bdk_wallet
-                .lock()
-                .unwrap()
+                .lock_or_recover()
                 .public_descriptor(KeychainKind::External)
                 .to_string();
             descriptors.push((wallet.address_type, external_pubkey));
@@ -655,6 +1539,54 @@ impl<P: WalletPersister> NgAccount<P> {
         descriptors
     }
 
+    /// Account level extended public keys of every wallet, in both the
+    /// standard and the SLIP-132 encoding.
+    ///
+    /// Multisig wallets return one entry per cosigner key.
+    pub fn get_export_xpubs(&self) -> Vec<ExportXpub> {
+        let config = self.config.read_or_recover();
+        let mut xpubs = vec![];
+
+        for wallet in self.all_wallets_or_recover().iter() {
+            // multisig-only descriptors are exported with their hinted script type
+            let address_type = config
+                .descriptors
+                .iter()
+                .find(|descriptor| descriptor.address_type == wallet.address_type)
+                .and_then(|descriptor| descriptor.export_addr_hint)
+                .unwrap_or(wallet.address_type);
+
+            wallet
+                .bdk_wallet
+                .lock_or_recover()
+                .public_descriptor(KeychainKind::External)
+                .for_each_key(|key| {
+                    let (origin, xpub) = match key {
+                        DescriptorPublicKey::XPub(xkey) => (&xkey.origin, xkey.xkey),
+                        DescriptorPublicKey::MultiXPub(xkey) => (&xkey.origin, xkey.xkey),
+                        DescriptorPublicKey::Single(_) => return true,
+                    };
+                    let (fingerprint, derivation_path) = match origin {
+                        Some((fingerprint, path)) if !path.is_master() => {
+                            (*fingerprint, format!("m/{path}"))
+                        }
+                        Some((fingerprint, _)) => (*fingerprint, "m".to_string()),
+                        None => (xpub.fingerprint(), "m".to_string()),
+                    };
+                    xpubs.push(ExportXpub {
+                        address_type,
+                        xpub: xpub.to_string(),
+                        slip132: slip132::encode(&xpub, address_type),
+                        derivation_path,
+                        fingerprint: fingerprint.to_string().to_uppercase(),
+                    });
+                    true
+                });
+        }
+
+        xpubs
+    }
+
     #[cfg(feature = "envoy")]
     pub fn fetch_fee_from_electrum(
         txid: &str,
@@ -691,72 +1623,163 @@ impl<P: WalletPersister> NgAccount<P> {
         Some(fee)
     }
 
+    /// Fetch the outputs spent by foreign inputs of the transactions of the
+    /// account from `electrum_server`, so [`Self::transactions`] shows
+    /// their address and amount. Returns how many outputs were fetched.
+    #[cfg(feature = "envoy")]
+    pub fn fetch_foreign_inputs(
+        &self,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> anyhow::Result<usize> {
+        let client = utils::build_electrum_client(electrum_server, socks_proxy, validate_domain)?;
+        let mut fetched = 0;
+        for wallet in self.all_wallets()?.iter() {
+            let missing: Vec<OutPoint> = {
+                let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+                let graph = bdk_wallet.tx_graph();
+                bdk_wallet
+                    .transactions()
+                    .filter(|tx| !tx.tx_node.tx.is_coinbase())
+                    .flat_map(|tx| tx.tx_node.tx.input.clone())
+                    .map(|input| input.previous_output)
+                    .filter(|outpoint| graph.get_txout(*outpoint).is_none())
+                    .collect()
+            };
+            if missing.is_empty() {
+                continue;
+            }
+            for outpoint in missing {
+                // the client caches the transactions it fetched
+                let prev_tx = client.fetch_tx(outpoint.txid)?;
+                let Some(prev_out) = prev_tx.output.get(outpoint.vout as usize) else {
+                    continue;
+                };
+                wallet
+                    .bdk_wallet
+                    .lock_or_err()?
+                    .insert_txout(outpoint, prev_out.clone());
+                fetched += 1;
+            }
+            wallet.persist()?;
+        }
+        self.refresh();
+        Ok(fetched)
+    }
+
+    #[cfg(feature = "esplora")]
+    pub fn fetch_fee_esplora(
+        txid: &str,
+        esplora_url: &str,
+        socks_proxy: Option<&str>,
+    ) -> Option<u64> {
+        use bdk_wallet::bitcoin::Txid;
+        use std::str::FromStr;
+        let client = utils::build_esplora_client(esplora_url, socks_proxy);
+
+        let tx_id = Txid::from_str(txid).ok()?;
+        let tx = client
+            .get_tx(&tx_id)
+            .inspect_err(|e| log::warn!("esplora get_tx failed: {e}"))
+            .ok()??;
+
+        let input_sum: u64 = tx
+            .input
+            .iter()
+            .filter_map(|input| {
+                let prev_tx = client.get_tx(&input.previous_output.txid).ok()??;
+                let prev_out = prev_tx.output.get(input.previous_output.vout as usize)?;
+
+                Some(prev_out.value.to_sat())
+            })
+            .sum();
+
+        let output_sum: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+
+        let fee = input_sum.saturating_sub(output_sum);
+        Some(fee)
+    }
+
     pub fn update_fee(&self, txid: &str, fee: u64) -> anyhow::Result<()> {
         self.meta_storage
             .set_fee(txid, fee)
-            .with_context(|| "Failed to set fee")
+            .with_context(|| "Failed to set fee")?;
+        self.refresh();
+        Ok(())
     }
 
     pub fn update(&self, payload: Vec<u8>) -> anyhow::Result<()> {
+        // an update already applied changes nothing, applied again its
+        // metadata would undo the changes made since
+        let hash = sha256::Hash::hash(&payload).to_string();
+        if self.meta_storage.get_applied_update(&hash)?.is_some() {
+            log::info!("Skipping already applied RemoteUpdate {hash}");
+            return Ok(());
+        }
         let update = RemoteUpdate::deserialize(&payload)?;
+        let sequence = update.sequence;
 
         // Validate all binding fields before mutating anything.
         {
-            let config = self.config.read().unwrap();
+            let config = self.config.read_or_err()?;
 
             if update.account_id != config.id {
-                return Err(anyhow!(
-                    "RemoteUpdate account_id mismatch: expected {}, got {}",
-                    config.id,
-                    update.account_id
-                ));
+                return Err(SyncError::AccountMismatch {
+                    expected: config.id.clone(),
+                    got: update.account_id,
+                }
+                .into());
             }
 
             if update.network != config.network {
-                return Err(anyhow!(
-                    "RemoteUpdate network mismatch: expected {:?}, got {:?}",
-                    config.network,
-                    update.network
-                ));
+                return Err(SyncError::NetworkMismatch {
+                    expected: config.network,
+                    got: update.network,
+                }
+                .into());
             }
 
             let expected_hash = config.descriptor_hash();
             if update.descriptor_hash != expected_hash {
-                return Err(anyhow!(
-                    "RemoteUpdate descriptor hash mismatch: update was not produced for this account"
-                ));
+                return Err(SyncError::DescriptorMismatch.into());
             }
 
             if update.sequence <= config.last_remote_sequence {
-                return Err(anyhow!(
-                    "RemoteUpdate sequence {} is not newer than last accepted sequence {}; possible replay attack",
-                    update.sequence,
-                    config.last_remote_sequence
-                ));
+                return Err(SyncError::Replay {
+                    sequence: update.sequence,
+                    last: config.last_remote_sequence,
+                }
+                .into());
             }
 
             if let Some(ref m) = update.metadata {
                 if m.id != config.id {
-                    return Err(anyhow!("RemoteUpdate metadata account_id mismatch"));
+                    return Err(SyncError::MetadataRejected("account_id mismatch").into());
                 }
                 if m.network != config.network {
-                    return Err(anyhow!("RemoteUpdate metadata network mismatch"));
+                    return Err(SyncError::MetadataRejected("network mismatch").into());
                 }
                 if m.preferred_address_type != config.preferred_address_type {
-                    return Err(anyhow!(
-                        "RemoteUpdate metadata preferred_address_type change rejected; use set_preferred_address_type"
-                    ));
+                    return Err(SyncError::MetadataRejected(
+                        "preferred_address_type change rejected; use set_preferred_address_type",
+                    )
+                    .into());
                 }
                 if m.descriptors != config.descriptors {
-                    return Err(anyhow!(
-                        "RemoteUpdate metadata descriptor change rejected; use add_new_descriptor"
-                    ));
+                    return Err(SyncError::MetadataRejected(
+                        "descriptor change rejected; use add_new_descriptor",
+                    )
+                    .into());
                 }
                 if m.index != config.index {
-                    return Err(anyhow!("RemoteUpdate metadata index change rejected"));
+                    return Err(SyncError::MetadataRejected("index change rejected").into());
                 }
                 if m.multisig != config.multisig {
-                    return Err(anyhow!("RemoteUpdate metadata multisig change rejected"));
+                    return Err(SyncError::MetadataRejected("multisig change rejected").into());
+                }
+                if m.vault != config.vault {
+                    return Err(SyncError::MetadataRejected("vault change rejected").into());
                 }
             }
         }
@@ -766,11 +1789,11 @@ impl<P: WalletPersister> NgAccount<P> {
         }
 
         {
-            let mut config = self.config.write().unwrap();
+            let mut config = self.config.write_or_err()?;
             if let Some(m) = update.metadata {
                 // Only copy cosmetic / sync-state fields; security-critical
                 // fields (id, network, descriptors, preferred_address_type,
-                // index, multisig) are validated above and never overwritten here.
+                // index, multisig, vault) are validated above and never overwritten here.
                 config.name = m.name;
                 config.color = m.color;
                 config.date_synced = m.date_synced;
@@ -783,19 +1806,61 @@ impl<P: WalletPersister> NgAccount<P> {
         }
 
         self.persist()?;
+        let applied = AppliedUpdate {
+            hash: hash.clone(),
+            sequence,
+            applied_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        };
+        self.meta_storage
+            .set_applied_update(&hash, &serde_json::to_string(&applied)?)?;
+        self.prune_applied_updates()?;
         Ok(())
     }
 
+    // Forget the applied updates beyond the most recent MAX_APPLIED_UPDATES
+    fn prune_applied_updates(&self) -> anyhow::Result<()> {
+        let mut applied = self.applied_updates()?;
+        if applied.len() <= MAX_APPLIED_UPDATES {
+            return Ok(());
+        }
+        applied.sort_by_key(|applied| std::cmp::Reverse(applied.sequence));
+        for applied in &applied[MAX_APPLIED_UPDATES..] {
+            self.meta_storage.remove_applied_update(&applied.hash)?;
+        }
+        Ok(())
+    }
+
+    fn applied_updates(&self) -> anyhow::Result<Vec<AppliedUpdate>> {
+        Ok(self
+            .meta_storage
+            .list_applied_updates()?
+            .iter()
+            .map(|applied| serde_json::from_str::<AppliedUpdate>(applied))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// The update of highest sequence applied by [`Self::update`], for the
+    /// sync layer to know where the account stands.
+    pub fn last_applied_update(&self) -> anyhow::Result<Option<AppliedUpdate>> {
+        Ok(self
+            .applied_updates()?
+            .into_iter()
+            .max_by_key(|applied| applied.sequence))
+    }
+
     pub fn get_address_script_type(&self, address: &str) -> anyhow::Result<AddressType> {
-        let network = self.config.read().unwrap().network;
+        let network = self.config.read_or_err()?.network;
         let address: Address<NetworkUnchecked> =
-            Address::from_str(address).map_err(|_| anyhow::anyhow!("Could not parse address"))?;
+            Address::from_str(address).map_err(|_| AccountError::InvalidAddress)?;
         let address: Address<NetworkChecked> = address
             .require_network(network)
-            .map_err(|_| anyhow::anyhow!("Address is invalid for current network: {network}"))?;
+            .map_err(|_| AccountError::WrongNetwork(network))?;
         match address.address_type() {
             Some(t) => t.try_into(),
-            None => Err(anyhow::anyhow!("Unknown address type")),
+            None => Err(AccountError::UnknownAddressType.into()),
         }
     }
 
@@ -805,17 +1870,15 @@ impl<P: WalletPersister> NgAccount<P> {
     ) -> anyhow::Result<AddressVerificationInfo> {
         let address_type = self.get_address_script_type(&address)?;
 
-        let wallets = self.wallets.read().unwrap();
+        self.open_wallet(address_type)?;
+        let wallets = self.wallets.read_or_err()?;
         let wallet = wallets.iter().find(|w| w.address_type == address_type);
         let wallet = match wallet {
             Some(w) => w,
-            None => anyhow::bail!(
-                "No wallet found with the corresponding address type: {:?}",
-                address_type
-            ),
+            None => return Err(AccountError::WalletNotFound(address_type).into()),
         };
 
-        let bdk_wallet = wallet.bdk_wallet.lock().unwrap();
+        let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
         let external_descriptor = bdk_wallet
             .public_descriptor(KeychainKind::External)
             .to_string();
@@ -834,7 +1897,7 @@ impl<P: WalletPersister> NgAccount<P> {
             address,
             internal_descriptor,
             external_descriptor: Some(external_descriptor),
-            network: self.config.read().unwrap().network,
+            network: self.config.read_or_err()?.network,
             address_type,
             receive_start,
             change_start,
@@ -866,22 +1929,19 @@ impl<P: WalletPersister> NgAccount<P> {
     ) -> anyhow::Result<AddressVerificationResult> {
         let address_type = self.get_address_script_type(&address)?;
 
+        self.open_wallet(address_type)?;
         let wallet = self
             .wallets
-            .read()
-            .unwrap()
+            .read_or_err()?
             .iter()
             .find(|w| w.address_type == address_type)
             .cloned();
         let wallet = match wallet {
             Some(w) => w,
-            None => anyhow::bail!(
-                "No wallet found with the corresponding address type: {:?}",
-                address_type
-            ),
+            None => return Err(AccountError::WalletNotFound(address_type).into()),
         };
 
-        let wallet = wallet.bdk_wallet.lock().unwrap();
+        let wallet = wallet.bdk_wallet.lock_or_err()?;
 
         let receive_start = self
             .meta_storage
@@ -890,40 +1950,92 @@ impl<P: WalletPersister> NgAccount<P> {
             .meta_storage
             .get_last_verified_address(address_type, KeychainKind::Internal)?;
 
-        let result = search_for_address(
-            &wallet,
-            &address,
-            attempt_number,
-            chunk_size,
-            receive_start,
-            change_start,
-            address_type,
-        );
+        // resume the search of the same address, skipping the indexes an
+        // earlier attempt already compared
+        let mut search = match self.meta_storage.get_verification_search(address_type)? {
+            Some(search) => serde_json::from_str::<VerificationSearch>(&search)?,
+            None => VerificationSearch::default(),
+        };
+        if search.address != address {
+            search = VerificationSearch {
+                address: address.clone(),
+                ..Default::default()
+            };
+        }
 
-        if let (Some(index), Some(keychain)) = (result.found_index, result.keychain) {
-            self.meta_storage
-                .set_last_verified_address(address_type, keychain, index)?;
+        // addresses the wallet revealed are found without deriving any
+        let script = Address::<NetworkUnchecked>::from_str(&address)?
+            .assume_checked()
+            .script_pubkey();
+        let indexed = spk_index::lookup(self.meta_storage.as_ref(), &script)?
+            .filter(|derivation| derivation.address_type == address_type);
+        let result = match indexed {
+            Some(derivation) => {
+                let (receive, change) = match derivation.keychain {
+                    KeychainKind::External => (derivation.index, change_start),
+                    KeychainKind::Internal => (receive_start, derivation.index),
+                };
+                AddressVerificationResult {
+                    found_index: Some(derivation.index),
+                    keychain: Some(derivation.keychain),
+                    address_type,
+                    change_lower: change,
+                    change_upper: change,
+                    receive_lower: receive,
+                    receive_upper: receive,
+                }
+            }
+            None => search_address(
+                &wallet,
+                &address,
+                attempt_number,
+                chunk_size,
+                receive_start,
+                change_start,
+                address_type,
+                &mut search,
+            ),
+        };
+
+        match (result.found_index, result.keychain) {
+            (Some(index), Some(keychain)) => {
+                self.meta_storage
+                    .set_last_verified_address(address_type, keychain, index)?;
+                self.meta_storage
+                    .set_verification_search(address_type, None)?;
+            }
+            _ => self
+                .meta_storage
+                .set_verification_search(address_type, Some(&serde_json::to_string(&search)?))?,
         }
 
         Ok(result)
     }
 
+    /// Forget the verified addresses and the searches in progress, so the
+    /// next verification starts over from index 0.
+    pub fn reset_verification_state(&self) -> anyhow::Result<()> {
+        self.meta_storage.reset_verification_state()?;
+        self.meta_storage.persist()?;
+        Ok(())
+    }
+
     /// Closes all wallet connections, releasing database file handles.
     /// This should be called before deleting the account directory from disk.
     pub fn close(&self) {
-        self.wallets.write().unwrap().clear();
+        self.wallets.write_or_recover().clear();
+        self.drop_closed_wallets();
     }
 
     pub fn get_bip329_data(&self) -> anyhow::Result<Vec<String>> {
         let mut result = vec![];
         let mut seen_tx_refs = HashSet::new();
-        let config = self.config.read().unwrap();
+        let config = self.config.read_or_err()?;
 
-        for wallet in self.wallets.read().unwrap().iter() {
+        for wallet in self.all_wallets()?.iter() {
             let descriptor = wallet
                 .bdk_wallet
-                .lock()
-                .unwrap()
+                .lock_or_err()?
                 .public_descriptor(KeychainKind::External)
                 .to_string();
 
@@ -969,6 +2081,43 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 }
 
+pub(crate) fn check_tx_id(tx_id: &str) -> Result<(), AccountError> {
+    Txid::from_str(tx_id)
+        .map(|_| ())
+        .map_err(|_| AccountError::InvalidTxId(tx_id.to_string()))
+}
+
+// Output ids are `txid:vout`.
+pub(crate) fn check_output_id(output_id: &str) -> Result<(), AccountError> {
+    OutPoint::from_str(output_id)
+        .map(|_| ())
+        .map_err(|_| AccountError::InvalidOutputId(output_id.to_string()))
+}
+
+/// Reported by [`NgAccount::prioritized_sync`] after each wallet, with
+/// the account totals so far.
+#[derive(Debug, Clone)]
+pub struct SyncedWallet {
+    pub address_type: AddressType,
+    pub balance: Balance,
+    pub transactions: Vec<BitcoinTransaction>,
+    /// Wallets left to sync.
+    pub remaining: usize,
+}
+
+/// Receives [`SyncedWallet`] reports.
+#[cfg(feature = "envoy")]
+pub type SyncedCallback = Arc<dyn Fn(SyncedWallet) + Send + Sync>;
+
+/// Outcome of [`NgAccount::repair_indices`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexRepair {
+    /// The new last revealed index of each keychain that moved.
+    pub revealed: Vec<(AddressType, KeychainKind, u32)>,
+    pub new_transactions: usize,
+    pub new_utxos: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct AddressVerificationResult {
     pub found_index: Option<u32>,
@@ -991,6 +2140,64 @@ pub struct AddressVerificationInfo {
     pub change_start: u32,
 }
 
+/// Indexes already compared with the address being verified, as sorted
+/// and disjoint inclusive ranges, so an interrupted search resumes where
+/// it stopped.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationSearch {
+    pub address: String,
+    pub receive: Vec<(u32, u32)>,
+    pub change: Vec<(u32, u32)>,
+}
+
+impl VerificationSearch {
+    fn ranges(&self, keychain: KeychainKind) -> &[(u32, u32)] {
+        match keychain {
+            KeychainKind::External => &self.receive,
+            KeychainKind::Internal => &self.change,
+        }
+    }
+
+    pub fn contains(&self, keychain: KeychainKind, index: u32) -> bool {
+        let ranges = self.ranges(keychain);
+        let i = ranges.partition_point(|&(_, upper)| upper < index);
+        ranges.get(i).is_some_and(|&(lower, _)| lower <= index)
+    }
+
+    fn insert(&mut self, keychain: KeychainKind, index: u32) {
+        let ranges = match keychain {
+            KeychainKind::External => &mut self.receive,
+            KeychainKind::Internal => &mut self.change,
+        };
+        // first range ending next to index or after it
+        let i = ranges.partition_point(|&(_, upper)| upper.saturating_add(1) < index);
+        if let Some(range) = ranges.get_mut(i)
+            && range.0 <= index.saturating_add(1)
+        {
+            range.0 = range.0.min(index);
+            range.1 = range.1.max(index);
+            if let Some(&(lower, upper)) = ranges.get(i + 1)
+                && lower <= ranges[i].1.saturating_add(1)
+            {
+                ranges[i].1 = upper;
+                ranges.remove(i + 1);
+            }
+        } else {
+            ranges.insert(i, (index, index));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportXpub {
+    pub address_type: AddressType,
+    pub xpub: String,
+    /// The same key with the SLIP-132 prefix of `address_type`.
+    pub slip132: String,
+    pub derivation_path: String,
+    pub fingerprint: String,
+}
+
 pub fn search_for_address(
     wallet: &bdk_wallet::Wallet,
     address: &str,
@@ -1000,8 +2207,41 @@ pub fn search_for_address(
     change_start: u32,
     address_type: AddressType,
 ) -> AddressVerificationResult {
+    search_address(
+        wallet,
+        address,
+        attempt_number,
+        chunk_size,
+        receive_start,
+        change_start,
+        address_type,
+        &mut VerificationSearch::default(),
+    )
+}
+
+// search_for_address, skipping the indexes in `search` and adding the ones
+// it compares
+#[allow(clippy::too_many_arguments)]
+fn search_address(
+    wallet: &bdk_wallet::Wallet,
+    address: &str,
+    attempt_number: u32,
+    chunk_size: u32,
+    receive_start: u32,
+    change_start: u32,
+    address_type: AddressType,
+    search: &mut VerificationSearch,
+) -> AddressVerificationResult {
+    let mut matches = |keychain: KeychainKind, index: u32| {
+        if search.contains(keychain, index) {
+            return false;
+        }
+        search.insert(keychain, index);
+        address == wallet.peek_address(keychain, index).to_string()
+    };
+
     // Optimization to always check address 0, which is often used during pairing
-    if address == wallet.peek_address(KeychainKind::External, 0).to_string() {
+    if matches(KeychainKind::External, 0) {
         return AddressVerificationResult {
             found_index: Some(0),
             keychain: Some(KeychainKind::External),
@@ -1032,7 +2272,7 @@ pub fn search_for_address(
                     KeychainKind::Internal => change_lower = low_index,
                     KeychainKind::External => receive_lower = low_index,
                 }
-                if address == wallet.peek_address(keychain, low_index).to_string() {
+                if matches(keychain, low_index) {
                     return AddressVerificationResult {
                         found_index: Some(low_index),
                         keychain: Some(keychain),
@@ -1050,7 +2290,7 @@ pub fn search_for_address(
                     KeychainKind::Internal => change_upper = high_index,
                     KeychainKind::External => receive_upper = high_index,
                 }
-                if address == wallet.peek_address(keychain, high_index).to_string() {
+                if matches(keychain, high_index) {
                     return AddressVerificationResult {
                         found_index: Some(high_index),
                         keychain: Some(keychain),
@@ -1104,16 +2344,90 @@ mod tests {
             network: Network::Bitcoin,
             id: "test_id".to_string(),
             multisig: None,
+            vault: None,
             archived: false,
             last_remote_sequence: 0,
+            last_metadata_sync: 0,
+            gap_limit: None,
+            dust_relay_fee: None,
+            birthday: None,
+            seed_id: None,
+            spending_policy: None,
+            migrated_from: None,
+            migrated_to: None,
+            deferred_address_types: vec![],
+            retired_descriptors: vec![],
         };
 
         let account = NgAccount {
             config: Arc::new(RwLock::new(config)),
             wallets: Arc::new(RwLock::new(Vec::<NgWallet<Connection>>::new())),
             meta_storage: Arc::new(InMemoryMetaStorage::default()),
+            events: Default::default(),
+            lazy: Default::default(),
+            locked: Default::default(),
+            query_cache: Default::default(),
         };
 
         let _sendable: Box<dyn Any + Send> = Box::new(account);
     }
+
+    #[test]
+    fn verification_search_merges_ranges() {
+        let mut search = VerificationSearch::default();
+        for index in [5, 3, 4, 10, 0, 9] {
+            search.insert(KeychainKind::External, index);
+        }
+        assert_eq!(search.receive, vec![(0, 0), (3, 5), (9, 10)]);
+        search.insert(KeychainKind::External, 4);
+        search.insert(KeychainKind::External, 1);
+        search.insert(KeychainKind::External, 2);
+        assert_eq!(search.receive, vec![(0, 5), (9, 10)]);
+        assert!(search.change.is_empty());
+
+        assert!(search.contains(KeychainKind::External, 2));
+        assert!(search.contains(KeychainKind::External, 10));
+        assert!(!search.contains(KeychainKind::External, 7));
+        assert!(!search.contains(KeychainKind::External, 11));
+        assert!(!search.contains(KeychainKind::Internal, 2));
+    }
+
+    #[test]
+    fn legacy_persister_files_are_renamed() {
+        use bdk_wallet::descriptor::calc_checksum;
+
+        // spelled with `h` markers, as stored by older apps
+        let body = "wpkh(tprv8ZgxMBicQKsPeLx4U7UmbcYU5VhS4BRxv86o1gNqNqxEEJL47F9ZZhvBi1EVbKPmmFYnTEZ6uArarK6zZyrZf7mSyWZRAuNKQp4dHfxBdMM/84h/1h/0h/0/*)";
+        let descriptor = format!("{body}#{}", calc_checksum(body).unwrap());
+        let file_name = get_persister_file_name(&descriptor, None);
+        let legacy = legacy_persister_file_name(&descriptor, None);
+        assert_ne!(file_name, legacy);
+
+        let dir = std::env::temp_dir().join("ngwallet_legacy_persister");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let account_path = dir.to_string_lossy().to_string();
+        std::fs::write(dir.join(&legacy), "wallet").unwrap();
+        std::fs::write(dir.join(format!("{legacy}-wal")), "journal").unwrap();
+
+        let migrated = migrate_persister_file(&account_path, &descriptor, None).unwrap();
+        assert_eq!(migrated, file_name);
+        assert!(!dir.join(&legacy).exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join(&file_name)).unwrap(),
+            "wallet"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("{file_name}-wal"))).unwrap(),
+            "journal"
+        );
+
+        // a file with the new name is never replaced
+        std::fs::write(dir.join(&legacy), "stale").unwrap();
+        migrate_persister_file(&account_path, &descriptor, None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join(&file_name)).unwrap(),
+            "wallet"
+        );
+    }
 }
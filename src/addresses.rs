@@ -0,0 +1,245 @@
+//! Address explorer: every address of a wallet with its usage and balance.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use bdk_wallet::bitcoin::{Address, ScriptBuf};
+use bdk_wallet::{AddressInfo, KeychainKind, PersistedWallet, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::config::AddressType;
+use crate::error::{AccountError, MutexExt, RwLockExt};
+use crate::events::AccountEvent;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub index: u32,
+    pub address: String,
+    /// True once any transaction paid to or spent from the address.
+    pub used: bool,
+    /// Unspent amount held by the address, in sats.
+    pub balance: u64,
+    /// Note set on the address.
+    pub label: Option<String>,
+    pub tx_count: usize,
+}
+
+/// How far the revealed addresses of a keychain run past the used ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressGap {
+    pub address_type: AddressType,
+    pub keychain: KeychainKind,
+    /// Last revealed index, `None` if no address was revealed.
+    pub revealed_index: Option<u32>,
+    /// Highest index any transaction paid to, `None` if none was paid.
+    pub last_used_index: Option<u32>,
+    /// Revealed addresses after the last used one.
+    pub unused_run: u32,
+    /// See [`NgAccount::gap_limit`].
+    pub gap_limit: u32,
+}
+
+impl AddressGap {
+    /// True if a full scan, like the one of a restore, stops before the
+    /// last revealed addresses, so payments to them aren't found.
+    pub fn exceeds_gap_limit(&self) -> bool {
+        self.unused_run > self.gap_limit
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// True if any transaction of the account paid to `address`.
+    ///
+    /// Used addresses of the account are already skipped by
+    /// [`NgAccount::next_address`], this is for checking addresses handed
+    /// out earlier or pasted as a recipient.
+    pub fn is_address_used(&self, address: &str) -> Result<bool> {
+        let network = self.config.read_or_err()?.network;
+        let script = Address::from_str(address)
+            .with_context(|| "Invalid address")?
+            .require_network(network)
+            .with_context(|| "Address is for another network")?
+            .script_pubkey();
+        Ok(self
+            .all_wallets_or_recover()
+            .iter()
+            .any(|wallet| has_received_to(&wallet.bdk_wallet.lock_or_recover(), &script)))
+    }
+
+    /// True if `address` belongs to any wallet of the account, revealed or
+    /// not.
+    pub fn owns_address(&self, address: &str) -> Result<bool> {
+        let network = self.config.read_or_err()?.network;
+        let script = Address::from_str(address)
+            .with_context(|| "Invalid address")?
+            .require_network(network)
+            .with_context(|| "Address is for another network")?
+            .script_pubkey();
+        for wallet in self.all_wallets()?.iter() {
+            if wallet.bdk_wallet.lock_or_err()?.is_mine(script.clone()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Revealed and used addresses of every keychain of every wallet, for
+    /// warning about addresses handed out past the gap limit.
+    pub fn address_gaps(&self) -> Result<Vec<AddressGap>> {
+        let gap_limit = self.gap_limit();
+        let mut gaps = vec![];
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            for keychain in bdk_wallet.keychains().map(|(keychain, _)| keychain) {
+                let revealed_index = bdk_wallet.derivation_index(keychain);
+                let last_used_index = bdk_wallet.spk_index().last_used_index(keychain);
+                let unused_run = match (revealed_index, last_used_index) {
+                    (Some(revealed), Some(used)) => revealed.saturating_sub(used),
+                    (Some(revealed), None) => revealed + 1,
+                    (None, _) => 0,
+                };
+                gaps.push(AddressGap {
+                    address_type: wallet.address_type,
+                    keychain,
+                    revealed_index,
+                    last_used_index,
+                    unused_run,
+                    gap_limit,
+                });
+            }
+        }
+        Ok(gaps)
+    }
+
+    /// Reveal the next `count` addresses of `keychain` of the `address_type`
+    /// wallet, used or not, and persist once. For handing out many
+    /// addresses ahead, like invoices or test payments; revealing more than
+    /// [`NgAccount::gap_limit`] unused ones hides payments to the last ones
+    /// from a restore, see [`NgAccount::address_gaps`].
+    pub fn reveal_addresses(
+        &self,
+        address_type: AddressType,
+        keychain: KeychainKind,
+        count: u32,
+    ) -> Result<Vec<AddressInfo>> {
+        self.open_wallet(address_type)?;
+        let wallet = self
+            .wallets
+            .read_or_err()?
+            .iter()
+            .find(|wallet| wallet.address_type == address_type)
+            .cloned()
+            .ok_or(AccountError::WalletNotFound(address_type))?;
+        let addresses: Vec<AddressInfo> = {
+            let mut bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            (0..count)
+                .map(|_| bdk_wallet.reveal_next_address(keychain))
+                .collect()
+        };
+        self.persist()?;
+        if keychain == KeychainKind::External {
+            for address in &addresses {
+                self.events.emit(AccountEvent::AddressRevealed {
+                    address: address.address.to_string(),
+                    address_type,
+                    index: address.index,
+                });
+            }
+        }
+        Ok(addresses)
+    }
+
+    /// Next unused change address of the coordinator wallet, for another
+    /// account to send its change to with
+    /// [`crate::send::TransactionParams::change_address`].
+    pub fn transfer_address(&self) -> Result<String> {
+        let address = self
+            .get_coordinator_wallet()
+            .bdk_wallet
+            .lock_or_err()?
+            .next_unused_address(KeychainKind::Internal)
+            .address
+            .to_string();
+        self.persist()?;
+        Ok(address)
+    }
+
+    /// Addresses of the `address_type` wallet at the indexes in `range` of
+    /// `keychain`, revealed or not.
+    pub fn list_addresses(
+        &self,
+        address_type: AddressType,
+        keychain: KeychainKind,
+        range: Range<u32>,
+    ) -> Result<Vec<AddressEntry>> {
+        self.open_wallet(address_type)?;
+        let wallet = self
+            .wallets
+            .read_or_err()?
+            .iter()
+            .find(|wallet| wallet.address_type == address_type)
+            .cloned()
+            .ok_or(AccountError::WalletNotFound(address_type))?;
+        let wallet = wallet.bdk_wallet.lock_or_err()?;
+
+        let mut entries: Vec<AddressEntry> = vec![];
+        let mut by_script = HashMap::new();
+        for index in range {
+            let address = wallet.peek_address(keychain, index).address;
+            by_script.insert(address.script_pubkey(), entries.len());
+            entries.push(AddressEntry {
+                index,
+                address: address.to_string(),
+                used: false,
+                balance: 0,
+                label: self.meta_storage.get_note(&address.to_string())?,
+                tx_count: 0,
+            });
+        }
+
+        for tx in wallet.transactions() {
+            let tx = tx.tx_node.tx;
+            let spent = tx
+                .input
+                .iter()
+                .filter_map(|input| wallet.tx_graph().get_txout(input.previous_output))
+                .map(|txout| &txout.script_pubkey);
+            let received = tx.output.iter().map(|txout| &txout.script_pubkey);
+            let mut touched: Vec<usize> = spent
+                .chain(received)
+                .filter_map(|script| by_script.get(script).copied())
+                .collect();
+            touched.sort_unstable();
+            touched.dedup();
+            for position in touched {
+                entries[position].tx_count += 1;
+                entries[position].used = true;
+            }
+        }
+
+        for utxo in wallet.list_unspent() {
+            if let Some(position) = by_script.get(&utxo.txout.script_pubkey) {
+                entries[*position].balance += utxo.txout.value.to_sat();
+            }
+        }
+        Ok(entries)
+    }
+}
+
+// Returns true if any transaction of the wallet pays to `script`.
+pub(crate) fn has_received_to<P: WalletPersister>(
+    wallet: &PersistedWallet<P>,
+    script: &ScriptBuf,
+) -> bool {
+    wallet.transactions().any(|canonical_tx| {
+        canonical_tx
+            .tx_node
+            .tx
+            .output
+            .iter()
+            .any(|output| &output.script_pubkey == script)
+    })
+}
@@ -0,0 +1,287 @@
+//! On-chain clustering of the account's coins.
+//!
+//! Chain analysis links addresses with two heuristics: the inputs of a
+//! transaction belong to the same owner (common-input-ownership), and so
+//! does its change. [`NgAccount::clusters`] applies them to the account
+//! history so the coin-control UI can show which coins are already linked
+//! before the user merges more.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::hashes::{Hash, sha256};
+use bdk_wallet::bitcoin::hex::DisplayHex;
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::transaction::{BitcoinTransaction, Input, KeyChain};
+
+/// Coins and transactions of the account linked to each other on chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cluster {
+    /// Derived from the oldest coin of the cluster, so it doesn't change
+    /// across syncs unless the cluster is merged into an older one.
+    pub id: String,
+    pub addresses: Vec<String>,
+    /// Unspent outputs, as `txid:vout`.
+    pub utxos: Vec<String>,
+    /// Transactions that received to or spent from the cluster.
+    pub transactions: Vec<String>,
+    /// Sum of the unspent outputs, in sats.
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterReport {
+    /// Largest balance first.
+    pub clusters: Vec<Cluster>,
+}
+
+impl ClusterReport {
+    /// Cluster of the output `output_id` (`txid:vout`).
+    pub fn cluster_of(&self, output_id: &str) -> Option<&Cluster> {
+        self.clusters
+            .iter()
+            .find(|cluster| cluster.utxos.iter().any(|utxo| utxo == output_id))
+    }
+}
+
+/// Addresses linked by the common-input-ownership and change heuristics in
+/// a transaction history.
+pub(crate) struct LinkedAddresses<'a> {
+    // address of every output in the history, by `txid:vout`
+    addresses: HashMap<String, &'a str>,
+    clusters: Clusters,
+}
+
+impl<'a> LinkedAddresses<'a> {
+    pub(crate) fn new(history: &'a [BitcoinTransaction]) -> Self {
+        let mut addresses: HashMap<String, &str> = HashMap::new();
+        for tx in history {
+            for output in &tx.outputs {
+                addresses.insert(output.get_id(), output.address.as_str());
+            }
+        }
+
+        let mut linked = Self {
+            addresses,
+            clusters: Clusters::default(),
+        };
+        for tx in history {
+            let mut spent: Vec<&str> = tx
+                .inputs
+                .iter()
+                .filter_map(|input| linked.address_of(input))
+                .collect();
+            if spent.is_empty() {
+                continue;
+            }
+            spent.extend(
+                tx.outputs
+                    .iter()
+                    .filter(|output| output.keychain == Some(KeyChain::Internal))
+                    .map(|output| output.address.as_str()),
+            );
+            for address in &spent[1..] {
+                linked.clusters.union(spent[0], address);
+            }
+        }
+        linked
+    }
+
+    /// Address of the output `input` spends, if it is in the history.
+    pub(crate) fn address_of(&self, input: &Input) -> Option<&'a str> {
+        self.addresses
+            .get(&format!("{}:{}", input.tx_id, input.vout))
+            .copied()
+    }
+
+    /// Representative of the cluster of `address`.
+    pub(crate) fn root(&mut self, address: &str) -> String {
+        self.clusters.find(address)
+    }
+}
+
+#[derive(Default)]
+struct Clusters {
+    parents: HashMap<String, String>,
+}
+
+impl Clusters {
+    fn find(&mut self, address: &str) -> String {
+        let parent = match self.parents.get(address) {
+            None => return address.to_string(),
+            Some(parent) if parent == address => return address.to_string(),
+            Some(parent) => parent.clone(),
+        };
+        let root = self.find(&parent);
+        self.parents.insert(address.to_string(), root.clone());
+        root
+    }
+
+    fn union(&mut self, a: &str, b: &str) {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a != root_b {
+            self.parents.insert(root_a, root_b);
+        }
+    }
+}
+
+/// Cluster the coins of `history` that belong to the account.
+pub fn cluster(history: &[BitcoinTransaction]) -> ClusterReport {
+    let mut linked = LinkedAddresses::new(history);
+    let spent: HashSet<String> = history
+        .iter()
+        .flat_map(|tx| &tx.inputs)
+        .map(|input| format!("{}:{}", input.tx_id, input.vout))
+        .collect();
+
+    // oldest first, unconfirmed last, so the result doesn't depend on the
+    // order of the history
+    let mut history: Vec<&BitcoinTransaction> = history.iter().collect();
+    history.sort_by(|a, b| {
+        (a.date.is_none(), a.date, &a.tx_id).cmp(&(b.date.is_none(), b.date, &b.tx_id))
+    });
+    let mut outputs: Vec<_> = history
+        .iter()
+        .flat_map(|tx| &tx.outputs)
+        .filter(|output| output.keychain.is_some())
+        .collect();
+    outputs.sort_by_key(|output| (output.date.is_none(), output.date, output.get_id()));
+
+    let mut clusters: BTreeMap<String, Cluster> = BTreeMap::new();
+    for output in outputs {
+        let root = linked.root(&output.address);
+        let cluster = clusters.entry(root).or_insert_with(|| Cluster {
+            id: cluster_id(&output.get_id()),
+            addresses: vec![],
+            utxos: vec![],
+            transactions: vec![],
+            balance: 0,
+        });
+        if !cluster.addresses.contains(&output.address) {
+            cluster.addresses.push(output.address.clone());
+        }
+        if !cluster.transactions.contains(&output.tx_id) {
+            cluster.transactions.push(output.tx_id.clone());
+        }
+        if !spent.contains(&output.get_id()) {
+            cluster.utxos.push(output.get_id());
+            cluster.balance += output.amount;
+        }
+    }
+
+    for tx in history {
+        let roots: Vec<String> = tx
+            .inputs
+            .iter()
+            .filter_map(|input| linked.address_of(input))
+            .map(|address| linked.root(address))
+            .collect();
+        for root in roots {
+            if let Some(cluster) = clusters.get_mut(&root)
+                && !cluster.transactions.contains(&tx.tx_id)
+            {
+                cluster.transactions.push(tx.tx_id.clone());
+            }
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = clusters.into_values().collect();
+    clusters.sort_by(|a, b| b.balance.cmp(&a.balance).then_with(|| a.id.cmp(&b.id)));
+    ClusterReport { clusters }
+}
+
+// Short identifier of the cluster whose oldest coin is `output_id`.
+fn cluster_id(output_id: &str) -> String {
+    let hash = sha256::Hash::hash(output_id.as_bytes()).to_byte_array();
+    hash[..8].to_lower_hex_string()
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Clusters of the coins of the account, see [`cluster`].
+    pub fn clusters(&self) -> Result<ClusterReport> {
+        Ok(cluster(&self.transactions()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Output;
+    use crate::transaction::fixtures::{self, input};
+
+    fn output(tx_id: &str, vout: u32, address: &str, keychain: KeyChain) -> Output {
+        Output {
+            keychain: Some(keychain),
+            ..fixtures::output(tx_id, vout, address)
+        }
+    }
+
+    fn tx(tx_id: &str, date: u64, inputs: Vec<Input>, outputs: Vec<Output>) -> BitcoinTransaction {
+        BitcoinTransaction {
+            inputs,
+            outputs: outputs
+                .into_iter()
+                .map(|output| Output {
+                    date: Some(date),
+                    ..output
+                })
+                .collect(),
+            date: Some(date),
+            ..fixtures::tx(tx_id)
+        }
+    }
+
+    #[test]
+    fn co_spent_coins_and_change_are_clustered() {
+        let mut history = vec![
+            tx(
+                "a",
+                1,
+                vec![],
+                vec![output("a", 0, "addr_a", KeyChain::External)],
+            ),
+            tx(
+                "b",
+                2,
+                vec![],
+                vec![output("b", 0, "addr_b", KeyChain::External)],
+            ),
+            tx(
+                "c",
+                3,
+                vec![],
+                vec![output("c", 0, "addr_c", KeyChain::External)],
+            ),
+        ];
+        let before = cluster(&history);
+        assert_eq!(before.clusters.len(), 3);
+        let id_a = before.cluster_of("a:0").unwrap().id.clone();
+
+        // a and b are spent together, the change joins them
+        history.push(tx(
+            "d",
+            4,
+            vec![input("b", 0), input("a", 0)],
+            vec![output("d", 1, "change_d", KeyChain::Internal)],
+        ));
+        let report = cluster(&history);
+        assert_eq!(report.clusters.len(), 2);
+        let linked = report.cluster_of("d:1").unwrap();
+        assert_eq!(linked.id, id_a);
+        assert_eq!(linked.addresses, vec!["addr_a", "addr_b", "change_d"]);
+        assert_eq!(linked.utxos, vec!["d:1"]);
+        assert_eq!(linked.transactions, vec!["a", "b", "d"]);
+        assert_eq!(linked.balance, 10_000);
+        assert_eq!(
+            report.cluster_of("c:0").unwrap().id,
+            before.cluster_of("c:0").unwrap().id
+        );
+
+        // the ids don't depend on the order of the history
+        history.reverse();
+        assert_eq!(cluster(&history), report);
+    }
+}
@@ -0,0 +1,109 @@
+//! Archiving dormant accounts.
+//!
+//! An archived account drops its BDK wallets and releases their persisters,
+//! keeping only the config and a balance snapshot in the metadata store.
+//! [`NgAccount::unarchive`] loads the wallets back from their persisters.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+use bdk_wallet::bitcoin::Amount;
+use bdk_wallet::{Balance, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::error::{AccountError, RwLockExt};
+use crate::ngwallet::NgWallet;
+
+/// Balance of an account when it was archived, in sats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    pub immature: u64,
+    pub trusted_pending: u64,
+    pub untrusted_pending: u64,
+    pub confirmed: u64,
+}
+
+impl From<Balance> for BalanceSnapshot {
+    fn from(balance: Balance) -> Self {
+        Self {
+            immature: balance.immature.to_sat(),
+            trusted_pending: balance.trusted_pending.to_sat(),
+            untrusted_pending: balance.untrusted_pending.to_sat(),
+            confirmed: balance.confirmed.to_sat(),
+        }
+    }
+}
+
+impl From<BalanceSnapshot> for Balance {
+    fn from(snapshot: BalanceSnapshot) -> Self {
+        Self {
+            immature: Amount::from_sat(snapshot.immature),
+            trusted_pending: Amount::from_sat(snapshot.trusted_pending),
+            untrusted_pending: Amount::from_sat(snapshot.untrusted_pending),
+            confirmed: Amount::from_sat(snapshot.confirmed),
+        }
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    pub fn is_archived(&self) -> bool {
+        self.config.read_or_recover().archived
+    }
+
+    /// Persist and drop the wallets of the account, keeping the config and
+    /// a snapshot of the balance. Until [`Self::unarchive`] only the config
+    /// and [`Self::balance`] are available.
+    pub fn archive(&self) -> Result<()> {
+        if self.is_archived() {
+            return Err(AccountError::AlreadyArchived.into());
+        }
+        let balance = self.balance()?;
+        self.meta_storage
+            .set_balance_snapshot(&balance.into())
+            .with_context(|| "Failed to store balance snapshot")?;
+
+        self.config.write_or_err()?.archived = true;
+        self.persist()?;
+        self.meta_storage.persist()?;
+        self.wallets.write_or_err()?.clear();
+        self.drop_closed_wallets();
+        Ok(())
+    }
+
+    /// Load the wallets of an archived account from `persisters`, given in
+    /// the order of the descriptors of the account config.
+    pub fn unarchive(&self, persisters: Vec<Arc<Mutex<P>>>) -> Result<()>
+    where
+        <P as WalletPersister>::Error: Debug,
+    {
+        if !self.is_archived() {
+            return Err(AccountError::NotArchived.into());
+        }
+        let descriptors = self.config.read_or_err()?.descriptors.clone();
+        if descriptors.len() != persisters.len() {
+            bail!(
+                "Expected {} persisters, got {}",
+                descriptors.len(),
+                persisters.len()
+            );
+        }
+
+        let mut wallets = vec![];
+        for (descriptor, persister) in descriptors.into_iter().zip(persisters) {
+            let wallet = NgWallet::load(
+                descriptor.internal,
+                descriptor.external,
+                self.meta_storage.clone(),
+                persister,
+            )
+            .with_context(|| "Failed to load wallet")?;
+            wallets.push(wallet);
+        }
+        *self.wallets.write_or_err()? = wallets;
+
+        self.config.write_or_err()?.archived = false;
+        self.persist()
+    }
+}
@@ -0,0 +1,302 @@
+//! Foreign language bindings, generated with uniffi.
+//!
+//! Mobile apps use [`Account`] instead of [`NgAccount`] directly: it owns a
+//! concrete account whose wallets are persisted in SQLite files and whose
+//! metadata lives in the redb store, both in the account directory. Types
+//! the app doesn't inspect field by field (draft transactions, backups) cross
+//! the boundary as JSON, like they do in [`crate::account::RemoteUpdate`].
+//!
+//! Errors carry the stable code of [`crate::error::error_code`] so the app
+//! can show a localized message.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::rusqlite::Connection;
+use bdk_wallet::{KeychainKind, SignOptions};
+
+use crate::account::{Descriptor, NgAccount, migrate_persister_file};
+use crate::config::{AddressType, MultiSigDetails, NgAccountBackup, NgAccountBuilder};
+use crate::error::{ComposeError, RwLockExt, error_code};
+use crate::fee_rate::FeeRateSatPerKvb;
+use crate::send::{DraftTransaction, FeeSharing, OutputOrdering, SpendPath, TransactionParams};
+
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+pub enum BindingsError {
+    #[error("{message}")]
+    Failed { code: Option<u32>, message: String },
+}
+
+impl From<anyhow::Error> for BindingsError {
+    fn from(error: anyhow::Error) -> Self {
+        BindingsError::Failed {
+            code: error_code(&error),
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+impl From<ComposeError> for BindingsError {
+    fn from(error: ComposeError) -> Self {
+        BindingsError::Failed {
+            code: Some(error.code()),
+            message: error.to_string(),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, BindingsError>;
+
+#[derive(Clone, uniffi::Record)]
+pub struct AccountDescriptor {
+    pub internal: String,
+    pub external: Option<String>,
+}
+
+impl fmt::Debug for AccountDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountDescriptor")
+            .field("internal", &"<redacted descriptor>")
+            .field(
+                "external",
+                &self.external.as_ref().map(|_| "<redacted descriptor>"),
+            )
+            .finish()
+    }
+}
+
+/// Parameters of [`Account::create`], see [`NgAccountBuilder`].
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct NewAccount {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+    pub device_serial: Option<String>,
+    pub date_added: Option<String>,
+    /// "bitcoin", "testnet", "signet" or "regtest".
+    pub network: String,
+    /// Address type name, like "p2wpkh" or "p2tr".
+    pub preferred_address_type: String,
+    pub index: u32,
+    pub seed_has_passphrase: bool,
+    pub descriptors: Vec<AccountDescriptor>,
+}
+
+/// Parameters of [`Account::compose`], see [`TransactionParams`].
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct SpendParams {
+    pub address: String,
+    pub amount: u64,
+    /// In sat/kvB.
+    pub fee_rate: u64,
+    /// Ids (`txid:vout`) of the outputs to spend, empty to let coin
+    /// selection choose.
+    pub selected_outputs: Vec<String>,
+    pub note: Option<String>,
+    pub tag: Option<String>,
+    pub do_not_spend_change: bool,
+    pub ordering: OutputOrdering,
+    /// Address of another account to send the change to.
+    pub change_address: Option<String>,
+    pub spend_path: SpendPath,
+}
+
+#[derive(uniffi::Object)]
+pub struct Account {
+    inner: NgAccount<Connection>,
+}
+
+fn parse_network(network: &str) -> Result<Network> {
+    Network::from_str(network)
+        .map_err(|e| BindingsError::from(anyhow::anyhow!("Invalid network {network}: {e}")))
+}
+
+// One SQLite persister per descriptor, in `account_path`.
+fn open_descriptors(
+    account_path: &str,
+    descriptors: Vec<AccountDescriptor>,
+) -> anyhow::Result<Vec<Descriptor<Connection>>> {
+    descriptors
+        .into_iter()
+        .map(|descriptor| {
+            let file_name = migrate_persister_file(
+                account_path,
+                &descriptor.internal,
+                descriptor.external.as_deref(),
+            )?;
+            let connection = Connection::open(format!("{account_path}/{file_name}"))?;
+            Ok(Descriptor {
+                internal: descriptor.internal,
+                external: descriptor.external,
+                bdk_persister: Arc::new(Mutex::new(connection)),
+            })
+        })
+        .collect()
+}
+
+#[uniffi::export]
+impl Account {
+    /// Create a new account in `account_path`.
+    #[uniffi::constructor]
+    pub fn create(params: NewAccount, account_path: String) -> Result<Arc<Self>> {
+        let descriptors = open_descriptors(&account_path, params.descriptors)?;
+        let inner = NgAccountBuilder::default()
+            .id(params.id)
+            .name(params.name)
+            .color(params.color)
+            .device_serial(params.device_serial)
+            .date_added(params.date_added)
+            .network(parse_network(&params.network)?)
+            .preferred_address_type(AddressType::try_from(params.preferred_address_type)?)
+            .index(params.index)
+            .seed_has_passphrase(params.seed_has_passphrase)
+            .descriptors(descriptors)
+            .account_path(Some(account_path.clone()))
+            .build_from_file(Some(account_path))?;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// Create a watch-only multisig account from a multisig config file
+    /// (the format exported by Passport and Sparrow).
+    #[uniffi::constructor]
+    pub fn import_multisig(
+        config: String,
+        id: String,
+        color: String,
+        network: String,
+        account_path: String,
+    ) -> Result<Arc<Self>> {
+        let (multisig, name) = MultiSigDetails::from_config(&config)?;
+        let secp = Secp256k1::new();
+        let (external, _) = multisig.to_descriptor(KeychainKind::External, &secp, None)?;
+        let (internal, _) = multisig.to_descriptor(KeychainKind::Internal, &secp, None)?;
+        let descriptors = open_descriptors(
+            &account_path,
+            vec![AccountDescriptor {
+                internal: internal.to_string(),
+                external: Some(external.to_string()),
+            }],
+        )?;
+        let inner = NgAccountBuilder::default()
+            .id(id)
+            .name(name)
+            .color(color)
+            .network(parse_network(&network)?)
+            .multisig(multisig)
+            .descriptors(descriptors)
+            .account_path(Some(account_path.clone()))
+            .build_from_file(Some(account_path))?;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// Open an account created earlier in `account_path`.
+    #[uniffi::constructor]
+    pub fn open(descriptors: Vec<AccountDescriptor>, account_path: String) -> Result<Arc<Self>> {
+        let descriptors = open_descriptors(&account_path, descriptors)?;
+        let inner = NgAccount::open_account_from_file(descriptors, Some(account_path))?;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    pub fn id(&self) -> String {
+        self.inner.config.read_or_recover().id.clone()
+    }
+
+    /// Total balance in sats.
+    pub fn balance(&self) -> Result<u64> {
+        Ok(self.inner.balance()?.total().to_sat())
+    }
+
+    /// Next unused receive address of the preferred address type.
+    pub fn next_address(&self) -> Result<String> {
+        let preferred = self.inner.config.read_or_recover().preferred_address_type;
+        let addresses = self.inner.next_address()?;
+        addresses
+            .iter()
+            .find(|(_, address_type)| *address_type == preferred)
+            .or(addresses.first())
+            .map(|(info, _)| info.address.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Account has no wallets").into())
+    }
+
+    /// Scan every wallet of the account and apply the updates.
+    pub fn full_scan(&self, electrum_server: String, socks_proxy: Option<String>) -> Result<()> {
+        Ok(self
+            .inner
+            .rescan(&electrum_server, socks_proxy.as_deref(), None)?)
+    }
+
+    /// Compose a transaction, returned as a JSON [`DraftTransaction`].
+    pub fn compose(&self, params: SpendParams) -> Result<String> {
+        let selected_outputs = match params.selected_outputs.is_empty() {
+            true => vec![],
+            false => self
+                .inner
+                .utxos()?
+                .into_iter()
+                .filter(|output| params.selected_outputs.contains(&output.get_id()))
+                .collect(),
+        };
+        let draft = self.inner.compose_psbt(TransactionParams {
+            address: params.address,
+            amount: params.amount,
+            fee_rate: FeeRateSatPerKvb(params.fee_rate),
+            selected_outputs,
+            note: params.note,
+            tag: params.tag,
+            do_not_spend_change: params.do_not_spend_change,
+            ordering: params.ordering,
+            change_address: params.change_address,
+            spend_path: params.spend_path,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::default(),
+            spend_from_tag: None,
+            embed_memo: false,
+        })?;
+        Ok(serde_json::to_string(&draft).map_err(anyhow::Error::from)?)
+    }
+
+    /// Sign a serialized PSBT with the keys held by the account.
+    pub fn sign(&self, psbt: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self.inner.sign(&psbt, SignOptions::default())?)
+    }
+
+    /// Replace the PSBT of a JSON draft with the signed `psbt` returned by a
+    /// signer, finalizing it when possible.
+    pub fn decode_signed(&self, draft: String, psbt: Vec<u8>) -> Result<String> {
+        let draft: DraftTransaction = serde_json::from_str(&draft).map_err(anyhow::Error::from)?;
+        let draft = NgAccount::<Connection>::decode_psbt(draft, &psbt)?;
+        Ok(serde_json::to_string(&draft).map_err(anyhow::Error::from)?)
+    }
+
+    /// Broadcast a finalized JSON draft, returning the txid.
+    pub fn broadcast(
+        &self,
+        draft: String,
+        electrum_server: String,
+        socks_proxy: Option<String>,
+    ) -> Result<String> {
+        let draft: DraftTransaction = serde_json::from_str(&draft).map_err(anyhow::Error::from)?;
+        let txid = NgAccount::<Connection>::broadcast_psbt(
+            draft,
+            &electrum_server,
+            socks_proxy.as_deref(),
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Broadcast failed: {e}"))?;
+        Ok(txid.to_string())
+    }
+
+    /// JSON backup of the account config and metadata.
+    pub fn backup(&self) -> Result<String> {
+        Ok(self.inner.get_backup_json()?)
+    }
+
+    /// Restore notes, tags and other metadata from a JSON backup.
+    pub fn restore_metadata(&self, backup: String) -> Result<()> {
+        let backup = NgAccountBackup::deserialize(&backup).map_err(anyhow::Error::from)?;
+        Ok(self.inner.restore_metadata(&backup)?)
+    }
+}
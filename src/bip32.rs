@@ -34,9 +34,25 @@ pub enum ParsePathError {
     ExpectedHardened,
 }
 
+/// Purposes of the BIPs [`NgAccountPath::parse`] knows.
+pub const STANDARD_PURPOSES: [u32; 5] = [44, 48, 49, 84, 86];
+
+/// Purposes whose paths have a script type level, like BIP-0048.
+pub const SCRIPT_TYPE_PURPOSES: [u32; 1] = [48];
+
 impl NgAccountPath {
     /// Parse a BIP-0044 like derivation path.
     pub fn parse(path: impl AsRef<[ChildNumber]>) -> Result<Option<Self>, ParsePathError> {
+        Self::parse_with_purposes(path, &STANDARD_PURPOSES, &SCRIPT_TYPE_PURPOSES)
+    }
+
+    /// Parse a BIP-0044 like derivation path of one of `purposes`, with a
+    /// script type level for `script_type_purposes`.
+    pub fn parse_with_purposes(
+        path: impl AsRef<[ChildNumber]>,
+        purposes: &[u32],
+        script_type_purposes: &[u32],
+    ) -> Result<Option<Self>, ParsePathError> {
         let mut iter = path.as_ref().iter().copied();
 
         // Only proceed if purpose is a BIP purpose we know, otherwise just
@@ -45,7 +61,7 @@ impl NgAccountPath {
             return Ok(None);
         };
 
-        if !matches!(purpose, 44 | 48 | 49 | 84 | 86) {
+        if !purposes.contains(&purpose) {
             return Ok(None);
         }
 
@@ -55,7 +71,7 @@ impl NgAccountPath {
             Self::expect_hardened(&mut iter)?.ok_or(ParsePathError::ExpectedCoinType)?;
         let account = Self::expect_hardened(&mut iter)?.ok_or(ParsePathError::ExpectedAccount)?;
 
-        let script_type = if purpose == 48 {
+        let script_type = if script_type_purposes.contains(&purpose) {
             Some(Self::expect_hardened(&mut iter)?.ok_or(ParsePathError::ExpectedScriptType)?)
         } else {
             None
@@ -1,8 +1,12 @@
+use crate::bip32::{NgAccountPath, ParsePathError};
 use crate::config::AddressType;
+use crate::key_handle::{SecretString, erase_keymap};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use bdk_wallet::KeychainKind;
 use bdk_wallet::bitcoin::Network;
 use bdk_wallet::bitcoin::bip32;
-use bdk_wallet::bitcoin::bip32::{Fingerprint, Xpriv};
+use bdk_wallet::bitcoin::bip32::{ChildNumber, Fingerprint, Xpriv};
 use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing};
 use bdk_wallet::descriptor::ExtendedDescriptor;
 use bdk_wallet::keys::KeyMap;
@@ -10,7 +14,7 @@ use bdk_wallet::keys::bip39;
 use bdk_wallet::keys::bip39::{Language, Mnemonic};
 use bdk_wallet::miniscript::descriptor::DescriptorType;
 use bdk_wallet::template::{Bip44, Bip48Member, Bip49, Bip84, Bip86, DescriptorTemplateOut};
-use std::{cmp::min, fmt};
+use core::{cmp::min, fmt};
 use thiserror::Error;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
@@ -127,6 +131,15 @@ pub enum Error {
 
     #[error("couldn't derive seed")]
     Bip85,
+
+    #[error("not an Electrum seed")]
+    NotElectrumSeed,
+
+    #[error("invalid extended private key")]
+    InvalidXprv,
+
+    #[error("couldn't build descriptors")]
+    InvalidDescriptor,
 }
 
 #[derive(PartialEq)]
@@ -150,19 +163,27 @@ impl fmt::Debug for Descriptors {
     }
 }
 
+// the keymaps hold the private keys of the account
+impl Drop for Descriptors {
+    fn drop(&mut self) {
+        erase_keymap(&mut self.descriptor.1);
+        erase_keymap(&mut self.change_descriptor.1);
+    }
+}
+
 impl Descriptors {
     pub fn bip(&self) -> &str {
         &self.bip
     }
 
-    pub fn descriptor_xprv(&self) -> String {
+    pub fn descriptor_xprv(&self) -> SecretString {
         let (desc, map) = &self.descriptor;
-        desc.to_string_with_secret(map)
+        SecretString::new(desc.to_string_with_secret(map))
     }
 
-    pub fn change_descriptor_xprv(&self) -> String {
+    pub fn change_descriptor_xprv(&self) -> SecretString {
         let (desc, map) = &self.change_descriptor;
-        desc.to_string_with_secret(map)
+        SecretString::new(desc.to_string_with_secret(map))
     }
 
     pub fn descriptor_xpub(&self) -> String {
@@ -215,80 +236,194 @@ pub fn get_descriptors(
     account_index: u32,
 ) -> anyhow::Result<Vec<Descriptors>> {
     let xprv: Xpriv = Xpriv::new_master(network, seed)?;
+    get_descriptors_from_xprv(xprv, network, account_index)
+}
 
-    let mut descriptors = vec![];
-
-    let descriptor_templates = vec![
-        NgDescriptorTemplate {
-            bip: String::from("49"),
-            export_addr_hint: AddressType::P2ShWpkh,
-            receive_template: Bip49(xprv, KeychainKind::External)
-                .build_account(network, account_index)?,
-            change_template: Bip49(xprv, KeychainKind::Internal)
-                .build_account(network, account_index)?,
-        },
-        NgDescriptorTemplate {
-            bip: String::from("44"),
-            export_addr_hint: AddressType::P2pkh,
-            receive_template: Bip44(xprv, KeychainKind::External)
-                .build_account(network, account_index)?,
-            change_template: Bip44(xprv, KeychainKind::Internal)
-                .build_account(network, account_index)?,
-        },
-        NgDescriptorTemplate {
-            bip: String::from("84"),
-            export_addr_hint: AddressType::P2wpkh,
-            receive_template: Bip84(xprv, KeychainKind::External)
-                .build_account(network, account_index)?,
-            change_template: Bip84(xprv, KeychainKind::Internal)
-                .build_account(network, account_index)?,
-        },
-        NgDescriptorTemplate {
-            bip: String::from("86"),
-            export_addr_hint: AddressType::P2tr,
-            receive_template: Bip86(xprv, KeychainKind::External)
-                .build_account(network, account_index)?,
-            change_template: Bip86(xprv, KeychainKind::Internal)
-                .build_account(network, account_index)?,
-        },
-        NgDescriptorTemplate {
-            bip: String::from("48_1"),
-            export_addr_hint: AddressType::P2ShWsh,
-            receive_template: Bip48Member(xprv, KeychainKind::External, 1)
-                .build_account(network, account_index)?,
-            change_template: Bip48Member(xprv, KeychainKind::Internal, 1)
-                .build_account(network, account_index)?,
-        },
-        NgDescriptorTemplate {
-            bip: String::from("48_2"),
-            export_addr_hint: AddressType::P2wsh,
-            receive_template: Bip48Member(xprv, KeychainKind::External, 2)
-                .build_account(network, account_index)?,
-            change_template: Bip48Member(xprv, KeychainKind::Internal, 2)
-                .build_account(network, account_index)?,
-        },
-    ];
-
-    for template in descriptor_templates {
-        let (bip, export_addr_hint, descriptor, key_map, change_descriptor, change_key_map) = (
-            template.bip,
-            template.export_addr_hint,
-            template.receive_template.0,
-            template.receive_template.1,
-            template.change_template.0,
-            template.change_template.1,
-        );
+/// Descriptors of every supported script type for the master key `xprv`.
+pub fn get_descriptors_from_xprv(
+    xprv: Xpriv,
+    network: Network,
+    account_index: u32,
+) -> anyhow::Result<Vec<Descriptors>> {
+    DescriptorTemplateRegistry::default().get_descriptors_from_xprv(xprv, network, account_index)
+}
 
-        descriptors.push(Descriptors {
-            descriptor_type: descriptor.desc_type(),
-            bip,
+/// Builds the receive and change descriptors of a template from the master
+/// key, network and account index.
+pub type BuildTemplate =
+    fn(Xpriv, Network, u32) -> anyhow::Result<(DescriptorTemplateOut, DescriptorTemplateOut)>;
+
+/// A descriptor template known to a [`DescriptorTemplateRegistry`].
+#[derive(Debug, Clone)]
+pub struct DescriptorTemplateSpec {
+    /// Name of the template, like `84` or `48_2`.
+    pub bip: String,
+    /// First level of the derivation paths of the template.
+    pub purpose: u32,
+    /// True if the paths have a script type level after the account, like
+    /// BIP-0048 ones.
+    pub has_script_type: bool,
+    pub export_addr_hint: AddressType,
+    pub build: BuildTemplate,
+}
+
+impl DescriptorTemplateSpec {
+    pub fn template(
+        &self,
+        xprv: Xpriv,
+        network: Network,
+        account_index: u32,
+    ) -> anyhow::Result<NgDescriptorTemplate> {
+        let (receive_template, change_template) = (self.build)(xprv, network, account_index)?;
+        Ok(NgDescriptorTemplate {
+            bip: self.bip.clone(),
+            export_addr_hint: self.export_addr_hint,
+            receive_template,
+            change_template,
+        })
+    }
+}
+
+/// The descriptor templates accounts are created from.
+///
+/// The default registry holds the BIP-0044, 49, 84, 86 and 48 templates,
+/// callers can [`register`](Self::register) templates with other derivation
+/// paths and parse their paths with [`parse_path`](Self::parse_path).
+#[derive(Debug, Clone)]
+pub struct DescriptorTemplateRegistry {
+    specs: Vec<DescriptorTemplateSpec>,
+}
+
+impl Default for DescriptorTemplateRegistry {
+    fn default() -> Self {
+        let spec = |bip: &str,
+                    purpose: u32,
+                    export_addr_hint: AddressType,
+                    build: BuildTemplate| DescriptorTemplateSpec {
+            bip: String::from(bip),
+            purpose,
+            has_script_type: purpose == 48,
             export_addr_hint,
-            descriptor: (descriptor, key_map),
-            change_descriptor: (change_descriptor, change_key_map),
-        });
+            build,
+        };
+        Self {
+            specs: vec![
+                spec("49", 49, AddressType::P2ShWpkh, |xprv, network, index| {
+                    Ok((
+                        Bip49(xprv, KeychainKind::External).build_account(network, index)?,
+                        Bip49(xprv, KeychainKind::Internal).build_account(network, index)?,
+                    ))
+                }),
+                spec("44", 44, AddressType::P2pkh, |xprv, network, index| {
+                    Ok((
+                        Bip44(xprv, KeychainKind::External).build_account(network, index)?,
+                        Bip44(xprv, KeychainKind::Internal).build_account(network, index)?,
+                    ))
+                }),
+                spec("84", 84, AddressType::P2wpkh, |xprv, network, index| {
+                    Ok((
+                        Bip84(xprv, KeychainKind::External).build_account(network, index)?,
+                        Bip84(xprv, KeychainKind::Internal).build_account(network, index)?,
+                    ))
+                }),
+                spec("86", 86, AddressType::P2tr, |xprv, network, index| {
+                    Ok((
+                        Bip86(xprv, KeychainKind::External).build_account(network, index)?,
+                        Bip86(xprv, KeychainKind::Internal).build_account(network, index)?,
+                    ))
+                }),
+                spec("48_1", 48, AddressType::P2ShWsh, |xprv, network, index| {
+                    Ok((
+                        Bip48Member(xprv, KeychainKind::External, 1)
+                            .build_account(network, index)?,
+                        Bip48Member(xprv, KeychainKind::Internal, 1)
+                            .build_account(network, index)?,
+                    ))
+                }),
+                spec("48_2", 48, AddressType::P2wsh, |xprv, network, index| {
+                    Ok((
+                        Bip48Member(xprv, KeychainKind::External, 2)
+                            .build_account(network, index)?,
+                        Bip48Member(xprv, KeychainKind::Internal, 2)
+                            .build_account(network, index)?,
+                    ))
+                }),
+            ],
+        }
     }
+}
 
-    Ok(descriptors)
+impl DescriptorTemplateRegistry {
+    /// Add `spec` after the registered templates. Names are unique, and a
+    /// purpose either always or never has a script type level.
+    pub fn register(&mut self, spec: DescriptorTemplateSpec) -> anyhow::Result<()> {
+        if self.specs.iter().any(|s| s.bip == spec.bip) {
+            anyhow::bail!("Template {} is already registered", spec.bip);
+        }
+        if self
+            .specs
+            .iter()
+            .any(|s| s.purpose == spec.purpose && s.has_script_type != spec.has_script_type)
+        {
+            anyhow::bail!("Purpose {} is registered with another layout", spec.purpose);
+        }
+        self.specs.push(spec);
+        Ok(())
+    }
+
+    pub fn specs(&self) -> &[DescriptorTemplateSpec] {
+        &self.specs
+    }
+
+    /// Descriptors of every registered template for `seed`.
+    pub fn get_descriptors(
+        &self,
+        seed: &[u8],
+        network: Network,
+        account_index: u32,
+    ) -> anyhow::Result<Vec<Descriptors>> {
+        let xprv: Xpriv = Xpriv::new_master(network, seed)?;
+        self.get_descriptors_from_xprv(xprv, network, account_index)
+    }
+
+    /// Descriptors of every registered template for the master key `xprv`.
+    pub fn get_descriptors_from_xprv(
+        &self,
+        xprv: Xpriv,
+        network: Network,
+        account_index: u32,
+    ) -> anyhow::Result<Vec<Descriptors>> {
+        let mut descriptors = vec![];
+        for spec in self.specs.iter() {
+            let template = spec.template(xprv, network, account_index)?;
+            let (descriptor, key_map, _) = template.receive_template;
+            let (change_descriptor, change_key_map, _) = template.change_template;
+
+            descriptors.push(Descriptors {
+                descriptor_type: descriptor.desc_type(),
+                bip: template.bip,
+                export_addr_hint: template.export_addr_hint,
+                descriptor: (descriptor, key_map),
+                change_descriptor: (change_descriptor, change_key_map),
+            });
+        }
+        Ok(descriptors)
+    }
+
+    /// Parse `path` as a path of one of the registered purposes.
+    pub fn parse_path(
+        &self,
+        path: impl AsRef<[ChildNumber]>,
+    ) -> Result<Option<NgAccountPath>, ParsePathError> {
+        let purposes: Vec<u32> = self.specs.iter().map(|s| s.purpose).collect();
+        let script_type_purposes: Vec<u32> = self
+            .specs
+            .iter()
+            .filter(|s| s.has_script_type)
+            .map(|s| s.purpose)
+            .collect();
+        NgAccountPath::parse_with_purposes(path, &purposes, &script_type_purposes)
+    }
 }
 
 #[cfg(test)]
@@ -311,8 +446,8 @@ mod test {
 
         let descriptors = get_descriptors(&seed, Network::Bitcoin, 0).unwrap();
 
-        assert_eq!(descriptors[0].descriptor_xprv(), "sh(wpkh(xprv9s21ZrQH143K4EyEi77g3rpPu5byQ3EnnMJ4Y2KRNFp5Z4hin7er2j1VEtW92DfDyLGaXvv7LAnMbeHLwWSkv3WJjNhXDhjV7up579LwqWK/49'/0'/0'/0/*))#ujfh5d2y".to_owned());
-        assert_eq!(descriptors[0].change_descriptor_xprv(), "sh(wpkh(xprv9s21ZrQH143K4EyEi77g3rpPu5byQ3EnnMJ4Y2KRNFp5Z4hin7er2j1VEtW92DfDyLGaXvv7LAnMbeHLwWSkv3WJjNhXDhjV7up579LwqWK/49'/0'/0'/1/*))#63pj0qps".to_owned());
+        assert_eq!(descriptors[0].descriptor_xprv().expose(), "sh(wpkh(xprv9s21ZrQH143K4EyEi77g3rpPu5byQ3EnnMJ4Y2KRNFp5Z4hin7er2j1VEtW92DfDyLGaXvv7LAnMbeHLwWSkv3WJjNhXDhjV7up579LwqWK/49'/0'/0'/0/*))#ujfh5d2y".to_owned());
+        assert_eq!(descriptors[0].change_descriptor_xprv().expose(), "sh(wpkh(xprv9s21ZrQH143K4EyEi77g3rpPu5byQ3EnnMJ4Y2KRNFp5Z4hin7er2j1VEtW92DfDyLGaXvv7LAnMbeHLwWSkv3WJjNhXDhjV7up579LwqWK/49'/0'/0'/1/*))#63pj0qps".to_owned());
         assert_eq!(descriptors[0].descriptor_xpub(), "sh(wpkh([ab88de89/49'/0'/0']xpub6CpdbYf1vdUMh5ryZWEQBoBVvmTTFYdi92VvknfMeVsgjiXXnmyDrCdkUKLzvEUYgBJrvyb3pmW488dctFrfJ1RaVNPa1T1nmraemfFCbuY/0/*))#k4daxnp5".to_owned());
         assert_eq!(descriptors[0].change_descriptor_xpub(), "sh(wpkh([ab88de89/49'/0'/0']xpub6CpdbYf1vdUMh5ryZWEQBoBVvmTTFYdi92VvknfMeVsgjiXXnmyDrCdkUKLzvEUYgBJrvyb3pmW488dctFrfJ1RaVNPa1T1nmraemfFCbuY/1/*))#r5rt7v5t".to_owned());
 
@@ -323,6 +458,64 @@ mod test {
         assert_eq!(descriptors[5].change_descriptor_xpub(), "pkh([ab88de89/48'/0'/0'/2']xpub6EPJuK8Ejz82nKc7PsRgcYqdcQH9G1ZikCTasr9i79CbXxMMiPfxEyA14S6HPTHufmcQR7x8t5L3BP9tRfm9EBRBPic2xV892j9z4ePESae/1/*)#0ufxu0ey".to_owned());
     }
 
+    #[test]
+    fn custom_templates_are_registered() {
+        use crate::bip39::{DescriptorTemplateRegistry, DescriptorTemplateSpec};
+        use crate::config::AddressType;
+        use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv};
+        use bdk_wallet::descriptor;
+        use core::str::FromStr;
+
+        let seed = Mnemonic::parse(
+            "axis minimum please frozen option smooth alone identify term fatigue crisp entry",
+        )
+        .unwrap()
+        .to_seed("");
+        let mut registry = DescriptorTemplateRegistry::default();
+        let custom = DescriptorTemplateSpec {
+            bip: "1017".to_string(),
+            purpose: 1017,
+            has_script_type: false,
+            export_addr_hint: AddressType::P2wpkh,
+            build: |xprv: Xpriv, _, index| {
+                let keychain = |change: u32| {
+                    let path = DerivationPath::from_str(&format!("m/1017'/0'/{index}'/{change}"))?;
+                    anyhow::Ok(descriptor!(wpkh((xprv, path)))?)
+                };
+                Ok((keychain(0)?, keychain(1)?))
+            },
+        };
+        registry.register(custom.clone()).unwrap();
+        assert!(registry.register(custom).is_err());
+
+        let descriptors = registry
+            .get_descriptors(&seed, Network::Bitcoin, 0)
+            .unwrap();
+        assert_eq!(descriptors.len(), 7);
+        assert_eq!(descriptors[6].bip, "1017");
+        assert!(
+            descriptors[6]
+                .descriptor_xprv()
+                .expose()
+                .starts_with("wpkh(xprv")
+        );
+        assert_ne!(
+            descriptors[6].descriptor_xprv(),
+            descriptors[6].change_descriptor_xprv()
+        );
+        assert_eq!(
+            get_descriptors(&seed, Network::Bitcoin, 0).unwrap(),
+            descriptors[..6]
+        );
+
+        let path = DerivationPath::from_str("m/1017'/0'/0'/1/3").unwrap();
+        assert_eq!(crate::bip32::NgAccountPath::parse(&path), Ok(None));
+        let parsed = registry.parse_path(&path).unwrap().unwrap();
+        assert_eq!(parsed.purpose, 1017);
+        assert_eq!(parsed.is_change(), Some(true));
+        assert_eq!(parsed.address_index, Some(3));
+    }
+
     #[cfg(feature = "envoy")]
     #[test]
     fn test_get_random_seed() {
@@ -0,0 +1,243 @@
+//! BIP-85 child seeds.
+//!
+//! [`Bip85Manager`] derives child mnemonics, WIF keys and xprvs from a
+//! master key, and records in the [`MetaStorage`] which indexes were handed
+//! out so a new child never reuses one by accident.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bdk_wallet::bitcoin::bip32::Xpriv;
+use bdk_wallet::bitcoin::secp256k1::{All, Secp256k1};
+use bdk_wallet::bitcoin::{Network, PrivateKey};
+use zeroize::Zeroizing;
+
+use crate::bip39::{self, MasterKey, WordCount};
+use crate::store::MetaStorage;
+
+/// A BIP-85 application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bip85Application {
+    /// An English BIP-39 mnemonic.
+    Mnemonic(WordCount),
+    /// A WIF encoded private key.
+    Wif,
+    /// An extended private key.
+    Xprv,
+}
+
+impl Bip85Application {
+    /// Derivation path of the child at `index`, after `83696968'`.
+    pub fn path(&self, index: u32) -> String {
+        match self {
+            Self::Mnemonic(word_count) => format!("39'/0'/{}'/{index}'", u32::from(*word_count)),
+            Self::Wif => format!("2'/{index}'"),
+            Self::Xprv => format!("32'/{index}'"),
+        }
+    }
+
+    // Application and index of a path built by `path`.
+    fn parse_path(path: &str) -> Option<(Self, u32)> {
+        let levels = path
+            .split('/')
+            .map(|level| level.strip_suffix('\'')?.parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match levels.as_slice() {
+            [39, 0, 12, index] => Some((Self::Mnemonic(WordCount::Twelve), *index)),
+            [39, 0, 18, index] => Some((Self::Mnemonic(WordCount::Eighteen), *index)),
+            [39, 0, 24, index] => Some((Self::Mnemonic(WordCount::TwentyFour), *index)),
+            [2, index] => Some((Self::Wif, *index)),
+            [32, index] => Some((Self::Xprv, *index)),
+            _ => None,
+        }
+    }
+}
+
+/// A child handed out by a [`Bip85Manager`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip85Child {
+    pub application: Bip85Application,
+    pub index: u32,
+    pub label: String,
+}
+
+pub struct Bip85Manager {
+    root: Xpriv,
+    storage: Arc<dyn MetaStorage>,
+    secp: Secp256k1<All>,
+}
+
+impl fmt::Debug for Bip85Manager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bip85Manager")
+            .field("storage", &self.storage)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Bip85Manager {
+    fn drop(&mut self) {
+        self.root.private_key.non_secure_erase();
+    }
+}
+
+impl Bip85Manager {
+    /// Derive children of `master_key`, recording them in `storage`.
+    pub fn new(
+        master_key: &MasterKey,
+        network: Network,
+        storage: Arc<dyn MetaStorage>,
+    ) -> Result<Self> {
+        let root = Xpriv::new_master(network, &master_key.key.0)?;
+        Ok(Self::from_xprv(root, storage))
+    }
+
+    /// Derive children of the `root` master xprv.
+    pub fn from_xprv(root: Xpriv, storage: Arc<dyn MetaStorage>) -> Self {
+        Self {
+            root,
+            storage,
+            secp: Secp256k1::new(),
+        }
+    }
+
+    /// Children handed out so far, by application and index.
+    pub fn children(&self) -> Result<Vec<Bip85Child>> {
+        let mut children: Vec<Bip85Child> = self
+            .storage
+            .list_bip85_children()?
+            .into_iter()
+            .filter_map(|(path, label)| {
+                let (application, index) = Bip85Application::parse_path(&path)?;
+                Some(Bip85Child {
+                    application,
+                    index,
+                    label,
+                })
+            })
+            .collect();
+        children.sort_by_key(|child| (child.application.path(0), child.index));
+        Ok(children)
+    }
+
+    /// First index of `application` above every index handed out.
+    pub fn next_index(&self, application: Bip85Application) -> Result<u32> {
+        Ok(self
+            .children()?
+            .iter()
+            .filter(|child| child.application == application)
+            .map(|child| child.index + 1)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Derive the child mnemonic at `index` without recording it, to show
+    /// it again or check a backup.
+    pub fn mnemonic(&self, word_count: WordCount, index: u32) -> Result<Zeroizing<String>> {
+        let mnemonic = ::bip85::to_mnemonic(&self.secp, &self.root, word_count.into(), index)
+            .map_err(|_| bip39::Error::Bip85)?;
+        Ok(Zeroizing::new(mnemonic.to_string()))
+    }
+
+    /// Derive the child mnemonic at `index` and record it under `label`.
+    pub fn derive_mnemonic(
+        &self,
+        word_count: WordCount,
+        index: u32,
+        label: &str,
+    ) -> Result<Zeroizing<String>> {
+        let mnemonic = self.mnemonic(word_count, index)?;
+        self.record(Bip85Application::Mnemonic(word_count), index, label)?;
+        Ok(mnemonic)
+    }
+
+    /// Derive the mnemonic at the next unused index, returning the index too.
+    pub fn next_mnemonic(
+        &self,
+        word_count: WordCount,
+        label: &str,
+    ) -> Result<(u32, Zeroizing<String>)> {
+        let index = self.next_index(Bip85Application::Mnemonic(word_count))?;
+        Ok((index, self.derive_mnemonic(word_count, index, label)?))
+    }
+
+    /// Derive the WIF private key at `index` and record it under `label`.
+    pub fn derive_wif(&self, index: u32, label: &str) -> Result<PrivateKey> {
+        let key =
+            ::bip85::to_wif(&self.secp, &self.root, index).map_err(|_| bip39::Error::Bip85)?;
+        self.record(Bip85Application::Wif, index, label)?;
+        Ok(key)
+    }
+
+    /// Derive the xprv at `index` and record it under `label`.
+    pub fn derive_xprv(&self, index: u32, label: &str) -> Result<Xpriv> {
+        let xprv =
+            ::bip85::to_xprv(&self.secp, &self.root, index).map_err(|_| bip39::Error::Bip85)?;
+        self.record(Bip85Application::Xprv, index, label)?;
+        Ok(xprv)
+    }
+
+    fn record(&self, application: Bip85Application, index: u32, label: &str) -> Result<()> {
+        self.storage
+            .set_bip85_child(&application.path(index), label)?;
+        self.storage.persist()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryMetaStorage;
+    use std::str::FromStr;
+
+    // Test vectors of BIP-85.
+    const ROOT: &str = "xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb";
+
+    #[test]
+    fn children_are_derived_and_recorded() {
+        let storage = Arc::new(InMemoryMetaStorage::default());
+        let manager = Bip85Manager::from_xprv(Xpriv::from_str(ROOT).unwrap(), storage.clone());
+
+        let (index, mnemonic) = manager.next_mnemonic(WordCount::Twelve, "Kids").unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(
+            mnemonic.as_str(),
+            "girl mad pet galaxy egg matter matrix prison refuse sense ordinary nose"
+        );
+        let wif = manager.derive_wif(0, "Paper wallet").unwrap();
+        assert_eq!(
+            wif.to_wif(),
+            "Kzyv4uF39d4Jrw2W7UryTHwZr1zQVNk4dAFyqE6BuMrMh1Za7uhp"
+        );
+        let xprv = manager.derive_xprv(0, "Node").unwrap();
+        assert_eq!(
+            xprv.to_string(),
+            "xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX"
+        );
+
+        // showing a child again doesn't record it
+        manager.mnemonic(WordCount::TwentyFour, 5).unwrap();
+        let (index, _) = manager.next_mnemonic(WordCount::Twelve, "Guest").unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(
+            manager
+                .next_index(Bip85Application::Mnemonic(WordCount::TwentyFour))
+                .unwrap(),
+            0
+        );
+
+        let children = manager.children().unwrap();
+        assert_eq!(children.len(), 4);
+        assert_eq!(
+            children[0],
+            Bip85Child {
+                application: Bip85Application::Wif,
+                index: 0,
+                label: "Paper wallet".to_string(),
+            }
+        );
+        assert_eq!(storage.list_bip85_children().unwrap().len(), children.len());
+    }
+}
@@ -1,19 +1,44 @@
-use anyhow::{self, Context, bail};
+use anyhow::{self, Context};
 use bdk_core::bitcoin::hex::DisplayHex;
+#[cfg(feature = "std")]
+use bdk_core::{ConfirmationBlockTime, TxUpdate};
+#[cfg(feature = "std")]
+use bdk_wallet::bitcoin::Txid;
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
 #[cfg(feature = "sha2")]
 use sha2::{Digest, Sha256};
-use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
-use std::fmt;
-use std::str::FromStr;
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
 use std::sync::Arc;
 
+#[cfg(feature = "std")]
 use crate::account::{Descriptor, NgAccount, RemoteUpdate};
 use crate::bip39::{Descriptors, MasterKey};
+use crate::collections::BTreeMap;
+#[cfg(feature = "std")]
 use crate::db::RedbMetaStorage;
+#[cfg(feature = "std")]
+use crate::fee_rate::FeeRateSatPerKvb;
+#[cfg(feature = "std")]
+use crate::fiat::FiatValue;
+use crate::key_handle::erase_xpriv;
+#[cfg(feature = "std")]
+use crate::policy::SpendingPolicy;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
 use crate::store::MetaStorage;
+#[cfg(feature = "std")]
+use crate::tags::TagInfo;
+#[cfg(feature = "std")]
 use crate::utils::get_address_type;
+#[cfg(feature = "std")]
+use crate::vault::VaultDetails;
 use bdk_wallet::KeychainKind;
+#[cfg(feature = "std")]
 use bdk_wallet::WalletPersister;
 use bdk_wallet::bitcoin::bip32::{
     self, ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub,
@@ -31,6 +56,7 @@ use bdk_wallet::miniscript::{
 use foundation_urtypes::registry::{
     ChildNumber as UrChildNumber, HDKeyRef, Key as UrKey, Terminal,
 };
+#[cfg(feature = "std")]
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
@@ -54,6 +80,7 @@ pub const ACCEPTED_FORMATS: &[AddressType] = &[AddressType::P2wsh, AddressType::
 //
 // BlueWallet emits `Zpub` whenever the multisig Format is P2WSH, which caused
 // `from_config` to fail on otherwise-valid imports — see SFT-6907.
+#[cfg(feature = "std")]
 fn normalize_slip132(key: &str) -> String {
     const XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
     const TPUB: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
@@ -157,6 +184,77 @@ impl MultiSigSigner {
     pub fn get_pubkey_str(&self) -> &str {
         &self.pubkey
     }
+
+    /// Script type of the BIP-48 derivation of the signer, `None` for
+    /// other derivations.
+    pub fn bip48_script_type(&self) -> Option<AddressType> {
+        let path = self.get_derivation().ok()?;
+        match path.as_ref() {
+            [
+                ChildNumber::Hardened { index: 48 },
+                _,
+                _,
+                ChildNumber::Hardened { index: script_type },
+            ] => match script_type {
+                1 => Some(AddressType::P2ShWsh),
+                2 => Some(AddressType::P2wsh),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The key of the signer in descriptors, `[fingerprint/derivation]xpub/<keychain>/*`.
+    pub fn to_descriptor_key(&self, keychain: KeychainKind) -> Option<DescriptorPublicKey> {
+        let (fingerprint, derivation_path, pubkey) = match (
+            self.get_fingerprint(),
+            self.get_derivation(),
+            self.get_pubkey(),
+        ) {
+            (f, Ok(d), Ok(p)) => (f, d, p),
+            _ => return None,
+        };
+        let path = DerivationPath::master().child(ChildNumber::Normal {
+            index: keychain as u32,
+        });
+        let descriptor_x_key: DescriptorXKey<Xpub> = DescriptorXKey {
+            origin: Some((fingerprint, derivation_path)),
+            xkey: pubkey,
+            derivation_path: path,
+            wildcard: Wildcard::Unhardened,
+        };
+        Some(DescriptorPublicKey::XPub(descriptor_x_key))
+    }
+}
+
+/// A problem of a multisig config that doesn't prevent using it, returned
+/// by [`MultiSigDetails::new_lenient`] instead of failing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiSigWarning {
+    /// The BIP-48 derivation of the signer is the one of another script
+    /// type, usually a `/1'` key in a P2WSH quorum or a `/2'` key in a
+    /// P2SH-P2WSH one.
+    ScriptTypeMismatch {
+        fingerprint: Fingerprint,
+        derivation: String,
+        format: AddressType,
+    },
+}
+
+impl fmt::Display for MultiSigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiSigWarning::ScriptTypeMismatch {
+                fingerprint,
+                derivation,
+                format,
+            } => write!(
+                f,
+                "Signer {fingerprint} derivation {derivation} is not for a {} multisig",
+                format.to_export_string()
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -217,12 +315,35 @@ impl fmt::Display for MultiSigDetails {
 
 impl MultiSigDetails {
     pub fn new(
+        policy_threshold: usize,
+        policy_total_keys: usize,
+        format: AddressType,
+        network_kind: Option<NetworkKind>,
+        signers: Vec<MultiSigSigner>,
+    ) -> Result<Self, anyhow::Error> {
+        let (details, warnings) = Self::new_lenient(
+            policy_threshold,
+            policy_total_keys,
+            format,
+            network_kind,
+            signers,
+        )?;
+        if let Some(warning) = warnings.first() {
+            anyhow::bail!("{warning}");
+        }
+        Ok(details)
+    }
+
+    /// Like [`Self::new`], but returns the signers whose BIP-48 derivation
+    /// doesn't match `format` as warnings instead of failing, for configs
+    /// already in use on chain.
+    pub fn new_lenient(
         policy_threshold: usize,
         policy_total_keys: usize,
         format: AddressType,
         mut network_kind: Option<NetworkKind>,
         mut signers: Vec<MultiSigSigner>,
-    ) -> Result<Self, anyhow::Error> {
+    ) -> Result<(Self, Vec<MultiSigWarning>), anyhow::Error> {
         // Sort by xpubs
         signers.sort();
 
@@ -274,7 +395,21 @@ impl MultiSigDetails {
             );
         }
 
-        Ok(Self {
+        let warnings = signers
+            .iter()
+            .filter(|signer| {
+                signer
+                    .bip48_script_type()
+                    .is_some_and(|script_type| script_type != format)
+            })
+            .map(|signer| MultiSigWarning::ScriptTypeMismatch {
+                fingerprint: signer.get_fingerprint(),
+                derivation: signer.derivation.clone(),
+                format,
+            })
+            .collect();
+
+        let details = Self {
             policy_threshold,
             policy_total_keys,
             format,
@@ -282,13 +417,48 @@ impl MultiSigDetails {
                 "Network kind was neither specified nor infered from xpubs"
             ))?,
             signers,
-        })
+        };
+        Ok((details, warnings))
     }
 
     pub fn get_signers(&self) -> &Vec<MultiSigSigner> {
         &self.signers
     }
 
+    /// The same quorum with the signer of `old_fingerprint` replaced by
+    /// `new_signer`, for when a key is lost or compromised.
+    pub fn rotate_signer(
+        &self,
+        old_fingerprint: Fingerprint,
+        new_signer: MultiSigSigner,
+    ) -> Result<Self, anyhow::Error> {
+        let position = self
+            .signers
+            .iter()
+            .position(|signer| signer.get_fingerprint() == old_fingerprint)
+            .ok_or(anyhow::anyhow!("Multisig has no signer {old_fingerprint}"))?;
+
+        let mut signers = self.signers.clone();
+        signers.remove(position);
+        if signers.iter().any(|signer| {
+            signer.fingerprint == new_signer.fingerprint || signer.pubkey == new_signer.pubkey
+        }) {
+            anyhow::bail!(
+                "Signer {} is already part of the multisig",
+                new_signer.get_fingerprint()
+            );
+        }
+        signers.push(new_signer);
+
+        Self::new(
+            self.policy_threshold,
+            self.policy_total_keys,
+            self.format,
+            Some(self.network_kind),
+            signers,
+        )
+    }
+
     pub fn default_name(&self) -> String {
         format!(
             "Multisig-{}-of-{}-{:?}",
@@ -297,7 +467,21 @@ impl MultiSigDetails {
     }
 
     // TODO: replace anyhows with thiserrors
+    #[cfg(feature = "std")]
     pub fn from_config(config: &str) -> Result<(Self, String), anyhow::Error> {
+        let (details, name, warnings) = Self::from_config_lenient(config)?;
+        if let Some(warning) = warnings.first() {
+            anyhow::bail!("{warning}");
+        }
+        Ok((details, name))
+    }
+
+    /// Like [`Self::from_config`], with the warnings of
+    /// [`Self::new_lenient`].
+    #[cfg(feature = "std")]
+    pub fn from_config_lenient(
+        config: &str,
+    ) -> Result<(Self, String, Vec<MultiSigWarning>), anyhow::Error> {
         let mut name: Option<String> = None;
         let mut policy_threshold: Option<usize> = None;
         let mut policy_total_keys: Option<usize> = None;
@@ -389,7 +573,7 @@ impl MultiSigDetails {
             }
         }
 
-        let res = Self::new(
+        let (res, warnings) = Self::new_lenient(
             policy_threshold.ok_or(anyhow::anyhow!(
                 "Multisig config is missing policy threshold"
             ))?,
@@ -403,7 +587,7 @@ impl MultiSigDetails {
 
         let name = name.unwrap_or(res.default_name());
 
-        Ok((res, name))
+        Ok((res, name, warnings))
     }
 
     fn from_sorted_multi<T: bdk_wallet::descriptor::ScriptContext>(
@@ -563,31 +747,6 @@ impl MultiSigDetails {
         }
     }
 
-    fn signer_to_xpub(
-        &self,
-        signer: &MultiSigSigner,
-        keychain: KeychainKind,
-    ) -> Option<DescriptorPublicKey> {
-        let (fingerprint, derivation_path, pubkey) = match (
-            signer.get_fingerprint(),
-            signer.get_derivation(),
-            signer.get_pubkey(),
-        ) {
-            (f, Ok(d), Ok(p)) => (f, d, p),
-            _ => return None,
-        };
-        let path = DerivationPath::master().child(ChildNumber::Normal {
-            index: keychain as u32,
-        });
-        let descriptor_x_key: DescriptorXKey<Xpub> = DescriptorXKey {
-            origin: Some((fingerprint, derivation_path)),
-            xkey: pubkey,
-            derivation_path: path,
-            wildcard: Wildcard::Unhardened,
-        };
-        Some(DescriptorPublicKey::XPub(descriptor_x_key))
-    }
-
     pub fn to_descriptor<C: Signing>(
         &self,
         keychain: KeychainKind,
@@ -603,7 +762,7 @@ impl MultiSigDetails {
         let signers = self
             .signers
             .iter()
-            .filter_map(|s| self.signer_to_xpub(s, keychain))
+            .filter_map(|s| s.to_descriptor_key(keychain))
             .collect::<Vec<DescriptorPublicKey>>();
 
         let descriptor = match self.format {
@@ -621,38 +780,7 @@ impl MultiSigDetails {
             ),
         };
 
-        let mut keymap = BTreeMap::<DescriptorPublicKey, DescriptorSecretKey>::new();
-
-        if let Some(master) = master_key {
-            let fp = master.fingerprint;
-            let master_xprv = Xpriv::new_master(self.network_kind, &master.key.0)?;
-
-            descriptor.for_each_key(|pubkey| {
-                if let DescriptorPublicKey::XPub(xkey) = pubkey
-                    && let Some(origin) = &xkey.origin
-                    && origin.0 == fp
-                    && let Ok(derived_xprv) = master_xprv.derive_priv(secp, &origin.1)
-                {
-                    let derived_xpub = Xpub::from_priv(secp, &derived_xprv);
-                    let desc_xkey = DescriptorXKey {
-                        origin: Some(origin.clone()),
-                        xkey: derived_xprv,
-                        derivation_path: xkey.derivation_path.clone(),
-                        wildcard: xkey.wildcard,
-                    };
-                    keymap.insert(
-                        DescriptorPublicKey::XPub(DescriptorXKey {
-                            origin: Some(origin.clone()),
-                            xkey: derived_xpub,
-                            derivation_path: xkey.derivation_path.clone(),
-                            wildcard: xkey.wildcard,
-                        }),
-                        DescriptorSecretKey::XPrv(desc_xkey),
-                    );
-                }
-                true
-            });
-        }
+        let keymap = derive_keymap(&descriptor, self.network_kind, secp, master_key)?;
         Ok((descriptor, keymap))
     }
 
@@ -715,6 +843,62 @@ impl MultiSigDetails {
     }
 }
 
+/// Private keys of the keys of `descriptor` derived from `master_key`, empty
+/// if there is no master key.
+pub(crate) fn derive_keymap<C: Signing>(
+    descriptor: &BdkDescriptor<DescriptorPublicKey>,
+    network_kind: NetworkKind,
+    secp: &Secp256k1<C>,
+    master_key: Option<&MasterKey>,
+) -> Result<BTreeMap<DescriptorPublicKey, DescriptorSecretKey>, anyhow::Error> {
+    match master_key {
+        Some(master) => {
+            let mut master_xprv = Xpriv::new_master(network_kind, &master.key.0)?;
+            let keymap = derive_keymap_from(descriptor, secp, &master_xprv, master.fingerprint);
+            erase_xpriv(&mut master_xprv);
+            Ok(keymap)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Private keys of the keys of `descriptor` derived from `master_xprv` of
+/// fingerprint `fp`.
+pub(crate) fn derive_keymap_from<C: Signing>(
+    descriptor: &BdkDescriptor<DescriptorPublicKey>,
+    secp: &Secp256k1<C>,
+    master_xprv: &Xpriv,
+    fp: Fingerprint,
+) -> BTreeMap<DescriptorPublicKey, DescriptorSecretKey> {
+    let mut keymap = BTreeMap::<DescriptorPublicKey, DescriptorSecretKey>::new();
+    descriptor.for_each_key(|pubkey| {
+        if let DescriptorPublicKey::XPub(xkey) = pubkey
+            && let Some(origin) = &xkey.origin
+            && origin.0 == fp
+            && let Ok(derived_xprv) = master_xprv.derive_priv(secp, &origin.1)
+        {
+            let derived_xpub = Xpub::from_priv(secp, &derived_xprv);
+            let desc_xkey = DescriptorXKey {
+                origin: Some(origin.clone()),
+                xkey: derived_xprv,
+                derivation_path: xkey.derivation_path.clone(),
+                wildcard: xkey.wildcard,
+            };
+            keymap.insert(
+                DescriptorPublicKey::XPub(DescriptorXKey {
+                    origin: Some(origin.clone()),
+                    xkey: derived_xpub,
+                    derivation_path: xkey.derivation_path.clone(),
+                    wildcard: xkey.wildcard,
+                }),
+                DescriptorSecretKey::XPrv(desc_xkey),
+            );
+        }
+        true
+    });
+    keymap
+}
+
 /// Convert a `foundation-urtypes` key entry into a [`MultiSigSigner`].
 ///
 /// We walk the CBOR-decoded view (`DerivedKeyRef` + `KeypathRef`) and
@@ -967,6 +1151,7 @@ impl fmt::Debug for NgDescriptor {
     }
 }
 
+#[cfg(feature = "std")]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct NgAccountConfig {
     pub name: String,
@@ -982,13 +1167,95 @@ pub struct NgAccountConfig {
     pub id: String,
     pub multisig: Option<MultiSigDetails>,
     #[serde(default)]
+    pub vault: Option<VaultDetails>,
+    #[serde(default)]
     pub archived: bool,
     /// Monotonic counter incremented by each accepted `RemoteUpdate`. Used to
     /// reject replayed or stale updates.
     #[serde(default)]
     pub last_remote_sequence: u64,
+    /// Sequence of the last metadata sync applied, see
+    /// [`crate::metadata_sync`].
+    #[serde(default)]
+    pub last_metadata_sync: u64,
+    /// Consecutive unused addresses after which a full scan stops, `None`
+    /// uses the crate default.
+    #[serde(default)]
+    pub gap_limit: Option<u32>,
+    /// Fee rate deciding which outputs are dust, `None` uses the default of
+    /// the network, see [`crate::dust::DustPolicy`].
+    #[serde(default)]
+    pub dust_relay_fee: Option<FeeRateSatPerKvb>,
+    /// First block that can hold transactions of the account, `None` scans
+    /// the whole chain.
+    #[serde(default)]
+    pub birthday: Option<Birthday>,
+    /// Identifies the mnemonic of the account whatever its passphrase, see
+    /// [`crate::passphrase::seed_id`].
+    #[serde(default)]
+    pub seed_id: Option<String>,
+    /// Limits checked when composing and signing, `None` for no limits.
+    #[serde(default)]
+    pub spending_policy: Option<SpendingPolicy>,
+    /// Id of the account this one was migrated from, see
+    /// [`NgAccount::plan_migration`].
+    #[serde(default)]
+    pub migrated_from: Option<String>,
+    /// Id of the account this one was migrated to.
+    #[serde(default)]
+    pub migrated_to: Option<String>,
+    /// Script types synced last by a prioritized sync, or skipped, see
+    /// [`NgAccount::prioritized_sync`].
+    #[serde(default)]
+    pub deferred_address_types: Vec<AddressType>,
+    /// Descriptors replaced by [`NgAccount::replace_descriptor`], oldest
+    /// first, so the history of their wallets can still be found.
+    #[serde(default)]
+    pub retired_descriptors: Vec<NgDescriptor>,
 }
 
+/// When an account was created. Nothing before it is scanned.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Birthday {
+    /// Block height.
+    Height(u32),
+    /// Unix timestamp, in seconds, compared with block times.
+    Timestamp(u64),
+}
+
+#[cfg(feature = "std")]
+impl Birthday {
+    /// True if a transaction confirmed at `anchor` predates the birthday.
+    pub fn excludes(&self, anchor: &ConfirmationBlockTime) -> bool {
+        match self {
+            Birthday::Height(height) => anchor.block_id.height < *height,
+            Birthday::Timestamp(timestamp) => anchor.confirmation_time < *timestamp,
+        }
+    }
+
+    /// Drop the transactions confirmed before the birthday from `tx_update`.
+    /// Unconfirmed transactions and the ones also confirmed after the
+    /// birthday (in a reorg) are kept.
+    pub fn prune(&self, tx_update: &mut TxUpdate<ConfirmationBlockTime>) {
+        let (before, after): (Vec<_>, Vec<_>) = tx_update
+            .anchors
+            .iter()
+            .partition(|(anchor, _)| self.excludes(anchor));
+        let after: HashSet<Txid> = after.into_iter().map(|(_, txid)| *txid).collect();
+        let before: HashSet<Txid> = before
+            .into_iter()
+            .map(|(_, txid)| *txid)
+            .filter(|txid| !after.contains(txid))
+            .collect();
+        tx_update
+            .txs
+            .retain(|tx| !before.contains(&tx.compute_txid()));
+        tx_update.anchors.retain(|(_, txid)| !before.contains(txid));
+    }
+}
+
+#[cfg(feature = "std")]
 impl fmt::Debug for NgAccountConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let descriptors = format!("<redacted; {} descriptors>", self.descriptors.len());
@@ -1005,12 +1272,27 @@ impl fmt::Debug for NgAccountConfig {
             .field("network", &self.network)
             .field("id", &self.id)
             .field("multisig", &self.multisig)
+            .field("vault", &self.vault)
             .field("archived", &self.archived)
             .field("last_remote_sequence", &self.last_remote_sequence)
+            .field("last_metadata_sync", &self.last_metadata_sync)
+            .field("gap_limit", &self.gap_limit)
+            .field("dust_relay_fee", &self.dust_relay_fee)
+            .field("birthday", &self.birthday)
+            .field("seed_id", &self.seed_id)
+            .field("spending_policy", &self.spending_policy)
+            .field("migrated_from", &self.migrated_from)
+            .field("migrated_to", &self.migrated_to)
+            .field("deferred_address_types", &self.deferred_address_types)
+            .field(
+                "retired_descriptors",
+                &format!("<redacted; {} descriptors>", self.retired_descriptors.len()),
+            )
             .finish()
     }
 }
 
+#[cfg(feature = "std")]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct NgAccountBackup {
     pub ng_account_config: NgAccountConfig,
@@ -1024,8 +1306,13 @@ pub struct NgAccountBackup {
     pub notes: HashMap<String, String>,
     pub tags: HashMap<String, String>,
     pub do_not_spend: HashMap<String, bool>,
+    #[serde(default)]
+    pub fiat_values: HashMap<String, FiatValue>,
+    #[serde(default)]
+    pub tag_infos: Vec<TagInfo>,
 }
 
+#[cfg(feature = "std")]
 impl fmt::Debug for NgAccountBackup {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let public_descriptors = format!(
@@ -1040,10 +1327,13 @@ impl fmt::Debug for NgAccountBackup {
             .field("notes", &self.notes)
             .field("tags", &self.tags)
             .field("do_not_spend", &self.do_not_spend)
+            .field("fiat_values", &self.fiat_values)
+            .field("tag_infos", &self.tag_infos)
             .finish()
     }
 }
 
+#[cfg(feature = "std")]
 impl NgAccountConfig {
     /// SHA-256 of all descriptor strings, sorted by address type.
     /// Used as a binding field in `RemoteUpdate` to ensure updates are applied
@@ -1090,7 +1380,7 @@ impl NgAccountConfig {
         let update: RemoteUpdate = minicbor_serde::from_slice(&remote_update)?;
         match update.metadata {
             None => {
-                bail!("expected metadata")
+                anyhow::bail!("expected metadata")
             }
             Some(update) => Ok(update),
         }
@@ -1124,6 +1414,7 @@ impl NgAccountConfig {
     }
 }
 
+#[cfg(feature = "std")]
 fn descriptor_contains_private_material(descriptor: &str) -> bool {
     let descriptor = descriptor.to_ascii_lowercase();
     ["xprv", "tprv", "yprv", "zprv", "uprv", "vprv"]
@@ -1131,6 +1422,7 @@ fn descriptor_contains_private_material(descriptor: &str) -> bool {
         .any(|marker| descriptor.contains(marker))
 }
 
+#[cfg(feature = "std")]
 impl NgAccountBackup {
     pub fn serialize(&self) -> String {
         serde_json::to_string_pretty(self).unwrap()
@@ -1141,6 +1433,7 @@ impl NgAccountBackup {
     }
 }
 
+#[cfg(feature = "std")]
 impl<P: WalletPersister> Default for NgAccountBuilder<P> {
     fn default() -> Self {
         Self {
@@ -1157,11 +1450,21 @@ impl<P: WalletPersister> Default for NgAccountBuilder<P> {
             date_synced: None,
             seed_has_passphrase: None,
             multisig: None,
+            vault: None,
             archived: None,
+            gap_limit: None,
+            dust_relay_fee: None,
+            birthday: None,
+            seed_id: None,
+            spending_policy: None,
+            migrated_from: None,
+            deferred_address_types: vec![],
+            metadata_sync_key: None,
         }
     }
 }
 
+#[cfg(feature = "std")]
 pub struct NgAccountBuilder<P: WalletPersister> {
     name: Option<String>,
     color: Option<String>,
@@ -1176,9 +1479,19 @@ pub struct NgAccountBuilder<P: WalletPersister> {
     date_synced: Option<String>,
     seed_has_passphrase: Option<bool>,
     multisig: Option<MultiSigDetails>,
+    vault: Option<VaultDetails>,
     archived: Option<bool>,
+    gap_limit: Option<u32>,
+    dust_relay_fee: Option<FeeRateSatPerKvb>,
+    birthday: Option<Birthday>,
+    seed_id: Option<String>,
+    spending_policy: Option<SpendingPolicy>,
+    migrated_from: Option<String>,
+    deferred_address_types: Vec<AddressType>,
+    metadata_sync_key: Option<[u8; 32]>,
 }
 
+#[cfg(feature = "std")]
 impl<P: WalletPersister> NgAccountBuilder<P> {
     pub fn name(mut self, name: String) -> Self {
         self.name = Some(name);
@@ -1245,6 +1558,53 @@ impl<P: WalletPersister> NgAccountBuilder<P> {
         self
     }
 
+    pub fn vault(mut self, vault: VaultDetails) -> Self {
+        self.vault = Some(vault);
+        self
+    }
+
+    pub fn gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = Some(gap_limit);
+        self
+    }
+
+    pub fn dust_relay_fee(mut self, dust_relay_fee: FeeRateSatPerKvb) -> Self {
+        self.dust_relay_fee = Some(dust_relay_fee);
+        self
+    }
+
+    pub fn birthday(mut self, birthday: Birthday) -> Self {
+        self.birthday = Some(birthday);
+        self
+    }
+
+    pub fn seed_id(mut self, seed_id: Option<String>) -> Self {
+        self.seed_id = seed_id;
+        self
+    }
+
+    pub fn spending_policy(mut self, spending_policy: SpendingPolicy) -> Self {
+        self.spending_policy = Some(spending_policy);
+        self
+    }
+
+    pub fn migrated_from(mut self, account_id: Option<String>) -> Self {
+        self.migrated_from = account_id;
+        self
+    }
+
+    pub fn deferred_address_types(mut self, address_types: Vec<AddressType>) -> Self {
+        self.deferred_address_types = address_types;
+        self
+    }
+
+    /// Key of the [`crate::metadata_sync`] payloads, shared with the paired
+    /// device.
+    pub fn metadata_sync_key(mut self, key: [u8; 32]) -> Self {
+        self.metadata_sync_key = Some(key);
+        self
+    }
+
     pub fn build_in_memory(self) -> anyhow::Result<NgAccount<P>> {
         let meta_storage = Arc::new(crate::store::InMemoryMetaStorage::default());
         self.build(meta_storage)
@@ -1255,6 +1615,17 @@ impl<P: WalletPersister> NgAccountBuilder<P> {
         self.build(meta_storage)
     }
 
+    /// Build the account with its metadata in `namespace`, see
+    /// [`RedbMetaStorage::from_file_in_namespace`].
+    pub fn build_in_namespace(
+        self,
+        db_path: Option<String>,
+        namespace: &str,
+    ) -> anyhow::Result<NgAccount<P>> {
+        let meta_storage = Arc::new(RedbMetaStorage::from_file_in_namespace(db_path, namespace)?);
+        self.build(meta_storage)
+    }
+
     pub fn build_from_db(self, db: redb::Database) -> anyhow::Result<NgAccount<P>> {
         let meta_storage = Arc::new(RedbMetaStorage::from_db(db));
         self.build(meta_storage)
@@ -1281,14 +1652,15 @@ impl<P: WalletPersister> NgAccountBuilder<P> {
             device_serial: self.device_serial,
             date_added: self.date_added,
             network: self.network.ok_or(anyhow::anyhow!("Network is required"))?,
-            preferred_address_type: match self.multisig {
-                Some(ref m) => m.format.flatten(),
-                None => self
+            preferred_address_type: match (&self.multisig, &self.vault) {
+                (Some(m), _) => m.format.flatten(),
+                (None, Some(_)) => AddressType::P2wsh,
+                (None, None) => self
                     .preferred_address_type
                     .ok_or(anyhow::anyhow!("Preferred address type is required"))?,
             },
             descriptors: ng_descriptors,
-            index: if self.multisig.is_none() {
+            index: if self.multisig.is_none() && self.vault.is_none() {
                 self.index.ok_or(anyhow::anyhow!("Index is required"))?
             } else {
                 0
@@ -1297,11 +1669,26 @@ impl<P: WalletPersister> NgAccountBuilder<P> {
             date_synced: self.date_synced,
             seed_has_passphrase: self.seed_has_passphrase.unwrap_or(false),
             multisig: self.multisig,
+            vault: self.vault,
             archived: self.archived.unwrap_or_default(),
             last_remote_sequence: 0,
+            last_metadata_sync: 0,
+            gap_limit: self.gap_limit,
+            dust_relay_fee: self.dust_relay_fee,
+            birthday: self.birthday,
+            seed_id: self.seed_id,
+            spending_policy: self.spending_policy,
+            migrated_from: self.migrated_from,
+            migrated_to: None,
+            deferred_address_types: self.deferred_address_types,
+            retired_descriptors: vec![],
         };
 
-        NgAccount::new_from_descriptors(ng_account_config, storage, descriptors)
+        let account = NgAccount::new_from_descriptors(ng_account_config, storage, descriptors)?;
+        if let Some(key) = self.metadata_sync_key {
+            account.set_metadata_sync_key(&key)?;
+        }
+        Ok(account)
     }
 }
 
@@ -1847,4 +2234,148 @@ Format: P2WSH
         assert_eq!(multisig_a, multisig_b);
         assert_eq!(multisig_a.sha256(), multisig_b.sha256())
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn birthday_prunes_older_transactions() {
+        use bdk_core::BlockId;
+        use bdk_wallet::bitcoin::absolute::LockTime;
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::transaction::Version;
+        use bdk_wallet::bitcoin::{BlockHash, Transaction};
+
+        let transaction = |lock_time: u32| {
+            std::sync::Arc::new(Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::from_consensus(lock_time),
+                input: vec![],
+                output: vec![],
+            })
+        };
+        let anchor = |height: u32| ConfirmationBlockTime {
+            block_id: BlockId {
+                height,
+                hash: BlockHash::all_zeros(),
+            },
+            confirmation_time: 1_700_000_000 + height as u64 * 600,
+        };
+        let (old, recent, unconfirmed) = (transaction(1), transaction(2), transaction(3));
+
+        let mut tx_update = TxUpdate::default();
+        tx_update.txs = vec![old.clone(), recent.clone(), unconfirmed.clone()];
+        tx_update.anchors.insert((anchor(100), old.compute_txid()));
+        tx_update
+            .anchors
+            .insert((anchor(200), recent.compute_txid()));
+
+        let mut by_height = tx_update.clone();
+        Birthday::Height(150).prune(&mut by_height);
+        assert_eq!(by_height.txs, vec![recent.clone(), unconfirmed.clone()]);
+        assert_eq!(by_height.anchors.len(), 1);
+
+        let mut by_time = tx_update;
+        Birthday::Timestamp(anchor(150).confirmation_time).prune(&mut by_time);
+        assert_eq!(by_time.txs, vec![recent, unconfirmed]);
+    }
+
+    #[test]
+    fn multisig_signer_is_rotated() {
+        let descriptor = "wsh(sortedmulti(2,[71C8BD85/48h/0h/0h/2h]xpub6ESpvmZa75rCQWKik2KoCZrjTi6xhSubZKJ25rbtgZRk2g9tZTJqubhaGD3dJeqruw9KMCaanoEfJ1PVtBXiwTuuqLVwk9ucqkRv1sKWiEC/<0;1>/*,[AB88DE89/48h/0h/0h/2h]xpub6EPJuK8Ejz82nKc7PsRgcYqdcQH9G1ZikCTasr9i79CbXxMMiPfxEyA14S6HPTHufmcQR7x8t5L3BP9tRfm9EBRBPic2xV892j9z4ePESae/<0;1>/*,[A9F9964A/48h/0h/0h/2h]xpub6FQY5W8WygMVYY2nTP188jFHNdZfH2t9qtcS8SPpFatUGiciqUsGZpNvEa1oABEyeAsrUL2XSnvuRUdrhf5LcMXcjhrUFBcneBYYZzky3Mc/<0;1>/*))";
+        let (multisig, _) = MultiSigDetails::from_descriptor(descriptor).unwrap();
+        let old = Fingerprint::from_str("71C8BD85").unwrap();
+        let new_signer = MultiSigSigner::new_from_strings(
+            "m/48'/0'/0'/2'",
+            "0C327BCC",
+            "xpub6EEcDVwFP73WMNcGzHC43g3THk3LuVj1QNsWEkVwqM6URNDEurAjgfaNnUoDYGinSXiBjih4JFdmWF8P895ddhSJZfJkuoJU2bJhCr78VUq",
+        )
+        .unwrap();
+
+        let rotated = multisig.rotate_signer(old, new_signer.clone()).unwrap();
+        assert_eq!(rotated.policy_threshold, 2);
+        assert_eq!(rotated.policy_total_keys, 3);
+        assert_eq!(rotated.format, AddressType::P2wsh);
+        let fingerprints: Vec<_> = rotated
+            .get_signers()
+            .iter()
+            .map(|signer| signer.get_fingerprint())
+            .collect();
+        assert!(!fingerprints.contains(&old));
+        assert!(fingerprints.contains(&new_signer.get_fingerprint()));
+        // still sorted like sortedmulti()
+        let mut sorted = rotated.get_signers().clone();
+        sorted.sort();
+        assert_eq!(&sorted, rotated.get_signers());
+
+        // the old signer is gone
+        assert!(rotated.rotate_signer(old, new_signer.clone()).is_err());
+        // the new signer is already a cosigner
+        let existing = multisig
+            .get_signers()
+            .iter()
+            .find(|signer| signer.get_fingerprint() != old)
+            .unwrap()
+            .clone();
+        assert!(multisig.rotate_signer(old, existing).is_err());
+        // a testnet key in a mainnet multisig
+        let testnet_signer = MultiSigSigner::new_from_strings(
+            "m/48'/1'/0'/2'",
+            "662A42E4",
+            "tpubDFGqX4Ge633XixPNo4uF5h6sPkv32bwJrknDmmPGMq8Tn3Pu9QgWfk5hUiDe7gvv2eaFeaHXgjiZwKvnP3AhusoaWBK3qTv8cznyHxxGoSF",
+        )
+        .unwrap();
+        assert!(multisig.rotate_signer(old, testnet_signer).is_err());
+    }
+
+    #[test]
+    fn multisig_bip48_script_type_mismatch() {
+        // the second key is from the P2SH-P2WSH branch
+        let config = "Name: Mixed branches
+Policy: 2 of 2
+Format: P2WSH
+
+Derivation: m/48'/1'/0'/2'
+AB88DE89: tpubDFUc8ddWCzA8kC195Zn6UitBcBGXbPbtjktU2dk2Deprnf6sR15GAyHLQKUjAPa3gqD74g7Eea3NSqkb9FfYRZzEm2MTbCtTDZAKSHezJwb
+
+Derivation: m/48'/1'/0'/1'
+662A42E4: tpubDFGqX4Ge633XixPNo4uF5h6sPkv32bwJrknDmmPGMq8Tn3Pu9QgWfk5hUiDe7gvv2eaFeaHXgjiZwKvnP3AhusoaWBK3qTv8cznyHxxGoSF";
+        let error = MultiSigDetails::from_config(config).unwrap_err();
+        assert!(error.to_string().contains("662a42e4"));
+
+        let (multisig, name, warnings) = MultiSigDetails::from_config_lenient(config).unwrap();
+        assert_eq!(name, "Mixed branches");
+        assert_eq!(
+            warnings,
+            vec![MultiSigWarning::ScriptTypeMismatch {
+                fingerprint: Fingerprint::from_str("662A42E4").unwrap(),
+                derivation: "m/48'/1'/0'/1'".to_string(),
+                format: AddressType::P2wsh,
+            }]
+        );
+
+        // the same keys in a P2SH-P2WSH quorum, the first one is off now
+        let (_, warnings) = MultiSigDetails::new_lenient(
+            2,
+            2,
+            AddressType::P2ShWsh,
+            None,
+            multisig.get_signers().clone(),
+        )
+        .unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        // derivations outside of BIP-48 aren't checked
+        let signers = multisig
+            .get_signers()
+            .iter()
+            .map(|signer| {
+                MultiSigSigner::new_from_strings(
+                    "m/45'",
+                    &signer.get_fingerprint().to_string(),
+                    signer.get_pubkey_str(),
+                )
+                .unwrap()
+            })
+            .collect();
+        assert!(MultiSigDetails::new(2, 2, AddressType::P2wsh, None, signers).is_ok());
+    }
 }
@@ -1,44 +1,134 @@
+use crate::archive::BalanceSnapshot;
 use crate::config::{AddressType, NgAccountConfig};
-use crate::store::MetaStorage;
+use crate::error::MutexExt;
+use crate::fiat::FiatValue;
+use crate::spk_index::SpkDerivation;
+use crate::store::{MetaBatch, MetaStorage};
+use crate::sync_plan::SyncCheckpoint;
 use anyhow::{Context, Result};
 use bdk_wallet::KeychainKind;
-use redb::{Builder, Database, ReadableTable, TableDefinition};
+use redb::{Builder, Database, ReadableTable, TableDefinition, TableHandle, WriteTransaction};
+use std::fmt;
+use std::sync::{Arc, Mutex};
 
 const FEE_TABLE: TableDefinition<&str, u64> = TableDefinition::new("fees");
+// JSON encoded FiatValue per txid
+const FIAT_TABLE: TableDefinition<&str, &str> = TableDefinition::new("fiat_values");
 
 const NOTE_TABLE: TableDefinition<&str, &str> = TableDefinition::new("notes");
 const TAG_TABLE: TableDefinition<&str, &str> = TableDefinition::new("tags");
 const TAGS_LIST: TableDefinition<&str, &str> = TableDefinition::new("tags_list");
 
 const DO_NOT_SPEND_TABLE: TableDefinition<&str, bool> = TableDefinition::new("do_not_spend");
+const DO_NOT_SPEND_TAGS_TABLE: TableDefinition<&str, bool> =
+    TableDefinition::new("do_not_spend_tags");
 
 const ACCOUNT_CONFIG: TableDefinition<&str, &str> = TableDefinition::new("config");
+// JSON encoded BalanceSnapshot of an archived account
+const BALANCE_SNAPSHOT_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("balance_snapshot");
 
 const LAST_VERIFIED_ADDRESS_TABLE: TableDefinition<&str, u32> =
     TableDefinition::new("last_verified_address");
+// JSON encoded VerificationSearch in progress, by address type
+const VERIFICATION_SEARCH_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("verification_search");
+// JSON encoded SpkDerivation, by spk_index::spk_key
+const SPK_INDEX_TABLE: TableDefinition<&str, &str> = TableDefinition::new("spk_index");
+// JSON encoded SyncCheckpoint, by address type
+const SYNC_CHECKPOINT_TABLE: TableDefinition<&str, &str> = TableDefinition::new("sync_checkpoints");
+
+const BIP85_CHILDREN_TABLE: TableDefinition<&str, &str> = TableDefinition::new("bip85_children");
+
+const WHITELIST_TABLE: TableDefinition<&str, &str> = TableDefinition::new("whitelist");
+
+const SYNC_KEY_TABLE: TableDefinition<&str, &str> = TableDefinition::new("sync_key");
+// JSON encoded AccountSnapshot of the last persist
+const ACCOUNT_SNAPSHOT_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("account_snapshot");
+// JSON encoded PersistJournal of a persist in progress
+const PERSIST_JOURNAL_TABLE: TableDefinition<&str, &str> = TableDefinition::new("persist_journal");
+
+const PAYMENT_TEMPLATES_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("payment_templates");
+
+const TAG_INFO_TABLE: TableDefinition<&str, &str> = TableDefinition::new("tag_infos");
+
+const APPLIED_UPDATES_TABLE: TableDefinition<&str, &str> = TableDefinition::new("applied_updates");
+
+// Namespace of the database of RedbMetaStorage::from_file
+const DEFAULT_NAMESPACE: &str = "account";
+
+type Write = Box<dyn FnOnce(&WriteTransaction) -> Result<()> + Send>;
 
-#[derive(Debug)]
 pub struct RedbMetaStorage {
-    db: Database,
+    db: Arc<Database>,
+    // writes queued when this is the storage of a batch, see
+    // MetaStorage::begin_batch
+    batch: Option<Arc<Mutex<Vec<Write>>>>,
+}
+
+impl fmt::Debug for RedbMetaStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbMetaStorage")
+            .field("db", &self.db)
+            .finish_non_exhaustive()
+    }
 }
 
 impl RedbMetaStorage {
     pub fn from_file(path: Option<String>) -> anyhow::Result<Self> {
+        Self::open_file(path, DEFAULT_NAMESPACE)
+    }
+
+    /// Storage of its own in the `path` directory, in a database separate
+    /// from [`Self::from_file`] and every other namespace. `namespace` is
+    /// made of ASCII letters, digits and dashes.
+    pub fn from_file_in_namespace(path: Option<String>, namespace: &str) -> anyhow::Result<Self> {
+        let valid = namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid || namespace.is_empty() || namespace == DEFAULT_NAMESPACE {
+            anyhow::bail!("Invalid metadata namespace {namespace:?}");
+        }
+        Self::open_file(path, namespace)
+    }
+
+    fn open_file(path: Option<String>, namespace: &str) -> anyhow::Result<Self> {
         let db = {
             let file_path = path
-                .clone()
-                .map(|p| format!("{p}/account.meta"))
-                .unwrap_or("account.meta".to_string());
+                .map(|p| format!("{p}/{namespace}.meta"))
+                .unwrap_or(format!("{namespace}.meta"));
             Builder::new()
                 .create(file_path)
                 .with_context(|| "Failed to create redb database")?
         };
 
-        Ok(RedbMetaStorage { db })
+        Ok(Self::from_db(db))
     }
 
     pub fn from_db(db: Database) -> Self {
-        Self { db }
+        Self {
+            db: Arc::new(db),
+            batch: None,
+        }
+    }
+
+    // Runs `write` in its own transaction, or queues it in the batch this is
+    // the storage of.
+    fn write(
+        &self,
+        write: impl FnOnce(&WriteTransaction) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        if let Some(batch) = &self.batch {
+            batch.lock_or_err()?.push(Box::new(write));
+            return Ok(());
+        }
+        let write_txn = self.db.begin_write()?;
+        write(&write_txn)?;
+        write_txn
+            .commit()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))
     }
 
     //TODO: fix persist
@@ -50,14 +140,12 @@ impl RedbMetaStorage {
 
 impl MetaStorage for RedbMetaStorage {
     fn set_fee(&self, txid: &str, fee: u64) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let txid = txid.to_string();
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(FEE_TABLE)?;
-            table.insert(txid, fee)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(txid.as_str(), fee)?;
+            Ok(())
+        })
     }
 
     fn get_fee(&self, txid: &str) -> Result<Option<u64>> {
@@ -72,15 +160,35 @@ impl MetaStorage for RedbMetaStorage {
         }
     }
 
+    fn set_fiat_value(&self, txid: &str, value: &FiatValue) -> Result<()> {
+        let txid = txid.to_string();
+        let value = serde_json::to_string(value)?;
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(FIAT_TABLE)?;
+            table.insert(txid.as_str(), value.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_fiat_value(&self, txid: &str) -> Result<Option<FiatValue>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(FIAT_TABLE) {
+            Ok(table) => match table.get(txid) {
+                Ok(Some(value)) => Ok(Some(serde_json::from_str(value.value())?)),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
     fn set_note(&self, key: &str, value: &str) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(NOTE_TABLE)?;
-            table.insert(&key, &value)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(key.as_str(), value.as_str())?;
+            Ok(())
+        })
     }
 
     fn get_note(&self, key: &str) -> Result<Option<String>> {
@@ -114,36 +222,30 @@ impl MetaStorage for RedbMetaStorage {
     }
 
     fn add_tag(&self, tag: &str) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let tag = tag.to_string();
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(TAGS_LIST)?;
-            table.insert(tag.to_string().to_lowercase().as_str(), tag)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(tag.to_lowercase().as_str(), tag.as_str())?;
+            Ok(())
+        })
     }
 
     fn remove_tag(&self, tag: &str) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let tag = tag.to_string();
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(TAGS_LIST)?;
             //keys are stored in lowercase
-            table.remove(tag.to_string().to_lowercase().as_str())?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.remove(tag.to_lowercase().as_str())?;
+            Ok(())
+        })
     }
     fn set_tag(&self, key: &str, value: &str) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(TAG_TABLE)?;
-            table.insert(&key, &value)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(key.as_str(), value.as_str())?;
+            Ok(())
+        })
     }
 
     fn get_tag(&self, key: &str) -> Result<Option<String>> {
@@ -161,14 +263,12 @@ impl MetaStorage for RedbMetaStorage {
     }
 
     fn set_do_not_spend(&self, key: &str, value: bool) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let key = key.to_string();
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(DO_NOT_SPEND_TABLE)?;
-            table.insert(&key, &value)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(key.as_str(), value)?;
+            Ok(())
+        })
     }
     fn get_do_not_spend(&self, key: &str) -> Result<bool> {
         let read_txn = self.db.begin_read()?;
@@ -184,15 +284,37 @@ impl MetaStorage for RedbMetaStorage {
         }
     }
 
+    fn set_tag_do_not_spend(&self, tag: &str, value: bool) -> Result<()> {
+        //keys are stored in lowercase
+        let tag = tag.to_lowercase();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(DO_NOT_SPEND_TAGS_TABLE)?;
+            table.insert(tag.as_str(), value)?;
+            Ok(())
+        })
+    }
+
+    fn get_tag_do_not_spend(&self, tag: &str) -> Result<bool> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(DO_NOT_SPEND_TAGS_TABLE) {
+            Ok(table) => match table.get(tag.to_lowercase().as_str()) {
+                Ok(v) => match v {
+                    None => Ok(false),
+                    Some(value) => Ok(value.value()),
+                },
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(false),
+        }
+    }
+
     fn set_config(&self, deserialized_config: &str) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let config = deserialized_config.to_string();
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(ACCOUNT_CONFIG)?;
-            table.insert("config", deserialized_config)?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert("config", config.as_str())?;
+            Ok(())
+        })
     }
 
     fn get_config(&self) -> Result<Option<NgAccountConfig>> {
@@ -209,23 +331,39 @@ impl MetaStorage for RedbMetaStorage {
         }
     }
 
+    fn set_balance_snapshot(&self, snapshot: &BalanceSnapshot) -> Result<()> {
+        let snapshot = serde_json::to_string(snapshot)?;
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(BALANCE_SNAPSHOT_TABLE)?;
+            table.insert("balance", snapshot.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_balance_snapshot(&self) -> Result<Option<BalanceSnapshot>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(BALANCE_SNAPSHOT_TABLE) {
+            Ok(table) => match table.get("balance") {
+                Ok(Some(value)) => Ok(Some(serde_json::from_str(value.value())?)),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
     fn set_last_verified_address(
         &self,
         address_type: AddressType,
         keychain: KeychainKind,
         index: u32,
     ) -> Result<()> {
-        let write_txn = self.db.begin_write()?;
-        {
+        let key = format!("{},{}", address_type as u8, keychain as u8);
+        self.write(move |write_txn| {
             let mut table = write_txn.open_table(LAST_VERIFIED_ADDRESS_TABLE)?;
-            table.insert(
-                format!("{},{}", address_type as u8, keychain as u8).as_str(),
-                index,
-            )?;
-        }
-        write_txn
-            .commit()
-            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            table.insert(key.as_str(), index)?;
+            Ok(())
+        })
     }
 
     fn get_last_verified_address(
@@ -248,7 +386,411 @@ impl MetaStorage for RedbMetaStorage {
         }
     }
 
+    fn set_verification_search(
+        &self,
+        address_type: AddressType,
+        search: Option<&str>,
+    ) -> Result<()> {
+        let key = (address_type as u8).to_string();
+        let search = search.map(str::to_string);
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(VERIFICATION_SEARCH_TABLE)?;
+            match search {
+                Some(search) => table.insert(key.as_str(), search.as_str())?,
+                None => table.remove(key.as_str())?,
+            };
+            Ok(())
+        })
+    }
+
+    fn get_verification_search(&self, address_type: AddressType) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(VERIFICATION_SEARCH_TABLE) {
+            Ok(table) => match table.get((address_type as u8).to_string().as_str()) {
+                Ok(value) => Ok(value.map(|value| value.value().to_string())),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn reset_verification_state(&self) -> Result<()> {
+        self.write(move |write_txn| {
+            write_txn.delete_table(LAST_VERIFIED_ADDRESS_TABLE)?;
+            write_txn.delete_table(VERIFICATION_SEARCH_TABLE)?;
+            Ok(())
+        })
+    }
+
+    fn set_spk_derivations(&self, derivations: &[(String, SpkDerivation)]) -> Result<()> {
+        let derivations = derivations
+            .iter()
+            .map(|(key, derivation)| Ok((key.clone(), serde_json::to_string(derivation)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(SPK_INDEX_TABLE)?;
+            for (key, derivation) in &derivations {
+                table.insert(key.as_str(), derivation.as_str())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_spk_derivation(&self, key: &str) -> Result<Option<SpkDerivation>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(SPK_INDEX_TABLE) {
+            Ok(table) => match table.get(key) {
+                Ok(Some(value)) => Ok(Some(serde_json::from_str(value.value())?)),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn set_sync_checkpoint(
+        &self,
+        address_type: AddressType,
+        checkpoint: &SyncCheckpoint,
+    ) -> Result<()> {
+        let key = (address_type as u8).to_string();
+        let checkpoint = serde_json::to_string(checkpoint)?;
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(SYNC_CHECKPOINT_TABLE)?;
+            table.insert(key.as_str(), checkpoint.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_sync_checkpoint(&self, address_type: AddressType) -> Result<Option<SyncCheckpoint>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(SYNC_CHECKPOINT_TABLE) {
+            Ok(table) => match table.get((address_type as u8).to_string().as_str()) {
+                Ok(Some(value)) => Ok(Some(serde_json::from_str(value.value())?)),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn remove_sync_checkpoint(&self, address_type: AddressType) -> Result<()> {
+        let key = (address_type as u8).to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(SYNC_CHECKPOINT_TABLE)?;
+            table.remove(key.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn set_bip85_child(&self, path: &str, label: &str) -> Result<()> {
+        let (path, label) = (path.to_string(), label.to_string());
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(BIP85_CHILDREN_TABLE)?;
+            table.insert(path.as_str(), label.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn list_bip85_children(&self) -> Result<Vec<(String, String)>> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(BIP85_CHILDREN_TABLE) {
+            Ok(table) => table,
+            Err(_) => return Ok(vec![]),
+        };
+        let mut children = vec![];
+        for entry in table.iter()? {
+            let (path, label) = entry?;
+            children.push((path.value().to_string(), label.value().to_string()));
+        }
+        Ok(children)
+    }
+
+    fn begin_batch(&self) -> Result<Option<MetaBatch>> {
+        let writes = Arc::new(Mutex::new(vec![]));
+        let storage = Self {
+            db: self.db.clone(),
+            batch: Some(writes.clone()),
+        };
+        let db = self.db.clone();
+        Ok(Some(MetaBatch::new(Arc::new(storage), move || {
+            let writes = std::mem::take(&mut *writes.lock_or_err()?);
+            let write_txn = db.begin_write()?;
+            for write in writes {
+                write(&write_txn)?;
+            }
+            write_txn
+                .commit()
+                .map_err(|e| anyhow::anyhow!(e.to_string()))
+        })))
+    }
+
+    fn set_whitelist(&self, whitelist: &str) -> Result<()> {
+        let whitelist = whitelist.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(WHITELIST_TABLE)?;
+            table.insert("whitelist", whitelist.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_whitelist(&self) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(WHITELIST_TABLE) {
+            Ok(table) => match table.get("whitelist") {
+                Ok(Some(value)) => Ok(Some(value.value().to_string())),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn set_sync_key(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(SYNC_KEY_TABLE)?;
+            table.insert("sync_key", key.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_sync_key(&self) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(SYNC_KEY_TABLE) {
+            Ok(table) => match table.get("sync_key") {
+                Ok(Some(value)) => Ok(Some(value.value().to_string())),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn set_account_snapshot(&self, snapshot: &str) -> Result<()> {
+        let snapshot = snapshot.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(ACCOUNT_SNAPSHOT_TABLE)?;
+            table.insert("snapshot", snapshot.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_account_snapshot(&self) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(ACCOUNT_SNAPSHOT_TABLE) {
+            Ok(table) => match table.get("snapshot") {
+                Ok(Some(value)) => Ok(Some(value.value().to_string())),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn set_persist_journal(&self, journal: Option<&str>) -> Result<()> {
+        let journal = journal.map(str::to_string);
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(PERSIST_JOURNAL_TABLE)?;
+            match journal {
+                Some(journal) => table.insert("journal", journal.as_str())?,
+                None => table.remove("journal")?,
+            };
+            Ok(())
+        })
+    }
+
+    fn get_persist_journal(&self) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(PERSIST_JOURNAL_TABLE) {
+            Ok(table) => match table.get("journal") {
+                Ok(Some(value)) => Ok(Some(value.value().to_string())),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn set_payment_template(&self, name: &str, template: &str) -> Result<()> {
+        let (name, template) = (name.to_string(), template.to_string());
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(PAYMENT_TEMPLATES_TABLE)?;
+            table.insert(name.as_str(), template.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn remove_payment_template(&self, name: &str) -> Result<()> {
+        let name = name.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(PAYMENT_TEMPLATES_TABLE)?;
+            table.remove(name.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn list_payment_templates(&self) -> Result<Vec<String>> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(PAYMENT_TEMPLATES_TABLE) {
+            Ok(table) => table,
+            Err(_) => return Ok(vec![]),
+        };
+        let mut templates = vec![];
+        for entry in table.iter()? {
+            let (_, template) = entry?;
+            templates.push(template.value().to_string());
+        }
+        Ok(templates)
+    }
+
+    fn set_tag_info(&self, tag: &str, info: &str) -> Result<()> {
+        let (tag, info) = (tag.to_string(), info.to_string());
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(TAG_INFO_TABLE)?;
+            table.insert(tag.as_str(), info.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn remove_tag_info(&self, tag: &str) -> Result<()> {
+        let tag = tag.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(TAG_INFO_TABLE)?;
+            table.remove(tag.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn list_tag_infos(&self) -> Result<Vec<String>> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(TAG_INFO_TABLE) {
+            Ok(table) => table,
+            Err(_) => return Ok(vec![]),
+        };
+        let mut infos = vec![];
+        for entry in table.iter()? {
+            let (_, info) = entry?;
+            infos.push(info.value().to_string());
+        }
+        Ok(infos)
+    }
+
+    fn set_applied_update(&self, hash: &str, update: &str) -> Result<()> {
+        let (hash, update) = (hash.to_string(), update.to_string());
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(APPLIED_UPDATES_TABLE)?;
+            table.insert(hash.as_str(), update.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn get_applied_update(&self, hash: &str) -> Result<Option<String>> {
+        let read_txn = self.db.begin_read()?;
+        match read_txn.open_table(APPLIED_UPDATES_TABLE) {
+            Ok(table) => match table.get(hash) {
+                Ok(Some(value)) => Ok(Some(value.value().to_string())),
+                Ok(None) => Ok(None),
+                Err(e) => Err(anyhow::anyhow!(e.to_string())),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn remove_applied_update(&self, hash: &str) -> Result<()> {
+        let hash = hash.to_string();
+        self.write(move |write_txn| {
+            let mut table = write_txn.open_table(APPLIED_UPDATES_TABLE)?;
+            table.remove(hash.as_str())?;
+            Ok(())
+        })
+    }
+
+    fn list_applied_updates(&self) -> Result<Vec<String>> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(APPLIED_UPDATES_TABLE) {
+            Ok(table) => table,
+            Err(_) => return Ok(vec![]),
+        };
+        let mut updates = vec![];
+        for entry in table.iter()? {
+            let (_, update) = entry?;
+            updates.push(update.value().to_string());
+        }
+        Ok(updates)
+    }
+
+    fn wipe(&self) -> Result<Vec<String>> {
+        let write_txn = self.db.begin_write()?;
+        let tables: Vec<_> = write_txn.list_tables()?.collect();
+        let mut wiped = vec![];
+        for table in tables {
+            let name = table.name().to_string();
+            if write_txn.delete_table(table)? {
+                wiped.push(name);
+            }
+        }
+        write_txn
+            .commit()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        Ok(wiped)
+    }
+
     fn persist(&self) -> Result<bool> {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::with_batch;
+    use redb::backends::InMemoryBackend;
+
+    fn storage() -> RedbMetaStorage {
+        let db = Builder::new()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        RedbMetaStorage::from_db(db)
+    }
+
+    #[test]
+    fn batched_writes_are_committed_together() {
+        let storage = storage();
+        with_batch(&storage, |batch| {
+            batch.set_note("tx", "Rent")?;
+            batch.set_tag("tx:0", "Savings")?;
+            // queued, not visible before the commit
+            assert_eq!(storage.get_note("tx")?, Some("".to_string()));
+            assert_eq!(batch.get_note("tx")?, Some("".to_string()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(storage.get_note("tx").unwrap(), Some("Rent".to_string()));
+        assert_eq!(
+            storage.get_tag("tx:0").unwrap(),
+            Some("Savings".to_string())
+        );
+
+        // writes made to the storage itself, as from another thread, aren't
+        // part of the batch and are kept when it fails
+        let failed: Result<()> = with_batch(&storage, |batch| {
+            batch.set_note("tx", "Groceries")?;
+            storage.set_note("other", "Gift")?;
+            anyhow::bail!("import failed")
+        });
+        assert!(failed.is_err());
+        assert_eq!(storage.get_note("tx").unwrap(), Some("Rent".to_string()));
+        assert_eq!(storage.get_note("other").unwrap(), Some("Gift".to_string()));
+
+        // batches don't share their writes
+        let first = storage.begin_batch().unwrap().unwrap();
+        let second = storage.begin_batch().unwrap().unwrap();
+        first.storage().set_note("tx", "Fuel").unwrap();
+        second.storage().set_tag("tx:0", "Travel").unwrap();
+        drop(first);
+        second.commit().unwrap();
+        assert_eq!(storage.get_note("tx").unwrap(), Some("Rent".to_string()));
+        assert_eq!(storage.get_tag("tx:0").unwrap(), Some("Travel".to_string()));
+    }
+}
@@ -0,0 +1,79 @@
+//! Deleting an account and everything it stored.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use bdk_wallet::WalletPersister;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::account::{NgAccount, get_persister_file_name, legacy_persister_file_name};
+use crate::error::{AccountError, RwLockExt};
+
+/// What [`NgAccount::destroy`] deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DestroyReport {
+    /// Metadata tables that were dropped.
+    pub tables: Vec<String>,
+    /// Wallet database files that were removed.
+    pub files: Vec<String>,
+    /// Number of wallets that were closed.
+    pub wallets: usize,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Delete the account: drop every metadata table, close the wallets,
+    /// remove their database files from `account_path` (the directory the
+    /// account was built in, `None` if the wallets are not on disk) and
+    /// zeroize the descriptors held in the config.
+    ///
+    /// `confirm_token` must be the account id, guarding against deleting
+    /// the wrong account. Every clone of the account is unusable afterwards.
+    pub fn destroy(
+        &self,
+        confirm_token: &str,
+        account_path: Option<String>,
+    ) -> Result<DestroyReport> {
+        let mut config = self.config.write_or_err()?;
+        if confirm_token != config.id {
+            return Err(AccountError::ConfirmationMismatch.into());
+        }
+
+        let mut report = DestroyReport {
+            tables: self
+                .meta_storage
+                .wipe()
+                .with_context(|| "Failed to wipe account metadata")?,
+            ..Default::default()
+        };
+
+        // releases the persisters, closing the database connections
+        report.wallets = self.address_types()?.len();
+        self.wallets.write_or_err()?.clear();
+        self.drop_closed_wallets();
+
+        for descriptor in config.descriptors.iter_mut() {
+            if let Some(account_path) = &account_path {
+                let (internal, external) = (&descriptor.internal, descriptor.external.as_deref());
+                // a file not migrated yet keeps its legacy name
+                for file_name in [
+                    get_persister_file_name(internal, external),
+                    legacy_persister_file_name(internal, external),
+                ] {
+                    let file = Path::new(account_path).join(file_name);
+                    if file.exists() {
+                        std::fs::remove_file(&file)
+                            .with_context(|| format!("Failed to remove {}", file.display()))?;
+                        report.files.push(file.display().to_string());
+                    }
+                }
+            }
+            descriptor.internal.zeroize();
+            if let Some(external) = descriptor.external.as_mut() {
+                external.zeroize();
+            }
+        }
+        config.descriptors.clear();
+        Ok(report)
+    }
+}
@@ -0,0 +1,101 @@
+//! Duress accounts.
+//!
+//! A duress account is a decoy to open under coercion instead of the real
+//! account. It comes from the same mnemonic through a derivation domain of
+//! its own, so its keys are unrelated to the wallets of the mnemonic with
+//! any passphrase. Its config has no seed id and its metadata lives in a
+//! namespace named after its own master key, so nothing stored with it
+//! points back to the primary account.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::bip32::{Fingerprint, Xpriv};
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha512};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::keys::bip39::Mnemonic;
+use zeroize::Zeroizing;
+
+use crate::account::NgAccount;
+use crate::config::NgAccountBuilder;
+use crate::error::RwLockExt;
+use crate::passphrase::{descriptors_like, seed_id};
+
+// Key of the HMAC turning the BIP-39 seed into the duress seed.
+const DURESS_DOMAIN: &[u8] = b"ngwallet duress seed";
+
+/// Master key of the duress account of `mnemonic` with `passphrase`.
+pub fn duress_xprv(mnemonic: &str, passphrase: &str, network: Network) -> Result<Xpriv> {
+    let seed = Zeroizing::new(Mnemonic::from_str(mnemonic)?.to_seed(passphrase));
+    let mut engine = HmacEngine::<sha512::Hash>::new(DURESS_DOMAIN);
+    engine.input(seed.as_slice());
+    let duress_seed = Zeroizing::new(Hmac::<sha512::Hash>::from_engine(engine).to_byte_array());
+    Ok(Xpriv::new_master(network, duress_seed.as_slice())?)
+}
+
+/// Metadata namespace of the account of master key `fingerprint`.
+pub fn metadata_namespace(fingerprint: Fingerprint) -> String {
+    format!("meta-{fingerprint}")
+}
+
+pub struct DuressAccount<P: WalletPersister> {
+    pub builder: NgAccountBuilder<P>,
+    /// Namespace to build and open the account in, see
+    /// [`NgAccountBuilder::build_in_namespace`].
+    pub namespace: String,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Builder of the duress account of `mnemonic` with `passphrase`: the
+    /// same network, script types and account index, and private
+    /// descriptors if this account has them. The name and color are left to
+    /// the caller, so the decoy doesn't have to look like this account.
+    /// `persisters` hold the new wallets, in the order of the descriptors
+    /// of this account.
+    ///
+    /// The mnemonic must match the seed id of this account, if it has one.
+    pub fn duress_account(
+        &self,
+        mnemonic: &str,
+        passphrase: &str,
+        id: String,
+        persisters: Vec<Arc<Mutex<P>>>,
+    ) -> Result<DuressAccount<P>> {
+        let config = self.config.read_or_err()?.clone();
+        if let Some(id) = &config.seed_id
+            && *id != seed_id(mnemonic)?
+        {
+            bail!("Mnemonic doesn't match this account");
+        }
+        if config.multisig.is_some() || config.vault.is_some() {
+            bail!("Only single signature accounts have duress accounts");
+        }
+        if config.descriptors.len() != persisters.len() {
+            bail!(
+                "Expected {} persisters, got {}",
+                config.descriptors.len(),
+                persisters.len()
+            );
+        }
+        if id == config.id {
+            bail!("The duress account needs an id of its own");
+        }
+
+        let xprv = duress_xprv(mnemonic, passphrase, config.network)?;
+        let fingerprint = xprv.fingerprint(&Secp256k1::new());
+        let descriptors = descriptors_like(&config, xprv, persisters)?;
+
+        Ok(DuressAccount {
+            builder: NgAccountBuilder::default()
+                .id(id)
+                .network(config.network)
+                .preferred_address_type(config.preferred_address_type)
+                .index(config.index)
+                .descriptors(descriptors),
+            namespace: metadata_namespace(fingerprint),
+        })
+    }
+}
@@ -0,0 +1,64 @@
+//! Dust limits.
+//!
+//! An output is dust when spending it would cost more than a third of its
+//! value at the dust relay fee rate, and nodes don't relay transactions
+//! creating one. The limit depends on the script of the output: at the
+//! default rate it is 546 sats for P2PKH, 294 for P2WPKH and 330 for P2TR.
+
+use bdk_wallet::bitcoin::{Network, Script};
+use serde::{Deserialize, Serialize};
+
+use crate::fee_rate::FeeRateSatPerKvb;
+
+/// Dust relay fee rate of Bitcoin Core, see `-dustrelayfee`.
+pub const DEFAULT_DUST_RELAY_FEE: FeeRateSatPerKvb = FeeRateSatPerKvb(3_000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DustPolicy {
+    pub dust_relay_fee: FeeRateSatPerKvb,
+}
+
+impl DustPolicy {
+    /// Default relay policy of the nodes of `network`. Bitcoin Core uses the
+    /// same dust relay fee on every network.
+    pub fn for_network(_network: Network) -> Self {
+        Self {
+            dust_relay_fee: DEFAULT_DUST_RELAY_FEE,
+        }
+    }
+
+    /// Smallest value of an output paying `script` that isn't dust, in
+    /// sats.
+    pub fn dust_limit(&self, script: &Script) -> u64 {
+        script
+            .minimal_non_dust_custom(self.dust_relay_fee.to_bdk())
+            .to_sat()
+    }
+
+    pub fn is_dust(&self, amount: u64, script: &Script) -> bool {
+        amount < self.dust_limit(script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::hashes::Hash;
+    use bdk_wallet::bitcoin::{PubkeyHash, ScriptBuf, WPubkeyHash};
+
+    #[test]
+    fn dust_limit_follows_the_script_type() {
+        let policy = DustPolicy::for_network(Network::Bitcoin);
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros());
+        let p2wpkh = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+        assert_eq!(policy.dust_limit(&p2pkh), 546);
+        assert_eq!(policy.dust_limit(&p2wpkh), 294);
+        assert!(policy.is_dust(293, &p2wpkh));
+        assert!(!policy.is_dust(294, &p2wpkh));
+
+        let cheaper = DustPolicy {
+            dust_relay_fee: FeeRateSatPerKvb(1_000),
+        };
+        assert_eq!(cheaper.dust_limit(&p2wpkh), 98);
+    }
+}
@@ -0,0 +1,309 @@
+//! Restoring wallets created by Electrum.
+//!
+//! Electrum seeds look like BIP-39 mnemonics but aren't: the words carry no
+//! checksum, the version of the seed is the prefix of an HMAC of the words
+//! and the seed is stretched with the `electrum` salt. Electrum can also
+//! export the extended private key of a wallet, in its SLIP-132 encoding
+//! (`zprv` for segwit wallets).
+
+use crate::bip39::{Descriptors, Error, Key, get_descriptors_from_xprv};
+use crate::config::AddressType;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use bdk_wallet::bitcoin::bip32::Xpriv;
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha512};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{Network, NetworkKind, base58};
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use zeroize::Zeroize;
+
+const PBKDF2_ROUNDS: usize = 2048;
+
+/// Version of an Electrum seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectrumSeedType {
+    /// Legacy P2PKH wallet, version prefix `01`.
+    Standard,
+    /// Native segwit P2WPKH wallet, version prefix `100`.
+    Segwit,
+}
+
+impl ElectrumSeedType {
+    fn address_type(self) -> AddressType {
+        match self {
+            ElectrumSeedType::Standard => AddressType::P2pkh,
+            ElectrumSeedType::Segwit => AddressType::P2wpkh,
+        }
+    }
+}
+
+// Lowercase words separated by single spaces. Electrum also strips accents
+// and CJK spaces, which only matter for the non-English wordlists.
+fn normalize(mnemonic: &str) -> String {
+    mnemonic
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Version of `mnemonic` if it is an Electrum seed. Two-factor seeds are
+/// not supported and return `None`.
+pub fn electrum_seed_type(mnemonic: &str) -> Option<ElectrumSeedType> {
+    let mut engine = HmacEngine::<sha512::Hash>::new(b"Seed version");
+    engine.input(normalize(mnemonic).as_bytes());
+    let version = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+    match (version[0], version[1] >> 4) {
+        (0x01, _) => Some(ElectrumSeedType::Standard),
+        (0x10, 0x0) => Some(ElectrumSeedType::Segwit),
+        _ => None,
+    }
+}
+
+/// The seed of an Electrum mnemonic: PBKDF2-HMAC-SHA512 of the words, salted
+/// with `electrum` and the passphrase.
+pub fn electrum_seed(mnemonic: &str, passphrase: &str) -> Key {
+    let mut password = normalize(mnemonic);
+    let mut salt = String::from("electrum");
+    salt.push_str(&normalize(passphrase));
+
+    // a single block of output, the size of SHA-512
+    let mut engine = HmacEngine::<sha512::Hash>::new(password.as_bytes());
+    engine.input(salt.as_bytes());
+    engine.input(&1u32.to_be_bytes());
+    let mut block = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+    let mut key = block;
+    for _ in 1..PBKDF2_ROUNDS {
+        let mut engine = HmacEngine::<sha512::Hash>::new(password.as_bytes());
+        engine.input(&block);
+        block = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+        for (k, b) in key.iter_mut().zip(block.iter()) {
+            *k ^= b;
+        }
+    }
+    block.zeroize();
+    password.zeroize();
+    salt.zeroize();
+    Key(key)
+}
+
+/// Descriptors of the wallet Electrum creates from `mnemonic`: `pkh` at the
+/// master key for standard seeds, `wpkh` under `m/0'` for segwit seeds.
+pub fn get_electrum_descriptors(
+    mnemonic: &str,
+    passphrase: &str,
+    network: Network,
+) -> Result<Descriptors, Error> {
+    let seed_type = electrum_seed_type(mnemonic).ok_or(Error::NotElectrumSeed)?;
+    let seed = electrum_seed(mnemonic, passphrase);
+    let xprv = Xpriv::new_master(network, &seed.0)?;
+    let (bip, receive, change) = match seed_type {
+        ElectrumSeedType::Standard => (
+            "electrum",
+            format!("pkh({xprv}/0/*)"),
+            format!("pkh({xprv}/1/*)"),
+        ),
+        ElectrumSeedType::Segwit => (
+            "electrum_segwit",
+            format!("wpkh({xprv}/0'/0/*)"),
+            format!("wpkh({xprv}/0'/1/*)"),
+        ),
+    };
+    descriptors(bip, seed_type.address_type(), &receive, &change)
+}
+
+fn descriptors(
+    bip: &str,
+    export_addr_hint: AddressType,
+    receive: &str,
+    change: &str,
+) -> Result<Descriptors, Error> {
+    let secp = Secp256k1::new();
+    let descriptor = ExtendedDescriptor::parse_descriptor(&secp, receive)
+        .map_err(|_| Error::InvalidDescriptor)?;
+    let change_descriptor = ExtendedDescriptor::parse_descriptor(&secp, change)
+        .map_err(|_| Error::InvalidDescriptor)?;
+    Ok(Descriptors {
+        bip: String::from(bip),
+        export_addr_hint,
+        descriptor_type: descriptor.0.desc_type(),
+        descriptor,
+        change_descriptor,
+    })
+}
+
+// SLIP-132 private key versions and the script type they stand for, with
+// the standard version of their network.
+const XPRV_VERSIONS: &[([u8; 4], AddressType, [u8; 4])] = &[
+    (
+        [0x04, 0x88, 0xAD, 0xE4],
+        AddressType::P2pkh,
+        [0x04, 0x88, 0xAD, 0xE4],
+    ),
+    (
+        [0x04, 0x9D, 0x78, 0x78],
+        AddressType::P2ShWpkh,
+        [0x04, 0x88, 0xAD, 0xE4],
+    ),
+    (
+        [0x04, 0xB2, 0x43, 0x0C],
+        AddressType::P2wpkh,
+        [0x04, 0x88, 0xAD, 0xE4],
+    ),
+    (
+        [0x04, 0x35, 0x83, 0x94],
+        AddressType::P2pkh,
+        [0x04, 0x35, 0x83, 0x94],
+    ),
+    (
+        [0x04, 0x4A, 0x4E, 0x28],
+        AddressType::P2ShWpkh,
+        [0x04, 0x35, 0x83, 0x94],
+    ),
+    (
+        [0x04, 0x5F, 0x18, 0xBC],
+        AddressType::P2wpkh,
+        [0x04, 0x35, 0x83, 0x94],
+    ),
+];
+
+/// Descriptors of a raw extended private key (`xprv`, `yprv`, `zprv` or
+/// their testnet forms).
+///
+/// A master key gets the same descriptors as a BIP-39 seed, see
+/// [`get_descriptors_from_xprv`]. An account key, like the ones Electrum
+/// exports, gets the receive and change chains of the script type of its
+/// encoding.
+pub fn get_xprv_descriptors(xprv: &str, account_index: u32) -> Result<Vec<Descriptors>, Error> {
+    let mut bytes = base58::decode_check(xprv.trim()).map_err(|_| Error::InvalidXprv)?;
+    if bytes.len() < 4 {
+        return Err(Error::InvalidXprv);
+    }
+    let (address_type, version) = XPRV_VERSIONS
+        .iter()
+        .find(|(slip132, _, _)| bytes[..4] == slip132[..])
+        .map(|(_, address_type, version)| (*address_type, *version))
+        .ok_or(Error::InvalidXprv)?;
+    bytes[..4].copy_from_slice(&version);
+    let xprv = Xpriv::decode(&bytes)?;
+    bytes.fill(0);
+
+    if xprv.depth == 0 {
+        let network = match xprv.network {
+            NetworkKind::Main => Network::Bitcoin,
+            NetworkKind::Test => Network::Testnet,
+        };
+        return get_descriptors_from_xprv(xprv, network, account_index)
+            .map_err(|_| Error::InvalidDescriptor);
+    }
+
+    let (receive, change) = match address_type {
+        AddressType::P2ShWpkh => (
+            format!("sh(wpkh({xprv}/0/*))"),
+            format!("sh(wpkh({xprv}/1/*))"),
+        ),
+        AddressType::P2wpkh => (format!("wpkh({xprv}/0/*)"), format!("wpkh({xprv}/1/*)")),
+        _ => (format!("pkh({xprv}/0/*)"), format!("pkh({xprv}/1/*)")),
+    };
+    Ok(vec![descriptors("xprv", address_type, &receive, &change)?])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_wallet::bitcoin::bip32::DerivationPath;
+    use bdk_wallet::bitcoin::hex::DisplayHex;
+    use core::str::FromStr;
+
+    const SEGWIT: &str =
+        "bitter grass shiver impose acquire brush forget axis eager alone wine silver";
+    const STANDARD: &str =
+        "cycle rocket west magnet parrot shuffle foot correct salt library feed song";
+    const BIP39: &str =
+        "axis minimum please frozen option smooth alone identify term fatigue crisp entry";
+
+    #[test]
+    fn electrum_seed_versions() {
+        assert_eq!(electrum_seed_type(SEGWIT), Some(ElectrumSeedType::Segwit));
+        assert_eq!(
+            electrum_seed_type(&format!("  {}\n", SEGWIT.to_uppercase())),
+            Some(ElectrumSeedType::Segwit)
+        );
+        assert_eq!(
+            electrum_seed_type(STANDARD),
+            Some(ElectrumSeedType::Standard)
+        );
+        assert_eq!(electrum_seed_type(BIP39), None);
+        assert!(matches!(
+            get_electrum_descriptors(BIP39, "", Network::Bitcoin),
+            Err(Error::NotElectrumSeed)
+        ));
+    }
+
+    #[test]
+    fn electrum_seed_stretching() {
+        assert_eq!(
+            electrum_seed(SEGWIT, "").0.to_lower_hex_string(),
+            "8ff3b1fa35d0bace7e80255253ee1ada21586eccd341bb90ae8ff5a7214e7d62b2be30df64807fd8716e604c66da392834853644bffdab8a7d9ac029f52a9a8c"
+        );
+        assert_eq!(
+            electrum_seed(SEGWIT, "secret").0.to_lower_hex_string(),
+            "6967cffc7795c59811a9ccb94b63d67d3663513aab4a5f6771912e36834afa7820927687b291df9957d4143de0626a8cbaa16ad9e29d8a7c860e258d80620bdd"
+        );
+        assert_eq!(
+            electrum_seed(STANDARD, "").0.to_lower_hex_string(),
+            "00302d7db162de47e6cd5074221aee6bbcb6be93982af90c04d0e7710dd26013aeb7848850a56a546e7955b360e561139d62805f2d5d3c940880b0dc91b60b29"
+        );
+    }
+
+    #[test]
+    fn electrum_descriptors() {
+        let segwit = get_electrum_descriptors(SEGWIT, "", Network::Bitcoin).unwrap();
+        assert_eq!(segwit.export_addr_hint, AddressType::P2wpkh);
+        assert!(segwit.descriptor_xpub().starts_with("wpkh(["));
+        assert!(segwit.descriptor_xpub().contains("/0']xpub"));
+        assert!(segwit.change_descriptor_xpub().contains("/1/*)"));
+
+        let standard = get_electrum_descriptors(STANDARD, "", Network::Bitcoin).unwrap();
+        assert_eq!(standard.export_addr_hint, AddressType::P2pkh);
+        assert!(standard.descriptor_xpub().starts_with("pkh(xpub"));
+    }
+
+    #[test]
+    fn xprv_descriptors() {
+        let seed = electrum_seed(SEGWIT, "").0;
+        let master = Xpriv::new_master(Network::Bitcoin, &seed).unwrap();
+        let descriptors = get_xprv_descriptors(&master.to_string(), 0).unwrap();
+        assert_eq!(descriptors.len(), 6);
+
+        // the account key of the segwit wallet, as a zprv
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/0'").unwrap();
+        let account = master.derive_priv(&secp, &path).unwrap();
+        let mut bytes = account.encode();
+        bytes[..4].copy_from_slice(&[0x04, 0xB2, 0x43, 0x0C]);
+        let zprv = base58::encode_check(&bytes);
+        assert!(zprv.starts_with("zprv"));
+
+        let descriptors = get_xprv_descriptors(&zprv, 0).unwrap();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].export_addr_hint, AddressType::P2wpkh);
+        let electrum = get_electrum_descriptors(SEGWIT, "", Network::Bitcoin).unwrap();
+        // same addresses, the exported key only lacks the origin
+        let address = |descriptor: &Descriptors| {
+            descriptor
+                .descriptor
+                .0
+                .at_derivation_index(0)
+                .unwrap()
+                .address(Network::Bitcoin)
+                .unwrap()
+        };
+        assert_eq!(address(&descriptors[0]), address(&electrum));
+
+        assert!(matches!(
+            get_xprv_descriptors("xpub661MyMwAqRbcF", 0),
+            Err(Error::InvalidXprv)
+        ));
+    }
+}
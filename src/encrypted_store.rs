@@ -0,0 +1,546 @@
+//! Metadata encryption at rest.
+//!
+//! [`EncryptedMetaStorage`] wraps another [`MetaStorage`] and encrypts the
+//! user written values with a key provided by the device (keychain,
+//! keystore, secure element) before they reach it:
+//!
+//! - notes, output tags, the labels of BIP-85 children, the whitelist, the
+//!   metadata sync key, the account snapshot and the persist journal,
+//! - tag names, with tag policies keyed by a keyed hash of the name,
+//! - payment templates, keyed by a keyed hash of their name,
+//! - the name, device serial and descriptors of the account config. The
+//!   other config fields stay readable so the wrapped storage can parse it.
+//!
+//! Lookup keys (txids, output ids), fees, fiat values, address indexes, sync
+//! checkpoints and the hashes of applied remote updates are stored as is.
+//!
+//! Each value is authenticated together with the name of its table and its
+//! lookup key, so values swapped between rows or tables of the wrapped
+//! storage fail to decrypt. Values only ever read by listing their table
+//! (tag names, payment templates, tag policies) are bound to the table.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use bdk_wallet::KeychainKind;
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use bdk_wallet::bitcoin::hex::{DisplayHex, FromHex};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use zeroize::Zeroize;
+
+use crate::archive::BalanceSnapshot;
+use crate::config::{AddressType, NgAccountConfig};
+use crate::error::StorageError;
+use crate::fiat::FiatValue;
+use crate::spk_index::SpkDerivation;
+use crate::store::{MetaBatch, MetaStorage};
+use crate::sync_plan::SyncCheckpoint;
+
+const NONCE_LEN: usize = 24;
+
+pub struct EncryptedMetaStorage {
+    inner: Arc<dyn MetaStorage>,
+    cipher: XChaCha20Poly1305,
+    // keys the hashes of tag and template names used as lookup keys
+    tag_key: [u8; 32],
+}
+
+impl EncryptedMetaStorage {
+    /// Encrypt the metadata written to `inner` with `key`. The same key is
+    /// needed to read it back.
+    pub fn new(inner: Arc<dyn MetaStorage>, key: &[u8; 32]) -> Self {
+        let mut encryption_key = derive_key(key, b"ngwallet metadata encryption");
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&encryption_key));
+        encryption_key.zeroize();
+        Self {
+            inner,
+            cipher,
+            tag_key: derive_key(key, b"ngwallet tag ids"),
+        }
+    }
+
+    // Hex encoded nonce and ciphertext of the value stored under `key` in
+    // `table`, empty values are kept empty since they mean "no value" to
+    // the account.
+    fn encrypt(&self, table: &str, key: &str, plaintext: &str) -> Result<String> {
+        if plaintext.is_empty() {
+            return Ok(String::new());
+        }
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: &aad(table, key),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| StorageError::Encryption)?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(ciphertext);
+        Ok(encrypted.to_lower_hex_string())
+    }
+
+    fn decrypt(&self, table: &str, key: &str, encrypted: &str) -> Result<String> {
+        if encrypted.is_empty() {
+            return Ok(String::new());
+        }
+        let encrypted = Vec::<u8>::from_hex(encrypted).map_err(|_| StorageError::NotEncrypted)?;
+        if encrypted.len() < NONCE_LEN {
+            return Err(StorageError::NotEncrypted.into());
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad(table, key),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| StorageError::Decryption)?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    fn decrypt_option(
+        &self,
+        table: &str,
+        key: &str,
+        encrypted: Option<String>,
+    ) -> Result<Option<String>> {
+        encrypted
+            .map(|value| self.decrypt(table, key, &value))
+            .transpose()
+    }
+
+    // Tags are case insensitive, so the policy of a tag is keyed by the hash
+    // of its lowercase name.
+    fn tag_id(&self, tag: &str) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.tag_key);
+        engine.input(tag.to_lowercase().as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine)
+            .to_byte_array()
+            .to_lower_hex_string()
+    }
+
+    fn template_id(&self, name: &str) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.tag_key);
+        engine.input(b"payment template: ");
+        engine.input(name.as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine)
+            .to_byte_array()
+            .to_lower_hex_string()
+    }
+
+    // Script hashes would tell which addresses are ours, so they are keyed
+    // with the storage key too.
+    fn spk_id(&self, key: &str) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.tag_key);
+        engine.input(b"spk: ");
+        engine.input(key.as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine)
+            .to_byte_array()
+            .to_lower_hex_string()
+    }
+
+    // Encrypted names of the stored tags matching `tag` case insensitively.
+    fn stored_tags(&self, tag: &str) -> Result<Vec<String>> {
+        let mut stored = vec![];
+        for encrypted in self.inner.list_tags()? {
+            if self.decrypt("tags_list", "", &encrypted)?.to_lowercase() == tag.to_lowercase() {
+                stored.push(encrypted);
+            }
+        }
+        Ok(stored)
+    }
+
+    // Maps the private fields of `config`, with the name of each field.
+    fn map_config(
+        &self,
+        mut config: NgAccountConfig,
+        map: impl Fn(&str, &str) -> Result<String>,
+    ) -> Result<NgAccountConfig> {
+        config.name = map("name", &config.name)?;
+        config.device_serial = config
+            .device_serial
+            .as_deref()
+            .map(|serial| map("device_serial", serial))
+            .transpose()?;
+        for (index, descriptor) in config.descriptors.iter_mut().enumerate() {
+            descriptor.internal = map(
+                &format!("descriptors/{index}/internal"),
+                &descriptor.internal,
+            )?;
+            descriptor.external = descriptor
+                .external
+                .as_deref()
+                .map(|external| map(&format!("descriptors/{index}/external"), external))
+                .transpose()?;
+        }
+        Ok(config)
+    }
+}
+
+// Associated data of a value: its table, then its lookup key. Table names
+// hold no NUL byte.
+fn aad(table: &str, key: &str) -> Vec<u8> {
+    [table.as_bytes(), &[0], key.as_bytes()].concat()
+}
+
+fn derive_key(key: &[u8; 32], purpose: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    engine.input(purpose);
+    Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+impl Drop for EncryptedMetaStorage {
+    fn drop(&mut self) {
+        self.tag_key.zeroize();
+    }
+}
+
+impl std::fmt::Debug for EncryptedMetaStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedMetaStorage")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MetaStorage for EncryptedMetaStorage {
+    fn set_fee(&self, txid: &str, fee: u64) -> Result<()> {
+        self.inner.set_fee(txid, fee)
+    }
+
+    fn get_fee(&self, txid: &str) -> Result<Option<u64>> {
+        self.inner.get_fee(txid)
+    }
+
+    fn set_fiat_value(&self, txid: &str, value: &FiatValue) -> Result<()> {
+        self.inner.set_fiat_value(txid, value)
+    }
+
+    fn get_fiat_value(&self, txid: &str) -> Result<Option<FiatValue>> {
+        self.inner.get_fiat_value(txid)
+    }
+
+    fn set_note(&self, key: &str, value: &str) -> Result<()> {
+        self.inner
+            .set_note(key, &self.encrypt("notes", key, value)?)
+    }
+
+    fn get_note(&self, key: &str) -> Result<Option<String>> {
+        self.decrypt_option("notes", key, self.inner.get_note(key)?)
+    }
+
+    fn list_tags(&self) -> Result<Vec<String>> {
+        self.inner
+            .list_tags()?
+            .iter()
+            .map(|tag| self.decrypt("tags_list", "", tag))
+            .collect()
+    }
+
+    fn add_tag(&self, tag: &str) -> Result<()> {
+        // replaces a tag differing only in case, like the other storages
+        self.remove_tag(tag)?;
+        self.inner.add_tag(&self.encrypt("tags_list", "", tag)?)
+    }
+
+    fn remove_tag(&self, tag: &str) -> Result<()> {
+        for stored in self.stored_tags(tag)? {
+            self.inner.remove_tag(&stored)?;
+        }
+        Ok(())
+    }
+
+    fn set_tag(&self, key: &str, value: &str) -> Result<()> {
+        self.inner.set_tag(key, &self.encrypt("tags", key, value)?)
+    }
+
+    fn get_tag(&self, key: &str) -> Result<Option<String>> {
+        self.decrypt_option("tags", key, self.inner.get_tag(key)?)
+    }
+
+    fn set_do_not_spend(&self, key: &str, value: bool) -> Result<()> {
+        self.inner.set_do_not_spend(key, value)
+    }
+
+    fn get_do_not_spend(&self, key: &str) -> Result<bool> {
+        self.inner.get_do_not_spend(key)
+    }
+
+    fn set_tag_do_not_spend(&self, tag: &str, value: bool) -> Result<()> {
+        self.inner.set_tag_do_not_spend(&self.tag_id(tag), value)
+    }
+
+    fn get_tag_do_not_spend(&self, tag: &str) -> Result<bool> {
+        self.inner.get_tag_do_not_spend(&self.tag_id(tag))
+    }
+
+    fn set_config(&self, deserialized_config: &str) -> Result<()> {
+        let config: NgAccountConfig = serde_json::from_str(deserialized_config)?;
+        let config =
+            self.map_config(config, |field, value| self.encrypt("config", field, value))?;
+        self.inner.set_config(&config.serialize())
+    }
+
+    fn get_config(&self) -> Result<Option<NgAccountConfig>> {
+        self.inner
+            .get_config()?
+            .map(|config| {
+                self.map_config(config, |field, value| self.decrypt("config", field, value))
+            })
+            .transpose()
+    }
+
+    fn set_balance_snapshot(&self, snapshot: &BalanceSnapshot) -> Result<()> {
+        self.inner.set_balance_snapshot(snapshot)
+    }
+
+    fn get_balance_snapshot(&self) -> Result<Option<BalanceSnapshot>> {
+        self.inner.get_balance_snapshot()
+    }
+
+    fn set_last_verified_address(
+        &self,
+        address_type: AddressType,
+        keychain: KeychainKind,
+        index: u32,
+    ) -> Result<()> {
+        self.inner
+            .set_last_verified_address(address_type, keychain, index)
+    }
+
+    fn get_last_verified_address(
+        &self,
+        address_type: AddressType,
+        keychain: KeychainKind,
+    ) -> Result<u32> {
+        self.inner.get_last_verified_address(address_type, keychain)
+    }
+
+    fn set_verification_search(
+        &self,
+        address_type: AddressType,
+        search: Option<&str>,
+    ) -> Result<()> {
+        let key = format!("{address_type:?}");
+        let search = search
+            .map(|search| self.encrypt("verification_search", &key, search))
+            .transpose()?;
+        self.inner
+            .set_verification_search(address_type, search.as_deref())
+    }
+
+    fn get_verification_search(&self, address_type: AddressType) -> Result<Option<String>> {
+        self.decrypt_option(
+            "verification_search",
+            &format!("{address_type:?}"),
+            self.inner.get_verification_search(address_type)?,
+        )
+    }
+
+    fn reset_verification_state(&self) -> Result<()> {
+        self.inner.reset_verification_state()
+    }
+
+    fn set_spk_derivations(&self, derivations: &[(String, SpkDerivation)]) -> Result<()> {
+        let derivations: Vec<_> = derivations
+            .iter()
+            .map(|(key, derivation)| (self.spk_id(key), *derivation))
+            .collect();
+        self.inner.set_spk_derivations(&derivations)
+    }
+
+    fn get_spk_derivation(&self, key: &str) -> Result<Option<SpkDerivation>> {
+        self.inner.get_spk_derivation(&self.spk_id(key))
+    }
+
+    fn set_sync_checkpoint(
+        &self,
+        address_type: AddressType,
+        checkpoint: &SyncCheckpoint,
+    ) -> Result<()> {
+        self.inner.set_sync_checkpoint(address_type, checkpoint)
+    }
+
+    fn get_sync_checkpoint(&self, address_type: AddressType) -> Result<Option<SyncCheckpoint>> {
+        self.inner.get_sync_checkpoint(address_type)
+    }
+
+    fn remove_sync_checkpoint(&self, address_type: AddressType) -> Result<()> {
+        self.inner.remove_sync_checkpoint(address_type)
+    }
+
+    fn set_bip85_child(&self, path: &str, label: &str) -> Result<()> {
+        self.inner
+            .set_bip85_child(path, &self.encrypt("bip85_children", path, label)?)
+    }
+
+    fn list_bip85_children(&self) -> Result<Vec<(String, String)>> {
+        self.inner
+            .list_bip85_children()?
+            .into_iter()
+            .map(|(path, label)| {
+                let label = self.decrypt("bip85_children", &path, &label)?;
+                Ok((path, label))
+            })
+            .collect()
+    }
+
+    fn set_whitelist(&self, whitelist: &str) -> Result<()> {
+        self.inner
+            .set_whitelist(&self.encrypt("whitelist", "", whitelist)?)
+    }
+
+    fn get_whitelist(&self) -> Result<Option<String>> {
+        self.decrypt_option("whitelist", "", self.inner.get_whitelist()?)
+    }
+
+    fn set_sync_key(&self, key: &str) -> Result<()> {
+        self.inner.set_sync_key(&self.encrypt("sync_key", "", key)?)
+    }
+
+    fn get_sync_key(&self) -> Result<Option<String>> {
+        self.decrypt_option("sync_key", "", self.inner.get_sync_key()?)
+    }
+
+    fn set_account_snapshot(&self, snapshot: &str) -> Result<()> {
+        self.inner
+            .set_account_snapshot(&self.encrypt("account_snapshot", "", snapshot)?)
+    }
+
+    fn get_account_snapshot(&self) -> Result<Option<String>> {
+        self.decrypt_option("account_snapshot", "", self.inner.get_account_snapshot()?)
+    }
+
+    fn set_persist_journal(&self, journal: Option<&str>) -> Result<()> {
+        let journal = journal
+            .map(|journal| self.encrypt("persist_journal", "", journal))
+            .transpose()?;
+        self.inner.set_persist_journal(journal.as_deref())
+    }
+
+    fn get_persist_journal(&self) -> Result<Option<String>> {
+        self.decrypt_option("persist_journal", "", self.inner.get_persist_journal()?)
+    }
+
+    fn set_payment_template(&self, name: &str, template: &str) -> Result<()> {
+        self.inner.set_payment_template(
+            &self.template_id(name),
+            &self.encrypt("payment_templates", "", template)?,
+        )
+    }
+
+    fn remove_payment_template(&self, name: &str) -> Result<()> {
+        self.inner.remove_payment_template(&self.template_id(name))
+    }
+
+    fn list_payment_templates(&self) -> Result<Vec<String>> {
+        self.inner
+            .list_payment_templates()?
+            .iter()
+            .map(|template| self.decrypt("payment_templates", "", template))
+            .collect()
+    }
+
+    fn set_tag_info(&self, tag: &str, info: &str) -> Result<()> {
+        self.inner
+            .set_tag_info(&self.tag_id(tag), &self.encrypt("tag_infos", "", info)?)
+    }
+
+    fn remove_tag_info(&self, tag: &str) -> Result<()> {
+        self.inner.remove_tag_info(&self.tag_id(tag))
+    }
+
+    fn list_tag_infos(&self) -> Result<Vec<String>> {
+        self.inner
+            .list_tag_infos()?
+            .iter()
+            .map(|info| self.decrypt("tag_infos", "", info))
+            .collect()
+    }
+
+    fn set_applied_update(&self, hash: &str, update: &str) -> Result<()> {
+        self.inner.set_applied_update(hash, update)
+    }
+
+    fn get_applied_update(&self, hash: &str) -> Result<Option<String>> {
+        self.inner.get_applied_update(hash)
+    }
+
+    fn remove_applied_update(&self, hash: &str) -> Result<()> {
+        self.inner.remove_applied_update(hash)
+    }
+
+    fn list_applied_updates(&self) -> Result<Vec<String>> {
+        self.inner.list_applied_updates()
+    }
+
+    fn begin_batch(&self) -> Result<Option<MetaBatch>> {
+        Ok(self.inner.begin_batch()?.map(|batch| {
+            batch.wrap(|inner| {
+                Arc::new(Self {
+                    inner,
+                    cipher: self.cipher.clone(),
+                    tag_key: self.tag_key,
+                })
+            })
+        }))
+    }
+
+    fn wipe(&self) -> Result<Vec<String>> {
+        self.inner.wipe()
+    }
+
+    fn persist(&self) -> Result<bool> {
+        self.inner.persist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryMetaStorage;
+
+    #[test]
+    fn values_are_encrypted_at_rest() {
+        let inner = Arc::new(InMemoryMetaStorage::default());
+        let storage = EncryptedMetaStorage::new(inner.clone(), &[7; 32]);
+
+        storage.set_note("tx", "Rent for March").unwrap();
+        assert_eq!(
+            storage.get_note("tx").unwrap().as_deref(),
+            Some("Rent for March")
+        );
+        let at_rest = inner.get_note("tx").unwrap().unwrap();
+        assert!(!at_rest.contains("Rent"));
+
+        storage.set_tag("tx:0", "Savings").unwrap();
+        storage.add_tag("Savings").unwrap();
+        storage.set_tag_do_not_spend("Savings", true).unwrap();
+        assert_eq!(storage.get_tag("tx:0").unwrap().as_deref(), Some("Savings"));
+        assert_eq!(storage.list_tags().unwrap(), vec!["Savings".to_string()]);
+        assert!(storage.get_tag_do_not_spend("savings").unwrap());
+        assert!(!inner.get_tag_do_not_spend("savings").unwrap());
+
+        // tags are case insensitive
+        storage.add_tag("SAVINGS").unwrap();
+        assert_eq!(storage.list_tags().unwrap(), vec!["SAVINGS".to_string()]);
+        storage.remove_tag("savings").unwrap();
+        assert!(storage.list_tags().unwrap().is_empty());
+
+        // empty values still mean "no value"
+        storage.set_tag("tx:0", "").unwrap();
+        assert_eq!(storage.get_tag("tx:0").unwrap().as_deref(), Some(""));
+
+        let wrong_key = EncryptedMetaStorage::new(inner.clone(), &[8; 32]);
+        assert!(wrong_key.get_note("tx").is_err());
+
+        // a value moved to another row or table doesn't decrypt
+        inner.set_note("other tx", &at_rest).unwrap();
+        assert!(storage.get_note("other tx").is_err());
+        inner.set_tag("tx", &at_rest).unwrap();
+        assert!(storage.get_tag("tx").is_err());
+    }
+}
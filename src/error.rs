@@ -0,0 +1,415 @@
+//! Crate wide errors.
+//!
+//! The account APIs return [`anyhow::Error`], wrapping one of the typed
+//! errors below where the failure is something the app can explain to the
+//! user. [`error_code`] finds it in the chain and returns its stable code,
+//! which the app maps to a localized message:
+//!
+//! | Range | Error |
+//! |-------|-------|
+//! | 100   | [`NgError`] |
+//! | 1000  | [`AccountError`] |
+//! | 2000  | [`SyncError`] |
+//! | 3000  | [`StorageError`] |
+//! | 4000  | [`ComposeError`] |
+//! | 5000  | [`PolicyError`] |
+//! | 6000  | [`PolicyViolation`] |
+//! | 7000  | [`HeaderError`] |
+//!
+//! Codes are never reused or renumbered, new variants get new codes.
+
+use std::any::type_name;
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::chain::local_chain::CannotConnectError;
+use thiserror::Error;
+
+use crate::config::AddressType;
+pub use crate::send::TransactionComposeError as ComposeError;
+
+#[derive(Debug, Error)]
+pub enum NgError {
+    /// A thread panicked while holding the lock, the data behind it may be
+    /// inconsistent.
+    #[error("Lock on {0} is poisoned")]
+    LockPoisoned(&'static str),
+    /// A wallet of a lazily opened account failed to open.
+    #[error("Failed to open the {0:?} wallet: {1}")]
+    WalletNotOpened(AddressType, String),
+}
+
+impl NgError {
+    pub fn code(&self) -> u32 {
+        match self {
+            NgError::LockPoisoned(_) => 100,
+            NgError::WalletNotOpened(..) => 101,
+        }
+    }
+}
+
+/// Errors about the account itself: its wallets, config and state.
+#[derive(Debug, Error)]
+pub enum AccountError {
+    #[error("No wallet found for address type {0:?}")]
+    WalletNotFound(AddressType),
+    #[error("Account config not found")]
+    ConfigNotFound,
+    #[error("Descriptor already exists")]
+    DescriptorExists,
+    #[error("Address type already exists")]
+    AddressTypeExists,
+    #[error("Could not parse address")]
+    InvalidAddress,
+    #[error("Address is invalid for current network: {0}")]
+    WrongNetwork(Network),
+    #[error("Unknown address type")]
+    UnknownAddressType,
+    #[error("Not a multisig account")]
+    NotMultisig,
+    #[error("Account is already archived")]
+    AlreadyArchived,
+    #[error("Account is not archived")]
+    NotArchived,
+    #[error("Confirmation token does not match the account id")]
+    ConfirmationMismatch,
+    #[error("Invalid transaction id: {0}")]
+    InvalidTxId(String),
+    #[error("Invalid output id: {0}")]
+    InvalidOutputId(String),
+    /// The account is in read-only mode, see [`crate::lock`].
+    #[error("Account is locked")]
+    AccountLocked,
+}
+
+impl AccountError {
+    pub fn code(&self) -> u32 {
+        match self {
+            AccountError::WalletNotFound(_) => 1000,
+            AccountError::ConfigNotFound => 1001,
+            AccountError::DescriptorExists => 1002,
+            AccountError::AddressTypeExists => 1003,
+            AccountError::InvalidAddress => 1004,
+            AccountError::WrongNetwork(_) => 1005,
+            AccountError::UnknownAddressType => 1006,
+            AccountError::NotMultisig => 1007,
+            AccountError::AlreadyArchived => 1008,
+            AccountError::NotArchived => 1009,
+            AccountError::ConfirmationMismatch => 1010,
+            AccountError::InvalidTxId(_) => 1011,
+            AccountError::InvalidOutputId(_) => 1012,
+            AccountError::AccountLocked => 1013,
+        }
+    }
+}
+
+/// Errors applying chain updates, [`crate::account::RemoteUpdate`]s and
+/// metadata syncs.
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// The update does not connect to the local chain, the wallet needs a
+    /// full scan.
+    #[error(transparent)]
+    CannotConnect(#[from] CannotConnectError),
+    #[error("RemoteUpdate account_id mismatch: expected {expected}, got {got}")]
+    AccountMismatch { expected: String, got: String },
+    #[error("RemoteUpdate network mismatch: expected {expected:?}, got {got:?}")]
+    NetworkMismatch { expected: Network, got: Network },
+    #[error("RemoteUpdate descriptor hash mismatch: update was not produced for this account")]
+    DescriptorMismatch,
+    #[error(
+        "RemoteUpdate sequence {sequence} is not newer than last accepted sequence {last}; possible replay attack"
+    )]
+    Replay { sequence: u64, last: u64 },
+    /// The metadata of the update changes a field that can only be changed
+    /// locally.
+    #[error("RemoteUpdate metadata {0}")]
+    MetadataRejected(&'static str),
+    /// A [`crate::metadata_sync::SignedMetadataSync`] not signed with the
+    /// sync key of this account.
+    #[error("Metadata sync signature does not match this account")]
+    BadSignature,
+    #[error(
+        "Metadata sync sequence {sequence} is not newer than last applied sequence {last}; possible replay attack"
+    )]
+    StaleMetadataSync { sequence: u64, last: u64 },
+    /// The account has no metadata sync key, it wasn't paired.
+    #[error("Account has no metadata sync key; pair it first")]
+    NoSyncKey,
+}
+
+impl SyncError {
+    pub fn code(&self) -> u32 {
+        match self {
+            SyncError::CannotConnect(_) => 2000,
+            SyncError::AccountMismatch { .. } => 2001,
+            SyncError::NetworkMismatch { .. } => 2002,
+            SyncError::DescriptorMismatch => 2003,
+            SyncError::Replay { .. } => 2004,
+            SyncError::MetadataRejected(_) => 2005,
+            SyncError::BadSignature => 2006,
+            SyncError::StaleMetadataSync { .. } => 2007,
+            SyncError::NoSyncKey => 2008,
+        }
+    }
+}
+
+/// Errors of the metadata storage.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Metadata is not encrypted")]
+    NotEncrypted,
+    #[error("Failed to encrypt metadata")]
+    Encryption,
+    #[error("Failed to decrypt metadata, wrong key?")]
+    Decryption,
+}
+
+impl StorageError {
+    pub fn code(&self) -> u32 {
+        match self {
+            StorageError::Database(_) => 3000,
+            StorageError::Serialization(_) => 3001,
+            StorageError::NotEncrypted => 3002,
+            StorageError::Encryption => 3003,
+            StorageError::Decryption => 3004,
+        }
+    }
+}
+
+macro_rules! impl_database_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for StorageError {
+                fn from(error: $error) -> Self {
+                    StorageError::Database(error.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_database_error!(
+    redb::Error,
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
+
+/// Errors of the spending policies in [`crate::policy`].
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("The account has no whitelist")]
+    NoWhitelist,
+    #[error("Whitelist signature does not match its authority")]
+    InvalidWhitelistSignature,
+    #[error("Whitelist is signed by another authority")]
+    WhitelistAuthorityMismatch,
+    #[error("Whitelist version {version} is not newer than the current version {current}")]
+    StaleWhitelist { version: u64, current: u64 },
+    /// The PSBT pays outputs, by index, that aren't whitelisted.
+    #[error("Outputs {0:?} pay destinations that are not whitelisted")]
+    NotWhitelisted(Vec<usize>),
+}
+
+impl PolicyError {
+    pub fn code(&self) -> u32 {
+        match self {
+            PolicyError::NoWhitelist => 5000,
+            PolicyError::InvalidWhitelistSignature => 5001,
+            PolicyError::WhitelistAuthorityMismatch => 5002,
+            PolicyError::StaleWhitelist { .. } => 5003,
+            PolicyError::NotWhitelisted(_) => 5004,
+        }
+    }
+}
+
+/// A transaction exceeds the [`crate::policy::SpendingPolicy`] of the
+/// account. Amounts are in sats sent out of the account, fees excluded.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PolicyViolation {
+    #[error("Sending {amount} sats exceeds the limit of {limit} sats per transaction")]
+    MaxPerTransaction { amount: u64, limit: u64 },
+    #[error(
+        "Sending {amount} sats after {spent} sats in the last 24 hours exceeds the daily limit of {limit} sats"
+    )]
+    MaxPerDay { amount: u64, spent: u64, limit: u64 },
+    /// Only signs after another signer did.
+    #[error("Sending {amount} sats requires a co-signer above {threshold} sats")]
+    CosignRequired { amount: u64, threshold: u64 },
+}
+
+impl PolicyViolation {
+    pub fn code(&self) -> u32 {
+        match self {
+            PolicyViolation::MaxPerTransaction { .. } => 6000,
+            PolicyViolation::MaxPerDay { .. } => 6001,
+            PolicyViolation::CosignRequired { .. } => 6002,
+        }
+    }
+}
+
+impl ComposeError {
+    pub fn code(&self) -> u32 {
+        match self {
+            ComposeError::CreateTxError(_) => 4000,
+            ComposeError::WalletError(_) => 4001,
+            ComposeError::Error(_) => 4002,
+            ComposeError::LockedUtxoSelected(_) => 4003,
+            ComposeError::PolicyViolation(_) => 4004,
+            ComposeError::DustAmount { .. } => 4005,
+            ComposeError::AccountLocked => 4006,
+        }
+    }
+}
+
+/// Block headers rejected by the [`crate::headers::HeaderStore`], at
+/// `height`.
+#[derive(Debug, Error)]
+pub enum HeaderError {
+    #[error("Header at height {height} does not connect to the chain below it")]
+    NotConnected { height: u32 },
+    #[error("Header at height {height} does not meet its proof of work target")]
+    InvalidPow { height: u32 },
+    #[error("Header at height {height} has an invalid proof of work target")]
+    BadTarget { height: u32 },
+    #[error("First header is not the genesis block of the network")]
+    WrongGenesis,
+    #[error("Headers forking at height {height} have less work than the stored chain")]
+    LessWork { height: u32 },
+    /// The first headers stored start at neither the genesis block nor a
+    /// checkpoint.
+    #[error("Header at height {height} is not a checkpoint of the network")]
+    NotCheckpoint { height: u32 },
+}
+
+impl HeaderError {
+    pub fn code(&self) -> u32 {
+        match self {
+            HeaderError::NotConnected { .. } => 7000,
+            HeaderError::InvalidPow { .. } => 7001,
+            HeaderError::BadTarget { .. } => 7002,
+            HeaderError::WrongGenesis => 7003,
+            HeaderError::LessWork { .. } => 7004,
+            HeaderError::NotCheckpoint { .. } => 7005,
+        }
+    }
+}
+
+/// Stable code of the first typed error in the chain of `error`, `None` if
+/// it has none.
+pub fn error_code(error: &anyhow::Error) -> Option<u32> {
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<NgError>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<AccountError>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<SyncError>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<StorageError>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<PolicyError>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<PolicyViolation>() {
+            Some(e.code())
+        } else if let Some(e) = cause.downcast_ref::<HeaderError>() {
+            Some(e.code())
+        } else {
+            cause.downcast_ref::<ComposeError>().map(ComposeError::code)
+        }
+    })
+}
+
+/// Locking without panicking on poisoned mutexes.
+///
+/// Fallible code returns [`NgError::LockPoisoned`], accessors that can't
+/// fail take over the guard of the poisoned lock instead.
+pub(crate) trait MutexExt<T> {
+    fn lock_or_err(&self) -> Result<MutexGuard<'_, T>, NgError>;
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> MutexExt<T> for Mutex<T> {
+    fn lock_or_err(&self) -> Result<MutexGuard<'_, T>, NgError> {
+        self.lock()
+            .map_err(|_| NgError::LockPoisoned(type_name::<T>()))
+    }
+
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// [`MutexExt`] for read-write locks.
+pub(crate) trait RwLockExt<T> {
+    fn read_or_err(&self) -> Result<RwLockReadGuard<'_, T>, NgError>;
+    fn write_or_err(&self) -> Result<RwLockWriteGuard<'_, T>, NgError>;
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_or_err(&self) -> Result<RwLockReadGuard<'_, T>, NgError> {
+        self.read()
+            .map_err(|_| NgError::LockPoisoned(type_name::<T>()))
+    }
+
+    fn write_or_err(&self) -> Result<RwLockWriteGuard<'_, T>, NgError> {
+        self.write()
+            .map_err(|_| NgError::LockPoisoned(type_name::<T>()))
+    }
+
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn poisoned_locks_return_errors() {
+        let lock = Arc::new(Mutex::new(1));
+        let poisoner = lock.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+
+        assert!(matches!(lock.lock_or_err(), Err(NgError::LockPoisoned(_))));
+        assert_eq!(*lock.lock_or_recover(), 1);
+    }
+
+    #[test]
+    fn error_codes_survive_context() {
+        use anyhow::Context;
+
+        let error = Err::<(), _>(AccountError::WalletNotFound(AddressType::P2tr))
+            .with_context(|| "Failed to sync")
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(1000));
+
+        let error = anyhow::Error::from(SyncError::Replay {
+            sequence: 1,
+            last: 2,
+        });
+        assert_eq!(error_code(&error), Some(2004));
+        assert!(error.to_string().contains("not newer"));
+
+        assert_eq!(error_code(&anyhow::anyhow!("untyped")), None);
+    }
+}
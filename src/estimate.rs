@@ -0,0 +1,228 @@
+//! Transaction size estimates.
+//!
+//! [`NgAccount::estimate_tx_size`] predicts the weight of the transaction
+//! [`NgAccount::compose_psbt`] would build, from the satisfaction weights of
+//! the descriptors, without building or signing a PSBT. It is cheap enough
+//! to update the fee on every tick of a fee slider.
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::bitcoin::{
+    Address, Amount, FeeRate, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Weight, absolute,
+    transaction,
+};
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::account::NgAccount;
+use crate::config::AddressType;
+use crate::error::{MutexExt, RwLockExt};
+use crate::fee_rate::FeeRateSatPerKvb;
+use crate::send::TransactionParams;
+use crate::tags::restrict_to_tag;
+use crate::transaction::Output;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputWeight {
+    /// The spent output, `txid:vout`.
+    pub output_id: String,
+    pub address_type: AddressType,
+    /// Weight of the signed input.
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeEstimate {
+    pub vbytes: u64,
+    pub weight: u64,
+    pub per_input_breakdown: Vec<InputWeight>,
+    /// Whether the transaction has a change output.
+    pub has_change: bool,
+}
+
+impl SizeEstimate {
+    /// Fee of the transaction at `fee_rate`, in sats.
+    pub fn fee(&self, fee_rate: FeeRateSatPerKvb) -> u64 {
+        fee_for(Weight::from_wu(self.weight), fee_rate.to_bdk())
+    }
+}
+
+/// An input to be spent, with the weight of its signatures.
+#[derive(Debug, Clone)]
+pub(crate) struct WeighedInput {
+    pub output: Output,
+    pub address_type: AddressType,
+    pub satisfaction_weight: Weight,
+    pub segwit: bool,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Size of the transaction [`Self::compose_psbt`] would build for
+    /// `params`, without the fee rate of `params` mattering beyond the
+    /// number of inputs it takes.
+    ///
+    /// Inputs are picked from the selected outputs, or all the spendable
+    /// ones, largest first. Coin selection may pick others, so the estimate
+    /// can be off by a few inputs on large wallets.
+    pub fn estimate_tx_size(&self, params: &TransactionParams) -> Result<SizeEstimate> {
+        let recipient = Address::from_str(&params.address)
+            .map_err(|_| anyhow!("Invalid address format"))?
+            .require_network(self.config.read_or_err()?.network)
+            .map_err(|_| anyhow!("Address network mismatch"))?
+            .script_pubkey();
+        let change = self.change_script_for_estimate(params.change_address.as_deref())?;
+
+        let mut do_not_spend = vec![];
+        let mut spendables = vec![];
+        Self::filter_spendable_and_do_not_spendables(
+            params.selected_outputs.clone(),
+            restrict_to_tag(self.utxos()?, params.spend_from_tag.as_deref()),
+            &mut do_not_spend,
+            &mut spendables,
+        )
+        .map_err(|locked| anyhow!("Locked outputs selected: {}", locked.join(", ")))?;
+        let mut inputs = spendables
+            .into_iter()
+            .map(|output| self.weigh_input(output))
+            .collect::<Result<Vec<_>>>()?;
+        inputs.sort_by(|a, b| b.output.amount.cmp(&a.output.amount));
+
+        let available: u64 = inputs.iter().map(|input| input.output.amount).sum();
+        if params.amount > available {
+            bail!("Insufficient funds: {available} sats available");
+        }
+        let fee_rate = params.fee_rate.to_bdk();
+        let sweep = params.amount == available;
+
+        let mut picked = vec![];
+        let mut total = 0;
+        for input in inputs {
+            total += input.output.amount;
+            picked.push(input);
+            if sweep {
+                continue;
+            }
+            let with_change = tx_weight(&picked, &[&recipient, &change]);
+            let fee = fee_for(with_change, fee_rate);
+            if total >= params.amount + fee {
+                let change_value = total - params.amount - fee;
+                let has_change = change_value >= change.minimal_non_dust().to_sat();
+                let weight = match has_change {
+                    true => with_change,
+                    false => tx_weight(&picked, &[&recipient]),
+                };
+                return Ok(estimate(&picked, weight, has_change));
+            }
+        }
+        if !sweep {
+            bail!("Insufficient funds to pay the fee");
+        }
+        let weight = tx_weight(&picked, &[&recipient]);
+        Ok(estimate(&picked, weight, false))
+    }
+
+    /// The spendable output with the weight of signing it, from the
+    /// descriptor of the wallet holding it.
+    pub(crate) fn weigh_input(&self, output: Output) -> Result<WeighedInput> {
+        let outpoint = output.get_outpoint();
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let Some(utxo) = bdk_wallet.get_utxo(outpoint) else {
+                continue;
+            };
+            let descriptor = bdk_wallet.public_descriptor(utxo.keychain);
+            let satisfaction_weight = descriptor
+                .max_weight_to_satisfy()
+                .map_err(|e| anyhow!("Cannot weigh input {}: {e}", output.get_id()))?;
+            return Ok(WeighedInput {
+                address_type: wallet.address_type,
+                satisfaction_weight,
+                segwit: descriptor.desc_type().segwit_version().is_some(),
+                output,
+            });
+        }
+        bail!("Output {} is not in the account", output.get_id())
+    }
+
+    // Script of the change output: the next change address of the
+    // coordinator wallet, or the address of another account.
+    fn change_script_for_estimate(&self, change_address: Option<&str>) -> Result<ScriptBuf> {
+        let wallet = self.get_coordinator_wallet();
+        let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+        match change_address {
+            Some(address) => Ok(Address::from_str(address)
+                .map_err(|_| anyhow!("Invalid change address format"))?
+                .require_network(bdk_wallet.network())
+                .map_err(|_| anyhow!("Change address network mismatch"))?
+                .script_pubkey()),
+            None => {
+                let keychain = match bdk_wallet.keychains().count() {
+                    1 => KeychainKind::External,
+                    _ => KeychainKind::Internal,
+                };
+                let index = bdk_wallet.next_derivation_index(keychain);
+                Ok(bdk_wallet.peek_address(keychain, index).script_pubkey())
+            }
+        }
+    }
+}
+
+/// Weight of the signed transaction spending `inputs` to `outputs`.
+pub(crate) fn tx_weight(inputs: &[WeighedInput], outputs: &[&ScriptBuf]) -> Weight {
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|_| TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|script_pubkey| TxOut {
+                value: Amount::ZERO,
+                script_pubkey: (*script_pubkey).clone(),
+            })
+            .collect(),
+    };
+    let satisfactions = inputs
+        .iter()
+        .map(|input| input.satisfaction_weight)
+        .fold(Weight::ZERO, |total, weight| total + weight);
+    // the segwit marker and flag, and the witness item count of each input
+    let witness = match inputs.iter().any(|input| input.segwit) {
+        true => Weight::from_wu(2 + inputs.len() as u64),
+        false => Weight::ZERO,
+    };
+    tx.weight() + satisfactions + witness
+}
+
+/// Weight of a signed input spending `input`, witness item count included.
+pub(crate) fn input_weight(input: &WeighedInput) -> Weight {
+    TxIn::default().segwit_weight() + input.satisfaction_weight
+}
+
+pub(crate) fn fee_for(weight: Weight, fee_rate: FeeRate) -> u64 {
+    fee_rate
+        .fee_wu(weight)
+        .unwrap_or(Amount::MAX_MONEY)
+        .to_sat()
+}
+
+fn estimate(inputs: &[WeighedInput], weight: Weight, has_change: bool) -> SizeEstimate {
+    SizeEstimate {
+        vbytes: weight.to_vbytes_ceil(),
+        weight: weight.to_wu(),
+        per_input_breakdown: inputs
+            .iter()
+            .map(|input| InputWeight {
+                output_id: input.output.get_id(),
+                address_type: input.address_type,
+                weight: input_weight(input).to_wu(),
+            })
+            .collect(),
+        has_change,
+    }
+}
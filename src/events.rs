@@ -0,0 +1,103 @@
+//! Change notifications for accounts.
+//!
+//! Subscribers get an [`AccountEvent`] for every change applied to an
+//! account, so apps don't have to diff whole transaction lists after each
+//! sync. See [`NgAccount::subscribe`](crate::account::NgAccount::subscribe).
+
+use std::fmt::{self, Debug};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+
+use bdk_wallet::Balance;
+
+use crate::config::AddressType;
+use crate::error::MutexExt;
+use crate::ngwallet::ReorgEvent;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountEvent {
+    /// A transaction the account didn't know about was added.
+    NewTransaction { tx_id: String },
+    /// A known transaction was included in a block.
+    TransactionConfirmed { tx_id: String, block_height: u32 },
+    /// The account balance differs from the one before the last update.
+    BalanceChanged(Balance),
+    /// A receive address was handed out for the first time.
+    AddressRevealed {
+        address: String,
+        address_type: AddressType,
+        index: u32,
+    },
+    /// A note, tag or spending flag changed. `id` is the transaction id,
+    /// output id or tag name the change applies to.
+    MetadataChanged { id: String },
+    /// Transactions spending the same outputs as the unconfirmed transaction
+    /// `tx_id` were seen, so it was or may be replaced. Also sent when the
+    /// user bumps the fee of their own transaction.
+    DoubleSpendDetected {
+        tx_id: String,
+        conflicts_with: Vec<String>,
+    },
+    /// An update replaced blocks of the local chain and un-confirmed
+    /// transactions of the account.
+    Reorg(ReorgEvent),
+}
+
+/// Subscribers of an account, shared between clones of the account.
+#[derive(Clone, Default)]
+pub(crate) struct EventBus(Arc<Mutex<Vec<Sender<AccountEvent>>>>);
+
+impl EventBus {
+    pub(crate) fn subscribe(&self) -> Receiver<AccountEvent> {
+        let (sender, receiver) = channel();
+        self.0.lock_or_recover().push(sender);
+        receiver
+    }
+
+    /// Whether anyone is listening, used to skip computing events nobody
+    /// would receive.
+    pub(crate) fn has_subscribers(&self) -> bool {
+        !self.0.lock_or_recover().is_empty()
+    }
+
+    /// Send `event` to every subscriber, dropping the ones whose receiver
+    /// is gone.
+    pub(crate) fn emit(&self, event: AccountEvent) {
+        self.0
+            .lock_or_recover()
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.lock().map(|senders| senders.len()).unwrap_or(0);
+        write!(f, "<{count} event subscribers>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_subscribers_are_removed() {
+        let bus = EventBus::default();
+        assert!(!bus.has_subscribers());
+
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        drop(second);
+
+        let event = AccountEvent::MetadataChanged {
+            id: "tag".to_string(),
+        };
+        bus.emit(event.clone());
+        assert_eq!(first.try_recv().unwrap(), event);
+        assert_eq!(bus.0.lock().unwrap().len(), 1);
+
+        drop(first);
+        bus.emit(event);
+        assert!(!bus.has_subscribers());
+    }
+}
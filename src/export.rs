@@ -0,0 +1,251 @@
+//! Wallet files for desktop software.
+//!
+//! Lets users open a Passport-paired account as a watch-only wallet in
+//! Electrum or Sparrow, and load a multisig quorum onto Specter, Sparrow
+//! and Keystone.
+
+use anyhow::{Context, Result, anyhow, bail};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::{KeychainKind, WalletPersister};
+use foundation_ur::Encoder;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::account::NgAccount;
+use crate::config::{AddressType, MultiSigDetails};
+use crate::error::{MutexExt, RwLockExt};
+use crate::slip132;
+use crate::utils::join_multipath_descriptor;
+
+/// Electrum's wallet file version the exported files are written in.
+const ELECTRUM_SEED_VERSION: u32 = 17;
+
+/// UR type of the Keystone multisig QR code, the config file as a CBOR
+/// byte string.
+const KEYSTONE_UR_TYPE: &str = "bytes";
+
+#[derive(Serialize)]
+struct ElectrumKeystore {
+    #[serde(rename = "type")]
+    keystore_type: &'static str,
+    xpub: String,
+    xprv: Option<String>,
+    derivation: String,
+    root_fingerprint: String,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct SparrowWallet {
+    label: String,
+    blockheight: u32,
+    descriptor: String,
+}
+
+#[derive(Serialize)]
+struct SpecterDevice {
+    #[serde(rename = "type")]
+    device_type: &'static str,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct SpecterWallet {
+    label: String,
+    blockheight: u32,
+    descriptor: String,
+    devices: Vec<SpecterDevice>,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Electrum wallet file of the account.
+    ///
+    /// Electrum only supports one script type per wallet, the preferred
+    /// address type is exported. Multisig accounts are exported as an
+    /// `MofN` wallet with one keystore per cosigner.
+    pub fn export_electrum_json(&self) -> Result<String> {
+        let config = self.config.read_or_err()?.clone();
+
+        let mut wallet = Map::new();
+        if let Some(multisig) = &config.multisig {
+            wallet.insert(
+                "wallet_type".to_string(),
+                json!(format!(
+                    "{}of{}",
+                    multisig.policy_threshold, multisig.policy_total_keys
+                )),
+            );
+            for (index, keystore) in electrum_multisig_keystores(multisig, &config.name)?
+                .into_iter()
+                .enumerate()
+            {
+                wallet.insert(format!("x{}/", index + 1), serde_json::to_value(keystore)?);
+            }
+        } else {
+            let address_type = config.preferred_address_type;
+            if address_type == AddressType::P2tr {
+                return Err(anyhow!("Electrum does not support taproot wallets"));
+            }
+            let xpub = self
+                .get_export_xpubs()
+                .into_iter()
+                .find(|xpub| xpub.address_type == address_type)
+                .ok_or_else(|| anyhow!("No xpub found for {address_type:?}"))?;
+            wallet.insert("wallet_type".to_string(), json!("standard"));
+            wallet.insert(
+                "keystore".to_string(),
+                serde_json::to_value(ElectrumKeystore {
+                    keystore_type: "bip32",
+                    xpub: xpub.slip132,
+                    xprv: None,
+                    derivation: xpub.derivation_path,
+                    root_fingerprint: xpub.fingerprint.to_lowercase(),
+                    label: config.name.clone(),
+                })?,
+            );
+        }
+        wallet.insert("use_encryption".to_string(), json!(false));
+        wallet.insert("seed_version".to_string(), json!(ELECTRUM_SEED_VERSION));
+
+        serde_json::to_string_pretty(&Value::Object(wallet))
+            .with_context(|| "Error serializing Electrum wallet")
+    }
+
+    /// Sparrow wallet file of the account.
+    ///
+    /// Uses the `label`/`blockheight`/`descriptor` layout Sparrow imports,
+    /// with the public `<0;1>` multipath descriptor of the preferred address
+    /// type. The block
+    /// height of the first confirmed transaction is used as a scan start.
+    pub fn export_sparrow_json(&self) -> Result<String> {
+        let label = self.config.read_or_err()?.name.clone();
+        let blockheight = self
+            .transactions()?
+            .iter()
+            .filter(|tx| tx.is_confirmed)
+            .map(|tx| tx.block_height)
+            .min()
+            .unwrap_or(0);
+        let (external, internal) = {
+            let wallet = self.get_coordinator_wallet();
+            let wallet = wallet.bdk_wallet.lock_or_err()?;
+            (
+                wallet.public_descriptor(KeychainKind::External).to_string(),
+                wallet.public_descriptor(KeychainKind::Internal).to_string(),
+            )
+        };
+        let descriptor = join_multipath_descriptor(&external, &internal).unwrap_or(external);
+
+        serde_json::to_string_pretty(&SparrowWallet {
+            label,
+            blockheight,
+            descriptor,
+        })
+        .with_context(|| "Error serializing Sparrow wallet")
+    }
+}
+
+fn electrum_multisig_keystores(
+    multisig: &MultiSigDetails,
+    name: &str,
+) -> Result<Vec<ElectrumKeystore>> {
+    multisig
+        .get_signers()
+        .iter()
+        .enumerate()
+        .map(|(index, signer)| {
+            let xpub = signer.get_pubkey()?;
+            Ok(ElectrumKeystore {
+                keystore_type: "bip32",
+                xpub: slip132::encode(&xpub, multisig.format),
+                xprv: None,
+                derivation: signer.get_derivation_inner().to_string(),
+                root_fingerprint: signer.get_fingerprint().to_string(),
+                label: format!("{name} #{}", index + 1),
+            })
+        })
+        .collect()
+}
+
+/// Multisig setup files for cosigner devices and coordinators, besides the
+/// Coldcard text file of [`MultiSigDetails::to_config`].
+impl MultiSigDetails {
+    /// Specter wallet file, with the receive descriptor and one device per
+    /// cosigner.
+    pub fn to_specter_json(&self, name: &str) -> Result<String> {
+        let (external, _) = self.to_descriptor(KeychainKind::External, &Secp256k1::new(), None)?;
+        let devices = self
+            .get_signers()
+            .iter()
+            .map(|signer| SpecterDevice {
+                device_type: "other",
+                label: signer.get_fingerprint().to_string().to_uppercase(),
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&SpecterWallet {
+            label: name.to_string(),
+            blockheight: 0,
+            descriptor: external.to_string(),
+            devices,
+        })
+        .with_context(|| "Error serializing Specter wallet")
+    }
+
+    /// Sparrow wallet file, like [`NgAccount::export_sparrow_json`] with
+    /// the `<0;1>` multipath descriptor of the quorum.
+    pub fn to_sparrow_json(&self, name: &str) -> Result<String> {
+        let secp = Secp256k1::new();
+        let (external, _) = self.to_descriptor(KeychainKind::External, &secp, None)?;
+        let (internal, _) = self.to_descriptor(KeychainKind::Internal, &secp, None)?;
+        let descriptor = join_multipath_descriptor(&external.to_string(), &internal.to_string())
+            .ok_or_else(|| anyhow!("Multisig descriptors are not multipath"))?;
+
+        serde_json::to_string_pretty(&SparrowWallet {
+            label: name.to_string(),
+            blockheight: 0,
+            descriptor,
+        })
+        .with_context(|| "Error serializing Sparrow wallet")
+    }
+
+    /// Keystone multisig setup file, the Coldcard text file with Keystone's
+    /// header.
+    pub fn to_keystone_config(&self, name: &str) -> String {
+        format!(
+            "# Keystone Multisig setup file (created by ngwallet)\n#\n{}",
+            self.to_config(name.to_string())
+        )
+    }
+
+    /// Keystone multisig QR code: the setup file in `ur:bytes` parts of at
+    /// most `max_fragment_length` bytes.
+    pub fn to_keystone_ur_parts(
+        &self,
+        name: &str,
+        max_fragment_length: usize,
+    ) -> Result<Vec<String>> {
+        if max_fragment_length == 0 {
+            bail!("Fragment length must be positive");
+        }
+        let cbor = cbor_bytes(self.to_keystone_config(name).as_bytes());
+        let mut encoder = Encoder::new();
+        encoder.start(KEYSTONE_UR_TYPE, &cbor, max_fragment_length);
+        Ok((0..encoder.sequence_count())
+            .map(|_| encoder.next_part().to_string())
+            .collect())
+    }
+}
+
+// `data` as a CBOR byte string.
+fn cbor_bytes(data: &[u8]) -> Vec<u8> {
+    let len = data.len();
+    let mut cbor = match len {
+        0..=23 => vec![0x40 | len as u8],
+        24..=0xff => vec![0x58, len as u8],
+        0x100..=0xffff => [[0x59].as_slice(), &(len as u16).to_be_bytes()].concat(),
+        _ => [[0x5a].as_slice(), &(len as u32).to_be_bytes()].concat(),
+    };
+    cbor.extend_from_slice(data);
+    cbor
+}
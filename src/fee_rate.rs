@@ -1,3 +1,4 @@
+use anyhow::Result;
 use bdk_wallet::bitcoin::FeeRate;
 use serde::{Deserialize, Serialize};
 use std::ops::Add;
@@ -60,3 +61,23 @@ impl Add for FeeRateSatPerKwu {
         FeeRateSatPerKwu(self.0 + rhs.0)
     }
 }
+
+/// Fee rate estimates, implemented by the app or an Electrum client.
+pub trait FeeEstimator: Send + Sync {
+    /// Fee rate for a transaction to confirm within `target_blocks`.
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRateSatPerKvb>;
+}
+
+#[cfg(feature = "envoy")]
+impl FeeEstimator for bdk_electrum::electrum_client::Client {
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRateSatPerKvb> {
+        use bdk_electrum::electrum_client::ElectrumApi;
+
+        // BTC/kvB, negative when the server has no estimate
+        let estimate = self.estimate_fee(target_blocks as usize)?;
+        if estimate <= 0.0 {
+            anyhow::bail!("No fee estimate for {target_blocks} blocks");
+        }
+        Ok(FeeRateSatPerKvb((estimate * 100_000_000.0).ceil() as u64))
+    }
+}
@@ -0,0 +1,76 @@
+//! Fiat valuation of transactions.
+//!
+//! The app records the fiat value of a transaction at confirmation time,
+//! either directly or through a [`PriceSource`]. Values are kept in the
+//! metadata store, shown on [`BitcoinTransaction`](crate::transaction::BitcoinTransaction)
+//! and included in backups for tax reporting.
+
+use anyhow::{Context, Result};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::SignedAmount;
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FiatValue {
+    /// Value of the transaction amount, negative for outgoing transactions.
+    pub value: f64,
+    /// ISO 4217 currency code, e.g. `USD`.
+    pub currency: String,
+}
+
+/// Historical bitcoin prices, implemented by the app.
+pub trait PriceSource: Send + Sync {
+    /// Price of one bitcoin in `currency` at `timestamp` (unix seconds).
+    fn price_at(&self, currency: &str, timestamp: u64) -> Result<f64>;
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    pub fn set_fiat_value(&self, tx_id: &str, value: f64, currency: &str) -> Result<()> {
+        self.meta_storage
+            .set_fiat_value(
+                tx_id,
+                &FiatValue {
+                    value,
+                    currency: currency.to_string(),
+                },
+            )
+            .with_context(|| "Could not set fiat value")?;
+        self.refresh();
+        self.emit_metadata_changed(tx_id);
+        Ok(())
+    }
+
+    /// Value confirmed transactions without a fiat value in `currency` at
+    /// their confirmation time, replacing their value in another currency.
+    /// Returns how many transactions were valued.
+    pub fn record_fiat_values(&self, source: &dyn PriceSource, currency: &str) -> Result<usize> {
+        let mut recorded = 0;
+        for tx in self.transactions()? {
+            let Some(date) = tx.date.filter(|_| tx.is_confirmed) else {
+                continue;
+            };
+            if tx.fiat_value.is_some() && tx.fiat_currency.as_deref() == Some(currency) {
+                continue;
+            }
+            let price = source
+                .price_at(currency, date)
+                .with_context(|| format!("Could not get {currency} price at {date}"))?;
+            let value = SignedAmount::from_sat(tx.amount).to_btc() * price;
+            self.meta_storage.set_fiat_value(
+                &tx.tx_id,
+                &FiatValue {
+                    value,
+                    currency: currency.to_string(),
+                },
+            )?;
+            self.emit_metadata_changed(&tx.tx_id);
+            recorded += 1;
+        }
+        if recorded > 0 {
+            self.refresh();
+        }
+        Ok(recorded)
+    }
+}
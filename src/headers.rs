@@ -0,0 +1,492 @@
+//! Block headers shared by the accounts.
+//!
+//! [`HeaderStore`] keeps a chain of block headers received from the backend
+//! in a redb database of its own, one per network, that every account of
+//! the app reads. Each header is validated before it is stored: it must
+//! meet the proof of work target it claims, that target can't be easier
+//! than the limit of the network nor change within a difficulty period, and
+//! it must build on the header below it. At a retarget the target must be
+//! the one computed from the timestamps of the period when its first header
+//! is stored, and at most 4 times easier or harder than the previous one
+//! otherwise. The testnet targets, which allow minimum difficulty blocks
+//! anywhere, aren't checked.
+//!
+//! The stored chain starts at the genesis block or at one of the
+//! checkpoints compiled in, nothing else is trusted. Headers forking from
+//! the stored chain replace it if they have more work, which
+//! [`HeaderInsert::fork_height`] reports.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+use bdk_wallet::bitcoin::block::Header;
+use bdk_wallet::bitcoin::consensus::{deserialize, serialize};
+use bdk_wallet::bitcoin::constants::genesis_block;
+use bdk_wallet::bitcoin::{BlockHash, CompactTarget, Network, Params, Work};
+use redb::{Builder, Database, ReadableTable, TableDefinition};
+
+use crate::error::HeaderError;
+
+// Consensus encoded header by height
+const HEADERS_TABLE: TableDefinition<u32, &[u8]> = TableDefinition::new("headers");
+// Height by block hash
+const HEIGHTS_TABLE: TableDefinition<&str, u32> = TableDefinition::new("header_heights");
+
+// Blocks the stored chain can start from besides the genesis block, from the
+// checkpoints of Bitcoin Core
+const MAINNET_CHECKPOINTS: &[(u32, &str)] = &[
+    (
+        11111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+];
+
+const TESTNET_CHECKPOINTS: &[(u32, &str)] = &[(
+    546,
+    "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+)];
+
+/// Outcome of [`HeaderStore::insert_headers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderInsert {
+    pub tip_height: u32,
+    /// Height of the first stored header replaced by the new ones, if they
+    /// forked from the stored chain.
+    pub fork_height: Option<u32>,
+}
+
+pub struct HeaderStore {
+    db: Database,
+    network: Network,
+}
+
+impl fmt::Debug for HeaderStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderStore")
+            .field("db", &self.db)
+            .field("network", &self.network)
+            .finish()
+    }
+}
+
+impl HeaderStore {
+    /// Headers of `network`, in the `path` directory.
+    pub fn from_file(path: Option<String>, network: Network) -> Result<Self> {
+        let file_path = path
+            .map(|p| format!("{p}/headers-{network}.meta"))
+            .unwrap_or(format!("headers-{network}.meta"));
+        let db = Builder::new()
+            .create(file_path)
+            .with_context(|| "Failed to create redb database")?;
+        Ok(Self::from_db(db, network))
+    }
+
+    pub fn from_db(db: Database, network: Network) -> Self {
+        Self { db, network }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Height and header of the tip of the stored chain.
+    pub fn tip(&self) -> Result<Option<(u32, Header)>> {
+        let read_txn = self.db.begin_read()?;
+        let Ok(table) = read_txn.open_table(HEADERS_TABLE) else {
+            return Ok(None);
+        };
+        match table.last()? {
+            Some((height, header)) => Ok(Some((height.value(), deserialize(header.value())?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&self, height: u32) -> Result<Option<Header>> {
+        let read_txn = self.db.begin_read()?;
+        let Ok(table) = read_txn.open_table(HEADERS_TABLE) else {
+            return Ok(None);
+        };
+        match table.get(height)? {
+            Some(header) => Ok(Some(deserialize(header.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Height of the stored header of `block_hash`.
+    pub fn height_of(&self, block_hash: &BlockHash) -> Result<Option<u32>> {
+        let read_txn = self.db.begin_read()?;
+        let Ok(table) = read_txn.open_table(HEIGHTS_TABLE) else {
+            return Ok(None);
+        };
+        Ok(table
+            .get(block_hash.to_string().as_str())?
+            .map(|height| height.value()))
+    }
+
+    /// Validate `headers`, the consecutive headers from `start_height` up,
+    /// and store them. They must connect to the stored chain, unless it is
+    /// empty, and replace the stored headers they fork from only with more
+    /// work. Nothing is stored if any of them is invalid.
+    pub fn insert_headers(&self, start_height: u32, headers: &[Header]) -> Result<HeaderInsert> {
+        let params = Params::new(self.network);
+        for (i, header) in headers.iter().enumerate() {
+            check_pow(&params, header, start_height + i as u32)?;
+        }
+
+        let write_txn = self.db.begin_write()?;
+        let inserted = {
+            let mut table = write_txn.open_table(HEADERS_TABLE)?;
+            let mut heights = write_txn.open_table(HEIGHTS_TABLE)?;
+            let tip = table.last()?.map(|(height, _)| height.value());
+            let stored = |height: u32| -> Result<Option<Header>> {
+                match table.get(height)? {
+                    Some(header) => Ok(Some(deserialize(header.value())?)),
+                    None => Ok(None),
+                }
+            };
+            // the first header of the period ending below a retarget
+            // `height`, from the new headers or the stored ones below them
+            let period_start = |height: u32| -> Result<Option<Header>> {
+                if !is_retarget(&params, height) {
+                    return Ok(None);
+                }
+                let start = height - params.difficulty_adjustment_interval() as u32;
+                match start.checked_sub(start_height) {
+                    Some(i) => Ok(headers.get(i as usize).copied()),
+                    None => stored(start),
+                }
+            };
+
+            if let Some(first) = headers.first() {
+                if start_height == 0 {
+                    if first.block_hash() != genesis_block(&params).block_hash() {
+                        return Err(HeaderError::WrongGenesis.into());
+                    }
+                } else if let Some(previous) = stored(start_height - 1)? {
+                    let start = period_start(start_height)?;
+                    check_link(&params, &previous, first, start_height, start.as_ref())?;
+                } else if tip.is_some() {
+                    return Err(HeaderError::NotConnected {
+                        height: start_height,
+                    }
+                    .into());
+                } else if !is_checkpoint(self.network, start_height, first.block_hash()) {
+                    return Err(HeaderError::NotCheckpoint {
+                        height: start_height,
+                    }
+                    .into());
+                }
+            }
+            for (i, pair) in headers.windows(2).enumerate() {
+                let height = start_height + i as u32 + 1;
+                let start = period_start(height)?;
+                check_link(&params, &pair[0], &pair[1], height, start.as_ref())?;
+            }
+
+            // skip the headers already stored
+            let mut new = 0;
+            while new < headers.len() {
+                match stored(start_height + new as u32)? {
+                    Some(header) if header == headers[new] => new += 1,
+                    _ => break,
+                }
+            }
+            let fork = start_height + new as u32;
+            let mut replaced = vec![];
+            if let Some(tip) = tip {
+                for height in fork..=tip {
+                    replaced.extend(stored(height)?);
+                }
+            }
+            let headers = &headers[new..];
+
+            if headers.is_empty() {
+                HeaderInsert {
+                    tip_height: tip.unwrap_or_default(),
+                    fork_height: None,
+                }
+            } else {
+                if !replaced.is_empty() && chain_work(headers) <= chain_work(&replaced) {
+                    return Err(HeaderError::LessWork { height: fork }.into());
+                }
+                for (i, header) in replaced.iter().enumerate() {
+                    table.remove(fork + i as u32)?;
+                    heights.remove(header.block_hash().to_string().as_str())?;
+                }
+                for (i, header) in headers.iter().enumerate() {
+                    let height = fork + i as u32;
+                    table.insert(height, serialize(header).as_slice())?;
+                    heights.insert(header.block_hash().to_string().as_str(), height)?;
+                }
+                HeaderInsert {
+                    tip_height: fork + headers.len() as u32 - 1,
+                    fork_height: (!replaced.is_empty()).then_some(fork),
+                }
+            }
+        };
+        write_txn.commit()?;
+        Ok(inserted)
+    }
+}
+
+fn check_pow(params: &Params, header: &Header, height: u32) -> Result<(), HeaderError> {
+    if header.target() > params.max_attainable_target {
+        return Err(HeaderError::BadTarget { height });
+    }
+    header
+        .validate_pow(header.target())
+        .map_err(|_| HeaderError::InvalidPow { height })?;
+    Ok(())
+}
+
+fn is_retarget(params: &Params, height: u32) -> bool {
+    height > 0 && u64::from(height) % params.difficulty_adjustment_interval() == 0
+}
+
+fn is_checkpoint(network: Network, height: u32, block_hash: BlockHash) -> bool {
+    let checkpoints = match network {
+        Network::Bitcoin => MAINNET_CHECKPOINTS,
+        Network::Testnet => TESTNET_CHECKPOINTS,
+        _ => &[],
+    };
+    checkpoints
+        .iter()
+        .any(|(checkpoint, hash)| *checkpoint == height && block_hash.to_string() == *hash)
+}
+
+// `header` at `height` builds on `previous`. `period_start` is the first
+// header of the period ending with `previous` when `height` is a retarget
+// and it is known.
+fn check_link(
+    params: &Params,
+    previous: &Header,
+    header: &Header,
+    height: u32,
+    period_start: Option<&Header>,
+) -> Result<(), HeaderError> {
+    if header.prev_blockhash != previous.block_hash() {
+        return Err(HeaderError::NotConnected { height });
+    }
+    if params.allow_min_difficulty_blocks {
+        return Ok(());
+    }
+    let valid = if !is_retarget(params, height) || params.no_pow_retargeting {
+        header.bits == previous.bits
+    } else if let Some(start) = period_start {
+        let timespan = previous.time.saturating_sub(start.time);
+        header.bits
+            == CompactTarget::from_next_work_required(previous.bits, timespan.into(), params)
+    } else {
+        let (target, last) = (header.target(), previous.target());
+        target >= last.min_transition_threshold() && target <= last.max_transition_threshold(params)
+    };
+    if !valid {
+        return Err(HeaderError::BadTarget { height });
+    }
+    Ok(())
+}
+
+fn chain_work(headers: &[Header]) -> Option<Work> {
+    headers.iter().map(Header::work).reduce(|a, b| a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::TxMerkleNode;
+    use bdk_wallet::bitcoin::block::Version;
+    use bdk_wallet::bitcoin::hashes::Hash;
+    use redb::backends::InMemoryBackend;
+
+    fn store() -> HeaderStore {
+        let db = Builder::new()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        HeaderStore::from_db(db, Network::Regtest)
+    }
+
+    // `count` headers on top of `previous`, told apart from other branches
+    // by `branch`.
+    fn mine(previous: &Header, count: usize, branch: u8) -> Vec<Header> {
+        let mut headers: Vec<Header> = vec![];
+        for _ in 0..count {
+            let previous = headers.last().unwrap_or(previous);
+            let mut header = Header {
+                version: Version::ONE,
+                prev_blockhash: previous.block_hash(),
+                merkle_root: TxMerkleNode::from_byte_array([branch; 32]),
+                time: previous.time + 600,
+                bits: previous.bits,
+                nonce: 0,
+            };
+            while header.validate_pow(header.target()).is_err() {
+                header.nonce += 1;
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn headers_are_validated_and_reorged() {
+        let store = store();
+        let genesis = genesis_block(Network::Regtest).header;
+        let chain = mine(&genesis, 3, 0);
+        assert_eq!(store.tip().unwrap(), None);
+        store.insert_headers(0, &[genesis]).unwrap();
+        let inserted = store.insert_headers(1, &chain).unwrap();
+        assert_eq!(inserted.tip_height, 3);
+        assert_eq!(inserted.fork_height, None);
+        assert_eq!(store.get(2).unwrap(), Some(chain[1]));
+        assert_eq!(store.height_of(&chain[2].block_hash()).unwrap(), Some(3));
+
+        // already stored
+        let inserted = store.insert_headers(1, &chain[..2]).unwrap();
+        assert_eq!(inserted.tip_height, 3);
+
+        // a gap above the tip
+        let next = mine(&chain[2], 2, 0);
+        let error = store.insert_headers(5, &next[1..]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<HeaderError>(),
+            Some(HeaderError::NotConnected { height: 5 })
+        ));
+
+        let mut invalid = next[0];
+        while invalid.validate_pow(invalid.target()).is_ok() {
+            invalid.nonce += 1;
+        }
+        let error = store.insert_headers(4, &[invalid]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<HeaderError>(),
+            Some(HeaderError::InvalidPow { height: 4 })
+        ));
+
+        // as long as the stored chain from height 2
+        let fork = mine(&chain[0], 2, 1);
+        let error = store.insert_headers(2, &fork).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<HeaderError>(),
+            Some(HeaderError::LessWork { height: 2 })
+        ));
+        assert_eq!(store.tip().unwrap(), Some((3, chain[2])));
+
+        let fork = mine(&chain[0], 3, 1);
+        let inserted = store.insert_headers(2, &fork).unwrap();
+        assert_eq!(inserted.tip_height, 4);
+        assert_eq!(inserted.fork_height, Some(2));
+        assert_eq!(store.tip().unwrap(), Some((4, fork[2])));
+        assert_eq!(store.height_of(&chain[2].block_hash()).unwrap(), None);
+
+        let error = store.insert_headers(0, &[chain[0]]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<HeaderError>(),
+            Some(HeaderError::WrongGenesis)
+        ));
+
+        // an empty store only starts from the genesis block or a checkpoint
+        let error = self::store().insert_headers(2, &fork[1..]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<HeaderError>(),
+            Some(HeaderError::NotCheckpoint { height: 2 })
+        ));
+    }
+
+    #[test]
+    fn retarget_is_bounded() {
+        let params = Params::new(Network::Bitcoin);
+        let interval = params.difficulty_adjustment_interval() as u32;
+        let two_weeks = params.pow_target_timespan as u32;
+        let start = Header {
+            bits: CompactTarget::from_consensus(0x1b0404cb),
+            ..genesis_block(Network::Bitcoin).header
+        };
+        let previous = Header {
+            time: start.time + two_weeks * 2,
+            ..start
+        };
+        let next = |bits: CompactTarget| Header {
+            prev_blockhash: previous.block_hash(),
+            time: previous.time + 600,
+            bits,
+            ..previous
+        };
+
+        // twice as long as expected, the target doubles
+        let doubled = CompactTarget::from_next_work_required(
+            previous.bits,
+            u64::from(two_weeks * 2),
+            &params,
+        );
+        assert!(next(doubled).target() > previous.target());
+        check_link(&params, &previous, &next(doubled), interval, Some(&start)).unwrap();
+        check_link(&params, &previous, &next(doubled), interval, None).unwrap();
+
+        // another target than the timestamps give
+        let error = check_link(
+            &params,
+            &previous,
+            &next(previous.bits),
+            interval,
+            Some(&start),
+        );
+        assert!(matches!(error, Err(HeaderError::BadTarget { .. })));
+
+        // a jump to the easiest target
+        let easiest = params.max_attainable_target.to_compact_lossy();
+        let error = check_link(&params, &previous, &next(easiest), interval, None);
+        assert!(matches!(error, Err(HeaderError::BadTarget { .. })));
+
+        // outside of a retarget
+        let error = check_link(&params, &previous, &next(doubled), interval + 1, None);
+        assert!(matches!(error, Err(HeaderError::BadTarget { .. })));
+    }
+}
@@ -0,0 +1,368 @@
+//! Balance over time, for charts, and the transaction history exported
+//! for accounting tools.
+
+use std::io::Write;
+
+use anyhow::Result;
+use bdk_wallet::WalletPersister;
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::ngwallet::FEE_UNKNOWN;
+use crate::transaction::BitcoinTransaction;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BalanceResolution {
+    /// One point per UTC day with confirmed activity, at the start of the day.
+    Daily,
+    /// One point per block with confirmed activity, at the block time.
+    PerBlock,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Confirmed balance of the account after each day or block that
+    /// changed it, oldest first, as `(timestamp, confirmed_sats)`.
+    ///
+    /// The series is a step function: periods without a point keep the
+    /// balance of the previous one.
+    pub fn balance_history(&self, resolution: BalanceResolution) -> Result<Vec<(u64, u64)>> {
+        Ok(balance_series(&self.transactions()?, resolution))
+    }
+}
+
+/// Running confirmed balance over `transactions`, see
+/// [`NgAccount::balance_history`].
+pub fn balance_series(
+    transactions: &[BitcoinTransaction],
+    resolution: BalanceResolution,
+) -> Vec<(u64, u64)> {
+    let mut confirmed: Vec<&BitcoinTransaction> =
+        transactions.iter().filter(|tx| tx.is_confirmed).collect();
+    confirmed.sort_by_key(|tx| (tx.block_height, tx.date));
+
+    let mut series: Vec<(u64, u64)> = vec![];
+    let mut last_key = None;
+    let mut balance: i64 = 0;
+    for tx in confirmed {
+        balance += tx.amount;
+        let timestamp = tx.date.unwrap_or_default();
+        let (key, timestamp) = match resolution {
+            BalanceResolution::Daily => {
+                let day = timestamp - timestamp % SECONDS_PER_DAY;
+                (day, day)
+            }
+            BalanceResolution::PerBlock => (tx.block_height as u64, timestamp),
+        };
+        let point = (timestamp, balance.max(0) as u64);
+        match series.last_mut() {
+            Some(last) if last_key == Some(key) => *last = point,
+            _ => series.push(point),
+        }
+        last_key = Some(key);
+    }
+    series
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryFormat {
+    /// RFC 4180 CSV with a header line.
+    Csv,
+    /// An array of one object per transaction.
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryExportOptions {
+    /// Only transactions dated at or after this Unix timestamp.
+    pub from: Option<u64>,
+    /// Only transactions dated before this Unix timestamp.
+    pub to: Option<u64>,
+    /// Also export the unconfirmed transactions.
+    pub include_unconfirmed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+    /// Between addresses of the account, only the fee was spent.
+    #[serde(rename = "self")]
+    SelfTransfer,
+}
+
+// One exported transaction. Amounts are in BTC with a decimal point,
+// whatever the locale.
+#[derive(Serialize)]
+struct HistoryRow {
+    /// UTC, ISO 8601. None while unconfirmed.
+    date: Option<String>,
+    tx_id: String,
+    direction: Direction,
+    /// Change of the balance, fee included.
+    amount: String,
+    /// Only for outgoing transactions, the fee of incoming ones being paid
+    /// by the sender.
+    fee: Option<String>,
+    fiat_value: Option<String>,
+    fiat_currency: Option<String>,
+    note: Option<String>,
+    tags: Vec<String>,
+}
+
+impl HistoryRow {
+    fn new(tx: &BitcoinTransaction) -> Self {
+        let direction = match tx.amount {
+            amount if amount > 0 => Direction::Incoming,
+            _ if !tx.outputs.is_empty()
+                && tx.outputs.iter().all(|output| output.keychain.is_some()) =>
+            {
+                Direction::SelfTransfer
+            }
+            _ => Direction::Outgoing,
+        };
+        let fee = match direction {
+            Direction::Incoming => None,
+            _ if tx.fee == FEE_UNKNOWN => None,
+            _ => Some(btc(tx.fee as i64)),
+        };
+        let mut tags: Vec<String> = vec![];
+        for tag in tx
+            .inputs
+            .iter()
+            .filter_map(|input| input.tag.as_ref())
+            .chain(tx.outputs.iter().filter_map(|output| output.tag.as_ref()))
+        {
+            if !tag.is_empty() && !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        Self {
+            date: tx.date.filter(|_| tx.is_confirmed).map(iso_8601),
+            tx_id: tx.tx_id.clone(),
+            direction,
+            amount: btc(tx.amount),
+            fee,
+            fiat_value: tx.fiat_value.map(|value| format!("{value:.2}")),
+            fiat_currency: tx.fiat_currency.clone(),
+            note: tx.note.clone().filter(|note| !note.is_empty()),
+            tags,
+        }
+    }
+
+    fn csv(&self) -> String {
+        let direction = match self.direction {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+            Direction::SelfTransfer => "self",
+        };
+        [
+            csv_field(self.date.as_deref().unwrap_or_default()),
+            csv_field(&self.tx_id),
+            csv_field(direction),
+            csv_field(&self.amount),
+            csv_field(self.fee.as_deref().unwrap_or_default()),
+            csv_field(self.fiat_value.as_deref().unwrap_or_default()),
+            csv_field(self.fiat_currency.as_deref().unwrap_or_default()),
+            csv_text(self.note.as_deref().unwrap_or_default()),
+            csv_text(&self.tags.join(";")),
+        ]
+        .join(",")
+    }
+}
+
+const CSV_HEADER: &str = "date,txid,direction,amount,fee,fiat_value,fiat_currency,note,tags";
+
+// Quoted if needed, with its quotes doubled.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+// Free text is also kept from being read as a formula by spreadsheets.
+fn csv_text(text: &str) -> String {
+    match text.starts_with(['=', '+', '-', '@']) {
+        true => csv_field(&format!("'{text}")),
+        false => csv_field(text),
+    }
+}
+
+// Sats as BTC, like `-0.00150000`.
+fn btc(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    format!("{sign}{}.{:08}", sats / 100_000_000, sats % 100_000_000)
+}
+
+// Unix timestamp as a UTC ISO 8601 date, like `2023-11-14T22:13:20Z`.
+fn iso_8601(timestamp: u64) -> String {
+    let seconds = timestamp % SECONDS_PER_DAY;
+    // days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // days_from_civil inverse
+    let days = (timestamp / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Write the transactions of `transactions` selected by `options` to
+/// `writer` in `format`, oldest first and the unconfirmed ones last.
+pub fn write_history(
+    transactions: &[BitcoinTransaction],
+    format: HistoryFormat,
+    options: &HistoryExportOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let mut selected: Vec<&BitcoinTransaction> = transactions
+        .iter()
+        .filter(|tx| match tx.is_confirmed {
+            true => {
+                let date = tx.date.unwrap_or_default();
+                options.from.is_none_or(|from| date >= from)
+                    && options.to.is_none_or(|to| date < to)
+            }
+            false => options.include_unconfirmed,
+        })
+        .collect();
+    selected.sort_by_key(|tx| (!tx.is_confirmed, tx.block_height, tx.date, tx.tx_id.clone()));
+    let rows = selected.into_iter().map(HistoryRow::new);
+
+    match format {
+        HistoryFormat::Csv => {
+            write!(writer, "{CSV_HEADER}\r\n")?;
+            for row in rows {
+                write!(writer, "{}\r\n", row.csv())?;
+            }
+        }
+        HistoryFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, &rows.collect::<Vec<_>>())?;
+        }
+    }
+    Ok(())
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// The transaction history in `format`, for accounting tools, see
+    /// [`write_history`].
+    pub fn export_history(
+        &self,
+        format: HistoryFormat,
+        options: &HistoryExportOptions,
+    ) -> Result<String> {
+        let mut export = vec![];
+        write_history(&self.transactions()?, format, options, &mut export)?;
+        Ok(String::from_utf8(export)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::fixtures;
+
+    fn tx(block_height: u32, date: u64, amount: i64) -> BitcoinTransaction {
+        BitcoinTransaction {
+            block_height,
+            is_confirmed: block_height > 0,
+            amount,
+            date: Some(date),
+            ..fixtures::tx(&format!("{block_height}-{amount}"))
+        }
+    }
+
+    #[test]
+    fn per_block_and_daily_series() {
+        let day = SECONDS_PER_DAY;
+        let transactions = vec![
+            tx(12, 2 * day + 10, -3_000),
+            tx(10, day + 100, 10_000),
+            tx(11, day + 700, 5_000),
+            // unconfirmed, not part of the history
+            tx(0, 3 * day, 1_000),
+        ];
+
+        assert_eq!(
+            balance_series(&transactions, BalanceResolution::PerBlock),
+            vec![
+                (day + 100, 10_000),
+                (day + 700, 15_000),
+                (2 * day + 10, 12_000)
+            ]
+        );
+        assert_eq!(
+            balance_series(&transactions, BalanceResolution::Daily),
+            vec![(day, 15_000), (2 * day, 12_000)]
+        );
+        assert!(balance_series(&[], BalanceResolution::Daily).is_empty());
+    }
+
+    #[test]
+    fn dates_are_utc() {
+        assert_eq!(iso_8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_8601(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn history_is_exported() {
+        let day = SECONDS_PER_DAY;
+        let mut received = tx(10, day, 150_000);
+        received.note = Some("Invoice 12, paid".to_string());
+        received.fiat_value = Some(45.5);
+        received.fiat_currency = Some("USD".to_string());
+        let mut sent = tx(11, 2 * day, -50_300);
+        sent.fee = 300;
+        sent.note = Some("=1+1".to_string());
+        let transactions = vec![sent, received, tx(0, 3 * day, 1_000)];
+
+        let mut csv = vec![];
+        write_history(
+            &transactions,
+            HistoryFormat::Csv,
+            &HistoryExportOptions::default(),
+            &mut csv,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!(
+                "{CSV_HEADER}\r\n\
+                 1970-01-02T00:00:00Z,10-150000,incoming,0.00150000,,45.50,USD,\"Invoice 12, paid\",\r\n\
+                 1970-01-03T00:00:00Z,11--50300,outgoing,-0.00050300,0.00000300,,,'=1+1,\r\n"
+            )
+        );
+
+        let options = HistoryExportOptions {
+            from: Some(2 * day),
+            to: None,
+            include_unconfirmed: true,
+        };
+        let mut json = vec![];
+        write_history(&transactions, HistoryFormat::Json, &options, &mut json).unwrap();
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["direction"], "outgoing");
+        assert_eq!(rows[1]["date"], serde_json::Value::Null);
+        assert_eq!(rows[1]["amount"], "0.00001000");
+    }
+}
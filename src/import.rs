@@ -0,0 +1,195 @@
+//! Descriptor import with deduplication against existing accounts.
+//!
+//! The crate has no manager owning every account, so callers pass the
+//! accounts they track and act on the returned [`ImportResult`]: add the
+//! descriptor with [`NgAccount::add_new_descriptor`] or build a new account.
+//!
+//! A seed or bare xpub gives descriptors for every script type, most of
+//! them never used. [`probe_descriptors`] keeps only those with history in
+//! their first addresses, so the new account doesn't sync empty wallets.
+
+use anyhow::{Context, Result, bail};
+use bdk_wallet::bitcoin::ScriptBuf;
+use bdk_wallet::bitcoin::bip32::{ChildNumber, Fingerprint};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey, ForEachKey};
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::{self, NgAccount};
+use crate::config::{AddressType, MultiSigDetails};
+use crate::error::{MutexExt, RwLockExt};
+use crate::utils::get_address_type;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportResult {
+    /// The descriptor (or multisig quorum) is already tracked by this account.
+    AlreadyTracked { account_id: String },
+    /// The descriptor comes from the same key and account index as an
+    /// existing single-sig account, under a script type it doesn't have yet.
+    NewScriptType {
+        account_id: String,
+        address_type: AddressType,
+    },
+    /// No existing account matches, a new one should be created.
+    NewAccount,
+}
+
+/// Decide how `descriptor` relates to `accounts`.
+///
+/// Descriptors are compared in their normalized public form, so private,
+/// public and multipath variants of the same descriptor are recognized.
+/// Multisig descriptors are matched by quorum, regardless of signer order.
+pub fn import_descriptor<P: WalletPersister>(
+    accounts: &[NgAccount<P>],
+    descriptor: &str,
+) -> Result<ImportResult> {
+    let secp = Secp256k1::new();
+    let (descriptor, _) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())
+            .with_context(|| "Invalid descriptor")?;
+    let normalized: Vec<String> = descriptor
+        .into_single_descriptors()
+        .with_context(|| "Invalid multipath descriptor")?
+        .iter()
+        .map(|descriptor| descriptor.to_string())
+        .collect();
+    let multisig = MultiSigDetails::from_descriptor(&normalized[0])
+        .ok()
+        .map(|(details, _)| details);
+
+    for account in accounts {
+        let is_tracked = account.all_wallets_or_recover().iter().any(|wallet| {
+            let wallet = wallet.bdk_wallet.lock_or_recover();
+            [KeychainKind::External, KeychainKind::Internal]
+                .into_iter()
+                .any(|keychain| {
+                    normalized.contains(&wallet.public_descriptor(keychain).to_string())
+                })
+        });
+        let config = account.config.read_or_err()?;
+        let same_quorum = multisig.is_some() && config.multisig == multisig;
+        if is_tracked || same_quorum {
+            return Ok(ImportResult::AlreadyTracked {
+                account_id: config.id.clone(),
+            });
+        }
+    }
+
+    if multisig.is_some() {
+        return Ok(ImportResult::NewAccount);
+    }
+    let Some((fingerprint, account_index)) = key_origin(&normalized[0]) else {
+        return Ok(ImportResult::NewAccount);
+    };
+    let address_type = get_address_type(&normalized[0]);
+
+    for account in accounts {
+        let xfp = account.get_xfp();
+        let config = account.config.read_or_err()?;
+        if config.multisig.is_some()
+            || config.index != account_index
+            || !xfp.eq_ignore_ascii_case(&fingerprint.to_string())
+            || config
+                .descriptors
+                .iter()
+                .any(|descriptor| descriptor.address_type == address_type)
+        {
+            continue;
+        }
+        return Ok(ImportResult::NewScriptType {
+            account_id: config.id.clone(),
+            address_type,
+        });
+    }
+
+    Ok(ImportResult::NewAccount)
+}
+
+/// Whether scripts were ever paid to, implemented by the app or an
+/// Electrum client.
+pub trait HistoryProbe: Send + Sync {
+    /// True if any of `scripts` has a transaction history.
+    fn has_history(&self, scripts: &[ScriptBuf]) -> Result<bool>;
+}
+
+#[cfg(feature = "envoy")]
+impl HistoryProbe for bdk_electrum::electrum_client::Client {
+    fn has_history(&self, scripts: &[ScriptBuf]) -> Result<bool> {
+        use bdk_electrum::electrum_client::ElectrumApi;
+
+        let histories = self.batch_script_get_history(scripts.iter().map(|s| s.as_script()))?;
+        Ok(histories.iter().any(|history| !history.is_empty()))
+    }
+}
+
+/// The descriptors of `descriptors` with history in their first `depth`
+/// receive or change addresses, and the one of `preferred` even if unused,
+/// in their original order.
+///
+/// Script types are those the account builder gives the wallets, so the
+/// result can be passed straight to
+/// [`NgAccountBuilder::descriptors`](crate::config::NgAccountBuilder::descriptors).
+pub fn probe_descriptors<P: WalletPersister>(
+    descriptors: Vec<account::Descriptor<P>>,
+    preferred: AddressType,
+    depth: u32,
+    probe: &dyn HistoryProbe,
+) -> Result<Vec<account::Descriptor<P>>> {
+    if !descriptors
+        .iter()
+        .any(|descriptor| get_address_type(&descriptor.internal) == preferred)
+    {
+        bail!("No descriptor for the preferred address type {preferred:?}");
+    }
+
+    let secp = Secp256k1::new();
+    let mut active = vec![];
+    for descriptor in descriptors {
+        if get_address_type(&descriptor.internal) == preferred {
+            active.push(descriptor);
+            continue;
+        }
+        let mut scripts = vec![];
+        for keychain in [Some(&descriptor.internal), descriptor.external.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            let (parsed, _) =
+                Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, keychain.trim())
+                    .with_context(|| "Invalid descriptor")?;
+            for single in parsed
+                .into_single_descriptors()
+                .with_context(|| "Invalid multipath descriptor")?
+            {
+                for index in 0..depth {
+                    scripts.push(single.at_derivation_index(index)?.script_pubkey());
+                }
+            }
+        }
+        if probe.has_history(&scripts)? {
+            active.push(descriptor);
+        }
+    }
+    Ok(active)
+}
+
+// Master fingerprint and BIP-44 style account index (third path level) of a
+// single key descriptor.
+fn key_origin(descriptor: &str) -> Option<(Fingerprint, u32)> {
+    let descriptor: Descriptor<DescriptorPublicKey> = descriptor.parse().ok()?;
+    let mut origin = None;
+    let mut keys = 0;
+    descriptor.for_each_key(|key| {
+        keys += 1;
+        origin = key
+            .full_derivation_path()
+            .map(|path| (key.master_fingerprint(), path));
+        true
+    });
+    let (fingerprint, path) = origin.filter(|_| keys == 1)?;
+    match path.as_ref().get(2)? {
+        ChildNumber::Hardened { index } => Some((fingerprint, *index)),
+        ChildNumber::Normal { .. } => None,
+    }
+}
@@ -0,0 +1,237 @@
+//! Consistency checks of an opened account.
+//!
+//! [`NgAccount::validate_integrity`] compares the descriptors of the
+//! [`NgAccountConfig`](crate::config::NgAccountConfig) with the wallets
+//! that were actually loaded, the multisig details and the persister files,
+//! so a stale config is reported up front instead of failing deep inside
+//! another call.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Result;
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::{NgAccount, get_persister_file_name, legacy_persister_file_name};
+use crate::config::{AddressType, MultiSigDetails};
+use crate::error::{MutexExt, RwLockExt};
+use crate::utils;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegrityIssue {
+    /// A configured descriptor has no loaded wallet.
+    MissingWallet { address_type: AddressType },
+    /// A wallet was loaded for a descriptor the config doesn't list.
+    UnconfiguredWallet { address_type: AddressType },
+    /// The loaded wallet of an address type has another descriptor than
+    /// the configured one.
+    DescriptorMismatch { address_type: AddressType },
+    /// A configured descriptor doesn't parse.
+    InvalidDescriptor {
+        address_type: AddressType,
+        error: String,
+    },
+    NetworkMismatch {
+        address_type: AddressType,
+        expected: Network,
+        found: Network,
+    },
+    /// No wallet has the preferred address type.
+    MissingPreferredWallet { address_type: AddressType },
+    /// The wallet doesn't have the keys and threshold of the multisig
+    /// details.
+    MultisigMismatch { address_type: AddressType },
+    /// No persister file was found for a configured descriptor.
+    MissingPersister { file_name: String },
+    /// A persister file matches none of the configured descriptors.
+    OrphanPersister { file_name: String },
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingWallet { address_type } => {
+                write!(f, "No wallet loaded for the {address_type:?} descriptor")
+            }
+            Self::UnconfiguredWallet { address_type } => {
+                write!(f, "The {address_type:?} wallet is not in the config")
+            }
+            Self::DescriptorMismatch { address_type } => {
+                write!(f, "The {address_type:?} wallet has another descriptor")
+            }
+            Self::InvalidDescriptor {
+                address_type,
+                error,
+            } => write!(f, "Invalid {address_type:?} descriptor: {error}"),
+            Self::NetworkMismatch {
+                address_type,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The {address_type:?} wallet is on {found}, the account on {expected}"
+            ),
+            Self::MissingPreferredWallet { address_type } => {
+                write!(
+                    f,
+                    "No wallet for the preferred {address_type:?} address type"
+                )
+            }
+            Self::MultisigMismatch { address_type } => {
+                write!(
+                    f,
+                    "The {address_type:?} wallet doesn't match the multisig details"
+                )
+            }
+            Self::MissingPersister { file_name } => write!(f, "Missing wallet file {file_name}"),
+            Self::OrphanPersister { file_name } => write!(f, "Unused wallet file {file_name}"),
+        }
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Cross-check the config against the loaded wallets and, if
+    /// `persister_files` isn't empty, against the wallet files found for
+    /// the account. No issues means the account is consistent.
+    pub fn validate_integrity(&self, persister_files: &[String]) -> Result<Vec<IntegrityIssue>> {
+        let config = self.config.read_or_err()?.clone();
+        let wallets = self.all_wallets()?;
+        let secp = Secp256k1::new();
+        let mut issues = vec![];
+
+        let mut configured = BTreeSet::new();
+        for descriptor in &config.descriptors {
+            let address_type = descriptor.address_type;
+            configured.insert(address_type);
+
+            // the change descriptor, picked out of a multipath descriptor
+            let internal = match utils::split_multipath_descriptor(&descriptor.internal) {
+                Ok(Some((_, internal))) => internal,
+                Ok(None) => descriptor.internal.clone(),
+                Err(e) => {
+                    issues.push(IntegrityIssue::InvalidDescriptor {
+                        address_type,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let internal = match ExtendedDescriptor::parse_descriptor(&secp, &internal) {
+                Ok((internal, _)) => internal,
+                Err(e) => {
+                    issues.push(IntegrityIssue::InvalidDescriptor {
+                        address_type,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            let Some(wallet) = wallets
+                .iter()
+                .find(|wallet| wallet.address_type == address_type)
+            else {
+                issues.push(IntegrityIssue::MissingWallet { address_type });
+                continue;
+            };
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            if bdk_wallet
+                .public_descriptor(KeychainKind::Internal)
+                .to_string()
+                != internal.to_string()
+            {
+                issues.push(IntegrityIssue::DescriptorMismatch { address_type });
+            }
+            if bdk_wallet.network() != config.network {
+                issues.push(IntegrityIssue::NetworkMismatch {
+                    address_type,
+                    expected: config.network,
+                    found: bdk_wallet.network(),
+                });
+            }
+            if let Some(multisig) = &config.multisig {
+                let external = bdk_wallet.public_descriptor(KeychainKind::External);
+                let matches = MultiSigDetails::from_descriptor(&external.to_string())
+                    .is_ok_and(|(details, _)| same_keys(&details, multisig));
+                if !matches {
+                    issues.push(IntegrityIssue::MultisigMismatch { address_type });
+                }
+            }
+        }
+
+        for wallet in wallets.iter() {
+            if !configured.contains(&wallet.address_type) {
+                issues.push(IntegrityIssue::UnconfiguredWallet {
+                    address_type: wallet.address_type,
+                });
+            }
+        }
+        if !wallets
+            .iter()
+            .any(|wallet| wallet.address_type == config.preferred_address_type)
+        {
+            issues.push(IntegrityIssue::MissingPreferredWallet {
+                address_type: config.preferred_address_type,
+            });
+        }
+
+        if !persister_files.is_empty() {
+            let found: BTreeSet<&str> = persister_files
+                .iter()
+                .filter_map(|file| Path::new(file).file_name()?.to_str())
+                .collect();
+            // a file not migrated yet keeps its legacy name, see
+            // migrate_persister_file
+            let expected: Vec<(String, String)> = config
+                .descriptors
+                .iter()
+                .map(|descriptor| {
+                    let (internal, external) =
+                        (&descriptor.internal, descriptor.external.as_deref());
+                    (
+                        get_persister_file_name(internal, external),
+                        legacy_persister_file_name(internal, external),
+                    )
+                })
+                .collect();
+            for (file_name, legacy) in &expected {
+                if !found.contains(file_name.as_str()) && !found.contains(legacy.as_str()) {
+                    issues.push(IntegrityIssue::MissingPersister {
+                        file_name: file_name.clone(),
+                    });
+                }
+            }
+            for file_name in found {
+                if !expected
+                    .iter()
+                    .any(|(name, legacy)| name == file_name || legacy == file_name)
+                {
+                    issues.push(IntegrityIssue::OrphanPersister {
+                        file_name: file_name.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+}
+
+// Same threshold and signers, whatever the script type.
+fn same_keys(a: &MultiSigDetails, b: &MultiSigDetails) -> bool {
+    let fingerprints = |details: &MultiSigDetails| -> BTreeSet<_> {
+        details
+            .get_signers()
+            .iter()
+            .map(|signer| signer.get_fingerprint())
+            .collect()
+    };
+    a.policy_threshold == b.policy_threshold
+        && a.policy_total_keys == b.policy_total_keys
+        && fingerprints(a) == fingerprints(b)
+}
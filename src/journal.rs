@@ -0,0 +1,205 @@
+//! Persisting the wallets and the config of an account together.
+//!
+//! Each wallet of an account persists to its own persister and the config
+//! to the [`MetaStorage`], so a crash in between leaves some wallets ahead
+//! of the others and of the config. [`NgAccount::persist`] first writes a
+//! [`PersistJournal`] with the staged changes of every wallet and the
+//! config to the metadata storage, then persists the wallets, and last
+//! commits the config and clears the journal in a single batch.
+//!
+//! Opening an account with a journal left over rolls the persist forward,
+//! writing the changes of the wallets again before loading them. BDK
+//! changesets merge, so writing one twice is harmless. If a wallet can't be
+//! written the persist is rolled back to the last committed config instead,
+//! the wallets keep the changes they got, which a sync brings again anyway.
+
+use std::fmt::Debug;
+
+use anyhow::{Result, anyhow};
+use bdk_wallet::{ChangeSet, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::{Descriptor, NgAccount};
+use crate::config::AddressType;
+use crate::error::{AccountError, MutexExt};
+use crate::ngwallet::NgWallet;
+use crate::store::{MetaStorage, with_batch};
+use crate::utils::get_address_type;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PersistJournal {
+    /// Serialized config committed by the persist.
+    config: String,
+    /// Staged changes of the wallets, by address type.
+    changesets: Vec<(AddressType, ChangeSet)>,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Journal the persist of `wallets` and `config`, if any wallet has
+    /// changes to persist.
+    pub(crate) fn begin_persist(&self, wallets: &[NgWallet<P>], config: &str) -> Result<()> {
+        let mut changesets = vec![];
+        for wallet in wallets {
+            if let Some(changeset) = wallet.staged()? {
+                changesets.push((wallet.address_type, changeset));
+            }
+        }
+        if changesets.is_empty() {
+            return Ok(());
+        }
+        let journal = PersistJournal {
+            config: config.to_string(),
+            changesets,
+        };
+        self.meta_storage
+            .set_persist_journal(Some(&serde_json::to_string(&journal)?))
+    }
+
+    /// Commit `config` and drop the journal of the persist.
+    pub(crate) fn commit_persist(&self, config: &str) -> Result<()> {
+        let storage = self.meta_storage.as_ref();
+        with_batch(storage, |storage| {
+            storage.set_config(config)?;
+            storage.set_persist_journal(None)
+        })
+    }
+}
+
+/// Finish the persist interrupted while writing the wallets of
+/// `descriptors`, if any. Must run before the wallets are loaded.
+pub(crate) fn recover_persist<P: WalletPersister>(
+    storage: &dyn MetaStorage,
+    descriptors: &[Descriptor<P>],
+) -> Result<()>
+where
+    <P as WalletPersister>::Error: Debug,
+{
+    let Some(journal) = storage.get_persist_journal()? else {
+        return Ok(());
+    };
+    let journal: PersistJournal = match serde_json::from_str(&journal) {
+        Ok(journal) => journal,
+        Err(e) => {
+            log::warn!("Dropping an unreadable persist journal: {e}");
+            return storage.set_persist_journal(None);
+        }
+    };
+
+    let rolled_forward = journal
+        .changesets
+        .iter()
+        .try_for_each(|(address_type, changeset)| {
+            let descriptor = descriptors
+                .iter()
+                .find(|descriptor| get_address_type(&descriptor.internal) == *address_type)
+                .ok_or(AccountError::WalletNotFound(*address_type))?;
+            P::persist(&mut *descriptor.bdk_persister.lock_or_err()?, changeset)
+                .map_err(|e| anyhow!("Could not persist wallet: {e:?}"))
+        });
+    with_batch(storage, |storage| {
+        match &rolled_forward {
+            Ok(()) => storage.set_config(&journal.config)?,
+            Err(e) => log::warn!("Rolling back an interrupted persist: {e:?}"),
+        }
+        storage.set_persist_journal(None)
+    })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    use bdk_wallet::bitcoin::Network;
+    use bdk_wallet::keys::bip39::Mnemonic;
+    use bdk_wallet::rusqlite::Connection;
+
+    use super::*;
+    use crate::bip39::get_descriptors;
+    use crate::config::NgAccountBuilder;
+    use crate::error::RwLockExt;
+    use crate::store::InMemoryMetaStorage;
+    use crate::testkit::{AccountFixture, FIXTURE_MNEMONIC};
+
+    fn descriptors() -> Vec<Descriptor<Connection>> {
+        let seed = Mnemonic::from_str(FIXTURE_MNEMONIC).unwrap().to_seed("");
+        get_descriptors(&seed, Network::Signet, 0)
+            .unwrap()
+            .into_iter()
+            .filter(|d| ["84", "86"].contains(&d.bip.as_str()))
+            .map(|d| Descriptor {
+                internal: d.descriptor_xpub(),
+                external: Some(d.change_descriptor_xpub()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            })
+            .collect()
+    }
+
+    fn reopen(descriptors: &[Descriptor<Connection>]) -> Vec<Descriptor<Connection>> {
+        descriptors
+            .iter()
+            .map(|d| Descriptor {
+                internal: d.internal.clone(),
+                external: d.external.clone(),
+                bdk_persister: d.bdk_persister.clone(),
+            })
+            .collect()
+    }
+
+    // Stage a rename and funds, and persist the first wallet only.
+    fn interrupted_persist(
+        descriptors: &[Descriptor<Connection>],
+        meta: Arc<dyn MetaStorage>,
+    ) -> u64 {
+        let account = NgAccountBuilder::default()
+            .name("Before".to_string())
+            .color("red".to_string())
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(reopen(descriptors))
+            .network(Network::Signet)
+            .id("journal".to_string())
+            .build(meta)
+            .unwrap();
+        account.persist().unwrap();
+
+        let fixture = AccountFixture::default();
+        for update in fixture.updates(&account).unwrap() {
+            account.apply(update).unwrap();
+        }
+        account.config.write_or_err().unwrap().name = "After".to_string();
+        let config = account.config.read_or_err().unwrap().serialize();
+        let wallets = account.wallets.read_or_err().unwrap();
+        account.begin_persist(&wallets, &config).unwrap();
+        wallets[0].persist().unwrap();
+        fixture.wallet_balance()
+    }
+
+    #[test]
+    fn interrupted_persist_is_rolled_forward() {
+        let descriptors = descriptors();
+        let meta: Arc<dyn MetaStorage> = Arc::new(InMemoryMetaStorage::default());
+        let wallet_balance = interrupted_persist(&descriptors, meta.clone());
+        assert!(meta.get_persist_journal().unwrap().is_some());
+
+        let account = NgAccount::open_account(reopen(&descriptors), meta.clone()).unwrap();
+        assert_eq!(account.config.read_or_err().unwrap().name, "After");
+        assert_eq!(
+            account.balance().unwrap().total().to_sat(),
+            2 * wallet_balance
+        );
+        assert!(meta.get_persist_journal().unwrap().is_none());
+    }
+
+    #[test]
+    fn unrecoverable_persist_is_rolled_back() {
+        let descriptors = descriptors();
+        let meta: Arc<dyn MetaStorage> = Arc::new(InMemoryMetaStorage::default());
+        interrupted_persist(&descriptors, meta.clone());
+
+        // the taproot wallet is missing, so its changes can't be written
+        let account = NgAccount::open_account(reopen(&descriptors[..1]), meta.clone()).unwrap();
+        assert_eq!(account.config.read_or_err().unwrap().name, "Before");
+        assert!(meta.get_persist_journal().unwrap().is_none());
+    }
+}
@@ -0,0 +1,223 @@
+//! Scoped access to secret keys.
+//!
+//! A [`KeyHandle`] holds the seed of a [`MasterKey`] and only lends the keys
+//! derived from it to a closure. [`KeyHandle::with_xpriv`] and
+//! [`KeyHandle::with_keymap`] erase the master key and the keys derived in
+//! the call once the closure returns, and the seed is zeroized when the
+//! handle is dropped, so no secret outlives the signing that needed it.
+//! Strings carrying private keys, like descriptors with xprvs, are wrapped
+//! in a [`SecretString`] that keeps them out of logs.
+
+use anyhow::anyhow;
+use bdk_wallet::bitcoin::NetworkKind;
+use bdk_wallet::bitcoin::bip32::{ChainCode, Fingerprint, Xpriv};
+use bdk_wallet::bitcoin::psbt::Psbt;
+use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing, Verification};
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use bdk_wallet::keys::{DescriptorSecretKey, KeyMap};
+use core::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::bip39::{Key, MasterKey};
+use crate::config::derive_keymap_from;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+pub struct KeyHandle {
+    key: Key,
+    network: NetworkKind,
+    fingerprint: Fingerprint,
+}
+
+impl fmt::Debug for KeyHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyHandle")
+            .field("network", &self.network)
+            .field("fingerprint", &self.fingerprint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyHandle {
+    pub fn new(master_key: &MasterKey, network: impl Into<NetworkKind>) -> Self {
+        Self {
+            key: master_key.key.clone(),
+            network: network.into(),
+            fingerprint: master_key.fingerprint,
+        }
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// Call `f` with the master key, erased when `f` returns.
+    pub fn with_xpriv<T>(&self, f: impl FnOnce(&Xpriv) -> T) -> anyhow::Result<T> {
+        self.lend_xpriv(&mut None, f)
+    }
+
+    /// Call `f` with the private keys of the keys of `descriptor` derived
+    /// from the master key, erased when `f` returns.
+    pub fn with_keymap<C: Signing, T>(
+        &self,
+        descriptor: &ExtendedDescriptor,
+        secp: &Secp256k1<C>,
+        f: impl FnOnce(&KeyMap) -> T,
+    ) -> anyhow::Result<T> {
+        self.lend_keymap(descriptor, secp, &mut KeyMap::new(), f)
+    }
+
+    // The master key, derived into `slot`.
+    fn master<'a>(&self, slot: &'a mut Option<Xpriv>) -> anyhow::Result<&'a Xpriv> {
+        let xpriv = Xpriv::new_master(self.network, &self.key.0)
+            .map_err(|e| anyhow!("Failed to derive master key: {e}"))?;
+        Ok(slot.insert(xpriv))
+    }
+
+    // with_xpriv with the master key in `slot`, which the tests
+    // check is erased.
+    fn lend_xpriv<T>(
+        &self,
+        slot: &mut Option<Xpriv>,
+        f: impl FnOnce(&Xpriv) -> T,
+    ) -> anyhow::Result<T> {
+        let result = f(self.master(slot)?);
+        if let Some(xpriv) = slot {
+            erase_xpriv(xpriv);
+        }
+        Ok(result)
+    }
+
+    // with_keymap with the keys in `keymap`, which the tests check
+    // is erased.
+    fn lend_keymap<C: Signing, T>(
+        &self,
+        descriptor: &ExtendedDescriptor,
+        secp: &Secp256k1<C>,
+        keymap: &mut KeyMap,
+        f: impl FnOnce(&KeyMap) -> T,
+    ) -> anyhow::Result<T> {
+        let mut master = None;
+        let derived = self
+            .master(&mut master)
+            .map(|xpriv| derive_keymap_from(descriptor, secp, xpriv, self.fingerprint));
+        if let Some(xpriv) = &mut master {
+            erase_xpriv(xpriv);
+        }
+        *keymap = derived?;
+        let result = f(keymap);
+        erase_keymap(keymap);
+        Ok(result)
+    }
+
+    /// Add the signatures of the master key to `psbt`. Inputs it has no key
+    /// for are left untouched.
+    pub fn sign_psbt<C: Signing + Verification>(
+        &self,
+        psbt: &mut Psbt,
+        secp: &Secp256k1<C>,
+    ) -> anyhow::Result<()> {
+        // keys are looked up through the BIP-32 derivations of the inputs
+        self.with_xpriv(|xpriv| psbt.sign(xpriv, secp).map(|_| ()))?
+            .map_err(|(_, errors)| anyhow!("Failed to sign inputs: {errors:?}"))
+    }
+}
+
+/// A string carrying private keys. Debug and Display redact it, and it is
+/// zeroized on drop.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// The string, which is no longer zeroized on drop.
+    pub fn into_string(mut self) -> String {
+        core::mem::take(&mut self.0)
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Overwrite the private key and chain code of `xpriv`.
+pub fn erase_xpriv(xpriv: &mut Xpriv) {
+    xpriv.private_key.non_secure_erase();
+    xpriv.chain_code = ChainCode::from([0; 32]);
+}
+
+/// Overwrite the private keys of `keymap` and empty it.
+pub fn erase_keymap(keymap: &mut KeyMap) {
+    for secret in keymap.values_mut() {
+        match secret {
+            DescriptorSecretKey::Single(single) => single.key.inner.non_secure_erase(),
+            DescriptorSecretKey::XPrv(xkey) => erase_xpriv(&mut xkey.xkey),
+            DescriptorSecretKey::MultiXPrv(xkey) => erase_xpriv(&mut xkey.xkey),
+        }
+    }
+    keymap.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip39::get_descriptors_from_xprv;
+    use bdk_wallet::bitcoin::Network;
+
+    #[test]
+    fn keys_are_erased_after_use() {
+        let secp = Secp256k1::new();
+        let master_key =
+            MasterKey::from_entropy(&secp, Network::Signet, &[7; 16], "", None).unwrap();
+        let handle = KeyHandle::new(&master_key, Network::Signet);
+
+        let mut master = None;
+        let fingerprint = handle
+            .lend_xpriv(&mut master, |xpriv| xpriv.fingerprint(&secp))
+            .unwrap();
+        assert_eq!(fingerprint, handle.fingerprint());
+        let master = master.unwrap();
+        assert_ne!(master.fingerprint(&secp), handle.fingerprint());
+        assert_eq!(master.chain_code, ChainCode::from([0; 32]));
+
+        let xprv = Xpriv::new_master(Network::Signet, &master_key.key.0).unwrap();
+        let descriptors = get_descriptors_from_xprv(xprv, Network::Signet, 0).unwrap();
+        let descriptor = &descriptors[0].descriptor.0;
+        let mut keymap = KeyMap::new();
+        let keys = handle
+            .lend_keymap(descriptor, &secp, &mut keymap, |keymap| keymap.len())
+            .unwrap();
+        assert_eq!(keys, 1);
+        assert!(keymap.is_empty());
+    }
+
+    #[test]
+    fn secret_strings_are_redacted() {
+        let secret = SecretString::from("wpkh(tprv8ZgxMBicQKsPe/84'/1'/0'/0/*)".to_string());
+        assert_eq!(format!("{secret:?}"), "SecretString(<redacted>)");
+        assert_eq!(secret.to_string(), "<redacted>");
+        assert!(secret.expose().contains("tprv"));
+        assert!(secret.into_string().contains("tprv"));
+    }
+}
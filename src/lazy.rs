@@ -0,0 +1,253 @@
+//! Wallets opened on demand.
+//!
+//! Opening a wallet parses its descriptors, opens its persister and loads
+//! its whole chain and transaction graph, for every script type of the
+//! account. [`NgAccount::open_account_lazy`] opens only the coordinator
+//! wallet and keeps the others closed until a call needs them: calls on one
+//! address type, like [`NgAccount::apply`] or [`NgAccount::sync_request`],
+//! open its wallet, and calls over the whole account, like
+//! [`NgAccount::balance`], open them all.
+//!
+//! Wallets are only closed when a single one is opened: besides the
+//! coordinator, at most `capacity` wallets stay open then, the least
+//! recently used are persisted and closed first. Calls over the whole
+//! account leave every wallet open until then, so the capacity bounds the
+//! wallets kept open between calls on single address types, not the memory
+//! of an account used as a whole. Such accounts gain little from opening
+//! lazily.
+
+use std::fmt::{self, Debug};
+use std::sync::{Arc, RwLockReadGuard};
+
+use anyhow::{Context, Result, bail};
+use bdk_wallet::WalletPersister;
+
+use crate::account::{Descriptor, NgAccount};
+use crate::config::AddressType;
+use crate::error::{AccountError, MutexExt, NgError, RwLockExt};
+use crate::ngwallet::NgWallet;
+use crate::store::MetaStorage;
+use crate::utils::get_address_type;
+
+type OpenWallet<P> = Arc<dyn Fn(&Descriptor<P>) -> Result<NgWallet<P>> + Send + Sync>;
+
+/// The wallets of a lazily opened account, besides the coordinator.
+pub(crate) struct LazyWallets<P: WalletPersister> {
+    descriptors: Vec<(AddressType, Descriptor<P>)>,
+    /// Open wallets, least recently used first.
+    open: Vec<AddressType>,
+    capacity: usize,
+    open_wallet: OpenWallet<P>,
+}
+
+impl<P: WalletPersister> Debug for LazyWallets<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyWallets")
+            .field("open", &self.open)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P: WalletPersister> LazyWallets<P> {
+    fn closed(&self) -> Vec<AddressType> {
+        self.descriptors
+            .iter()
+            .map(|(address_type, _)| *address_type)
+            .filter(|address_type| !self.open.contains(address_type))
+            .collect()
+    }
+
+    // The wallet of `address_type` if it was closed, `None` if it is open
+    // or not opened lazily.
+    fn open(&mut self, address_type: AddressType) -> Result<Option<NgWallet<P>>, NgError> {
+        if let Some(position) = self.open.iter().position(|open| *open == address_type) {
+            self.open.remove(position);
+            self.open.push(address_type);
+            return Ok(None);
+        }
+        let Some((_, descriptor)) = self
+            .descriptors
+            .iter()
+            .find(|(closed, _)| *closed == address_type)
+        else {
+            return Ok(None);
+        };
+        let wallet = (self.open_wallet)(descriptor)
+            .map_err(|e| NgError::WalletNotOpened(address_type, format!("{e:#}")))?;
+        self.open.push(address_type);
+        Ok(Some(wallet))
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Open the account with only the wallet of the preferred address type,
+    /// the others are opened when needed. `capacity` is how many wallets
+    /// besides the coordinator stay open after opening a single one, at
+    /// least one, see the [module documentation](self).
+    pub fn open_account_lazy(
+        descriptors: Vec<Descriptor<P>>,
+        meta_storage: Arc<dyn MetaStorage>,
+        capacity: usize,
+    ) -> Result<Self>
+    where
+        <P as WalletPersister>::Error: Debug,
+    {
+        crate::journal::recover_persist(meta_storage.as_ref(), &descriptors)
+            .with_context(|| "Failed to recover the last persist")?;
+        let config = meta_storage
+            .get_config()
+            .with_context(|| "Failed to get load account config")?
+            .ok_or(AccountError::ConfigNotFound)?;
+        let (coordinator, others): (Vec<_>, Vec<_>) =
+            descriptors.into_iter().partition(|descriptor| {
+                get_address_type(&descriptor.internal) == config.preferred_address_type
+            });
+        if coordinator.is_empty() {
+            bail!("No wallet found with the preferred address type");
+        }
+
+        let account = Self::open_account(coordinator, meta_storage.clone())?;
+        let open_wallet: OpenWallet<P> = Arc::new(move |descriptor: &Descriptor<P>| {
+            NgWallet::load(
+                descriptor.internal.clone(),
+                descriptor.external.clone(),
+                meta_storage.clone(),
+                descriptor.bdk_persister.clone(),
+            )
+        });
+        *account.lazy.lock_or_err()? = Some(LazyWallets {
+            descriptors: others
+                .into_iter()
+                .map(|descriptor| (get_address_type(&descriptor.internal), descriptor))
+                .collect(),
+            open: vec![],
+            capacity: capacity.max(1),
+            open_wallet,
+        });
+        Ok(account)
+    }
+
+    /// Whether the wallet of `address_type` is open. Wallets of accounts
+    /// not opened lazily always are.
+    pub fn is_wallet_open(&self, address_type: AddressType) -> Result<bool> {
+        Ok(self
+            .wallets
+            .read_or_err()?
+            .iter()
+            .any(|wallet| wallet.address_type == address_type))
+    }
+
+    /// Address types of the wallets of the account, open or not.
+    pub fn address_types(&self) -> Result<Vec<AddressType>> {
+        let mut address_types: Vec<AddressType> = self
+            .wallets
+            .read_or_err()?
+            .iter()
+            .map(|wallet| wallet.address_type)
+            .collect();
+        if let Some(lazy) = self.lazy.lock_or_err()?.as_ref() {
+            address_types.extend(lazy.closed());
+        }
+        Ok(address_types)
+    }
+
+    /// Open the wallet of `address_type` if it is closed, and close the
+    /// least recently used ones beyond the capacity.
+    pub fn open_wallet(&self, address_type: AddressType) -> Result<()> {
+        let mut lazy = self.lazy.lock_or_err()?;
+        let Some(lazy) = lazy.as_mut() else {
+            return Ok(());
+        };
+        if let Some(wallet) = lazy.open(address_type)? {
+            self.keep_locked(&wallet)?;
+            self.wallets.write_or_err()?.push(wallet);
+        }
+
+        let preferred = self.config.read_or_err()?.preferred_address_type;
+        while lazy.open.len() > lazy.capacity {
+            let Some(position) = lazy
+                .open
+                .iter()
+                .position(|open| *open != address_type && *open != preferred)
+            else {
+                break;
+            };
+            let closed = lazy.open.remove(position);
+            let mut wallets = self.wallets.write_or_err()?;
+            if let Some(index) = wallets
+                .iter()
+                .position(|wallet| wallet.address_type == closed)
+            {
+                wallets[index].persist()?;
+                wallets.remove(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// The wallets of the account, after opening the closed ones.
+    pub(crate) fn all_wallets(&self) -> Result<RwLockReadGuard<'_, Vec<NgWallet<P>>>, NgError> {
+        self.open_closed_wallets()?;
+        self.wallets.read_or_err()
+    }
+
+    /// Like [`Self::all_wallets`], but a wallet failing to open is left
+    /// closed.
+    pub(crate) fn all_wallets_or_recover(&self) -> RwLockReadGuard<'_, Vec<NgWallet<P>>> {
+        if let Err(e) = self.open_closed_wallets() {
+            log::warn!("Could not open the closed wallets: {e}");
+        }
+        self.wallets.read_or_recover()
+    }
+
+    /// Open the wallet of `address_type` from `descriptor` from now on,
+    /// when it is closed.
+    pub(crate) fn set_lazy_descriptor(
+        &self,
+        address_type: AddressType,
+        descriptor: Descriptor<P>,
+    ) -> Result<(), NgError> {
+        if let Some(lazy) = self.lazy.lock_or_err()?.as_mut()
+            && let Some((_, lazy_descriptor)) = lazy
+                .descriptors
+                .iter_mut()
+                .find(|(lazy_type, _)| *lazy_type == address_type)
+        {
+            *lazy_descriptor = descriptor;
+        }
+        Ok(())
+    }
+
+    /// Forget the closed wallets, releasing their persisters.
+    pub(crate) fn drop_closed_wallets(&self) {
+        self.lazy.lock_or_recover().take();
+    }
+
+    // Open every closed wallet. They stay open beyond the capacity until
+    // the next call to open_wallet.
+    pub(crate) fn open_closed_wallets(&self) -> Result<(), NgError> {
+        let mut lazy = self.lazy.lock_or_err()?;
+        let Some(lazy) = lazy.as_mut() else {
+            return Ok(());
+        };
+        for address_type in lazy.closed() {
+            if let Some(wallet) = lazy.open(address_type)? {
+                self.keep_locked(&wallet)?;
+                self.wallets.write_or_err()?.push(wallet);
+            }
+        }
+        Ok(())
+    }
+
+    // Wallets opened while the account is locked load their keys, drop
+    // them again.
+    fn keep_locked(&self, wallet: &NgWallet<P>) -> Result<(), NgError> {
+        if self.is_locked() {
+            wallet
+                .drop_signers()
+                .map_err(|e| NgError::WalletNotOpened(wallet.address_type, format!("{e:#}")))?;
+        }
+        Ok(())
+    }
+}
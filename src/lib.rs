@@ -1,27 +1,170 @@
+//! Wallet library shared by Envoy and Passport.
+//!
+//! Without the default `std` feature only the validation core is built:
+//! [`bip32`], [`bip39`], [`electrum_seed`], [`key_handle`], the multisig parts
+//! of [`config`], the [`pairing`] payload and [`psbt`]. The `slip39` feature adds Shamir
+//! backups of seeds, also available without std.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod account;
+#[cfg(feature = "std")]
+pub mod addresses;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod archive;
 pub mod config;
+#[cfg(feature = "std")]
+pub mod destroy;
+#[cfg(feature = "std")]
+pub mod duress;
+#[cfg(feature = "std")]
+pub mod dust;
+pub mod electrum_seed;
+#[cfg(feature = "std")]
+pub mod encrypted_store;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod estimate;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
 pub mod fee_rate;
+#[cfg(feature = "std")]
+pub mod fiat;
+#[cfg(feature = "std")]
+pub mod headers;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod integrity;
+#[cfg(feature = "std")]
+pub mod journal;
+pub mod key_handle;
+#[cfg(feature = "std")]
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+pub mod metadata_sync;
+#[cfg(feature = "std")]
+pub mod migration;
+#[cfg(feature = "std")]
 pub mod ngwallet;
+#[cfg(feature = "std")]
+pub mod ownership;
+#[cfg(feature = "std")]
+pub mod package;
+pub mod pairing;
+#[cfg(feature = "std")]
+pub mod passphrase;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "std")]
+pub mod privacy;
+#[cfg(feature = "std")]
+pub mod proof_of_reserves;
 pub mod psbt;
+#[cfg(feature = "std")]
 pub mod rbf;
+#[cfg(feature = "std")]
+pub mod recipient;
+#[cfg(feature = "testing")]
+pub mod regtest;
+#[cfg(feature = "std")]
+pub mod rotation;
+#[cfg(feature = "std")]
+pub mod search;
+#[cfg(feature = "std")]
 pub mod send;
+#[cfg(feature = "std")]
+pub mod spk_index;
+#[cfg(feature = "std")]
 pub mod store;
+#[cfg(feature = "std")]
+pub mod sweep;
+#[cfg(feature = "std")]
+pub mod sync_plan;
+#[cfg(feature = "std")]
+pub mod tags;
+#[cfg(feature = "std")]
+pub mod templates;
+#[cfg(feature = "testing")]
+pub mod testkit;
+#[cfg(feature = "std")]
 pub mod transaction;
+#[cfg(feature = "std")]
 pub mod utxo;
+#[cfg(feature = "std")]
+pub mod vault;
 
 pub use bdk_wallet;
+#[cfg(feature = "std")]
 pub use redb;
 
+#[cfg(feature = "bindings")]
+pub mod bindings;
 pub mod bip32;
 pub mod bip39;
+#[cfg(feature = "std")]
+pub mod bip85;
+#[cfg(feature = "std")]
 pub mod db;
+#[cfg(feature = "std")]
 pub mod sign_message;
+#[cfg(feature = "std")]
+pub mod signer;
+#[cfg(feature = "std")]
+pub mod signing_session;
+#[cfg(feature = "std")]
+pub mod slip132;
+#[cfg(feature = "slip39")]
+pub mod slip39;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "tor")]
+pub mod tor;
+#[cfg(feature = "std")]
 pub mod utils;
 
+#[cfg(feature = "bindings")]
+uniffi::setup_scaffolding!();
+
 #[cfg(feature = "envoy")]
 pub use bdk_electrum;
-#[cfg(feature = "envoy")]
+#[cfg(feature = "esplora")]
+pub use bdk_esplora;
+
+#[cfg(feature = "std")]
 const DEFAULT_STOP_GAP: usize = 300;
 
 #[cfg(feature = "envoy")]
 const BATCH_SIZE: usize = 5;
+
+// What the std prelude provides, for the modules built without std.
+#[cfg(not(feature = "std"))]
+mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
+// Collections of the validation core, sets are ordered without std.
+pub(crate) mod collections {
+    #[cfg(not(feature = "std"))]
+    pub use alloc::collections::{BTreeMap, BTreeSet as HashSet};
+    #[cfg(feature = "std")]
+    pub use std::collections::{BTreeMap, HashSet};
+}
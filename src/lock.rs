@@ -0,0 +1,70 @@
+//! Read-only mode.
+//!
+//! Apps gating signing behind a PIN or biometrics do it once here.
+//! [`NgAccount::lock`] drops the private keys of every wallet of the
+//! account, and until [`NgAccount::unlock`] loads them again from the
+//! descriptors of its config, signing calls fail with
+//! [`AccountError::AccountLocked`], and [`NgAccount::compose_psbt`] with
+//! [`TransactionComposeError::AccountLocked`] if the account holds keys.
+//! Syncing, addresses and labels keep working. Backups never carry private
+//! descriptors, see [`NgAccount::get_backup_json`].
+//!
+//! Locking doesn't remove the secrets from the account: the private
+//! descriptors stay in memory, in the descriptors of its config, and in its
+//! metadata storage, which is how [`NgAccount::unlock`] gets them back. The
+//! lock guards the signing calls of the account, not its memory or storage.
+//! Apps that must not keep secrets resident build watch-only accounts and
+//! sign with a [`crate::key_handle::KeyHandle`] instead.
+//!
+//! The lock is not persisted, accounts always open unlocked.
+//!
+//! [`TransactionComposeError::AccountLocked`]: crate::send::TransactionComposeError::AccountLocked
+
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use bdk_wallet::WalletPersister;
+
+use crate::account::NgAccount;
+use crate::error::{AccountError, RwLockExt};
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Drop the signers of the account and switch it to read-only mode,
+    /// shared by its clones. The private descriptors stay in its config.
+    pub fn lock(&self) -> Result<()> {
+        self.locked.store(true, Ordering::SeqCst);
+        // closed wallets drop their keys when opened
+        for wallet in self.wallets.read_or_err()?.iter() {
+            wallet.drop_signers()?;
+        }
+        Ok(())
+    }
+
+    /// Load the private keys of the account back from the descriptors of
+    /// its config, and leave read-only mode.
+    pub fn unlock(&self) -> Result<()> {
+        let descriptors = self.config.read_or_err()?.descriptors.clone();
+        for wallet in self.wallets.read_or_err()?.iter() {
+            if let Some(descriptor) = descriptors
+                .iter()
+                .find(|descriptor| descriptor.address_type == wallet.address_type)
+            {
+                wallet.load_signers(&descriptor.internal, descriptor.external.as_deref())?;
+            }
+        }
+        self.locked.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`AccountError::AccountLocked`] if the account is locked.
+    pub(crate) fn check_unlocked(&self) -> Result<(), AccountError> {
+        if self.is_locked() {
+            return Err(AccountError::AccountLocked);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Notes, tags and do-not-spend flags synced between paired devices.
+//!
+//! [`NgAccount::metadata_sync`] packs the labels of an account and its name
+//! in a [`MetadataSync`], signed with an HMAC keyed by the sync key of the
+//! account. The key is random, made by Passport when it shows the
+//! [`crate::pairing::PairingPayload`] and scanned by Envoy along with the
+//! descriptors, so it never travels with the payloads and can't be derived
+//! from the public descriptors. [`NgAccount::apply_metadata_sync`] refuses a
+//! payload whose signature doesn't match, so whatever carries it between
+//! the devices can't inject labels or rename the account. Each payload has a
+//! sequence number, and one not newer than the last applied is refused too.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use bdk_wallet::bitcoin::hex::{DisplayHex, FromHex};
+use chacha20poly1305::aead::OsRng;
+use rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::account::{NgAccount, check_output_id, check_tx_id};
+use crate::error::{RwLockExt, SyncError};
+use crate::store::with_batch;
+
+/// Version of the payload format, bumped on incompatible changes.
+pub const METADATA_SYNC_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataSync {
+    pub version: u8,
+    /// Must grow with each payload of the sender.
+    pub sequence: u64,
+    /// Name of the account.
+    pub name: String,
+    /// Notes by transaction id.
+    pub notes: BTreeMap<String, String>,
+    /// Tags of the unspent outputs, by output id.
+    pub tags: BTreeMap<String, String>,
+    /// Do-not-spend flags of the unspent outputs, by output id.
+    pub do_not_spend: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedMetadataSync {
+    /// CBOR encoded [`MetadataSync`].
+    pub payload: Vec<u8>,
+    /// HMAC-SHA256 of `payload` with the sync key of the account.
+    pub signature: [u8; 32],
+}
+
+impl SignedMetadataSync {
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        minicbor_serde::to_vec(self).map_err(|e| anyhow!("Could not encode metadata sync: {e}"))
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        Ok(minicbor_serde::from_slice(bytes)?)
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Signed [`MetadataSync`] of the account, numbered `sequence`.
+    pub fn metadata_sync(&self, sequence: u64) -> Result<SignedMetadataSync> {
+        let mut notes = BTreeMap::new();
+        for tx in self.transactions()? {
+            if let Some(note) = tx.note {
+                notes.insert(tx.tx_id, note);
+            }
+        }
+        let mut tags = BTreeMap::new();
+        let mut do_not_spend = BTreeMap::new();
+        for utxo in self.utxos()? {
+            let output_id = utxo.get_id();
+            // the flag of the output alone, not the policy of its tag
+            do_not_spend.insert(
+                output_id.clone(),
+                self.meta_storage.get_do_not_spend(&output_id)?,
+            );
+            if let Some(tag) = self.meta_storage.get_tag(&output_id)? {
+                tags.insert(output_id, tag);
+            }
+        }
+        let sync = MetadataSync {
+            version: METADATA_SYNC_VERSION,
+            sequence,
+            name: self.config.read_or_err()?.name.clone(),
+            notes,
+            tags,
+            do_not_spend,
+        };
+
+        let payload = minicbor_serde::to_vec(&sync)
+            .map_err(|e| anyhow!("Could not encode metadata sync: {e}"))?;
+        let signature = self.sign_metadata_sync(&payload)?;
+        Ok(SignedMetadataSync { payload, signature })
+    }
+
+    /// Verify `signed` and write its labels and account name, in a single
+    /// batch. Labels of outputs and transactions missing from the payload
+    /// are kept.
+    pub fn apply_metadata_sync(&self, signed: &SignedMetadataSync) -> Result<()> {
+        let expected = self.sign_metadata_sync(&signed.payload)?;
+        if !constant_time_eq(&expected, &signed.signature) {
+            return Err(SyncError::BadSignature.into());
+        }
+        let sync: MetadataSync = minicbor_serde::from_slice(&signed.payload)?;
+        if sync.version > METADATA_SYNC_VERSION {
+            bail!("Unsupported metadata sync version {}", sync.version);
+        }
+        for tx_id in sync.notes.keys() {
+            check_tx_id(tx_id)?;
+        }
+        for output_id in sync.tags.keys().chain(sync.do_not_spend.keys()) {
+            check_output_id(output_id)?;
+        }
+
+        {
+            let mut config = self.config.write_or_err()?;
+            if sync.sequence <= config.last_metadata_sync {
+                return Err(SyncError::StaleMetadataSync {
+                    sequence: sync.sequence,
+                    last: config.last_metadata_sync,
+                }
+                .into());
+            }
+            let mut updated = config.clone();
+            updated.name = sync.name.clone();
+            updated.last_metadata_sync = sync.sequence;
+
+            let storage = self.meta_storage.as_ref();
+            with_batch(storage, |storage| {
+                for (tx_id, note) in &sync.notes {
+                    storage.set_note(tx_id, note)?;
+                }
+                for (output_id, tag) in &sync.tags {
+                    storage.set_tag(output_id, tag)?;
+                    if !tag.is_empty() {
+                        storage.add_tag(tag)?;
+                    }
+                }
+                for (output_id, state) in &sync.do_not_spend {
+                    storage.set_do_not_spend(output_id, *state)?;
+                }
+                storage.set_config(&updated.serialize())
+            })?;
+            *config = updated;
+        }
+
+        self.refresh();
+        let ids = sync
+            .notes
+            .keys()
+            .chain(sync.tags.keys())
+            .chain(sync.do_not_spend.keys());
+        for id in ids {
+            self.emit_metadata_changed(id);
+        }
+        Ok(())
+    }
+
+    /// Set the key the payloads are signed with, the
+    /// [`crate::pairing::PairingPayload::sync_key`] scanned at pairing.
+    pub fn set_metadata_sync_key(&self, key: &[u8; 32]) -> Result<()> {
+        self.meta_storage.set_sync_key(&key.to_lower_hex_string())
+    }
+
+    /// The sync key of the account, a new random one if it has none yet.
+    pub(crate) fn metadata_sync_key_or_new(&self) -> Result<[u8; 32]> {
+        if let Some(key) = self.metadata_sync_key()? {
+            return Ok(key);
+        }
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        self.set_metadata_sync_key(&key)?;
+        Ok(key)
+    }
+
+    fn metadata_sync_key(&self) -> Result<Option<[u8; 32]>> {
+        match self.meta_storage.get_sync_key()? {
+            Some(key) => Ok(Some(<[u8; 32]>::from_hex(&key)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// HMAC of `payload` keyed with the sync key of the account.
+    fn sign_metadata_sync(&self, payload: &[u8]) -> Result<[u8; 32]> {
+        let key = self.metadata_sync_key()?.ok_or(SyncError::NoSyncKey)?;
+        let mut engine = HmacEngine::<sha256::Hash>::new(&key);
+        engine.input(payload);
+        Ok(Hmac::<sha256::Hash>::from_engine(engine).to_byte_array())
+    }
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,169 @@
+//! Migrating a multisig account to a new quorum.
+//!
+//! When a cosigner key is lost or compromised, the quorum changes and so
+//! do all the addresses. [`MultiSigDetails::rotate_signer`] swaps the key,
+//! and [`NgAccount::plan_migration`] creates the account of the new quorum
+//! and the transactions sweeping the old one into it.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::{KeychainKind, WalletPersister};
+
+use crate::account::{Descriptor, NgAccount};
+use crate::config::{MultiSigDetails, NgAccountBuilder};
+use crate::error::{MutexExt, RwLockExt};
+use crate::send::{
+    DraftTransaction, FeeRateSatPerKvb, FeeSharing, OutputOrdering, SpendPath, TransactionParams,
+};
+use crate::store::MetaStorage;
+use crate::transaction::Output;
+
+pub struct MigrationPlan<P: WalletPersister> {
+    /// The account of the new quorum.
+    pub account: NgAccount<P>,
+    /// One sweep per tag of the coins of the old account, each paying a new
+    /// address of [`Self::account`] tagged the same.
+    pub sweeps: Vec<DraftTransaction>,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Create the account of the `new_details` quorum in `persister` and
+    /// `storage`, and sweep the funds of this multisig account into it at
+    /// `fee_rate`.
+    ///
+    /// Coins marked as do not spend stay in this account. Coins are swept
+    /// by tag, so differently tagged coins aren't merged on chain and the
+    /// tags carry over to the new account. Both accounts keep the id of the
+    /// other, and the sweeps get a note in each.
+    pub fn plan_migration(
+        &self,
+        new_details: MultiSigDetails,
+        id: String,
+        persister: Arc<Mutex<P>>,
+        storage: Arc<dyn MetaStorage>,
+        fee_rate: FeeRateSatPerKvb,
+    ) -> Result<MigrationPlan<P>> {
+        let config = self.config.read_or_err()?.clone();
+        let Some(old_details) = &config.multisig else {
+            bail!("Only multisig accounts are migrated");
+        };
+        if *old_details == new_details {
+            bail!("The new quorum is the same as the old one");
+        }
+        if old_details.network_kind != new_details.network_kind {
+            bail!("The new quorum is on another network");
+        }
+
+        let secp = Secp256k1::new();
+        let (external, _) = new_details.to_descriptor(KeychainKind::External, &secp, None)?;
+        let (internal, _) = new_details.to_descriptor(KeychainKind::Internal, &secp, None)?;
+        let account = NgAccountBuilder::default()
+            .id(id)
+            .name(config.name.clone())
+            .color(config.color.clone())
+            .network(config.network)
+            .multisig(new_details)
+            .migrated_from(Some(config.id.clone()))
+            .descriptors(vec![Descriptor {
+                internal: internal.to_string(),
+                external: Some(external.to_string()),
+                bdk_persister: persister,
+            }])
+            .build(storage)?;
+        let new_id = account.config.read_or_err()?.id.clone();
+
+        let utxos = self.utxos()?;
+        let mut by_tag: BTreeMap<Option<String>, Vec<Output>> = BTreeMap::new();
+        for utxo in utxos.iter().filter(|utxo| !utxo.do_not_spend) {
+            by_tag
+                .entry(utxo.tag.clone())
+                .or_default()
+                .push(utxo.clone());
+        }
+
+        let mut sweeps = vec![];
+        let mut swept = vec![];
+        let coordinator = self.get_coordinator_wallet();
+        let mut coordinator_wallet = coordinator.bdk_wallet.lock_or_err()?;
+        for (tag, mut spendables) in by_tag {
+            let address = account
+                .get_coordinator_wallet()
+                .bdk_wallet
+                .lock_or_err()?
+                .reveal_next_address(KeychainKind::External)
+                .address;
+            account.persist()?;
+
+            let mut do_not_spend: Vec<Output> = utxos
+                .iter()
+                .filter(|utxo| !spendables.contains(utxo))
+                .cloned()
+                .collect();
+            let psbt = self.prepare_psbt(
+                &mut coordinator_wallet,
+                address.script_pubkey(),
+                &mut spendables,
+                &mut do_not_spend,
+                None,
+                Some(fee_rate.to_bdk()),
+                0,
+                true,
+                OutputOrdering::default(),
+                None,
+                SpendPath::Primary,
+                &[],
+            )?;
+            let txid = psbt.unsigned_tx.compute_txid();
+            let vout = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .position(|output| output.script_pubkey == address.script_pubkey())
+                .ok_or_else(|| anyhow!("Sweep doesn't pay the new account"))?;
+
+            let params = TransactionParams {
+                address: address.to_string(),
+                amount: spendables.iter().map(|utxo| utxo.amount).sum(),
+                fee_rate,
+                selected_outputs: spendables,
+                note: Some(format!("Migration to {new_id}")),
+                tag: tag.clone(),
+                do_not_spend_change: false,
+                ordering: OutputOrdering::default(),
+                change_address: None,
+                spend_path: SpendPath::Primary,
+                foreign_inputs: vec![],
+                fee_sharing: FeeSharing::Ours,
+                spend_from_tag: None,
+                embed_memo: false,
+            };
+            sweeps.push(self.prepare_draft_transaction(
+                psbt,
+                &mut coordinator_wallet,
+                utxos.clone(),
+                params,
+                true,
+            ));
+            swept.push((txid, vout, tag));
+        }
+        // notes and tags refresh the wallets, which needs the lock
+        drop(coordinator_wallet);
+
+        // segwit txids don't change once signed
+        for (txid, vout, tag) in swept {
+            let txid = txid.to_string();
+            self.set_note_unchecked(&txid, &format!("Migration to {new_id}"))?;
+            account.set_note_unchecked(&txid, &format!("Migration from {}", config.id))?;
+            if let Some(tag) = tag {
+                account.set_tag(&format!("{txid}:{vout}"), &tag)?;
+            }
+        }
+
+        self.config.write_or_err()?.migrated_to = Some(new_id);
+        self.persist()?;
+        Ok(MigrationPlan { account, sweeps })
+    }
+}
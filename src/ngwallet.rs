@@ -1,19 +1,26 @@
-use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
 use std::result::Result::Ok;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use bdk_core::TxUpdate;
-use bdk_wallet::bitcoin::{Address, Amount, Network, Psbt, Transaction};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{
+    Address, Amount, BlockHash, Network, Psbt, ScriptBuf, Transaction, Txid,
+};
 use bdk_wallet::chain::ChainPosition::{Confirmed, Unconfirmed};
 use bdk_wallet::chain::local_chain::CannotConnectError;
 #[cfg(feature = "envoy")]
-use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse, SyncRequest, SyncResponse};
-use bdk_wallet::descriptor::IntoWalletDescriptor;
+use bdk_wallet::chain::spk_client::{
+    FullScanRequest, FullScanResponse, SyncItem, SyncRequest, SyncResponse,
+};
+use bdk_wallet::descriptor::{ExtendedDescriptor, IntoWalletDescriptor};
+use bdk_wallet::keys::KeyMap;
 use bdk_wallet::miniscript::ForEachKey;
+use bdk_wallet::{ChangeSet, KeychainKind, WalletPersister};
 use bdk_wallet::{CreateWithPersistError, LoadWithPersistError, PersistedWallet, SignOptions};
-use bdk_wallet::{KeychainKind, WalletPersister};
 use bdk_wallet::{Update, Wallet};
 #[cfg(feature = "envoy")]
 use log::info;
@@ -21,12 +28,16 @@ use log::info;
 pub const FEE_UNKNOWN: u64 = i64::MAX as u64; // flutter max intiger for fee
 
 use crate::config::AddressType;
+use crate::error::MutexExt;
 #[cfg(feature = "envoy")]
 use crate::{BATCH_SIZE, DEFAULT_STOP_GAP};
 
 use crate::fee_rate::FeeRateSatPerKvb;
+use crate::spk_index;
 use crate::store::MetaStorage;
-use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output};
+use crate::transaction::{
+    self, BitcoinTransaction, COINJOIN_TAG, Input, KeyChain, Output, TxKind, TxType,
+};
 use crate::utils;
 
 #[derive(Debug)]
@@ -54,12 +65,71 @@ pub enum PsbtOutputOwnership {
     Unknown,
 }
 
-#[derive(Debug)]
+/// Progress of a full scan or sync, reported through a [`ProgressCallback`].
+#[cfg(feature = "envoy")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Keychain of the script pubkey that was just queried.
+    pub keychain: Option<KeychainKind>,
+    /// Derivation index of the script pubkey that was just queried.
+    pub current_index: Option<u32>,
+    /// Number of script pubkeys queried so far.
+    pub spks_scanned: usize,
+    /// Number of script pubkeys left to query. Only known when syncing,
+    /// a full scan stops once it hits the stop gap.
+    pub spks_remaining: Option<usize>,
+    /// Number of completed request batches sent to the server.
+    pub batches_completed: usize,
+    /// Number of transactions found, set on the final report.
+    pub txs_found: usize,
+    /// `true` for the final report, sent once the server has answered.
+    pub finished: bool,
+}
+
+/// Receives [`ScanProgress`] reports. Wrap a channel sender in the closure
+/// to consume the reports from another thread.
+#[cfg(feature = "envoy")]
+pub type ProgressCallback = Arc<dyn Fn(ScanProgress) + Send + Sync>;
+
+/// Confirmed transactions that became unconfirmed because an update
+/// replaced blocks of the local chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    pub address_type: AddressType,
+    /// Height of the first replaced block.
+    pub fork_height: u32,
+    pub unconfirmed_tx_ids: Vec<String>,
+}
+
+/// Results of the expensive wallet queries, kept until the wallet or its
+/// metadata changes.
+///
+/// `generation` is bumped on every invalidation so that a query which raced
+/// with an update does not store its (already stale) result.
+#[derive(Debug, Default)]
+struct QueryCache {
+    generation: u64,
+    transactions: Option<Vec<BitcoinTransaction>>,
+    utxos: Option<Vec<Output>>,
+}
+
 pub struct NgWallet<P: WalletPersister> {
     pub bdk_wallet: Arc<Mutex<PersistedWallet<P>>>,
     pub address_type: AddressType,
     pub(crate) meta_storage: Arc<dyn MetaStorage>,
     bdk_persister: Arc<Mutex<P>>,
+    query_cache: Arc<Mutex<QueryCache>>,
+}
+
+// the signers of the BDK wallet print their private keys
+impl<P: WalletPersister> Debug for NgWallet<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NgWallet")
+            .field("bdk_wallet", &"<redacted>")
+            .field("address_type", &self.address_type)
+            .field("meta_storage", &self.meta_storage)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<P: WalletPersister> Clone for NgWallet<P> {
@@ -69,6 +139,7 @@ impl<P: WalletPersister> Clone for NgWallet<P> {
             address_type: self.address_type,
             meta_storage: self.meta_storage.clone(),
             bdk_persister: self.bdk_persister.clone(),
+            query_cache: self.query_cache.clone(),
         }
     }
 }
@@ -82,14 +153,17 @@ impl<P: WalletPersister> NgWallet<P> {
         bdk_persister: Arc<Mutex<P>>,
     ) -> Result<NgWallet<P>>
     where
-        D: IntoWalletDescriptor + Send + Clone + 'static,
+        D: IntoWalletDescriptor + ToString + Send + Clone + 'static,
     {
         let wallet = match external_descriptor {
-            None => Wallet::create_single(internal_descriptor),
+            None => match utils::split_multipath_descriptor(&internal_descriptor.to_string())? {
+                Some((external, internal)) => Wallet::create(external, internal),
+                None => Wallet::create_single(internal_descriptor),
+            },
             Some(external_descriptor) => Wallet::create(external_descriptor, internal_descriptor),
         }
         .network(network)
-        .create_wallet(&mut *bdk_persister.lock().unwrap())
+        .create_wallet(&mut *bdk_persister.lock_or_err()?)
         .map_err(|e| match e {
             CreateWithPersistError::Persist(_) => {
                 anyhow::anyhow!("Could not persist wallet")
@@ -112,17 +186,24 @@ impl<P: WalletPersister> NgWallet<P> {
             bdk_persister,
             meta_storage,
             address_type,
+            query_cache: Default::default(),
         })
     }
 
     pub fn persist(&self) -> Result<bool> {
-        self.bdk_wallet
-            .lock()
-            .unwrap()
-            .persist(&mut self.bdk_persister.lock().unwrap())
+        self.refresh();
+        let mut wallet = self.bdk_wallet.lock_or_err()?;
+        index_spks(self.meta_storage.as_ref(), self.address_type, &wallet);
+        wallet
+            .persist(&mut self.bdk_persister.lock_or_err()?)
             .map_err(|_| anyhow::anyhow!("Could not persist wallet"))
     }
 
+    /// Changes not persisted yet, see [`crate::journal`].
+    pub(crate) fn staged(&self) -> Result<Option<ChangeSet>> {
+        Ok(self.bdk_wallet.lock_or_err()?.staged().cloned())
+    }
+
     pub fn load<D>(
         internal_descriptor: D,
         external_descriptor: Option<D>,
@@ -130,14 +211,24 @@ impl<P: WalletPersister> NgWallet<P> {
         bdk_persister: Arc<Mutex<P>>,
     ) -> Result<NgWallet<P>>
     where
-        D: IntoWalletDescriptor + Send + Clone + 'static,
+        D: IntoWalletDescriptor + ToString + Send + Clone + 'static,
         <P as WalletPersister>::Error: Debug,
     {
-        let wallet = Wallet::load()
-            .descriptor(KeychainKind::Internal, Some(internal_descriptor))
-            .descriptor(KeychainKind::External, external_descriptor)
+        let multipath = match external_descriptor {
+            None => utils::split_multipath_descriptor(&internal_descriptor.to_string())?,
+            Some(_) => None,
+        };
+        let params = match multipath {
+            Some((external, internal)) => Wallet::load()
+                .descriptor(KeychainKind::Internal, Some(internal))
+                .descriptor(KeychainKind::External, Some(external)),
+            None => Wallet::load()
+                .descriptor(KeychainKind::Internal, Some(internal_descriptor))
+                .descriptor(KeychainKind::External, external_descriptor),
+        };
+        let wallet = params
             .extract_keys()
-            .load_wallet(&mut *bdk_persister.lock().unwrap())
+            .load_wallet(&mut *bdk_persister.lock_or_err()?)
             .map_err(|e| match e {
                 LoadWithPersistError::Persist(_) => {
                     anyhow::anyhow!("Failed to load wallet from persister: {e:?}")
@@ -150,16 +241,60 @@ impl<P: WalletPersister> NgWallet<P> {
 
         let address_type =
             utils::get_address_type(&wallet.public_descriptor(KeychainKind::External).to_string());
+        index_spks(meta_storage.as_ref(), address_type, &wallet);
         Ok(Self {
             bdk_wallet: Arc::new(Mutex::new(wallet)),
             bdk_persister,
             meta_storage,
             address_type,
+            query_cache: Default::default(),
         })
     }
 
+    /// Drop the cached [`Self::transactions`] and [`Self::utxos`] results.
+    ///
+    /// This is done automatically when an update is applied or the wallet is
+    /// persisted, call it after touching `bdk_wallet` or the metadata store
+    /// directly.
+    pub fn refresh(&self) {
+        let mut cache = self.lock_query_cache();
+        cache.generation = cache.generation.wrapping_add(1);
+        cache.transactions = None;
+        cache.utxos = None;
+    }
+
+    /// Bumped by every [`Self::refresh`].
+    pub(crate) fn generation(&self) -> u64 {
+        self.lock_query_cache().generation
+    }
+
+    fn lock_query_cache(&self) -> std::sync::MutexGuard<'_, QueryCache> {
+        match self.query_cache.lock() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
     pub fn transactions(&self) -> Result<Vec<BitcoinTransaction>> {
-        let wallet = self.bdk_wallet.lock().unwrap();
+        let generation = {
+            let cache = self.lock_query_cache();
+            if let Some(transactions) = &cache.transactions {
+                return Ok(transactions.clone());
+            }
+            cache.generation
+        };
+
+        let transactions = self.load_transactions()?;
+
+        let mut cache = self.lock_query_cache();
+        if cache.generation == generation {
+            cache.transactions = Some(transactions.clone());
+        }
+        Ok(transactions)
+    }
+
+    fn load_transactions(&self) -> Result<Vec<BitcoinTransaction>> {
+        let wallet = self.bdk_wallet.lock_or_err()?;
         let mut transactions: Vec<BitcoinTransaction> = vec![];
         let tip_height = wallet.latest_checkpoint().height();
         let storage = &self.meta_storage;
@@ -216,23 +351,26 @@ impl<P: WalletPersister> NgWallet<P> {
                 .map(|input| {
                     let tx_id = input.previous_output.txid.to_string();
                     let vout = input.previous_output.vout;
-                    let amount = if wallet.get_utxo(input.previous_output).is_some() {
+                    // the spent output, if the graph has its transaction or
+                    // it was fetched for the fee
+                    let prev_out = wallet.tx_graph().get_txout(input.previous_output);
+                    let keychain = prev_out.and_then(|prev_out| {
                         wallet
-                            .get_utxo(input.previous_output)
-                            .unwrap()
-                            .txout
-                            .value
-                            .to_sat()
-                    } else {
-                        0
-                    };
+                            .derivation_of_spk(prev_out.script_pubkey.clone())
+                            .map(|(keychain, _)| KeyChain::from(keychain))
+                    });
                     Input {
                         tx_id: tx_id.clone(),
                         vout,
-                        amount,
+                        amount: prev_out.map_or(0, |prev_out| prev_out.value.to_sat()),
                         tag: storage
                             .get_tag(format!("{}{}", &tx_id, vout).as_str())
                             .unwrap_or(None),
+                        address: prev_out.map(|prev_out| {
+                            utils::get_address_as_string(&prev_out.script_pubkey, wallet.network())
+                        }),
+                        is_mine: keychain.is_some(),
+                        keychain,
                     }
                 })
                 .collect::<Vec<Input>>();
@@ -244,7 +382,12 @@ impl<P: WalletPersister> NgWallet<P> {
                 .enumerate()
                 .map(|(index, output)| {
                     let amount = output.value;
-                    let do_not_spend = storage.get_do_not_spend(&tx_id).unwrap_or(false);
+                    let mut tag = storage.get_tag(&format!("{}:{}", &tx_id, index)).unwrap();
+                    if wallet.is_mine(output.script_pubkey.clone()) {
+                        tag = coinjoin_tag(storage.as_ref(), tx.as_ref(), index, tag);
+                    }
+                    let do_not_spend = storage.get_do_not_spend(&tx_id).unwrap_or(false)
+                        || is_tag_do_not_spend(storage.as_ref(), tag.as_deref());
                     Output {
                         tx_id: tx_id.clone(),
                         vout: index as u32,
@@ -253,7 +396,7 @@ impl<P: WalletPersister> NgWallet<P> {
                             &output.script_pubkey,
                             wallet.network(),
                         ),
-                        tag: storage.get_tag(&format!("{}:{}", &tx_id, index)).unwrap(),
+                        tag,
                         do_not_spend,
                         keychain: wallet
                             .derivation_of_spk(output.script_pubkey.clone())
@@ -377,7 +520,23 @@ impl<P: WalletPersister> NgWallet<P> {
                 0
             });
 
-            storage.get_note(&tx_id).unwrap_or(None);
+            let fiat = storage.get_fiat_value(&tx_id).unwrap_or(None);
+            let conflicts_with = wallet
+                .tx_graph()
+                .direct_conflicts(tx.as_ref())
+                .map(|(_, txid)| txid.to_string())
+                .collect::<Vec<_>>();
+            let keychains: Vec<Option<KeyChain>> = outputs
+                .iter()
+                .map(|output| output.keychain.clone())
+                .collect();
+            let tx_type = TxType::classify(
+                tx.as_ref(),
+                sent > Amount::ZERO,
+                &keychains,
+                false,
+                !conflicts_with.is_empty(),
+            );
             transactions.push(BitcoinTransaction {
                 tx_id: tx_id.clone(),
                 block_height,
@@ -394,21 +553,81 @@ impl<P: WalletPersister> NgWallet<P> {
                 note: storage.get_note(&tx_id).unwrap(),
                 //empty account_id for now,will be populated from account later
                 account_id: "".to_string(),
+                fiat_value: fiat.as_ref().map(|fiat| fiat.value),
+                fiat_currency: fiat.map(|fiat| fiat.currency),
+                conflicts_with,
+                package: None,
+                tx_kind: TxKind::of(tx.as_ref()),
+                tx_type,
             })
         }
 
         Ok(transactions)
     }
 
+    /// Transactions of the wallet spending some of the outputs `txid` spends,
+    /// including ones that are no longer in the canonical history.
+    pub(crate) fn conflicts_of(&self, txid: Txid) -> Result<Vec<String>> {
+        let wallet = self.bdk_wallet.lock_or_err()?;
+        let graph = wallet.tx_graph();
+        let Some(tx) = graph.get_tx(txid) else {
+            return Ok(vec![]);
+        };
+        Ok(graph
+            .direct_conflicts(&tx)
+            .map(|(_, txid)| txid.to_string())
+            .collect())
+    }
+
     #[cfg(feature = "envoy")]
     pub fn sync_request(&self) -> SyncRequest<(KeychainKind, u32)> {
         self.bdk_wallet
-            .lock()
-            .unwrap()
+            .lock_or_recover()
             .start_sync_with_revealed_spks()
             .build()
     }
 
+    /// Same as [`Self::sync_request`], reporting every script pubkey the
+    /// client queries to `on_progress`.
+    #[cfg(feature = "envoy")]
+    pub fn sync_request_with_progress(
+        &self,
+        on_progress: ProgressCallback,
+    ) -> SyncRequest<(KeychainKind, u32)> {
+        self.bdk_wallet
+            .lock_or_recover()
+            .start_sync_with_revealed_spks()
+            .inspect(move |item, progress| {
+                let (keychain, current_index) = match item {
+                    SyncItem::Spk((keychain, index), _) => (Some(keychain), Some(index)),
+                    _ => (None, None),
+                };
+                on_progress(ScanProgress {
+                    keychain,
+                    current_index,
+                    spks_scanned: progress.spks_consumed,
+                    spks_remaining: Some(progress.spks_remaining),
+                    batches_completed: progress.spks_consumed / BATCH_SIZE,
+                    txs_found: 0,
+                    finished: false,
+                });
+            })
+            .build()
+    }
+
+    /// The `count` scripts of `keychain` after the last revealed one, with
+    /// their index, to look for activity on addresses the wallet doesn't
+    /// know it used.
+    pub fn unrevealed_spks(&self, keychain: KeychainKind, count: u32) -> Vec<(u32, ScriptBuf)> {
+        let wallet = self.bdk_wallet.lock_or_recover();
+        let start = wallet
+            .derivation_index(keychain)
+            .map_or(0, |index| index.saturating_add(1));
+        (start..start.saturating_add(count))
+            .map(|index| (index, wallet.peek_address(keychain, index).script_pubkey()))
+            .collect()
+    }
+
     #[cfg(feature = "envoy")]
     pub fn sync(
         request: SyncRequest<(KeychainKind, u32)>,
@@ -428,6 +647,25 @@ impl<P: WalletPersister> NgWallet<P> {
         Ok(update)
     }
 
+    /// Same as [`Self::sync`], sending a final report with the number of
+    /// transactions found to `on_progress` once the sync is done.
+    #[cfg(feature = "envoy")]
+    pub fn sync_with_progress(
+        request: SyncRequest<(KeychainKind, u32)>,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+        on_progress: ProgressCallback,
+    ) -> Result<SyncResponse> {
+        let update = Self::sync(request, electrum_server, socks_proxy, validate_domain)?;
+        on_progress(ScanProgress {
+            txs_found: update.tx_update.txs.len(),
+            finished: true,
+            ..Default::default()
+        });
+        Ok(update)
+    }
+
     #[cfg(feature = "envoy")]
     pub fn scan(
         request: FullScanRequest<KeychainKind>,
@@ -442,6 +680,32 @@ impl<P: WalletPersister> NgWallet<P> {
         Ok(update)
     }
 
+    /// Same as [`Self::scan`], sending a final report with the number of
+    /// transactions found to `on_progress` once the scan is done.
+    #[cfg(feature = "envoy")]
+    pub fn scan_with_progress(
+        request: FullScanRequest<KeychainKind>,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        stop_gap: Option<usize>,
+        validate_domain: Option<bool>,
+        on_progress: ProgressCallback,
+    ) -> Result<FullScanResponse<KeychainKind>> {
+        let update = Self::scan(
+            request,
+            electrum_server,
+            socks_proxy,
+            stop_gap,
+            validate_domain,
+        )?;
+        on_progress(ScanProgress {
+            txs_found: update.tx_update.txs.len(),
+            finished: true,
+            ..Default::default()
+        });
+        Ok(update)
+    }
+
     #[cfg(feature = "envoy")]
     pub fn full_scan_request(&self) -> FullScanRequest<KeychainKind> {
         match self.bdk_wallet.lock() {
@@ -450,16 +714,99 @@ impl<P: WalletPersister> NgWallet<P> {
         }
     }
 
-    pub fn apply_update(&self, update: Update) -> Result<(), CannotConnectError> {
+    /// Same as [`Self::full_scan_request`], reporting every script pubkey the
+    /// client queries to `on_progress`.
+    #[cfg(feature = "envoy")]
+    pub fn full_scan_request_with_progress(
+        &self,
+        on_progress: ProgressCallback,
+    ) -> FullScanRequest<KeychainKind> {
+        let mut spks_scanned = 0;
+        let inspect = move |keychain: KeychainKind, index: u32, _: &bdk_wallet::bitcoin::Script| {
+            spks_scanned += 1;
+            on_progress(ScanProgress {
+                keychain: Some(keychain),
+                current_index: Some(index),
+                spks_scanned,
+                spks_remaining: None,
+                batches_completed: spks_scanned / BATCH_SIZE,
+                txs_found: 0,
+                finished: false,
+            });
+        };
         match self.bdk_wallet.lock() {
-            Ok(mut wallet) => wallet.apply_update(update),
-            Err(poisoned) => {
-                // Recover from poisoned lock and continue
-                poisoned.into_inner().apply_update(update)
-            }
+            Ok(wallet) => wallet.start_full_scan().inspect(inspect).build(),
+            Err(poisoned) => poisoned
+                .into_inner()
+                .start_full_scan()
+                .inspect(inspect)
+                .build(),
         }
     }
 
+    pub fn apply_update(&self, update: Update) -> Result<(), CannotConnectError> {
+        self.apply_update_with_reorg_check(update).map(|_| ())
+    }
+
+    /// Same as [`Self::apply_update`], also returning the transactions that
+    /// lost their confirmation if the update replaced blocks of the local chain.
+    pub fn apply_update_with_reorg_check(
+        &self,
+        update: Update,
+    ) -> Result<Option<ReorgEvent>, CannotConnectError> {
+        let mut wallet = self
+            .bdk_wallet
+            .lock()
+            // Recover from poisoned lock and continue
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let blocks_before: BTreeMap<u32, BlockHash> = wallet
+            .local_chain()
+            .iter_checkpoints()
+            .map(|checkpoint| (checkpoint.height(), checkpoint.hash()))
+            .collect();
+        let confirmed_before: Vec<Txid> = wallet
+            .transactions()
+            .filter(|tx| tx.chain_position.is_confirmed())
+            .map(|tx| tx.tx_node.txid)
+            .collect();
+
+        let result = wallet.apply_update(update);
+        if result.is_ok() {
+            index_spks(self.meta_storage.as_ref(), self.address_type, &wallet);
+        }
+
+        let fork_height = blocks_before.iter().find_map(|(height, hash)| {
+            match wallet.local_chain().get(*height) {
+                Some(checkpoint) if checkpoint.hash() == *hash => None,
+                _ => Some(*height),
+            }
+        });
+        let reorg = fork_height.map(|fork_height| ReorgEvent {
+            address_type: self.address_type,
+            fork_height,
+            unconfirmed_tx_ids: confirmed_before
+                .into_iter()
+                .filter(|txid| {
+                    wallet
+                        .get_tx(*txid)
+                        .is_none_or(|tx| !tx.chain_position.is_confirmed())
+                })
+                .map(|txid| txid.to_string())
+                .collect(),
+        });
+        drop(wallet);
+
+        self.refresh();
+        result.map(|_| reorg.filter(|reorg| !reorg.unconfirmed_tx_ids.is_empty()))
+    }
+
+    /// Height and hash of the latest block known to the wallet.
+    pub fn chain_tip(&self) -> (u32, BlockHash) {
+        let tip = self.bdk_wallet.lock_or_recover().latest_checkpoint();
+        (tip.height(), tip.hash())
+    }
+
     // Inserts a transaction into the wallet and updates the `seen_at` timestamp.
     // After broadcasting a transaction, the wallet must wait for a sync to display it.
     // This function is used to insert the transaction immediately for UI updates.
@@ -470,18 +817,36 @@ impl<P: WalletPersister> NgWallet<P> {
         tx_update.seen_ats = [(tx_id, seen_at)].into();
         {
             self.bdk_wallet
-                .lock()
-                .expect("Failed to lock bdk_wallet")
+                .lock_or_recover()
                 .apply_update(Update {
                     tx_update,
                     ..Default::default()
                 })
                 .expect("failed to apply update");
         }
+        self.refresh();
     }
 
     pub fn utxos(&self) -> Result<Vec<Output>> {
-        let wallet = self.bdk_wallet.lock().expect("Failed to lock bdk_wallet");
+        let generation = {
+            let cache = self.lock_query_cache();
+            if let Some(utxos) = &cache.utxos {
+                return Ok(utxos.clone());
+            }
+            cache.generation
+        };
+
+        let utxos = self.load_utxos()?;
+
+        let mut cache = self.lock_query_cache();
+        if cache.generation == generation {
+            cache.utxos = Some(utxos.clone());
+        }
+        Ok(utxos)
+    }
+
+    fn load_utxos(&self) -> Result<Vec<Output>> {
+        let wallet = self.bdk_wallet.lock_or_err()?;
         let mut unspents: Vec<Output> = vec![];
         let tip_height = wallet.latest_checkpoint().height();
 
@@ -494,7 +859,7 @@ impl<P: WalletPersister> NgWallet<P> {
             );
             let wallet_tx = wallet.get_tx(local_output.outpoint.txid);
             let mut confirmations = 0;
-            match wallet_tx {
+            match &wallet_tx {
                 None => {}
                 Some(wallet_tx) => {
                     match wallet_tx.chain_position {
@@ -525,9 +890,21 @@ impl<P: WalletPersister> NgWallet<P> {
                 }
             }
 
+            let mut tag = meta_storage
+                .get_tag(out_put_id.clone().as_str())
+                .unwrap_or(None);
+            if let Some(wallet_tx) = &wallet_tx {
+                tag = coinjoin_tag(
+                    meta_storage.as_ref(),
+                    wallet_tx.tx_node.tx.as_ref(),
+                    local_output.outpoint.vout as usize,
+                    tag,
+                );
+            }
             let do_not_spend = meta_storage
                 .get_do_not_spend(out_put_id.as_str())
-                .unwrap_or(false);
+                .unwrap_or(false)
+                || is_tag_do_not_spend(meta_storage.as_ref(), tag.as_deref());
 
             unspents.push(Output {
                 tx_id: local_output.outpoint.txid.to_string(),
@@ -545,9 +922,7 @@ impl<P: WalletPersister> NgWallet<P> {
                             KeyChain::Internal
                         }
                     }),
-                tag: meta_storage
-                    .get_tag(out_put_id.clone().as_str())
-                    .unwrap_or(None),
+                tag,
                 do_not_spend,
                 date,
                 is_confirmed: confirmations >= 1,
@@ -558,7 +933,7 @@ impl<P: WalletPersister> NgWallet<P> {
 
     //check if the wallet got signers,
     pub fn is_hot(&self) -> bool {
-        let wallet = self.bdk_wallet.lock().unwrap();
+        let wallet = self.bdk_wallet.lock_or_recover();
         !wallet
             .get_signers(KeychainKind::Internal)
             .signers()
@@ -572,29 +947,68 @@ impl<P: WalletPersister> NgWallet<P> {
     pub fn sign(&self, psbt: &str) -> Result<String> {
         let mut psbt = Psbt::from_str(psbt)?;
         self.bdk_wallet
-            .lock()
-            .unwrap()
+            .lock_or_err()?
             .sign(&mut psbt, SignOptions::default())?;
         Ok(psbt.serialize_hex())
     }
     pub fn sent_and_received(&self, tx: &Transaction) -> (Amount, Amount) {
-        self.bdk_wallet.lock().unwrap().sent_and_received(tx)
+        self.bdk_wallet.lock_or_recover().sent_and_received(tx)
     }
 
     pub fn sign_psbt(&self, psbt: &mut Psbt, options: SignOptions) -> Result<()> {
-        self.bdk_wallet.lock().unwrap().sign(psbt, options)?;
+        self.bdk_wallet.lock_or_err()?.sign(psbt, options)?;
+        Ok(())
+    }
+
+    /// Forget the private keys of the wallet, it signs nothing until
+    /// [`Self::load_signers`].
+    pub(crate) fn drop_signers(&self) -> Result<()> {
+        let mut wallet = self.bdk_wallet.lock_or_err()?;
+        wallet.set_keymap(KeychainKind::External, KeyMap::new());
+        wallet.set_keymap(KeychainKind::Internal, KeyMap::new());
+        Ok(())
+    }
+
+    /// Load the private keys of `internal_descriptor` and
+    /// `external_descriptor`, the descriptors the wallet was opened with.
+    /// Public descriptors load no key.
+    pub(crate) fn load_signers(
+        &self,
+        internal_descriptor: &str,
+        external_descriptor: Option<&str>,
+    ) -> Result<()> {
+        let descriptors = match external_descriptor {
+            Some(external) => vec![
+                (KeychainKind::Internal, internal_descriptor.to_string()),
+                (KeychainKind::External, external.to_string()),
+            ],
+            None => match utils::split_multipath_descriptor(internal_descriptor)? {
+                Some((external, internal)) => vec![
+                    (KeychainKind::Internal, internal),
+                    (KeychainKind::External, external),
+                ],
+                None => vec![(KeychainKind::Internal, internal_descriptor.to_string())],
+            },
+        };
+        let secp = Secp256k1::new();
+        let mut wallet = self.bdk_wallet.lock_or_err()?;
+        for (keychain, descriptor) in descriptors {
+            let (_, keymap) = ExtendedDescriptor::parse_descriptor(&secp, &descriptor)?;
+            wallet.set_keymap(keychain, keymap);
+        }
         Ok(())
     }
 
     pub fn cancel_tx(&self, tx: &Transaction) -> Result<()> {
-        self.bdk_wallet.lock().unwrap().cancel_tx(tx);
+        self.bdk_wallet.lock_or_err()?.cancel_tx(tx);
+        self.refresh();
         Ok(())
     }
 
     pub fn parse_psbt(&self, psbt_str: &str) -> Result<PsbtInfo> {
         let psbt = Psbt::from_str(psbt_str)?;
         let tx = psbt.unsigned_tx;
-        let wallet = self.bdk_wallet.lock().unwrap();
+        let wallet = self.bdk_wallet.lock_or_err()?;
         let mut outputs = Vec::new();
         let mut fee = 0;
 
@@ -636,8 +1050,7 @@ impl<P: WalletPersister> NgWallet<P> {
     pub fn get_all_xfps(&self) -> Vec<String> {
         let mut xfps: Vec<String> = Vec::new();
         self.bdk_wallet
-            .lock()
-            .unwrap()
+            .lock_or_recover()
             .public_descriptor(KeychainKind::Internal)
             .for_each_key(|key| {
                 xfps.push(key.master_fingerprint().to_string().to_uppercase());
@@ -655,14 +1068,46 @@ impl<P: WalletPersister> NgWallet<P> {
     pub fn reveal_addresses_up_to(&mut self, keychain: KeychainKind, index: u32) -> Result<()> {
         let _ = self
             .bdk_wallet
-            .lock()
-            .unwrap()
+            .lock_or_err()?
             .reveal_addresses_to(keychain, index);
         self.persist().unwrap();
         Ok(())
     }
 }
 
+// The index only speeds up lookups, which fall back to the wallets, so a
+// failure to update it doesn't fail the caller.
+fn index_spks(meta_storage: &dyn MetaStorage, address_type: AddressType, wallet: &Wallet) {
+    if let Err(e) = spk_index::index_revealed(meta_storage, address_type, wallet) {
+        log::info!("Could not index the revealed scripts: {e:?}");
+    }
+}
+
+// Outputs inherit the do-not-spend policy of their tag.
+fn is_tag_do_not_spend(meta_storage: &dyn MetaStorage, tag: Option<&str>) -> bool {
+    match tag {
+        Some(tag) if !tag.is_empty() => meta_storage.get_tag_do_not_spend(tag).unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Untagged mixed outputs of a likely coinjoin get the coinjoin tag, so coin
+// selection can keep them apart.
+fn coinjoin_tag(
+    meta_storage: &dyn MetaStorage,
+    tx: &Transaction,
+    vout: usize,
+    tag: Option<String>,
+) -> Option<String> {
+    if tag.is_some() || !transaction::is_mixed_output(tx, vout) {
+        return tag;
+    }
+    let _ = meta_storage
+        .set_tag(&format!("{}:{vout}", tx.compute_txid()), COINJOIN_TAG)
+        .ok();
+    Some(COINJOIN_TAG.to_string())
+}
+
 fn script_type(script: &bdk_wallet::bitcoin::Script) -> &'static str {
     if script.is_op_return() {
         "op_return"
@@ -0,0 +1,142 @@
+//! Address ownership proofs for auditors.
+//!
+//! Exchanges and auditors ask users to show they control an address before
+//! a withdrawal or during an audit. An [`OwnershipProof`] signs a statement
+//! with the key of each address, the way [`NgAccount::sign_message`] does,
+//! and packages the signatures with the derivation of every address so the
+//! auditor can also check it against the account xpubs.
+
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::bitcoin::bip32::{ChildNumber, DerivationPath};
+use bdk_wallet::bitcoin::{Address, Network, Script};
+use bdk_wallet::miniscript::ForEachKey;
+use bdk_wallet::miniscript::descriptor::{DescriptorPublicKey, Wildcard};
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::config::AddressType;
+use crate::error::{MutexExt, RwLockExt};
+use crate::sign_message;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyOrigin {
+    /// Master key fingerprint, uppercase like in the xpub exports.
+    pub fingerprint: String,
+    /// Path of the address key, like `m/84'/0'/0'/0/5`.
+    pub derivation_path: String,
+    /// The xpub the address key derives from.
+    pub xpub: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressProof {
+    pub address: String,
+    pub address_type: AddressType,
+    pub keychain: KeychainKind,
+    /// Legacy signature of P2PKH and P2WPKH addresses, BIP-322 otherwise.
+    pub signature: String,
+    /// One key per cosigner of a multisig address.
+    pub keys: Vec<KeyOrigin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnershipProof {
+    pub statement: String,
+    pub network: Network,
+    pub addresses: Vec<AddressProof>,
+}
+
+impl OwnershipProof {
+    /// Addresses whose signature of the statement doesn't verify. The
+    /// proof is valid if there are none.
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let mut invalid = vec![];
+        for proof in &self.addresses {
+            let address = Address::from_str(&proof.address)?.require_network(self.network)?;
+            if !sign_message::verify_message(&address, &self.statement, &proof.signature)? {
+                invalid.push(proof.address.clone());
+            }
+        }
+        Ok(invalid)
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Sign `statement` with the key of each of `addresses`, which must all
+    /// belong to the account.
+    pub fn prove_address_ownership(
+        &self,
+        addresses: &[String],
+        statement: &str,
+    ) -> Result<OwnershipProof> {
+        if addresses.is_empty() {
+            bail!("No addresses to prove the ownership of");
+        }
+        let network = self.config.read_or_err()?.network;
+
+        let mut proofs = vec![];
+        for address in addresses {
+            let signed = self.sign_message(address, statement)?;
+            let script = Address::from_str(&signed.address)?
+                .require_network(network)?
+                .script_pubkey();
+            let (address_type, keychain, keys) = self
+                .key_origins(&script)?
+                .ok_or_else(|| anyhow!("Address {address} does not belong to this account"))?;
+            proofs.push(AddressProof {
+                address: signed.address,
+                address_type,
+                keychain,
+                signature: signed.signature,
+                keys,
+            });
+        }
+
+        Ok(OwnershipProof {
+            statement: statement.to_string(),
+            network,
+            addresses: proofs,
+        })
+    }
+
+    // Keys of the address locked by `script`, if it belongs to the account.
+    fn key_origins(
+        &self,
+        script: &Script,
+    ) -> Result<Option<(AddressType, KeychainKind, Vec<KeyOrigin>)>> {
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let Some((keychain, index)) = bdk_wallet.derivation_of_spk(script.to_owned()) else {
+                continue;
+            };
+
+            let mut keys = vec![];
+            bdk_wallet.public_descriptor(keychain).for_each_key(|key| {
+                let DescriptorPublicKey::XPub(xkey) = key else {
+                    return true;
+                };
+                let (fingerprint, origin) = match &xkey.origin {
+                    Some((fingerprint, path)) => (*fingerprint, path.clone()),
+                    None => (xkey.xkey.fingerprint(), DerivationPath::master()),
+                };
+                let path = origin.extend(&xkey.derivation_path);
+                let path = match xkey.wildcard {
+                    Wildcard::None => path,
+                    Wildcard::Unhardened => path.child(ChildNumber::Normal { index }),
+                    Wildcard::Hardened => path.child(ChildNumber::Hardened { index }),
+                };
+                keys.push(KeyOrigin {
+                    fingerprint: fingerprint.to_string().to_uppercase(),
+                    derivation_path: format!("m/{path}"),
+                    xpub: xkey.xkey.to_string(),
+                });
+                true
+            });
+            return Ok(Some((wallet.address_type, keychain, keys)));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,295 @@
+//! Fee packages of unconfirmed transactions.
+//!
+//! Miners select an unconfirmed transaction together with its unconfirmed
+//! ancestors, so a child paying a high fee stays stuck behind a parent
+//! paying too little. [`FeePackage`] gives the UI what it needs to explain
+//! this: the ancestors and descendants of a transaction and the fee rate
+//! of each group.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fee_rate::FeeRateSatPerKvb;
+use crate::ngwallet::FEE_UNKNOWN;
+use crate::transaction::BitcoinTransaction;
+#[cfg(feature = "envoy")]
+use {
+    crate::account::NgAccount,
+    crate::utils,
+    anyhow::{Result, anyhow, bail},
+    bdk_electrum::electrum_client::ElectrumApi,
+    bdk_wallet::WalletPersister,
+    bdk_wallet::bitcoin::Txid,
+    std::str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeePackage {
+    /// Unconfirmed ancestors, not counting the transaction itself.
+    pub ancestor_count: u32,
+    /// Fee rate of the transaction and its unconfirmed ancestors, the one
+    /// miners select it by.
+    pub ancestor_fee_rate: FeeRateSatPerKvb,
+    /// Unconfirmed descendants, not counting the transaction itself.
+    pub descendant_count: u32,
+    /// Fee rate of the transaction and its unconfirmed descendants.
+    pub descendant_fee_rate: FeeRateSatPerKvb,
+}
+
+// Fee and vsize of a set of transactions.
+#[derive(Default)]
+struct Totals {
+    count: u32,
+    fee: u64,
+    vsize: u64,
+}
+
+impl Totals {
+    fn add(&mut self, fee: u64, vsize: u64) {
+        self.count += 1;
+        self.fee += fee;
+        self.vsize += vsize;
+    }
+
+    fn fee_rate(&self) -> FeeRateSatPerKvb {
+        FeeRateSatPerKvb(match self.vsize {
+            0 => 0,
+            vsize => self.fee * 1000 / vsize,
+        })
+    }
+}
+
+/// Set the package of every unconfirmed transaction in `transactions` from
+/// the unconfirmed transactions in the list. Packages including a
+/// transaction of unknown fee are left out.
+pub(crate) fn set_packages(transactions: &mut [BitcoinTransaction]) {
+    let unconfirmed: BTreeMap<&str, &BitcoinTransaction> = transactions
+        .iter()
+        .filter(|tx| !tx.is_confirmed)
+        .map(|tx| (tx.tx_id.as_str(), tx))
+        .collect();
+    let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for tx in unconfirmed.values() {
+        for input in &tx.inputs {
+            if let Some((parent, _)) = unconfirmed.get_key_value(input.tx_id.as_str()) {
+                children.entry(parent).or_default().push(&tx.tx_id);
+            }
+        }
+    }
+
+    let packages: BTreeMap<String, FeePackage> = unconfirmed
+        .keys()
+        .filter_map(|tx_id| {
+            let ancestors = totals(&unconfirmed, &children, *tx_id, true)?;
+            let descendants = totals(&unconfirmed, &children, *tx_id, false)?;
+            let package = FeePackage {
+                ancestor_count: ancestors.count - 1,
+                ancestor_fee_rate: ancestors.fee_rate(),
+                descendant_count: descendants.count - 1,
+                descendant_fee_rate: descendants.fee_rate(),
+            };
+            Some((tx_id.to_string(), package))
+        })
+        .collect();
+
+    for tx in transactions {
+        tx.package = packages.get(&tx.tx_id).copied();
+    }
+}
+
+// Totals of `tx_id` with its unconfirmed ancestors, or descendants.
+fn totals<'a>(
+    unconfirmed: &BTreeMap<&'a str, &'a BitcoinTransaction>,
+    children: &BTreeMap<&'a str, Vec<&'a str>>,
+    tx_id: &'a str,
+    ancestors: bool,
+) -> Option<Totals> {
+    let mut totals = Totals::default();
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![tx_id];
+    while let Some(tx_id) = pending.pop() {
+        if !seen.insert(tx_id) {
+            continue;
+        }
+        let tx = unconfirmed[tx_id];
+        if tx.fee == FEE_UNKNOWN {
+            return None;
+        }
+        totals.add(tx.fee, tx.vsize as u64);
+        match ancestors {
+            true => pending.extend(
+                tx.inputs
+                    .iter()
+                    .filter_map(|input| unconfirmed.get_key_value(input.tx_id.as_str()))
+                    .map(|(parent, _)| *parent),
+            ),
+            false => pending.extend(children.get(tx_id).into_iter().flatten()),
+        }
+    }
+    Some(totals)
+}
+
+#[cfg(feature = "envoy")]
+impl<P: WalletPersister> NgAccount<P> {
+    /// Package of the unconfirmed transaction `tx_id`, looking up in the
+    /// mempool of an Electrum server the fees the wallet doesn't know and
+    /// the ancestors it doesn't have, such as the parents of an incoming
+    /// payment.
+    pub fn mempool_package(
+        &self,
+        tx_id: &str,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> Result<FeePackage> {
+        let transactions = self.transactions()?;
+        let client = utils::build_electrum_client(electrum_server, socks_proxy, validate_domain)?;
+
+        // fee, vsize and parents of an unconfirmed transaction, None once
+        // confirmed
+        let lookup = |txid: Txid| -> Result<Option<(u64, u64, Vec<Txid>)>> {
+            let known = transactions.iter().find(|tx| tx.tx_id == txid.to_string());
+            if let Some(tx) = known {
+                if tx.is_confirmed {
+                    return Ok(None);
+                }
+                if tx.fee != FEE_UNKNOWN {
+                    let parents = tx
+                        .inputs
+                        .iter()
+                        .map(|input| Txid::from_str(&input.tx_id))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    return Ok(Some((tx.fee, tx.vsize as u64, parents)));
+                }
+            }
+            let tx = client.inner.transaction_get(&txid)?;
+            let output = tx
+                .output
+                .first()
+                .ok_or_else(|| anyhow!("Transaction {txid} has no outputs"))?;
+            let history = client.inner.script_get_history(&output.script_pubkey)?;
+            let entry = history
+                .iter()
+                .find(|entry| entry.tx_hash == txid)
+                .ok_or_else(|| anyhow!("Transaction {txid} not found on the server"))?;
+            if entry.height > 0 {
+                return Ok(None);
+            }
+            let fee = entry
+                .fee
+                .ok_or_else(|| anyhow!("Unknown fee of transaction {txid}"))?;
+            // a height of -1 marks mempool transactions with unconfirmed
+            // parents
+            let parents = match entry.height {
+                0 => vec![],
+                _ => tx
+                    .input
+                    .iter()
+                    .map(|input| input.previous_output.txid)
+                    .collect(),
+            };
+            Ok(Some((fee, tx.vsize() as u64, parents)))
+        };
+
+        let txid = Txid::from_str(tx_id)?;
+        let mut children: BTreeMap<Txid, Vec<Txid>> = BTreeMap::new();
+        for tx in transactions.iter().filter(|tx| !tx.is_confirmed) {
+            for input in &tx.inputs {
+                children
+                    .entry(Txid::from_str(&input.tx_id)?)
+                    .or_default()
+                    .push(Txid::from_str(&tx.tx_id)?);
+            }
+        }
+
+        let mut ancestors = Totals::default();
+        let mut descendants = Totals::default();
+        for (totals, up) in [(&mut ancestors, true), (&mut descendants, false)] {
+            let mut seen = BTreeSet::new();
+            let mut pending = vec![txid];
+            while let Some(txid) = pending.pop() {
+                if !seen.insert(txid) {
+                    continue;
+                }
+                let Some((fee, vsize, parents)) = lookup(txid)? else {
+                    continue;
+                };
+                totals.add(fee, vsize);
+                match up {
+                    true => pending.extend(parents),
+                    false => pending.extend(children.get(&txid).into_iter().flatten()),
+                }
+            }
+        }
+        if ancestors.count == 0 {
+            bail!("Transaction {tx_id} is confirmed");
+        }
+
+        Ok(FeePackage {
+            ancestor_count: ancestors.count - 1,
+            ancestor_fee_rate: ancestors.fee_rate(),
+            descendant_count: descendants.count - 1,
+            descendant_fee_rate: descendants.fee_rate(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::fixtures;
+
+    fn tx(tx_id: &str, parents: &[&str], fee: u64, vsize: usize) -> BitcoinTransaction {
+        BitcoinTransaction {
+            block_height: 0,
+            confirmations: 0,
+            is_confirmed: false,
+            fee,
+            inputs: parents
+                .iter()
+                .map(|parent| fixtures::input(parent, 0))
+                .collect(),
+            vsize,
+            ..fixtures::tx(tx_id)
+        }
+    }
+
+    #[test]
+    fn low_fee_parent_holds_back_child() {
+        let mut confirmed = tx("confirmed", &[], 1_000, 100);
+        confirmed.is_confirmed = true;
+        let mut transactions = vec![
+            confirmed,
+            // 1 sat/vB parent
+            tx("parent", &["confirmed"], 200, 200),
+            // 20 sat/vB child
+            tx("child", &["parent"], 2_000, 100),
+            tx("unknown", &[], FEE_UNKNOWN, 100),
+            tx("unknown child", &["unknown"], 1_000, 100),
+        ];
+        set_packages(&mut transactions);
+
+        assert_eq!(transactions[0].package, None);
+        assert_eq!(
+            transactions[1].package,
+            Some(FeePackage {
+                ancestor_count: 0,
+                ancestor_fee_rate: FeeRateSatPerKvb(1_000),
+                descendant_count: 1,
+                descendant_fee_rate: FeeRateSatPerKvb(7_333),
+            })
+        );
+        assert_eq!(
+            transactions[2].package,
+            Some(FeePackage {
+                ancestor_count: 1,
+                ancestor_fee_rate: FeeRateSatPerKvb(7_333),
+                descendant_count: 0,
+                descendant_fee_rate: FeeRateSatPerKvb(20_000),
+            })
+        );
+        assert_eq!(transactions[3].package, None);
+        assert_eq!(transactions[4].package, None);
+    }
+}
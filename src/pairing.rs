@@ -0,0 +1,256 @@
+//! Passport <-> Envoy account pairing.
+//!
+//! Passport shows a [`PairingPayload`] as an animated QR code: the account
+//! name, color and master fingerprint, the public descriptors of every
+//! script type and the metadata sync key, CBOR encoded and split into UR
+//! parts. Envoy scans the parts back with [`PairingPayload::from_ur_parts`]
+//! and creates the watch-only account from
+//! [`PairingPayload::account_builder`].
+//!
+//! The payload holds a secret, the sync key, and must only be shown to the
+//! device being paired.
+
+use core::fmt;
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::bitcoin::Network;
+use foundation_ur::{Decoder, Encoder, UR};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AddressType;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+#[cfg(feature = "std")]
+use {
+    crate::account::{Descriptor, NgAccount},
+    crate::config::NgAccountBuilder,
+    crate::error::{MutexExt, RwLockExt},
+    bdk_wallet::bitcoin::NetworkKind,
+    bdk_wallet::bitcoin::secp256k1::Secp256k1,
+    bdk_wallet::descriptor::ExtendedDescriptor,
+    bdk_wallet::keys::DescriptorPublicKey,
+    bdk_wallet::miniscript::ForEachKey,
+    bdk_wallet::{KeychainKind, WalletPersister},
+    std::sync::{Arc, Mutex},
+};
+
+/// UR type of the pairing payload.
+pub const UR_TYPE: &str = "passport-pairing";
+
+/// Version of the payload format, bumped on incompatible changes.
+pub const PAIRING_VERSION: u8 = 2;
+
+/// Public descriptors of one script type, like an
+/// [`crate::config::NgDescriptor`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingDescriptor {
+    pub address_type: AddressType,
+    /// The change descriptor, or the only descriptor of the wallet.
+    pub internal: String,
+    pub external: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingPayload {
+    pub version: u8,
+    pub name: String,
+    pub color: String,
+    /// Master key fingerprint, uppercase.
+    pub fingerprint: String,
+    pub network: Network,
+    /// Account index of the descriptors.
+    pub index: u32,
+    pub preferred_address_type: AddressType,
+    pub descriptors: Vec<PairingDescriptor>,
+    /// Key the [`crate::metadata_sync`] payloads of the paired accounts are
+    /// signed with.
+    pub sync_key: [u8; 32],
+}
+
+impl fmt::Debug for PairingPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairingPayload")
+            .field("version", &self.version)
+            .field("name", &self.name)
+            .field("color", &self.color)
+            .field("fingerprint", &self.fingerprint)
+            .field("network", &self.network)
+            .field("index", &self.index)
+            .field("preferred_address_type", &self.preferred_address_type)
+            .field("descriptors", &self.descriptors)
+            .field("sync_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl PairingPayload {
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        minicbor_serde::to_vec(self).map_err(|e| anyhow!("Could not encode pairing payload: {e}"))
+    }
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        let payload: Self = minicbor_serde::from_slice(bytes)
+            .map_err(|e| anyhow!("Invalid pairing payload: {e}"))?;
+        if payload.version != PAIRING_VERSION {
+            bail!("Unsupported pairing payload version {}", payload.version);
+        }
+        Ok(payload)
+    }
+
+    /// The CBOR payload split into UR parts of at most
+    /// `max_fragment_length` bytes, to be shown one after the other.
+    pub fn to_ur_parts(&self, max_fragment_length: usize) -> Result<Vec<String>> {
+        if max_fragment_length == 0 {
+            bail!("Fragment length must be positive");
+        }
+        let cbor = self.to_cbor()?;
+        let mut encoder = Encoder::new();
+        encoder.start(UR_TYPE, &cbor, max_fragment_length);
+        Ok((0..encoder.sequence_count())
+            .map(|_| encoder.next_part().to_string())
+            .collect())
+    }
+
+    /// Decode the payload from scanned UR `parts`, in any order. Fails if
+    /// more parts are needed.
+    pub fn from_ur_parts<S: AsRef<str>>(parts: &[S]) -> Result<Self> {
+        let mut decoder = Decoder::default();
+        for part in parts {
+            let ur = UR::parse(part.as_ref()).map_err(|e| anyhow!("Invalid UR: {e}"))?;
+            if ur.as_type() != UR_TYPE {
+                bail!("Expected a {UR_TYPE} UR, got {}", ur.as_type());
+            }
+            decoder
+                .receive(ur)
+                .map_err(|e| anyhow!("Invalid UR part: {e}"))?;
+            if decoder.is_complete() {
+                break;
+            }
+        }
+        let message = decoder
+            .message()
+            .map_err(|e| anyhow!("Invalid pairing payload: {e}"))?
+            .ok_or_else(|| anyhow!("More parts of the pairing payload are needed"))?;
+        Self::from_cbor(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PairingPayload {
+    /// Builder of the watch-only account of the payload. `persisters` hold
+    /// the wallets, in the order of the descriptors.
+    ///
+    /// The descriptors must be public, on the network of the payload and
+    /// derive from its master fingerprint.
+    pub fn account_builder<P: WalletPersister>(
+        self,
+        id: String,
+        persisters: Vec<Arc<Mutex<P>>>,
+    ) -> Result<NgAccountBuilder<P>> {
+        if self.descriptors.is_empty() {
+            bail!("Pairing payload has no descriptors");
+        }
+        if self.descriptors.len() != persisters.len() {
+            bail!(
+                "Expected {} persisters, got {}",
+                self.descriptors.len(),
+                persisters.len()
+            );
+        }
+
+        let secp = Secp256k1::new();
+        let network = NetworkKind::from(self.network);
+        let mut descriptors = vec![];
+        for (descriptor, persister) in self.descriptors.into_iter().zip(persisters) {
+            for string in descriptor.external.iter().chain([&descriptor.internal]) {
+                let (parsed, keymap) = ExtendedDescriptor::parse_descriptor(&secp, string)?;
+                if !keymap.is_empty() {
+                    bail!("Pairing descriptors must be public");
+                }
+                let matches = parsed.for_each_key(|key| {
+                    let on_network = match key {
+                        DescriptorPublicKey::XPub(xkey) => xkey.xkey.network == network,
+                        DescriptorPublicKey::MultiXPub(xkey) => xkey.xkey.network == network,
+                        DescriptorPublicKey::Single(_) => true,
+                    };
+                    on_network
+                        && key.master_fingerprint().to_string().to_uppercase() == self.fingerprint
+                });
+                if !matches {
+                    bail!(
+                        "Descriptor is not of account {} on {}",
+                        self.fingerprint,
+                        self.network
+                    );
+                }
+            }
+            descriptors.push(Descriptor {
+                internal: descriptor.internal,
+                external: descriptor.external,
+                bdk_persister: persister,
+            });
+        }
+
+        Ok(NgAccountBuilder::default()
+            .id(id)
+            .name(self.name)
+            .color(self.color)
+            .network(self.network)
+            .preferred_address_type(self.preferred_address_type)
+            .index(self.index)
+            .descriptors(descriptors)
+            .metadata_sync_key(self.sync_key))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: WalletPersister> NgAccount<P> {
+    /// Pairing payload of the account, with the public descriptors of each
+    /// of its wallets and the metadata sync key, made on the first pairing.
+    /// Multisig accounts are paired with their config file instead.
+    pub fn pairing_payload(&self) -> Result<PairingPayload> {
+        let config = self.config.read_or_err()?.clone();
+        if config.multisig.is_some() || config.vault.is_some() {
+            bail!("Only single signature accounts are paired");
+        }
+
+        let mut descriptors = vec![];
+        for wallet in self.all_wallets()?.iter() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let descriptor = match bdk_wallet.keychains().count() {
+                1 => PairingDescriptor {
+                    address_type: wallet.address_type,
+                    internal: bdk_wallet
+                        .public_descriptor(KeychainKind::External)
+                        .to_string(),
+                    external: None,
+                },
+                _ => PairingDescriptor {
+                    address_type: wallet.address_type,
+                    internal: bdk_wallet
+                        .public_descriptor(KeychainKind::Internal)
+                        .to_string(),
+                    external: Some(
+                        bdk_wallet
+                            .public_descriptor(KeychainKind::External)
+                            .to_string(),
+                    ),
+                },
+            };
+            descriptors.push(descriptor);
+        }
+
+        Ok(PairingPayload {
+            version: PAIRING_VERSION,
+            name: config.name,
+            color: config.color,
+            fingerprint: self.get_xfp(),
+            network: config.network,
+            index: config.index,
+            preferred_address_type: config.preferred_address_type,
+            descriptors,
+            sync_key: self.metadata_sync_key_or_new()?,
+        })
+    }
+}
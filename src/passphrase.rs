@@ -0,0 +1,156 @@
+//! Passphrase wallets.
+//!
+//! Each BIP-39 passphrase turns a mnemonic into a different wallet.
+//! [`NgAccount::with_passphrase`] prepares the account the same mnemonic
+//! gives with another passphrase, and the [`seed_id`] recorded in both
+//! configs tells such siblings apart from unrelated accounts.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::bip32::{Fingerprint, Xpriv};
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use bdk_wallet::bitcoin::hex::DisplayHex;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use bdk_wallet::keys::bip39::Mnemonic;
+use bdk_wallet::miniscript::{DescriptorPublicKey, ForEachKey};
+use zeroize::Zeroizing;
+
+use crate::account::{Descriptor, NgAccount};
+use crate::bip39::get_descriptors_from_xprv;
+use crate::config::{NgAccountBuilder, NgAccountConfig};
+use crate::error::RwLockExt;
+
+/// Identifier of `mnemonic` shared by all its passphrases: a keyed hash of
+/// its entropy, which doesn't reveal the wallets it derives.
+pub fn seed_id(mnemonic: &str) -> Result<String> {
+    let mnemonic = Mnemonic::from_str(mnemonic)?;
+    let (entropy, len) = mnemonic.to_entropy_array();
+    let entropy = Zeroizing::new(entropy);
+    let mut engine = HmacEngine::<sha256::Hash>::new(b"ngwallet seed id");
+    engine.input(&entropy[..len]);
+    let hash = Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+    Ok(hash[..8].to_lower_hex_string())
+}
+
+impl NgAccountConfig {
+    /// True if `other` comes from the same mnemonic with a different
+    /// passphrase.
+    pub fn is_passphrase_sibling(&self, other: &NgAccountConfig) -> bool {
+        self.seed_id.is_some()
+            && self.seed_id == other.seed_id
+            && self.master_fingerprint() != other.master_fingerprint()
+    }
+
+    // Fingerprint of the master key of the first descriptor.
+    fn master_fingerprint(&self) -> Option<Fingerprint> {
+        let descriptor = self.descriptors.first()?;
+        let secp = Secp256k1::new();
+        let (descriptor, _) =
+            ExtendedDescriptor::parse_descriptor(&secp, &descriptor.internal).ok()?;
+        let mut fingerprint = None;
+        descriptor.for_each_key(|key: &DescriptorPublicKey| {
+            fingerprint = Some(key.master_fingerprint());
+            false
+        });
+        fingerprint
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Builder of the account `mnemonic` gives with `passphrase`: the same
+    /// script types and account index, private descriptors if this account
+    /// has them, and an id suffixed with the new master fingerprint.
+    /// `persisters` hold the new wallets, in the order of the descriptors of
+    /// this account.
+    ///
+    /// An account without a seed id adopts the one of `mnemonic`, otherwise
+    /// the mnemonic must match it.
+    pub fn with_passphrase(
+        &self,
+        mnemonic: &str,
+        passphrase: &str,
+        persisters: Vec<Arc<Mutex<P>>>,
+    ) -> Result<NgAccountBuilder<P>> {
+        let seed_id = seed_id(mnemonic)?;
+        let config = {
+            let mut config = self.config.write_or_err()?;
+            match &config.seed_id {
+                Some(id) if *id != seed_id => bail!("Mnemonic doesn't match this account"),
+                Some(_) => {}
+                None => config.seed_id = Some(seed_id.clone()),
+            }
+            config.clone()
+        };
+        self.persist()?;
+
+        if config.multisig.is_some() || config.vault.is_some() {
+            bail!("Only single signature accounts have passphrase wallets");
+        }
+        if config.descriptors.len() != persisters.len() {
+            bail!(
+                "Expected {} persisters, got {}",
+                config.descriptors.len(),
+                persisters.len()
+            );
+        }
+
+        let seed = Zeroizing::new(Mnemonic::from_str(mnemonic)?.to_seed(passphrase));
+        let xprv = Xpriv::new_master(config.network, seed.as_slice())?;
+        let fingerprint = xprv.fingerprint(&Secp256k1::new());
+        let descriptors = descriptors_like(&config, xprv, persisters)?;
+
+        Ok(NgAccountBuilder::default()
+            .id(format!("{}-{fingerprint}", config.id))
+            .name(config.name)
+            .color(config.color)
+            .device_serial(config.device_serial)
+            .network(config.network)
+            .preferred_address_type(config.preferred_address_type)
+            .index(config.index)
+            .seed_has_passphrase(true)
+            .seed_id(Some(seed_id))
+            .descriptors(descriptors))
+    }
+}
+
+/// Descriptors of `xprv` with the script types and account index of
+/// `config`, private if its descriptors are. `persisters` hold the wallets,
+/// in the order of the descriptors of `config`.
+pub(crate) fn descriptors_like<P: WalletPersister>(
+    config: &NgAccountConfig,
+    xprv: Xpriv,
+    persisters: Vec<Arc<Mutex<P>>>,
+) -> Result<Vec<Descriptor<P>>> {
+    let templates = get_descriptors_from_xprv(xprv, config.network, config.index)?;
+    let private = config.has_private_descriptors();
+
+    let secp = Secp256k1::new();
+    let mut descriptors = vec![];
+    for (descriptor, persister) in config.descriptors.iter().zip(persisters) {
+        let (parsed, _) = ExtendedDescriptor::parse_descriptor(&secp, &descriptor.internal)?;
+        let template = templates
+            .iter()
+            .find(|template| template.descriptor_type == parsed.desc_type())
+            .ok_or_else(|| anyhow!("No descriptor for {:?}", parsed.desc_type()))?;
+        let (internal, external) = match private {
+            true => (
+                template.change_descriptor_xprv().into_string(),
+                template.descriptor_xprv().into_string(),
+            ),
+            false => (
+                template.change_descriptor_xpub(),
+                template.descriptor_xpub(),
+            ),
+        };
+        descriptors.push(Descriptor {
+            internal,
+            external: (descriptor.external.is_some() || parsed.is_multipath()).then_some(external),
+            bdk_persister: persister,
+        });
+    }
+    Ok(descriptors)
+}
@@ -0,0 +1,500 @@
+//! Spending policies.
+//!
+//! Treasury style deployments pin the destinations an account may pay in a
+//! [`SignedWhitelist`]: addresses and xpubs (as descriptors) signed by an
+//! authority address with a legacy or BIP-322 message signature.
+//! [`NgAccount::sign_whitelisted`] refuses to sign PSBTs paying anything
+//! else.
+//!
+//! A [`SpendingPolicy`] in the account config limits the amounts sent, it
+//! is checked by [`NgAccount::compose_psbt`], which leaves the drafts above
+//! the co-signing threshold unsigned, and before signing by
+//! [`NgAccount::sign`], [`NgAccount::sign_with_signers`] and the fee
+//! bumps.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use bdk_wallet::bitcoin::bip32::Fingerprint;
+use bdk_wallet::bitcoin::key::XOnlyPublicKey;
+use bdk_wallet::bitcoin::psbt::Input;
+use bdk_wallet::bitcoin::script::Instruction;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::sighash::SighashCache;
+use bdk_wallet::bitcoin::{Address, Network, Psbt, Script, ScriptBuf, TapLeafHash, TxOut};
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use bdk_wallet::miniscript::psbt::PsbtExt;
+use bdk_wallet::signer::SignerId;
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::error::{MutexExt, PolicyError, PolicyViolation, RwLockExt};
+use crate::sign_message::verify_message;
+use crate::transaction::BitcoinTransaction;
+
+/// Seconds in the window of [`SpendingPolicy::max_per_day`].
+const DAY: u64 = 24 * 60 * 60;
+
+/// Addresses derived from each wildcard descriptor of a whitelist, on each
+/// of its keychains.
+pub const WHITELIST_LOOKAHEAD: u32 = 1000;
+
+/// A destination allowed by a [`SignedWhitelist`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum WhitelistEntry {
+    Address(String),
+    /// An xpub as a descriptor, single or multipath, allowing its first
+    /// [`WHITELIST_LOOKAHEAD`] addresses.
+    Descriptor(String),
+}
+
+impl WhitelistEntry {
+    fn script_pubkeys(&self, network: Network) -> Result<Vec<ScriptBuf>> {
+        match self {
+            WhitelistEntry::Address(address) => {
+                let address = Address::from_str(address)
+                    .with_context(|| format!("Invalid whitelisted address {address}"))?
+                    .require_network(network)
+                    .with_context(|| {
+                        format!("Whitelisted address {address} is for another network")
+                    })?;
+                Ok(vec![address.script_pubkey()])
+            }
+            WhitelistEntry::Descriptor(descriptor) => {
+                let descriptor = ExtendedDescriptor::from_str(descriptor)
+                    .with_context(|| "Invalid whitelisted descriptor")?;
+                let mut script_pubkeys = vec![];
+                for descriptor in descriptor.into_single_descriptors()? {
+                    let indexes = match descriptor.has_wildcard() {
+                        true => 0..WHITELIST_LOOKAHEAD,
+                        false => 0..1,
+                    };
+                    for index in indexes {
+                        script_pubkeys.push(descriptor.at_derivation_index(index)?.script_pubkey());
+                    }
+                }
+                Ok(script_pubkeys)
+            }
+        }
+    }
+}
+
+/// Destinations an account may pay, signed by `authority`.
+///
+/// The authority signs [`SignedWhitelist::message`]; a whitelist replaces
+/// the stored one only if it is signed by the same authority with a higher
+/// `version`, so an older list can't be replayed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedWhitelist {
+    pub authority: String,
+    pub version: u64,
+    pub entries: Vec<WhitelistEntry>,
+    /// Base64 message signature of the authority.
+    pub signature: String,
+}
+
+impl SignedWhitelist {
+    /// The message the authority signs for `version` of `entries`.
+    pub fn message_for(version: u64, entries: &[WhitelistEntry]) -> String {
+        let mut message = format!("ngwallet whitelist\nversion: {version}\n");
+        for entry in entries {
+            match entry {
+                WhitelistEntry::Address(address) => {
+                    message.push_str(&format!("address: {address}\n"))
+                }
+                WhitelistEntry::Descriptor(descriptor) => {
+                    message.push_str(&format!("descriptor: {descriptor}\n"))
+                }
+            }
+        }
+        message
+    }
+
+    pub fn message(&self) -> String {
+        Self::message_for(self.version, &self.entries)
+    }
+
+    /// Check the signature of the authority and that every entry is valid
+    /// for `network`.
+    pub fn verify(&self, network: Network) -> Result<()> {
+        let authority = Address::from_str(&self.authority)
+            .with_context(|| "Invalid whitelist authority")?
+            .require_network(network)
+            .with_context(|| "Whitelist authority is for another network")?;
+        if !verify_message(&authority, &self.message(), &self.signature)? {
+            return Err(PolicyError::InvalidWhitelistSignature.into());
+        }
+        self.script_pubkeys(network)?;
+        Ok(())
+    }
+
+    /// Script pubkeys of every whitelisted destination.
+    pub fn script_pubkeys(&self, network: Network) -> Result<HashSet<ScriptBuf>> {
+        let mut script_pubkeys = HashSet::new();
+        for entry in &self.entries {
+            script_pubkeys.extend(entry.script_pubkeys(network)?);
+        }
+        Ok(script_pubkeys)
+    }
+}
+
+/// Limits on the sats an account sends out, fees excluded. Transfers to
+/// the account itself and OP_RETURN outputs, which pay no one (as the
+/// output of a proof of reserves), are not limited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendingPolicy {
+    /// Most sats a single transaction may send.
+    #[serde(default)]
+    pub max_per_tx: Option<u64>,
+    /// Most sats sent in any 24 hours, unconfirmed transactions included.
+    #[serde(default)]
+    pub max_per_day: Option<u64>,
+    /// Transactions sending more sats are only signed after another signer
+    /// of their inputs did.
+    #[serde(default)]
+    pub cosign_above: Option<u64>,
+}
+
+impl SpendingPolicy {
+    /// Check the limits for a transaction sending `amount` sats after `spent`
+    /// sats were sent in the last 24 hours. The co-signing threshold is
+    /// checked when signing.
+    pub fn check(&self, amount: u64, spent: u64) -> Result<(), PolicyViolation> {
+        if let Some(limit) = self.max_per_tx
+            && amount > limit
+        {
+            return Err(PolicyViolation::MaxPerTransaction { amount, limit });
+        }
+        if let Some(limit) = self.max_per_day
+            && amount.saturating_add(spent) > limit
+        {
+            return Err(PolicyViolation::MaxPerDay {
+                amount,
+                spent,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn requires_cosigner(&self, amount: u64) -> bool {
+        self.cosign_above
+            .is_some_and(|threshold| amount > threshold)
+    }
+}
+
+/// Sats `history` sent out of the account since the unix timestamp `since`.
+/// Unconfirmed transactions without a date count too.
+pub fn spent_since(history: &[BitcoinTransaction], since: u64) -> u64 {
+    history
+        .iter()
+        .filter(|tx| tx.amount < 0 && tx.date.is_none_or(|date| date >= since))
+        .map(|tx| tx.amount.unsigned_abs().saturating_sub(tx.fee))
+        .sum()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn script_has_key(script: &Script, key: &[u8]) -> bool {
+    script.instructions().any(|instruction| {
+        matches!(instruction, Ok(Instruction::PushBytes(bytes)) if bytes.as_bytes() == key)
+    })
+}
+
+// The script of `input` the spent output commits to: the witness script of
+// P2WSH and P2SH-P2WSH outputs, the redeem script of P2SH outputs.
+fn committed_script<'a>(input: &'a Input, script_pubkey: &Script) -> Option<&'a ScriptBuf> {
+    if let Some(script) = &input.witness_script {
+        let p2wsh = ScriptBuf::new_p2wsh(&script.wscript_hash());
+        let nested = input.redeem_script.as_ref().is_some_and(|redeem| {
+            *redeem == p2wsh
+                && script_pubkey == ScriptBuf::new_p2sh(&redeem.script_hash()).as_script()
+        });
+        return (script_pubkey == p2wsh.as_script() || nested).then_some(script);
+    }
+    let redeem = input.redeem_script.as_ref()?;
+    (script_pubkey == ScriptBuf::new_p2sh(&redeem.script_hash()).as_script()).then_some(redeem)
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    pub fn spending_policy(&self) -> Option<SpendingPolicy> {
+        self.config.read_or_recover().spending_policy.clone()
+    }
+
+    pub fn set_spending_policy(&self, policy: Option<SpendingPolicy>) -> Result<()> {
+        self.config.write_or_err()?.spending_policy = policy;
+        self.persist()
+    }
+
+    /// Check the limits of the spending policy for a transaction sending
+    /// `amount` sats, `history` being the transactions of the account.
+    pub(crate) fn check_spending_limits(
+        &self,
+        amount: u64,
+        history: &[BitcoinTransaction],
+    ) -> Result<(), PolicyViolation> {
+        let Some(policy) = self.spending_policy() else {
+            return Ok(());
+        };
+        policy.check(amount, spent_since(history, now().saturating_sub(DAY)))
+    }
+
+    /// False if the spending policy needs a co-signer above `amount` sats
+    /// sent, the drafts of such transactions are left unsigned.
+    pub(crate) fn signs_alone(&self, amount: u64) -> bool {
+        !self
+            .spending_policy()
+            .is_some_and(|policy| policy.requires_cosigner(amount))
+    }
+
+    /// Checks shared by every entry point signing a PSBT: the account is
+    /// unlocked and the spending policy allows `psbt`.
+    pub(crate) fn check_can_sign(&self, psbt: &Psbt) -> Result<()> {
+        self.check_unlocked()?;
+        self.check_spending_policy(psbt)
+    }
+
+    /// Check the spending policy before signing `psbt`: its limits, and
+    /// that another signer signed first above the co-signing threshold.
+    fn check_spending_policy(&self, psbt: &Psbt) -> Result<()> {
+        let Some(policy) = self.spending_policy() else {
+            return Ok(());
+        };
+
+        let mut amount = 0;
+        for output in &psbt.unsigned_tx.output {
+            if !output.script_pubkey.is_op_return() && !self.is_mine(&output.script_pubkey)? {
+                amount += output.value.to_sat();
+            }
+        }
+
+        // a transaction signed again is already in the history, and so are
+        // the transactions a replacement spends the inputs of
+        let txid = psbt.unsigned_tx.compute_txid().to_string();
+        let spent: HashSet<(String, u32)> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| {
+                let outpoint = input.previous_output;
+                (outpoint.txid.to_string(), outpoint.vout)
+            })
+            .collect();
+        let history: Vec<_> = self
+            .transactions()?
+            .into_iter()
+            .filter(|tx| {
+                tx.tx_id != txid
+                    && !tx
+                        .inputs
+                        .iter()
+                        .any(|input| spent.contains(&(input.tx_id.clone(), input.vout)))
+            })
+            .collect();
+        policy.check(amount, spent_since(&history, now().saturating_sub(DAY)))?;
+
+        if policy.requires_cosigner(amount) && !self.is_cosigned(psbt)? {
+            return Err(PolicyViolation::CosignRequired {
+                amount,
+                threshold: policy.cosign_above.unwrap_or_default(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// True if an input of `psbt` carries a valid signature by a key of its
+    /// script that none of the signers of the account holds. The scripts of
+    /// the PSBT only count if the output spent, as the account knows it,
+    /// commits to them.
+    fn is_cosigned(&self, psbt: &Psbt) -> Result<bool> {
+        let mut ours: HashSet<Fingerprint> = HashSet::new();
+        for wallet in self.all_wallets()?.iter() {
+            let wallet = wallet.bdk_wallet.lock_or_err()?;
+            for keychain in [KeychainKind::External, KeychainKind::Internal] {
+                for id in wallet.get_signers(keychain).ids() {
+                    if let SignerId::Fingerprint(fingerprint) = id {
+                        ours.insert(*fingerprint);
+                    }
+                }
+            }
+        }
+
+        let secp = Secp256k1::verification_only();
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let Some(spent) = self.spent_output(psbt, index)? else {
+                continue;
+            };
+            if input
+                .witness_utxo
+                .as_ref()
+                .is_some_and(|utxo| *utxo != spent)
+            {
+                continue;
+            }
+            let script = committed_script(input, &spent.script_pubkey);
+            let output_key = match spent.script_pubkey.is_p2tr() {
+                true => XOnlyPublicKey::from_slice(&spent.script_pubkey.as_bytes()[2..]).ok(),
+                false => None,
+            };
+            for (key, signature) in &input.partial_sigs {
+                let is_ours = input
+                    .bip32_derivation
+                    .get(&key.inner)
+                    .is_some_and(|(fingerprint, _)| ours.contains(fingerprint));
+                if is_ours || !script.is_some_and(|script| script_has_key(script, &key.to_bytes()))
+                {
+                    continue;
+                }
+                let message = psbt.sighash_msg(index, &mut cache, None)?.to_secp_msg();
+                if secp
+                    .verify_ecdsa(&message, &signature.signature, &key.inner)
+                    .is_ok()
+                {
+                    return Ok(true);
+                }
+            }
+
+            for ((key, leaf_hash), signature) in &input.tap_script_sigs {
+                let is_ours = input
+                    .tap_key_origins
+                    .get(key)
+                    .is_some_and(|(_, (fingerprint, _))| ours.contains(fingerprint));
+                let in_leaf = input
+                    .tap_scripts
+                    .iter()
+                    .any(|(control_block, (script, version))| {
+                        TapLeafHash::from_script(script, *version) == *leaf_hash
+                            && *version == control_block.leaf_version
+                            && output_key.is_some_and(|output_key| {
+                                control_block.verify_taproot_commitment(&secp, output_key, script)
+                            })
+                            && script_has_key(script, &key.serialize())
+                    });
+                if is_ours || !in_leaf {
+                    continue;
+                }
+                let message = psbt
+                    .sighash_msg(index, &mut cache, Some(*leaf_hash))?
+                    .to_secp_msg();
+                if secp
+                    .verify_schnorr(&signature.signature, &message, key)
+                    .is_ok()
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // The output spent by input `index` of `psbt`, from the wallets, or from
+    // the previous transaction of the PSBT if it hashes to the outpoint.
+    fn spent_output(&self, psbt: &Psbt, index: usize) -> Result<Option<TxOut>> {
+        let outpoint = psbt.unsigned_tx.input[index].previous_output;
+        for wallet in self.all_wallets()?.iter() {
+            if let Some(txout) = wallet
+                .bdk_wallet
+                .lock_or_err()?
+                .tx_graph()
+                .get_txout(outpoint)
+            {
+                return Ok(Some(txout.clone()));
+            }
+        }
+        Ok(psbt.inputs[index]
+            .non_witness_utxo
+            .as_ref()
+            .filter(|tx| tx.compute_txid() == outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned()))
+    }
+
+    fn is_mine(&self, script: &Script) -> Result<bool> {
+        for wallet in self.all_wallets()?.iter() {
+            if wallet.bdk_wallet.lock_or_err()?.is_mine(script.to_owned()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Store `whitelist` after checking its signature. It must be signed by
+    /// the authority of the stored whitelist, if any, with a higher version.
+    pub fn set_whitelist(&self, whitelist: &SignedWhitelist) -> Result<()> {
+        let network = self.config.read_or_err()?.network;
+        whitelist.verify(network)?;
+        if let Some(current) = self.whitelist()? {
+            if current.authority != whitelist.authority {
+                return Err(PolicyError::WhitelistAuthorityMismatch.into());
+            }
+            if whitelist.version <= current.version {
+                return Err(PolicyError::StaleWhitelist {
+                    version: whitelist.version,
+                    current: current.version,
+                }
+                .into());
+            }
+        }
+        self.meta_storage
+            .set_whitelist(&serde_json::to_string(whitelist)?)?;
+        self.meta_storage.persist()?;
+        Ok(())
+    }
+
+    /// The stored whitelist, its signature checked again in case the
+    /// storage was tampered with.
+    pub fn whitelist(&self) -> Result<Option<SignedWhitelist>> {
+        let Some(whitelist) = self.meta_storage.get_whitelist()? else {
+            return Ok(None);
+        };
+        let whitelist: SignedWhitelist = serde_json::from_str(&whitelist)?;
+        whitelist.verify(self.config.read_or_err()?.network)?;
+        Ok(Some(whitelist))
+    }
+
+    /// Indexes of the outputs of `psbt` paying neither the account nor a
+    /// whitelisted destination. Empty OP_RETURN outputs pay nobody and are
+    /// allowed.
+    pub fn whitelist_violations(&self, psbt: &Psbt) -> Result<Vec<usize>> {
+        let whitelist = self.whitelist()?.ok_or(PolicyError::NoWhitelist)?;
+        let allowed = whitelist.script_pubkeys(self.config.read_or_err()?.network)?;
+
+        let mut violations = vec![];
+        for (index, output) in psbt.unsigned_tx.output.iter().enumerate() {
+            let script = &output.script_pubkey;
+            if allowed.contains(script) || (script.is_op_return() && output.value.to_sat() == 0) {
+                continue;
+            }
+            if !self.is_mine(script)? {
+                violations.push(index);
+            }
+        }
+        Ok(violations)
+    }
+
+    /// [`NgAccount::sign`] enforcing the whitelist: fails with
+    /// [`PolicyError::NotWhitelisted`] listing the offending outputs when
+    /// `psbt` pays anywhere else, and without one stored.
+    pub fn sign_whitelisted(
+        &self,
+        psbt: &[u8],
+        options: bdk_wallet::SignOptions,
+    ) -> Result<Vec<u8>> {
+        let decoded = Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT")?;
+        let violations = self.whitelist_violations(&decoded)?;
+        if !violations.is_empty() {
+            return Err(PolicyError::NotWhitelisted(violations).into());
+        }
+        self.sign(psbt, options)
+    }
+}
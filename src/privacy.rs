@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::LinkedAddresses;
+use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output};
+
+/// Points deducted from the score for each finding.
+const MIXED_TAGS_PENALTY: u8 = 30;
+const ROUND_PAYMENT_PENALTY: u8 = 20;
+const MERGED_CLUSTERS_PENALTY: u8 = 30;
+/// Extra points deducted for every cluster merged beyond the second one.
+const EXTRA_CLUSTER_PENALTY: u8 = 10;
+
+/// Privacy analysis of a draft transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacyReport {
+    /// Inputs carry different tags (untagged counts as its own tag), linking
+    /// coins the user deliberately kept apart.
+    pub mixes_tags: bool,
+    /// The payment is a round amount while the change is not, which makes
+    /// the change output easy to identify.
+    pub round_payment: bool,
+    /// Inputs come from address clusters that were never linked on chain before.
+    pub merges_clusters: bool,
+    /// Number of distinct clusters the inputs belong to.
+    pub cluster_count: usize,
+    /// Overall score from 0 (bad) to 100 (no findings).
+    pub score: u8,
+}
+
+/// Analyze a draft transaction against the account history.
+///
+/// Clusters are built with the common-input-ownership heuristic: addresses
+/// spent together in `history`, and the change they paid to, are assumed to
+/// be linked already.
+pub fn analyze(
+    inputs: &[Input],
+    outputs: &[Output],
+    payment_amount: u64,
+    history: &[BitcoinTransaction],
+) -> PrivacyReport {
+    let input_tags: HashSet<&str> = inputs
+        .iter()
+        .map(|input| match input.tag.as_deref() {
+            Some(tag) if !tag.is_empty() => tag,
+            _ => "untagged",
+        })
+        .collect();
+    let mixes_tags = input_tags.len() > 1;
+
+    let change: Vec<&Output> = outputs
+        .iter()
+        .filter(|output| output.keychain == Some(KeyChain::Internal))
+        .collect();
+    let round_payment = is_round_amount(payment_amount)
+        && !change.is_empty()
+        && change.iter().all(|output| !is_round_amount(output.amount));
+
+    let cluster_count = count_input_clusters(inputs, history);
+    let merges_clusters = cluster_count > 1;
+
+    let mut score: u8 = 100;
+    if mixes_tags {
+        score = score.saturating_sub(MIXED_TAGS_PENALTY);
+    }
+    if round_payment {
+        score = score.saturating_sub(ROUND_PAYMENT_PENALTY);
+    }
+    if merges_clusters {
+        let extra_clusters = (cluster_count - 2).min(u8::MAX as usize) as u8;
+        score = score
+            .saturating_sub(MERGED_CLUSTERS_PENALTY)
+            .saturating_sub(extra_clusters.saturating_mul(EXTRA_CLUSTER_PENALTY));
+    }
+
+    PrivacyReport {
+        mixes_tags,
+        round_payment,
+        merges_clusters,
+        cluster_count,
+        score,
+    }
+}
+
+/// An amount with at most two significant digits and at least three
+/// trailing zeros in sats (e.g. 4_000 or 1_500_000).
+pub fn is_round_amount(amount: u64) -> bool {
+    if amount == 0 {
+        return false;
+    }
+    let mut significant = amount;
+    let mut zeros = 0;
+    while significant % 10 == 0 {
+        significant /= 10;
+        zeros += 1;
+    }
+    zeros >= 3 && significant < 100
+}
+
+fn count_input_clusters(inputs: &[Input], history: &[BitcoinTransaction]) -> usize {
+    let mut linked = LinkedAddresses::new(history);
+    inputs
+        .iter()
+        .map(|input| match linked.address_of(input) {
+            Some(address) => linked.root(address),
+            // unknown funding output, treat as its own cluster
+            None => format!("{}:{}", input.tx_id, input.vout),
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::fixtures;
+
+    fn output(tx_id: &str, vout: u32, address: &str, keychain: Option<KeyChain>) -> Output {
+        Output {
+            keychain,
+            ..fixtures::output(tx_id, vout, address)
+        }
+    }
+
+    fn input(tx_id: &str, vout: u32, tag: Option<&str>) -> Input {
+        Input {
+            tag: tag.map(str::to_string),
+            ..fixtures::input(tx_id, vout)
+        }
+    }
+
+    fn tx(tx_id: &str, inputs: Vec<Input>, outputs: Vec<Output>) -> BitcoinTransaction {
+        BitcoinTransaction {
+            inputs,
+            outputs,
+            ..fixtures::tx(tx_id)
+        }
+    }
+
+    #[test]
+    fn round_amounts() {
+        assert!(is_round_amount(4_000));
+        assert!(is_round_amount(1_500_000));
+        assert!(!is_round_amount(2_003));
+        assert!(!is_round_amount(123_000));
+        assert!(!is_round_amount(0));
+    }
+
+    #[test]
+    fn clean_spend_scores_full() {
+        let history = vec![tx("a", vec![], vec![output("a", 0, "addr_a", None)])];
+        let change = output("new", 1, "change", Some(KeyChain::Internal));
+        let report = analyze(
+            &[input("a", 0, Some("savings"))],
+            &[change],
+            2_003,
+            &history,
+        );
+        assert_eq!(report.cluster_count, 1);
+        assert!(!report.mixes_tags && !report.round_payment && !report.merges_clusters);
+        assert_eq!(report.score, 100);
+    }
+
+    #[test]
+    fn detects_mixed_tags_and_merged_clusters() {
+        let history = vec![
+            tx("a", vec![], vec![output("a", 0, "addr_a", None)]),
+            tx("b", vec![], vec![output("b", 0, "addr_b", None)]),
+        ];
+        let inputs = [input("a", 0, Some("savings")), input("b", 0, None)];
+        let mut change = output("new", 1, "change", Some(KeyChain::Internal));
+        change.amount = 12_345;
+        let report = analyze(&inputs, &[change], 50_000, &history);
+        assert!(report.mixes_tags);
+        assert!(report.round_payment);
+        assert!(report.merges_clusters);
+        assert_eq!(report.cluster_count, 2);
+        assert_eq!(report.score, 20);
+    }
+
+    #[test]
+    fn previously_co_spent_addresses_share_a_cluster() {
+        let history = vec![
+            tx("a", vec![], vec![output("a", 0, "addr_a", None)]),
+            tx("b", vec![], vec![output("b", 0, "addr_b", None)]),
+            tx(
+                "c",
+                vec![input("a", 0, None), input("b", 0, None)],
+                vec![output("c", 0, "change_c", Some(KeyChain::Internal))],
+            ),
+            tx("d", vec![], vec![output("d", 0, "addr_a", None)]),
+        ];
+        let report = analyze(
+            &[input("d", 0, None), input("c", 0, None)],
+            &[],
+            2_003,
+            &history,
+        );
+        assert_eq!(report.cluster_count, 1);
+        assert!(!report.merges_clusters);
+    }
+}
@@ -0,0 +1,253 @@
+//! Proof of reserves (BIP-127).
+//!
+//! A proof is a PSBT spending the account UTXOs together with a challenge
+//! input committing to a message. The challenge input spends an outpoint
+//! that can't exist, so the proof can never be broadcast, while the
+//! signatures show control of the keys locking the UTXOs at the time the
+//! message was chosen.
+
+use anyhow::{Context, Result, anyhow, bail};
+use bdk_wallet::bitcoin::hashes::{Hash, sha256d};
+use bdk_wallet::bitcoin::opcodes::all::OP_RETURN;
+use bdk_wallet::bitcoin::script::{Builder, Instruction};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{
+    Amount, EcdsaSighashType, OutPoint, Psbt, ScriptBuf, Sequence, TapSighashType, Transaction,
+    TxIn, TxOut, Txid, absolute, ecdsa, psbt, taproot, transaction,
+};
+use bdk_wallet::miniscript::psbt::PsbtExt;
+use bdk_wallet::{SignOptions, WalletPersister};
+
+use crate::account::NgAccount;
+use crate::error::MutexExt;
+
+/// Outpoint spent by the challenge input of a proof for `message`.
+pub fn challenge_outpoint(message: &str) -> OutPoint {
+    let hash = sha256d::Hash::hash(format!("Proof-of-Reserves: {message}").as_bytes());
+    OutPoint::new(Txid::from_raw_hash(hash), 0)
+}
+
+// The single output of a proof, burning the total so the transaction is
+// useless even if the challenge input could be satisfied.
+fn unspendable_script() -> ScriptBuf {
+    Builder::new().push_opcode(OP_RETURN).into_script()
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Unsigned proof of reserves for `message` over `outpoints`, or over
+    /// every UTXO of the account when `None`.
+    ///
+    /// Legacy (non segwit) outputs are not supported: signing them needs the
+    /// previous transaction, which doesn't exist for the challenge input.
+    /// Signers must trust witness UTXOs, see [`Self::sign_reserve_proof`].
+    pub fn create_reserve_proof(
+        &self,
+        message: &str,
+        outpoints: Option<&[OutPoint]>,
+    ) -> Result<Psbt> {
+        let mut inputs: Vec<(OutPoint, psbt::Input)> = vec![];
+        for wallet in self.all_wallets_or_recover().iter() {
+            let wallet = wallet.bdk_wallet.lock_or_err()?;
+            for utxo in wallet.list_unspent() {
+                if outpoints.is_some_and(|outpoints| !outpoints.contains(&utxo.outpoint)) {
+                    continue;
+                }
+                let outpoint = utxo.outpoint;
+                let input = wallet
+                    .get_psbt_input(utxo, None, false)
+                    .with_context(|| format!("Failed to create input for {outpoint}"))?;
+                if input.witness_utxo.is_none() {
+                    bail!("Legacy output {outpoint} can't be part of a proof of reserves");
+                }
+                inputs.push((outpoint, input));
+            }
+        }
+        if let Some(outpoints) = outpoints
+            && let Some(missing) = outpoints
+                .iter()
+                .find(|outpoint| !inputs.iter().any(|(found, _)| found == *outpoint))
+        {
+            bail!("{missing} is not an unspent output of this account");
+        }
+        let Some((_, first)) = inputs.first() else {
+            bail!("No UTXOs to prove");
+        };
+
+        // The challenge input is signed with the key of the first UTXO.
+        let challenge = psbt::Input {
+            witness_utxo: first.witness_utxo.as_ref().map(|utxo| TxOut {
+                value: Amount::ZERO,
+                script_pubkey: utxo.script_pubkey.clone(),
+            }),
+            non_witness_utxo: None,
+            ..first.clone()
+        };
+        let total: Amount = inputs
+            .iter()
+            .filter_map(|(_, input)| input.witness_utxo.as_ref())
+            .map(|utxo| utxo.value)
+            .sum();
+
+        let tx = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: [challenge_outpoint(message)]
+                .into_iter()
+                .chain(inputs.iter().map(|(outpoint, _)| *outpoint))
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    sequence: Sequence::MAX,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: total,
+                script_pubkey: unspendable_script(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs = [challenge]
+            .into_iter()
+            .chain(inputs.into_iter().map(|(_, input)| input))
+            .collect();
+        Ok(psbt)
+    }
+
+    /// Sign a proof of reserves with the keys of this account.
+    pub fn sign_reserve_proof(&self, psbt: &Psbt) -> Result<Psbt> {
+        let signed = self.sign(
+            &psbt.serialize(),
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )?;
+        Psbt::deserialize(&signed).with_context(|| "Failed to deserialize signed proof")
+    }
+}
+
+// Whether every signature of the final scripts of `input` commits to all
+// inputs and outputs. Only signatures of segwit outputs are looked for,
+// legacy outputs can't be part of a proof.
+fn signs_all(input: &psbt::Input) -> Result<bool> {
+    let Some(utxo) = input.witness_utxo.as_ref() else {
+        bail!("Proof input has no witness UTXO");
+    };
+    let mut witness: Vec<&[u8]> = input
+        .final_script_witness
+        .as_ref()
+        .map(|witness| witness.iter().collect())
+        .unwrap_or_default();
+
+    if utxo.script_pubkey.is_p2tr() {
+        if witness.len() > 1
+            && witness
+                .last()
+                .is_some_and(|item| item.first() == Some(&0x50))
+        {
+            witness.pop(); // annex
+        }
+        // the key path has the signature alone, the script path ends with
+        // the script and the control block
+        let signatures = match witness.len() {
+            1 => &witness[..],
+            n => &witness[..n.saturating_sub(2)],
+        };
+        return Ok(signatures
+            .iter()
+            .filter_map(|item| taproot::Signature::from_slice(item).ok())
+            .all(|signature| {
+                matches!(
+                    signature.sighash_type,
+                    TapSighashType::All | TapSighashType::Default
+                )
+            }));
+    }
+
+    // the public key or witness script comes last
+    witness.pop();
+    let script_sig_pushes: Vec<&[u8]> = input
+        .final_script_sig
+        .iter()
+        .flat_map(|script| script.instructions())
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+            _ => None,
+        })
+        .collect();
+    Ok(witness
+        .into_iter()
+        .chain(script_sig_pushes)
+        .filter_map(|item| ecdsa::Signature::from_slice(item).ok())
+        .all(|signature| signature.sighash_type == EcdsaSighashType::All))
+}
+
+/// Verify a signed proof of reserves for `message` and return the amount
+/// it proves.
+///
+/// `unspent` must hold the outputs currently unspent on chain for the
+/// outpoints spent by the proof, as fetched by the verifier. Proofs signed
+/// with a sighash type other than `ALL` are rejected, since their
+/// signatures could be reused in another proof.
+pub fn verify_reserve_proof(
+    psbt: &Psbt,
+    message: &str,
+    unspent: &[(OutPoint, TxOut)],
+) -> Result<Amount> {
+    let tx = &psbt.unsigned_tx;
+    if tx.input.len() < 2 || psbt.inputs.len() != tx.input.len() {
+        bail!("Proof spends no UTXOs");
+    }
+    if tx.input[0].previous_output != challenge_outpoint(message) {
+        bail!("Proof is not for this message");
+    }
+    if tx.output.len() != 1 || tx.output[0].script_pubkey != unspendable_script() {
+        bail!("Proof output is not unspendable");
+    }
+    let Some(challenge) = psbt.inputs[0].witness_utxo.as_ref() else {
+        bail!("Invalid challenge input");
+    };
+    if challenge.value != Amount::ZERO {
+        bail!("Invalid challenge input");
+    }
+
+    let mut total = Amount::ZERO;
+    for (txin, input) in tx.input.iter().zip(&psbt.inputs).skip(1) {
+        let outpoint = txin.previous_output;
+        let (_, utxo) = unspent
+            .iter()
+            .find(|(unspent, _)| *unspent == outpoint)
+            .ok_or_else(|| anyhow!("{outpoint} is not an unspent output"))?;
+        if input.witness_utxo.as_ref() != Some(utxo) {
+            bail!("Proof input {outpoint} does not match the unspent output");
+        }
+        total += utxo.value;
+    }
+    if tx.output[0].value != total {
+        bail!("Proof output does not match the proven amount");
+    }
+    // the challenge is signed with the key of the first proven UTXO
+    if psbt.inputs[1]
+        .witness_utxo
+        .as_ref()
+        .is_none_or(|utxo| utxo.script_pubkey != challenge.script_pubkey)
+    {
+        bail!("Challenge input is not locked by the first proven UTXO");
+    }
+
+    // The sighash type hints of the PSBT are set by the prover, the types
+    // of the signatures themselves are checked.
+    for (txin, input) in tx.input.iter().zip(&psbt.inputs) {
+        if !signs_all(input)? {
+            bail!(
+                "Input {} is signed with a sighash type other than ALL",
+                txin.previous_output
+            );
+        }
+    }
+
+    // checks every input script, signatures included, against its UTXO
+    psbt.extract(&Secp256k1::verification_only())
+        .map_err(|e| anyhow!("Invalid proof signatures: {e}"))?;
+    Ok(total)
+}
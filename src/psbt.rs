@@ -1,3 +1,4 @@
+pub mod memo;
 mod multisig;
 mod op_return;
 mod p2pkh;
@@ -5,8 +6,12 @@ mod p2sh;
 mod p2tr;
 mod p2wpkh;
 mod p2wsh;
+pub mod vectors;
 
 use crate::bip32::{NgAccountPath, ParsePathError};
+use crate::collections::{BTreeMap, HashSet};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use bdk_wallet::bitcoin::bip32;
 use bdk_wallet::bitcoin::bip32::{
     ChildNumber, DerivationPath, Fingerprint, KeySource, Xpriv, Xpub,
@@ -19,8 +24,9 @@ use bdk_wallet::bitcoin::{
 };
 use bdk_wallet::descriptor::ExtendedDescriptor;
 use bdk_wallet::keys::{DescriptorPublicKey, SinglePub, SinglePubKey};
-use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
+use bdk_wallet::miniscript::ForEachKey;
+use bdk_wallet::miniscript::descriptor::DescriptorType;
+use core::cmp::Ordering;
 use thiserror::Error;
 
 /// Details of a PSBT.
@@ -280,6 +286,15 @@ pub enum Error {
     #[error("the multisig script of input/output number {index} is malformed")]
     InvalidMultisigScript { index: usize },
 
+    /// A taproot input or output with scripts doesn't have an internal key.
+    #[error("the input/output number {index} is missing its taproot internal key")]
+    MissingTapInternalKey { index: usize },
+
+    /// The leaf scripts, control blocks or merkle root of a taproot input
+    /// or output aren't committed to by its output key.
+    #[error("the taproot scripts of input/output number {index} don't match its output key")]
+    InvalidTapScript { index: usize },
+
     // TODO(jeandudey): Remove this.
     #[error("not yet implemented")]
     Unimplemented,
@@ -387,6 +402,204 @@ pub fn validate<C>(
 ) -> Result<TransactionDetails, Error>
 where
     C: Signing + Verification,
+{
+    debug_assert!(is_master_key(master_key));
+    let keys = MasterKey {
+        key: master_key,
+        fingerprint: master_key.fingerprint(secp),
+    };
+    validate_with(secp, &keys, psbt, network)
+}
+
+/// Validate a PSBT against the public `descriptors` of a watch-only wallet.
+///
+/// Inputs and outputs are classified as with [`validate`], keys being
+/// checked against the xpubs of the descriptors instead of the master key.
+/// Keys outside of the xpubs can't be checked, outputs paying to them are
+/// external rather than suspicious. The descriptors reported are the ones
+/// of `descriptors` the inputs spend from, and the multisig ones rebuilt
+/// from the PSBT.
+pub fn validate_watch_only<C>(
+    secp: &Secp256k1<C>,
+    descriptors: &[ExtendedDescriptor],
+    psbt: &Psbt,
+    network: Network,
+) -> Result<TransactionDetails, Error>
+where
+    C: Signing + Verification,
+{
+    validate_with(secp, &WatchOnlyKeys { descriptors }, psbt, network)
+}
+
+/// The keys a PSBT is validated against.
+trait Keys {
+    /// Returns true if one of the keys has the master `fingerprint`.
+    fn has_fingerprint(&self, fingerprint: Fingerprint) -> bool;
+
+    /// The key at `source`, `None` if it isn't derived from one of the keys.
+    fn derive<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        source: &KeySource,
+    ) -> Result<Option<Xpub>, bip32::Error>
+    where
+        C: Signing + Verification;
+
+    /// The single-sig descriptor of `descriptor_type` of the account of the
+    /// key at `source`.
+    fn descriptor<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor_type: DescriptorType,
+        source: &KeySource,
+        network: Network,
+    ) -> Option<ExtendedDescriptor>
+    where
+        C: Signing + Verification;
+}
+
+struct MasterKey<'a> {
+    key: &'a Xpriv,
+    // Calculating it involves deriving the Xpub, so it is done once.
+    fingerprint: Fingerprint,
+}
+
+impl Keys for MasterKey<'_> {
+    fn has_fingerprint(&self, fingerprint: Fingerprint) -> bool {
+        self.fingerprint == fingerprint
+    }
+
+    fn derive<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        source: &KeySource,
+    ) -> Result<Option<Xpub>, bip32::Error>
+    where
+        C: Signing + Verification,
+    {
+        if source.0 != self.fingerprint {
+            return Ok(None);
+        }
+        let derived_xpriv = self.key.derive_priv(secp, &source.1)?;
+        Ok(Some(Xpub::from_priv(secp, &derived_xpriv)))
+    }
+
+    fn descriptor<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor_type: DescriptorType,
+        source: &KeySource,
+        network: Network,
+    ) -> Option<ExtendedDescriptor>
+    where
+        C: Signing + Verification,
+    {
+        let path = &source.1;
+        match descriptor_type {
+            DescriptorType::Pkh => Some(p2pkh::descriptor(secp, self.key, path, network)),
+            DescriptorType::Wpkh => Some(p2wpkh::descriptor(secp, self.key, path, network)),
+            DescriptorType::ShWpkh => {
+                Some(p2sh::p2shwpkh_descriptor(secp, self.key, path, network))
+            }
+            DescriptorType::Tr => Some(p2tr::descriptor(secp, self.key, path, network)),
+            _ => None,
+        }
+    }
+}
+
+struct WatchOnlyKeys<'a> {
+    descriptors: &'a [ExtendedDescriptor],
+}
+
+impl Keys for WatchOnlyKeys<'_> {
+    fn has_fingerprint(&self, fingerprint: Fingerprint) -> bool {
+        self.descriptors.iter().any(|descriptor| {
+            descriptor.for_any_key(|key| {
+                xkey_origin(key)
+                    .is_some_and(|(key_fingerprint, _, _)| key_fingerprint == fingerprint)
+            })
+        })
+    }
+
+    fn derive<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        source: &KeySource,
+    ) -> Result<Option<Xpub>, bip32::Error>
+    where
+        C: Signing + Verification,
+    {
+        let mut derived = None;
+        self.descriptors.iter().any(|descriptor| {
+            descriptor.for_any_key(|key| {
+                derived = derive_below(secp, key, source);
+                derived.is_some()
+            })
+        });
+        Ok(derived)
+    }
+
+    fn descriptor<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor_type: DescriptorType,
+        source: &KeySource,
+        _network: Network,
+    ) -> Option<ExtendedDescriptor>
+    where
+        C: Signing + Verification,
+    {
+        self.descriptors
+            .iter()
+            .find(|descriptor| {
+                descriptor.desc_type() == descriptor_type
+                    && descriptor.for_any_key(|key| derive_below(secp, key, source).is_some())
+            })
+            .cloned()
+    }
+}
+
+/// The fingerprint and path of the master key of an extended key, and the
+/// extended key.
+fn xkey_origin(key: &DescriptorPublicKey) -> Option<(Fingerprint, DerivationPath, Xpub)> {
+    let (origin, xkey) = match key {
+        DescriptorPublicKey::XPub(xkey) => (&xkey.origin, xkey.xkey),
+        DescriptorPublicKey::MultiXPub(xkey) => (&xkey.origin, xkey.xkey),
+        DescriptorPublicKey::Single(_) => return None,
+    };
+    Some(match origin {
+        Some((fingerprint, path)) => (*fingerprint, path.clone(), xkey),
+        None => (xkey.fingerprint(), DerivationPath::master(), xkey),
+    })
+}
+
+/// The key at `source` derived from the extended `key`, if `source` is
+/// below it with unhardened steps only.
+fn derive_below<C>(
+    secp: &Secp256k1<C>,
+    key: &DescriptorPublicKey,
+    source: &KeySource,
+) -> Option<Xpub>
+where
+    C: Verification,
+{
+    let (fingerprint, origin, xkey) = xkey_origin(key)?;
+    if fingerprint != source.0 {
+        return None;
+    }
+    let rest = source.1.as_ref().strip_prefix(origin.as_ref())?;
+    xkey.derive_pub(secp, &rest).ok()
+}
+
+fn validate_with<C, K>(
+    secp: &Secp256k1<C>,
+    keys: &K,
+    psbt: &Psbt,
+    network: Network,
+) -> Result<TransactionDetails, Error>
+where
+    C: Signing + Verification,
+    K: Keys,
 {
     // SAFETY: This is allowed because the implementation of ExtendedDescriptor
     // correctly implements hash with interior mutability as Tr does not hash
@@ -398,8 +611,6 @@ where
     let mut inputs = Vec::new();
     let mut outputs = Vec::new();
 
-    let fingerprint = master_key.fingerprint(secp);
-
     // TODO: After validating these xpubs use these to validate further
     // derivations in the inputs and outputs, to also verify the keys that
     // aren't "ours" to avoid creating spending from/to a multisig where
@@ -407,7 +618,7 @@ where
     //
     // Of course the caller must also make sure that the descriptors we
     // return for multisig are valid too.
-    let maybe_valid = validate_xpubs(secp, master_key, &psbt.xpub, fingerprint)?;
+    let maybe_valid = validate_xpubs(secp, keys, &psbt.xpub)?;
     match maybe_valid {
         Some(true) => (),
         Some(false) => return Err(Error::FraudulentKey),
@@ -421,12 +632,12 @@ where
         let has_bip32 = input
             .bip32_derivation
             .iter()
-            .any(|(_, (v, _))| *v == fingerprint);
+            .any(|(_, (v, _))| keys.has_fingerprint(*v));
 
         let has_tap = input
             .tap_key_origins
             .iter()
-            .any(|(_, (_, (v, _)))| *v == fingerprint);
+            .any(|(_, (_, (v, _)))| keys.has_fingerprint(*v));
 
         has_bip32 || has_tap
     });
@@ -457,17 +668,16 @@ where
             }
         }
 
-        let has_our_public_keys =
-            validate_public_keys(secp, master_key, &input.bip32_derivation, fingerprint)
-                .map_err(Error::from)
-                .and_then(|maybe_valid| match maybe_valid {
-                    Some(true) => Ok(true),
-                    Some(false) => Err(Error::FraudulentKey),
-                    None => Ok(false),
-                })?;
+        let has_our_public_keys = validate_public_keys(secp, keys, &input.bip32_derivation)
+            .map_err(Error::from)
+            .and_then(|maybe_valid| match maybe_valid {
+                Some(true) => Ok(true),
+                Some(false) => Err(Error::FraudulentKey),
+                None => Ok(false),
+            })?;
 
         let has_our_x_only_public_keys =
-            validate_x_only_public_keys(secp, master_key, &input.tap_key_origins, fingerprint)
+            validate_x_only_public_keys(secp, keys, &input.tap_key_origins)
                 .map_err(Error::from)
                 .and_then(|maybe_valid| match maybe_valid {
                     Some(true) => Ok(true),
@@ -485,22 +695,19 @@ where
             funding_utxo(input, txin, i)?.ok_or(Error::MissingInputFundingUtxo { index: i })?;
 
         if funding_utxo.script_pubkey.is_p2tr() {
-            // Only single-sig P2TR supported for now.
-            if input.tap_key_origins.len() != 1 {
-                return Err(Error::MultipleKeysNotExpected { index: i });
-            }
-
-            let (x_only_pk, (_, source)) = input.tap_key_origins.first_key_value().unwrap();
-            let address = Address::p2tr(secp, *x_only_pk, None, network);
-            if !address.matches_script_pubkey(&funding_utxo.script_pubkey) {
-                return Err(Error::FraudulentInput { index: i });
-            }
+            let address = p2tr::validate_input(secp, input, funding_utxo, network, i)?;
 
             inputs.push(PsbtInput {
                 amount: funding_utxo.value,
                 address,
             });
-            descriptors.insert(p2tr::descriptor(secp, master_key, &source.1, network));
+
+            // The descriptor of a script path spend can't be rebuilt from
+            // the PSBT alone, only BIP-0086 ones are reported.
+            if p2tr::is_key_path_only(input) {
+                let (_, (_, source)) = input.tap_key_origins.first_key_value().unwrap();
+                descriptors.extend(keys.descriptor(secp, DescriptorType::Tr, source, network));
+            }
         } else if funding_utxo.script_pubkey.is_p2wpkh() {
             if input.bip32_derivation.len() != 1 {
                 return Err(Error::MultipleKeysNotExpected { index: i });
@@ -518,7 +725,7 @@ where
                 amount: funding_utxo.value,
                 address,
             });
-            descriptors.insert(p2wpkh::descriptor(secp, master_key, &source.1, network));
+            descriptors.extend(keys.descriptor(secp, DescriptorType::Wpkh, source, network));
         } else if funding_utxo.script_pubkey.is_p2pkh() {
             // Legacy inputs must supply the full previous transaction so the
             // referenced output can be verified. witness_utxo alone is not
@@ -542,7 +749,7 @@ where
                 amount: funding_utxo.value,
                 address,
             });
-            descriptors.insert(p2pkh::descriptor(secp, master_key, &source.1, network));
+            descriptors.extend(keys.descriptor(secp, DescriptorType::Pkh, source, network));
         } else if funding_utxo.script_pubkey.is_p2wsh() {
             if let Some(witness_script) = input.witness_script.as_ref() {
                 // Verify sha256(witness_script) matches the P2WSH scriptPubKey so
@@ -596,8 +803,11 @@ where
                         amount: funding_utxo.value,
                         address,
                     });
-                    descriptors.insert(p2sh::p2shwpkh_descriptor(
-                        secp, master_key, &source.1, network,
+                    descriptors.extend(keys.descriptor(
+                        secp,
+                        DescriptorType::ShWpkh,
+                        source,
+                        network,
                     ));
                 } else if redeem_script.is_p2wsh() {
                     if let Some(witness_script) = input.witness_script.as_ref() {
@@ -645,17 +855,16 @@ where
             return Err(Error::MissingOutput { index: i });
         };
 
-        let has_our_public_keys =
-            validate_public_keys(secp, master_key, &output.bip32_derivation, fingerprint)
-                .map_err(Error::from)
-                .and_then(|maybe_valid| match maybe_valid {
-                    Some(true) => Ok(true),
-                    Some(false) => Err(Error::FraudulentKey),
-                    None => Ok(false),
-                })?;
+        let has_our_public_keys = validate_public_keys(secp, keys, &output.bip32_derivation)
+            .map_err(Error::from)
+            .and_then(|maybe_valid| match maybe_valid {
+                Some(true) => Ok(true),
+                Some(false) => Err(Error::FraudulentKey),
+                None => Ok(false),
+            })?;
 
         let has_our_x_only_public_keys =
-            validate_x_only_public_keys(secp, master_key, &output.tap_key_origins, fingerprint)
+            validate_x_only_public_keys(secp, keys, &output.tap_key_origins)
                 .map_err(Error::from)
                 .and_then(|maybe_valid| match maybe_valid {
                     Some(true) => Ok(true),
@@ -691,40 +900,32 @@ where
 }
 
 /// Validate that the extended public keys in `xpubs` are correctly derived
-/// from the `master_key`.
-///
-/// The `fingerprint` parameter should be equal to `master_key.fingerprint()`,
-/// but it is passed as a parameter to avoid calculating it each time since it
-/// involves converting the Xpriv to an Xpub and calculating the identifier
-/// which can be time consuming.
+/// from our `keys`.
 ///
 /// # Return
 ///
-/// - `Ok(None)`: the `fingerprint` didn't match any of the xpubs.
-/// - `Ok(Some(true))`: the `fingerprint` matched at least one of the xpubs and
+/// - `Ok(None)`: none of the xpubs derive from our keys.
+/// - `Ok(Some(true))`: at least one of the xpubs derives from our keys and
 ///   the derived xpubs matched correctly.
-/// - `Ok(Some(false))`: the `fingerprint` matched at least one of the xpubs but
+/// - `Ok(Some(false))`: at least one of the xpubs derives from our keys but
 ///   the derived xpubs didn't match, highly likely this is fraudulent.
-/// - `Err(_)`: failed to derive one of the xpubs from the master key.
-fn validate_xpubs<C>(
+/// - `Err(_)`: failed to derive one of the xpubs from our keys.
+fn validate_xpubs<C, K>(
     secp: &Secp256k1<C>,
-    master_key: &Xpriv,
+    keys: &K,
     xpubs: &BTreeMap<Xpub, KeySource>,
-    fingerprint: Fingerprint,
 ) -> Result<Option<bool>, bip32::Error>
 where
-    C: Signing,
+    C: Signing + Verification,
+    K: Keys,
 {
-    debug_assert!(is_master_key(master_key));
-    debug_assert!(master_key.fingerprint(secp) == fingerprint);
-
     let mut fingerprint_seen = false;
-    for (xpub, source) in keys_iterator(xpubs, fingerprint) {
-        debug_assert!(source.0 == fingerprint);
+    for (xpub, source) in xpubs {
+        let Some(derived_xpub) = keys.derive(secp, source)? else {
+            continue;
+        };
         fingerprint_seen = true;
 
-        let derived_xpriv = master_key.derive_priv(secp, &source.1)?;
-        let derived_xpub = Xpub::from_priv(secp, &derived_xpriv);
         if xpub != &derived_xpub {
             return Ok(Some(false));
         }
@@ -745,30 +946,27 @@ fn is_master_key(xpriv: &Xpriv) -> bool {
 }
 
 /// Validate that the public keys in `bip32_derivations` are correctly
-/// derived from the `master_key`.
+/// derived from our `keys`.
 ///
 /// # Return
 ///
 /// This has the same behaviour as in [`validate_xpubs`].
-fn validate_public_keys<C>(
+fn validate_public_keys<C, K>(
     secp: &Secp256k1<C>,
-    master_key: &Xpriv,
+    keys: &K,
     bip32_derivations: &BTreeMap<PublicKey, KeySource>,
-    fingerprint: Fingerprint,
 ) -> Result<Option<bool>, bip32::Error>
 where
-    C: Signing,
+    C: Signing + Verification,
+    K: Keys,
 {
-    debug_assert!(is_master_key(master_key));
-    debug_assert!(master_key.fingerprint(secp) == fingerprint);
-
     let mut fingerprint_seen = false;
-    for (pk, source) in keys_iterator(bip32_derivations, fingerprint) {
-        debug_assert!(source.0 == fingerprint);
+    for (pk, source) in bip32_derivations {
+        let Some(derived_xpub) = keys.derive(secp, source)? else {
+            continue;
+        };
         fingerprint_seen = true;
 
-        let derived_xpriv = master_key.derive_priv(secp, &source.1)?;
-        let derived_xpub = Xpub::from_priv(secp, &derived_xpriv);
         if pk != &derived_xpub.public_key {
             return Ok(Some(false));
         }
@@ -782,30 +980,27 @@ where
 }
 
 /// Validate that the X-only public keys in `tap_key_origins` are correctly
-/// derived from the `master_key`.
+/// derived from our `keys`.
 ///
 /// # Return
 ///
 /// This has the same behaviour as in [`validate_xpubs`].
-fn validate_x_only_public_keys<C>(
+fn validate_x_only_public_keys<C, K>(
     secp: &Secp256k1<C>,
-    master_key: &Xpriv,
+    keys: &K,
     tap_key_origins: &BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
-    fingerprint: Fingerprint,
 ) -> Result<Option<bool>, bip32::Error>
 where
-    C: Signing,
+    C: Signing + Verification,
+    K: Keys,
 {
-    debug_assert!(is_master_key(master_key));
-    debug_assert!(master_key.fingerprint(secp) == fingerprint);
-
     let mut fingerprint_seen = false;
-    for (x_only_pk, (_, source)) in x_only_keys_iterator(tap_key_origins, fingerprint) {
-        debug_assert!(source.0 == fingerprint);
+    for (x_only_pk, (_, source)) in tap_key_origins {
+        let Some(derived_xpub) = keys.derive(secp, source)? else {
+            continue;
+        };
         fingerprint_seen = true;
 
-        let derived_xpriv = master_key.derive_priv(secp, &source.1)?;
-        let derived_xpub = Xpub::from_priv(secp, &derived_xpriv);
         if x_only_pk != &derived_xpub.public_key.x_only_public_key().0 {
             return Ok(Some(false));
         }
@@ -818,24 +1013,6 @@ where
     }
 }
 
-/// Returns an iterator over the extended public keys matching the fingerprint.
-fn keys_iterator<K>(
-    keys: &BTreeMap<K, KeySource>,
-    fingerprint: Fingerprint,
-) -> impl Iterator<Item = (&K, &KeySource)> {
-    keys.iter()
-        .filter(move |(_, source)| fingerprint == source.0)
-}
-
-/// Returns an iterator over the x-only public keys matching the fingerprint.
-fn x_only_keys_iterator<K>(
-    keys: &BTreeMap<K, (Vec<TapLeafHash>, KeySource)>,
-    fingerprint: Fingerprint,
-) -> impl Iterator<Item = (&K, &(Vec<TapLeafHash>, KeySource))> {
-    keys.iter()
-        .filter(move |(_, (_, source))| fingerprint == source.0)
-}
-
 fn funding_utxo<'a>(
     input: &'a psbt::Input,
     txin: &'a TxIn,
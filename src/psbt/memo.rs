@@ -0,0 +1,106 @@
+//! Transaction note and change tag carried in a PSBT.
+//!
+//! Notes and tags live in the metadata storage of the account composing the
+//! transaction, and are lost when the PSBT goes through a signer and back.
+//! [`PsbtMemo::embed`] writes them to global proprietary key-value pairs of
+//! the PSBT with the [`MEMO_PREFIX`] prefix, which signers keep untouched,
+//! and [`PsbtMemo::read`] gets them back.
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use bdk_wallet::bitcoin::psbt::{Psbt, raw::ProprietaryKey};
+
+/// Prefix of the proprietary keys of the memo.
+pub const MEMO_PREFIX: &[u8] = b"ngwallet";
+const NOTE_SUBTYPE: u8 = 0x00;
+const CHANGE_TAG_SUBTYPE: u8 = 0x01;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PsbtMemo {
+    pub note: Option<String>,
+    /// Tag of the change output.
+    pub change_tag: Option<String>,
+}
+
+impl PsbtMemo {
+    /// Memo of `psbt`, empty if it has none. Values that aren't UTF-8 are
+    /// ignored.
+    pub fn read(psbt: &Psbt) -> Self {
+        let value = |subtype| {
+            psbt.proprietary
+                .get(&memo_key(subtype))
+                .and_then(|value| String::from_utf8(value.clone()).ok())
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            note: value(NOTE_SUBTYPE),
+            change_tag: value(CHANGE_TAG_SUBTYPE),
+        }
+    }
+
+    /// Write the memo to `psbt`, replacing the one it had. Empty values are
+    /// left out.
+    pub fn embed(&self, psbt: &mut Psbt) {
+        for (subtype, value) in [
+            (NOTE_SUBTYPE, &self.note),
+            (CHANGE_TAG_SUBTYPE, &self.change_tag),
+        ] {
+            match value.as_deref().filter(|value| !value.is_empty()) {
+                Some(value) => {
+                    psbt.proprietary
+                        .insert(memo_key(subtype), value.as_bytes().to_vec());
+                }
+                None => {
+                    psbt.proprietary.remove(&memo_key(subtype));
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.note.is_none() && self.change_tag.is_none()
+    }
+}
+
+fn memo_key(subtype: u8) -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: MEMO_PREFIX.to_vec(),
+        subtype,
+        key: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::{Transaction, absolute, transaction};
+
+    #[test]
+    fn memo_survives_serialization() {
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        })
+        .unwrap();
+        assert!(PsbtMemo::read(&psbt).is_empty());
+
+        let memo = PsbtMemo {
+            note: Some("Rent ✓".to_string()),
+            change_tag: Some("Savings".to_string()),
+        };
+        memo.embed(&mut psbt);
+        let psbt = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(PsbtMemo::read(&psbt), memo);
+
+        let mut psbt = psbt;
+        PsbtMemo {
+            note: Some(String::new()),
+            change_tag: Some("Savings".to_string()),
+        }
+        .embed(&mut psbt);
+        assert_eq!(PsbtMemo::read(&psbt).note, None);
+        assert_eq!(psbt.proprietary.len(), 1);
+    }
+}
@@ -1,7 +1,7 @@
 use bdk_wallet::bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
 use bdk_wallet::bitcoin::script::{Instruction, Instructions};
 use bdk_wallet::bitcoin::{PublicKey, Script};
-use std::iter::Peekable;
+use core::iter::Peekable;
 use thiserror::Error;
 
 /// Errors that can happen during the disassembly of the multi-sig script.
@@ -1,8 +1,10 @@
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use crate::psbt::{OpReturnPart, OutputKind, PsbtOutput};
 use bdk_wallet::bitcoin::TxOut;
 use bdk_wallet::bitcoin::opcodes::all::OP_RETURN;
 use bdk_wallet::bitcoin::script::Instruction;
-use std::str;
+use core::str;
 
 /// Parse an OP_RETURN output to retrieve the message.
 ///
@@ -1,4 +1,7 @@
 use crate::bip32::NgAccountPath;
+use crate::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use crate::psbt::{
     Error, OutputKind, PsbtOutput, derive_account_xpub, derive_full_descriptor_pubkey, sort_keys,
 };
@@ -12,7 +15,6 @@ use bdk_wallet::miniscript::descriptor::{DescriptorXKey, Wildcard};
 use bdk_wallet::miniscript::descriptor::{Sh, Wpkh};
 use bdk_wallet::miniscript::{ForEachKey, Miniscript};
 use bdk_wallet::template::Bip49Public;
-use std::collections::BTreeMap;
 
 pub fn validate_output(
     output: &psbt::Output,
@@ -1,19 +1,118 @@
 use crate::bip32::NgAccountPath;
+use crate::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use crate::psbt::{
     Error, OutputKind, PsbtOutput, derive_account_xpub, derive_full_descriptor_pubkey,
 };
-use bdk_wallet::bitcoin::bip32::{ChildNumber, Xpriv};
+use bdk_wallet::bitcoin::bip32::{ChildNumber, DerivationPath, KeySource, Xpriv};
+use bdk_wallet::bitcoin::key::TapTweak;
 use bdk_wallet::bitcoin::psbt;
-use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing, Verification};
-use bdk_wallet::bitcoin::{Address, Network, TxOut};
+use bdk_wallet::bitcoin::script::Instruction;
+use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing, Verification, XOnlyPublicKey};
+use bdk_wallet::bitcoin::taproot::TapNodeHash;
+use bdk_wallet::bitcoin::{Address, Network, Script, TapLeafHash, TxOut};
 use bdk_wallet::descriptor::ExtendedDescriptor;
 use bdk_wallet::template::Bip86Public;
 
+type TapKeyOrigins = BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>;
+
+/// Returns true if the input only carries the information of a BIP-0086 key
+/// path spend: a single key, which is the internal key, and no scripts.
+pub fn is_key_path_only(input: &psbt::Input) -> bool {
+    is_single_key(
+        &input.tap_key_origins,
+        input.tap_internal_key,
+        input.tap_scripts.is_empty() && input.tap_merkle_root.is_none(),
+    )
+}
+
+fn is_single_key(
+    tap_key_origins: &TapKeyOrigins,
+    internal_key: Option<XOnlyPublicKey>,
+    no_scripts: bool,
+) -> bool {
+    match tap_key_origins.first_key_value() {
+        Some((x_only_pk, _)) => {
+            tap_key_origins.len() == 1
+                && no_scripts
+                && internal_key.is_none_or(|internal_key| internal_key == *x_only_pk)
+        }
+        None => false,
+    }
+}
+
+/// Validate a Pay to Taproot (P2TR) input, returning its address.
+///
+/// Key path only inputs must pay to the BIP-0086 output key of their single
+/// key. Otherwise every leaf script and control block of the input must be
+/// committed to by the output key, see [`validate_tree`].
+pub fn validate_input<C>(
+    secp: &Secp256k1<C>,
+    input: &psbt::Input,
+    txout: &TxOut,
+    network: Network,
+    index: usize,
+) -> Result<Address, Error>
+where
+    C: Verification,
+{
+    let address = Address::from_script(&txout.script_pubkey, network.params())
+        .map_err(|_| Error::FraudulentInput { index })?;
+
+    if is_key_path_only(input) {
+        let (x_only_pk, _) = input
+            .tap_key_origins
+            .first_key_value()
+            .expect("is_key_path_only checks for one entry");
+        if Address::p2tr(secp, *x_only_pk, None, network) != address {
+            return Err(Error::FraudulentInput { index });
+        }
+        return Ok(address);
+    }
+
+    let internal_key = input
+        .tap_internal_key
+        .ok_or(Error::MissingTapInternalKey { index })?;
+    let output_key = output_key(&txout.script_pubkey).ok_or(Error::FraudulentInput { index })?;
+
+    let mut leaves = Vec::with_capacity(input.tap_scripts.len());
+    for (control_block, (script, leaf_version)) in &input.tap_scripts {
+        if control_block.internal_key != internal_key
+            || control_block.leaf_version != *leaf_version
+            || !control_block.verify_taproot_commitment(secp, output_key, script)
+        {
+            return Err(Error::InvalidTapScript { index });
+        }
+        leaves.push((
+            TapLeafHash::from_script(script, *leaf_version),
+            script.as_script(),
+        ));
+    }
+
+    // The control blocks prove the leaves, the merkle root (or its absence)
+    // proves there is nothing else hidden in the tree.
+    if (input.tap_merkle_root.is_some() || input.tap_scripts.is_empty())
+        && !commits_to(secp, internal_key, input.tap_merkle_root, output_key)
+    {
+        return Err(Error::InvalidTapScript { index });
+    }
+
+    if !validate_tree(&input.tap_key_origins, internal_key, &leaves) {
+        return Err(Error::FraudulentInput { index });
+    }
+
+    Ok(address)
+}
+
 /// Validate a Pay to Taproot (P2TR) output.
 ///
 /// # Notes
 ///
-/// - This only supports single signature addresses based on BIP-0086.
+/// - Single key outputs must be BIP-0086 addresses.
+/// - Outputs with a script tree must commit to their internal key and
+///   `tap_tree`, they are suspicious unless all the keys share the same
+///   derivation path.
 pub fn validate_output<C>(
     secp: &Secp256k1<C>,
     output: &psbt::Output,
@@ -24,27 +123,139 @@ pub fn validate_output<C>(
 where
     C: Verification,
 {
-    // Only single-sig support for now.
-    if output.tap_key_origins.len() != 1 {
-        return Err(Error::MultipleKeysNotExpected { index });
+    if is_single_key(
+        &output.tap_key_origins,
+        output.tap_internal_key,
+        output.tap_tree.is_none(),
+    ) {
+        let (x_only_pk, (_, source)) = output
+            .tap_key_origins
+            .first_key_value()
+            .expect("is_single_key checks for one entry");
+
+        let address = Address::p2tr(secp, *x_only_pk, None, network);
+        if !address.matches_script_pubkey(&txout.script_pubkey) {
+            return Err(Error::FraudulentOutput { index });
+        }
+
+        return Ok(PsbtOutput {
+            amount: txout.value,
+            kind: OutputKind::from_derivation_path(&source.1, 86, network, address)?,
+        });
     }
 
-    let (x_only_pk, (_, source)) = output
-        .tap_key_origins
-        .first_key_value()
-        .expect("the previous statement checks for at least one entry");
+    let internal_key = output
+        .tap_internal_key
+        .ok_or(Error::MissingTapInternalKey { index })?;
+    let output_key = output_key(&txout.script_pubkey).ok_or(Error::FraudulentOutput { index })?;
+    let merkle_root = output
+        .tap_tree
+        .as_ref()
+        .map(|tap_tree| tap_tree.node_info().node_hash());
+    if !commits_to(secp, internal_key, merkle_root, output_key) {
+        return Err(Error::InvalidTapScript { index });
+    }
 
-    let address = Address::p2tr(secp, *x_only_pk, None, network);
-    if !address.matches_script_pubkey(&txout.script_pubkey) {
+    let leaves = output
+        .tap_tree
+        .iter()
+        .flat_map(|tap_tree| tap_tree.script_leaves())
+        .map(|leaf| {
+            (
+                TapLeafHash::from_script(leaf.script(), leaf.version()),
+                leaf.script(),
+            )
+        })
+        .collect::<Vec<_>>();
+    if !validate_tree(&output.tap_key_origins, internal_key, &leaves) {
         return Err(Error::FraudulentOutput { index });
     }
 
+    let address = Address::from_script(&txout.script_pubkey, network.params())
+        .map_err(|_| Error::FraudulentOutput { index })?;
+
+    let (_, (_, (_, path))) = output
+        .tap_key_origins
+        .first_key_value()
+        .ok_or(Error::ExpectedKeys { index })?;
+    let are_paths_equal = output
+        .tap_key_origins
+        .values()
+        .all(|(_, (_, other_path))| other_path == path);
+    let kind = match purpose(path) {
+        Some(purpose) if are_paths_equal => {
+            OutputKind::from_derivation_path(path, purpose, network, address)?
+        }
+        _ => OutputKind::Suspicious(address),
+    };
+
     Ok(PsbtOutput {
         amount: txout.value,
-        kind: OutputKind::from_derivation_path(&source.1, 86, network, address)?,
+        kind,
+    })
+}
+
+/// Validate the keys of a taproot script tree.
+///
+/// `leaves` are the leaf hashes and scripts already proven to be committed to
+/// by the output key. Every key in `tap_key_origins` must either be the
+/// internal key, if it lists no leaves, or appear in each leaf it lists.
+/// Leaves not in `leaves` aren't proven and make the tree invalid.
+fn validate_tree(
+    tap_key_origins: &TapKeyOrigins,
+    internal_key: XOnlyPublicKey,
+    leaves: &[(TapLeafHash, &Script)],
+) -> bool {
+    tap_key_origins.iter().all(|(x_only_pk, (leaf_hashes, _))| {
+        if leaf_hashes.is_empty() {
+            return *x_only_pk == internal_key;
+        }
+
+        leaf_hashes.iter().all(|leaf_hash| {
+            leaves
+                .iter()
+                .find(|(hash, _)| hash == leaf_hash)
+                .is_some_and(|(_, script)| script_has_key(script, x_only_pk))
+        })
     })
 }
 
+/// Returns true if `internal_key` tweaked with `merkle_root` is `output_key`.
+fn commits_to<C>(
+    secp: &Secp256k1<C>,
+    internal_key: XOnlyPublicKey,
+    merkle_root: Option<TapNodeHash>,
+    output_key: XOnlyPublicKey,
+) -> bool
+where
+    C: Verification,
+{
+    internal_key.tap_tweak(secp, merkle_root).0.to_inner() == output_key
+}
+
+fn script_has_key(script: &Script, x_only_pk: &XOnlyPublicKey) -> bool {
+    let serialized = x_only_pk.serialize();
+    script.instructions().any(|instruction| {
+        matches!(instruction, Ok(Instruction::PushBytes(bytes)) if bytes.as_bytes() == serialized)
+    })
+}
+
+/// The output key of a P2TR script pubkey.
+fn output_key(script_pubkey: &Script) -> Option<XOnlyPublicKey> {
+    if !script_pubkey.is_p2tr() {
+        return None;
+    }
+
+    XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).ok()
+}
+
+fn purpose(path: &DerivationPath) -> Option<u32> {
+    match path.as_ref().first()? {
+        ChildNumber::Hardened { index } => Some(*index),
+        ChildNumber::Normal { .. } => None,
+    }
+}
+
 /// Compute the account descriptor for P2TR from the `path` derivation path.
 pub fn descriptor<C>(
     secp: &Secp256k1<C>,
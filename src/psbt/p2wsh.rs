@@ -1,4 +1,7 @@
 use crate::bip32::NgAccountPath;
+use crate::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use crate::psbt::{Error, OutputKind, PsbtOutput, sort_keys};
 use bdk_wallet::bitcoin::bip32::{ChildNumber, DerivationPath, KeySource, Xpub};
 use bdk_wallet::bitcoin::psbt;
@@ -8,7 +11,6 @@ use bdk_wallet::descriptor::{Descriptor, ExtendedDescriptor, Segwitv0};
 use bdk_wallet::keys::DescriptorPublicKey;
 use bdk_wallet::miniscript::descriptor::{DescriptorXKey, Wildcard, Wsh};
 use bdk_wallet::miniscript::{ForEachKey, Miniscript};
-use std::collections::BTreeMap;
 
 /// Validate a Pay to Witness Script Hash (P2WSH).
 pub fn validate_output(
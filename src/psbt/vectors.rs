@@ -0,0 +1,172 @@
+//! PSBT validation test vectors.
+//!
+//! A [`TestVector`] is a PSBT, the master key and network it is validated
+//! with, and the expected outcome: a [`DetailsSnapshot`] of the
+//! [`TransactionDetails`] or the error message. The crate's own vectors
+//! live in `tests/psbt_vectors`, and the firmware runs the same files
+//! through [`TestVector::run`] to check it validates PSBTs like we do.
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::psbt::{Error, OutputKind, TransactionDetails, validate, validate_network};
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::bip32::Xpriv;
+use bdk_wallet::bitcoin::psbt::Psbt;
+use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing, Verification};
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub name: String,
+    pub description: String,
+    /// The PSBT, in base64.
+    pub psbt: String,
+    /// The master key validating the PSBT.
+    pub master_key: String,
+    pub network: Network,
+    pub expected: Outcome,
+}
+
+/// Outcome of the validation of a PSBT.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Details(DetailsSnapshot),
+    /// The message of the validation error.
+    Error(String),
+}
+
+/// [`TransactionDetails`] in a form that can be stored and compared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetailsSnapshot {
+    pub total_with_self_send: u64,
+    pub total_self_send: u64,
+    pub total_non_change_self_send: u64,
+    pub fee: u64,
+    /// Sorted, with their checksum.
+    pub descriptors: Vec<String>,
+    pub inputs: Vec<InputSnapshot>,
+    pub outputs: Vec<OutputSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputSnapshot {
+    pub amount: u64,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKindSnapshot {
+    Change,
+    Transfer,
+    External,
+    Suspicious,
+    OpReturn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSnapshot {
+    pub amount: u64,
+    pub kind: OutputKindSnapshot,
+    /// Every kind of output but OP_RETURNs has an address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// The account of a transfer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<u32>,
+}
+
+impl From<&TransactionDetails> for DetailsSnapshot {
+    fn from(details: &TransactionDetails) -> Self {
+        let mut descriptors: Vec<String> = details
+            .descriptors
+            .iter()
+            .map(|descriptor| descriptor.to_string())
+            .collect();
+        descriptors.sort();
+
+        Self {
+            total_with_self_send: details.total_with_self_send.to_sat(),
+            total_self_send: details.total_self_send.to_sat(),
+            total_non_change_self_send: details.total_non_change_self_send.to_sat(),
+            fee: details.fee.to_sat(),
+            descriptors,
+            inputs: details
+                .inputs
+                .iter()
+                .map(|input| InputSnapshot {
+                    amount: input.amount.to_sat(),
+                    address: input.address.to_string(),
+                })
+                .collect(),
+            outputs: details
+                .outputs
+                .iter()
+                .map(|output| {
+                    let (kind, account) = match output.kind {
+                        OutputKind::Change(_) => (OutputKindSnapshot::Change, None),
+                        OutputKind::Transfer { account, .. } => {
+                            (OutputKindSnapshot::Transfer, Some(account))
+                        }
+                        OutputKind::External(_) => (OutputKindSnapshot::External, None),
+                        OutputKind::Suspicious(_) => (OutputKindSnapshot::Suspicious, None),
+                        OutputKind::OpReturn(_) => (OutputKindSnapshot::OpReturn, None),
+                    };
+                    OutputSnapshot {
+                        amount: output.amount.to_sat(),
+                        kind,
+                        address: output.to_address().map(|address| address.to_string()),
+                        account,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A vector that can't be run, as opposed to one whose PSBT fails to
+/// validate.
+#[derive(Debug, thiserror::Error)]
+pub enum VectorError {
+    #[error("invalid PSBT in vector {name}")]
+    InvalidPsbt { name: String },
+    #[error("invalid master key in vector {name}")]
+    InvalidMasterKey { name: String },
+}
+
+impl TestVector {
+    /// Validate the PSBT like a signer would: its network first, then its
+    /// inputs and outputs against the master key.
+    pub fn run<C>(&self, secp: &Secp256k1<C>) -> Result<Outcome, VectorError>
+    where
+        C: Signing + Verification,
+    {
+        let psbt = Psbt::from_str(&self.psbt).map_err(|_| VectorError::InvalidPsbt {
+            name: self.name.clone(),
+        })?;
+        let master_key =
+            Xpriv::from_str(&self.master_key).map_err(|_| VectorError::InvalidMasterKey {
+                name: self.name.clone(),
+            })?;
+        let details =
+            validate_network(&psbt).and_then(|_| validate(secp, &master_key, &psbt, self.network));
+        Ok(outcome(details))
+    }
+
+    /// Returns true if validating the PSBT gives the expected outcome.
+    pub fn passes<C>(&self, secp: &Secp256k1<C>) -> Result<bool, VectorError>
+    where
+        C: Signing + Verification,
+    {
+        Ok(self.run(secp)? == self.expected)
+    }
+}
+
+fn outcome(details: Result<TransactionDetails, Error>) -> Outcome {
+    match details {
+        Ok(details) => Outcome::Details(DetailsSnapshot::from(&details)),
+        Err(e) => Outcome::Error(e.to_string()),
+    }
+}
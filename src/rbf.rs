@@ -1,13 +1,16 @@
 use crate::account::NgAccount;
+use crate::error::{MutexExt, RwLockExt};
 #[cfg(feature = "envoy")]
-use crate::fee_rate::FeeRateSatPerKvb;
+use crate::estimate;
 use crate::fee_rate::FeeRateSatPerKwu;
+#[cfg(feature = "envoy")]
+use crate::fee_rate::{FeeEstimator, FeeRateSatPerKvb};
 use crate::ngwallet::NgWallet;
 use crate::rbf::BumpFeeError::ComposeTxError;
 use crate::send::DraftTransaction;
 #[cfg(feature = "envoy")]
-use crate::send::TransactionFeeResult;
-use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output};
+use crate::send::{DEFAULT_MAX_FEE_RATE, recommended_fee_rate};
+use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output, TxType};
 use anyhow::Result;
 #[cfg(feature = "envoy")]
 use bdk_core::bitcoin::policy::DEFAULT_INCREMENTAL_RELAY_FEE;
@@ -20,10 +23,10 @@ use bdk_wallet::bitcoin::{Address, Amount, OutPoint, Psbt, Sequence, Txid};
 use bdk_wallet::error::CreateTxError::CoinSelection;
 use bdk_wallet::error::{BuildFeeBumpError, CreateTxError};
 use bdk_wallet::miniscript::psbt::PsbtExt;
-#[cfg(feature = "envoy")]
-use bdk_wallet::psbt::PsbtUtils;
 use bdk_wallet::{AddForeignUtxoError, KeychainKind, SignOptions, WalletPersister};
 use log::info;
+#[cfg(feature = "envoy")]
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 #[derive(Debug)]
@@ -45,6 +48,41 @@ pub enum BumpFeeError {
     UnableToAccessWallet,
     UnableToAddForeignUtxo(AddForeignUtxoError),
     LockedUtxoSelected(Vec<String>),
+    /// The account is locked, or its spending policy doesn't allow the
+    /// replacement, see [`crate::policy`].
+    SigningRefused(anyhow::Error),
+}
+
+/// An unconfirmed outgoing transaction paying less than the current
+/// estimate, with its ready-to-sign replacement.
+#[cfg(feature = "envoy")]
+#[derive(Debug)]
+pub struct FeeBumpSuggestion {
+    pub tx_id: String,
+    pub fee_rate: FeeRateSatPerKvb,
+    /// The estimate, or the minimum replacement fee rate if higher.
+    pub bumped_fee_rate: FeeRateSatPerKvb,
+    pub draft_transaction: DraftTransaction,
+}
+
+/// Fee bounds of a replacement, from [`NgAccount::get_max_bump_fee`].
+#[cfg(feature = "envoy")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BumpFeeBounds {
+    pub max_fee_rate: FeeRateSatPerKvb,
+    /// The BIP-125 minimum fee rate, the rate of
+    /// [`Self::draft_transaction`].
+    pub min_fee_rate: FeeRateSatPerKvb,
+    /// The estimate for confirmation within
+    /// [`RECOMMENDED_TARGET_BLOCKS`](crate::send::RECOMMENDED_TARGET_BLOCKS),
+    /// between the minimum and the maximum. `None` without an estimate.
+    pub recommended_fee_rate: Option<FeeRateSatPerKvb>,
+    /// The BIP-125 floor: the fee of the original transaction plus the
+    /// incremental relay fee for its size, in sats.
+    pub min_fee: u64,
+    /// The most the replacement can pay, in sats.
+    pub max_fee: u64,
+    pub draft_transaction: DraftTransaction,
 }
 
 // TODO: chore: cleanup duplicate code
@@ -53,8 +91,7 @@ impl<P: WalletPersister> NgAccount<P> {
     fn get_address(&self, key_chain: KeychainKind) -> AddressInfo {
         self.get_coordinator_wallet()
             .bdk_wallet
-            .lock()
-            .unwrap()
+            .lock_or_recover()
             .reveal_next_address(key_chain)
     }
 
@@ -85,142 +122,196 @@ impl<P: WalletPersister> NgAccount<P> {
         )
     }
 
+    /// Replacements of the unconfirmed outgoing transactions paying less
+    /// than `fee_estimator` expects for confirmation within `target_blocks`.
+    /// Transactions that can't be bumped (not signaling RBF, locked change,
+    /// not enough funds, refused by the spending policy) are left out.
     #[cfg(feature = "envoy")]
-    pub fn get_max_bump_fee(
+    pub fn suggest_fee_bumps(
         &self,
-        selected_outputs: Vec<Output>,
-        bitcoin_transaction: BitcoinTransaction,
-    ) -> Result<TransactionFeeResult, BumpFeeError> {
-        let unspend_outputs = self.utxos().unwrap();
-        //check if transaction is output is locked
-        for unspend_output in unspend_outputs.clone() {
-            if unspend_output.tx_id == bitcoin_transaction.clone().tx_id
-                && unspend_output.do_not_spend
+        target_blocks: u16,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Vec<FeeBumpSuggestion>, BumpFeeError> {
+        let estimate = fee_estimator
+            .estimate(target_blocks)
+            .map_err(|_| BumpFeeError::FeeRateUnavailable)?;
+        let transactions = self
+            .transactions()
+            .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+        let utxos = self
+            .utxos()
+            .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+
+        let mut suggestions = vec![];
+        for transaction in transactions {
+            if transaction.is_confirmed
+                || transaction.amount >= 0
+                || transaction.fee_rate >= estimate
             {
-                return Err(BumpFeeError::ChangeOutputLocked);
+                continue;
+            }
+            if utxos
+                .iter()
+                .any(|utxo| utxo.tx_id == transaction.tx_id && utxo.do_not_spend)
+            {
+                continue;
             }
-        }
-
-        let min_sats_per_vb = Self::get_minimum_rbf_fee_rate(&bitcoin_transaction);
-
-        let mut receive_amount = bitcoin_transaction.amount.unsigned_abs();
 
-        //self spend
-        if bitcoin_transaction.fee == (bitcoin_transaction.amount.unsigned_abs()) {
-            for output in bitcoin_transaction.clone().outputs {
-                if let Some(KeyChain::External) = output.keychain {
-                    receive_amount = output.amount;
+            let bumped_fee_rate = estimate.max(FeeRateSatPerKvb::from(
+                Self::get_minimum_rbf_fee_rate(&transaction),
+            ));
+            match self.get_rbf_draft_tx(
+                vec![],
+                transaction.clone(),
+                FeeRateSatPerKwu::from(bumped_fee_rate),
+                None,
+                None,
+                transaction.get_change_tag(),
+                transaction.note.clone(),
+            ) {
+                Ok(draft_transaction) => suggestions.push(FeeBumpSuggestion {
+                    tx_id: transaction.tx_id.clone(),
+                    fee_rate: transaction.fee_rate,
+                    bumped_fee_rate,
+                    draft_transaction,
+                }),
+                Err(BumpFeeError::ComposeBumpTxError(_))
+                | Err(BumpFeeError::ComposeTxError(CoinSelection(_)))
+                | Err(BumpFeeError::InsufficientFunds)
+                | Err(BumpFeeError::IrreplaceableTransaction(_))
+                | Err(BumpFeeError::LockedUtxoSelected(_))
+                | Err(BumpFeeError::SigningRefused(_)) => {
+                    info!("Can't bump {}", transaction.tx_id);
                 }
+                Err(e) => return Err(e),
             }
         }
+        Ok(suggestions)
+    }
 
-        // will keep updating until the maximum fee boundary is found
-        let mut max_fee: Option<u64> = None;
-
-        // sets max fee rate to 1000 sat/vB; this will eventually fail, revealing the available amount.
-        let mut max_fee_rate = FeeRateSatPerKwu::from_sat_per_vb(1000);
+    /// Fee bounds of the replacement of `bitcoin_transaction`, and the
+    /// replacement at the minimum fee rate.
+    ///
+    /// The replacement keeps the inputs and recipients of the original and
+    /// drops its change. At the maximum fee it also spends every confirmed
+    /// spendable output, or only `selected_outputs` if any, on fees.
+    #[cfg(feature = "envoy")]
+    pub fn get_max_bump_fee(
+        &self,
+        selected_outputs: Vec<Output>,
+        bitcoin_transaction: BitcoinTransaction,
+        fee_estimator: Option<&dyn FeeEstimator>,
+    ) -> Result<BumpFeeBounds, BumpFeeError> {
+        let utxos = self
+            .utxos()
+            .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+        //check if transaction is output is locked
+        if utxos
+            .iter()
+            .any(|utxo| utxo.tx_id == bitcoin_transaction.tx_id && utxo.do_not_spend)
+        {
+            return Err(BumpFeeError::ChangeOutputLocked);
+        }
+        let tx_id = Txid::from_str(&bitcoin_transaction.tx_id)
+            .map_err(|_| BumpFeeError::TransactionNotFound())?;
 
-        let mut tries = 0;
-        loop {
-            tries += 1;
-            if tries > 8 {
-                return Err(BumpFeeError::FeeRateUnavailable);
-            }
-            if max_fee.is_some() {
-                //try creating a psbt with max fee
-                match self.get_rbf_bump_psbt(
-                    selected_outputs.clone(),
-                    bitcoin_transaction.clone(),
-                    //placeholder since max_fee will be used
-                    FeeRateSatPerKwu::from_sat_per_vb(1),
-                    max_fee,
-                    None,
-                ) {
-                    Ok(psbt) => match psbt.fee_rate() {
-                        None => {
-                            return Err(BumpFeeError::ChangeOutputLocked);
-                        }
-                        Some(r) => {
-                            max_fee_rate = FeeRateSatPerKwu::from_bdk(r);
-                            break;
-                        }
-                    },
-                    Err(e) => match e {
-                        ComposeTxError(error) => match error {
-                            CreateTxError::FeeTooLow { required } => {
-                                max_fee = Some(required.to_sat());
-                            }
-                            CreateTxError::FeeRateTooLow { required } => {
-                                max_fee_rate = FeeRateSatPerKwu::from_bdk(required)
-                                    + FeeRateSatPerKwu::from_sat_per_vb(1);
-                                max_fee = None;
-                            }
-                            CoinSelection(error) => {
-                                info!(
-                                    "Error while composing bump tx {:?} {:?}",
-                                    error.clone().available.to_sat(),
-                                    error.clone().needed.to_sat()
-                                );
-                                max_fee = Some(error.available.to_sat() - (receive_amount));
-                            }
-                            _ => {
-                                return Err(ComposeTxError(error));
-                            }
-                        },
-                        _err => {
-                            return Err(_err);
-                        }
-                    },
-                }
-                info!("Max fee set to: {} ", max_fee.unwrap());
-            } else {
-                //use minimum
-                match self.get_rbf_bump_psbt(
-                    selected_outputs.clone(),
-                    bitcoin_transaction.clone(),
-                    max_fee_rate,
-                    None,
-                    None,
-                ) {
-                    Ok(psbt) => match psbt.fee_rate() {
-                        None => {
-                            return Err(BumpFeeError::ChangeOutputLocked);
-                        }
-                        Some(r) => {
-                            max_fee_rate = FeeRateSatPerKwu::from_bdk(r);
-                            break;
-                        }
-                    },
-                    Err(e) => match e {
-                        ComposeTxError(error) => match error {
-                            CreateTxError::FeeTooLow { required } => {
-                                max_fee = Some(required.to_sat());
-                            }
-                            CreateTxError::FeeRateTooLow { required } => {
-                                max_fee_rate = FeeRateSatPerKwu::from_bdk(required)
-                                    + FeeRateSatPerKwu::from_sat_per_vb(1);
-                                max_fee = None;
-                            }
-                            CoinSelection(error) => {
-                                max_fee = Some(
-                                    error.available.to_sat()
-                                        - (receive_amount + bitcoin_transaction.fee),
-                                );
-                                info!("Max fee set to: {} ", max_fee.unwrap());
-                            }
-                            _ => {
-                                return Err(ComposeTxError(error));
-                            }
-                        },
-                        _err => {
-                            return Err(_err);
-                        }
-                    },
-                }
+        let transactions = self
+            .transactions()
+            .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+        let confirmed: Vec<Output> = utxos
+            .into_iter()
+            .filter(|utxo| {
+                transactions
+                    .iter()
+                    .any(|tx| tx.tx_id == utxo.tx_id && tx.is_confirmed)
+            })
+            .collect();
+        let mut do_not_spend_utxos = vec![];
+        let mut spendables = vec![];
+        Self::filter_spendable_and_do_not_spendables(
+            selected_outputs.clone(),
+            confirmed,
+            &mut do_not_spend_utxos,
+            &mut spendables,
+        )
+        .map_err(BumpFeeError::LockedUtxoSelected)?;
+        // weighing the inputs locks the wallets holding them
+        let extra_inputs = spendables
+            .into_iter()
+            .map(|output| self.weigh_input(output))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+
+        let (replacement, original_inputs) = {
+            let wallets = self
+                .all_wallets()
+                .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+            let wallet_index = Self::find_outgoing_wallet_index(&wallets, tx_id);
+            let bdk_wallet = wallets
+                .get(wallet_index)
+                .ok_or(BumpFeeError::UnableToAccessWallet)?
+                .bdk_wallet
+                .lock()
+                .map_err(|_| BumpFeeError::UnableToAccessWallet)?;
+            let mut replacement = bdk_wallet
+                .get_tx(tx_id)
+                .ok_or(BumpFeeError::TransactionNotFound())?
+                .tx_node
+                .tx
+                .as_ref()
+                .clone();
+            let fee = bdk_wallet
+                .calculate_fee(&replacement)
+                .map_err(|_| BumpFeeError::TransactionNotFound())?;
+            let original_inputs = replacement
+                .output
+                .iter()
+                .map(|output| output.value)
+                .sum::<Amount>()
+                + fee;
+
+            // the change output, as BDK finds it when bumping the fee
+            let change_keychain = match bdk_wallet.keychains().count() {
+                1 => KeychainKind::External,
+                _ => KeychainKind::Internal,
+            };
+            if replacement.output.len() > 1
+                && let Some(change) = replacement.output.iter().position(|output| {
+                    bdk_wallet
+                        .derivation_of_spk(output.script_pubkey.clone())
+                        .is_some_and(|(keychain, _)| keychain == change_keychain)
+                })
+            {
+                replacement.output.remove(change);
             }
+            (replacement, original_inputs.to_sat())
+        };
+
+        let recipients: u64 = replacement
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum();
+        let extra: u64 = extra_inputs.iter().map(|input| input.output.amount).sum();
+        let max_fee = (original_inputs + extra).saturating_sub(recipients);
+        let weight = extra_inputs
+            .iter()
+            .map(estimate::input_weight)
+            .fold(replacement.weight(), |total, weight| total + weight);
+
+        let min_fee = Self::get_minimum_rbf_fee(&bitcoin_transaction);
+        if max_fee < min_fee {
+            return Err(BumpFeeError::InsufficientFunds);
+        }
+        let max_fee_rate = FeeRateSatPerKwu(max_fee.saturating_mul(1000) / weight.to_wu().max(1))
+            .min(FeeRateSatPerKwu::from_bdk(DEFAULT_MAX_FEE_RATE));
+
+        let min_sats_per_vb = Self::get_minimum_rbf_fee_rate(&bitcoin_transaction);
+        if max_fee_rate < min_sats_per_vb {
+            return Err(BumpFeeError::InsufficientFunds);
         }
         let tx = self.get_rbf_draft_tx(
-            selected_outputs.clone(),
+            selected_outputs,
             bitcoin_transaction.clone(),
             min_sats_per_vb,
             None,
@@ -229,9 +320,14 @@ impl<P: WalletPersister> NgAccount<P> {
             bitcoin_transaction.note.clone(),
         )?;
 
-        Ok(TransactionFeeResult {
-            max_fee_rate: FeeRateSatPerKvb::from(max_fee_rate),
-            min_fee_rate: tx.transaction.fee_rate,
+        let max_fee_rate = FeeRateSatPerKvb::from(max_fee_rate);
+        let min_fee_rate = FeeRateSatPerKvb::from(min_sats_per_vb);
+        Ok(BumpFeeBounds {
+            max_fee_rate,
+            min_fee_rate,
+            recommended_fee_rate: recommended_fee_rate(fee_estimator, min_fee_rate, max_fee_rate),
+            min_fee,
+            max_fee,
             draft_transaction: tx,
         })
     }
@@ -354,11 +450,14 @@ impl<P: WalletPersister> NgAccount<P> {
                             vout: input.previous_output.vout,
                             amount: out.amount,
                             tag: input_tag,
+                            address: Some(out.address.clone()),
+                            is_mine: out.keychain.is_some(),
+                            keychain: out.keychain,
                         }
                     })
                     .collect::<Vec<Input>>();
 
-                let transaction = Self::transform_psbt_to_bitcointx(
+                let mut transaction = Self::transform_psbt_to_bitcointx(
                     psbt.clone(),
                     address.clone().to_string(),
                     new_outputs.clone(),
@@ -366,6 +465,12 @@ impl<P: WalletPersister> NgAccount<P> {
                     rbf_note.clone(),
                     current_transaction.account_id.clone(),
                 );
+                let keychains: Vec<Option<KeyChain>> = new_outputs
+                    .iter()
+                    .map(|output| output.keychain.clone())
+                    .collect();
+                transaction.tx_type =
+                    TxType::classify(&psbt.unsigned_tx, true, &keychains, false, true);
 
                 let input_tags: Vec<String> = inputs
                     .clone()
@@ -379,6 +484,9 @@ impl<P: WalletPersister> NgAccount<P> {
                     input_tags,
                     change_out_put_tag,
                     transaction,
+                    warnings: vec![],
+                    privacy: None,
+                    foreign_contribution: None,
                 })
             }
             Err(er) => Err(er),
@@ -398,7 +506,7 @@ impl<P: WalletPersister> NgAccount<P> {
         let tx_id = Txid::from_str(bitcoin_transaction.clone().tx_id.as_str())
             .map_err(|_| BumpFeeError::TransactionNotFound())?;
 
-        let wallets = self.wallets.read().unwrap();
+        let wallets = self.all_wallets_or_recover();
         let wallet_index = Self::find_outgoing_wallet_index(&wallets, tx_id);
 
         let psbt = {
@@ -485,14 +593,20 @@ impl<P: WalletPersister> NgAccount<P> {
             }
             tx_builder.finish()
         };
+        drop(wallets);
         match psbt {
             Ok(mut psbt) => {
+                // the replacement may pay its inputs anywhere with drain_to
+                if let Err(e) = self.check_can_sign(&psbt) {
+                    self.cancel_tx(psbt).unwrap();
+                    return Err(BumpFeeError::SigningRefused(e));
+                }
                 let sign_options = SignOptions {
                     trust_witness_utxo: true,
                     ..Default::default()
                 };
                 Self::sign_psbt(
-                    self.wallets.read().unwrap().clone(),
+                    self.all_wallets_or_recover().clone(),
                     &mut psbt,
                     sign_options,
                 );
@@ -507,8 +621,8 @@ impl<P: WalletPersister> NgAccount<P> {
     }
 
     fn derivation_of_spk(&self, script_buf: ScriptBuf) -> Option<(KeychainKind, u32)> {
-        for ng_wallets in self.wallets.read().unwrap().iter() {
-            let wallet = ng_wallets.bdk_wallet.lock().unwrap();
+        for ng_wallets in self.all_wallets_or_recover().iter() {
+            let wallet = ng_wallets.bdk_wallet.lock_or_recover();
             if let Some(derivation) = wallet.derivation_of_spk(script_buf.clone()) {
                 return Some(derivation);
             }
@@ -516,7 +630,7 @@ impl<P: WalletPersister> NgAccount<P> {
         None
     }
     fn network(&self) -> Network {
-        for ng_wallets in self.wallets.read().unwrap().iter() {
+        for ng_wallets in self.wallets.read_or_recover().iter() {
             if let Ok(bdk_wallet) = ng_wallets.bdk_wallet.lock() {
                 return bdk_wallet.network();
             }
@@ -530,7 +644,7 @@ impl<P: WalletPersister> NgAccount<P> {
     fn find_outgoing_wallet_index(wallets: &[NgWallet<P>], tx_id: Txid) -> usize {
         let mut wallet_index = 0;
         for (index, wallet) in wallets.iter().enumerate() {
-            let wallet = wallet.bdk_wallet.lock().unwrap();
+            let wallet = wallet.bdk_wallet.lock_or_recover();
             let tx = wallet.get_tx(tx_id);
             if let Some(tx) = tx {
                 let (sent, received) = wallet.sent_and_received(&tx.tx_node.tx);
@@ -544,16 +658,21 @@ impl<P: WalletPersister> NgAccount<P> {
         wallet_index
     }
 
+    /// The BIP-125 absolute fee floor of a replacement: the fee of the
+    /// original plus the incremental relay fee for its size, in sats.
+    #[cfg(feature = "envoy")]
+    fn get_minimum_rbf_fee(transaction: &BitcoinTransaction) -> u64 {
+        let relay_fee_per_vb = (DEFAULT_INCREMENTAL_RELAY_FEE / 1000) as u64; // 1 sat/vB
+        transaction.fee + transaction.vsize as u64 * relay_fee_per_vb
+    }
+
     #[cfg(feature = "envoy")]
     fn get_minimum_rbf_fee_rate(transaction: &BitcoinTransaction) -> FeeRateSatPerKwu {
-        let original_fee = transaction.fee; // fee is sats
         let original_vsize = transaction.vsize as u64;
         let relay_fee_per_vb = (DEFAULT_INCREMENTAL_RELAY_FEE / 1000) as u64; // 1 sat/vB
 
-        // calculate the minimum additional fee for RBF
-        let min_additional_fee = original_vsize * relay_fee_per_vb;
-        let min_replacement_fee = original_fee + min_additional_fee;
-        let min_sat_per_vb = min_replacement_fee.div_ceil(original_vsize);
+        let min_replacement_fee = Self::get_minimum_rbf_fee(transaction);
+        let min_sat_per_vb = min_replacement_fee.div_ceil(original_vsize.max(1));
 
         // Sanity check: ensure the fee rate meets or exceeds the network minimum
         // If the transaction paid 1 sat/vb,
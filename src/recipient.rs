@@ -0,0 +1,128 @@
+//! Checking a pasted or scanned recipient.
+//!
+//! [`NgAccount::validate_recipient`] takes a plain address or a BIP-21
+//! `bitcoin:` URI and reports everything the send screen warns about in a
+//! single call: an address of another network, a script type the account
+//! doesn't know, a payment to the account itself or to an address that was
+//! already paid.
+
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::address::NetworkUnchecked;
+use bdk_wallet::bitcoin::{Address, Amount, Denomination};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::addresses::has_received_to;
+use crate::config::AddressType;
+use crate::error::{AccountError, MutexExt, RwLockExt};
+
+const BIP21_SCHEME: &str = "bitcoin:";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipientInfo {
+    /// The address alone, out of the URI it was given in.
+    pub address: String,
+    /// False if the address is for another network than the account.
+    pub network_ok: bool,
+    /// `None` for witness versions without a known script type.
+    pub script_type: Option<AddressType>,
+    /// True if the address belongs to a wallet of the account.
+    pub is_own: bool,
+    /// True if a transaction of the account already paid to the address.
+    pub reused: bool,
+    /// Amount requested by the URI, in sats.
+    pub bip21_amount: Option<u64>,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Diagnostics of `address_or_uri`, an address or a BIP-21 URI. Fails if
+    /// no address can be read from it. An address of another network is
+    /// reported with `network_ok` false, and is never own nor reused.
+    pub fn validate_recipient(&self, address_or_uri: &str) -> Result<RecipientInfo> {
+        let (address, bip21_amount) = parse_bip21(address_or_uri.trim())?;
+        let unchecked: Address<NetworkUnchecked> =
+            Address::from_str(address).map_err(|_| AccountError::InvalidAddress)?;
+        let network = self.config.read_or_err()?.network;
+        let network_ok = unchecked.is_valid_for_network(network);
+        let address = unchecked.assume_checked();
+        let script_type = address.address_type().and_then(|t| t.try_into().ok());
+
+        let mut is_own = false;
+        let mut reused = false;
+        if network_ok {
+            let script = address.script_pubkey();
+            for wallet in self.all_wallets()?.iter() {
+                let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+                is_own |= bdk_wallet.is_mine(script.clone());
+                reused |= has_received_to(&bdk_wallet, &script);
+            }
+        }
+
+        Ok(RecipientInfo {
+            address: address.to_string(),
+            network_ok,
+            script_type,
+            is_own,
+            reused,
+            bip21_amount,
+        })
+    }
+}
+
+/// Address and amount of a BIP-21 URI, or `input` itself if it isn't one.
+/// Rejects the URIs with a required parameter this doesn't know, as BIP-21
+/// asks.
+fn parse_bip21(input: &str) -> Result<(&str, Option<u64>)> {
+    let Some(scheme) = input.get(..BIP21_SCHEME.len()) else {
+        return Ok((input, None));
+    };
+    if !scheme.eq_ignore_ascii_case(BIP21_SCHEME) {
+        return Ok((input, None));
+    }
+    let uri = &input[BIP21_SCHEME.len()..];
+    let (address, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+    let mut amount = None;
+    for (key, value) in query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| param.split_once('=').unwrap_or((param, "")))
+    {
+        if key.eq_ignore_ascii_case("amount") {
+            if amount.is_some() {
+                bail!("Payment URI has more than one amount");
+            }
+            let value = Amount::from_str_in(value, Denomination::Bitcoin)
+                .map_err(|e| anyhow!("Invalid payment URI amount: {e}"))?;
+            amount = Some(value.to_sat());
+        } else if key.starts_with("req-") {
+            bail!("Payment URI requires unsupported parameter {key}");
+        }
+    }
+    Ok((address, amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip21_uris_are_parsed() {
+        let address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        assert_eq!(parse_bip21(address).unwrap(), (address, None));
+        assert_eq!(
+            parse_bip21(&format!("bitcoin:{address}")).unwrap(),
+            (address, None)
+        );
+        assert_eq!(
+            parse_bip21(&format!("BITCOIN:{address}?label=Shop&amount=0.0005")).unwrap(),
+            (address, Some(50_000))
+        );
+        assert!(parse_bip21(&format!("bitcoin:{address}?amount=0.5&amount=1")).is_err());
+        assert!(parse_bip21(&format!("bitcoin:{address}?amount=lots")).is_err());
+        assert!(parse_bip21(&format!("bitcoin:{address}?req-pop=yes")).is_err());
+    }
+}
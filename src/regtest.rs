@@ -0,0 +1,280 @@
+//! Helpers for integration tests against a local regtest node.
+//!
+//! The integration tests used to scan live Signet and Testnet4 Electrum
+//! servers, so they failed whenever those were slow or down, and couldn't
+//! mine or fund anything. With the `testing` feature, [`RegtestNode`]
+//! drives a local bitcoind over JSON-RPC and waits for the electrs indexing
+//! it, to mine blocks, fund an [`NgAccount`] from the node's wallet and sync
+//! the account.
+//!
+//! The node is configured from the environment, see
+//! [`RegtestConfig::from_env`]. The defaults match
+//!
+//! ```text
+//! bitcoind -regtest -rpcuser=ngwallet -rpcpassword=ngwallet
+//! electrs --network regtest --cookie ngwallet:ngwallet
+//! ```
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+use bdk_electrum::electrum_client::{Client, ElectrumApi};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bdk_wallet::bitcoin::{Amount, BlockHash, Denomination, Network, Txid};
+use serde_json::{Value, json};
+
+use crate::account::NgAccount;
+use crate::error::RwLockExt;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+// How long electrs gets to index a block or a transaction.
+const INDEX_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Blocks to mine before the node's first coinbase can be spent.
+const COINBASE_MATURITY: u32 = 101;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegtestConfig {
+    /// `host:port` of the bitcoind JSON-RPC server.
+    pub rpc_address: String,
+    pub rpc_user: String,
+    pub rpc_password: String,
+    /// Wallet of the node that mines and funds accounts, created if needed.
+    pub wallet: String,
+    /// The electrs indexing the node, like `tcp://127.0.0.1:60401`.
+    pub electrum_server: String,
+}
+
+impl Default for RegtestConfig {
+    fn default() -> Self {
+        Self {
+            rpc_address: "127.0.0.1:18443".to_string(),
+            rpc_user: "ngwallet".to_string(),
+            rpc_password: "ngwallet".to_string(),
+            wallet: "ngwallet".to_string(),
+            electrum_server: "tcp://127.0.0.1:60401".to_string(),
+        }
+    }
+}
+
+impl RegtestConfig {
+    /// The defaults, overridden by the `NGWALLET_REGTEST_RPC`,
+    /// `NGWALLET_REGTEST_RPC_USER`, `NGWALLET_REGTEST_RPC_PASSWORD`,
+    /// `NGWALLET_REGTEST_WALLET` and `NGWALLET_REGTEST_ELECTRUM` variables.
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: String| std::env::var(name).unwrap_or(default);
+        let default = Self::default();
+        Self {
+            rpc_address: var("NGWALLET_REGTEST_RPC", default.rpc_address),
+            rpc_user: var("NGWALLET_REGTEST_RPC_USER", default.rpc_user),
+            rpc_password: var("NGWALLET_REGTEST_RPC_PASSWORD", default.rpc_password),
+            wallet: var("NGWALLET_REGTEST_WALLET", default.wallet),
+            electrum_server: var("NGWALLET_REGTEST_ELECTRUM", default.electrum_server),
+        }
+    }
+}
+
+pub struct RegtestNode {
+    config: RegtestConfig,
+}
+
+impl RegtestNode {
+    /// Connect to the node of `config`, which must run on regtest, and load
+    /// its wallet.
+    pub fn connect(config: RegtestConfig) -> Result<Self> {
+        let node = Self { config };
+
+        let chain = node.rpc("/", "getblockchaininfo", json!([]))?["chain"].clone();
+        if chain != "regtest" {
+            bail!("The node runs on {chain}, not regtest");
+        }
+
+        let wallet = node.config.wallet.as_str();
+        let loaded = node.rpc("/", "listwallets", json!([]))?;
+        if !json_array(&loaded).iter().any(|name| name == wallet) {
+            let on_disk = node.rpc("/", "listwalletdir", json!([]))?;
+            let exists = json_array(&on_disk["wallets"])
+                .iter()
+                .any(|entry| entry["name"] == wallet);
+            match exists {
+                true => node.rpc("/", "loadwallet", json!([wallet]))?,
+                false => node.rpc("/", "createwallet", json!([wallet]))?,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Connect to the node configured by the environment, see
+    /// [`RegtestConfig::from_env`].
+    pub fn from_env() -> Result<Self> {
+        Self::connect(RegtestConfig::from_env())
+    }
+
+    pub fn config(&self) -> &RegtestConfig {
+        &self.config
+    }
+
+    pub fn electrum_server(&self) -> &str {
+        &self.config.electrum_server
+    }
+
+    /// Call `method` of the node's wallet, or of the node itself.
+    pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+        self.rpc(&format!("/wallet/{}", self.config.wallet), method, params)
+    }
+
+    pub fn height(&self) -> Result<u32> {
+        let height = self.call("getblockcount", json!([]))?;
+        height
+            .as_u64()
+            .map(|height| height as u32)
+            .ok_or_else(|| anyhow!("Invalid block count {height}"))
+    }
+
+    /// Mine `blocks` to the node's wallet and wait for electrs to index
+    /// them.
+    pub fn mine_blocks(&self, blocks: u32) -> Result<Vec<BlockHash>> {
+        let address = self.call("getnewaddress", json!([]))?;
+        let hashes = self.call("generatetoaddress", json!([blocks, address]))?;
+        let hashes = json_array(&hashes)
+            .iter()
+            .map(|hash| {
+                let hash = hash.as_str().unwrap_or_default();
+                BlockHash::from_str(hash).with_context(|| format!("Invalid block hash {hash}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let height = self.height()?;
+        let client = self.electrum_client()?;
+        wait_for(&format!("block {height}"), || {
+            client
+                .block_headers_subscribe()
+                .is_ok_and(|header| header.height as u32 >= height)
+        })?;
+        Ok(hashes)
+    }
+
+    /// Send `amount` from the node's wallet to the next address of the
+    /// preferred address type of `account`, like a faucet. The transaction
+    /// is left unconfirmed, see [`Self::mine_blocks`].
+    pub fn fund<P: WalletPersister>(&self, account: &NgAccount<P>, amount: Amount) -> Result<Txid> {
+        let config = account.config.read_or_err()?.clone();
+        if config.network != Network::Regtest {
+            bail!("Account is on {}, not regtest", config.network);
+        }
+        let addresses = account.next_address()?;
+        let (address, _) = addresses
+            .iter()
+            .find(|(_, address_type)| *address_type == config.preferred_address_type)
+            .or(addresses.first())
+            .ok_or_else(|| anyhow!("Account has no wallet"))?;
+
+        if self.balance()? < amount {
+            self.mine_blocks(COINBASE_MATURITY)?;
+        }
+        if self.balance()? < amount {
+            bail!("The node's wallet can't fund {amount}");
+        }
+
+        let txid = self.call(
+            "sendtoaddress",
+            json!({
+                "address": address.address.to_string(),
+                "amount": amount.to_string_in(Denomination::Bitcoin),
+                "fee_rate": 2,
+            }),
+        )?;
+        let txid = Txid::from_str(txid.as_str().unwrap_or_default())
+            .with_context(|| format!("Invalid txid {txid}"))?;
+
+        let client = self.electrum_client()?;
+        wait_for(&format!("transaction {txid}"), || {
+            client.transaction_get(&txid).is_ok()
+        })?;
+        Ok(txid)
+    }
+
+    /// Full scan of every wallet of `account` against the node's electrs.
+    pub fn sync<P: WalletPersister>(&self, account: &NgAccount<P>) -> Result<()> {
+        account.rescan(&self.config.electrum_server, None, None)
+    }
+
+    // Spendable balance of the node's wallet.
+    fn balance(&self) -> Result<Amount> {
+        let balance = self.call("getbalance", json!([]))?;
+        let btc = balance
+            .as_f64()
+            .ok_or_else(|| anyhow!("Invalid balance {balance}"))?;
+        Ok(Amount::from_btc(btc)?)
+    }
+
+    fn electrum_client(&self) -> Result<Client> {
+        Client::new(&self.config.electrum_server)
+            .with_context(|| format!("Can't reach electrs at {}", self.config.electrum_server))
+    }
+
+    fn rpc(&self, path: &str, method: &str, params: Value) -> Result<Value> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "ngwallet",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        let auth = BASE64.encode(format!(
+            "{}:{}",
+            self.config.rpc_user, self.config.rpc_password
+        ));
+
+        let mut stream = TcpStream::connect(&self.config.rpc_address)
+            .with_context(|| format!("Can't reach bitcoind at {}", self.config.rpc_address))?;
+        stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+        // HTTP/1.0 so the reply isn't chunked and ends with the connection
+        write!(
+            stream,
+            "POST {path} HTTP/1.0\r\n\
+             Host: {}\r\n\
+             Authorization: Basic {auth}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            self.config.rpc_address,
+            body.len()
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| anyhow!("Malformed reply to {method}"))?;
+        let status = head.lines().next().unwrap_or_default();
+        let reply: Value =
+            serde_json::from_str(body).with_context(|| format!("{method} failed: {status}"))?;
+        if !reply["error"].is_null() {
+            bail!(
+                "{method} failed: {}",
+                reply["error"]["message"].as_str().unwrap_or(status)
+            );
+        }
+        Ok(reply["result"].clone())
+    }
+}
+
+fn json_array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn wait_for(what: &str, mut ready: impl FnMut() -> bool) -> Result<()> {
+    let start = Instant::now();
+    while !ready() {
+        if start.elapsed() > INDEX_TIMEOUT {
+            bail!("Timed out waiting for electrs to index {what}");
+        }
+        sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
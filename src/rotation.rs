@@ -0,0 +1,178 @@
+//! Watch-only wallets moving to new descriptors.
+//!
+//! A hardware wallet reset with a new passphrase derives other keys for the
+//! same script types, and the descriptors of its watch-only account silently
+//! stop matching. [`NgAccount::descriptor_status`] tells a descriptor sent by
+//! the device apart from the one of the wallet of its script type, and
+//! [`NgAccount::replace_descriptor`] moves the wallet to it.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use bdk_wallet::bitcoin::NetworkKind;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::descriptor::ExtendedDescriptor;
+use bdk_wallet::keys::DescriptorPublicKey;
+use bdk_wallet::miniscript::ForEachKey;
+use bdk_wallet::{KeychainKind, WalletPersister};
+
+use crate::account::{Descriptor, NgAccount};
+use crate::config::{AddressType, NgDescriptor};
+use crate::error::{AccountError, RwLockExt};
+use crate::ngwallet::NgWallet;
+use crate::utils::{self, get_address_type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorStatus {
+    /// The descriptor of the wallet of its script type.
+    Unchanged,
+    /// Another descriptor of the script type of a wallet, the keys of the
+    /// device changed.
+    Rotated,
+    /// The account has no wallet of its script type.
+    New,
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// How `internal`, a descriptor sent by the device, compares with the
+    /// wallet of its script type.
+    pub fn descriptor_status(&self, internal: &str) -> Result<DescriptorStatus> {
+        let address_type = get_address_type(internal);
+        let normalized = utils::normalize_descriptor(internal)?;
+        let config = self.config.read_or_err()?;
+        let status = match config
+            .descriptors
+            .iter()
+            .find(|descriptor| descriptor.address_type == address_type)
+        {
+            None => DescriptorStatus::New,
+            Some(descriptor)
+                if utils::normalize_descriptor(&descriptor.internal)
+                    .is_ok_and(|internal| internal == normalized) =>
+            {
+                DescriptorStatus::Unchanged
+            }
+            Some(_) => DescriptorStatus::Rotated,
+        };
+        Ok(status)
+    }
+
+    /// Move the watch-only wallet of `address_type` to new public
+    /// descriptors, its wallet created in `bdk_persister`. The old persister
+    /// keeps the old wallet.
+    ///
+    /// The old descriptors go to
+    /// [`crate::config::NgAccountConfig::retired_descriptors`], and notes,
+    /// tags and fiat values stay with the transactions and outputs they
+    /// were set on. The verified addresses and the sync checkpoint of the
+    /// old wallet are forgotten, the new one needs a full scan. The config
+    /// and these changes are written in a single batch.
+    pub fn replace_descriptor(
+        &self,
+        address_type: AddressType,
+        new_internal: String,
+        new_external: Option<String>,
+        bdk_persister: Arc<Mutex<P>>,
+    ) -> Result<()> {
+        let network = {
+            let config = self.config.read_or_err()?;
+            if config.multisig.is_some() || config.vault.is_some() {
+                bail!("Multisig accounts are migrated to a new quorum instead");
+            }
+            if config.has_private_descriptors() {
+                bail!("Only watch-only wallets get new descriptors");
+            }
+            if !config
+                .descriptors
+                .iter()
+                .any(|descriptor| descriptor.address_type == address_type)
+            {
+                return Err(AccountError::WalletNotFound(address_type).into());
+            }
+            config.network
+        };
+        if get_address_type(&new_internal) != address_type {
+            bail!("Descriptor is not of address type {address_type:?}");
+        }
+        let secp = Secp256k1::new();
+        let network_kind = NetworkKind::from(network);
+        for string in new_external.iter().chain([&new_internal]) {
+            let (parsed, keymap) = ExtendedDescriptor::parse_descriptor(&secp, string)?;
+            if !keymap.is_empty() {
+                bail!("New descriptors must be public");
+            }
+            let on_network = parsed.for_each_key(|key| match key {
+                DescriptorPublicKey::XPub(xkey) => xkey.xkey.network == network_kind,
+                DescriptorPublicKey::MultiXPub(xkey) => xkey.xkey.network == network_kind,
+                DescriptorPublicKey::Single(_) => true,
+            });
+            if !on_network {
+                bail!("Descriptor is not on {network}");
+            }
+        }
+        if self.descriptor_status(&new_internal)? == DescriptorStatus::Unchanged {
+            return Err(AccountError::DescriptorExists.into());
+        }
+
+        self.open_wallet(address_type)?;
+        let wallet = NgWallet::new_from_descriptor(
+            new_internal.clone(),
+            new_external.clone(),
+            network,
+            self.meta_storage.clone(),
+            bdk_persister.clone(),
+        )?;
+        {
+            let mut config = self.config.write_or_err()?;
+            let mut updated = config.clone();
+            let Some(descriptor) = updated
+                .descriptors
+                .iter_mut()
+                .find(|descriptor| descriptor.address_type == address_type)
+            else {
+                return Err(AccountError::WalletNotFound(address_type).into());
+            };
+            let export_addr_hint = descriptor.export_addr_hint;
+            let retired = std::mem::replace(
+                descriptor,
+                NgDescriptor {
+                    internal: new_internal.clone(),
+                    external: new_external.clone(),
+                    address_type,
+                    export_addr_hint,
+                },
+            );
+            updated.retired_descriptors.push(retired);
+
+            let storage = self.meta_storage.as_ref();
+            crate::store::with_batch(storage, |storage| {
+                storage.set_config(&updated.serialize())?;
+                for keychain in [KeychainKind::External, KeychainKind::Internal] {
+                    storage.set_last_verified_address(address_type, keychain, 0)?;
+                }
+                storage.set_verification_search(address_type, None)?;
+                storage.remove_sync_checkpoint(address_type)
+            })?;
+
+            let mut wallets = self.wallets.write_or_err()?;
+            if let Some(old) = wallets
+                .iter_mut()
+                .find(|wallet| wallet.address_type == address_type)
+            {
+                old.persist()?;
+                *old = wallet;
+            }
+            *config = updated;
+        }
+        self.set_lazy_descriptor(
+            address_type,
+            Descriptor {
+                internal: new_internal,
+                external: new_external,
+                bdk_persister,
+            },
+        )?;
+        self.persist()?;
+        Ok(())
+    }
+}
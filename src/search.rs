@@ -0,0 +1,294 @@
+//! Search of the account's history.
+//!
+//! [`NgAccount::search`] matches a query against the transactions, unspent
+//! outputs and tags of the account, so the app's search bar doesn't pull
+//! the whole history to filter it. Matching is case insensitive, and an
+//! exact match ranks above a prefix match, which ranks above a substring
+//! match.
+
+use std::cmp::Reverse;
+
+use anyhow::Result;
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::{Amount, Denomination};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::transaction::{BitcoinTransaction, Output};
+
+/// How well a field matched, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Substring,
+}
+
+/// The field that matched. Between matches of the same kind, the first
+/// fields rank higher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SearchField {
+    TxId,
+    Address,
+    Note,
+    Tag,
+    Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// The transaction id, or `txid:vout` of an output.
+    pub id: String,
+    /// Best matching field.
+    pub field: SearchField,
+    pub kind: MatchKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchResults {
+    /// Best match first, then newest first.
+    pub transactions: Vec<SearchHit>,
+    /// Unspent outputs, best match first, then newest first.
+    pub utxos: Vec<SearchHit>,
+    /// Tag names, best match first.
+    pub tags: Vec<String>,
+}
+
+struct Query {
+    text: String,
+    // the query read as an amount, in sats or in BTC if it has a decimal
+    // point
+    sats: Option<u64>,
+}
+
+impl Query {
+    fn new(query: &str) -> Self {
+        let text = query.trim().to_lowercase();
+        let sats = match text.contains('.') {
+            true => Amount::from_str_in(&text, Denomination::Bitcoin)
+                .ok()
+                .map(Amount::to_sat),
+            false => text.parse().ok(),
+        };
+        Self { text, sats }
+    }
+
+    fn text(&self, text: &str) -> Option<MatchKind> {
+        let text = text.to_lowercase();
+        if text == self.text {
+            Some(MatchKind::Exact)
+        } else if text.starts_with(&self.text) {
+            Some(MatchKind::Prefix)
+        } else if text.contains(&self.text) {
+            Some(MatchKind::Substring)
+        } else {
+            None
+        }
+    }
+
+    // Amounts match exactly, or by their leading digits in sats.
+    fn amount(&self, sats: u64) -> Option<MatchKind> {
+        if self.sats == Some(sats) {
+            return Some(MatchKind::Exact);
+        }
+        let digits = self.text.bytes().all(|byte| byte.is_ascii_digit());
+        (digits && sats.to_string().starts_with(&self.text)).then_some(MatchKind::Prefix)
+    }
+}
+
+// Best of the fields that matched.
+fn best(
+    matches: impl IntoIterator<Item = (SearchField, Option<MatchKind>)>,
+) -> Option<(MatchKind, SearchField)> {
+    matches
+        .into_iter()
+        .filter_map(|(field, kind)| Some((kind?, field)))
+        .min()
+}
+
+fn tx_match(query: &Query, tx: &BitcoinTransaction) -> Option<(MatchKind, SearchField)> {
+    let addresses = std::iter::once(tx.address.as_str())
+        .chain(tx.outputs.iter().map(|output| output.address.as_str()))
+        .filter(|address| !address.is_empty())
+        .map(|address| (SearchField::Address, query.text(address)));
+    let tags = tx
+        .outputs
+        .iter()
+        .filter_map(|output| output.tag.as_deref())
+        .chain(tx.inputs.iter().filter_map(|input| input.tag.as_deref()))
+        .map(|tag| (SearchField::Tag, query.text(tag)));
+    best(
+        [
+            (SearchField::TxId, query.text(&tx.tx_id)),
+            (
+                SearchField::Note,
+                tx.note.as_deref().and_then(|n| query.text(n)),
+            ),
+            (SearchField::Amount, query.amount(tx.amount.unsigned_abs())),
+        ]
+        .into_iter()
+        .chain(addresses)
+        .chain(tags),
+    )
+}
+
+fn utxo_match(query: &Query, utxo: &Output) -> Option<(MatchKind, SearchField)> {
+    best([
+        (SearchField::TxId, query.text(&utxo.get_id())),
+        (SearchField::Address, query.text(&utxo.address)),
+        (
+            SearchField::Tag,
+            utxo.tag.as_deref().and_then(|t| query.text(t)),
+        ),
+        (SearchField::Amount, query.amount(utxo.amount)),
+    ])
+}
+
+// Hits sorted by match, then newest first, pending ones being the newest.
+fn ranked(mut hits: Vec<(MatchKind, SearchField, Option<u64>, String)>) -> Vec<SearchHit> {
+    hits.sort_by_key(|(kind, field, date, _)| (*kind, *field, Reverse(date.unwrap_or(u64::MAX))));
+    hits.into_iter()
+        .map(|(kind, field, _, id)| SearchHit { id, field, kind })
+        .collect()
+}
+
+/// Search `query` in `transactions`, `utxos` and `tags`. An empty query
+/// matches nothing.
+pub fn search(
+    query: &str,
+    transactions: &[BitcoinTransaction],
+    utxos: &[Output],
+    tags: &[String],
+) -> SearchResults {
+    let query = Query::new(query);
+    if query.text.is_empty() {
+        return SearchResults::default();
+    }
+
+    let transactions = transactions
+        .iter()
+        .filter_map(|tx| {
+            let (kind, field) = tx_match(&query, tx)?;
+            Some((kind, field, tx.date, tx.tx_id.clone()))
+        })
+        .collect();
+    let utxos = utxos
+        .iter()
+        .filter_map(|utxo| {
+            let (kind, field) = utxo_match(&query, utxo)?;
+            Some((kind, field, utxo.date, utxo.get_id()))
+        })
+        .collect();
+    let mut tags: Vec<(MatchKind, &String)> = tags
+        .iter()
+        .filter_map(|tag| Some((query.text(tag)?, tag)))
+        .collect();
+    tags.sort();
+
+    SearchResults {
+        transactions: ranked(transactions),
+        utxos: ranked(utxos),
+        tags: tags.into_iter().map(|(_, tag)| tag.clone()).collect(),
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Transactions, unspent outputs and tags matching `query`, see
+    /// [`search`].
+    pub fn search(&self, query: &str) -> Result<SearchResults> {
+        Ok(search(
+            query,
+            &self.transactions()?,
+            &self.utxos()?,
+            &self.list_tags()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::KeyChain;
+    use crate::transaction::fixtures;
+
+    fn output(tx_id: &str, address: &str, amount: u64, tag: Option<&str>) -> Output {
+        Output {
+            amount,
+            tag: tag.map(str::to_string),
+            date: Some(1),
+            keychain: Some(KeyChain::External),
+            ..fixtures::output(tx_id, 0, address)
+        }
+    }
+
+    fn tx(tx_id: &str, date: u64, note: Option<&str>, outputs: Vec<Output>) -> BitcoinTransaction {
+        BitcoinTransaction {
+            amount: outputs.iter().map(|output| output.amount as i64).sum(),
+            outputs,
+            note: note.map(str::to_string),
+            date: Some(date),
+            ..fixtures::tx(tx_id)
+        }
+    }
+
+    #[test]
+    fn matches_are_ranked() {
+        let transactions = vec![
+            tx(
+                "aa11",
+                1,
+                Some("Rent for May"),
+                vec![output("aa11", "tb1qrent", 150_000, Some("Bills"))],
+            ),
+            tx(
+                "bb22",
+                2,
+                Some("rent"),
+                vec![output("bb22", "tb1qother", 100_000, None)],
+            ),
+            tx(
+                "cc33",
+                3,
+                None,
+                vec![output("cc33", "tb1qcurrent", 2_000, None)],
+            ),
+        ];
+        let utxos: Vec<Output> = transactions
+            .iter()
+            .flat_map(|tx| tx.outputs.clone())
+            .collect();
+        let tags = vec!["Bills".to_string(), "Rentals".to_string()];
+
+        let results = search("RENT", &transactions, &utxos, &tags);
+        let ids: Vec<&str> = results
+            .transactions
+            .iter()
+            .map(|hit| hit.id.as_str())
+            .collect();
+        // exact note, then prefix of a note and of an address, then a
+        // substring of an address
+        assert_eq!(ids, vec!["bb22", "aa11", "cc33"]);
+        assert_eq!(results.transactions[0].field, SearchField::Note);
+        assert_eq!(results.transactions[1].kind, MatchKind::Prefix);
+        assert_eq!(results.tags, vec!["Rentals"]);
+
+        // amounts in BTC or sats
+        let results = search("0.0015", &transactions, &utxos, &tags);
+        assert_eq!(results.utxos[0].id, "aa11:0");
+        assert_eq!(results.utxos[0].kind, MatchKind::Exact);
+        let results = search("100", &transactions, &utxos, &tags);
+        assert_eq!(
+            results
+                .transactions
+                .iter()
+                .map(|hit| hit.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["bb22"]
+        );
+
+        assert_eq!(
+            search(" ", &transactions, &utxos, &tags),
+            SearchResults::default()
+        );
+    }
+}
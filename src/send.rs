@@ -1,32 +1,61 @@
-use crate::ngwallet::NgWallet;
-use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output};
+use crate::ngwallet::{FEE_UNKNOWN, NgWallet};
+use crate::privacy::{self, PrivacyReport};
+use crate::psbt::memo::PsbtMemo;
+use crate::transaction::{BitcoinTransaction, Input, KeyChain, Output, TxKind, TxType};
 use anyhow::{Context, Result};
 use bdk_core::bitcoin::Sequence;
-use bdk_wallet::bitcoin::psbt::ExtractTxError;
+use bdk_core::bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
+use bdk_wallet::bitcoin::consensus::serialize;
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, sha256};
 use bdk_wallet::bitcoin::secp256k1::Secp256k1;
 use bdk_wallet::bitcoin::{
-    Address, Amount, FeeRate, Psbt, ScriptBuf, Transaction, TxIn, Txid, Weight, psbt,
+    Address, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Transaction, TxIn, TxOut, Txid, Weight,
+    psbt,
 };
 use bdk_wallet::coin_selection::InsufficientFunds;
+use bdk_wallet::descriptor::policy::SatisfiableItem;
 use bdk_wallet::error::CreateTxError;
 use bdk_wallet::error::CreateTxError::CoinSelection;
 use bdk_wallet::miniscript::psbt::PsbtExt;
 use bdk_wallet::psbt::PsbtUtils;
-use bdk_wallet::{KeychainKind, PersistedWallet, SignOptions, TxOrdering, WalletPersister};
+use bdk_wallet::{KeychainKind, PersistedWallet, SignOptions, TxOrdering, Wallet, WalletPersister};
 use core::fmt;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::str::FromStr;
-use std::sync::MutexGuard;
+use std::sync::{Arc, MutexGuard};
 
 use crate::account::NgAccount;
-#[cfg(feature = "envoy")]
+use crate::addresses::has_received_to;
+use crate::error::{MutexExt, PolicyViolation, RwLockExt};
+use crate::estimate;
+use crate::spk_index;
+use crate::tags::restrict_to_tag;
 use crate::utils;
+use crate::vault::SpendPathSelection;
 #[cfg(feature = "envoy")]
 use bdk_electrum::electrum_client::Error;
 
 /// from bdk_wallet
+/// Confirmation target of the recommended fee rate of
+/// [`TransactionFeeResult`].
+pub const RECOMMENDED_TARGET_BLOCKS: u16 = 6;
+
+/// The recommended fee rate for confirmation within
+/// [`RECOMMENDED_TARGET_BLOCKS`], clamped to `min..=max`.
+pub(crate) fn recommended_fee_rate(
+    fee_estimator: Option<&dyn FeeEstimator>,
+    min: FeeRateSatPerKvb,
+    max: FeeRateSatPerKvb,
+) -> Option<FeeRateSatPerKvb> {
+    let estimate = fee_estimator?
+        .estimate(RECOMMENDED_TARGET_BLOCKS)
+        .inspect_err(|e| info!("No fee estimate: {e:?}"))
+        .ok()?;
+    Some(estimate.min(max).max(min))
+}
+
 /// From [`FeeRate`], the maximum fee rate that is used for extracting transactions.
 /// The default `max_fee_rate` value used for extracting transactions with [`extract_tx`]
 ///
@@ -34,6 +63,19 @@ use bdk_electrum::electrum_client::Error;
 /// 1000 sats/vByte. 25k sats/vByte is obviously a mistake at this point.
 pub const DEFAULT_MAX_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(25_000);
 
+/// Fee rates above this are almost certainly a mistake and trigger
+/// [`TxWarning::AbsurdFeeRate`].
+pub const ABSURD_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1_000);
+
+/// Change worth less than this many times the fee needed to spend it later
+/// triggers [`TxWarning::UneconomicalChange`].
+pub const UNECONOMICAL_CHANGE_FACTOR: u64 = 3;
+
+/// Tag of the change output sent to another account with
+/// [`TransactionParams::change_address`].
+pub const TRANSFER_TAG: &str = "Transfer";
+
+use crate::fee_rate::FeeEstimator;
 pub use crate::fee_rate::{FeeRateSatPerKvb, FeeRateSatPerKwu};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,12 +85,112 @@ pub struct DraftTransaction {
     pub change_out_put_tag: Option<String>,
     pub input_tags: Vec<String>,
     pub is_finalized: bool,
+    #[serde(default)]
+    pub warnings: Vec<TxWarning>,
+    #[serde(default)]
+    pub privacy: Option<PrivacyReport>,
+    /// What the inputs of other parties put in, see
+    /// [`TransactionParams::foreign_inputs`].
+    #[serde(default)]
+    pub foreign_contribution: Option<ForeignContribution>,
+}
+
+/// Value of the foreign inputs of a transaction, and their share of its
+/// fee. The account pays the rest of the fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForeignContribution {
+    pub amount: u64,
+    pub fee: u64,
+}
+
+/// Issues found while composing a transaction that the user should review
+/// before signing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxWarning {
+    /// The change output is below the dust limit for its script type.
+    DustChange { amount: u64 },
+    /// Spending the change output later would cost a large part of its value.
+    UneconomicalChange { amount: u64, spend_fee: u64 },
+    /// The recipient address already received funds from this account.
+    AddressReuse { address: String },
+    /// The recipient is an address of this account that was already used.
+    UsedOwnAddress { address: String },
+    /// The fee rate is far above what the network requires.
+    AbsurdFeeRate { fee_rate: FeeRateSatPerKvb },
+}
+
+/// A server to broadcast through, see [`NgAccount::broadcast_to_all`].
+#[cfg(any(feature = "envoy", feature = "esplora"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastEndpoint {
+    #[cfg(feature = "envoy")]
+    Electrum {
+        url: String,
+        validate_domain: Option<bool>,
+    },
+    #[cfg(feature = "esplora")]
+    Esplora { url: String },
+}
+
+#[cfg(any(feature = "envoy", feature = "esplora"))]
+impl BroadcastEndpoint {
+    pub fn url(&self) -> &str {
+        match self {
+            #[cfg(feature = "envoy")]
+            Self::Electrum { url, .. } => url,
+            #[cfg(feature = "esplora")]
+            Self::Esplora { url } => url,
+        }
+    }
+
+    fn broadcast(&self, transaction: &Transaction, socks_proxy: Option<&str>) -> Result<()> {
+        match self {
+            #[cfg(feature = "envoy")]
+            Self::Electrum {
+                url,
+                validate_domain,
+            } => {
+                utils::build_electrum_client(url, socks_proxy, *validate_domain)?
+                    .transaction_broadcast(transaction)?;
+            }
+            #[cfg(feature = "esplora")]
+            Self::Esplora { url } => {
+                utils::build_esplora_client(url, socks_proxy)
+                    .broadcast(transaction)
+                    .map_err(|e| anyhow::anyhow!("Esplora broadcast failed: {}", e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of [`NgAccount::broadcast_to_all`].
+#[cfg(any(feature = "envoy", feature = "esplora"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastReport {
+    pub txid: Txid,
+    /// Index of the first endpoint that accepted the transaction.
+    pub accepted_by: Option<usize>,
+    /// Error returned by each endpoint that rejected the transaction or
+    /// couldn't be reached before one accepted it, by endpoint index.
+    pub errors: BTreeMap<usize, String>,
+}
+
+#[cfg(any(feature = "envoy", feature = "esplora"))]
+impl BroadcastReport {
+    pub fn is_success(&self) -> bool {
+        self.accepted_by.is_some()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransactionFeeResult {
     pub max_fee_rate: FeeRateSatPerKvb,
+    /// The minimum relay fee rate of the network.
     pub min_fee_rate: FeeRateSatPerKvb,
+    /// The estimate for confirmation within [`RECOMMENDED_TARGET_BLOCKS`],
+    /// between the minimum and the maximum. `None` without an estimate.
+    pub recommended_fee_rate: Option<FeeRateSatPerKvb>,
     pub draft_transaction: DraftTransaction,
 }
 
@@ -61,6 +203,142 @@ pub struct TransactionParams {
     pub note: Option<String>,
     pub tag: Option<String>,
     pub do_not_spend_change: bool,
+    pub ordering: OutputOrdering,
+    /// Address of another of the user's accounts to send the change to,
+    /// see [`NgAccount::transfer_address`]. `None` keeps the change in this
+    /// account.
+    pub change_address: Option<String>,
+    /// Key spending the inputs of a vault account, see
+    /// [`crate::vault::VaultDetails`].
+    pub spend_path: SpendPath,
+    /// Inputs of other parties, with the weight of their satisfaction.
+    /// Their value goes to the recipient on top of `amount`, less their
+    /// share of the fee.
+    pub foreign_inputs: Vec<(OutPoint, psbt::Input, Weight)>,
+    pub fee_sharing: FeeSharing,
+    /// Spend only outputs with this tag, compared case insensitively.
+    /// Outputs with other tags or none are left out of coin selection, and
+    /// selecting one fails like selecting a locked output.
+    pub spend_from_tag: Option<String>,
+    /// Carry `note` and `tag` in the PSBT, see [`PsbtMemo`], so they come
+    /// back with it from the signer.
+    pub embed_memo: bool,
+}
+
+/// Who pays the fee of a transaction with foreign inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeSharing {
+    /// The account pays the whole fee.
+    #[default]
+    Ours,
+    /// The other parties pay for the weight of their inputs.
+    ByInputWeight,
+}
+
+/// Spending path of a vault account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum SpendPath {
+    /// The primary key, spending at any time.
+    #[default]
+    Primary,
+    /// The recovery key, once the inputs are buried under the vault delay.
+    Recovery,
+}
+
+impl SpendPath {
+    /// Policy path choosing this spending path in wallets whose policy is
+    /// one of two branches, `None` for the other wallets.
+    pub(crate) fn policy_path(
+        self,
+        wallet: &Wallet,
+        keychain: KeychainKind,
+    ) -> Option<BTreeMap<String, Vec<usize>>> {
+        let policy = wallet.policies(keychain).ok()??;
+        match policy.item {
+            SatisfiableItem::Thresh {
+                ref items,
+                threshold: 1,
+            } if items.len() == 2 => {
+                let branch = match self {
+                    SpendPath::Primary => 0,
+                    SpendPath::Recovery => 1,
+                };
+                Some(BTreeMap::from([(policy.id, vec![branch])]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Order of the inputs and outputs of a composed transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum OutputOrdering {
+    /// Random order, so the change output can't be told apart by position.
+    #[default]
+    Shuffle,
+    /// Pseudo random order derived from `seed`. Composing the same
+    /// transaction with the same seed gives byte identical PSBTs, across
+    /// devices too.
+    SeededShuffle { seed: u64 },
+    /// BIP-69 lexicographic order.
+    Bip69,
+    /// Inputs and outputs in the order coin selection added them.
+    Untouched,
+}
+
+type Sort<T> = Arc<dyn Fn(&T, &T) -> std::cmp::Ordering + Send + Sync>;
+
+impl OutputOrdering {
+    pub(crate) fn to_bdk(self) -> TxOrdering {
+        match self {
+            OutputOrdering::Shuffle => TxOrdering::Shuffle,
+            OutputOrdering::Untouched => TxOrdering::Untouched,
+            OutputOrdering::Bip69 => {
+                // txids compare in their displayed, reversed byte order
+                let input_sort: Sort<TxIn> = Arc::new(|a, b| {
+                    let a_txid = a.previous_output.txid.to_byte_array();
+                    let b_txid = b.previous_output.txid.to_byte_array();
+                    a_txid
+                        .iter()
+                        .rev()
+                        .cmp(b_txid.iter().rev())
+                        .then(a.previous_output.vout.cmp(&b.previous_output.vout))
+                });
+                let output_sort: Sort<TxOut> = Arc::new(|a, b| {
+                    a.value
+                        .cmp(&b.value)
+                        .then_with(|| a.script_pubkey.as_bytes().cmp(b.script_pubkey.as_bytes()))
+                });
+                TxOrdering::Custom {
+                    input_sort: Some(input_sort),
+                    output_sort: Some(output_sort),
+                }
+            }
+            OutputOrdering::SeededShuffle { seed } => {
+                let input_sort: Sort<TxIn> = Arc::new(move |a, b| {
+                    let a = seeded_hash(seed, &serialize(&a.previous_output));
+                    let b = seeded_hash(seed, &serialize(&b.previous_output));
+                    a.cmp(&b)
+                });
+                let output_sort: Sort<TxOut> = Arc::new(move |a, b| {
+                    seeded_hash(seed, &serialize(a)).cmp(&seeded_hash(seed, &serialize(b)))
+                });
+                TxOrdering::Custom {
+                    input_sort: Some(input_sort),
+                    output_sort: Some(output_sort),
+                }
+            }
+        }
+    }
+}
+
+fn seeded_hash(seed: u64, data: &[u8]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&seed.to_le_bytes());
+    engine.input(data);
+    sha256::Hash::from_engine(engine)
 }
 
 #[derive(Debug)]
@@ -69,6 +347,15 @@ pub enum TransactionComposeError {
     WalletError(String),
     Error(String),
     LockedUtxoSelected(Vec<String>),
+    PolicyViolation(PolicyViolation),
+    /// The recipient amount is below the dust limit of its script, see
+    /// [`NgAccount::dust_policy`].
+    DustAmount {
+        amount: u64,
+        dust_limit: u64,
+    },
+    /// The account holds keys and is locked, see [`NgAccount::lock`].
+    AccountLocked,
 }
 
 impl fmt::Display for TransactionComposeError {
@@ -80,36 +367,45 @@ impl fmt::Display for TransactionComposeError {
             TransactionComposeError::LockedUtxoSelected(ids) => {
                 write!(f, "LockedUtxoSelected: {}", ids.join(", "))
             }
+            TransactionComposeError::PolicyViolation(e) => write!(f, "PolicyViolation: {e}"),
+            TransactionComposeError::DustAmount { amount, dust_limit } => write!(
+                f,
+                "DustAmount: {amount} sats is below the dust limit of {dust_limit} sats"
+            ),
+            TransactionComposeError::AccountLocked => write!(f, "AccountLocked"),
         }
     }
 }
 
+impl std::error::Error for TransactionComposeError {}
+
 // TODO: chore: cleanup duplicate code
 impl<P: WalletPersister> NgAccount<P> {
     //noinspection RsExternalLinter
     pub fn get_max_fee(
         &self,
         transaction_params: TransactionParams,
+        fee_estimator: Option<&dyn FeeEstimator>,
     ) -> Result<TransactionFeeResult, TransactionComposeError> {
+        if !transaction_params.foreign_inputs.is_empty() {
+            return Err(TransactionComposeError::Error(
+                "The maximum fee is not computed with foreign inputs".into(),
+            ));
+        }
         let utxos = self
             .utxos()
             .map_err(|e| TransactionComposeError::Error(format!("Failed to get UTXOs: {e:?}")))?;
-        let coordinator_ng_wallet = self.get_coordinator_wallet();
-        let mut coordinator_wallet = coordinator_ng_wallet
-            .bdk_wallet
-            .lock()
-            .map_err(|_| TransactionComposeError::WalletError("Failed to lock wallet".into()))?;
+        let utxos = restrict_to_tag(utxos, transaction_params.spend_from_tag.as_deref());
         let param = transaction_params.clone();
         let address = param.address;
         let default_fee = param.fee_rate;
         let selected_outputs = param.selected_outputs;
         let amount = param.amount;
-
-        let address = Address::from_str(&address)
-            .map_err(|_| TransactionComposeError::Error("Invalid address format".into()))?
-            .require_network(coordinator_wallet.network())
-            .map_err(|_| TransactionComposeError::Error("Address network mismatch".into()))?;
-        let script: ScriptBuf = address.clone().into();
+        let sent = match self.owns_address(&address) {
+            Ok(true) => 0,
+            _ => amount,
+        };
+        let sign = self.signs_alone(sent);
 
         //do not spend
         let mut do_not_spend_utxos: Vec<Output> = vec![];
@@ -139,142 +435,95 @@ impl<P: WalletPersister> NgAccount<P> {
             )));
         }
 
-        //clippy is wrong about max_fee unused_assignments
-        #[allow(unused_assignments)]
-        let mut max_fee = spendable_balance - amount;
-
-        //clippy is wrong about  max_fee_rate unused_assignments
-        #[allow(unused_assignments)]
-        let mut max_fee_rate = FeeRateSatPerKwu::from_sat_per_vb(1); // 1 sat/vB
+        // weighing the inputs locks the wallets holding them
+        let inputs = spendables
+            .iter()
+            .map(|utxo| self.weigh_input(utxo.clone()))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| TransactionComposeError::Error(format!("{e:?}")))?;
 
-        let mut receive_amount = amount;
+        let coordinator_ng_wallet = self.get_coordinator_wallet();
+        let mut coordinator_wallet = coordinator_ng_wallet
+            .bdk_wallet
+            .lock()
+            .map_err(|_| TransactionComposeError::WalletError("Failed to lock wallet".into()))?;
+        let address = Address::from_str(&address)
+            .map_err(|_| TransactionComposeError::Error("Invalid address format".into()))?
+            .require_network(coordinator_wallet.network())
+            .map_err(|_| TransactionComposeError::Error("Address network mismatch".into()))?;
+        let script: ScriptBuf = address.clone().into();
+        let dust_limit = self.dust_policy().dust_limit(&script);
+        if amount < dust_limit {
+            return Err(TransactionComposeError::DustAmount { amount, dust_limit });
+        }
         //if user is trying to sweep in order to find the max fee we set receive to min spend…
         //amount which is dust limit
-        if spendable_balance == amount {
-            receive_amount = 573; //dust limit
-        }
-
-        // Fix 4: Use saturating_sub to prevent underflow
-        max_fee = spendable_balance.saturating_sub(receive_amount);
+        let receive_amount = match spendable_balance == amount {
+            true => dust_limit,
+            false => amount,
+        };
+        let max_fee = spendable_balance.saturating_sub(receive_amount);
         if max_fee == 0 {
             return Err(TransactionComposeError::Error(
                 "Insufficient funds for fee calculation".into(),
             ));
         }
-
-        // Add a maximum iteration count to prevent infinite loops
-        let mut iterations = 0;
-        let max_iterations = 20;
-
-        loop {
-            iterations += 1;
-            if iterations > max_iterations {
-                return Err(TransactionComposeError::Error(
-                    "Failed to calculate maximum fee rate after maximum iterations".into(),
-                ));
-            }
-
+        let change_script =
+            self.get_change_script(&coordinator_wallet, param.change_address.as_deref())?;
+        self.check_spend_path(param.spend_path)?;
+
+        // The highest fee spends every spendable output and leaves no
+        // change, so its rate is at most the fee over the weight of that
+        // transaction. BDK rounds the fee of the transaction and of each
+        // input up on their own, which costs up to a sat each: the highest
+        // rate coin selection accepts is searched for between the two.
+        let weight = estimate::tx_weight(&inputs, &[&script]).to_wu().max(1);
+        let mut high = max_fee.saturating_mul(1000) / weight;
+        let mut low = max_fee
+            .saturating_sub(inputs.len() as u64 + 1)
+            .saturating_mul(1000)
+            / weight;
+        while low < high {
+            let rate = low + (high - low).div_ceil(2);
             let psbt = self.prepare_psbt(
                 &mut coordinator_wallet,
                 script.clone(),
                 &mut spendables,
                 &mut do_not_spend_utxos,
-                Some(max_fee),
                 None,
+                Some(FeeRate::from_sat_per_kwu(rate)),
                 receive_amount,
                 false,
+                param.ordering,
+                change_script.clone(),
+                param.spend_path,
+                &[],
             );
-
             match psbt {
-                Ok(mut psbt) => {
-                    let sign_options = SignOptions {
-                        trust_witness_utxo: true,
-                        try_finalize: true,
-                        ..Default::default()
-                    };
-                    // Always try signing
-                    let _ = coordinator_wallet
-                        .sign(&mut psbt, sign_options.clone())
-                        .is_ok();
-                    coordinator_wallet.cancel_tx(&psbt.clone().unsigned_tx);
-                    Self::sign_psbt(
-                        self.non_coordinator_wallets(),
-                        &mut psbt,
-                        sign_options.clone(),
-                    );
-
-                    match psbt.clone().extract_tx_fee_rate_limit() {
-                        Ok(..) => {
-                            max_fee_rate = FeeRateSatPerKwu::from_bdk(
-                                psbt.fee_rate()
-                                    .unwrap_or(FeeRate::from_sat_per_vb_unchecked(1)),
-                            );
-                            if max_fee_rate < FeeRateSatPerKwu::from_sat_per_vb(1) {
-                                max_fee_rate = FeeRateSatPerKwu::from_sat_per_vb(1);
-                            }
-                            break;
-                        }
-                        Err(error) => match error {
-                            ExtractTxError::AbsurdFeeRate { .. } => {
-                                max_fee_rate = FeeRateSatPerKwu::from_bdk(DEFAULT_MAX_FEE_RATE);
-                                break;
-                            }
-                            ExtractTxError::MissingInputValue { .. } => {
-                                max_fee_rate = FeeRateSatPerKwu::from_bdk(
-                                    psbt.fee_rate()
-                                        .unwrap_or(FeeRate::from_sat_per_vb_unchecked(1)),
-                                );
-                                break;
-                            }
-                            ExtractTxError::SendingTooMuch { psbt } => {
-                                max_fee_rate = FeeRateSatPerKwu::from_bdk(
-                                    psbt.fee_rate()
-                                        .unwrap_or(FeeRate::from_sat_per_vb_unchecked(1)),
-                                );
-                                break;
-                            }
-                            _er => {
-                                info!("Error calculating fee rate: {_er:?}");
-                                max_fee = max_fee.saturating_sub(receive_amount);
-                                if max_fee == 0 {
-                                    return Err(TransactionComposeError::Error(
-                                        "Cannot calculate fee: available amount too low".into(),
-                                    ));
-                                }
-                            }
-                        },
-                    }
-                    if let Some(r) = psbt.fee_rate() {
-                        max_fee_rate = FeeRateSatPerKwu::from_bdk(r);
-                        if max_fee_rate < FeeRateSatPerKwu::from_sat_per_vb(1) {
-                            max_fee_rate = FeeRateSatPerKwu::from_sat_per_vb(1);
-                        }
-                        break;
-                    }
+                Ok(psbt) => {
+                    coordinator_wallet.cancel_tx(&psbt.unsigned_tx);
+                    low = rate;
                 }
-                Err(e) => match e {
-                    CoinSelection(error) => {
-                        max_fee = error.available.to_sat().saturating_sub(receive_amount);
-                        if max_fee == 0 {
-                            return Err(TransactionComposeError::Error(
-                                "Cannot calculate fee: available amount too low".into(),
-                            ));
-                        }
-                    }
-                    err => {
-                        info!("Create tx error: {err:?}");
-                        return Err(TransactionComposeError::CreateTxError(err));
-                    }
-                },
+                Err(CoinSelection(_)) => high = rate - 1,
+                Err(e) => return Err(TransactionComposeError::CreateTxError(e)),
             }
         }
+        let max_fee_rate =
+            FeeRateSatPerKwu(low).min(FeeRateSatPerKwu::from_bdk(DEFAULT_MAX_FEE_RATE));
+        let min_fee_rate =
+            FeeRateSatPerKwu::from(FeeRateSatPerKvb(DEFAULT_MIN_RELAY_TX_FEE as u64));
+        if max_fee_rate < min_fee_rate {
+            return Err(TransactionComposeError::Error(
+                "Insufficient funds for the minimum relay fee".into(),
+            ));
+        }
 
         // default_fee is sat/kvB from the caller; convert to sat/kwu for BDK comparison
         let default_fee_kwu = FeeRateSatPerKwu::from(default_fee);
         let default_fee_rate = if max_fee_rate > default_fee_kwu {
-            default_fee_kwu.to_bdk()
+            default_fee_kwu.max(min_fee_rate).to_bdk()
         } else {
-            FeeRateSatPerKwu::from_sat_per_vb(1).to_bdk() // fall back to 1 sat/vB
+            min_fee_rate.to_bdk()
         };
 
         let psbt = self.prepare_psbt(
@@ -286,6 +535,10 @@ impl<P: WalletPersister> NgAccount<P> {
             Some(default_fee_rate),
             amount,
             amount == spendable_balance,
+            transaction_params.ordering,
+            change_script,
+            transaction_params.spend_path,
+            &[],
         );
 
         match psbt {
@@ -295,11 +548,19 @@ impl<P: WalletPersister> NgAccount<P> {
                     &mut coordinator_wallet,
                     utxos.clone(),
                     transaction_params.clone(),
+                    sign,
                 );
 
+                let max_fee_rate = FeeRateSatPerKvb::from(max_fee_rate);
+                let min_fee_rate = FeeRateSatPerKvb::from(min_fee_rate);
                 Ok(TransactionFeeResult {
-                    max_fee_rate: FeeRateSatPerKvb::from(max_fee_rate),
-                    min_fee_rate: FeeRateSatPerKvb(1000), // 1 sat/vB in sat/kvB
+                    max_fee_rate,
+                    min_fee_rate,
+                    recommended_fee_rate: recommended_fee_rate(
+                        fee_estimator,
+                        min_fee_rate,
+                        max_fee_rate,
+                    ),
                     draft_transaction,
                 })
             }
@@ -311,6 +572,9 @@ impl<P: WalletPersister> NgAccount<P> {
         &self,
         spend_params: TransactionParams,
     ) -> Result<DraftTransaction, TransactionComposeError> {
+        if self.is_locked() && self.config.read_or_recover().has_private_descriptors() {
+            return Err(TransactionComposeError::AccountLocked);
+        }
         let params = spend_params.clone();
         let address = params.address;
         let amount = params.amount;
@@ -318,7 +582,22 @@ impl<P: WalletPersister> NgAccount<P> {
         let selected_outputs = params.selected_outputs;
 
         //get current utxo set and balance
-        let utxos = self.utxos().unwrap();
+        let utxos = restrict_to_tag(self.utxos().unwrap(), params.spend_from_tag.as_deref());
+        //history for the privacy report, must be read before the wallet is locked
+        let history = self.transactions().unwrap_or_default();
+        // transfers to the account itself don't count against the limits
+        let sent = match self.owns_address(&address) {
+            Ok(true) => 0,
+            _ => amount,
+        };
+        self.check_spending_limits(sent, &history)
+            .map_err(TransactionComposeError::PolicyViolation)?;
+        let sign = self.signs_alone(sent);
+        let foreign_outputs = self.check_foreign_inputs(&params.foreign_inputs)?;
+        let foreign_value: u64 = foreign_outputs
+            .iter()
+            .map(|(_, prev_out)| prev_out.value.to_sat())
+            .sum();
 
         // The wallet will be locked for the rest of the spend method,
         // so calling other NgWallet APIs won't succeed.
@@ -333,6 +612,13 @@ impl<P: WalletPersister> NgAccount<P> {
             .require_network(coordinator_wallet.network())
             .map_err(|_| TransactionComposeError::Error("Address network mismatch".into()))?;
         let script: ScriptBuf = address.clone().into();
+        let dust_limit = self.dust_policy().dust_limit(&script);
+        if amount < dust_limit {
+            return Err(TransactionComposeError::DustAmount { amount, dust_limit });
+        }
+        let change_script =
+            self.get_change_script(&coordinator_wallet, params.change_address.as_deref())?;
+        self.check_spend_path(params.spend_path)?;
 
         //do not spend
         let mut do_not_spend_utxos: Vec<Output> = vec![];
@@ -370,8 +656,28 @@ impl<P: WalletPersister> NgAccount<P> {
         }
 
         let sweep = amount == spendable_balance;
+        if sweep && !foreign_outputs.is_empty() {
+            return Err(TransactionComposeError::Error(
+                "Foreign inputs can't be added to a sweep".into(),
+            ));
+        }
         // fee_rate is sat/kvB from the caller; convert to sat/kwu for BDK
         let fee_rate = fee_rate.to_bdk();
+        let foreign_fee: u64 = match spend_params.fee_sharing {
+            FeeSharing::Ours => 0,
+            FeeSharing::ByInputWeight => spend_params
+                .foreign_inputs
+                .iter()
+                .map(|(_, _, weight)| {
+                    estimate::fee_for(TxIn::default().segwit_weight() + *weight, fee_rate)
+                })
+                .sum(),
+        };
+        if foreign_fee > 0 && foreign_fee >= foreign_value {
+            return Err(TransactionComposeError::Error(
+                "Foreign inputs don't cover their share of the fee".into(),
+            ));
+        }
         let psbt = self.prepare_psbt(
             &mut coordinator_wallet,
             script.clone(),
@@ -379,17 +685,57 @@ impl<P: WalletPersister> NgAccount<P> {
             &mut do_not_spend_utxos,
             None,
             Some(fee_rate),
-            amount,
+            amount + foreign_value - foreign_fee,
             sweep,
+            spend_params.ordering,
+            change_script,
+            spend_params.spend_path,
+            &spend_params.foreign_inputs,
         );
 
         match psbt {
-            Ok(psbt) => Ok(self.prepare_draft_transaction(
-                psbt,
-                &mut coordinator_wallet,
-                utxos.clone(),
-                spend_params,
-            )),
+            Ok(mut psbt) => {
+                if spend_params.embed_memo {
+                    PsbtMemo {
+                        note: spend_params.note.clone(),
+                        change_tag: spend_params.tag.clone(),
+                    }
+                    .embed(&mut psbt);
+                }
+                let mut draft_transaction = self.prepare_draft_transaction(
+                    psbt,
+                    &mut coordinator_wallet,
+                    utxos.clone(),
+                    spend_params,
+                    sign,
+                );
+                if !foreign_outputs.is_empty() {
+                    // the account only pays `amount` of what the recipient gets
+                    draft_transaction.transaction.amount = -(amount as i64);
+                    for input in draft_transaction.transaction.inputs.iter_mut() {
+                        if let Some((_, prev_out)) = foreign_outputs.iter().find(|(outpoint, _)| {
+                            outpoint.txid.to_string() == input.tx_id && outpoint.vout == input.vout
+                        }) {
+                            input.amount = prev_out.value.to_sat();
+                            input.address = Some(utils::get_address_as_string(
+                                &prev_out.script_pubkey,
+                                coordinator_wallet.network(),
+                            ));
+                        }
+                    }
+                    draft_transaction.foreign_contribution = Some(ForeignContribution {
+                        amount: foreign_value,
+                        fee: foreign_fee,
+                    });
+                }
+                draft_transaction.privacy = Some(privacy::analyze(
+                    &draft_transaction.transaction.inputs,
+                    &draft_transaction.transaction.outputs,
+                    amount,
+                    &history,
+                ));
+                Ok(draft_transaction)
+            }
             Err(e) => Err(TransactionComposeError::CreateTxError(e)),
         }
     }
@@ -412,6 +758,89 @@ impl<P: WalletPersister> NgAccount<P> {
         bdk_client.transaction_broadcast(&transaction)
     }
 
+    #[cfg(feature = "esplora")]
+    pub fn broadcast_esplora(
+        spend: DraftTransaction,
+        esplora_url: &str,
+        socks_proxy: Option<&str>,
+    ) -> Result<Txid> {
+        let client = utils::build_esplora_client(esplora_url, socks_proxy);
+        let psbt = Psbt::deserialize(&spend.psbt)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize PSBT: {}", e))?;
+
+        let transaction = psbt
+            .extract_tx()
+            .map_err(|e| anyhow::anyhow!("Failed to extract transaction from PSBT: {}", e))?;
+
+        client
+            .broadcast(&transaction)
+            .map_err(|e| anyhow::anyhow!("Esplora broadcast failed: {}", e))?;
+        Ok(transaction.compute_txid())
+    }
+
+    /// Broadcast `spend` through every endpoint at once, so a single server
+    /// can neither censor nor silently drop it.
+    ///
+    /// Each endpoint gets its own connection through `socks_proxy`; with the
+    /// embedded Tor proxy each one is made over a fresh circuit. Returns as
+    /// soon as an endpoint accepts the transaction, the slower ones finish
+    /// in the background. Fails only if the transaction can't be extracted
+    /// from `spend` or there are no endpoints, otherwise the report tells
+    /// which endpoint accepted it.
+    #[cfg(any(feature = "envoy", feature = "esplora"))]
+    pub fn broadcast_to_all(
+        spend: &DraftTransaction,
+        endpoints: &[BroadcastEndpoint],
+        socks_proxy: Option<&str>,
+    ) -> Result<BroadcastReport> {
+        let psbt = Psbt::deserialize(&spend.psbt)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize PSBT: {}", e))?;
+        let transaction = psbt
+            .extract_tx()
+            .map_err(|e| anyhow::anyhow!("Failed to extract transaction from PSBT: {}", e))?;
+        let txid = transaction.compute_txid();
+        if endpoints.is_empty() {
+            anyhow::bail!("No broadcast endpoints");
+        }
+
+        // the threads aren't joined, the ones still connecting when an
+        // endpoint accepts the transaction are left to time out
+        let transaction = Arc::new(transaction);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            let sender = sender.clone();
+            let endpoint = endpoint.clone();
+            let transaction = transaction.clone();
+            let socks_proxy = socks_proxy.map(str::to_string);
+            std::thread::spawn(move || {
+                let result = endpoint.broadcast(&transaction, socks_proxy.as_deref());
+                if let Err(e) = &result {
+                    info!("Broadcast through {} failed: {e}", endpoint.url());
+                }
+                let _ = sender.send((index, result));
+            });
+        }
+        drop(sender);
+
+        let mut report = BroadcastReport {
+            txid,
+            accepted_by: None,
+            errors: BTreeMap::new(),
+        };
+        for (index, result) in receiver {
+            match result {
+                Ok(()) => {
+                    report.accepted_by = Some(index);
+                    break;
+                }
+                Err(e) => {
+                    report.errors.insert(index, e.to_string());
+                }
+            }
+        }
+        Ok(report)
+    }
+
     pub fn decode_psbt(
         draft_transaction: DraftTransaction,
         psbt: &[u8],
@@ -424,12 +853,18 @@ impl<P: WalletPersister> NgAccount<P> {
                 .finalize(&Secp256k1::verification_only())
                 .map_err(|(_, err)| anyhow::anyhow!("Failed to finalize PSBT {err:?}"))?;
         }
+        let memo = PsbtMemo::read(&psbt);
+        let mut transaction = draft_transaction.transaction;
+        transaction.note = transaction.note.or(memo.note);
         Ok(DraftTransaction {
             psbt: psbt.clone().serialize(),
             is_finalized: psbt.extract(&Secp256k1::verification_only()).is_ok(),
             input_tags: draft_transaction.input_tags,
-            change_out_put_tag: draft_transaction.change_out_put_tag,
-            transaction: draft_transaction.transaction,
+            change_out_put_tag: draft_transaction.change_out_put_tag.or(memo.change_tag),
+            transaction,
+            warnings: draft_transaction.warnings,
+            privacy: draft_transaction.privacy,
+            foreign_contribution: draft_transaction.foreign_contribution,
         })
     }
 
@@ -499,6 +934,11 @@ impl<P: WalletPersister> NgAccount<P> {
                 amount = -(outputs.amount as i64);
             }
         }
+        let keychains: Vec<Option<KeyChain>> = outputs
+            .iter()
+            .map(|output| output.keychain.clone())
+            .collect();
+        let tx_type = TxType::classify(&transaction, true, &keychains, false, false);
 
         BitcoinTransaction {
             tx_id: transaction.clone().compute_txid().to_string(),
@@ -518,6 +958,12 @@ impl<P: WalletPersister> NgAccount<P> {
             date: None,
             vsize: 0,
             account_id,
+            fiat_value: None,
+            fiat_currency: None,
+            conflicts_with: vec![],
+            package: None,
+            tx_kind: TxKind::Standard,
+            tx_type,
         }
     }
 
@@ -576,6 +1022,22 @@ impl<P: WalletPersister> NgAccount<P> {
                     out_put_tag = change_tag.clone();
                     out_put_do_not_spend_change = do_not_spend_change;
                 }
+            } else if let Some(indexed) = non_coordinator_wallets
+                .first()
+                .and_then(|wallet| spk_index::lookup(wallet.meta_storage.as_ref(), &script).ok()?)
+                .filter(|indexed| {
+                    non_coordinator_wallets
+                        .iter()
+                        .any(|wallet| wallet.address_type == indexed.address_type)
+                })
+            {
+                // the wallets share the account storage, so the index tells
+                // which one the output belongs to without locking them
+                derivation = Some((indexed.keychain, indexed.index));
+                if indexed.keychain == KeychainKind::Internal {
+                    out_put_tag = change_tag.clone();
+                    out_put_do_not_spend_change = do_not_spend_change;
+                }
             } else {
                 // Check if the change output belongs to the non-coordinator wallets
                 for wallet in non_coordinator_wallets.iter() {
@@ -642,20 +1104,16 @@ impl<P: WalletPersister> NgAccount<P> {
             }
 
             let utxo_id = format!("{tx_id}:{v_index}");
-            let mut tag: Option<String> = None;
-
-            for utxo in &utxos {
-                if utxo.get_id() == utxo_id {
-                    tag = utxo.tag.clone();
-                    break; // Found the matching utxo, no need to continue
-                }
-            }
+            let utxo = utxos.iter().find(|utxo| utxo.get_id() == utxo_id);
 
             inputs.push(Input {
                 tx_id,
                 vout: v_index,
                 amount,
-                tag,
+                tag: utxo.and_then(|utxo| utxo.tag.clone()),
+                address: utxo.map(|utxo| utxo.address.clone()),
+                keychain: utxo.and_then(|utxo| utxo.keychain.clone()),
+                is_mine: utxo.is_some(),
             });
         }
 
@@ -701,9 +1159,26 @@ impl<P: WalletPersister> NgAccount<P> {
         fee_rate: Option<FeeRate>,
         receive_amount: u64,
         sweep: bool,
+        ordering: OutputOrdering,
+        change_script: Option<ScriptBuf>,
+        spend_path: SpendPath,
+        foreign_inputs: &[(OutPoint, psbt::Input, Weight)],
     ) -> Result<Psbt, CreateTxError> {
-        let mut builder = wallet.build_tx();
-        builder.ordering(TxOrdering::Shuffle);
+        let policy_paths: Vec<_> = [KeychainKind::External, KeychainKind::Internal]
+            .into_iter()
+            .filter_map(|keychain| {
+                spend_path
+                    .policy_path(wallet, keychain)
+                    .map(|path| (path, keychain))
+            })
+            .collect();
+        let selection =
+            SpendPathSelection::new(self.config.read_or_recover().vault.as_ref(), spend_path);
+        let mut builder = wallet.build_tx().coin_selection(selection);
+        for (path, keychain) in policy_paths {
+            builder.policy_path(path, keychain);
+        }
+        builder.ordering(ordering.to_bdk());
         for do_not_spend_utxo in do_not_spend_utxos.iter().clone() {
             builder.add_unspendable(do_not_spend_utxo.get_outpoint());
         }
@@ -723,6 +1198,16 @@ impl<P: WalletPersister> NgAccount<P> {
                 }
             }
         }
+        for (outpoint, input, weight) in foreign_inputs {
+            builder
+                .add_foreign_utxo_with_sequence(
+                    *outpoint,
+                    input.clone(),
+                    *weight,
+                    Sequence::ENABLE_RBF_NO_LOCKTIME,
+                )
+                .map_err(|_| CreateTxError::NoUtxosSelected)?;
+        }
         if sweep {
             info!("drain_to ");
             builder.drain_wallet();
@@ -730,6 +1215,9 @@ impl<P: WalletPersister> NgAccount<P> {
         } else {
             info!("add_recipient ");
             builder.add_recipient(script.clone(), Amount::from_sat(receive_amount));
+            if let Some(change_script) = change_script {
+                builder.drain_to(change_script);
+            }
         }
 
         if let Some(fee_absolute) = fee_absolute {
@@ -739,11 +1227,91 @@ impl<P: WalletPersister> NgAccount<P> {
         if let Some(fee_rate) = fee_rate {
             builder.fee_rate(fee_rate);
         }
-        builder.set_exact_sequence(Sequence::ENABLE_RBF_NO_LOCKTIME);
+        // BDK sets the sequence of recovery spends to the relative timelock
+        if spend_path == SpendPath::Primary {
+            builder.set_exact_sequence(Sequence::ENABLE_RBF_NO_LOCKTIME);
+        }
 
         builder.finish()
     }
 
+    fn check_spend_path(&self, spend_path: SpendPath) -> Result<(), TransactionComposeError> {
+        if spend_path == SpendPath::Recovery && self.config.read_or_recover().vault.is_none() {
+            return Err(TransactionComposeError::Error(
+                "Only vault accounts have a recovery path".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    // The outputs spent by the foreign inputs, which must come with them and
+    // not be the account's.
+    fn check_foreign_inputs(
+        &self,
+        foreign_inputs: &[(OutPoint, psbt::Input, Weight)],
+    ) -> Result<Vec<(OutPoint, TxOut)>, TransactionComposeError> {
+        let mut prev_outs: Vec<(OutPoint, TxOut)> = vec![];
+        for (outpoint, input, _) in foreign_inputs {
+            if prev_outs.iter().any(|(seen, _)| seen == outpoint) {
+                return Err(TransactionComposeError::Error(format!(
+                    "Foreign input {outpoint} is added twice"
+                )));
+            }
+            let prev_out = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(prev_out), _) => Some(prev_out.clone()),
+                (None, Some(tx)) if tx.compute_txid() == outpoint.txid => {
+                    tx.output.get(outpoint.vout as usize).cloned()
+                }
+                _ => None,
+            }
+            .ok_or_else(|| {
+                TransactionComposeError::Error(format!(
+                    "Foreign input {outpoint} is missing the output it spends"
+                ))
+            })?;
+            for wallet in self.all_wallets_or_recover().iter() {
+                if wallet
+                    .bdk_wallet
+                    .lock_or_recover()
+                    .is_mine(prev_out.script_pubkey.clone())
+                {
+                    return Err(TransactionComposeError::Error(format!(
+                        "Foreign input {outpoint} belongs to this account"
+                    )));
+                }
+            }
+            prev_outs.push((*outpoint, prev_out));
+        }
+        Ok(prev_outs)
+    }
+
+    // Script of the change address of another account, rejecting addresses
+    // of this account since their change would be labeled as a transfer.
+    fn get_change_script(
+        &self,
+        coordinator_wallet: &MutexGuard<PersistedWallet<P>>,
+        change_address: Option<&str>,
+    ) -> Result<Option<ScriptBuf>, TransactionComposeError> {
+        let Some(change_address) = change_address else {
+            return Ok(None);
+        };
+        let script = Address::from_str(change_address)
+            .map_err(|_| TransactionComposeError::Error("Invalid change address format".into()))?
+            .require_network(coordinator_wallet.network())
+            .map_err(|_| TransactionComposeError::Error("Change address network mismatch".into()))?
+            .script_pubkey();
+        let mut is_own = coordinator_wallet.is_mine(script.clone());
+        for wallet in self.non_coordinator_wallets().iter() {
+            is_own |= wallet.bdk_wallet.lock_or_recover().is_mine(script.clone());
+        }
+        if is_own {
+            return Err(TransactionComposeError::Error(
+                "Change address belongs to this account".into(),
+            ));
+        }
+        Ok(Some(script))
+    }
+
     pub(crate) fn get_utxo_input(
         &self,
         output: &Output,
@@ -751,7 +1319,7 @@ impl<P: WalletPersister> NgAccount<P> {
     ) -> Option<(psbt::Input, Weight)> {
         let mut input_for_fore: Option<(psbt::Input, Weight)> = None;
         for wallet in wallets.iter() {
-            let wallet = wallet.bdk_wallet.lock().unwrap();
+            let wallet = wallet.bdk_wallet.lock_or_recover();
             let local_output = wallet.get_utxo(output.get_outpoint());
             match local_output {
                 None => {}
@@ -782,7 +1350,7 @@ impl<P: WalletPersister> NgAccount<P> {
 
     pub fn sign_psbt(wallets: Vec<NgWallet<P>>, psbt: &mut Psbt, sign_options: SignOptions) {
         for wallet in wallets {
-            let mut wallet = wallet.bdk_wallet.lock().unwrap();
+            let mut wallet = wallet.bdk_wallet.lock_or_recover();
             wallet.sign(psbt, sign_options.clone()).unwrap_or(false);
             //Why cancel? Canceling will reset the index.
             //We only increment the index if the transaction is broadcasted.
@@ -793,14 +1361,14 @@ impl<P: WalletPersister> NgAccount<P> {
     ///TODO, verify inputs belongs to the wallet
     pub fn get_bitcoin_tx_from_psbt(&self, psbt: &[u8]) -> Result<BitcoinTransaction> {
         let psbt = Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT")?;
-        let account_id = self.config.read().unwrap().id.clone();
+        let account_id = self.config.read_or_err()?.id.clone();
         let transaction = psbt.clone().unsigned_tx;
         let mut amount = 0;
         let mut address = "".to_string();
         for outputs in transaction.output.iter() {
             let script = outputs.script_pubkey.clone();
-            for wallet in self.wallets.read().unwrap().iter() {
-                let bdk_wallet = wallet.bdk_wallet.lock().unwrap();
+            for wallet in self.all_wallets()?.iter() {
+                let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
                 let derivation = bdk_wallet.derivation_of_spk(script.clone());
                 if derivation.is_none() {
                     address = Address::from_script(&script, bdk_wallet.network())
@@ -811,8 +1379,8 @@ impl<P: WalletPersister> NgAccount<P> {
             }
             //check for self spends
             if address.is_empty() {
-                for wallet in self.wallets.read().unwrap().iter() {
-                    let bdk_wallet = wallet.bdk_wallet.lock().unwrap();
+                for wallet in self.all_wallets()?.iter() {
+                    let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
                     let derivation = bdk_wallet.derivation_of_spk(script.clone());
                     if let Some((KeychainKind::External, _)) = derivation {
                         address = Address::from_script(&script, bdk_wallet.network())
@@ -838,10 +1406,191 @@ impl<P: WalletPersister> NgAccount<P> {
             inputs: vec![],
             address,
             outputs: vec![],
-            note: None,
+            note: PsbtMemo::read(&psbt).note,
             date: None,
             vsize: 0,
             account_id,
+            fiat_value: None,
+            fiat_currency: None,
+            conflicts_with: vec![],
+            package: None,
+            tx_kind: TxKind::Standard,
+            tx_type: self.tx_type(&transaction, false)?,
+        })
+    }
+
+    /// Decode a fully signed raw transaction (hex) and describe it from the
+    /// point of view of this account: inputs and outputs are matched against
+    /// every wallet and carry their existing tags, the note is attached if set.
+    pub fn decode_raw_tx(&self, hex: &str) -> Result<BitcoinTransaction> {
+        let transaction: Transaction =
+            bdk_wallet::bitcoin::consensus::encode::deserialize_hex(hex.trim())
+                .map_err(|e| anyhow::anyhow!("Failed to decode raw transaction: {e}"))?;
+        let txid = transaction.compute_txid();
+        let tx_id = txid.to_string();
+        let (account_id, network) = {
+            let config = self.config.read_or_err()?;
+            (config.id.clone(), config.network)
+        };
+        let wallets = self.all_wallets()?.clone();
+
+        let mut inputs = Vec::with_capacity(transaction.input.len());
+        let mut all_inputs_known = true;
+        for input in &transaction.input {
+            let outpoint = input.previous_output;
+            let prev_out = wallets.iter().find_map(|wallet| {
+                wallet
+                    .bdk_wallet
+                    .lock_or_recover()
+                    .tx_graph()
+                    .get_txout(outpoint)
+                    .cloned()
+            });
+            let keychain = prev_out.as_ref().and_then(|prev_out| {
+                wallets.iter().find_map(|wallet| {
+                    wallet
+                        .bdk_wallet
+                        .lock_or_recover()
+                        .derivation_of_spk(prev_out.script_pubkey.clone())
+                        .map(|(keychain, _)| KeyChain::from(keychain))
+                })
+            });
+            all_inputs_known &= prev_out.is_some();
+            inputs.push(Input {
+                tx_id: outpoint.txid.to_string(),
+                vout: outpoint.vout,
+                amount: prev_out
+                    .as_ref()
+                    .map_or(0, |prev_out| prev_out.value.to_sat()),
+                tag: self
+                    .meta_storage
+                    .get_tag(&format!("{}:{}", outpoint.txid, outpoint.vout))
+                    .unwrap_or(None)
+                    .filter(|tag| !tag.is_empty()),
+                address: prev_out
+                    .as_ref()
+                    .map(|prev_out| utils::get_address_as_string(&prev_out.script_pubkey, network)),
+                is_mine: keychain.is_some(),
+                keychain,
+            });
+        }
+
+        let mut outputs = Vec::with_capacity(transaction.output.len());
+        let mut address = "".to_string();
+        let mut self_transfer_address = "".to_string();
+        for (vout, tx_out) in transaction.output.iter().enumerate() {
+            let output_address = utils::get_address_as_string(&tx_out.script_pubkey, network);
+            let keychain = wallets.iter().find_map(|wallet| {
+                wallet
+                    .bdk_wallet
+                    .lock_or_recover()
+                    .derivation_of_spk(tx_out.script_pubkey.clone())
+                    .map(|(keychain, _)| keychain)
+            });
+            match keychain {
+                None if !tx_out.script_pubkey.is_op_return() => {
+                    address = output_address.clone();
+                }
+                Some(KeychainKind::External) if self_transfer_address.is_empty() => {
+                    self_transfer_address = output_address.clone();
+                }
+                _ => {}
+            }
+            let output_id = format!("{tx_id}:{vout}");
+            outputs.push(Output {
+                tx_id: tx_id.clone(),
+                vout: vout as u32,
+                amount: tx_out.value.to_sat(),
+                tag: self
+                    .meta_storage
+                    .get_tag(&output_id)
+                    .unwrap_or(None)
+                    .filter(|tag| !tag.is_empty()),
+                date: None,
+                is_confirmed: false,
+                address: output_address,
+                do_not_spend: self
+                    .meta_storage
+                    .get_do_not_spend(&output_id)
+                    .unwrap_or(false),
+                keychain: keychain.map(|keychain| match keychain {
+                    KeychainKind::External => KeyChain::External,
+                    KeychainKind::Internal => KeyChain::Internal,
+                }),
+            });
+        }
+        if address.is_empty() {
+            address = self_transfer_address;
+        }
+
+        // use the chain position if one of the wallets already knows the tx
+        let (block_height, confirmations, date) = wallets
+            .iter()
+            .find_map(|wallet| {
+                let wallet = wallet.bdk_wallet.lock_or_recover();
+                let tip_height = wallet.latest_checkpoint().height();
+                wallet.get_tx(txid).map(|tx| match tx.chain_position {
+                    bdk_wallet::chain::ChainPosition::Confirmed { anchor, .. } => {
+                        let height = anchor.block_id.height;
+                        (
+                            height,
+                            tip_height.saturating_sub(height) + 1,
+                            Some(anchor.confirmation_time),
+                        )
+                    }
+                    bdk_wallet::chain::ChainPosition::Unconfirmed { first_seen, .. } => {
+                        (0, 0, first_seen)
+                    }
+                })
+            })
+            .unwrap_or((0, 0, None));
+        for output in outputs.iter_mut() {
+            output.date = date;
+            output.is_confirmed = confirmations >= 1;
+        }
+
+        let fee = if all_inputs_known && !transaction.is_coinbase() {
+            let input_amount: u64 = inputs.iter().map(|input| input.amount).sum();
+            let output_amount: u64 = outputs.iter().map(|output| output.amount).sum();
+            input_amount
+                .checked_sub(output_amount)
+                .unwrap_or(FEE_UNKNOWN)
+        } else {
+            self.meta_storage
+                .get_fee(&tx_id)
+                .unwrap_or(None)
+                .unwrap_or(FEE_UNKNOWN)
+        };
+        let vsize = transaction.vsize();
+        let fee_rate = FeeRateSatPerKvb(if vsize > 0 && fee != FEE_UNKNOWN {
+            fee * 1000 / vsize as u64
+        } else {
+            0
+        });
+
+        let (sent, received) = self.sent_and_received(&transaction);
+
+        Ok(BitcoinTransaction {
+            tx_id: tx_id.clone(),
+            block_height,
+            confirmations,
+            is_confirmed: confirmations >= 1,
+            fee,
+            fee_rate,
+            amount: (received.to_sat() as i64) - (sent.to_sat() as i64),
+            inputs,
+            address,
+            outputs,
+            note: self.meta_storage.get_note(&tx_id).unwrap_or(None),
+            date,
+            vsize,
+            account_id,
+            fiat_value: None,
+            fiat_currency: None,
+            conflicts_with: vec![],
+            package: None,
+            tx_kind: TxKind::Standard,
+            tx_type: self.tx_type(&transaction, false)?,
         })
     }
 
@@ -851,25 +1600,29 @@ impl<P: WalletPersister> NgAccount<P> {
         coordinator_wallet: &mut MutexGuard<PersistedWallet<P>>,
         utxos: Vec<Output>,
         transaction_params: TransactionParams,
+        sign: bool,
     ) -> DraftTransaction {
-        // Always try signing
         let sign_options = SignOptions {
             trust_witness_utxo: true,
             ..Default::default()
         };
-        // Always try signing
-        let _ = coordinator_wallet
-            .sign(&mut psbt, sign_options.clone())
-            .is_ok();
+        // Always try signing, unless a co-signer must sign first
+        if sign {
+            let _ = coordinator_wallet
+                .sign(&mut psbt, sign_options.clone())
+                .is_ok();
+        }
         //reset index,
         coordinator_wallet.cancel_tx(&psbt.clone().unsigned_tx);
-        Self::sign_psbt(
-            self.non_coordinator_wallets(),
-            &mut psbt,
-            sign_options.clone(),
-        );
+        if sign {
+            Self::sign_psbt(
+                self.non_coordinator_wallets(),
+                &mut psbt,
+                sign_options.clone(),
+            );
+        }
         //extract outputs from tx and add tags and do_not_spend states
-        let outputs = Self::apply_meta_to_psbt_outputs(
+        let mut outputs = Self::apply_meta_to_psbt_outputs(
             coordinator_wallet,
             &self.non_coordinator_wallets(),
             utxos.clone(),
@@ -883,13 +1636,21 @@ impl<P: WalletPersister> NgAccount<P> {
             psbt.clone().unsigned_tx,
             utxos,
         );
+        // change sent to another account is a transfer between accounts
+        if let Some(change_address) = &transaction_params.change_address {
+            for output in outputs.iter_mut() {
+                if &output.address == change_address {
+                    output.tag = Some(TRANSFER_TAG.to_string());
+                }
+            }
+        }
         let transaction = Self::transform_psbt_to_bitcointx(
             psbt.clone(),
             transaction_params.address,
             outputs.clone(),
             inputs.clone(),
             transaction_params.note,
-            self.config.read().unwrap().id.clone(),
+            self.config.read_or_recover().id.clone(),
         );
 
         let mut change_out_put_tag: Option<String> = None;
@@ -905,12 +1666,160 @@ impl<P: WalletPersister> NgAccount<P> {
             .map(|input| input.tag.clone().unwrap_or("untagged".to_string()))
             .collect();
 
+        let warnings = self.get_draft_warnings(
+            &psbt,
+            coordinator_wallet,
+            &transaction.address,
+            &transaction.outputs,
+        );
+
         DraftTransaction {
             psbt: psbt.serialize(),
             is_finalized: psbt.extract(&Secp256k1::verification_only()).is_ok(),
             input_tags,
             change_out_put_tag,
             transaction,
+            warnings,
+            privacy: None,
+            foreign_contribution: None,
+        }
+    }
+
+    pub(crate) fn get_draft_warnings(
+        &self,
+        psbt: &Psbt,
+        coordinator_wallet: &MutexGuard<PersistedWallet<P>>,
+        recipient: &str,
+        outputs: &[Output],
+    ) -> Vec<TxWarning> {
+        let mut warnings = vec![];
+        let fee_rate = psbt.fee_rate();
+
+        // weight of an input spending the change later on:
+        // outpoint (32 + 4), script_sig length (1) and sequence (4) plus the satisfaction
+        // vault change is spent with the primary key later on
+        let satisfaction_weight = match &self.config.read_or_recover().vault {
+            Some(vault) => vault
+                .max_weight_to_satisfy(KeychainKind::Internal, SpendPath::Primary)
+                .ok(),
+            None => coordinator_wallet
+                .public_descriptor(KeychainKind::Internal)
+                .max_weight_to_satisfy()
+                .ok(),
+        };
+        let change_input_weight = satisfaction_weight
+            .map(|satisfaction| Weight::from_non_witness_data_size(32 + 4 + 1 + 4) + satisfaction);
+
+        for output in outputs
+            .iter()
+            .filter(|output| output.keychain == Some(KeyChain::Internal))
+        {
+            let Some(tx_out) = psbt.unsigned_tx.output.get(output.vout as usize) else {
+                continue;
+            };
+            if tx_out.value < tx_out.script_pubkey.minimal_non_dust() {
+                warnings.push(TxWarning::DustChange {
+                    amount: output.amount,
+                });
+                continue;
+            }
+            if let (Some(fee_rate), Some(weight)) = (fee_rate, change_input_weight) {
+                let spend_fee = fee_rate
+                    .fee_wu(weight)
+                    .unwrap_or(Amount::MAX_MONEY)
+                    .to_sat();
+                if output.amount < spend_fee.saturating_mul(UNECONOMICAL_CHANGE_FACTOR) {
+                    warnings.push(TxWarning::UneconomicalChange {
+                        amount: output.amount,
+                        spend_fee,
+                    });
+                }
+            }
+        }
+
+        if let Ok(address) = Address::from_str(recipient)
+            && let Ok(address) = address.require_network(coordinator_wallet.network())
+        {
+            let script = address.script_pubkey();
+            let non_coordinator_wallets = self.non_coordinator_wallets();
+            let mut is_own = coordinator_wallet.is_mine(script.clone());
+            let mut received_before = has_received_to(coordinator_wallet, &script);
+            for wallet in non_coordinator_wallets.iter() {
+                let wallet = wallet.bdk_wallet.lock_or_recover();
+                is_own |= wallet.is_mine(script.clone());
+                received_before |= has_received_to(&wallet, &script);
+            }
+            if received_before {
+                let address = address.to_string();
+                warnings.push(if is_own {
+                    TxWarning::UsedOwnAddress { address }
+                } else {
+                    TxWarning::AddressReuse { address }
+                });
+            }
+        }
+
+        if let Some(fee_rate) = fee_rate
+            && fee_rate > ABSURD_FEE_RATE
+        {
+            warnings.push(TxWarning::AbsurdFeeRate {
+                fee_rate: FeeRateSatPerKvb::from_bdk(fee_rate),
+            });
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::absolute::LockTime;
+    use bdk_wallet::bitcoin::transaction::Version;
+    use bdk_wallet::bitcoin::{OutPoint, Txid};
+
+    fn transaction(values: &[u64]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: values
+                .iter()
+                .map(|value| TxIn {
+                    previous_output: OutPoint::new(
+                        Txid::from_byte_array([(value / 1000) as u8; 32]),
+                        0,
+                    ),
+                    ..Default::default()
+                })
+                .collect(),
+            output: values
+                .iter()
+                .map(|value| TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
         }
     }
+
+    #[test]
+    fn deterministic_orderings() {
+        let mut tx = transaction(&[3000, 1000, 2000]);
+        OutputOrdering::Bip69.to_bdk().sort_tx(&mut tx);
+        let values: Vec<u64> = tx.output.iter().map(|out| out.value.to_sat()).collect();
+        assert_eq!(values, vec![1000, 2000, 3000]);
+        let txids: Vec<u8> = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid.to_byte_array()[0])
+            .collect();
+        assert_eq!(txids, vec![1, 2, 3]);
+
+        let seeded = OutputOrdering::SeededShuffle { seed: 7 }.to_bdk();
+        let mut a = transaction(&[3000, 1000, 2000, 4000]);
+        let mut b = transaction(&[4000, 2000, 1000, 3000]);
+        seeded.sort_tx(&mut a);
+        seeded.sort_tx(&mut b);
+        assert_eq!(a, b);
+    }
 }
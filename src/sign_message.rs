@@ -1,15 +1,34 @@
 use std::str::FromStr;
 
+use anyhow::{Context, anyhow, bail};
 use bdk_wallet::bitcoin::{
-    Address, CompressedPublicKey, Network, PrivateKey, PublicKey,
-    bip32::{DerivationPath, Xpriv},
+    Address, AddressType, Amount, CompressedPublicKey, Network, OutPoint, PrivateKey, Psbt,
+    PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness, absolute,
+    base64::{Engine, engine::general_purpose::STANDARD as BASE64},
+    bip32::{ChildNumber, DerivationPath, Xpriv},
+    consensus::{deserialize, serialize},
+    hashes::{Hash, HashEngine, sha256},
     key::TapTweak,
-    secp256k1::{Message, Secp256k1, XOnlyPublicKey},
+    opcodes::all::{OP_PUSHBYTES_0, OP_RETURN},
+    script::Builder,
+    secp256k1::{
+        All, Message, Secp256k1, XOnlyPublicKey,
+        ecdsa::{RecoverableSignature, RecoveryId},
+    },
     sign_message::{MessageSignature, signed_msg_hash},
+    transaction,
 };
+use bdk_wallet::miniscript::descriptor::{DescriptorSecretKey, Wildcard};
+use bdk_wallet::miniscript::psbt::{PsbtExt, PsbtInputExt};
+use bdk_wallet::{KeychainKind, SignOptions, Wallet, WalletPersister};
 use thiserror::Error;
 
+use crate::account::NgAccount;
 use crate::bip32::NgAccountPath;
+use crate::error::{MutexExt, RwLockExt};
+
+/// Tag of the BIP-322 message hash.
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
 
 /// A signed Bitcoin message (BIP-137).
 #[derive(Debug, Clone)]
@@ -126,6 +145,275 @@ fn derive_address_from_purpose(
     }
 }
 
+impl<P: WalletPersister> NgAccount<P> {
+    /// Sign `message` with the key of `address`, which must belong to the
+    /// account.
+    ///
+    /// P2PKH and P2WPKH addresses get a legacy Bitcoin Signed Message
+    /// signature, every other script type a BIP-322 signature: the witness
+    /// for native segwit and taproot ("simple"), the whole signed
+    /// transaction for wrapped segwit ("full").
+    pub fn sign_message(&self, address: &str, message: &str) -> anyhow::Result<SignedMessage> {
+        self.check_unlocked()?;
+        let address = self.parse_message_address(address)?;
+        let script_pubkey = address.script_pubkey();
+
+        for wallet in self.all_wallets_or_recover().iter() {
+            let wallet = wallet.bdk_wallet.lock_or_err()?;
+            let Some((keychain, index)) = wallet.derivation_of_spk(script_pubkey.clone()) else {
+                continue;
+            };
+            let signature = match address.address_type() {
+                Some(AddressType::P2pkh | AddressType::P2wpkh) => {
+                    sign_legacy(&wallet, keychain, index, &script_pubkey, message)?
+                }
+                _ => sign_bip322(&wallet, keychain, index, &script_pubkey, message)?,
+            };
+            return Ok(SignedMessage {
+                message: message.to_string(),
+                address: address.to_string(),
+                signature,
+            });
+        }
+        bail!("Address does not belong to this account")
+    }
+
+    /// Verify a legacy or BIP-322 `signature` of `message` by `address`.
+    /// The address doesn't need to belong to the account.
+    pub fn verify_message(
+        &self,
+        address: &str,
+        message: &str,
+        signature: &str,
+    ) -> anyhow::Result<bool> {
+        verify_message(&self.parse_message_address(address)?, message, signature)
+    }
+
+    fn parse_message_address(&self, address: &str) -> anyhow::Result<Address> {
+        let network = self.config.read_or_err()?.network;
+        Address::from_str(address.trim())
+            .with_context(|| "Invalid address")?
+            .require_network(network)
+            .with_context(|| "Address is for another network")
+    }
+}
+
+/// Verify a legacy (BIP-137) or BIP-322 `signature` of `message` by `address`.
+///
+/// Returns `Ok(false)` for well formed signatures that don't match.
+pub fn verify_message(address: &Address, message: &str, signature: &str) -> anyhow::Result<bool> {
+    let bytes = BASE64
+        .decode(signature.trim())
+        .with_context(|| "Signature is not base64")?;
+    let script_pubkey = address.script_pubkey();
+    match bytes.first().copied() {
+        Some(27..=42) if bytes.len() == 65 => verify_legacy(&script_pubkey, message, &bytes),
+        Some(_) => verify_bip322(&script_pubkey, message, &bytes),
+        None => bail!("Empty signature"),
+    }
+}
+
+/// Tagged hash of `message` signed by BIP-322 signatures.
+pub fn bip322_message_hash(message: &str) -> [u8; 32] {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+// The virtual transaction creating the output "spent" by a BIP-322 signature.
+pub(crate) fn bip322_to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(bip322_message_hash(message))
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    }
+}
+
+// The virtual transaction whose signature is the BIP-322 signature.
+pub(crate) fn bip322_to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            sequence: Sequence::ZERO,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+fn sign_legacy(
+    wallet: &Wallet,
+    keychain: KeychainKind,
+    index: u32,
+    script_pubkey: &Script,
+    message: &str,
+) -> anyhow::Result<String> {
+    let secp = Secp256k1::new();
+    let private_key = wallet
+        .get_signers(keychain)
+        .as_key_map(&secp)
+        .values()
+        .filter_map(|key| derive_private_key(key, index, &secp))
+        .find(|key| is_legacy_script_pubkey(key.public_key(&secp), script_pubkey))
+        .ok_or_else(|| anyhow!("No private key for this address"))?;
+
+    let msg = Message::from_digest(signed_msg_hash(message).to_byte_array());
+    let signature = MessageSignature {
+        signature: secp.sign_ecdsa_recoverable(&msg, &private_key.inner),
+        compressed: private_key.compressed,
+    };
+    Ok(signature.to_base64())
+}
+
+fn sign_bip322(
+    wallet: &Wallet,
+    keychain: KeychainKind,
+    index: u32,
+    script_pubkey: &Script,
+    message: &str,
+) -> anyhow::Result<String> {
+    let to_spend = bip322_to_spend(script_pubkey, message);
+    let mut psbt = Psbt::from_unsigned_tx(bip322_to_sign(&to_spend))?;
+    let descriptor = wallet
+        .public_descriptor(keychain)
+        .at_derivation_index(index)?;
+    psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+    psbt.inputs[0]
+        .update_with_descriptor_unchecked(&descriptor)
+        .map_err(|e| anyhow!("Failed to prepare message signature: {e}"))?;
+
+    // the spent output only exists in the signature, there is no previous
+    // transaction to check the witness UTXO against
+    let finalized = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            trust_witness_utxo: true,
+            ..Default::default()
+        },
+    )?;
+    if !finalized {
+        bail!("Failed to sign message, no private key for this address");
+    }
+
+    let input = psbt.inputs.remove(0);
+    let witness = input.final_script_witness.unwrap_or_default();
+    let script_sig = input.final_script_sig.unwrap_or_default();
+    let encoded = if script_sig.is_empty() {
+        serialize(&witness)
+    } else {
+        let mut to_sign = psbt.unsigned_tx;
+        to_sign.input[0].script_sig = script_sig;
+        to_sign.input[0].witness = witness;
+        serialize(&to_sign)
+    };
+    Ok(BASE64.encode(encoded))
+}
+
+fn verify_legacy(script_pubkey: &Script, message: &str, bytes: &[u8]) -> anyhow::Result<bool> {
+    // BIP-137 headers also encode the address type, the address itself is
+    // checked against the recovered key instead
+    let header = bytes[0] - 27;
+    let recovery_id = RecoveryId::from_i32((header & 0x03) as i32)?;
+    let signature = RecoverableSignature::from_compact(&bytes[1..], recovery_id)?;
+    let msg = Message::from_digest(signed_msg_hash(message).to_byte_array());
+    let Ok(inner) = Secp256k1::verification_only().recover_ecdsa(&msg, &signature) else {
+        return Ok(false);
+    };
+    let public_key = PublicKey {
+        compressed: header >= 4,
+        inner,
+    };
+    Ok(is_legacy_script_pubkey(public_key, script_pubkey))
+}
+
+fn verify_bip322(script_pubkey: &Script, message: &str, bytes: &[u8]) -> anyhow::Result<bool> {
+    let to_spend = bip322_to_spend(script_pubkey, message);
+    let to_sign = bip322_to_sign(&to_spend);
+
+    let (script_sig, witness) = match deserialize::<Witness>(bytes) {
+        Ok(witness) => (ScriptBuf::new(), witness),
+        Err(_) => {
+            let signed: Transaction =
+                deserialize(bytes).with_context(|| "Invalid BIP-322 signature")?;
+            if signed.input.len() != 1
+                || signed.input[0].previous_output != to_sign.input[0].previous_output
+                || signed.output != to_sign.output
+            {
+                return Ok(false);
+            }
+            let input = signed.input.into_iter().next().unwrap_or_default();
+            (input.script_sig, input.witness)
+        }
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(to_sign)?;
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(to_spend.output[0].clone());
+    input.non_witness_utxo = Some(to_spend);
+    input.final_script_sig = Some(script_sig).filter(|script| !script.is_empty());
+    input.final_script_witness = Some(witness).filter(|witness| !witness.is_empty());
+
+    // runs the script of the address against the signature
+    Ok(psbt.extract(&Secp256k1::verification_only()).is_ok())
+}
+
+// Whether `script_pubkey` is one of the single key scripts a legacy message
+// signature by `public_key` can be made for.
+fn is_legacy_script_pubkey(public_key: PublicKey, script_pubkey: &Script) -> bool {
+    let mut scripts = vec![ScriptBuf::new_p2pkh(&public_key.pubkey_hash())];
+    if let Ok(compressed) = CompressedPublicKey::try_from(public_key) {
+        let p2wpkh = ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash());
+        scripts.push(ScriptBuf::new_p2sh(&p2wpkh.script_hash()));
+        scripts.push(p2wpkh);
+    }
+    scripts
+        .iter()
+        .any(|script| script.as_script() == script_pubkey)
+}
+
+fn derive_private_key(
+    key: &DescriptorSecretKey,
+    index: u32,
+    secp: &Secp256k1<All>,
+) -> Option<PrivateKey> {
+    match key {
+        DescriptorSecretKey::Single(single) => Some(single.key),
+        DescriptorSecretKey::XPrv(xkey) => {
+            let path = match xkey.wildcard {
+                Wildcard::None => xkey.derivation_path.clone(),
+                Wildcard::Unhardened => xkey
+                    .derivation_path
+                    .child(ChildNumber::from_normal_idx(index).ok()?),
+                Wildcard::Hardened => xkey
+                    .derivation_path
+                    .child(ChildNumber::from_hardened_idx(index).ok()?),
+            };
+            Some(xkey.xkey.derive_priv(secp, &path).ok()?.to_priv())
+        }
+        DescriptorSecretKey::MultiXPrv(_) => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,6 +529,41 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn bip322_message_hashes() {
+        // test vectors from BIP-322
+        assert_eq!(
+            bip322_message_hash("").to_vec(),
+            hex_to_bytes("c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1")
+        );
+        assert_eq!(
+            bip322_message_hash("Hello World").to_vec(),
+            hex_to_bytes("f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a")
+        );
+    }
+
+    #[test]
+    fn verify_legacy_signature() {
+        let seed = test_seed();
+        let signed = sign_message(
+            &seed,
+            "m/84'/0'/0'/0/0",
+            "Hello, Bitcoin!",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        let address = Address::from_str(&signed.address).unwrap().assume_checked();
+
+        assert!(verify_message(&address, "Hello, Bitcoin!", &signed.signature).unwrap());
+        assert!(!verify_message(&address, "Hello, Bitcoin?", &signed.signature).unwrap());
+        assert!(verify_message(&address, "Hello, Bitcoin!", "not base64!").is_err());
+    }
+
+    fn hex_to_bytes(hex: &str) -> Vec<u8> {
+        use bdk_wallet::bitcoin::hex::FromHex;
+        Vec::from_hex(hex).unwrap()
+    }
+
     #[test]
     fn format_signed_message_output() {
         let signed = SignedMessage {
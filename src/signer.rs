@@ -0,0 +1,158 @@
+//! Signing devices.
+//!
+//! A [`Signer`] holds the keys of one cosigner, either in software or on an
+//! external device (Passport over QR/NFC, a HWI bridge, ...). Accounts route
+//! PSBTs to the signers registered in a [`SignerRegistry`], see
+//! [`NgAccount::sign_with_signers`].
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use bdk_wallet::bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{Network, Psbt};
+use bdk_wallet::{SignOptions, WalletPersister};
+
+use crate::account::NgAccount;
+use crate::bip39::MasterKey;
+use crate::config::MultiSigDetails;
+use crate::error::{MutexExt, RwLockExt};
+use crate::key_handle::{KeyHandle, erase_xpriv};
+
+pub trait Signer: Debug + Send + Sync {
+    /// Fingerprint of the master key of the signer.
+    fn get_fingerprint(&self) -> Fingerprint;
+
+    /// Extended public key at `path` from the master key.
+    fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub>;
+
+    /// Add the signatures of the signer to `psbt`. Inputs the signer has no
+    /// key for are left untouched.
+    fn sign_psbt(&self, psbt: &mut Psbt) -> Result<()>;
+
+    /// Show `address`, derived at `path`, on the signer for the user to
+    /// compare. Signers without a screen return an error.
+    fn display_address(&self, address: &str, path: &DerivationPath) -> Result<()> {
+        let _ = (address, path);
+        Err(anyhow!("Signer can't display addresses"))
+    }
+}
+
+/// Signer keeping the seed in memory, only deriving keys while it uses
+/// them, see [`KeyHandle`].
+pub struct SoftwareSigner {
+    key: KeyHandle,
+}
+
+impl SoftwareSigner {
+    pub fn new(master_key: &MasterKey, network: Network) -> Result<Self> {
+        Ok(Self {
+            key: KeyHandle::new(master_key, network),
+        })
+    }
+}
+
+impl Debug for SoftwareSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SoftwareSigner")
+            .field("fingerprint", &self.get_fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn get_fingerprint(&self) -> Fingerprint {
+        self.key.fingerprint()
+    }
+
+    fn get_xpub(&self, path: &DerivationPath) -> Result<Xpub> {
+        let secp = Secp256k1::new();
+        self.key.with_xpriv(|master| -> Result<Xpub> {
+            let mut xpriv = master.derive_priv(&secp, path)?;
+            let xpub = Xpub::from_priv(&secp, &xpriv);
+            erase_xpriv(&mut xpriv);
+            Ok(xpub)
+        })?
+    }
+
+    fn sign_psbt(&self, psbt: &mut Psbt) -> Result<()> {
+        self.key.sign_psbt(psbt, &Secp256k1::new())
+    }
+}
+
+/// Signers known to the app, by master key fingerprint.
+#[derive(Debug, Clone, Default)]
+pub struct SignerRegistry {
+    signers: BTreeMap<Fingerprint, Arc<dyn Signer>>,
+}
+
+impl SignerRegistry {
+    /// Add `signer`, replacing any signer with the same fingerprint.
+    pub fn register(&mut self, signer: Arc<dyn Signer>) {
+        self.signers.insert(signer.get_fingerprint(), signer);
+    }
+
+    pub fn unregister(&mut self, fingerprint: &Fingerprint) -> Option<Arc<dyn Signer>> {
+        self.signers.remove(fingerprint)
+    }
+
+    pub fn get(&self, fingerprint: &Fingerprint) -> Option<Arc<dyn Signer>> {
+        self.signers.get(fingerprint).cloned()
+    }
+
+    /// Registered signers of the cosigners of `multisig`.
+    pub fn signers_for(&self, multisig: &MultiSigDetails) -> Vec<Arc<dyn Signer>> {
+        multisig
+            .get_signers()
+            .iter()
+            .filter_map(|cosigner| self.get(&cosigner.get_fingerprint()))
+            .collect()
+    }
+
+    /// Fingerprints of the cosigners of `multisig` without a registered signer.
+    pub fn missing_for(&self, multisig: &MultiSigDetails) -> Vec<Fingerprint> {
+        multisig
+            .get_signers()
+            .iter()
+            .map(|cosigner| cosigner.get_fingerprint())
+            .filter(|fingerprint| !self.signers.contains_key(fingerprint))
+            .collect()
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Sign `psbt` with the signers of this account found in `registry`,
+    /// every cosigner for multisig accounts and the master key otherwise,
+    /// then finalize the inputs that have enough signatures.
+    pub fn sign_with_signers(&self, psbt: &[u8], registry: &SignerRegistry) -> Result<Vec<u8>> {
+        let mut psbt = Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT")?;
+        self.check_can_sign(&psbt)?;
+
+        let multisig = self.config.read_or_err()?.multisig.clone();
+        let signers = match multisig {
+            Some(multisig) => registry.signers_for(&multisig),
+            None => {
+                let fingerprint = self.get_xfp().to_lowercase().parse::<Fingerprint>()?;
+                registry.get(&fingerprint).into_iter().collect()
+            }
+        };
+        if signers.is_empty() {
+            bail!("No signer registered for this account");
+        }
+        for signer in signers {
+            signer
+                .sign_psbt(&mut psbt)
+                .with_context(|| format!("Signer {} failed", signer.get_fingerprint()))?;
+        }
+
+        for wallet in self.all_wallets_or_recover().iter() {
+            wallet
+                .bdk_wallet
+                .lock_or_err()?
+                .finalize_psbt(&mut psbt, SignOptions::default())?;
+        }
+        Ok(psbt.serialize())
+    }
+}
@@ -0,0 +1,346 @@
+//! Collecting the signatures of a multisig transaction.
+//!
+//! A [`SigningSession`] holds the PSBT while it travels between cosigners,
+//! merges the partially signed copies they return and reports which
+//! cosigners still have to sign.
+//!
+//! [`NgAccount::cosigner_challenge`] and [`NgAccount::verify_cosigner`]
+//! check that a cosigner device still holds its key, by having it sign a
+//! BIP-322 message from the first address of the account.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use bdk_wallet::bitcoin::bip32::Fingerprint;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::sighash::SighashCache;
+use bdk_wallet::bitcoin::{PublicKey, psbt::Psbt};
+use bdk_wallet::miniscript::psbt::PsbtInputExt;
+use bdk_wallet::{KeychainKind, SignOptions, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::config::MultiSigDetails;
+use crate::error::{AccountError, MutexExt, RwLockExt};
+use crate::sign_message::{bip322_to_sign, bip322_to_spend};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningProgress {
+    /// Fingerprints of the cosigners that signed every input.
+    pub signed: Vec<String>,
+    /// Fingerprints of the cosigners that didn't sign yet.
+    pub pending: Vec<String>,
+    pub threshold: usize,
+    /// Signatures still missing on the least signed input.
+    pub signatures_needed: usize,
+}
+
+impl SigningProgress {
+    pub fn is_complete(&self) -> bool {
+        self.signatures_needed == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SigningSession {
+    psbt: Psbt,
+    threshold: usize,
+    cosigners: Vec<Fingerprint>,
+}
+
+impl SigningSession {
+    pub fn new(psbt: Psbt, multisig: &MultiSigDetails) -> Self {
+        Self {
+            psbt,
+            threshold: multisig.policy_threshold,
+            cosigners: multisig
+                .get_signers()
+                .iter()
+                .map(|signer| signer.get_fingerprint())
+                .collect(),
+        }
+    }
+
+    pub fn psbt(&self) -> &Psbt {
+        &self.psbt
+    }
+
+    pub fn into_psbt(self) -> Psbt {
+        self.psbt
+    }
+
+    /// Add the signatures of `psbt`, a copy of the session PSBT signed by
+    /// another cosigner.
+    pub fn merge(&mut self, psbt: &Psbt) -> Result<()> {
+        if psbt.unsigned_tx.compute_txid() != self.psbt.unsigned_tx.compute_txid() {
+            bail!("PSBT is for another transaction");
+        }
+        self.psbt
+            .combine(psbt.clone())
+            .with_context(|| "Failed to merge PSBT")
+    }
+
+    pub fn progress(&self) -> SigningProgress {
+        // (inputs signed, inputs to sign) per cosigner
+        let mut inputs: BTreeMap<Fingerprint, (usize, usize)> = BTreeMap::new();
+        let mut signatures_needed = 0;
+
+        for input in &self.psbt.inputs {
+            let finalized =
+                input.final_script_witness.is_some() || input.final_script_sig.is_some();
+            let mut signatures = 0;
+            for (key, (fingerprint, _)) in &input.bip32_derivation {
+                if !self.cosigners.contains(fingerprint) {
+                    continue;
+                }
+                let signed = finalized || input.partial_sigs.contains_key(&PublicKey::new(*key));
+                let (signed_inputs, total_inputs) = inputs.entry(*fingerprint).or_default();
+                *total_inputs += 1;
+                if signed {
+                    *signed_inputs += 1;
+                    signatures += 1;
+                }
+            }
+            if !finalized {
+                signatures_needed =
+                    signatures_needed.max(self.threshold.saturating_sub(signatures));
+            }
+        }
+
+        let (signed, pending): (Vec<Fingerprint>, Vec<Fingerprint>) =
+            self.cosigners.iter().partition(|fingerprint| {
+                inputs
+                    .get(fingerprint)
+                    .is_some_and(|(signed, total)| signed == total)
+            });
+        let to_strings = |fingerprints: Vec<Fingerprint>| {
+            fingerprints
+                .iter()
+                .map(|fingerprint| fingerprint.to_string().to_uppercase())
+                .collect()
+        };
+        SigningProgress {
+            signed: to_strings(signed),
+            pending: to_strings(pending),
+            threshold: self.threshold,
+            signatures_needed,
+        }
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Start collecting cosigner signatures for `psbt`.
+    pub fn signing_session(&self, psbt: &[u8]) -> Result<SigningSession> {
+        let multisig = self
+            .config
+            .read_or_err()?
+            .multisig
+            .clone()
+            .ok_or(AccountError::NotMultisig)?;
+        let psbt = Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT")?;
+        Ok(SigningSession::new(psbt, &multisig))
+    }
+
+    /// Merge copies of the same unsigned transaction signed by different
+    /// signers, then finalize it if enough signatures were collected (the
+    /// policy threshold for multisig accounts).
+    pub fn combine_psbts(&self, psbts: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut psbts = psbts
+            .iter()
+            .map(|psbt| Psbt::deserialize(psbt).with_context(|| "Failed to deserialize PSBT"));
+        let first = psbts
+            .next()
+            .ok_or_else(|| anyhow!("No PSBTs to combine"))??;
+
+        let multisig = self.config.read_or_err()?.multisig.clone();
+        let mut session = match &multisig {
+            Some(multisig) => SigningSession::new(first, multisig),
+            // single signer accounts are done after one signature
+            None => SigningSession {
+                psbt: first,
+                threshold: 1,
+                cosigners: vec![],
+            },
+        };
+        for psbt in psbts {
+            session.merge(&psbt?)?;
+        }
+
+        let finalize = multisig.is_none() || session.progress().is_complete();
+        let mut psbt = session.into_psbt();
+        if finalize {
+            for wallet in self.all_wallets_or_recover().iter() {
+                wallet
+                    .bdk_wallet
+                    .lock_or_err()?
+                    .finalize_psbt(&mut psbt, SignOptions::default())?;
+            }
+        }
+        Ok(psbt.serialize())
+    }
+
+    /// PSBT for the cosigner `fingerprint` to sign, to check that its device
+    /// still holds its key. It signs `challenge` as a BIP-322 message from
+    /// the first receive address, so it can't be broadcast, and should be
+    /// new for every check so an old signature can't be replayed.
+    pub fn cosigner_challenge(&self, fingerprint: &str, challenge: &str) -> Result<Vec<u8>> {
+        let psbt = self.challenge_psbt(challenge)?;
+        self.cosigner_key(fingerprint, &psbt)?;
+        Ok(psbt.serialize())
+    }
+
+    /// Returns true if `signed_psbt` holds a valid signature of `challenge`
+    /// by the cosigner `fingerprint`, see [`Self::cosigner_challenge`].
+    pub fn verify_cosigner(
+        &self,
+        fingerprint: &str,
+        challenge: &str,
+        signed_psbt: &[u8],
+    ) -> Result<bool> {
+        let mut psbt = self.challenge_psbt(challenge)?;
+        let key = self.cosigner_key(fingerprint, &psbt)?;
+        let signed =
+            Psbt::deserialize(signed_psbt).with_context(|| "Failed to deserialize PSBT")?;
+        if signed.unsigned_tx != psbt.unsigned_tx {
+            // signed another challenge
+            return Ok(false);
+        }
+        let Some(signature) = signed
+            .inputs
+            .first()
+            .and_then(|input| input.partial_sigs.get(&key))
+        else {
+            return Ok(false);
+        };
+
+        psbt.inputs[0].sighash_type = Some(signature.sighash_type.into());
+        let (message, _) = psbt
+            .sighash_ecdsa(0, &mut SighashCache::new(&psbt.unsigned_tx))
+            .map_err(|e| anyhow!("Failed to compute challenge sighash: {e}"))?;
+        Ok(Secp256k1::verification_only()
+            .verify_ecdsa(&message, &signature.signature, &key.inner)
+            .is_ok())
+    }
+
+    // The key of the cosigner `fingerprint` signing the challenge `psbt`.
+    fn cosigner_key(&self, fingerprint: &str, psbt: &Psbt) -> Result<PublicKey> {
+        let multisig = self
+            .config
+            .read_or_err()?
+            .multisig
+            .clone()
+            .ok_or(AccountError::NotMultisig)?;
+        let fingerprint = Fingerprint::from_str(fingerprint)
+            .with_context(|| format!("Invalid fingerprint {fingerprint}"))?;
+        if !multisig
+            .get_signers()
+            .iter()
+            .any(|signer| signer.get_fingerprint() == fingerprint)
+        {
+            bail!("{fingerprint} is not a cosigner of the account");
+        }
+        psbt.inputs[0]
+            .bip32_derivation
+            .iter()
+            .find(|(_, (key_fingerprint, _))| *key_fingerprint == fingerprint)
+            .map(|(key, _)| PublicKey::new(*key))
+            .ok_or_else(|| anyhow!("No key of {fingerprint} in the account descriptor"))
+    }
+
+    fn challenge_psbt(&self, challenge: &str) -> Result<Psbt> {
+        let descriptor = self
+            .get_coordinator_wallet()
+            .bdk_wallet
+            .lock_or_err()?
+            .public_descriptor(KeychainKind::External)
+            .at_derivation_index(0)?;
+        let to_spend = bip322_to_spend(&descriptor.script_pubkey(), challenge);
+        let mut psbt = Psbt::from_unsigned_tx(bip322_to_sign(&to_spend))?;
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(to_spend.output[0].clone());
+        input.non_witness_utxo = Some(to_spend);
+        input
+            .update_with_descriptor_unchecked(&descriptor)
+            .map_err(|e| anyhow!("Failed to prepare cosigner challenge: {e}"))?;
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AddressType, MultiSigSigner};
+    use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+    use bdk_wallet::bitcoin::secp256k1::{Message, Secp256k1};
+    use bdk_wallet::bitcoin::{
+        Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, absolute, ecdsa, transaction,
+    };
+    use std::str::FromStr;
+
+    fn psbt(vout: u32) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(OutPoint::null().txid, vout),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bdk_wallet::bitcoin::Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn tracks_cosigner_signatures() {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let keys: Vec<Xpriv> = (1..=3)
+            .map(|seed| Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap())
+            .collect();
+        let signers = keys
+            .iter()
+            .map(|key| {
+                let xpub = Xpub::from_priv(&secp, &key.derive_priv(&secp, &path).unwrap());
+                MultiSigSigner::new(&path, &key.fingerprint(&secp), &xpub)
+            })
+            .collect();
+        let multisig = MultiSigDetails::new(2, 3, AddressType::P2wsh, None, signers).unwrap();
+
+        let mut unsigned = psbt(0);
+        for key in &keys {
+            unsigned.inputs[0].bip32_derivation.insert(
+                key.private_key.public_key(&secp),
+                (key.fingerprint(&secp), path.clone()),
+            );
+        }
+        let signed_by = |key: &Xpriv| {
+            let mut psbt = unsigned.clone();
+            let signature = secp.sign_ecdsa(&Message::from_digest([1; 32]), &key.private_key);
+            psbt.inputs[0].partial_sigs.insert(
+                PublicKey::new(key.private_key.public_key(&secp)),
+                ecdsa::Signature::sighash_all(signature),
+            );
+            psbt
+        };
+
+        let mut session = SigningSession::new(signed_by(&keys[1]), &multisig);
+        let progress = session.progress();
+        assert_eq!(progress.signatures_needed, 1);
+        assert_eq!(
+            progress.signed,
+            vec![keys[1].fingerprint(&secp).to_string().to_uppercase()]
+        );
+        assert_eq!(progress.pending.len(), 2);
+
+        session.merge(&signed_by(&keys[2])).unwrap();
+        let progress = session.progress();
+        assert!(progress.is_complete());
+        assert_eq!(progress.signed.len(), 2);
+
+        assert!(session.merge(&psbt(1)).is_err());
+    }
+}
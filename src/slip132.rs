@@ -0,0 +1,109 @@
+//! SLIP-132 extended public key encodings.
+//!
+//! Some wallet software still expects the script type of an account to be
+//! encoded in the version bytes of its xpub (`ypub`, `zpub`, `Zpub`, ...).
+//! These helpers convert between those and the standard `xpub`/`tpub` form.
+
+use anyhow::{Context, Result, anyhow};
+use bdk_wallet::bitcoin::NetworkKind;
+use bdk_wallet::bitcoin::base58;
+use bdk_wallet::bitcoin::bip32::Xpub;
+
+use crate::config::AddressType;
+
+pub const XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+pub const YPUB: [u8; 4] = [0x04, 0x9D, 0x7C, 0xB2];
+pub const ZPUB: [u8; 4] = [0x04, 0xB2, 0x47, 0x46];
+pub const YPUB_MULTISIG: [u8; 4] = [0x02, 0x95, 0xB4, 0x3F];
+pub const ZPUB_MULTISIG: [u8; 4] = [0x02, 0xAA, 0x7E, 0xD3];
+
+pub const TPUB: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+pub const UPUB: [u8; 4] = [0x04, 0x4A, 0x52, 0x62];
+pub const VPUB: [u8; 4] = [0x04, 0x5F, 0x1C, 0xF6];
+pub const UPUB_MULTISIG: [u8; 4] = [0x02, 0x42, 0x89, 0xEF];
+pub const VPUB_MULTISIG: [u8; 4] = [0x02, 0x57, 0x54, 0x83];
+
+const MAINNET_VERSIONS: &[[u8; 4]] = &[XPUB, YPUB, ZPUB, YPUB_MULTISIG, ZPUB_MULTISIG];
+const TESTNET_VERSIONS: &[[u8; 4]] = &[TPUB, UPUB, VPUB, UPUB_MULTISIG, VPUB_MULTISIG];
+
+/// Version bytes SLIP-132 assigns to `address_type`.
+///
+/// Script types without a SLIP-132 encoding (P2PKH, P2TR, bare P2SH) use
+/// the standard `xpub`/`tpub` versions.
+pub fn version_bytes(address_type: AddressType, network: NetworkKind) -> [u8; 4] {
+    let mainnet = network == NetworkKind::Main;
+    match (address_type, mainnet) {
+        (AddressType::P2ShWpkh, true) => YPUB,
+        (AddressType::P2ShWpkh, false) => UPUB,
+        (AddressType::P2wpkh, true) => ZPUB,
+        (AddressType::P2wpkh, false) => VPUB,
+        (AddressType::P2ShWsh, true) => YPUB_MULTISIG,
+        (AddressType::P2ShWsh, false) => UPUB_MULTISIG,
+        (AddressType::P2wsh, true) => ZPUB_MULTISIG,
+        (AddressType::P2wsh, false) => VPUB_MULTISIG,
+        (_, true) => XPUB,
+        (_, false) => TPUB,
+    }
+}
+
+/// Encode `xpub` with the SLIP-132 prefix for `address_type`.
+pub fn encode(xpub: &Xpub, address_type: AddressType) -> String {
+    let mut bytes = xpub.encode();
+    bytes[..4].copy_from_slice(&version_bytes(address_type, xpub.network));
+    base58::encode_check(&bytes)
+}
+
+/// Decode an extended public key in any SLIP-132 encoding.
+pub fn decode(key: &str) -> Result<Xpub> {
+    let mut bytes = base58::decode_check(key.trim()).with_context(|| "Invalid base58 key")?;
+    if bytes.len() != 78 {
+        return Err(anyhow!("Invalid extended public key length"));
+    }
+    let version: [u8; 4] = bytes[..4].try_into()?;
+    let standard = if MAINNET_VERSIONS.contains(&version) {
+        XPUB
+    } else if TESTNET_VERSIONS.contains(&version) {
+        TPUB
+    } else {
+        return Err(anyhow!("Unknown extended public key version"));
+    };
+    bytes[..4].copy_from_slice(&standard);
+    Xpub::decode(&bytes).with_context(|| "Invalid extended public key")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP-84 test vector, account 0 of "abandon ... about"
+    const ZPUB_84: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+    #[test]
+    fn zpub_roundtrip() {
+        let xpub = decode(ZPUB_84).unwrap();
+        assert!(xpub.to_string().starts_with("xpub"));
+        assert_eq!(encode(&xpub, AddressType::P2wpkh), ZPUB_84);
+        assert_eq!(encode(&xpub, AddressType::P2pkh), xpub.to_string());
+        assert!(encode(&xpub, AddressType::P2ShWpkh).starts_with("ypub"));
+        assert!(encode(&xpub, AddressType::P2wsh).starts_with("Zpub"));
+    }
+
+    #[test]
+    fn testnet_prefixes() {
+        let mut bytes = decode(ZPUB_84).unwrap().encode();
+        bytes[..4].copy_from_slice(&TPUB);
+        let tpub = Xpub::decode(&bytes).unwrap();
+        let vpub = encode(&tpub, AddressType::P2wpkh);
+        assert!(vpub.starts_with("vpub"));
+        assert_eq!(decode(&vpub).unwrap(), tpub);
+        assert!(encode(&tpub, AddressType::P2ShWsh).starts_with("Upub"));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(decode("not-a-real-xpub").is_err());
+        let mut bytes = decode(ZPUB_84).unwrap().encode();
+        bytes[..4].copy_from_slice(&[0, 0, 0, 0]);
+        assert!(decode(&base58::encode_check(&bytes)).is_err());
+    }
+}
@@ -0,0 +1,755 @@
+//! SLIP-39 Shamir backups.
+//!
+//! [`split`] turns the seed of a [`MasterKey`] into groups of mnemonic
+//! shares, [`combine`] and [`recover_master_key`] rebuild it from enough of
+//! them: `group_threshold` groups, each with at least its own member
+//! threshold of shares. The secret is encrypted with an optional SLIP-39
+//! passphrase before being split.
+//!
+//! New shares are extendable backups (SLIP-39 `ext` flag set); shares of
+//! older non-extendable backups can be combined as well.
+//!
+//! # Compatibility
+//!
+//! As the specification requires, the shared secret is the BIP-32 master
+//! secret: [`split`] shares the 64-byte BIP-39 seed (with its BIP-39
+//! passphrase already applied), and [`recover_master_key`] derives the
+//! master key from whatever secret the shares hold. Shares made here restore
+//! the same wallet in other SLIP-39 implementations and theirs restore the
+//! same wallet here. The 12 or 24 words can't be rebuilt from the shares.
+//! Wallets that only accept 128 or 256-bit secrets, such as Trezor, reject
+//! shares of a 512-bit seed rather than restoring another wallet.
+
+mod wordlist;
+
+use crate::bip39::{self, MasterKey};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::bip32::Xpriv;
+use bdk_wallet::bitcoin::hashes::{Hash, HashEngine, Hmac, HmacEngine, sha256};
+use rand_core::{CryptoRng, RngCore};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+use self::wordlist::WORDLIST;
+
+/// Maximum number of groups, and of members in a group.
+pub const MAX_SHARE_COUNT: u8 = 16;
+
+const SECRET_INDEX: u8 = 255;
+const DIGEST_INDEX: u8 = 254;
+const DIGEST_LEN: usize = 4;
+const ROUND_COUNT: u8 = 4;
+const BASE_ITERATIONS: u32 = 10_000;
+// identifier, flag and exponent, then group and member parameters
+const METADATA_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+const MIN_SECRET_LEN: usize = 16;
+// the round function yields a single PBKDF2 block per half
+const MAX_SECRET_LEN: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown SLIP-39 word")]
+    UnknownWord,
+
+    #[error("invalid SLIP-39 share: {0}")]
+    InvalidShare(&'static str),
+
+    #[error("invalid share checksum")]
+    Checksum,
+
+    #[error("shares belong to different backups")]
+    MismatchedShares,
+
+    #[error("not enough shares to recover the secret")]
+    InsufficientShares,
+
+    #[error("invalid digest, the shares or passphrase are wrong")]
+    Digest,
+
+    #[error("invalid backup parameters: {0}")]
+    InvalidParameters(&'static str),
+
+    #[error("SLIP-39 passphrases must be printable ASCII")]
+    InvalidPassphrase,
+
+    #[error("couldn't recover the seed: {0}")]
+    Seed(#[from] bip39::Error),
+}
+
+/// Member threshold and count of a group of shares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupSpec {
+    pub threshold: u8,
+    pub count: u8,
+}
+
+// x coordinate and value of a share of a secret.
+type IndexedShare = (u8, Zeroizing<Vec<u8>>);
+
+// A decoded share, see the SLIP-39 format.
+#[derive(Clone)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+    fn to_mnemonic(&self) -> Zeroizing<String> {
+        let mut words: Vec<u16> = vec![
+            self.identifier >> 5,
+            (self.identifier & 0x1f) << 5
+                | (self.extendable as u16) << 4
+                | self.iteration_exponent as u16,
+            (self.group_index as u16) << 6
+                | ((self.group_threshold - 1) as u16) << 2
+                | ((self.group_count - 1) as u16) >> 2,
+            ((self.group_count - 1) as u16 & 0x3) << 8
+                | (self.member_index as u16) << 4
+                | (self.member_threshold - 1) as u16,
+        ];
+
+        // the value is left padded with zero bits to a multiple of 10 bits
+        let word_count = (self.value.len() * 8).div_ceil(10);
+        let mut acc: u32 = 0;
+        let mut acc_bits = word_count * 10 - self.value.len() * 8;
+        for byte in self.value.iter() {
+            acc = acc << 8 | *byte as u32;
+            acc_bits += 8;
+            while acc_bits >= 10 {
+                acc_bits -= 10;
+                words.push((acc >> acc_bits) as u16 & 0x3ff);
+            }
+            acc &= (1 << acc_bits) - 1;
+        }
+        acc.zeroize();
+
+        let mut checksum = polymod(self.extendable, words.iter().chain(&[0; CHECKSUM_WORDS])) ^ 1;
+        words.extend((0..CHECKSUM_WORDS).map(|i| (checksum >> (10 * (2 - i))) as u16 & 0x3ff));
+
+        let mnemonic = words
+            .iter()
+            .map(|word| WORDLIST[*word as usize])
+            .collect::<Vec<_>>()
+            .join(" ");
+        words.zeroize();
+        checksum.zeroize();
+        Zeroizing::new(mnemonic)
+    }
+
+    fn from_mnemonic(mnemonic: &str) -> Result<Self, Error> {
+        let mut words = Zeroizing::new(Vec::new());
+        for word in mnemonic.split_whitespace() {
+            let word = word.to_lowercase();
+            let index = WORDLIST
+                .binary_search(&word.as_str())
+                .map_err(|_| Error::UnknownWord)?;
+            words.push(index as u16);
+        }
+
+        let value_words = words
+            .len()
+            .checked_sub(METADATA_WORDS + CHECKSUM_WORDS)
+            .ok_or(Error::InvalidShare("too short"))?;
+        let padding = value_words * 10 % 16;
+        if value_words * 10 < MIN_SECRET_LEN * 8 || padding > 8 {
+            return Err(Error::InvalidShare("invalid length"));
+        }
+
+        let extendable = words[1] >> 4 & 1 == 1;
+        if polymod(extendable, words.iter()) != 1 {
+            return Err(Error::Checksum);
+        }
+
+        let group_threshold = (words[2] >> 2 & 0xf) as u8 + 1;
+        let group_count = ((words[2] & 0x3) << 2 | words[3] >> 8) as u8 + 1;
+        if group_threshold > group_count {
+            return Err(Error::InvalidShare("group threshold above group count"));
+        }
+
+        let mut value = Zeroizing::new(Vec::with_capacity(value_words * 10 / 8));
+        let mut acc: u32 = 0;
+        let mut acc_bits = 0;
+        for (i, word) in words[METADATA_WORDS..METADATA_WORDS + value_words]
+            .iter()
+            .enumerate()
+        {
+            acc = acc << 10 | *word as u32;
+            acc_bits += 10;
+            if i == 0 {
+                if acc >> (10 - padding) != 0 {
+                    return Err(Error::InvalidShare("invalid padding"));
+                }
+                acc_bits -= padding;
+            }
+            while acc_bits >= 8 {
+                acc_bits -= 8;
+                value.push((acc >> acc_bits) as u8);
+            }
+            acc &= (1 << acc_bits) - 1;
+        }
+        acc.zeroize();
+
+        Ok(Self {
+            identifier: words[0] << 5 | words[1] >> 5,
+            extendable,
+            iteration_exponent: (words[1] & 0xf) as u8,
+            group_index: (words[2] >> 6) as u8,
+            group_threshold,
+            group_count,
+            member_index: (words[3] >> 4 & 0xf) as u8,
+            member_threshold: (words[3] & 0xf) as u8 + 1,
+            value,
+        })
+    }
+}
+
+// RS1024 checksum over the words, prefixed by the customization string.
+fn polymod<'a>(extendable: bool, words: impl Iterator<Item = &'a u16>) -> u32 {
+    const GENERATOR: [u32; 10] = [
+        0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009, 0x1C0C2412, 0x38086C24, 0x3090FC48,
+        0x21B1F890, 0x3F3F120,
+    ];
+    let customization: &[u8] = match extendable {
+        true => b"shamir_extendable",
+        false => b"shamir",
+    };
+    let mut checksum: u32 = 1;
+    let values = customization.iter().map(|b| *b as u32);
+    for value in values.chain(words.map(|w| *w as u32)) {
+        let top = checksum >> 20;
+        checksum = (checksum & 0xfffff) << 10 ^ value;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if top >> i & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+// GF(256) with the Rijndael polynomial, as exponent and logarithm tables of
+// the generator 3.
+const fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = poly as u8;
+        log[poly as usize] = i as u8;
+        poly = (poly << 1) ^ poly;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+const GF: ([u8; 255], [u8; 256]) = gf_tables();
+
+// Value at `x` of the polynomial going through `shares`.
+fn interpolate(shares: &[(u8, &[u8])], x: u8) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let (exp, log) = &GF;
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return Ok(Zeroizing::new(value.to_vec()));
+    }
+    let len = shares.first().ok_or(Error::InsufficientShares)?.1.len();
+    if shares.iter().any(|(_, value)| value.len() != len) {
+        return Err(Error::MismatchedShares);
+    }
+
+    let log_product: u32 = shares
+        .iter()
+        .map(|(i, _)| log[(i ^ x) as usize] as u32)
+        .sum();
+    let mut result = Zeroizing::new(vec![0u8; len]);
+    for (i, value) in shares {
+        let log_denominator: u32 = shares
+            .iter()
+            .map(|(j, _)| log[(j ^ i) as usize] as u32)
+            .sum();
+        let log_basis = (log_product + 255 * (shares.len() as u32 + 1)
+            - log[(i ^ x) as usize] as u32
+            - log_denominator)
+            % 255;
+        for (byte, y) in result.iter_mut().zip(value.iter()) {
+            if *y != 0 {
+                *byte ^= exp[((log[*y as usize] as u32 + log_basis) % 255) as usize];
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn digest(random: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(random);
+    engine.input(secret);
+    let hmac = Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+    let mut digest = [0; DIGEST_LEN];
+    digest.copy_from_slice(&hmac[..DIGEST_LEN]);
+    digest
+}
+
+fn split_secret<R: RngCore + CryptoRng>(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+    rng: &mut R,
+) -> Result<Vec<IndexedShare>, Error> {
+    if threshold == 1 {
+        return Ok((0..count)
+            .map(|index| (index, Zeroizing::new(secret.to_vec())))
+            .collect());
+    }
+
+    let mut shares = Vec::with_capacity(count as usize);
+    for index in 0..threshold - 2 {
+        let mut value = Zeroizing::new(vec![0u8; secret.len()]);
+        rng.fill_bytes(&mut value);
+        shares.push((index, value));
+    }
+    let mut digest_share = Zeroizing::new(vec![0u8; secret.len()]);
+    rng.fill_bytes(&mut digest_share[DIGEST_LEN..]);
+    let checksum = digest(&digest_share[DIGEST_LEN..], secret);
+    digest_share[..DIGEST_LEN].copy_from_slice(&checksum);
+
+    let mut base: Vec<(u8, &[u8])> = shares
+        .iter()
+        .map(|(index, value)| (*index, value.as_slice()))
+        .collect();
+    base.push((DIGEST_INDEX, &digest_share));
+    base.push((SECRET_INDEX, secret));
+    let mut interpolated = Vec::new();
+    for index in threshold - 2..count {
+        interpolated.push((index, interpolate(&base, index)?));
+    }
+    shares.extend(interpolated);
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, &[u8])]) -> Result<Zeroizing<Vec<u8>>, Error> {
+    if shares.len() < threshold as usize {
+        return Err(Error::InsufficientShares);
+    }
+    if threshold == 1 {
+        return Ok(Zeroizing::new(shares[0].1.to_vec()));
+    }
+    let shares = &shares[..threshold as usize];
+    let secret = interpolate(shares, SECRET_INDEX)?;
+    let digest_share = interpolate(shares, DIGEST_INDEX)?;
+    if digest(&digest_share[DIGEST_LEN..], &secret) != digest_share[..DIGEST_LEN] {
+        return Err(Error::Digest);
+    }
+    Ok(secret)
+}
+
+// The four round Feistel network encrypting the master secret, with
+// PBKDF2-HMAC-SHA256 as round function.
+fn feistel(
+    secret: &[u8],
+    passphrase: &str,
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    encrypt: bool,
+) -> Zeroizing<Vec<u8>> {
+    let half = secret.len() / 2;
+    let mut left = Zeroizing::new(secret[..half].to_vec());
+    let mut right = Zeroizing::new(secret[half..].to_vec());
+    let mut salt = Vec::new();
+    if !extendable {
+        salt.extend_from_slice(b"shamir");
+        salt.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let iterations = (BASE_ITERATIONS << iteration_exponent) / ROUND_COUNT as u32;
+
+    for round in 0..ROUND_COUNT {
+        let round = match encrypt {
+            true => round,
+            false => ROUND_COUNT - 1 - round,
+        };
+        let mut password = Zeroizing::new(vec![round]);
+        password.extend_from_slice(passphrase.as_bytes());
+        let mut round_salt = salt.clone();
+        round_salt.extend_from_slice(&right);
+        let mut output = pbkdf2_sha256(&password, &round_salt, iterations);
+        let next: Vec<u8> = left.iter().zip(output.iter()).map(|(l, f)| l ^ f).collect();
+        output.zeroize();
+        left = core::mem::replace(&mut right, Zeroizing::new(next));
+    }
+    let mut result = Zeroizing::new(right.to_vec());
+    result.extend_from_slice(&left);
+    result
+}
+
+// A single block of PBKDF2 output, enough for halves of secrets up to 64
+// bytes.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(password);
+    engine.input(salt);
+    engine.input(&1u32.to_be_bytes());
+    let mut block = Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+    let mut output = block;
+    for _ in 1..iterations {
+        let mut engine = HmacEngine::<sha256::Hash>::new(password);
+        engine.input(&block);
+        block = Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+        for (o, b) in output.iter_mut().zip(block.iter()) {
+            *o ^= b;
+        }
+    }
+    block.zeroize();
+    output
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), Error> {
+    match passphrase.bytes().all(|b| (32..=126).contains(&b)) {
+        true => Ok(()),
+        false => Err(Error::InvalidPassphrase),
+    }
+}
+
+/// Split `secret` into `groups` of mnemonic shares, `group_threshold` of
+/// which are needed to recover it. Stretching of the passphrase is doubled
+/// with each `iteration_exponent` step.
+pub fn split_secret_into_groups<R: RngCore + CryptoRng>(
+    secret: &[u8],
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[GroupSpec],
+    iteration_exponent: u8,
+    rng: &mut R,
+) -> Result<Vec<Vec<Zeroizing<String>>>, Error> {
+    if !(MIN_SECRET_LEN..=MAX_SECRET_LEN).contains(&secret.len()) || !secret.len().is_multiple_of(2)
+    {
+        return Err(Error::InvalidParameters(
+            "secret must be an even number of bytes, from 16 to 64",
+        ));
+    }
+    if iteration_exponent > 0xf {
+        return Err(Error::InvalidParameters("iteration exponent above 15"));
+    }
+    if groups.is_empty() || groups.len() > MAX_SHARE_COUNT as usize {
+        return Err(Error::InvalidParameters("1 to 16 groups are needed"));
+    }
+    if group_threshold == 0 || group_threshold as usize > groups.len() {
+        return Err(Error::InvalidParameters(
+            "group threshold must be between 1 and the group count",
+        ));
+    }
+    for group in groups {
+        if group.threshold == 0 || group.threshold > group.count || group.count > MAX_SHARE_COUNT {
+            return Err(Error::InvalidParameters(
+                "member threshold must be between 1 and the member count, at most 16",
+            ));
+        }
+        if group.threshold == 1 && group.count > 1 {
+            return Err(Error::InvalidParameters(
+                "groups with a threshold of 1 must have a single member",
+            ));
+        }
+    }
+    check_passphrase(passphrase)?;
+
+    let identifier = (rng.next_u32() & 0x7fff) as u16;
+    let extendable = true;
+    let encrypted = feistel(
+        secret,
+        passphrase,
+        iteration_exponent,
+        identifier,
+        extendable,
+        true,
+    );
+
+    let group_shares = split_secret(group_threshold, groups.len() as u8, &encrypted, rng)?;
+    let mut mnemonics = Vec::with_capacity(groups.len());
+    for ((group_index, group_secret), group) in group_shares.iter().zip(groups) {
+        let member_shares = split_secret(group.threshold, group.count, group_secret, rng)?;
+        mnemonics.push(
+            member_shares
+                .into_iter()
+                .map(|(member_index, value)| {
+                    Share {
+                        identifier,
+                        extendable,
+                        iteration_exponent,
+                        group_index: *group_index,
+                        group_threshold,
+                        group_count: groups.len() as u8,
+                        member_index,
+                        member_threshold: group.threshold,
+                        value,
+                    }
+                    .to_mnemonic()
+                })
+                .collect(),
+        );
+    }
+    Ok(mnemonics)
+}
+
+/// Split the BIP-39 seed of `master_key` into SLIP-39 share groups, see
+/// [`split_secret_into_groups`].
+///
+/// The seed is the BIP-32 master secret, so the shares restore the same
+/// wallet in other SLIP-39 implementations, see the
+/// [module documentation](self#compatibility).
+pub fn split<R: RngCore + CryptoRng>(
+    master_key: &MasterKey,
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[GroupSpec],
+    rng: &mut R,
+) -> Result<Vec<Vec<Zeroizing<String>>>, Error> {
+    split_secret_into_groups(
+        &master_key.key.0,
+        passphrase,
+        group_threshold,
+        groups,
+        0,
+        rng,
+    )
+}
+
+/// Recover the secret from the `mnemonics` shares, decrypting it with
+/// `passphrase`. A wrong passphrase gives a different secret, not an error.
+pub fn combine(mnemonics: &[&str], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+    check_passphrase(passphrase)?;
+    let shares = mnemonics
+        .iter()
+        .map(|mnemonic| Share::from_mnemonic(mnemonic))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = shares.first().ok_or(Error::InsufficientShares)?;
+    if shares.iter().any(|share| {
+        share.identifier != first.identifier
+            || share.extendable != first.extendable
+            || share.iteration_exponent != first.iteration_exponent
+            || share.group_threshold != first.group_threshold
+            || share.group_count != first.group_count
+            || share.value.len() != first.value.len()
+    }) {
+        return Err(Error::MismatchedShares);
+    }
+    if first.value.len() > MAX_SECRET_LEN || !first.value.len().is_multiple_of(2) {
+        return Err(Error::InvalidShare("unsupported secret length"));
+    }
+
+    let mut group_secrets: Vec<IndexedShare> = Vec::new();
+    for group_index in 0..first.group_count {
+        let mut members: Vec<&Share> = Vec::new();
+        for share in shares.iter().filter(|s| s.group_index == group_index) {
+            if !members.iter().any(|m| m.member_index == share.member_index) {
+                members.push(share);
+            }
+        }
+        let Some(threshold) = members.first().map(|m| m.member_threshold) else {
+            continue;
+        };
+        if members.iter().any(|m| m.member_threshold != threshold) {
+            return Err(Error::MismatchedShares);
+        }
+        // groups short of their threshold are left out
+        if members.len() < threshold as usize {
+            continue;
+        }
+        let member_values: Vec<(u8, &[u8])> = members
+            .iter()
+            .map(|m| (m.member_index, m.value.as_slice()))
+            .collect();
+        group_secrets.push((group_index, recover_secret(threshold, &member_values)?));
+    }
+
+    let group_values: Vec<(u8, &[u8])> = group_secrets
+        .iter()
+        .map(|(index, value)| (*index, value.as_slice()))
+        .collect();
+    let encrypted = recover_secret(first.group_threshold, &group_values)?;
+    Ok(feistel(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        first.identifier,
+        first.extendable,
+        false,
+    ))
+}
+
+/// Recover the BIP-32 master key from the `mnemonics` shares, whether they
+/// were made with [`split`] or by another SLIP-39 wallet. `passphrase` is
+/// the SLIP-39 passphrase.
+pub fn recover_master_key(
+    network: Network,
+    mnemonics: &[&str],
+    passphrase: &str,
+) -> Result<Xpriv, Error> {
+    let secret = combine(mnemonics, passphrase)?;
+    Xpriv::new_master(network, &secret).map_err(|e| Error::Seed(bip39::Error::from(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_wallet::bitcoin::hex::DisplayHex;
+    use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+
+    // Deterministic stand-in for the device RNG.
+    struct TestRng(u64);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+            self.0 >> 11
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest {
+                *byte = self.next_u64() as u8;
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for TestRng {}
+
+    #[test]
+    fn slip39_test_vectors() {
+        // a single share
+        let secret = combine(
+            &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            secret.to_lower_hex_string(),
+            "bb54aac4b89dc868ba37d9cc21b2cece"
+        );
+        assert_eq!(
+            recover_master_key(
+                Network::Bitcoin,
+                &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+                "TREZOR",
+            )
+            .unwrap()
+            .to_string(),
+            "xprv9s21ZrQH143K4QViKpwKCpS2zVbz8GrZgpEchMDg6KME9HZtjfL7iThE9w5muQA4YPHKN1u5VM1w8D4pvnjxa2BmpGMfXr7hnRrRHZ93awZ"
+        );
+
+        // two shares of a 2-of-3 group
+        let shares = [
+            "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+            "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+        ];
+        let secret = combine(&shares, "TREZOR").unwrap();
+        assert_eq!(
+            secret.to_lower_hex_string(),
+            "b43ceb7e57a0ea8766221624d01b0864"
+        );
+        assert_eq!(
+            recover_master_key(Network::Bitcoin, &shares, "TREZOR")
+                .unwrap()
+                .to_string(),
+            "xprv9s21ZrQH143K2nNuAbfWPHBtfiSCS14XQgb3otW4pX655q58EEZeC8zmjEUwucBu9dPnxdpbZLCn57yx45RBkwJHnwHFjZK4XPJ8SyeYjYg"
+        );
+        assert!(matches!(
+            combine(&shares[..1], "TREZOR"),
+            Err(Error::InsufficientShares)
+        ));
+
+        let typo = shares[0].replace("wrist", "wrap");
+        assert!(matches!(
+            combine(&[&typo, shares[1]], "TREZOR"),
+            Err(Error::Checksum)
+        ));
+    }
+
+    #[test]
+    fn split_and_combine_groups() {
+        let secret = [7u8; 32];
+        let groups = [
+            GroupSpec {
+                threshold: 1,
+                count: 1,
+            },
+            GroupSpec {
+                threshold: 2,
+                count: 3,
+            },
+            GroupSpec {
+                threshold: 3,
+                count: 5,
+            },
+        ];
+        let shares = split_secret_into_groups(&secret, "", 2, &groups, 0, &mut TestRng(1)).unwrap();
+        assert_eq!(shares.len(), 3);
+        assert_eq!(shares[2].len(), 5);
+        assert_eq!(shares[0][0].split(' ').count(), 33);
+
+        // the single share of the first group and two of the second
+        let picked = [
+            shares[0][0].as_str(),
+            shares[1][2].as_str(),
+            shares[1][0].as_str(),
+        ];
+        assert_eq!(combine(&picked, "").unwrap().as_slice(), secret);
+
+        // a group short of its threshold doesn't count
+        let picked = [
+            shares[0][0].as_str(),
+            shares[2][0].as_str(),
+            shares[2][1].as_str(),
+        ];
+        assert!(matches!(
+            combine(&picked, ""),
+            Err(Error::InsufficientShares)
+        ));
+
+        assert!(
+            split_secret_into_groups(&secret, "", 2, &groups[..1], 0, &mut TestRng(1)).is_err()
+        );
+    }
+
+    #[test]
+    fn master_key_round_trip() {
+        let secp = Secp256k1::new();
+        let master_key =
+            MasterKey::from_entropy(&secp, Network::Bitcoin, &[3u8; 16], "", None).unwrap();
+        let groups = [GroupSpec {
+            threshold: 2,
+            count: 3,
+        }];
+        let shares = split(&master_key, "slip39", 1, &groups, &mut TestRng(9)).unwrap();
+        let picked = [shares[0][1].as_str(), shares[0][2].as_str()];
+
+        // the shared secret is the seed itself, as other implementations expect
+        assert_eq!(
+            combine(&picked, "slip39").unwrap().as_slice(),
+            master_key.key.0
+        );
+
+        let recovered = recover_master_key(Network::Bitcoin, &picked, "slip39").unwrap();
+        assert_eq!(recovered.fingerprint(&secp), master_key.fingerprint);
+
+        let wrong = recover_master_key(Network::Bitcoin, &picked, "").unwrap();
+        assert_ne!(wrong.fingerprint(&secp), master_key.fingerprint);
+    }
+}
@@ -0,0 +1,134 @@
+//! Cached account summary for a fast start.
+//!
+//! Loading the BDK wallets of an account reads its whole chain and
+//! transaction graph, too slow to wait for before showing anything. Every
+//! [`NgAccount::persist`] stores an [`AccountSnapshot`] in the
+//! [`MetaStorage`], and [`AccountSnapshot::load`] reads it back from the
+//! metadata database alone, so the app can show the balance, the last
+//! transactions and a receive address while the wallets load.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::archive::BalanceSnapshot;
+use crate::db::RedbMetaStorage;
+use crate::error::{MutexExt, RwLockExt};
+use crate::store::MetaStorage;
+use crate::transaction::BitcoinTransaction;
+
+/// Transactions kept in a snapshot, most recent first.
+pub const SNAPSHOT_TRANSACTIONS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxSummary {
+    pub tx_id: String,
+    /// Received minus sent by the account, in sats.
+    pub amount: i64,
+    pub fee: u64,
+    pub is_confirmed: bool,
+    pub date: Option<u64>,
+    pub note: Option<String>,
+}
+
+impl From<&BitcoinTransaction> for TxSummary {
+    fn from(tx: &BitcoinTransaction) -> Self {
+        Self {
+            tx_id: tx.tx_id.clone(),
+            amount: tx.amount,
+            fee: tx.fee,
+            is_confirmed: tx.is_confirmed,
+            date: tx.date,
+            note: tx.note.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub account_id: String,
+    pub balance: BalanceSnapshot,
+    /// The last [`SNAPSHOT_TRANSACTIONS`] transactions, most recent first.
+    pub last_tx_summaries: Vec<TxSummary>,
+    /// Next unused receive address of the coordinator wallet.
+    pub next_receive_address: String,
+    /// Unix time of the snapshot, in seconds.
+    pub taken_at: u64,
+}
+
+impl AccountSnapshot {
+    /// Snapshot of the account stored by [`RedbMetaStorage::from_file`] in
+    /// `db_path`, without loading its wallets. `None` if the account was
+    /// never persisted with a snapshot.
+    pub fn load(db_path: Option<String>) -> Result<Option<Self>> {
+        Self::from_storage(&RedbMetaStorage::from_file(db_path)?)
+    }
+
+    pub fn from_storage(storage: &dyn MetaStorage) -> Result<Option<Self>> {
+        storage
+            .get_account_snapshot()?
+            .map(|snapshot| {
+                serde_json::from_str(&snapshot).with_context(|| "Invalid account snapshot")
+            })
+            .transpose()
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Balance, last transactions and next receive address of the account.
+    /// The receive address is not revealed.
+    pub fn snapshot(&self) -> Result<AccountSnapshot> {
+        let balance = self.balance()?.into();
+        let last_tx_summaries = self
+            .transactions()?
+            .iter()
+            .take(SNAPSHOT_TRANSACTIONS)
+            .map(TxSummary::from)
+            .collect();
+        let next_receive_address = {
+            let wallet = self.get_coordinator_wallet();
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let address = match bdk_wallet
+                .list_unused_addresses(KeychainKind::External)
+                .next()
+            {
+                Some(address) => address,
+                None => bdk_wallet.peek_address(
+                    KeychainKind::External,
+                    bdk_wallet.next_derivation_index(KeychainKind::External),
+                ),
+            };
+            address.address.to_string()
+        };
+        Ok(AccountSnapshot {
+            account_id: self.config.read_or_err()?.id.clone(),
+            balance,
+            last_tx_summaries,
+            next_receive_address,
+            taken_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Store the snapshot of the account. Archived accounts keep the one of
+    /// their last persist before archiving, and so do lazily opened
+    /// accounts until all their wallets are open.
+    pub(crate) fn record_snapshot(&self) -> Result<()> {
+        let open = self.wallets.read_or_err()?.len();
+        if self.is_archived() || open == 0 || self.address_types()?.len() > open {
+            return Ok(());
+        }
+        let snapshot = serde_json::to_string(&self.snapshot()?)?;
+        self.meta_storage.set_account_snapshot(&snapshot)
+    }
+
+    /// Snapshot stored by the last persist.
+    pub fn stored_snapshot(&self) -> Result<Option<AccountSnapshot>> {
+        AccountSnapshot::from_storage(self.meta_storage.as_ref())
+    }
+}
@@ -0,0 +1,114 @@
+//! Persisted index of the script pubkeys revealed by the account.
+//!
+//! Finding the owner of a script otherwise means locking every wallet of
+//! the account, and verifying an address means deriving it again. The
+//! index maps a hash of every revealed script pubkey to the wallet and
+//! derivation it belongs to, in the account's [`MetaStorage`], so lookups
+//! need neither and survive restarts. It is updated when a wallet is
+//! loaded, persisted or synced; a script missing from it may still be
+//! ours, so callers fall back to asking the wallets.
+
+use anyhow::Result;
+use bdk_wallet::bitcoin::Script;
+use bdk_wallet::bitcoin::hashes::{Hash, sha256};
+use bdk_wallet::bitcoin::hex::DisplayHex;
+use bdk_wallet::{KeychainKind, Wallet};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AddressType;
+use crate::store::MetaStorage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpkDerivation {
+    /// The wallet of the account the script belongs to.
+    pub address_type: AddressType,
+    pub keychain: KeychainKind,
+    pub index: u32,
+}
+
+/// Key of `script` in the index, the first 16 bytes of its SHA-256.
+pub fn spk_key(script: &Script) -> String {
+    let hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash[..16].to_lower_hex_string()
+}
+
+/// Derivation of `script`, if a wallet of the account revealed it.
+pub fn lookup(storage: &dyn MetaStorage, script: &Script) -> Result<Option<SpkDerivation>> {
+    storage.get_spk_derivation(&spk_key(script))
+}
+
+/// Index the scripts `wallet` revealed since it was last indexed.
+pub(crate) fn index_revealed(
+    storage: &dyn MetaStorage,
+    address_type: AddressType,
+    wallet: &Wallet,
+) -> Result<()> {
+    let mut derivations = vec![];
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        // scripts are indexed from 0 up, so the newest ones are the only
+        // ones missing
+        for (index, script) in wallet.spk_index().revealed_keychain_spks(keychain).rev() {
+            let key = spk_key(&script);
+            if storage.get_spk_derivation(&key)?.is_some() {
+                break;
+            }
+            derivations.push((
+                key,
+                SpkDerivation {
+                    address_type,
+                    keychain,
+                    index,
+                },
+            ));
+        }
+    }
+    if !derivations.is_empty() {
+        storage.set_spk_derivations(&derivations)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::InMemoryMetaStorage;
+    use bdk_wallet::bitcoin::Network;
+
+    const EXTERNAL: &str = "wpkh([20a6ab53/84'/1'/0']tpubDC4BKZc39XVBnaTSKLw9ks63KuuEFKdRB17PZMx6GfgxaMHhV79e3zSoVT2TDe9yxwyzm1YHMS8JFNQYWoTvkLJNHa5mTyA5Gkx8NwWVkvU/0/*)";
+    const INTERNAL: &str = "wpkh([20a6ab53/84'/1'/0']tpubDC4BKZc39XVBnaTSKLw9ks63KuuEFKdRB17PZMx6GfgxaMHhV79e3zSoVT2TDe9yxwyzm1YHMS8JFNQYWoTvkLJNHa5mTyA5Gkx8NwWVkvU/1/*)";
+
+    #[test]
+    fn revealed_scripts_are_indexed() {
+        let storage = InMemoryMetaStorage::default();
+        let mut wallet = Wallet::create(EXTERNAL, INTERNAL)
+            .network(Network::Testnet)
+            .create_wallet_no_persist()
+            .unwrap();
+        let _ = wallet.reveal_addresses_to(KeychainKind::External, 2);
+        index_revealed(&storage, AddressType::P2wpkh, &wallet).unwrap();
+
+        let script = wallet
+            .peek_address(KeychainKind::External, 2)
+            .script_pubkey();
+        assert_eq!(
+            lookup(&storage, &script).unwrap(),
+            Some(SpkDerivation {
+                address_type: AddressType::P2wpkh,
+                keychain: KeychainKind::External,
+                index: 2,
+            })
+        );
+
+        // only revealed scripts are indexed, until they are revealed
+        let change = wallet
+            .peek_address(KeychainKind::Internal, 0)
+            .script_pubkey();
+        assert_eq!(lookup(&storage, &change).unwrap(), None);
+        let _ = wallet.reveal_next_address(KeychainKind::Internal);
+        index_revealed(&storage, AddressType::P2wpkh, &wallet).unwrap();
+        assert_eq!(
+            lookup(&storage, &change).unwrap().map(|d| d.keychain),
+            Some(KeychainKind::Internal)
+        );
+    }
+}
@@ -1,12 +1,20 @@
+use crate::archive::BalanceSnapshot;
 use crate::config::{AddressType, NgAccountConfig};
+use crate::fiat::FiatValue;
+use crate::spk_index::SpkDerivation;
+use crate::sync_plan::SyncCheckpoint;
 use anyhow::Result;
 use bdk_wallet::KeychainKind;
-use std::{fmt::Debug, sync::Mutex};
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
 
 pub trait MetaStorage: Debug + Send + Sync {
     fn set_fee(&self, txid: &str, fee: u64) -> Result<()>;
     fn get_fee(&self, txid: &str) -> Result<Option<u64>>;
 
+    fn set_fiat_value(&self, txid: &str, value: &FiatValue) -> Result<()>;
+    fn get_fiat_value(&self, txid: &str) -> Result<Option<FiatValue>>;
+
     fn set_note(&self, key: &str, value: &str) -> Result<()>;
     fn get_note(&self, key: &str) -> Result<Option<String>>;
 
@@ -19,9 +27,17 @@ pub trait MetaStorage: Debug + Send + Sync {
     fn set_do_not_spend(&self, key: &str, value: bool) -> Result<()>;
     fn get_do_not_spend(&self, key: &str) -> Result<bool>;
 
+    /// Tag level do-not-spend policy, applies to every output carrying the tag.
+    fn set_tag_do_not_spend(&self, tag: &str, value: bool) -> Result<()>;
+    fn get_tag_do_not_spend(&self, tag: &str) -> Result<bool>;
+
     fn set_config(&self, deserialized_config: &str) -> Result<()>;
     fn get_config(&self) -> Result<Option<NgAccountConfig>>;
 
+    /// Balance kept while the account is archived.
+    fn set_balance_snapshot(&self, snapshot: &BalanceSnapshot) -> Result<()>;
+    fn get_balance_snapshot(&self) -> Result<Option<BalanceSnapshot>>;
+
     fn set_last_verified_address(
         &self,
         address_type: AddressType,
@@ -34,6 +50,82 @@ pub trait MetaStorage: Debug + Send + Sync {
         keychain: KeychainKind,
     ) -> Result<u32>;
 
+    /// Serialized [`crate::account::VerificationSearch`] of the address
+    /// being verified with `address_type`, `None` once it is found.
+    fn set_verification_search(
+        &self,
+        address_type: AddressType,
+        search: Option<&str>,
+    ) -> Result<()>;
+    fn get_verification_search(&self, address_type: AddressType) -> Result<Option<String>>;
+
+    /// Forget the last verified addresses and the searches in progress.
+    fn reset_verification_state(&self) -> Result<()>;
+
+    /// Derivations of the revealed script pubkeys, keyed by
+    /// [`crate::spk_index::spk_key`].
+    fn set_spk_derivations(&self, derivations: &[(String, SpkDerivation)]) -> Result<()>;
+    fn get_spk_derivation(&self, key: &str) -> Result<Option<SpkDerivation>>;
+
+    /// Where the last sync of the wallet of `address_type` left it.
+    fn set_sync_checkpoint(
+        &self,
+        address_type: AddressType,
+        checkpoint: &SyncCheckpoint,
+    ) -> Result<()>;
+    fn get_sync_checkpoint(&self, address_type: AddressType) -> Result<Option<SyncCheckpoint>>;
+    fn remove_sync_checkpoint(&self, address_type: AddressType) -> Result<()>;
+
+    /// BIP-85 children handed out, keyed by their application path like
+    /// `39'/0'/12'/0'`, with the label the user gave them.
+    fn set_bip85_child(&self, path: &str, label: &str) -> Result<()>;
+    fn list_bip85_children(&self) -> Result<Vec<(String, String)>>;
+
+    /// Serialized [`crate::policy::SignedWhitelist`] the account signs
+    /// against.
+    fn set_whitelist(&self, whitelist: &str) -> Result<()>;
+    fn get_whitelist(&self) -> Result<Option<String>>;
+
+    /// Hex encoded key of the metadata sync, shared with the paired device.
+    fn set_sync_key(&self, key: &str) -> Result<()>;
+    fn get_sync_key(&self) -> Result<Option<String>>;
+
+    /// Serialized [`crate::snapshot::AccountSnapshot`] of the last persist.
+    fn set_account_snapshot(&self, snapshot: &str) -> Result<()>;
+    fn get_account_snapshot(&self) -> Result<Option<String>>;
+
+    /// Serialized [`crate::journal::PersistJournal`] of a persist in
+    /// progress, `None` once it is committed.
+    fn set_persist_journal(&self, journal: Option<&str>) -> Result<()>;
+    fn get_persist_journal(&self) -> Result<Option<String>>;
+
+    /// Serialized [`crate::templates::PaymentTemplate`]s, keyed by name.
+    fn set_payment_template(&self, name: &str, template: &str) -> Result<()>;
+    fn remove_payment_template(&self, name: &str) -> Result<()>;
+    fn list_payment_templates(&self) -> Result<Vec<String>>;
+
+    /// Serialized [`crate::tags::TagInfo`]s, keyed by lowercase tag name.
+    fn set_tag_info(&self, tag: &str, info: &str) -> Result<()>;
+    fn remove_tag_info(&self, tag: &str) -> Result<()>;
+    fn list_tag_infos(&self) -> Result<Vec<String>>;
+
+    /// Serialized [`crate::account::AppliedUpdate`]s of the remote updates
+    /// applied, keyed by the hash of their payload.
+    fn set_applied_update(&self, hash: &str, update: &str) -> Result<()>;
+    fn get_applied_update(&self, hash: &str) -> Result<Option<String>>;
+    fn remove_applied_update(&self, hash: &str) -> Result<()>;
+    fn list_applied_updates(&self) -> Result<Vec<String>>;
+
+    /// A batch of writes owned by the caller, see [`MetaBatch`]. Storages
+    /// without transactions return `None` and write immediately.
+    fn begin_batch(&self) -> Result<Option<MetaBatch>> {
+        Ok(None)
+    }
+
+    /// Delete all metadata of the account, returning the names of the
+    /// tables that were dropped.
+    fn wipe(&self) -> Result<Vec<String>>;
+
     fn persist(&self) -> Result<bool>;
 }
 
@@ -44,8 +136,82 @@ pub struct InMemoryMetaStorage {
     tag_store: Map<String, String>,
     tag_list: Map<String, String>,
     do_not_spend_store: Map<String, bool>,
+    do_not_spend_tags: Map<String, bool>,
     last_verified_address_store: Map<(AddressType, KeychainKind), u32>,
+    verification_searches: Map<AddressType, String>,
+    spk_index: Map<String, SpkDerivation>,
+    sync_checkpoints: Map<AddressType, SyncCheckpoint>,
     fee_store: Map<String, u64>,
+    fiat_store: Map<String, FiatValue>,
+    balance_snapshot: Mutex<Option<BalanceSnapshot>>,
+    bip85_children: Map<String, String>,
+    whitelist: Mutex<Option<String>>,
+    sync_key: Mutex<Option<String>>,
+    account_snapshot: Mutex<Option<String>>,
+    persist_journal: Mutex<Option<String>>,
+    payment_templates: Map<String, String>,
+    tag_infos: Map<String, String>,
+    applied_updates: Map<String, String>,
+}
+
+/// Writes queued through [`MetaBatch::storage`] and written at once by
+/// [`MetaBatch::commit`]. Reads through it don't see the queued writes, and
+/// dropping the batch without committing discards them. Writes made to the
+/// storage the batch was begun on aren't part of it.
+pub struct MetaBatch {
+    storage: Arc<dyn MetaStorage>,
+    commit: Box<dyn FnOnce() -> Result<()> + Send>,
+}
+
+impl Debug for MetaBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetaBatch")
+            .field("storage", &self.storage)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MetaBatch {
+    pub fn new(
+        storage: Arc<dyn MetaStorage>,
+        commit: impl FnOnce() -> Result<()> + Send + 'static,
+    ) -> Self {
+        Self {
+            storage,
+            commit: Box::new(commit),
+        }
+    }
+
+    pub fn storage(&self) -> &dyn MetaStorage {
+        self.storage.as_ref()
+    }
+
+    /// The same batch, written through the storage `wrap` returns for the
+    /// batch storage, for storages wrapping another one.
+    pub fn wrap(self, wrap: impl FnOnce(Arc<dyn MetaStorage>) -> Arc<dyn MetaStorage>) -> Self {
+        Self {
+            storage: wrap(self.storage),
+            commit: self.commit,
+        }
+    }
+
+    pub fn commit(self) -> Result<()> {
+        (self.commit)()
+    }
+}
+
+/// Run `f` with a storage batching the writes made through it to `storage`,
+/// dropping them if `f` fails.
+pub fn with_batch<T>(
+    storage: &dyn MetaStorage,
+    f: impl FnOnce(&dyn MetaStorage) -> Result<T>,
+) -> Result<T> {
+    let Some(batch) = storage.begin_batch()? else {
+        return f(storage);
+    };
+    let value = f(batch.storage())?;
+    batch.commit()?;
+    Ok(value)
 }
 
 type Map<K, V> = Mutex<std::collections::HashMap<K, V>>;
@@ -62,6 +228,17 @@ impl MetaStorage for InMemoryMetaStorage {
         Ok(map.get(txid).cloned())
     }
 
+    fn set_fiat_value(&self, txid: &str, value: &FiatValue) -> Result<()> {
+        let mut map = self.fiat_store.lock().unwrap();
+        map.insert(txid.to_string(), value.clone());
+        Ok(())
+    }
+
+    fn get_fiat_value(&self, txid: &str) -> Result<Option<FiatValue>> {
+        let map = self.fiat_store.lock().unwrap();
+        Ok(map.get(txid).cloned())
+    }
+
     fn set_note(&self, key: &str, value: &str) -> Result<()> {
         self.notes_store
             .lock()
@@ -110,6 +287,16 @@ impl MetaStorage for InMemoryMetaStorage {
         Ok(map.get(key).cloned().unwrap_or(false))
     }
 
+    fn set_tag_do_not_spend(&self, tag: &str, value: bool) -> Result<()> {
+        let mut map = self.do_not_spend_tags.lock().unwrap();
+        map.insert(tag.to_lowercase(), value);
+        Ok(())
+    }
+    fn get_tag_do_not_spend(&self, tag: &str) -> Result<bool> {
+        let map = self.do_not_spend_tags.lock().unwrap();
+        Ok(map.get(&tag.to_lowercase()).cloned().unwrap_or(false))
+    }
+
     fn set_config(&self, deserialized_config: &str) -> Result<()> {
         let mut map = self.config_store.lock().unwrap();
         map.insert("config".to_string(), deserialized_config.to_string());
@@ -126,6 +313,15 @@ impl MetaStorage for InMemoryMetaStorage {
         }
     }
 
+    fn set_balance_snapshot(&self, snapshot: &BalanceSnapshot) -> Result<()> {
+        *self.balance_snapshot.lock().unwrap() = Some(*snapshot);
+        Ok(())
+    }
+
+    fn get_balance_snapshot(&self) -> Result<Option<BalanceSnapshot>> {
+        Ok(*self.balance_snapshot.lock().unwrap())
+    }
+
     fn set_last_verified_address(
         &self,
         address_type: AddressType,
@@ -146,6 +342,217 @@ impl MetaStorage for InMemoryMetaStorage {
         Ok(map.get(&(address_type, keychain)).unwrap_or(&0).to_owned())
     }
 
+    fn set_verification_search(
+        &self,
+        address_type: AddressType,
+        search: Option<&str>,
+    ) -> Result<()> {
+        let mut map = self.verification_searches.lock().unwrap();
+        match search {
+            Some(search) => map.insert(address_type, search.to_string()),
+            None => map.remove(&address_type),
+        };
+        Ok(())
+    }
+
+    fn get_verification_search(&self, address_type: AddressType) -> Result<Option<String>> {
+        let map = self.verification_searches.lock().unwrap();
+        Ok(map.get(&address_type).cloned())
+    }
+
+    fn reset_verification_state(&self) -> Result<()> {
+        self.last_verified_address_store.lock().unwrap().clear();
+        self.verification_searches.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn set_spk_derivations(&self, derivations: &[(String, SpkDerivation)]) -> Result<()> {
+        let mut map = self.spk_index.lock().unwrap();
+        map.extend(derivations.iter().cloned());
+        Ok(())
+    }
+
+    fn get_spk_derivation(&self, key: &str) -> Result<Option<SpkDerivation>> {
+        let map = self.spk_index.lock().unwrap();
+        Ok(map.get(key).copied())
+    }
+
+    fn set_sync_checkpoint(
+        &self,
+        address_type: AddressType,
+        checkpoint: &SyncCheckpoint,
+    ) -> Result<()> {
+        let mut map = self.sync_checkpoints.lock().unwrap();
+        map.insert(address_type, *checkpoint);
+        Ok(())
+    }
+
+    fn get_sync_checkpoint(&self, address_type: AddressType) -> Result<Option<SyncCheckpoint>> {
+        let map = self.sync_checkpoints.lock().unwrap();
+        Ok(map.get(&address_type).copied())
+    }
+
+    fn remove_sync_checkpoint(&self, address_type: AddressType) -> Result<()> {
+        let mut map = self.sync_checkpoints.lock().unwrap();
+        map.remove(&address_type);
+        Ok(())
+    }
+
+    fn set_bip85_child(&self, path: &str, label: &str) -> Result<()> {
+        let mut map = self.bip85_children.lock().unwrap();
+        map.insert(path.to_string(), label.to_string());
+        Ok(())
+    }
+
+    fn list_bip85_children(&self) -> Result<Vec<(String, String)>> {
+        let map = self.bip85_children.lock().unwrap();
+        Ok(map
+            .iter()
+            .map(|(path, label)| (path.clone(), label.clone()))
+            .collect())
+    }
+
+    fn set_whitelist(&self, whitelist: &str) -> Result<()> {
+        *self.whitelist.lock().unwrap() = Some(whitelist.to_string());
+        Ok(())
+    }
+
+    fn get_whitelist(&self) -> Result<Option<String>> {
+        Ok(self.whitelist.lock().unwrap().clone())
+    }
+
+    fn set_sync_key(&self, key: &str) -> Result<()> {
+        *self.sync_key.lock().unwrap() = Some(key.to_string());
+        Ok(())
+    }
+
+    fn get_sync_key(&self) -> Result<Option<String>> {
+        Ok(self.sync_key.lock().unwrap().clone())
+    }
+
+    fn set_account_snapshot(&self, snapshot: &str) -> Result<()> {
+        *self.account_snapshot.lock().unwrap() = Some(snapshot.to_string());
+        Ok(())
+    }
+
+    fn get_account_snapshot(&self) -> Result<Option<String>> {
+        Ok(self.account_snapshot.lock().unwrap().clone())
+    }
+
+    fn set_persist_journal(&self, journal: Option<&str>) -> Result<()> {
+        *self.persist_journal.lock().unwrap() = journal.map(str::to_string);
+        Ok(())
+    }
+
+    fn get_persist_journal(&self) -> Result<Option<String>> {
+        Ok(self.persist_journal.lock().unwrap().clone())
+    }
+
+    fn set_payment_template(&self, name: &str, template: &str) -> Result<()> {
+        let mut map = self.payment_templates.lock().unwrap();
+        map.insert(name.to_string(), template.to_string());
+        Ok(())
+    }
+
+    fn remove_payment_template(&self, name: &str) -> Result<()> {
+        self.payment_templates.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    fn list_payment_templates(&self) -> Result<Vec<String>> {
+        let map = self.payment_templates.lock().unwrap();
+        Ok(map.values().cloned().collect())
+    }
+
+    fn set_tag_info(&self, tag: &str, info: &str) -> Result<()> {
+        let mut map = self.tag_infos.lock().unwrap();
+        map.insert(tag.to_string(), info.to_string());
+        Ok(())
+    }
+
+    fn remove_tag_info(&self, tag: &str) -> Result<()> {
+        self.tag_infos.lock().unwrap().remove(tag);
+        Ok(())
+    }
+
+    fn list_tag_infos(&self) -> Result<Vec<String>> {
+        let map = self.tag_infos.lock().unwrap();
+        Ok(map.values().cloned().collect())
+    }
+
+    fn set_applied_update(&self, hash: &str, update: &str) -> Result<()> {
+        let mut map = self.applied_updates.lock().unwrap();
+        map.insert(hash.to_string(), update.to_string());
+        Ok(())
+    }
+
+    fn get_applied_update(&self, hash: &str) -> Result<Option<String>> {
+        let map = self.applied_updates.lock().unwrap();
+        Ok(map.get(hash).cloned())
+    }
+
+    fn remove_applied_update(&self, hash: &str) -> Result<()> {
+        self.applied_updates.lock().unwrap().remove(hash);
+        Ok(())
+    }
+
+    fn list_applied_updates(&self) -> Result<Vec<String>> {
+        let map = self.applied_updates.lock().unwrap();
+        Ok(map.values().cloned().collect())
+    }
+
+    fn wipe(&self) -> Result<Vec<String>> {
+        fn clear<K, V>(name: &str, map: &Map<K, V>, wiped: &mut Vec<String>) {
+            let mut map = map.lock().unwrap();
+            if !map.is_empty() {
+                map.clear();
+                wiped.push(name.to_string());
+            }
+        }
+
+        let mut wiped = vec![];
+        clear("config", &self.config_store, &mut wiped);
+        clear("notes", &self.notes_store, &mut wiped);
+        clear("tags", &self.tag_store, &mut wiped);
+        clear("tags_list", &self.tag_list, &mut wiped);
+        clear("do_not_spend", &self.do_not_spend_store, &mut wiped);
+        clear("do_not_spend_tags", &self.do_not_spend_tags, &mut wiped);
+        clear(
+            "last_verified_address",
+            &self.last_verified_address_store,
+            &mut wiped,
+        );
+        clear(
+            "verification_search",
+            &self.verification_searches,
+            &mut wiped,
+        );
+        clear("spk_index", &self.spk_index, &mut wiped);
+        clear("sync_checkpoints", &self.sync_checkpoints, &mut wiped);
+        clear("fees", &self.fee_store, &mut wiped);
+        clear("fiat_values", &self.fiat_store, &mut wiped);
+        clear("bip85_children", &self.bip85_children, &mut wiped);
+        clear("payment_templates", &self.payment_templates, &mut wiped);
+        clear("tag_infos", &self.tag_infos, &mut wiped);
+        clear("applied_updates", &self.applied_updates, &mut wiped);
+        if self.balance_snapshot.lock().unwrap().take().is_some() {
+            wiped.push("balance_snapshot".to_string());
+        }
+        if self.whitelist.lock().unwrap().take().is_some() {
+            wiped.push("whitelist".to_string());
+        }
+        if self.sync_key.lock().unwrap().take().is_some() {
+            wiped.push("sync_key".to_string());
+        }
+        if self.account_snapshot.lock().unwrap().take().is_some() {
+            wiped.push("account_snapshot".to_string());
+        }
+        if self.persist_journal.lock().unwrap().take().is_some() {
+            wiped.push("persist_journal".to_string());
+        }
+        Ok(wiped)
+    }
+
     fn persist(&self) -> Result<bool> {
         // In-memory storage does not require persistence
         Ok(true)
@@ -0,0 +1,417 @@
+//! Sweeping funds from a single private key, e.g. a paper wallet.
+//!
+//! The key is given either as WIF or as a BIP-38 encrypted key. All the
+//! standard single key script types are checked for funds, and everything
+//! found is spent to an address of the account in one transaction.
+
+use anyhow::{Context, Result, anyhow};
+use bdk_wallet::bitcoin::hashes::Hash;
+use bdk_wallet::bitcoin::script::{Builder, PushBytesBuf};
+use bdk_wallet::bitcoin::secp256k1::{Message, Secp256k1};
+use bdk_wallet::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bdk_wallet::bitcoin::{
+    Amount, CompressedPublicKey, FeeRate, Network, NetworkKind, OutPoint, PrivateKey, Psbt,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness, absolute, ecdsa, transaction,
+};
+use bip38::Decrypt;
+#[cfg(feature = "envoy")]
+use {
+    crate::account::NgAccount,
+    crate::error::{MutexExt, RwLockExt},
+    crate::send::{DraftTransaction, FeeRateSatPerKvb},
+    crate::transaction::{Input, KeyChain, Output, TxType},
+    crate::utils,
+    bdk_electrum::electrum_client::ElectrumApi,
+    bdk_wallet::miniscript::psbt::PsbtExt,
+    bdk_wallet::{KeychainKind, WalletPersister},
+};
+
+use crate::config::AddressType;
+
+/// Encrypted keys (BIP-38) always start with this prefix.
+const BIP38_PREFIX: &str = "6P";
+
+/// An unspent output locked to the swept key.
+#[derive(Debug, Clone)]
+pub struct SweepUtxo {
+    pub outpoint: OutPoint,
+    pub tx_out: TxOut,
+    pub address_type: AddressType,
+    /// The transaction creating the output, required to spend legacy outputs.
+    pub prev_tx: Option<Transaction>,
+}
+
+/// Parse a WIF or BIP-38 encrypted private key.
+///
+/// `passphrase` is only used (and required) for BIP-38 keys. WIF keys must
+/// belong to `network`.
+pub fn parse_sweep_key(
+    wif_or_bip38: &str,
+    passphrase: Option<&str>,
+    network: Network,
+) -> Result<PrivateKey> {
+    let key = wif_or_bip38.trim();
+    if key.starts_with(BIP38_PREFIX) {
+        let passphrase =
+            passphrase.ok_or_else(|| anyhow!("A passphrase is required for BIP-38 keys"))?;
+        let (secret, compressed) = key
+            .decrypt(passphrase)
+            .map_err(|e| anyhow!("Failed to decrypt BIP-38 key: {e:?}"))?;
+        let inner = bdk_wallet::bitcoin::secp256k1::SecretKey::from_slice(&secret)
+            .with_context(|| "Invalid BIP-38 secret")?;
+        return Ok(if compressed {
+            PrivateKey::new(inner, network)
+        } else {
+            PrivateKey::new_uncompressed(inner, network)
+        });
+    }
+
+    let private_key = PrivateKey::from_wif(key).with_context(|| "Invalid WIF private key")?;
+    if private_key.network != NetworkKind::from(network) {
+        return Err(anyhow!("Private key network mismatch"));
+    }
+    Ok(private_key)
+}
+
+/// The scripts a single key may have received funds on.
+///
+/// Uncompressed keys can only be used with P2PKH, compressed keys are also
+/// checked for P2WPKH and P2SH-P2WPKH.
+pub fn sweep_scripts(private_key: &PrivateKey) -> Vec<(AddressType, ScriptBuf)> {
+    let secp = Secp256k1::new();
+    let public_key = private_key.public_key(&secp);
+    let mut scripts = vec![(
+        AddressType::P2pkh,
+        ScriptBuf::new_p2pkh(&public_key.pubkey_hash()),
+    )];
+    if let Ok(compressed) = CompressedPublicKey::try_from(public_key) {
+        let p2wpkh = ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash());
+        scripts.push((
+            AddressType::P2ShWpkh,
+            ScriptBuf::new_p2sh(&p2wpkh.script_hash()),
+        ));
+        scripts.push((AddressType::P2wpkh, p2wpkh));
+    }
+    scripts
+}
+
+/// Build a signed and finalized PSBT spending all `utxos` to `destination`.
+pub fn build_sweep_psbt(
+    private_key: &PrivateKey,
+    utxos: &[SweepUtxo],
+    destination: ScriptBuf,
+    fee_rate: FeeRate,
+) -> Result<Psbt> {
+    if utxos.is_empty() {
+        return Err(anyhow!("No funds found for this key"));
+    }
+    let total: Amount = utxos.iter().map(|utxo| utxo.tx_out.value).sum();
+
+    // sign once without a fee to learn the final weight, then again with the fee
+    let unfunded = sign_sweep_psbt(private_key, utxos, destination.clone(), total)?;
+    let weight = unfunded
+        .clone()
+        .extract_tx_unchecked_fee_rate()
+        .weight()
+        // DER signatures vary in length by a byte
+        + Weight::from_non_witness_data_size(utxos.len() as u64);
+    let fee = fee_rate
+        .fee_wu(weight)
+        .ok_or_else(|| anyhow!("Fee overflow"))?;
+
+    let value = total
+        .checked_sub(fee)
+        .filter(|value| *value >= destination.minimal_non_dust())
+        .ok_or_else(|| anyhow!("Funds of {total} are too small to sweep"))?;
+
+    sign_sweep_psbt(private_key, utxos, destination, value)
+}
+
+fn sign_sweep_psbt(
+    private_key: &PrivateKey,
+    utxos: &[SweepUtxo],
+    destination: ScriptBuf,
+    value: Amount,
+) -> Result<Psbt> {
+    let secp = Secp256k1::new();
+    let public_key = private_key.public_key(&secp);
+    // the witness program, also the redeem script of P2SH-P2WPKH outputs
+    let p2wpkh = CompressedPublicKey::try_from(public_key)
+        .ok()
+        .map(|compressed| ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()));
+
+    let unsigned_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: utxos
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![TxOut {
+            value,
+            script_pubkey: destination,
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx.clone())
+        .map_err(|e| anyhow!("Failed to create PSBT: {e}"))?;
+    let mut cache = SighashCache::new(&unsigned_tx);
+
+    for (index, utxo) in utxos.iter().enumerate() {
+        let input = &mut psbt.inputs[index];
+        input.non_witness_utxo = utxo.prev_tx.clone();
+
+        let sighash = match utxo.address_type {
+            AddressType::P2pkh => cache
+                .legacy_signature_hash(
+                    index,
+                    &utxo.tx_out.script_pubkey,
+                    EcdsaSighashType::All.to_u32(),
+                )
+                .map_err(|e| anyhow!("Failed to compute sighash: {e}"))?
+                .to_byte_array(),
+            AddressType::P2wpkh | AddressType::P2ShWpkh => {
+                input.witness_utxo = Some(utxo.tx_out.clone());
+                let p2wpkh = p2wpkh
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Segwit outputs require a compressed key"))?;
+                cache
+                    .p2wpkh_signature_hash(index, p2wpkh, utxo.tx_out.value, EcdsaSighashType::All)
+                    .map_err(|e| anyhow!("Failed to compute sighash: {e}"))?
+                    .to_byte_array()
+            }
+            address_type => {
+                return Err(anyhow!("Cannot sweep {address_type:?} outputs"));
+            }
+        };
+
+        let signature = ecdsa::Signature::sighash_all(
+            secp.sign_ecdsa(&Message::from_digest(sighash), &private_key.inner),
+        );
+
+        match utxo.address_type {
+            AddressType::P2pkh => {
+                input.final_script_sig = Some(
+                    Builder::new()
+                        .push_slice(signature.serialize())
+                        .push_key(&public_key)
+                        .into_script(),
+                );
+            }
+            _ => {
+                input.final_script_witness = Some(Witness::p2wpkh(&signature, &public_key.inner));
+                if utxo.address_type == AddressType::P2ShWpkh
+                    && let Some(redeem_script) = p2wpkh.clone()
+                {
+                    let redeem_script = PushBytesBuf::try_from(redeem_script.into_bytes())
+                        .map_err(|_| anyhow!("Invalid redeem script"))?;
+                    input.final_script_sig =
+                        Some(Builder::new().push_slice(redeem_script).into_script());
+                }
+            }
+        }
+    }
+
+    Ok(psbt)
+}
+
+#[cfg(feature = "envoy")]
+impl<P: WalletPersister> NgAccount<P> {
+    /// Sweep all funds of a WIF or BIP-38 key into the next unused address
+    /// of the coordinator wallet.
+    ///
+    /// The returned draft is already signed and can be passed straight to
+    /// [`NgAccount::broadcast_psbt`].
+    pub fn compose_sweep_from_key(
+        &self,
+        wif_or_bip38: &str,
+        passphrase: Option<&str>,
+        fee_rate: FeeRateSatPerKvb,
+        electrum_server: &str,
+        socks_proxy: Option<&str>,
+        validate_domain: Option<bool>,
+    ) -> Result<DraftTransaction> {
+        let (account_id, network) = {
+            let config = self.config.read_or_err()?;
+            (config.id.clone(), config.network)
+        };
+        let private_key = parse_sweep_key(wif_or_bip38, passphrase, network)?;
+
+        let client = utils::build_electrum_client(electrum_server, socks_proxy, validate_domain)
+            .map_err(|e| anyhow!("Failed to connect to Electrum server: {e}"))?;
+        let mut utxos = vec![];
+        for (address_type, script) in sweep_scripts(&private_key) {
+            let unspent = client
+                .inner
+                .script_list_unspent(&script)
+                .map_err(|e| anyhow!("Failed to list unspent outputs: {e}"))?;
+            for unspent in unspent {
+                let prev_tx = client
+                    .fetch_tx(unspent.tx_hash)
+                    .map_err(|e| anyhow!("Failed to fetch transaction: {e}"))?;
+                let tx_out = prev_tx
+                    .output
+                    .get(unspent.tx_pos)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown output {}", unspent.tx_hash))?;
+                utxos.push(SweepUtxo {
+                    outpoint: OutPoint::new(unspent.tx_hash, unspent.tx_pos as u32),
+                    tx_out,
+                    address_type,
+                    prev_tx: Some(prev_tx.as_ref().clone()),
+                });
+            }
+        }
+
+        let coordinator_wallet = self.get_coordinator_wallet();
+        let address = coordinator_wallet
+            .bdk_wallet
+            .lock_or_err()?
+            .next_unused_address(KeychainKind::External)
+            .address;
+        coordinator_wallet.persist()?;
+
+        let psbt = build_sweep_psbt(
+            &private_key,
+            &utxos,
+            address.script_pubkey(),
+            fee_rate.to_bdk(),
+        )?;
+        let tx_id = psbt.unsigned_tx.compute_txid().to_string();
+        let amount = psbt.unsigned_tx.output[0].value.to_sat();
+
+        let inputs: Vec<Input> = utxos
+            .iter()
+            .map(|utxo| Input {
+                tx_id: utxo.outpoint.txid.to_string(),
+                vout: utxo.outpoint.vout,
+                amount: utxo.tx_out.value.to_sat(),
+                tag: None,
+                address: Some(utils::get_address_as_string(
+                    &utxo.tx_out.script_pubkey,
+                    network,
+                )),
+                keychain: None,
+                is_mine: false,
+            })
+            .collect();
+        let outputs = vec![Output {
+            tx_id,
+            vout: 0,
+            amount,
+            tag: None,
+            date: None,
+            is_confirmed: false,
+            address: address.to_string(),
+            do_not_spend: false,
+            keychain: Some(KeyChain::External),
+        }];
+        let input_tags = vec!["untagged".to_string(); inputs.len()];
+
+        let mut transaction = Self::transform_psbt_to_bitcointx(
+            psbt.clone(),
+            address.to_string(),
+            outputs,
+            inputs,
+            None,
+            account_id,
+        );
+        // funds are coming in, not going out to `address`
+        transaction.amount = amount as i64;
+        transaction.tx_type = TxType::Incoming;
+
+        Ok(DraftTransaction {
+            psbt: psbt.serialize(),
+            is_finalized: psbt.extract(&Secp256k1::verification_only()).is_ok(),
+            input_tags,
+            change_out_put_tag: None,
+            transaction,
+            warnings: vec![],
+            privacy: None,
+            foreign_contribution: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::Txid;
+
+    const WIF: &str = "L44B5gGEpqEDRS9vVPz7QT35jcBG2r3CZwSwQ4fCewXAhAhqGVpP";
+
+    fn utxo(private_key: &PrivateKey, address_type: AddressType, vout: u32) -> SweepUtxo {
+        let script_pubkey = sweep_scripts(private_key)
+            .into_iter()
+            .find(|(script_type, _)| *script_type == address_type)
+            .unwrap()
+            .1;
+        SweepUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            tx_out: TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey,
+            },
+            address_type,
+            prev_tx: None,
+        }
+    }
+
+    #[test]
+    fn parse_wif_and_bip38() {
+        let wif = parse_sweep_key(WIF, None, Network::Bitcoin).unwrap();
+        let bip38 = parse_sweep_key(
+            "6PYNKZ1EAgYgmQfmNVamxyXVWHzK5s6DGhwP4J5o44cvXdoY7sRzhtpUeo",
+            Some("TestingOneTwoThree"),
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert_eq!(wif, bip38);
+        assert!(parse_sweep_key(WIF, None, Network::Testnet).is_err());
+    }
+
+    #[test]
+    fn uncompressed_keys_only_sweep_p2pkh() {
+        let key = parse_sweep_key(
+            "5KN7MzqK5wt2TP1fQCYyHBtDrXdJuXbUzm4A9rKAteGu3Qi5CVR",
+            None,
+            Network::Bitcoin,
+        )
+        .unwrap();
+        let scripts = sweep_scripts(&key);
+        assert_eq!(scripts.len(), 1);
+        assert_eq!(scripts[0].0, AddressType::P2pkh);
+    }
+
+    #[test]
+    fn sweep_psbt_pays_requested_fee_rate() {
+        let key = parse_sweep_key(WIF, None, Network::Bitcoin).unwrap();
+        let utxos = vec![
+            utxo(&key, AddressType::P2wpkh, 0),
+            utxo(&key, AddressType::P2ShWpkh, 1),
+        ];
+        let destination = utxos[0].tx_out.script_pubkey.clone();
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(10);
+        let psbt = build_sweep_psbt(&key, &utxos, destination, fee_rate).unwrap();
+
+        let fee = psbt.fee().unwrap();
+        let tx = psbt.extract_tx().unwrap();
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + fee, Amount::from_sat(100_000));
+        assert!(fee >= fee_rate.fee_wu(tx.weight()).unwrap());
+        assert!(fee <= fee_rate.fee_vb(tx.vsize() as u64 + 4).unwrap());
+    }
+
+    #[test]
+    fn dust_is_not_swept() {
+        let key = parse_sweep_key(WIF, None, Network::Bitcoin).unwrap();
+        let mut dust = utxo(&key, AddressType::P2wpkh, 0);
+        dust.tx_out.value = Amount::from_sat(500);
+        let destination = dust.tx_out.script_pubkey.clone();
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(10);
+        assert!(build_sweep_psbt(&key, &[dust], destination, fee_rate).is_err());
+    }
+}
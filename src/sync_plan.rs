@@ -0,0 +1,119 @@
+//! Per wallet sync checkpoints.
+//!
+//! Every update applied to a wallet records a [`SyncCheckpoint`] in the
+//! [`MetaStorage`](crate::store::MetaStorage) of the account: the block it
+//! was synced to, the revealed indexes and when. [`NgAccount::sync_plan`]
+//! compares them with a time to live, so hosts sync only the wallets that
+//! need it instead of relying on the single `date_synced` of the config.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use bdk_wallet::bitcoin::BlockHash;
+use bdk_wallet::{KeychainKind, WalletPersister};
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::config::AddressType;
+use crate::error::{AccountError, MutexExt, RwLockExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCheckpoint {
+    /// Tip of the wallet's chain after the sync.
+    pub height: u32,
+    pub block_hash: BlockHash,
+    /// Last revealed receive and change indexes.
+    pub external_index: Option<u32>,
+    pub internal_index: Option<u32>,
+    /// Unix time of the sync, in seconds.
+    pub synced_at: u64,
+}
+
+impl SyncCheckpoint {
+    /// Seconds since the sync, as of `now`.
+    pub fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.synced_at)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletSyncState {
+    pub address_type: AddressType,
+    /// `None` if the wallet was never synced.
+    pub checkpoint: Option<SyncCheckpoint>,
+    pub stale: bool,
+}
+
+/// Sync state of the wallets of an account, in
+/// [`NgAccount::sync_order`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub wallets: Vec<WalletSyncState>,
+}
+
+impl SyncPlan {
+    /// The wallets to sync, most important first.
+    pub fn stale(&self) -> Vec<AddressType> {
+        self.wallets
+            .iter()
+            .filter(|wallet| wallet.stale)
+            .map(|wallet| wallet.address_type)
+            .collect()
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.wallets.iter().all(|wallet| !wallet.stale)
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    pub fn sync_checkpoint(&self, address_type: AddressType) -> Result<Option<SyncCheckpoint>> {
+        self.meta_storage.get_sync_checkpoint(address_type)
+    }
+
+    /// Which wallets were synced less than `ttl` ago. Wallets never synced
+    /// are stale.
+    pub fn sync_plan(&self, ttl: Duration) -> Result<SyncPlan> {
+        let now = now();
+        let mut wallets = vec![];
+        for address_type in self.sync_order()? {
+            let checkpoint = self.sync_checkpoint(address_type)?;
+            let stale = checkpoint.is_none_or(|checkpoint| checkpoint.age(now) >= ttl.as_secs());
+            wallets.push(WalletSyncState {
+                address_type,
+                checkpoint,
+                stale,
+            });
+        }
+        Ok(SyncPlan { wallets })
+    }
+
+    /// Record where the wallet of `address_type` stands after an update.
+    pub(crate) fn record_sync_checkpoint(&self, address_type: AddressType) -> Result<()> {
+        let checkpoint = {
+            let wallets = self.wallets.read_or_err()?;
+            let wallet = wallets
+                .iter()
+                .find(|wallet| wallet.address_type == address_type)
+                .ok_or(AccountError::WalletNotFound(address_type))?;
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let tip = bdk_wallet.latest_checkpoint();
+            SyncCheckpoint {
+                height: tip.height(),
+                block_hash: tip.hash(),
+                external_index: bdk_wallet.derivation_index(KeychainKind::External),
+                internal_index: bdk_wallet.derivation_index(KeychainKind::Internal),
+                synced_at: now(),
+            }
+        };
+        self.meta_storage
+            .set_sync_checkpoint(address_type, &checkpoint)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
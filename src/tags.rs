@@ -0,0 +1,163 @@
+//! Display metadata of tags.
+//!
+//! Outputs carry a bare tag name. A [`TagInfo`] kept in the
+//! [`crate::store::MetaStorage`] adds how the app shows the tag: its color,
+//! icon, position in the tag list and whether it is hidden. Tag names are
+//! case insensitive, and so is the lookup of their info.
+//!
+//! [`NgAccount::balances_by_tag`] sums the unspent outputs of each tag, and
+//! [`crate::send::TransactionParams::spend_from_tag`] keeps a spend to the
+//! outputs of one.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use bdk_wallet::WalletPersister;
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::transaction::Output;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagInfo {
+    pub name: String,
+    /// Color as the app encodes it, like `#f7931a`.
+    pub color: Option<String>,
+    /// Name of the icon in the app's icon set.
+    pub icon: Option<String>,
+    /// Position in the tag list, lowest first.
+    pub sort_order: u32,
+    /// Hidden from the tag list, like the tags of archived coins.
+    pub hidden: bool,
+}
+
+impl TagInfo {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            color: None,
+            icon: None,
+            sort_order: 0,
+            hidden: false,
+        }
+    }
+}
+
+/// Unspent outputs with a tag, amounts in sats.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagBalance {
+    /// `None` for the untagged outputs.
+    pub tag: Option<String>,
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+    pub utxo_count: usize,
+}
+
+// Key of the info of `tag` in the store.
+pub(crate) fn tag_key(tag: &str) -> String {
+    tag.to_lowercase()
+}
+
+// The tag of `output`, `None` if it has none or an empty one.
+fn output_tag(output: &Output) -> Option<&str> {
+    output.tag.as_deref().filter(|tag| !tag.is_empty())
+}
+
+/// `utxos` with the outputs not tagged `tag` marked do not spend, so coin
+/// selection leaves them out. `None` keeps them all spendable.
+pub(crate) fn restrict_to_tag(mut utxos: Vec<Output>, tag: Option<&str>) -> Vec<Output> {
+    if let Some(tag) = tag {
+        for utxo in utxos.iter_mut() {
+            if output_tag(utxo).is_none_or(|other| tag_key(other) != tag_key(tag)) {
+                utxo.do_not_spend = true;
+            }
+        }
+    }
+    utxos
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Store `info`, replacing the info of the tag with the same name.
+    pub fn save_tag_info(&self, info: &TagInfo) -> Result<()> {
+        if info.name.is_empty() {
+            bail!("Tag name is empty");
+        }
+        self.meta_storage
+            .set_tag_info(&tag_key(&info.name), &serde_json::to_string(info)?)?;
+        self.meta_storage.persist()?;
+        self.emit_metadata_changed(&info.name);
+        Ok(())
+    }
+
+    pub fn remove_tag_info(&self, tag: &str) -> Result<()> {
+        self.meta_storage.remove_tag_info(&tag_key(tag))?;
+        self.meta_storage.persist()?;
+        self.emit_metadata_changed(tag);
+        Ok(())
+    }
+
+    /// Balance of each tag, by tag name with the untagged outputs first.
+    /// Outputs locked with do not spend count too.
+    pub fn balances_by_tag(&self) -> Result<Vec<TagBalance>> {
+        let mut balances: BTreeMap<Option<String>, TagBalance> = BTreeMap::new();
+        for utxo in self.utxos()? {
+            let tag = output_tag(&utxo);
+            let balance = balances
+                .entry(tag.map(tag_key))
+                .or_insert_with(|| TagBalance {
+                    tag: tag.map(str::to_string),
+                    confirmed: 0,
+                    unconfirmed: 0,
+                    utxo_count: 0,
+                });
+            match utxo.is_confirmed {
+                true => balance.confirmed += utxo.amount,
+                false => balance.unconfirmed += utxo.amount,
+            }
+            balance.utxo_count += 1;
+        }
+        Ok(balances.into_values().collect())
+    }
+
+    /// Stored tag infos, by sort order then name.
+    pub fn tag_infos(&self) -> Result<Vec<TagInfo>> {
+        let mut infos = self
+            .meta_storage
+            .list_tag_infos()?
+            .iter()
+            .map(|info| serde_json::from_str(info))
+            .collect::<Result<Vec<TagInfo>, _>>()?;
+        infos.sort_by_key(|info| (info.sort_order, info.name.to_lowercase()));
+        Ok(infos)
+    }
+
+    pub fn tag_info(&self, tag: &str) -> Result<Option<TagInfo>> {
+        Ok(self
+            .tag_infos()?
+            .into_iter()
+            .find(|info| tag_key(&info.name) == tag_key(tag)))
+    }
+
+    /// Give the listed tags the sort order of their position in `tags`,
+    /// creating the infos they don't have yet.
+    pub fn reorder_tags(&self, tags: &[String]) -> Result<()> {
+        let infos = self.tag_infos()?;
+        crate::store::with_batch(self.meta_storage.as_ref(), |storage| {
+            for (sort_order, tag) in tags.iter().enumerate() {
+                let mut info = infos
+                    .iter()
+                    .find(|info| tag_key(&info.name) == tag_key(tag))
+                    .cloned()
+                    .unwrap_or_else(|| TagInfo::new(tag));
+                info.sort_order = sort_order as u32;
+                storage.set_tag_info(&tag_key(&info.name), &serde_json::to_string(&info)?)?;
+            }
+            Ok(())
+        })?;
+        self.meta_storage.persist()?;
+        for tag in tags {
+            self.emit_metadata_changed(tag);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Payment templates.
+//!
+//! A [`PaymentTemplate`] is a named payment (recipient, amount, tag and
+//! note) kept in the [`crate::store::MetaStorage`] and turned into
+//! [`TransactionParams`] or a [`DraftTransaction`] each time it is paid,
+//! for recurring payments.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use bdk_wallet::WalletPersister;
+use bdk_wallet::bitcoin::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::account::NgAccount;
+use crate::error::RwLockExt;
+use crate::fee_rate::FeeRateSatPerKvb;
+use crate::send::{
+    DraftTransaction, FeeSharing, OutputOrdering, SpendPath, TransactionComposeError,
+    TransactionParams,
+};
+
+/// How often a template is meant to be paid. Only a hint for the app, the
+/// wallet never pays on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Schedule {
+    Weekly,
+    Monthly,
+    Yearly,
+    /// Every given number of days.
+    Days(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentTemplate {
+    pub name: String,
+    pub address: String,
+    /// In sats.
+    pub amount: u64,
+    pub tag: Option<String>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+}
+
+impl PaymentTemplate {
+    /// Parameters paying the template at `fee_rate`, with coin selection
+    /// choosing the inputs.
+    pub fn params(&self, fee_rate: FeeRateSatPerKvb) -> TransactionParams {
+        TransactionParams {
+            address: self.address.clone(),
+            amount: self.amount,
+            fee_rate,
+            selected_outputs: vec![],
+            note: self.note.clone(),
+            tag: self.tag.clone(),
+            do_not_spend_change: false,
+            ordering: OutputOrdering::default(),
+            change_address: None,
+            spend_path: SpendPath::default(),
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::default(),
+            spend_from_tag: None,
+            embed_memo: false,
+        }
+    }
+}
+
+impl<P: WalletPersister> NgAccount<P> {
+    /// Store `template`, replacing the one with the same name.
+    pub fn save_payment_template(&self, template: &PaymentTemplate) -> Result<()> {
+        if template.name.is_empty() {
+            bail!("Payment template name is empty");
+        }
+        if template.amount == 0 {
+            bail!("Payment template amount is zero");
+        }
+        let network = self.config.read_or_err()?.network;
+        Address::from_str(&template.address)
+            .with_context(|| "Invalid address")?
+            .require_network(network)
+            .with_context(|| "Address is for another network")?;
+
+        self.meta_storage
+            .set_payment_template(&template.name, &serde_json::to_string(template)?)?;
+        self.meta_storage.persist()?;
+        Ok(())
+    }
+
+    pub fn remove_payment_template(&self, name: &str) -> Result<()> {
+        self.meta_storage.remove_payment_template(name)?;
+        self.meta_storage.persist()?;
+        Ok(())
+    }
+
+    /// Stored templates, by name.
+    pub fn payment_templates(&self) -> Result<Vec<PaymentTemplate>> {
+        let mut templates = self
+            .meta_storage
+            .list_payment_templates()?
+            .iter()
+            .map(|template| serde_json::from_str(template))
+            .collect::<Result<Vec<PaymentTemplate>, _>>()?;
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(templates)
+    }
+
+    pub fn payment_template(&self, name: &str) -> Result<Option<PaymentTemplate>> {
+        Ok(self
+            .payment_templates()?
+            .into_iter()
+            .find(|template| template.name == name))
+    }
+
+    /// Compose the payment of the template `name` at `fee_rate`.
+    pub fn compose_from_template(
+        &self,
+        name: &str,
+        fee_rate: FeeRateSatPerKvb,
+    ) -> Result<DraftTransaction, TransactionComposeError> {
+        let template = self
+            .payment_template(name)
+            .map_err(|e| TransactionComposeError::Error(e.to_string()))?
+            .ok_or_else(|| TransactionComposeError::Error(format!("No payment template {name}")))?;
+        self.compose_psbt(template.params(fee_rate))
+    }
+}
@@ -0,0 +1,287 @@
+//! Funded accounts for tests, without a network.
+//!
+//! [`AccountFixture`] fabricates the [`Update`]s a scan of a funded account
+//! would return: deterministic transactions paying its receive addresses,
+//! confirmed in made up blocks or seen in the mempool. Tests apply them to
+//! any account, or [`AccountFixture::build`] an in-memory hot account from
+//! a fixed mnemonic with them applied, instead of scanning live Electrum
+//! servers.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use bdk_wallet::bitcoin::hashes::{Hash, sha256};
+use bdk_wallet::bitcoin::{
+    Amount, BlockHash, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    WPubkeyHash, absolute, transaction,
+};
+use bdk_wallet::chain::{BlockId, ConfirmationBlockTime, TxUpdate};
+use bdk_wallet::keys::bip39::Mnemonic;
+use bdk_wallet::rusqlite::Connection;
+use bdk_wallet::{KeychainKind, Update, WalletPersister};
+
+use crate::account::{Descriptor, NgAccount};
+use crate::bip39::get_descriptors;
+use crate::config::{AddressType, NgAccountBuilder};
+use crate::error::{MutexExt, RwLockExt};
+
+/// Mnemonic of the accounts built by [`AccountFixture::build`].
+pub const FIXTURE_MNEMONIC: &str =
+    "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+/// Fee paid by every fixture transaction, in sats.
+pub const FIXTURE_FEE: u64 = 1_000;
+// Unix time of the first fixture block, and of the first unconfirmed
+// transaction being seen.
+const FIXTURE_TIME: u64 = 1_700_000_000;
+const BLOCK_INTERVAL: u64 = 600;
+
+#[derive(Debug, Clone)]
+pub struct AccountFixture {
+    pub network: Network,
+    /// Script types of the wallets of [`Self::build`].
+    pub address_types: Vec<AddressType>,
+    /// Transactions per wallet confirmed in a block each.
+    pub confirmed_txs: usize,
+    /// Transactions per wallet in the mempool.
+    pub unconfirmed_txs: usize,
+    /// Outputs paying the wallet in each transaction.
+    pub utxos_per_tx: usize,
+    /// Value of each output paying the wallet, in sats.
+    pub amount: u64,
+}
+
+impl Default for AccountFixture {
+    fn default() -> Self {
+        Self {
+            network: Network::Signet,
+            address_types: vec![AddressType::P2wpkh, AddressType::P2tr],
+            confirmed_txs: 2,
+            unconfirmed_txs: 0,
+            utxos_per_tx: 1,
+            amount: 100_000,
+        }
+    }
+}
+
+impl AccountFixture {
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn address_types(mut self, address_types: Vec<AddressType>) -> Self {
+        self.address_types = address_types;
+        self
+    }
+
+    pub fn confirmed_txs(mut self, confirmed_txs: usize) -> Self {
+        self.confirmed_txs = confirmed_txs;
+        self
+    }
+
+    pub fn unconfirmed_txs(mut self, unconfirmed_txs: usize) -> Self {
+        self.unconfirmed_txs = unconfirmed_txs;
+        self
+    }
+
+    pub fn utxos_per_tx(mut self, utxos_per_tx: usize) -> Self {
+        self.utxos_per_tx = utxos_per_tx;
+        self
+    }
+
+    pub fn amount(mut self, amount: u64) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    /// Balance of each wallet once the updates are applied, in sats.
+    pub fn wallet_balance(&self) -> u64 {
+        ((self.confirmed_txs + self.unconfirmed_txs) * self.utxos_per_tx) as u64 * self.amount
+    }
+
+    /// A hot account of [`FIXTURE_MNEMONIC`] with the wallets of
+    /// [`Self::address_types`], in memory, with the updates applied.
+    pub fn build(&self) -> Result<NgAccount<Connection>> {
+        let seed = Mnemonic::from_str(FIXTURE_MNEMONIC)?.to_seed("");
+        let mut descriptors = vec![];
+        for address_type in &self.address_types {
+            let Some(descriptor) = get_descriptors(&seed, self.network, 0)?
+                .into_iter()
+                .find(|descriptor| descriptor.export_addr_hint == *address_type)
+            else {
+                bail!("No descriptor template for {address_type:?}");
+            };
+            descriptors.push(Descriptor {
+                internal: descriptor.descriptor_xprv().into_string(),
+                external: Some(descriptor.change_descriptor_xprv().into_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory()?)),
+            });
+        }
+        let Some(preferred_address_type) = self.address_types.first() else {
+            bail!("The fixture needs at least one address type");
+        };
+
+        let account = NgAccountBuilder::default()
+            .name("Fixture".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(*preferred_address_type)
+            .index(0)
+            .descriptors(descriptors)
+            .date_synced(None)
+            .account_path(None)
+            .network(self.network)
+            .id("fixture".to_string())
+            .build_in_memory()?;
+        for update in self.updates(&account)? {
+            account.apply(update)?;
+        }
+        account.persist()?;
+        Ok(account)
+    }
+
+    /// The updates funding every wallet of `account`, as a full scan would
+    /// return them. The same fixture always fabricates the same
+    /// transactions and blocks for the same account.
+    ///
+    /// Blocks are added on top of the chain of each wallet, so updates can
+    /// be applied on top of earlier ones.
+    pub fn updates<P: WalletPersister>(
+        &self,
+        account: &NgAccount<P>,
+    ) -> Result<Vec<(AddressType, Update)>> {
+        let mut updates = vec![];
+        for (w, wallet) in account.all_wallets()?.iter().enumerate() {
+            let bdk_wallet = wallet.bdk_wallet.lock_or_err()?;
+            let mut chain = bdk_wallet.latest_checkpoint();
+            let first_height = chain.height() + 1;
+
+            let mut tx_update = TxUpdate::default();
+            let mut addresses = 0;
+            for j in 0..self.confirmed_txs + self.unconfirmed_txs {
+                let funding = OutPoint::new(fixture_txid(&format!("funding {w} {j}")), 0);
+                let output = (0..self.utxos_per_tx)
+                    .map(|_| {
+                        let address = bdk_wallet.peek_address(KeychainKind::External, addresses);
+                        addresses += 1;
+                        TxOut {
+                            value: Amount::from_sat(self.amount),
+                            script_pubkey: address.script_pubkey(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let tx = Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: absolute::LockTime::ZERO,
+                    input: vec![TxIn {
+                        previous_output: funding,
+                        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        ..Default::default()
+                    }],
+                    output,
+                };
+                let txid = tx.compute_txid();
+                // the spent output is known so the fee of the transaction is
+                let funding_value = self.amount * self.utxos_per_tx as u64 + FIXTURE_FEE;
+                tx_update.txouts.insert(
+                    funding,
+                    TxOut {
+                        value: Amount::from_sat(funding_value),
+                        script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(
+                            format!("funder {w} {j}").as_bytes(),
+                        )),
+                    },
+                );
+                tx_update.txs.push(Arc::new(tx));
+
+                match j < self.confirmed_txs {
+                    true => {
+                        let block_id = fixture_block(first_height + j as u32);
+                        chain = chain.insert(block_id);
+                        tx_update.anchors.insert((
+                            ConfirmationBlockTime {
+                                block_id,
+                                confirmation_time: FIXTURE_TIME + j as u64 * BLOCK_INTERVAL,
+                            },
+                            txid,
+                        ));
+                    }
+                    false => {
+                        tx_update.seen_ats.insert((txid, FIXTURE_TIME + j as u64));
+                    }
+                }
+            }
+            // one more block on top, so the last transaction has a
+            // confirmation like the others
+            chain = chain.insert(fixture_block(first_height + self.confirmed_txs as u32));
+
+            let mut last_active_indices = BTreeMap::new();
+            if addresses > 0 {
+                last_active_indices.insert(KeychainKind::External, addresses - 1);
+            }
+            updates.push((
+                wallet.address_type,
+                Update {
+                    last_active_indices,
+                    tx_update,
+                    chain: Some(chain),
+                },
+            ));
+        }
+        Ok(updates)
+    }
+}
+
+fn fixture_txid(label: &str) -> Txid {
+    Txid::from_byte_array(sha256::Hash::hash(label.as_bytes()).to_byte_array())
+}
+
+fn fixture_block(height: u32) -> BlockId {
+    let hash = sha256::Hash::hash(format!("block {height}").as_bytes());
+    BlockId {
+        height,
+        hash: BlockHash::from_byte_array(hash.to_byte_array()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_deterministic() {
+        let fixture = AccountFixture::default()
+            .confirmed_txs(2)
+            .unconfirmed_txs(1)
+            .utxos_per_tx(2)
+            .amount(20_000);
+        let account = fixture.build().unwrap();
+        let other = fixture.build().unwrap();
+
+        assert_eq!(
+            account.balance().unwrap().total().to_sat(),
+            2 * fixture.wallet_balance()
+        );
+        let transactions = account.transactions().unwrap();
+        assert_eq!(transactions.len(), 6);
+        assert_eq!(transactions.iter().filter(|tx| !tx.is_confirmed).count(), 2);
+        assert!(transactions.iter().all(|tx| tx.fee == FIXTURE_FEE));
+        assert_eq!(account.utxos().unwrap().len(), 12);
+
+        let tx_ids = |account: &NgAccount<Connection>| {
+            let mut tx_ids: Vec<String> = account
+                .transactions()
+                .unwrap()
+                .into_iter()
+                .map(|tx| tx.tx_id)
+                .collect();
+            tx_ids.sort();
+            tx_ids
+        };
+        assert_eq!(tx_ids(&account), tx_ids(&other));
+    }
+}
@@ -0,0 +1,206 @@
+//! Embedded Tor client.
+//!
+//! Bootstraps an [`arti_client::TorClient`] inside the process and exposes it
+//! as a local SOCKS5 proxy, so the existing `socks_proxy` argument of
+//! [`NgWallet::scan`](crate::ngwallet::NgWallet::scan),
+//! [`NgWallet::sync`](crate::ngwallet::NgWallet::sync) and
+//! [`NgAccount::broadcast_psbt`](crate::account::NgAccount::broadcast_psbt)
+//! can be pointed at it without an external Tor daemon.
+//!
+//! Every connection accepted by the proxy is placed in its own isolation
+//! group, so each request is sent over a fresh circuit.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use arti_client::config::TorClientConfigBuilder;
+use arti_client::{StreamPrefs, TorClient};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+// Wait after a failed accept, which keeps failing while the process is out
+// of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+static EMBEDDED_TOR: Mutex<Option<Arc<EmbeddedTor>>> = Mutex::new(None);
+
+pub struct EmbeddedTor {
+    // Keeps the Tor client and the proxy task alive.
+    _runtime: Runtime,
+    socks_addr: SocketAddr,
+}
+
+impl std::fmt::Debug for EmbeddedTor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddedTor")
+            .field("socks_addr", &self.socks_addr)
+            .finish()
+    }
+}
+
+impl EmbeddedTor {
+    /// Bootstrap a Tor client storing its state and directory cache under
+    /// `data_dir`, and start the local SOCKS5 proxy.
+    ///
+    /// Blocks until the client has a usable view of the network.
+    pub fn bootstrap(data_dir: &str) -> Result<Self> {
+        let data_dir = Path::new(data_dir);
+        let config = TorClientConfigBuilder::from_directories(
+            data_dir.join("state"),
+            data_dir.join("cache"),
+        )
+        .build()
+        .with_context(|| "Invalid Tor client configuration")?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("ngwallet-tor")
+            .build()
+            .with_context(|| "Failed to start Tor runtime")?;
+
+        let (client, listener) = runtime.block_on(async {
+            let client = TorClient::create_bootstrapped(config)
+                .await
+                .with_context(|| "Failed to bootstrap Tor")?;
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .with_context(|| "Failed to bind Tor SOCKS listener")?;
+            Ok::<_, anyhow::Error>((client, listener))
+        })?;
+
+        let socks_addr = listener.local_addr()?;
+        runtime.spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::warn!("Tor SOCKS listener failed to accept: {e}");
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let client = client.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = proxy_connection(client, stream).await {
+                        log::warn!("Tor proxy connection failed: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            _runtime: runtime,
+            socks_addr,
+        })
+    }
+
+    /// Return the process wide Tor client, bootstrapping it on first use.
+    pub fn global(data_dir: &str) -> Result<Arc<Self>> {
+        let mut global = EMBEDDED_TOR
+            .lock()
+            .map_err(|_| anyhow!("Tor client lock poisoned"))?;
+        if let Some(tor) = global.as_ref() {
+            return Ok(tor.clone());
+        }
+        let tor = Arc::new(Self::bootstrap(data_dir)?);
+        *global = Some(tor.clone());
+        Ok(tor)
+    }
+
+    /// The `host:port` of the local SOCKS5 proxy, in the form accepted by
+    /// the `socks_proxy` arguments.
+    pub fn socks_proxy(&self) -> String {
+        self.socks_addr.to_string()
+    }
+}
+
+async fn proxy_connection<R: arti_client::Runtime>(
+    client: TorClient<R>,
+    mut inbound: TcpStream,
+) -> Result<()> {
+    // Greeting: version, method count, methods.
+    let mut header = [0u8; 2];
+    inbound.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Err(anyhow!("Unsupported SOCKS version {}", header[0]));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    inbound.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        inbound
+            .write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(anyhow!("SOCKS client requires authentication"));
+    }
+    inbound
+        .write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])
+        .await?;
+
+    // Request: version, command, reserved, address type, address, port.
+    let mut request = [0u8; 4];
+    inbound.read_exact(&mut request).await?;
+    if request[1] != CMD_CONNECT {
+        send_reply(&mut inbound, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(anyhow!("Unsupported SOCKS command {}", request[1]));
+    }
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            inbound.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            inbound.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = inbound.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            inbound.read_exact(&mut domain).await?;
+            String::from_utf8(domain)?
+        }
+        atyp => {
+            send_reply(&mut inbound, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(anyhow!("Unsupported SOCKS address type {atyp}"));
+        }
+    };
+    let port = inbound.read_u16().await?;
+
+    let mut prefs = StreamPrefs::new();
+    prefs.new_isolation_group();
+    let mut outbound = match client.connect_with_prefs((host, port), &prefs).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            send_reply(&mut inbound, REPLY_GENERAL_FAILURE).await?;
+            return Err(e.into());
+        }
+    };
+    send_reply(&mut inbound, REPLY_SUCCEEDED).await?;
+
+    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+async fn send_reply(stream: &mut TcpStream, reply: u8) -> Result<()> {
+    // The bound address is meaningless for a Tor stream, always report 0.0.0.0:0.
+    stream
+        .write_all(&[SOCKS_VERSION, reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
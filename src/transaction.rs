@@ -1,5 +1,7 @@
 use crate::fee_rate::FeeRateSatPerKvb;
-use bdk_wallet::bitcoin::{OutPoint, Txid};
+use crate::package::FeePackage;
+use bdk_wallet::KeychainKind;
+use bdk_wallet::bitcoin::{OutPoint, Transaction, Txid};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -31,6 +33,14 @@ pub struct Input {
     pub vout: u32,
     pub amount: u64,
     pub tag: Option<String>,
+    /// Address of the spent output, `None` if the output is unknown.
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub keychain: Option<KeyChain>,
+    /// Whether the spent output is the account's.
+    #[serde(default)]
+    pub is_mine: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -39,6 +49,15 @@ pub enum KeyChain {
     Internal,
 }
 
+impl From<KeychainKind> for KeyChain {
+    fn from(keychain: KeychainKind) -> Self {
+        match keychain {
+            KeychainKind::External => KeyChain::External,
+            KeychainKind::Internal => KeyChain::Internal,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Output {
     pub tx_id: String,
@@ -68,6 +87,102 @@ impl PartialEq for Output {
     }
 }
 
+/// Tag given to the mixed outputs of a likely coinjoin received by the
+/// account, unless they are already tagged.
+pub const COINJOIN_TAG: &str = "Coinjoin";
+
+/// Fewest inputs, and outputs of a single value, of a likely coinjoin.
+/// Whirlpool mixes have exactly five of each.
+const COINJOIN_MIN_PARTICIPANTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxKind {
+    #[default]
+    Standard,
+    /// Many inputs and many outputs of the same value, as in Whirlpool or
+    /// WabiSabi coinjoins.
+    LikelyCoinjoin,
+}
+
+impl TxKind {
+    pub fn of(tx: &Transaction) -> Self {
+        let most_common_value = tx
+            .output
+            .iter()
+            .map(|output| same_value_outputs(tx, output.value.to_sat()))
+            .max()
+            .unwrap_or(0);
+        match tx.input.len() >= COINJOIN_MIN_PARTICIPANTS
+            && most_common_value >= COINJOIN_MIN_PARTICIPANTS
+        {
+            true => TxKind::LikelyCoinjoin,
+            false => TxKind::Standard,
+        }
+    }
+}
+
+/// What a transaction does for the account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxType {
+    #[default]
+    Incoming,
+    /// Pays outside of the account.
+    Outgoing,
+    /// Moves funds between the wallets of the account, or to one of its
+    /// receive addresses.
+    SelfTransfer,
+    /// Merges outputs of the account into a single one.
+    Consolidation,
+    /// Replaces another transaction, sending its funds back to change.
+    Cancellation,
+}
+
+impl TxType {
+    /// Type of `tx`, from whether the account `spends` in it and the
+    /// keychain of each of its outputs, `None` for outputs of others.
+    /// `between_wallets` is true if a wallet of the account receives in it
+    /// without spending, and `replaces` if it conflicts with another
+    /// transaction.
+    pub(crate) fn classify(
+        tx: &Transaction,
+        spends: bool,
+        keychains: &[Option<KeyChain>],
+        between_wallets: bool,
+        replaces: bool,
+    ) -> Self {
+        if !spends {
+            return TxType::Incoming;
+        }
+        if keychains.iter().any(Option::is_none) {
+            return TxType::Outgoing;
+        }
+        if replaces && matches!(keychains, [Some(KeyChain::Internal)]) {
+            return TxType::Cancellation;
+        }
+        match !between_wallets && tx.output.len() == 1 && tx.input.len() > 1 {
+            true => TxType::Consolidation,
+            false => TxType::SelfTransfer,
+        }
+    }
+}
+
+/// True if output `vout` of the likely coinjoin `tx` is a mixed output,
+/// sharing its value with other outputs, rather than change.
+pub fn is_mixed_output(tx: &Transaction, vout: usize) -> bool {
+    TxKind::of(tx) == TxKind::LikelyCoinjoin
+        && tx
+            .output
+            .get(vout)
+            .is_some_and(|output| same_value_outputs(tx, output.value.to_sat()) > 1)
+}
+
+fn same_value_outputs(tx: &Transaction, value: u64) -> usize {
+    tx.output
+        .iter()
+        .filter(|output| output.value.to_sat() == value)
+        .count()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BitcoinTransaction {
     pub tx_id: String,
@@ -84,6 +199,21 @@ pub struct BitcoinTransaction {
     pub date: Option<u64>,
     pub vsize: usize,
     pub account_id: String,
+    /// Fiat value at confirmation time, recorded by the app.
+    pub fiat_value: Option<f64>,
+    pub fiat_currency: Option<String>,
+    /// Transactions spending some of the same outputs, such as a replacement
+    /// of this transaction or the one it replaced.
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+    /// Fee package of an unconfirmed transaction, see
+    /// [`crate::package::FeePackage`].
+    #[serde(default)]
+    pub package: Option<FeePackage>,
+    #[serde(default)]
+    pub tx_kind: TxKind,
+    #[serde(default)]
+    pub tx_type: TxType,
 }
 
 impl BitcoinTransaction {
@@ -108,8 +238,150 @@ impl BitcoinTransaction {
     }
 }
 
+/// History entries for the unit tests of the modules reading the history.
+/// Tests override the fields they care about with struct update syntax.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// A confirmed incoming transaction without inputs or outputs.
+    pub(crate) fn tx(tx_id: &str) -> BitcoinTransaction {
+        BitcoinTransaction {
+            tx_id: tx_id.to_string(),
+            block_height: 1,
+            confirmations: 1,
+            is_confirmed: true,
+            fee: 0,
+            fee_rate: FeeRateSatPerKvb(0),
+            amount: 0,
+            inputs: vec![],
+            address: String::new(),
+            outputs: vec![],
+            note: None,
+            date: None,
+            vsize: 0,
+            account_id: String::new(),
+            fiat_value: None,
+            fiat_currency: None,
+            conflicts_with: vec![],
+            package: None,
+            tx_kind: TxKind::Standard,
+            tx_type: TxType::Incoming,
+        }
+    }
+
+    /// A confirmed output of 10_000 sats to `address`.
+    pub(crate) fn output(tx_id: &str, vout: u32, address: &str) -> Output {
+        Output {
+            tx_id: tx_id.to_string(),
+            vout,
+            amount: 10_000,
+            tag: None,
+            date: None,
+            is_confirmed: true,
+            address: address.to_string(),
+            do_not_spend: false,
+            keychain: None,
+        }
+    }
+
+    /// An input of 10_000 sats spending an output of someone else.
+    pub(crate) fn input(tx_id: &str, vout: u32) -> Input {
+        Input {
+            tx_id: tx_id.to_string(),
+            vout,
+            amount: 10_000,
+            tag: None,
+            address: None,
+            keychain: None,
+            is_mine: false,
+        }
+    }
+}
+
 // #[derive(Debug)]
 // pub struct NgTransaction {
 //     pub placeholder: Option<TransactionPlaceholder>,
 //     pub output: Option<BitcoinTransaction>,
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::bitcoin::absolute::LockTime;
+    use bdk_wallet::bitcoin::transaction::Version;
+    use bdk_wallet::bitcoin::{Amount, ScriptBuf, TxIn, TxOut};
+
+    fn tx(inputs: usize, values: &[u64]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(); inputs],
+            output: values
+                .iter()
+                .map(|value| TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn coinjoins_are_recognized() {
+        let whirlpool = tx(5, &[100_000; 5]);
+        assert_eq!(TxKind::of(&whirlpool), TxKind::LikelyCoinjoin);
+        assert!(is_mixed_output(&whirlpool, 4));
+
+        let wabisabi = tx(
+            40,
+            &[
+                5_000, 5_000, 5_000, 5_000, 5_000, 10_000, 10_000, 13_122, 20_000,
+            ],
+        );
+        assert_eq!(TxKind::of(&wabisabi), TxKind::LikelyCoinjoin);
+        assert!(is_mixed_output(&wabisabi, 5));
+        assert!(!is_mixed_output(&wabisabi, 7));
+
+        // a batched payment of equal amounts from a single coin
+        let payout = tx(1, &[100_000; 10]);
+        assert_eq!(TxKind::of(&payout), TxKind::Standard);
+        assert!(!is_mixed_output(&payout, 0));
+    }
+
+    #[test]
+    fn transactions_are_classified() {
+        use KeyChain::{External, Internal};
+
+        let payment = tx(1, &[10_000, 5_000]);
+        let keychains = [None, Some(Internal)];
+        assert_eq!(
+            TxType::classify(&payment, false, &keychains, false, false),
+            TxType::Incoming
+        );
+        assert_eq!(
+            TxType::classify(&payment, true, &keychains, false, false),
+            TxType::Outgoing
+        );
+
+        let to_self = [Some(External), Some(Internal)];
+        assert_eq!(
+            TxType::classify(&payment, true, &to_self, false, false),
+            TxType::SelfTransfer
+        );
+
+        let merge = tx(3, &[15_000]);
+        assert_eq!(
+            TxType::classify(&merge, true, &[Some(Internal)], false, false),
+            TxType::Consolidation
+        );
+        assert_eq!(
+            TxType::classify(&merge, true, &[Some(External)], true, false),
+            TxType::SelfTransfer
+        );
+        assert_eq!(
+            TxType::classify(&merge, true, &[Some(Internal)], false, true),
+            TxType::Cancellation
+        );
+    }
+}
@@ -1,13 +1,27 @@
+#[cfg(feature = "esplora")]
+use bdk_esplora::esplora_client::{BlockingClient, Builder};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
 use bdk_wallet::bitcoin::{Address, Network, ScriptBuf};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
 #[cfg(feature = "envoy")]
 use {
     bdk_electrum::BdkElectrumClient,
-    bdk_electrum::electrum_client::{Client, Config, Socks5Config},
+    bdk_electrum::electrum_client::{Client, Config, ElectrumApi, Param, Socks5Config},
+    serde::Deserialize,
+    std::time::{Duration, Instant},
 };
 
 use crate::config::AddressType;
 use serde::Serialize;
 
+/// Seconds a probed server has to answer each request.
+#[cfg(feature = "envoy")]
+const PROBE_TIMEOUT: u8 = 10;
+/// Blocks a server can be behind the highest tip of the probed servers and
+/// still be healthy, as they don't see new blocks at the same time.
+#[cfg(feature = "envoy")]
+pub const MAX_TIP_LAG: u32 = 1;
+
 #[derive(Serialize)]
 struct Bip329Item {
     #[serde(rename = "type")]
@@ -51,7 +65,149 @@ pub(crate) fn build_electrum_client(
     Ok(BdkElectrumClient::new(client))
 }
 
+/// Build a blocking Esplora (e.g. mempool.space) REST client.
+///
+/// `socks_proxy` takes the same `host:port` form as the Electrum builder,
+/// a full proxy URL such as `socks5://127.0.0.1:9050` is passed through as is.
+#[cfg(feature = "esplora")]
+pub(crate) fn build_esplora_client(esplora_url: &str, socks_proxy: Option<&str>) -> BlockingClient {
+    let mut builder = Builder::new(esplora_url.trim_end_matches('/')).timeout(30);
+
+    if let Some(socks_proxy) = socks_proxy {
+        let proxy = if socks_proxy.contains("://") {
+            socks_proxy.to_string()
+        } else {
+            format!("socks5://{socks_proxy}")
+        };
+        builder = builder.proxy(&proxy);
+    }
+
+    builder.build_blocking()
+}
+
 //
+/// Health of an Electrum server, see [`probe_electrum_servers`].
+#[cfg(feature = "envoy")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerHealth {
+    pub server: String,
+    pub reachable: bool,
+    pub tip_height: Option<u32>,
+    /// Round trip of the tip request.
+    pub latency: Option<Duration>,
+    /// Whether the server returns verbose transactions, which electrs
+    /// doesn't.
+    pub supports_verbose: bool,
+    /// How far the tip of the server is behind the highest tip of the
+    /// probed servers.
+    pub blocks_behind: Option<u32>,
+}
+
+#[cfg(feature = "envoy")]
+impl ServerHealth {
+    /// Reachable and not lagging behind the chain tip.
+    pub fn is_healthy(&self) -> bool {
+        self.reachable
+            && self
+                .blocks_behind
+                .is_some_and(|behind| behind <= MAX_TIP_LAG)
+    }
+}
+
+/// Probe `electrum_servers` in parallel, through `socks_proxy` if any.
+/// The healthy servers come first, fastest first, then the lagging ones
+/// and last the unreachable ones.
+#[cfg(feature = "envoy")]
+pub fn probe_electrum_servers(
+    electrum_servers: Vec<&str>,
+    socks_proxy: Option<&str>,
+) -> Vec<ServerHealth> {
+    let mut servers: Vec<ServerHealth> = std::thread::scope(|scope| {
+        let probes: Vec<_> = electrum_servers
+            .iter()
+            .map(|server| scope.spawn(move || probe_electrum_server(server, socks_proxy)))
+            .collect();
+        probes
+            .into_iter()
+            .zip(&electrum_servers)
+            .map(|(probe, server)| probe.join().unwrap_or_else(|_| unreachable_server(server)))
+            .collect()
+    });
+    rank_servers(&mut servers);
+    servers
+}
+
+#[cfg(feature = "envoy")]
+fn probe_electrum_server(server: &str, socks_proxy: Option<&str>) -> ServerHealth {
+    let config = Config::builder()
+        .timeout(Some(PROBE_TIMEOUT))
+        .retry(0)
+        .socks5(socks_proxy.map(Socks5Config::new))
+        .build();
+    let client = match Client::from_config(server, config) {
+        Ok(client) => client,
+        Err(e) => {
+            log::info!("Electrum server {server} is unreachable: {e}");
+            return unreachable_server(server);
+        }
+    };
+    let start = Instant::now();
+    let tip = match client.block_headers_subscribe() {
+        Ok(tip) => tip,
+        Err(e) => {
+            log::info!("Electrum server {server} did not send its tip: {e}");
+            return unreachable_server(server);
+        }
+    };
+    let latency = start.elapsed();
+
+    // the transaction doesn't exist, servers that can't return verbose
+    // transactions say so before looking it up
+    let verbose = client.raw_call(
+        "blockchain.transaction.get",
+        [Param::String("00".repeat(32)), Param::Bool(true)],
+    );
+    let supports_verbose = match verbose {
+        Ok(_) => true,
+        Err(e) => !e.to_string().to_lowercase().contains("verbose"),
+    };
+
+    ServerHealth {
+        server: server.to_string(),
+        reachable: true,
+        tip_height: Some(tip.height as u32),
+        latency: Some(latency),
+        supports_verbose,
+        blocks_behind: None,
+    }
+}
+
+#[cfg(feature = "envoy")]
+fn unreachable_server(server: &str) -> ServerHealth {
+    ServerHealth {
+        server: server.to_string(),
+        reachable: false,
+        tip_height: None,
+        latency: None,
+        supports_verbose: false,
+        blocks_behind: None,
+    }
+}
+
+// Fill in how far each server is behind and sort them, see
+// [`probe_electrum_servers`].
+#[cfg(feature = "envoy")]
+fn rank_servers(servers: &mut [ServerHealth]) {
+    let best_tip = servers.iter().filter_map(|server| server.tip_height).max();
+    for server in servers.iter_mut() {
+        server.blocks_behind = server
+            .tip_height
+            .zip(best_tip)
+            .map(|(tip, best)| best - tip);
+    }
+    servers.sort_by_key(|server| (!server.is_healthy(), !server.reachable, server.latency));
+}
+
 pub fn get_address_type(descriptor: &str) -> AddressType {
     if descriptor.starts_with("pkh(") {
         AddressType::P2pkh
@@ -68,6 +224,74 @@ pub fn get_address_type(descriptor: &str) -> AddressType {
     }
 }
 
+/// Receive and change descriptors of a `<0;1>` multipath (BIP-389)
+/// descriptor, `None` for single path descriptors. Private keys are kept.
+pub fn split_multipath_descriptor(descriptor: &str) -> anyhow::Result<Option<(String, String)>> {
+    if !descriptor.contains('<') {
+        return Ok(None);
+    }
+    let secp = Secp256k1::new();
+    let descriptor = descriptor.trim();
+    let (parsed, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)?;
+    if !parsed.is_multipath() {
+        return Ok(None);
+    }
+    if parsed.into_single_descriptors()?.len() != 2 {
+        anyhow::bail!("Multipath descriptors must have a receive and a change path");
+    }
+
+    // picks the path in the string, keeping private keys as they are
+    let body = descriptor.split('#').next().unwrap_or_default();
+    let single = |path: usize| -> anyhow::Result<String> {
+        let mut single = String::new();
+        let mut rest = body;
+        while let Some(start) = rest.find('<') {
+            let end = start
+                + rest[start..]
+                    .find('>')
+                    .ok_or_else(|| anyhow::anyhow!("Unclosed multipath step"))?;
+            single.push_str(&rest[..start]);
+            single.push_str(
+                rest[start + 1..end]
+                    .split(';')
+                    .nth(path)
+                    .unwrap_or_default(),
+            );
+            rest = &rest[end + 1..];
+        }
+        single.push_str(rest);
+        let (descriptor, keymap) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &single)?;
+        Ok(descriptor.to_string_with_secret(&keymap))
+    };
+    Ok(Some((single(0)?, single(1)?)))
+}
+
+/// `<0;1>` multipath form of the `external` and `internal` public
+/// descriptors, `None` if they differ by more than their keychain step.
+pub fn join_multipath_descriptor(external: &str, internal: &str) -> Option<String> {
+    let strip = |descriptor: &str| descriptor.split('#').next().unwrap_or_default().to_string();
+    let (external, internal) = (strip(external), strip(internal));
+    if !external.contains("/0/*") || external.replace("/0/*", "/1/*") != internal {
+        return None;
+    }
+    let multipath: Descriptor<DescriptorPublicKey> =
+        external.replace("/0/*", "/<0;1>/*").parse().ok()?;
+    Some(multipath.to_string())
+}
+
+/// Canonical form of `descriptor`: `'` hardened markers, lowercase
+/// fingerprints and a correct checksum, which is added if missing and
+/// replaced if wrong. Private keys are kept.
+///
+/// Descriptors should be compared, and used as keys, in this form.
+pub fn normalize_descriptor(descriptor: &str) -> anyhow::Result<String> {
+    let secp = Secp256k1::new();
+    let body = descriptor.trim().split('#').next().unwrap_or_default();
+    let (descriptor, keymap) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, body)?;
+    Ok(descriptor.to_string_with_secret(&keymap))
+}
+
 pub fn get_address_as_string(script: &ScriptBuf, network: Network) -> String {
     match Address::from_script(script, network) {
         Ok(address) => address.to_string(),
@@ -114,3 +338,57 @@ pub fn build_key_json(
 
     serde_json::to_string(&item).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANONICAL: &str = "wpkh([b032ef5f/84'/1'/0']tpubDCk2z9cyYbR3FGusMkYB5aSLTHuLNkkZuz9whR7x4JDh34rjD64bMhSXBns5qKf5QArdU5DK1Q6zLLg34SRqSV2EXutfgySyq3gZGsmYDT8/0/*)#csckd05z";
+
+    #[test]
+    fn descriptors_are_normalized() {
+        assert_eq!(normalize_descriptor(CANONICAL).unwrap(), CANONICAL);
+        let body = CANONICAL.split('#').next().unwrap();
+        // missing and wrong checksums
+        assert_eq!(normalize_descriptor(body).unwrap(), CANONICAL);
+        assert_eq!(
+            normalize_descriptor(&format!("{body}#aaaaaaaa")).unwrap(),
+            CANONICAL
+        );
+        // `h` markers and an uppercase fingerprint
+        let spelled = body.replace("'", "h").replace("b032ef5f", "B032EF5F");
+        assert_eq!(normalize_descriptor(&spelled).unwrap(), CANONICAL);
+
+        assert!(normalize_descriptor("wpkh(not a key)").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn servers_are_ranked_by_health() {
+        let server = |name: &str, tip_height: u32, latency: u64| ServerHealth {
+            server: name.to_string(),
+            reachable: true,
+            tip_height: Some(tip_height),
+            latency: Some(Duration::from_millis(latency)),
+            supports_verbose: true,
+            blocks_behind: None,
+        };
+        let mut servers = vec![
+            unreachable_server("down"),
+            server("lagging", 890_000, 10),
+            server("slow", 890_005, 300),
+            server("fast", 890_004, 40),
+        ];
+        rank_servers(&mut servers);
+
+        let names: Vec<&str> = servers.iter().map(|s| s.server.as_str()).collect();
+        assert_eq!(names, ["fast", "slow", "lagging", "down"]);
+        assert_eq!(servers[0].blocks_behind, Some(1));
+        assert_eq!(servers[2].blocks_behind, Some(5));
+        assert!(!servers[2].is_healthy());
+        assert_eq!(servers[3].blocks_behind, None);
+
+        let probed = probe_electrum_servers(vec!["tcp://127.0.0.1:1"], None);
+        assert!(!probed[0].reachable);
+    }
+}
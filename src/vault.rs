@@ -0,0 +1,408 @@
+//! Timelocked vault accounts.
+//!
+//! A vault has two keys: the primary key spends at any time, the recovery key
+//! only once the output is [`VaultDetails::delay`] blocks deep (BIP-68
+//! relative timelock). The descriptors are
+//! `wsh(or_d(pk(primary),and_v(v:pk(recovery),older(delay))))`, the path used
+//! by a transaction is chosen with [`crate::send::SpendPath`], and coin
+//! selection budgets the inputs with the weight of that path, see
+//! [`SpendPathSelection`].
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use bdk_wallet::bitcoin::relative;
+use bdk_wallet::bitcoin::secp256k1::{Secp256k1, Signing};
+use bdk_wallet::bitcoin::{Amount, FeeRate, Script, ScriptBuf, Weight};
+use bdk_wallet::coin_selection::{
+    CoinSelectionAlgorithm, CoinSelectionResult, DefaultCoinSelectionAlgorithm, InsufficientFunds,
+};
+use bdk_wallet::descriptor::Descriptor as BdkDescriptor;
+use bdk_wallet::keys::KeyMap;
+use bdk_wallet::miniscript::descriptor::DescriptorPublicKey;
+use bdk_wallet::miniscript::plan::Assets;
+use bdk_wallet::{KeychainKind, Utxo, WeightedUtxo};
+use rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::bip39::{Descriptors, MasterKey};
+use crate::config::{AddressType, MultiSigSigner, NetworkKind, derive_keymap};
+use crate::send::SpendPath;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VaultDetails {
+    /// Blocks an output must be buried under before the recovery key can
+    /// spend it.
+    pub delay: u16,
+    pub network_kind: NetworkKind,
+    primary: MultiSigSigner,
+    recovery: MultiSigSigner,
+}
+
+impl VaultDetails {
+    pub fn new(
+        delay: u16,
+        mut network_kind: Option<NetworkKind>,
+        primary: MultiSigSigner,
+        recovery: MultiSigSigner,
+    ) -> Result<Self> {
+        if delay == 0 {
+            bail!("Vault recovery delay should be at least one block");
+        }
+
+        if primary.get_pubkey()? == recovery.get_pubkey()? {
+            bail!("Vault primary and recovery keys should differ");
+        }
+
+        for key in [&primary, &recovery] {
+            let key_network: NetworkKind = key.get_pubkey()?.network.into();
+            let n = network_kind.get_or_insert(key_network);
+            if *n != key_network {
+                bail!("Vault has pubkeys from mismatched network types");
+            }
+        }
+
+        Ok(Self {
+            delay,
+            network_kind: network_kind.ok_or(anyhow::anyhow!(
+                "Network kind was neither specified nor infered from xpubs"
+            ))?,
+            primary,
+            recovery,
+        })
+    }
+
+    pub fn get_primary(&self) -> &MultiSigSigner {
+        &self.primary
+    }
+
+    pub fn get_recovery(&self) -> &MultiSigSigner {
+        &self.recovery
+    }
+
+    pub fn default_name(&self) -> String {
+        format!("Vault-{}-blocks-{:?}", self.delay, self.network_kind)
+    }
+
+    fn key(&self, spend_path: SpendPath, keychain: KeychainKind) -> Result<DescriptorPublicKey> {
+        let signer = match spend_path {
+            SpendPath::Primary => &self.primary,
+            SpendPath::Recovery => &self.recovery,
+        };
+        signer
+            .to_descriptor_key(keychain)
+            .with_context(|| format!("Invalid {spend_path:?} key"))
+    }
+
+    fn descriptor(&self, keychain: KeychainKind) -> Result<BdkDescriptor<DescriptorPublicKey>> {
+        let primary = self.key(SpendPath::Primary, keychain)?;
+        let recovery = self.key(SpendPath::Recovery, keychain)?;
+        let descriptor = BdkDescriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(or_d(pk({primary}),and_v(v:pk({recovery}),older({}))))",
+            self.delay
+        ))?;
+        descriptor.sanity_check()?;
+        Ok(descriptor)
+    }
+
+    pub fn to_descriptor<C: Signing>(
+        &self,
+        keychain: KeychainKind,
+        secp: &Secp256k1<C>,
+        master_key: Option<&MasterKey>,
+    ) -> Result<(BdkDescriptor<DescriptorPublicKey>, KeyMap)> {
+        let descriptor = self.descriptor(keychain)?;
+        let keymap = derive_keymap(&descriptor, self.network_kind, secp, master_key)?;
+        Ok((descriptor, keymap))
+    }
+
+    pub fn get_descriptors<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        master_key: Option<&MasterKey>,
+    ) -> Result<Vec<Descriptors>> {
+        let (external_desc, external_keymap) =
+            self.to_descriptor(KeychainKind::External, secp, master_key)?;
+        let (internal_desc, internal_keymap) =
+            self.to_descriptor(KeychainKind::Internal, secp, master_key)?;
+        let descriptor_type = external_desc.desc_type();
+
+        Ok(vec![Descriptors {
+            bip: String::from("vault"),
+            export_addr_hint: AddressType::P2wsh,
+            descriptor: (external_desc, external_keymap),
+            change_descriptor: (internal_desc, internal_keymap),
+            descriptor_type,
+        }])
+    }
+
+    /// Weight of the witness spending an output of `keychain` through
+    /// `spend_path`.
+    ///
+    /// BDK budgets every input with the heavier recovery satisfaction,
+    /// [`SpendPathSelection`] replaces it with this weight.
+    pub fn max_weight_to_satisfy(
+        &self,
+        keychain: KeychainKind,
+        spend_path: SpendPath,
+    ) -> Result<Weight> {
+        // the weight is the same at every index
+        let descriptor = self.descriptor(keychain)?.at_derivation_index(0)?;
+        let key = self
+            .key(spend_path, keychain)?
+            .at_derivation_index(0)?
+            .into_descriptor_public_key();
+        let mut assets = Assets::new().add(key);
+        if spend_path == SpendPath::Recovery {
+            assets = assets.older(relative::LockTime::from_height(self.delay));
+        }
+        let plan = descriptor
+            .plan(&assets)
+            .map_err(|_| anyhow::anyhow!("The {spend_path:?} path can't be satisfied"))?;
+        Ok(Weight::from_wu(plan.satisfaction_weight() as u64))
+    }
+
+    /// Script of the vault address at `index` of `keychain`.
+    pub fn script_pubkey(&self, keychain: KeychainKind, index: u32) -> Result<ScriptBuf> {
+        Ok(self
+            .descriptor(keychain)?
+            .at_derivation_index(index)?
+            .script_pubkey())
+    }
+}
+
+/// Default coin selection, budgeting the outputs of a vault with the
+/// satisfaction weight of the spending path used instead of the heaviest
+/// one, which would overpay the fee of primary spends.
+#[derive(Debug, Default)]
+pub(crate) struct SpendPathSelection {
+    // satisfaction weight of the outputs of each keychain, empty for the
+    // other accounts
+    weights: Vec<(KeychainKind, Weight)>,
+    inner: DefaultCoinSelectionAlgorithm,
+}
+
+impl SpendPathSelection {
+    /// Selection for spends of `vault` through `spend_path`. Without a
+    /// vault, or if the path can't be planned, the BDK weights are kept.
+    pub(crate) fn new(vault: Option<&VaultDetails>, spend_path: SpendPath) -> Self {
+        let weights = match vault {
+            Some(vault) => [KeychainKind::External, KeychainKind::Internal]
+                .into_iter()
+                .filter_map(|keychain| {
+                    let weight = vault.max_weight_to_satisfy(keychain, spend_path).ok()?;
+                    Some((keychain, weight))
+                })
+                .collect(),
+            None => vec![],
+        };
+        Self {
+            weights,
+            inner: DefaultCoinSelectionAlgorithm::default(),
+        }
+    }
+
+    fn reweigh(&self, utxos: Vec<WeightedUtxo>) -> Vec<WeightedUtxo> {
+        utxos
+            .into_iter()
+            .map(|mut utxo| {
+                // foreign inputs come with the weight of their own wallet
+                if let Utxo::Local(output) = &utxo.utxo
+                    && let Some((_, weight)) = self
+                        .weights
+                        .iter()
+                        .find(|(keychain, _)| *keychain == output.keychain)
+                {
+                    utxo.satisfaction_weight = *weight;
+                }
+                utxo
+            })
+            .collect()
+    }
+}
+
+impl CoinSelectionAlgorithm for SpendPathSelection {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        rand: &mut R,
+    ) -> Result<CoinSelectionResult, InsufficientFunds> {
+        self.inner.coin_select(
+            self.reweigh(required_utxos),
+            self.reweigh(optional_utxos),
+            fee_rate,
+            target_amount,
+            drain_script,
+            rand,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::Wallet;
+    use bdk_wallet::bitcoin::hashes::Hash;
+    use bdk_wallet::bitcoin::{BlockHash, Network, Transaction, TxOut};
+    use bdk_wallet::chain::{BlockId, ConfirmationBlockTime};
+    use bdk_wallet::test_utils::{insert_anchor, insert_checkpoint, insert_tx, new_tx};
+
+    fn signer(fingerprint: &str, pubkey: &str) -> MultiSigSigner {
+        MultiSigSigner::new_from_strings("m/48'/0'/0'/2'", fingerprint, pubkey).unwrap()
+    }
+
+    fn vault() -> VaultDetails {
+        VaultDetails::new(
+            144,
+            None,
+            signer(
+                "71c8bd85",
+                "xpub6ESpvmZa75rCQWKik2KoCZrjTi6xhSubZKJ25rbtgZRk2g9tZTJqubhaGD3dJeqruw9KMCaanoEfJ1PVtBXiwTuuqLVwk9ucqkRv1sKWiEC",
+            ),
+            signer(
+                "ab88de89",
+                "xpub6EPJuK8Ejz82nKc7PsRgcYqdcQH9G1ZikCTasr9i79CbXxMMiPfxEyA14S6HPTHufmcQR7x8t5L3BP9tRfm9EBRBPic2xV892j9z4ePESae",
+            ),
+        )
+        .unwrap()
+    }
+
+    fn wallet(vault: &VaultDetails) -> Wallet {
+        let secp = Secp256k1::new();
+        let (external, _) = vault
+            .to_descriptor(KeychainKind::External, &secp, None)
+            .unwrap();
+        let (internal, _) = vault
+            .to_descriptor(KeychainKind::Internal, &secp, None)
+            .unwrap();
+        Wallet::create(external.to_string(), internal.to_string())
+            .network(Network::Bitcoin)
+            .create_wallet_no_persist()
+            .unwrap()
+    }
+
+    #[test]
+    fn vault_descriptors() {
+        let vault = vault();
+        assert_eq!(vault.network_kind, NetworkKind::Main);
+        assert_eq!(vault.default_name(), "Vault-144-blocks-Main");
+
+        let descriptors = vault.get_descriptors(&Secp256k1::new(), None).unwrap();
+        let external = descriptors[0].descriptor.0.to_string();
+        assert!(external.starts_with("wsh(or_d(pk([71c8bd85/48"));
+        assert!(external.contains("/0/*),and_v(v:pk([ab88de89/48"));
+        assert!(external.contains("/0/*),older(144))))"));
+        assert!(
+            descriptors[0]
+                .change_descriptor
+                .0
+                .to_string()
+                .contains("/1/*")
+        );
+
+        let same_keys = VaultDetails::new(
+            144,
+            None,
+            vault.get_primary().clone(),
+            vault.get_primary().clone(),
+        );
+        assert!(same_keys.is_err());
+        let no_delay = VaultDetails::new(
+            0,
+            None,
+            vault.get_primary().clone(),
+            vault.get_recovery().clone(),
+        );
+        assert!(no_delay.is_err());
+    }
+
+    #[test]
+    fn spend_path_weights() {
+        let vault = vault();
+        let primary = vault
+            .max_weight_to_satisfy(KeychainKind::External, SpendPath::Primary)
+            .unwrap();
+        let recovery = vault
+            .max_weight_to_satisfy(KeychainKind::External, SpendPath::Recovery)
+            .unwrap();
+        // the recovery path also dissatisfies the primary key
+        assert!(primary < recovery);
+
+        let (descriptor, _) = vault
+            .to_descriptor(KeychainKind::External, &Secp256k1::new(), None)
+            .unwrap();
+        assert_eq!(descriptor.max_weight_to_satisfy().unwrap(), recovery);
+    }
+
+    #[test]
+    fn spend_path_selects_policy_branch() {
+        let wallet = wallet(&vault());
+
+        let primary = SpendPath::Primary
+            .policy_path(&wallet, KeychainKind::External)
+            .unwrap();
+        let recovery = SpendPath::Recovery
+            .policy_path(&wallet, KeychainKind::Internal)
+            .unwrap();
+        assert_eq!(primary.values().next().unwrap(), &vec![0]);
+        assert_eq!(recovery.values().next().unwrap(), &vec![1]);
+    }
+
+    #[test]
+    fn coin_selection_budgets_the_spend_path_weight() {
+        let vault = vault();
+        let mut wallet = wallet(&vault);
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let tx = Transaction {
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+            ..new_tx(0)
+        };
+        let block_id = BlockId {
+            height: 1_000,
+            hash: BlockHash::all_zeros(),
+        };
+        insert_checkpoint(&mut wallet, block_id);
+        insert_tx(&mut wallet, tx.clone());
+        insert_anchor(
+            &mut wallet,
+            tx.compute_txid(),
+            ConfirmationBlockTime {
+                block_id,
+                confirmation_time: 100,
+            },
+        );
+
+        let mut fee = |spend_path: SpendPath, selection: SpendPathSelection| {
+            let paths: Vec<_> = [KeychainKind::External, KeychainKind::Internal]
+                .into_iter()
+                .map(|keychain| (spend_path.policy_path(&wallet, keychain).unwrap(), keychain))
+                .collect();
+            let mut builder = wallet.build_tx().coin_selection(selection);
+            for (path, keychain) in paths {
+                builder.policy_path(path, keychain);
+            }
+            builder
+                .add_recipient(address.script_pubkey(), Amount::from_sat(50_000))
+                .fee_rate(FeeRate::from_sat_per_vb(10).unwrap());
+            builder.finish().unwrap().fee().unwrap()
+        };
+        let bdk = fee(SpendPath::Primary, SpendPathSelection::default());
+        let primary = fee(
+            SpendPath::Primary,
+            SpendPathSelection::new(Some(&vault), SpendPath::Primary),
+        );
+        let recovery = fee(
+            SpendPath::Recovery,
+            SpendPathSelection::new(Some(&vault), SpendPath::Recovery),
+        );
+        assert!(primary < bdk);
+        assert_eq!(recovery, bdk);
+    }
+}
@@ -8,10 +8,6 @@ const FUNDED_EXTERNAL_DESCRIPTOR: &str = "wpkh([b032ef5f/84'/1'/0']tpubDCk2z9cyY
 const FUNDED_INTERNAL_DESCRIPTOR_TR: &str = "tr([b032ef5f/86'/1'/0']tpubDCYjw9j1fst87iV3Mep6c3jUWe6JwCqLArZv7uZMEfa4VyXjU5uHZrWAMdokfsvm2HisA8Ym5Zbp4o5iCS3UARP6SxDdR2SvmSsSBAzvZMZ/0/*)#5ujy5gry";
 const FUNDED_EXTERNAL_DESCRIPTOR_TR: &str = "tr([b032ef5f/86'/1'/0']tpubDCYjw9j1fst87iV3Mep6c3jUWe6JwCqLArZv7uZMEfa4VyXjU5uHZrWAMdokfsvm2HisA8Ym5Zbp4o5iCS3UARP6SxDdR2SvmSsSBAzvZMZ/1/*)#9gh9fanu";
 
-#[cfg(feature = "envoy")]
-const ELECTRUM_SERVER: &str = "ssl://mempool.space:60602";
-const ELECTRUM_SERVER_T4: &str = "ssl://testnet4.foundation.xyz:50002";
-
 #[cfg(feature = "envoy")]
 mod utils;
 
@@ -33,14 +29,20 @@ mod tests {
         bdk_wallet::{KeychainKind, SignOptions, TxOrdering},
         ngwallet::account::Descriptor,
         ngwallet::account::NgAccount,
-        ngwallet::account::RemoteUpdate,
+        ngwallet::account::{MAX_APPLIED_UPDATES, RemoteUpdate},
         ngwallet::bip39::get_descriptors,
         ngwallet::config::{AddressType, NgAccountBackup, NgAccountBuilder, NgAccountConfig},
-        ngwallet::ngwallet::{NgWallet, PsbtOutputOwnership},
-        ngwallet::send::{FeeRateSatPerKvb, TransactionParams},
+        ngwallet::ngwallet::PsbtOutputOwnership,
+        ngwallet::send::{
+            FeeRateSatPerKvb, FeeSharing, OutputOrdering, SpendPath, TransactionParams,
+        },
+        ngwallet::tags::TagInfo,
         std::sync::{Arc, Mutex},
     };
 
+    #[cfg(feature = "testing")]
+    use ngwallet::testkit::{AccountFixture, FIXTURE_FEE};
+
     #[cfg(feature = "envoy")]
     fn assert_no_private_material(label: &str, value: &str) {
         for marker in ["xprv", "tprv", "yprv", "zprv", "uprv", "vprv"] {
@@ -52,7 +54,7 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "envoy")]
+    #[cfg(feature = "testing")]
     fn new_wallet_test_scan() {
         let descriptors = vec![
             Descriptor {
@@ -84,14 +86,7 @@ mod tests {
             .unwrap();
 
         // Let's imagine we are applying updates remotely
-        let mut updates = vec![];
-
-        for wallet in account.wallets.read().unwrap().iter() {
-            let (address_type, request) = account.full_scan_request(wallet.address_type).unwrap();
-            let update =
-                NgWallet::<Connection>::scan(request, ELECTRUM_SERVER, None, None, None).unwrap();
-            updates.push((address_type, Update::from(update)));
-        }
+        let updates = AccountFixture::default().updates(&account).unwrap();
 
         let config = account.config.read().unwrap();
         let payload = RemoteUpdate::new(
@@ -179,27 +174,20 @@ mod tests {
         }
         account.persist().unwrap();
 
-        //test fee electrum fee fetching
+        // fees are known from the spent outputs, and can be overridden
         let first_tx = account.transactions().unwrap()[0].clone();
-        let fee = NgAccount::<Connection>::fetch_fee_from_electrum(
-            &first_tx.tx_id,
-            ELECTRUM_SERVER,
-            None,
-            None,
-        );
-        assert!(fee.is_some());
-        assert!(fee.unwrap() > 0);
+        assert_eq!(first_tx.fee, FIXTURE_FEE);
         account
-            .update_fee(&first_tx.tx_id, fee.expect("Failed to fetch fee"))
+            .update_fee(&first_tx.tx_id, FIXTURE_FEE * 2)
             .expect("Failed to update fee");
         account.persist().unwrap();
         let transactions = account.transactions().unwrap();
         let tx_fee = transactions[0].fee;
-        assert_eq!(tx_fee, fee.unwrap());
+        assert_eq!(tx_fee, FIXTURE_FEE * 2);
     }
 
     #[test]
-    #[cfg(feature = "envoy")]
+    #[cfg(feature = "testing")]
     fn test_input_mis_match() {
         let seed = Mnemonic::parse(
             "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy",
@@ -225,8 +213,8 @@ mod tests {
                 descriptors
                     .into_iter()
                     .map(|d| Descriptor {
-                        internal: d.descriptor_xprv(),
-                        external: Some(d.change_descriptor_xprv()),
+                        internal: d.descriptor_xprv().into_string(),
+                        external: Some(d.change_descriptor_xprv().into_string()),
                         bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
                     })
                     .collect::<Vec<_>>()
@@ -266,15 +254,10 @@ mod tests {
             .unwrap();
 
         // Let's imagine we are applying updates remotely
-        let mut updates = vec![];
-
-        for wallet in account.wallets.read().unwrap().iter() {
-            let (address_type, request) = account.full_scan_request(wallet.address_type).unwrap();
-            let update =
-                NgWallet::<Connection>::scan(request, ELECTRUM_SERVER_T4, None, None, None)
-                    .unwrap();
-            updates.push((address_type, Update::from(update)));
-        }
+        let updates = AccountFixture::default()
+            .network(Network::Testnet4)
+            .updates(&account)
+            .unwrap();
 
         let config = account.config.read().unwrap();
         let payload = RemoteUpdate::new(
@@ -310,6 +293,13 @@ mod tests {
                 note: None,
                 tag: None,
                 do_not_spend_change: false,
+                ordering: OutputOrdering::Shuffle,
+                change_address: None,
+                spend_path: SpendPath::Primary,
+                foreign_inputs: vec![],
+                fee_sharing: FeeSharing::Ours,
+                spend_from_tag: None,
+                embed_memo: false,
             })
             .unwrap();
         let base = compose_tx.psbt.clone();
@@ -525,6 +515,55 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn cached_queries_follow_metadata_updates() {
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+
+        let txs = account.transactions().unwrap();
+        let cached = account.transactions().unwrap();
+        assert_eq!(
+            txs.iter().map(|tx| &tx.tx_id).collect::<Vec<_>>(),
+            cached.iter().map(|tx| &tx.tx_id).collect::<Vec<_>>()
+        );
+        let txid = txs[0].tx_id.clone();
+
+        account.set_note(&txid, "cached note").unwrap();
+        let tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.tx_id == txid)
+            .unwrap();
+        assert_eq!(tx.note.as_deref(), Some("cached note"));
+
+        // writes behind the back of the account show after a refresh only
+        account.meta_storage.set_note(&txid, "direct note").unwrap();
+        let note_of = |account: &NgAccount<Connection>| {
+            account
+                .transactions()
+                .unwrap()
+                .into_iter()
+                .find(|tx| tx.tx_id == txid)
+                .unwrap()
+                .note
+        };
+        assert_eq!(note_of(&account).as_deref(), Some("cached note"));
+        account.refresh();
+        assert_eq!(note_of(&account).as_deref(), Some("direct note"));
+
+        let output_id = account.utxos().unwrap()[0].get_id();
+        account.set_do_not_spend(&output_id, true).unwrap();
+        let output = account
+            .utxos()
+            .unwrap()
+            .into_iter()
+            .find(|output| output.get_id() == output_id)
+            .unwrap();
+        assert!(output.do_not_spend);
+    }
+
     #[test]
     #[cfg(feature = "envoy")]
     fn check_psbt_parsing() {
@@ -539,6 +578,13 @@ mod tests {
             note: Some("not a note".to_string()),
             tag: Some("hello".to_string()),
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
 
         println!("params: {params:?}");
@@ -637,722 +683,3650 @@ mod tests {
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn change_address_type() {
-        let account = utils::tests_util::get_ng_hot_wallet();
-        let wallet = account.get_coordinator_wallet();
-        assert_eq!(
-            account.config.read().unwrap().preferred_address_type,
-            AddressType::P2tr
-        );
-        assert_eq!(wallet.address_type, AddressType::P2tr);
+    fn decode_raw_tx_matches_wallet_history() {
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
 
+        let known = account.transactions().unwrap()[0].clone();
+        account.set_note(&known.tx_id, "decoded").unwrap();
         account
-            .set_preferred_address_type(AddressType::P2wpkh)
+            .set_tag(&format!("{}:0", known.tx_id), "Savings")
             .unwrap();
-        let wallet = account.get_coordinator_wallet();
+
+        let raw = account
+            .wallets
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|wallet| {
+                let wallet = wallet.bdk_wallet.lock().unwrap();
+                wallet.get_tx(known.tx_id.parse().unwrap()).map(|tx| {
+                    bdk_wallet::bitcoin::consensus::encode::serialize_hex(tx.tx_node.tx.as_ref())
+                })
+            })
+            .unwrap();
+
+        let decoded = account.decode_raw_tx(&raw).unwrap();
+        assert_eq!(decoded.tx_id, known.tx_id);
+        assert_eq!(decoded.amount, known.amount);
+        assert_eq!(decoded.confirmations, known.confirmations);
+        assert_eq!(decoded.note.as_deref(), Some("decoded"));
+        assert_eq!(decoded.outputs[0].tag.as_deref(), Some("Savings"));
+        assert!(decoded.outputs[0].keychain.is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn export_xpubs_use_slip132_prefixes() {
+        let account = make_test_account();
+        let xpubs = account.get_export_xpubs();
+        assert_eq!(xpubs.len(), 1);
+
+        let export = &xpubs[0];
+        assert_eq!(export.address_type, AddressType::P2wpkh);
+        assert_eq!(export.derivation_path, "m/84'/1'/0'");
+        assert_eq!(export.fingerprint, account.get_xfp());
+        assert!(export.xpub.starts_with("tpub"));
+        assert!(export.slip132.starts_with("vpub"));
         assert_eq!(
-            account.config.read().unwrap().preferred_address_type,
-            AddressType::P2wpkh
+            ngwallet::slip132::decode(&export.slip132)
+                .unwrap()
+                .to_string(),
+            export.xpub
         );
-        assert_eq!(wallet.address_type, AddressType::P2wpkh);
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn verify_address() {
-        let account = utils::tests_util::get_ng_hot_wallet();
+    fn export_electrum_and_sparrow_files() {
+        let account = make_test_account();
 
-        // testnet segwit receive address 0
-        let result = account
-            .verify_address(
-                String::from("tb1qp3s35d5579w9mtx4vkx2lngfpnwyjx8jxhveym"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(0));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 0);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 0);
+        let electrum: serde_json::Value =
+            serde_json::from_str(&account.export_electrum_json().unwrap()).unwrap();
+        assert_eq!(electrum["wallet_type"], "standard");
+        let keystore = &electrum["keystore"];
+        assert_eq!(keystore["type"], "bip32");
+        assert!(keystore["xpub"].as_str().unwrap().starts_with("vpub"));
+        assert_eq!(keystore["derivation"], "m/84'/1'/0'");
+        assert_eq!(
+            keystore["root_fingerprint"],
+            account.get_xfp().to_lowercase()
+        );
+        assert!(keystore["xprv"].is_null());
 
-        // testnet segwit receive address 5
-        let result = account
-            .verify_address(
-                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(5));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 4);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 5);
+        let sparrow = account.export_sparrow_json().unwrap();
+        assert_no_private_material("sparrow export", &sparrow);
+        let sparrow: serde_json::Value = serde_json::from_str(&sparrow).unwrap();
+        assert_eq!(sparrow["label"], "Test");
+        assert_eq!(sparrow["blockheight"], 0);
+        assert!(
+            sparrow["descriptor"]
+                .as_str()
+                .unwrap()
+                .starts_with("wpkh([")
+        );
+    }
 
-        // ensure the optimization to validate repeat addresses work
-        let result = account
-            .verify_address(
-                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(5));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 0);
-        assert_eq!(result.receive_lower, 5);
-        assert_eq!(result.receive_upper, 5);
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn import_descriptor_dedupes_accounts() {
+        use ngwallet::import::{ImportResult, import_descriptor};
 
-        // testnet segwit receive address 0, reset for next tests
-        let result = account
-            .verify_address(
-                String::from("tb1qp3s35d5579w9mtx4vkx2lngfpnwyjx8jxhveym"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(0));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 0);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 0);
+        let account = make_test_account();
+        let account_id = account.config.read().unwrap().id.clone();
+        let accounts = vec![account.clone()];
 
-        // testnet segwit receive address 30
-        let result = account
-            .verify_address(
-                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, None);
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 25);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 25);
+        // the public form of a tracked private descriptor
+        let public = account.get_external_public_descriptors()[0].1.clone();
+        assert_eq!(
+            import_descriptor(&accounts, &public).unwrap(),
+            ImportResult::AlreadyTracked {
+                account_id: account_id.clone()
+            }
+        );
+        assert_eq!(
+            import_descriptor(&accounts, INTERNAL_DESCRIPTOR_2).unwrap(),
+            ImportResult::NewScriptType {
+                account_id,
+                address_type: AddressType::P2tr
+            }
+        );
+        assert_eq!(
+            import_descriptor(&accounts, FUNDED_EXTERNAL_DESCRIPTOR).unwrap(),
+            ImportResult::NewAccount
+        );
+        assert!(import_descriptor(&accounts, "wpkh(garbage)").is_err());
+    }
 
-        let result = account
-            .verify_address(
-                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
-                1,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(30));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 29);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 30);
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn import_probe_keeps_active_script_types() {
+        use bdk_wallet::miniscript::{Descriptor as MiniscriptDescriptor, DescriptorPublicKey};
+        use ngwallet::import::{HistoryProbe, probe_descriptors};
 
-        // test that we resume the search from the last verified address, and the downward search
-        // works
-        // testnet segwit receive address 5
-        let result = account
-            .verify_address(
-                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, None);
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 25);
-        assert_eq!(result.receive_lower, 6);
-        assert_eq!(result.receive_upper, 55);
+        struct UsedScripts(Vec<ScriptBuf>);
+        impl HistoryProbe for UsedScripts {
+            fn has_history(&self, scripts: &[ScriptBuf]) -> anyhow::Result<bool> {
+                Ok(scripts.iter().any(|script| self.0.contains(script)))
+            }
+        }
 
-        let result = account
-            .verify_address(
-                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
-                1,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(5));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 25);
-        assert_eq!(result.receive_lower, 5);
-        assert_eq!(result.receive_upper, 55);
+        let seed = Mnemonic::parse(
+            "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy",
+        )
+        .unwrap()
+        .to_seed("");
+        let descriptors = || {
+            get_descriptors(&seed, Network::Signet, 0)
+                .unwrap()
+                .into_iter()
+                .map(|d| Descriptor {
+                    internal: d.descriptor_xpub(),
+                    external: Some(d.change_descriptor_xpub()),
+                    bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+                })
+                .collect::<Vec<_>>()
+        };
+        let address_types = |descriptors: &[Descriptor<Connection>]| {
+            descriptors
+                .iter()
+                .map(|d| ngwallet::utils::get_address_type(&d.internal))
+                .collect::<Vec<_>>()
+        };
 
-        // testnet segwit change address 0
-        let result = account
-            .verify_address(
-                String::from("tb1qm2rus4zu75exrlu9rrk0l3ctktkujtetqrjd88"),
-                0,
-                50,
-            )
+        // only the change address 3 of the taproot wallet was used
+        let taproot = descriptors()
+            .into_iter()
+            .find(|d| d.internal.starts_with("tr("))
             .unwrap();
-        assert_eq!(result.found_index, None);
+        let change: MiniscriptDescriptor<DescriptorPublicKey> =
+            taproot.external.unwrap().parse().unwrap();
+        let used = change.at_derivation_index(3).unwrap().script_pubkey();
+        let probe = UsedScripts(vec![used]);
 
-        // mainnet segwit receive address 0, should fail network requirement
-        assert!(
-            account
-                .verify_address(
-                    String::from("bc1q99mxpdle2pqs3pkaxcz2wmk8l0avgskyuuc6pl"),
-                    0,
-                    50,
-                )
-                .is_err()
+        let active = probe_descriptors(descriptors(), AddressType::P2wpkh, 5, &probe).unwrap();
+        assert_eq!(
+            address_types(&active),
+            vec![AddressType::P2wpkh, AddressType::P2tr]
         );
-
-        // testnet taproot receive address 0
-        let result = account
-            .verify_address(
-                String::from("tb1phv4spu4u6uakttj3mqqcr77la4u6a28j943d3cxjh02a6ny78d0s7tupl5"),
-                0,
-                50,
-            )
+        let account = NgAccountBuilder::default()
+            .name("Imported".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(active)
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("imported".to_string())
+            .build_in_memory()
             .unwrap();
-        assert_eq!(result.found_index.unwrap(), 0);
+        assert_eq!(account.wallets.read().unwrap().len(), 2);
+
+        // addresses past the probed depth don't count
+        let active = probe_descriptors(descriptors(), AddressType::P2wpkh, 3, &probe).unwrap();
+        assert_eq!(address_types(&active), vec![AddressType::P2wpkh]);
+        assert!(probe_descriptors(vec![], AddressType::P2wpkh, 5, &probe).is_err());
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn verify_address_2() {
-        let account = utils::tests_util::get_ng_hot_wallet();
+    fn gap_limit_is_configurable() {
+        let account = make_test_account();
+        assert_eq!(account.gap_limit(), 300);
 
-        // multi-attempt address verification (address 30 fails on first attempt, succeeds on second)
-        let result = account
-            .verify_address(
-                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, None);
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 25);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 25);
+        account.set_gap_limit(1000).unwrap();
+        assert_eq!(account.gap_limit(), 1000);
+        let stored = account.meta_storage.get_config().unwrap().unwrap();
+        assert_eq!(stored.gap_limit, Some(1000));
+    }
 
-        let result = account
-            .verify_address(
-                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
-                1,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, Some(30));
-        assert_eq!(result.change_lower, 0);
-        assert_eq!(result.change_upper, 29);
-        assert_eq!(result.receive_lower, 0);
-        assert_eq!(result.receive_upper, 30);
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn reorg_unconfirming_transactions_is_reported() {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{
+            BlockHash, OutPoint, Transaction, TxIn, Txid, absolute, transaction,
+        };
+        use bdk_wallet::chain::{BlockId, CheckPoint, ConfirmationBlockTime, TxUpdate};
+        use ngwallet::ngwallet::ReorgEvent;
 
-        // change address verification
-        let result = account
-            .verify_address(
-                String::from("tb1qm2rus4zu75exrlu9rrk0l3ctktkujtetqrjd88"),
-                0,
-                50,
-            )
-            .unwrap();
-        assert_eq!(result.found_index, None);
+        use ngwallet::events::AccountEvent;
 
-        // network validation (mainnet address should fail on testnet account)
-        assert!(
-            account
-                .verify_address(
-                    String::from("bc1q99mxpdle2pqs3pkaxcz2wmk8l0avgskyuuc6pl"),
-                    0,
-                    50,
-                )
-                .is_err()
-        );
-    }
+        let account = make_test_account();
+        let events = account.subscribe();
+        let reorgs = || -> Vec<ReorgEvent> {
+            events
+                .try_iter()
+                .filter_map(|event| match event {
+                    AccountEvent::Reorg(reorg) => Some(reorg),
+                    _ => None,
+                })
+                .collect()
+        };
 
-    #[test]
-    fn autocomplete_seedword() {
-        let suggestions = bip39::get_seedword_suggestions("fa", 3);
-        assert_eq!(suggestions, ["fabric", "face", "faculty"]);
+        let (_, genesis) = account.chain_tip();
+        let block = |height: u32, byte: u8| BlockId {
+            height,
+            hash: BlockHash::from_byte_array([byte; 32]),
+        };
+        let chain = |blocks: Vec<BlockId>| {
+            let genesis = BlockId {
+                height: 0,
+                hash: genesis,
+            };
+            CheckPoint::from_block_ids([genesis].into_iter().chain(blocks)).unwrap()
+        };
 
-        let suggestions = bip39::get_seedword_suggestions("xy", 3);
-        assert_eq!(suggestions, Vec::<&str>::new());
+        let address = account.next_address().unwrap()[0].0.address.clone();
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+
+        let mut tx_update = TxUpdate::default();
+        tx_update.txs = vec![Arc::new(tx)];
+        tx_update.anchors = [(
+            ConfirmationBlockTime {
+                block_id: block(2, 2),
+                confirmation_time: 100,
+            },
+            txid,
+        )]
+        .into();
+        let update = Update {
+            tx_update,
+            chain: Some(chain(vec![block(1, 1), block(2, 2)])),
+            ..Default::default()
+        };
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        assert_eq!(account.chain_tip().0, 2);
+        assert!(account.transactions().unwrap()[0].is_confirmed);
+        assert!(reorgs().is_empty());
+
+        // block 2 is replaced, the transaction is not in the new chain
+        let update = Update {
+            chain: Some(chain(vec![block(1, 1), block(2, 22), block(3, 33)])),
+            ..Default::default()
+        };
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        assert_eq!(
+            account.chain_tip(),
+            (3, BlockHash::from_byte_array([33; 32]))
+        );
+        assert_eq!(
+            reorgs(),
+            vec![ReorgEvent {
+                address_type: AddressType::P2wpkh,
+                fork_height: 2,
+                unconfirmed_tx_ids: vec![txid.to_string()],
+            }]
+        );
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn get_external_pubkey() {
-        let account = utils::tests_util::get_ng_hot_wallet();
-        let descriptors = account.get_external_public_descriptors();
-        assert_eq!(descriptors[0],
-            (AddressType::P2tr, "tr([20a6ab53/86'/1'/0']tpubDC8wiq86H9ZMiscQMoG1LcvQayTKs9Ef32n4fpVV8JR2FfCDgbTE2yECxi2Bgtkb7UEUheRyeprMtWRFdMXWQq8bx6ugwdTaMp6s2bNYjSV/0/*)#taglzc2a".to_string())
+    fn subscribers_receive_account_events() {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{
+            BlockHash, OutPoint, Transaction, TxIn, Txid, absolute, transaction,
+        };
+        use bdk_wallet::chain::{BlockId, CheckPoint, ConfirmationBlockTime, TxUpdate};
+        use ngwallet::events::AccountEvent;
+
+        let account = make_test_account();
+        let events = account.subscribe();
+
+        let address = account.next_address().unwrap()[0].0.clone();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            AccountEvent::AddressRevealed {
+                address: address.address.to_string(),
+                address_type: AddressType::P2wpkh,
+                index: 0,
+            }
         );
+        // the same unused address is not revealed twice
+        account.next_address().unwrap();
+        assert!(events.try_recv().is_err());
 
-        assert_eq!(descriptors[1],
-                   (AddressType::P2wpkh, "wpkh([20a6ab53/84'/1'/0']tpubDC4BKZc39XVBnaTSKLw9ks63KuuEFKdRB17PZMx6GfgxaMHhV79e3zSoVT2TDe9yxwyzm1YHMS8JFNQYWoTvkLJNHa5mTyA5Gkx8NwWVkvU/0/*)#m2myh9ws".to_string())
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: address.address.script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        let mut tx_update = TxUpdate::default();
+        tx_update.txs = vec![Arc::new(tx)];
+        tx_update.seen_ats = [(txid, 100)].into();
+        let update = Update {
+            tx_update,
+            ..Default::default()
+        };
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                AccountEvent::NewTransaction {
+                    tx_id: txid.to_string()
+                },
+                AccountEvent::BalanceChanged(account.balance().unwrap()),
+            ]
+        );
+
+        let (_, genesis) = account.chain_tip();
+        let block = BlockId {
+            height: 1,
+            hash: BlockHash::from_byte_array([1; 32]),
+        };
+        let mut tx_update = TxUpdate::default();
+        tx_update.anchors = [(
+            ConfirmationBlockTime {
+                block_id: block,
+                confirmation_time: 200,
+            },
+            txid,
+        )]
+        .into();
+        let update = Update {
+            tx_update,
+            chain: Some(
+                CheckPoint::from_block_ids([
+                    BlockId {
+                        height: 0,
+                        hash: genesis,
+                    },
+                    block,
+                ])
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                AccountEvent::TransactionConfirmed {
+                    tx_id: txid.to_string(),
+                    block_height: 1,
+                },
+                AccountEvent::BalanceChanged(account.balance().unwrap()),
+            ]
+        );
+
+        account.set_note(&txid.to_string(), "rent").unwrap();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            AccountEvent::MetadataChanged {
+                id: txid.to_string()
+            }
         );
     }
+
     #[test]
     #[cfg(feature = "envoy")]
-    fn test_bip329_entries_have_valid_structure() {
-        let account = utils::tests_util::get_ng_hot_wallet();
+    fn double_spends_are_detected() {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{OutPoint, Transaction, TxIn, Txid, absolute, transaction};
+        use bdk_wallet::chain::TxUpdate;
+        use ngwallet::events::AccountEvent;
 
-        let result = account.get_bip329_data().unwrap();
-        assert!(!result.is_empty());
+        let account = make_test_account();
+        let address = account.next_address().unwrap()[0].0.clone();
+        let events = account.subscribe();
 
-        for entry in &result {
-            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
+        let payment = |amount: u64, seen_at: u64| {
+            let tx = Transaction {
+                version: transaction::Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                    ..Default::default()
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(amount),
+                    script_pubkey: address.address.script_pubkey(),
+                }],
+            };
+            let txid = tx.compute_txid();
+            let mut tx_update = TxUpdate::default();
+            tx_update.txs = vec![Arc::new(tx)];
+            tx_update.seen_ats = [(txid, seen_at)].into();
+            let update = Update {
+                tx_update,
+                ..Default::default()
+            };
+            (txid.to_string(), update)
+        };
 
-            // All types should have "type" and "ref"
-            assert!(json.get("type").is_some());
-            assert!(json.get("ref").is_some());
+        let (original, update) = payment(10_000, 100);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        events.try_iter().for_each(drop);
 
-            match json.get("type").unwrap().as_str().unwrap() {
-                "xpub" => {
-                    assert!(
-                        json.get("ref")
-                            .unwrap()
-                            .as_str()
-                            .unwrap()
-                            .starts_with("tpub")
-                    );
-                    assert!(json.get("label").is_some() || json.get("label").is_none());
-                }
-                "output" => {
-                    assert!(json.get("ref").unwrap().as_str().unwrap().contains(':'));
-                    assert!(json.get("spendable").is_some());
-                }
-                "tx" => {
-                    assert!(json.get("origin").is_some());
-                }
-                other => panic!("Unexpected BIP329 type: {other}"),
+        // the sender replaces the payment with a smaller one
+        let (replacement, update) = payment(1_000, 200);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        let received = events.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            received[0],
+            AccountEvent::DoubleSpendDetected {
+                tx_id: original.clone(),
+                conflicts_with: vec![replacement.clone()],
             }
-        }
+        );
+        assert!(received.contains(&AccountEvent::NewTransaction {
+            tx_id: replacement.clone()
+        }));
+
+        let transactions = account.transactions().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].tx_id, replacement);
+        assert_eq!(transactions[0].conflicts_with, vec![original]);
+
+        // applying the same update again doesn't report the conflict twice
+        let (_, update) = payment(1_000, 300);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        assert!(
+            !events
+                .try_iter()
+                .any(|event| matches!(event, AccountEvent::DoubleSpendDetected { .. }))
+        );
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn test_bip329_contains_expected_testnet_xpub() {
-        let account = utils::tests_util::get_ng_hot_wallet();
-        let data = account.get_bip329_data().expect("should export BIP-329");
+    fn fiat_values_are_recorded_and_backed_up() {
+        use ngwallet::fiat::{FiatValue, PriceSource};
 
-        let expected_xpub = "tpubDC8wiq86H9ZMiscQMoG1LcvQayTKs9Ef32n4fpVV8JR2FfCDgbTE2yECxi2Bgtkb7UEUheRyeprMtWRFdMXWQq8bx6ugwdTaMp6s2bNYjSV";
+        struct FixedPrice;
+        impl PriceSource for FixedPrice {
+            fn price_at(&self, currency: &str, timestamp: u64) -> anyhow::Result<f64> {
+                assert_eq!(currency, "USD");
+                assert_eq!(timestamp, 1_700_000_000);
+                Ok(50_000.0)
+            }
+        }
 
-        let maybe_entry = data.iter().find(|entry| {
-            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
-            json.get("type") == Some(&serde_json::Value::String("xpub".to_string()))
-                && json.get("ref") == Some(&serde_json::Value::String(expected_xpub.to_string()))
-        });
+        let account = make_test_account();
+        let (txid, update) = confirmed_receive_update(&account, 200_000, 1, 1_700_000_000);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
 
-        assert!(
-            maybe_entry.is_some(),
-            "Expected hardcoded xpub not found in BIP-329 export"
+        assert_eq!(account.record_fiat_values(&FixedPrice, "USD").unwrap(), 1);
+        // already valued transactions are skipped
+        assert_eq!(account.record_fiat_values(&FixedPrice, "USD").unwrap(), 0);
+
+        let tx = account.transactions().unwrap().remove(0);
+        assert_eq!(tx.fiat_value, Some(100.0));
+        assert_eq!(tx.fiat_currency.as_deref(), Some("USD"));
+
+        account
+            .set_fiat_value(&txid.to_string(), 95.5, "EUR")
+            .unwrap();
+        let backup = NgAccountBackup::deserialize(&account.get_backup_json().unwrap()).unwrap();
+        assert_eq!(
+            backup.fiat_values.get(&txid.to_string()),
+            Some(&FiatValue {
+                value: 95.5,
+                currency: "EUR".to_string(),
+            })
         );
+
+        // a value in another currency is replaced
+        assert_eq!(account.record_fiat_values(&FixedPrice, "USD").unwrap(), 1);
+        let tx = account.transactions().unwrap().remove(0);
+        assert_eq!(tx.fiat_currency.as_deref(), Some("USD"));
     }
+
     #[test]
     #[cfg(feature = "envoy")]
-    fn test_bip329_export_contains_tx_and_output_notes() {
+    fn proof_of_reserves_roundtrip() {
+        use ngwallet::proof_of_reserves::verify_reserve_proof;
+
         let mut account = utils::tests_util::get_ng_hot_wallet();
         utils::tests_util::add_funds_to_wallet(&mut account);
+        let unspent: Vec<_> = account
+            .wallets
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|wallet| {
+                wallet
+                    .bdk_wallet
+                    .lock()
+                    .unwrap()
+                    .list_unspent()
+                    .collect::<Vec<_>>()
+            })
+            .map(|utxo| (utxo.outpoint, utxo.txout))
+            .collect();
+        let total: Amount = unspent.iter().map(|(_, utxo)| utxo.value).sum();
 
-        let txid = account.transactions().unwrap()[0].tx_id.clone();
+        let message = "Proof for auditor, nonce 42";
+        let proof = account.create_reserve_proof(message, None).unwrap();
+        assert_eq!(proof.unsigned_tx.input.len(), unspent.len() + 1);
+        // unsigned proofs don't verify
+        assert!(verify_reserve_proof(&proof, message, &unspent).is_err());
 
-        // Simulate adding notes
-        account.set_note(&txid, "Funding tx").unwrap();
+        let proof = account.sign_reserve_proof(&proof).unwrap();
+        assert_eq!(
+            verify_reserve_proof(&proof, message, &unspent).unwrap(),
+            total
+        );
+        assert!(verify_reserve_proof(&proof, "another message", &unspent).is_err());
+        // spent outputs can't be proven
+        assert!(verify_reserve_proof(&proof, message, &unspent[1..]).is_err());
 
-        let output_id = format!("{}:{}", txid, 0); // assuming vout = 0
+        let (outpoint, utxo) = unspent[0].clone();
+        let partial = account
+            .create_reserve_proof(message, Some(&[outpoint]))
+            .unwrap();
+        let partial = account.sign_reserve_proof(&partial).unwrap();
+        assert_eq!(
+            verify_reserve_proof(&partial, message, &unspent).unwrap(),
+            utxo.value
+        );
 
-        // Set a tag for the output
-        account.set_tag(&output_id, "important").unwrap();
+        // ANYONECANPAY signatures don't commit to the challenge, even with
+        // the sighash type hints of the PSBT removed
+        use bdk_wallet::bitcoin::{EcdsaSighashType, TapSighashType};
+        let mut anyone_can_pay = account
+            .create_reserve_proof(message, Some(&[outpoint]))
+            .unwrap();
+        let sighash_type = match utxo.script_pubkey.is_p2tr() {
+            true => TapSighashType::AllPlusAnyoneCanPay.into(),
+            false => EcdsaSighashType::AllPlusAnyoneCanPay.into(),
+        };
+        for input in anyone_can_pay.inputs.iter_mut() {
+            input.sighash_type = Some(sighash_type);
+        }
+        let signed = account
+            .sign(
+                &anyone_can_pay.serialize(),
+                SignOptions {
+                    trust_witness_utxo: true,
+                    allow_all_sighashes: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut anyone_can_pay = Psbt::deserialize(&signed).unwrap();
+        assert!(anyone_can_pay.inputs[1].final_script_witness.is_some());
+        for input in anyone_can_pay.inputs.iter_mut() {
+            input.sighash_type = None;
+        }
+        let error = verify_reserve_proof(&anyone_can_pay, message, &unspent).unwrap_err();
+        assert!(error.to_string().contains("sighash"));
 
-        let bip329_data = account.get_bip329_data().unwrap();
+        // a challenge locked by another script
+        let mut other_challenge = partial.clone();
+        other_challenge.inputs[0]
+            .witness_utxo
+            .as_mut()
+            .unwrap()
+            .script_pubkey = unspent
+            .iter()
+            .map(|(_, utxo)| utxo.script_pubkey.clone())
+            .find(|script| *script != utxo.script_pubkey)
+            .unwrap();
+        assert!(verify_reserve_proof(&other_challenge, message, &unspent).is_err());
+    }
 
-        let has_tx_note = bip329_data.iter().any(|entry| {
-            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
-            json.get("type") == Some(&"tx".into())
-                && json.get("label") == Some(&"Funding tx".into())
-        });
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn sign_and_verify_messages() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let addresses = account.next_address().unwrap();
+        let message = "I own this address";
 
-        let has_output_note = bip329_data.iter().any(|entry| {
-            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
-            json.get("type") == Some(&"output".into())
-                && json.get("label") == Some(&"important".into())
-        });
+        // taproot gets a BIP-322 signature, P2WPKH a legacy one
+        for (address, address_type) in addresses {
+            let address = address.address.to_string();
+            let signed = account.sign_message(&address, message).unwrap();
+            assert_eq!(signed.address, address);
+            assert!(
+                account
+                    .verify_message(&address, message, &signed.signature)
+                    .unwrap(),
+                "{address_type:?} signature does not verify"
+            );
+            assert!(
+                !account
+                    .verify_message(&address, "I don't own it", &signed.signature)
+                    .unwrap()
+            );
+        }
 
-        assert!(has_tx_note, "Missing tx note in BIP-329 export");
-        assert!(has_output_note, "Missing output note in BIP-329 export");
+        // someone else's address
+        let foreign = "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w";
+        assert!(account.sign_message(foreign, message).is_err());
+
+        // watch-only accounts can't sign
+        let watch_only = utils::tests_util::get_ng_watch_only_account();
+        let address = watch_only.next_address().unwrap()[0].0.address.to_string();
+        assert!(watch_only.sign_message(&address, message).is_err());
     }
 
-    // -------------------------------------------------------------------------
-    // SFT-7011: RemoteUpdate authenticity, replay-protection, account-binding
     #[test]
     #[cfg(feature = "envoy")]
-    fn new_wallet_test_scan_security() {
-        let descriptors = vec![
-            Descriptor {
-                internal: FUNDED_INTERNAL_DESCRIPTOR.to_string(),
-                external: Some(FUNDED_EXTERNAL_DESCRIPTOR.to_string()),
-                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
-            },
-            Descriptor {
-                internal: FUNDED_INTERNAL_DESCRIPTOR_TR.to_string(),
-                external: Some(FUNDED_EXTERNAL_DESCRIPTOR_TR.to_string()),
-                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
-            },
-        ];
+    fn sign_with_registered_software_signer() {
+        use bdk_wallet::bitcoin::bip32::DerivationPath;
+        use ngwallet::bip39::MasterKey;
+        use ngwallet::signer::{Signer, SignerRegistry, SoftwareSigner};
+        use std::str::FromStr;
 
-        let account = NgAccountBuilder::default()
-            .name("Passport Prime".to_string())
+        let mnemonic = Mnemonic::parse(
+            "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy",
+        )
+        .unwrap();
+        let seed = mnemonic.to_seed("");
+        let descriptors = get_descriptors(&seed, Network::Testnet4, 0)
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.bip() == "84")
+            .map(|d| Descriptor {
+                internal: d.change_descriptor_xpub(),
+                external: Some(d.descriptor_xpub()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            })
+            .collect::<Vec<_>>();
+        let mut account = NgAccountBuilder::default()
+            .name("Watch only".to_string())
             .color("red".to_string())
             .seed_has_passphrase(false)
             .device_serial(None)
             .date_added(None)
-            .preferred_address_type(AddressType::P2tr)
+            .preferred_address_type(AddressType::P2wpkh)
             .index(0)
             .descriptors(descriptors)
             .date_synced(None)
             .account_path(None)
-            .network(Network::Signet)
-            .id("1234567890".to_string())
+            .network(Network::Testnet4)
+            .id("watch-only".to_string())
             .build_in_memory()
             .unwrap();
+        utils::tests_util::add_funds_to_wallet(&mut account);
 
-        // --- Step 1: do a real network scan and build a valid payload ----------
-        let mut updates = vec![];
-        for wallet in account.wallets.read().unwrap().iter() {
-            let (address_type, request) = account.full_scan_request(wallet.address_type).unwrap();
-            let update =
-                NgWallet::<Connection>::scan(request, ELECTRUM_SERVER, None, None, None).unwrap();
-            updates.push((address_type, Update::from(update)));
-        }
-
-        let valid_payload = {
-            let cfg = account.config.read().unwrap();
-            RemoteUpdate::new(
-                cfg.id.clone(),
-                cfg.network,
-                cfg.descriptor_hash(),
-                cfg.last_remote_sequence + 1,
-                None,
-                updates,
-            )
-            .serialize()
+        let psbt = {
+            let wallet = account.get_coordinator_wallet();
+            let mut wallet = wallet.bdk_wallet.lock().unwrap();
+            let address = wallet.next_unused_address(KeychainKind::External).address;
+            let mut builder = wallet.build_tx();
+            builder.add_recipient(address.script_pubkey(), Amount::from_sat(10_000));
+            builder.finish().unwrap()
         };
 
-        account.update(valid_payload.clone()).unwrap();
-        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
-        assert!(account.balance().unwrap().total().to_sat() > 0);
-        assert!(!account.transactions().unwrap().is_empty());
+        let mut registry = SignerRegistry::default();
+        assert!(
+            account
+                .sign_with_signers(&psbt.serialize(), &registry)
+                .is_err()
+        );
 
-        //replay the exact same payload — must be rejected
-        let replay_err = account.update(valid_payload).unwrap_err();
+        let master_key = MasterKey::from_entropy(
+            &Secp256k1::new(),
+            Network::Testnet4,
+            &mnemonic.to_entropy(),
+            "",
+            None,
+        )
+        .unwrap();
+        let signer = SoftwareSigner::new(&master_key, Network::Testnet4).unwrap();
+        assert_eq!(
+            signer.get_fingerprint().to_string().to_uppercase(),
+            account.get_xfp()
+        );
+        let xpub = signer
+            .get_xpub(&DerivationPath::from_str("m/84'/1'/0'").unwrap())
+            .unwrap();
         assert!(
-            replay_err.to_string().contains("not newer"),
-            "replay should be rejected, got: {replay_err}"
+            account.get_external_public_descriptors()[0]
+                .1
+                .contains(&xpub.to_string())
         );
-        // State must not have changed after a failed replay.
-        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+        assert!(
+            signer
+                .display_address("tb1q", &DerivationPath::master())
+                .is_err()
+        );
+        registry.register(Arc::new(signer));
 
-        // wrong account_id — must be rejectedd
-        let wrong_id_payload = {
-            let cfg = account.config.read().unwrap();
-            RemoteUpdate::new(
-                "attacker-account".to_string(),
-                cfg.network,
-                cfg.descriptor_hash(),
-                cfg.last_remote_sequence + 1,
-                None,
-                vec![],
-            )
-            .serialize()
-        };
-        let wrong_id_err = account.update(wrong_id_payload).unwrap_err();
+        let signed = account
+            .sign_with_signers(&psbt.serialize(), &registry)
+            .unwrap();
+        let signed = Psbt::deserialize(&signed).unwrap();
         assert!(
-            wrong_id_err.to_string().contains("account_id mismatch"),
-            "wrong account_id should be rejected, got: {wrong_id_err}"
+            signed
+                .inputs
+                .iter()
+                .all(|input| input.final_script_witness.is_some())
         );
+        signed.extract_tx().unwrap();
+    }
 
-        //  metadata trying to change preferred_address_type
-        let tampered_meta_payload = {
-            let cfg = account.config.read().unwrap();
-            let mut meta = cfg.clone();
-            // Attempt to silently flip the coordinator wallet to P2wpkh.
-            meta.preferred_address_type = AddressType::P2wpkh;
-            RemoteUpdate::new(
-                cfg.id.clone(),
-                cfg.network,
-                cfg.descriptor_hash(),
-                cfg.last_remote_sequence + 1,
-                Some(meta),
-                vec![],
-            )
-            .serialize()
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn combine_multisig_psbts() {
+        use bdk_wallet::bitcoin::NetworkKind;
+        use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+        use ngwallet::config::{MultiSigDetails, MultiSigSigner};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let keys: Vec<Xpriv> = (1..=3)
+            .map(|seed| Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap())
+            .collect();
+        let xpubs: Vec<Xpub> = keys
+            .iter()
+            .map(|key| Xpub::from_priv(&secp, &key.derive_priv(&secp, &path).unwrap()))
+            .collect();
+        let descriptor = |keychain: u32| {
+            let keys = keys
+                .iter()
+                .zip(&xpubs)
+                .map(|(key, xpub)| {
+                    format!(
+                        "[{}/48'/1'/0'/2']{xpub}/{keychain}/*",
+                        key.fingerprint(&secp)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("wsh(sortedmulti(2,{keys}))")
         };
-        let meta_err = account.update(tampered_meta_payload).unwrap_err();
+        let multisig = MultiSigDetails::new(
+            2,
+            3,
+            AddressType::P2wsh,
+            Some(NetworkKind::Test),
+            keys.iter()
+                .zip(&xpubs)
+                .map(|(key, xpub)| MultiSigSigner::new(&path, &key.fingerprint(&secp), xpub))
+                .collect(),
+        )
+        .unwrap();
+
+        let mut account = NgAccountBuilder::default()
+            .name("Multisig".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wsh)
+            .index(0)
+            .descriptors(vec![Descriptor {
+                internal: descriptor(1),
+                external: Some(descriptor(0)),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            }])
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("multisig".to_string())
+            .multisig(multisig)
+            .build_in_memory()
+            .unwrap();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+
+        let psbt = {
+            let wallet = account.get_coordinator_wallet();
+            let mut wallet = wallet.bdk_wallet.lock().unwrap();
+            let address = wallet.next_unused_address(KeychainKind::External).address;
+            let mut builder = wallet.build_tx();
+            builder.add_recipient(address.script_pubkey(), Amount::from_sat(10_000));
+            builder.finish().unwrap()
+        };
+        let signed_by = |key: &Xpriv| {
+            let mut psbt = psbt.clone();
+            psbt.sign(key, &secp).unwrap();
+            psbt.serialize()
+        };
+
+        let session = account.signing_session(&signed_by(&keys[0])).unwrap();
+        let progress = session.progress();
+        assert_eq!(progress.signatures_needed, 1);
+        assert_eq!(
+            progress.signed,
+            vec![keys[0].fingerprint(&secp).to_string().to_uppercase()]
+        );
+
+        // one signature short of the threshold, left unfinalized
+        let combined = account
+            .combine_psbts(vec![signed_by(&keys[0]), psbt.serialize()])
+            .unwrap();
+        let combined = Psbt::deserialize(&combined).unwrap();
         assert!(
-            meta_err.to_string().contains("preferred_address_type"),
-            "preferred_address_type tamper should be rejected, got: {meta_err}"
+            combined
+                .inputs
+                .iter()
+                .all(|input| input.final_script_witness.is_none())
         );
-        // Coordinator wallet must still be P2tr.
+
+        let combined = account
+            .combine_psbts(vec![signed_by(&keys[0]), signed_by(&keys[2])])
+            .unwrap();
+        let combined = Psbt::deserialize(&combined).unwrap();
+        assert!(
+            combined
+                .inputs
+                .iter()
+                .all(|input| input.final_script_witness.is_some())
+        );
+        assert!(combined.extract_tx().is_ok());
+
+        let mut other = psbt.clone();
+        other.unsigned_tx.lock_time = bdk_wallet::bitcoin::absolute::LockTime::from_consensus(1);
+        assert!(
+            account
+                .combine_psbts(vec![signed_by(&keys[0]), other.serialize()])
+                .is_err()
+        );
+        assert!(account.combine_psbts(vec![]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn list_addresses_with_usage_and_balance() {
+        let account = make_test_account();
+        let (_, update) = confirmed_receive_update(&account, 50_000, 1, 1_700_000_000);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+
+        let addresses = account
+            .list_addresses(AddressType::P2wpkh, KeychainKind::External, 0..3)
+            .unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0].index, 0);
+        assert!(addresses[0].used);
+        assert_eq!(addresses[0].tx_count, 1);
+        assert_eq!(addresses[0].balance, 50_000);
+        assert!(!addresses[1].used);
+        assert_eq!(addresses[1].balance, 0);
+
+        account
+            .set_note_unchecked(&addresses[2].address, "Donations")
+            .unwrap();
+        let addresses = account
+            .list_addresses(AddressType::P2wpkh, KeychainKind::External, 2..3)
+            .unwrap();
+        assert_eq!(addresses[0].index, 2);
+        assert_eq!(addresses[0].label.as_deref(), Some("Donations"));
+
+        assert!(
+            account
+                .list_addresses(AddressType::P2tr, KeychainKind::External, 0..1)
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn address_gaps_past_the_gap_limit() {
+        let account = AccountFixture::default()
+            .address_types(vec![AddressType::P2wpkh])
+            .build()
+            .unwrap();
+        account.set_gap_limit(20).unwrap();
+
+        let gaps = account.address_gaps().unwrap();
+        assert_eq!(gaps.len(), 2);
+        let receive = &gaps[0];
+        assert_eq!(receive.keychain, KeychainKind::External);
+        assert_eq!(receive.revealed_index, Some(1));
+        assert_eq!(receive.last_used_index, Some(1));
+        assert_eq!(receive.unused_run, 0);
+        assert_eq!(receive.gap_limit, 20);
+        assert!(!receive.exceeds_gap_limit());
+        assert_eq!(gaps[1].revealed_index, None);
+        assert_eq!(gaps[1].unused_run, 0);
+
+        let _ = account.wallets.read().unwrap()[0]
+            .bdk_wallet
+            .lock()
+            .unwrap()
+            .reveal_addresses_to(KeychainKind::External, 61)
+            .count();
+        let receive = account.address_gaps().unwrap().remove(0);
+        assert_eq!(receive.revealed_index, Some(61));
+        assert_eq!(receive.unused_run, 60);
+        assert!(receive.exceeds_gap_limit());
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn spendable_balance_splits_locked_outputs() {
+        let account = AccountFixture::default()
+            .address_types(vec![AddressType::P2wpkh])
+            .confirmed_txs(3)
+            .unconfirmed_txs(1)
+            .amount(10_000)
+            .build()
+            .unwrap();
+        let mut confirmed: Vec<String> = account
+            .utxos()
+            .unwrap()
+            .iter()
+            .filter(|utxo| utxo.is_confirmed)
+            .map(|utxo| utxo.get_id())
+            .collect();
+        account
+            .set_do_not_spend(&confirmed.pop().unwrap(), true)
+            .unwrap();
+        let tagged = confirmed.pop().unwrap();
+        account.set_tag(&tagged, "Cold").unwrap();
+        account.set_tag_do_not_spend("Cold", true).unwrap();
+        // locked both ways, counted once
+        account.set_do_not_spend(&tagged, true).unwrap();
+        let other = confirmed.pop().unwrap();
+        account.set_tag(&other, "Cold").unwrap();
+
+        let balance = account.spendable_balance().unwrap();
+        assert_eq!(balance.total, 40_000);
+        assert_eq!(balance.locked_by_do_not_spend, 20_000);
+        assert_eq!(balance.locked_by_tag_policy, 10_000);
+        assert_eq!(balance.unconfirmed, 10_000);
+        assert_eq!(balance.spendable, 10_000);
+        assert_eq!(balance.total, account.balance().unwrap().total().to_sat());
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn balances_by_tag_and_spending_from_a_tag() {
+        let account = AccountFixture::default()
+            .address_types(vec![AddressType::P2wpkh])
+            .confirmed_txs(3)
+            .unconfirmed_txs(1)
+            .build()
+            .unwrap();
+        let utxos = account.utxos().unwrap();
+        let (unconfirmed, confirmed): (Vec<_>, Vec<_>) =
+            utxos.iter().partition(|utxo| !utxo.is_confirmed);
+        account.set_tag(&confirmed[0].get_id(), "KYC-free").unwrap();
+        account
+            .set_tag(&unconfirmed[0].get_id(), "kyc-free")
+            .unwrap();
+        account.set_tag(&confirmed[1].get_id(), "Cold").unwrap();
+
+        let balances = account.balances_by_tag().unwrap();
+        assert_eq!(balances.len(), 3);
+        assert_eq!(balances[0].tag, None);
+        assert_eq!(balances[0].confirmed, 100_000);
+        assert_eq!(balances[1].tag.as_deref(), Some("Cold"));
+        assert_eq!(balances[1].utxo_count, 1);
+        assert_eq!(balances[2].utxo_count, 2);
+        assert_eq!(balances[2].confirmed, 100_000);
+        assert_eq!(balances[2].unconfirmed, 100_000);
+
+        let params = |amount: u64| TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Bip69,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: Some("Kyc-Free".to_string()),
+            embed_memo: false,
+        };
+        let draft = account.compose_psbt(params(150_000)).unwrap();
+        let mut spent: Vec<String> = draft
+            .transaction
+            .inputs
+            .iter()
+            .map(|input| format!("{}:{}", input.tx_id, input.vout))
+            .collect();
+        spent.sort();
+        let mut tagged = vec![confirmed[0].get_id(), unconfirmed[0].get_id()];
+        tagged.sort();
+        assert_eq!(spent, tagged);
+        assert!(account.compose_psbt(params(250_000)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn signed_metadata_sync_between_paired_accounts() {
+        use ngwallet::error::error_code;
+        use ngwallet::metadata_sync::SignedMetadataSync;
+
+        let fixture = AccountFixture::default();
+        let passport = fixture.build().unwrap();
+        let envoy = fixture.build().unwrap();
+        let tx_id = passport.transactions().unwrap()[0].tx_id.clone();
+        let output_id = passport.utxos().unwrap()[0].get_id();
+        passport.set_note(&tx_id, "Salary").unwrap();
+        passport.set_tag(&output_id, "Savings").unwrap();
+        passport.set_do_not_spend(&output_id, true).unwrap();
+        passport.rename("Cold storage").unwrap();
+
+        // not paired yet
+        let error = passport.metadata_sync(1).unwrap_err();
+        assert_eq!(error_code(&error), Some(2008));
+        let key = [7; 32];
+        passport.set_metadata_sync_key(&key).unwrap();
+        envoy.set_metadata_sync_key(&key).unwrap();
+
+        let payload = passport.metadata_sync(1).unwrap().serialize().unwrap();
+        let sync = SignedMetadataSync::deserialize(&payload).unwrap();
+        envoy.apply_metadata_sync(&sync).unwrap();
+        assert_eq!(envoy.config.read().unwrap().name, "Cold storage");
         assert_eq!(
-            account.config.read().unwrap().preferred_address_type,
-            AddressType::P2tr
+            envoy.meta_storage.get_note(&tx_id).unwrap().as_deref(),
+            Some("Salary")
+        );
+        assert_eq!(
+            envoy.get_tag(&output_id).unwrap().as_deref(),
+            Some("Savings")
+        );
+        assert!(envoy.meta_storage.get_do_not_spend(&output_id).unwrap());
+
+        // replayed
+        let error = envoy.apply_metadata_sync(&sync).unwrap_err();
+        assert_eq!(error_code(&error), Some(2007));
+
+        // tampered with on the way
+        passport.rename("Hot storage").unwrap();
+        let mut tampered = passport.metadata_sync(2).unwrap();
+        let last = tampered.payload.len() - 1;
+        tampered.payload[last] ^= 1;
+        let error = envoy.apply_metadata_sync(&tampered).unwrap_err();
+        assert_eq!(error_code(&error), Some(2006));
+
+        // signed with another key, by whoever knows the descriptors
+        let other = fixture.build().unwrap();
+        other.set_metadata_sync_key(&[8; 32]).unwrap();
+        let error = other
+            .apply_metadata_sync(&passport.metadata_sync(2).unwrap())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(2006));
+        assert_eq!(envoy.config.read().unwrap().name, "Cold storage");
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn locked_accounts_are_read_only() {
+        use ngwallet::error::{ComposeError, error_code};
+
+        let account = AccountFixture::default()
+            .address_types(vec![AddressType::P2wpkh])
+            .build()
+            .unwrap();
+        let address = account
+            .reveal_addresses(AddressType::P2wpkh, KeychainKind::External, 1)
+            .unwrap()[0]
+            .address
+            .to_string();
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 10_000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Bip69,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        assert!(draft.is_finalized);
+
+        account.lock().unwrap();
+        assert!(account.is_locked());
+        assert!(!account.is_hot());
+        assert!(matches!(
+            account.compose_psbt(params.clone()),
+            Err(ComposeError::AccountLocked)
+        ));
+        let error = account
+            .sign(&draft.psbt, SignOptions::default())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(1013));
+        let error = account.sign_message(&address, "Hello").unwrap_err();
+        assert_eq!(error_code(&error), Some(1013));
+        // syncing and labels keep working
+        assert!(account.balance().unwrap().total().to_sat() > 0);
+        let tx_id = account.transactions().unwrap()[0].tx_id.clone();
+        account.set_note(&tx_id, "Locked").unwrap();
+
+        account.unlock().unwrap();
+        assert!(!account.is_locked());
+        assert!(account.is_hot());
+        assert!(account.compose_psbt(params).unwrap().is_finalized);
+        account.sign_message(&address, "Hello").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn reveal_a_batch_of_addresses() {
+        let account = AccountFixture::default()
+            .address_types(vec![AddressType::P2wpkh])
+            .build()
+            .unwrap();
+
+        let batch = account
+            .reveal_addresses(AddressType::P2wpkh, KeychainKind::External, 5)
+            .unwrap();
+        let indexes: Vec<u32> = batch.iter().map(|address| address.index).collect();
+        assert_eq!(indexes, vec![2, 3, 4, 5, 6]);
+        let listed = account
+            .list_addresses(AddressType::P2wpkh, KeychainKind::External, 2..7)
+            .unwrap();
+        for (revealed, entry) in batch.iter().zip(&listed) {
+            assert_eq!(revealed.address.to_string(), entry.address);
+            assert!(!entry.used);
+        }
+        let receive = account.address_gaps().unwrap().remove(0);
+        assert_eq!(receive.revealed_index, Some(6));
+
+        let change = account
+            .reveal_addresses(AddressType::P2wpkh, KeychainKind::Internal, 2)
+            .unwrap();
+        assert_eq!(change[1].index, 1);
+        assert!(
+            account
+                .reveal_addresses(AddressType::P2tr, KeychainKind::External, 1)
+                .is_err()
         );
     }
 
+    #[test]
     #[cfg(feature = "envoy")]
-    fn make_test_account() -> NgAccount<bdk_wallet::rusqlite::Connection> {
-        let descriptors = vec![Descriptor {
-            internal: INTERNAL_DESCRIPTOR.to_string(),
-            external: None,
-            bdk_persister: Arc::new(Mutex::new(
-                bdk_wallet::rusqlite::Connection::open_in_memory().unwrap(),
-            )),
-        }];
-        NgAccountBuilder::default()
-            .name("Test".to_string())
+    fn used_addresses_are_detected_and_skipped() {
+        let account = make_test_account();
+        let (_, update) = confirmed_receive_update(&account, 50_000, 1, 1_700_000_000);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        let paid = account
+            .list_addresses(AddressType::P2wpkh, KeychainKind::External, 0..1)
+            .unwrap()
+            .remove(0)
+            .address;
+
+        assert!(account.is_address_used(&paid).unwrap());
+        let next = account.next_address().unwrap()[0].0.address.to_string();
+        assert_ne!(next, paid);
+        assert!(!account.is_address_used(&next).unwrap());
+        assert!(account.is_address_used("not an address").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn recipients_are_validated() {
+        let account = make_test_account();
+        let (_, update) = confirmed_receive_update(&account, 50_000, 1, 1_700_000_000);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        let paid = account
+            .list_addresses(AddressType::P2wpkh, KeychainKind::External, 0..1)
+            .unwrap()
+            .remove(0)
+            .address;
+
+        let info = account
+            .validate_recipient(&format!("bitcoin:{paid}?amount=0.001"))
+            .unwrap();
+        assert_eq!(info.address, paid);
+        assert!(info.network_ok);
+        assert_eq!(info.script_type, Some(AddressType::P2wpkh));
+        assert!(info.is_own);
+        assert!(info.reused);
+        assert_eq!(info.bip21_amount, Some(100_000));
+
+        let external = account
+            .validate_recipient("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
+            .unwrap();
+        assert!(external.network_ok);
+        assert_eq!(external.script_type, Some(AddressType::P2tr));
+        assert!(!external.is_own && !external.reused);
+        assert_eq!(external.bip21_amount, None);
+
+        let mainnet = account
+            .validate_recipient("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .unwrap();
+        assert!(!mainnet.network_ok);
+        assert_eq!(mainnet.script_type, Some(AddressType::P2wpkh));
+        assert!(account.validate_recipient("bitcoin:nonsense").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn archive_and_unarchive_account() {
+        let persister = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let account = NgAccountBuilder::default()
+            .name("Dormant".to_string())
             .color("blue".to_string())
             .seed_has_passphrase(false)
             .device_serial(None)
             .date_added(None)
             .preferred_address_type(AddressType::P2wpkh)
             .index(0)
-            .descriptors(descriptors)
+            .descriptors(vec![Descriptor {
+                internal: INTERNAL_DESCRIPTOR.to_string(),
+                external: None,
+                bdk_persister: persister.clone(),
+            }])
             .date_synced(None)
             .account_path(None)
             .network(Network::Signet)
-            .id("account-id-abc".to_string())
+            .id("dormant".to_string())
             .build_in_memory()
-            .unwrap()
+            .unwrap();
+        let (_, update) = confirmed_receive_update(&account, 80_000, 1, 1_700_000_000);
+        account.apply((AddressType::P2wpkh, update)).unwrap();
+        let balance = account.balance().unwrap();
+
+        account.archive().unwrap();
+        assert!(account.is_archived());
+        assert!(account.wallets.read().unwrap().is_empty());
+        assert_eq!(account.balance().unwrap(), balance);
+        assert!(account.archive().is_err());
+
+        assert!(account.unarchive(vec![]).is_err());
+        account.unarchive(vec![persister]).unwrap();
+        assert!(!account.is_archived());
+        assert_eq!(account.transactions().unwrap().len(), 1);
+        assert_eq!(
+            account.balance().unwrap().confirmed,
+            Amount::from_sat(80_000)
+        );
     }
 
+    #[test]
     #[cfg(feature = "envoy")]
-    fn valid_payload(
-        account: &NgAccount<bdk_wallet::rusqlite::Connection>,
-        seq_offset: u64,
-    ) -> Vec<u8> {
-        let cfg = account.config.read().unwrap();
-        RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + seq_offset,
-            None,
-            vec![],
-        )
-        .serialize()
+    fn destroy_account_wipes_everything() {
+        use ngwallet::account::get_persister_file_name;
+
+        let dir = std::env::temp_dir().join("ngwallet_destroy_account");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(get_persister_file_name(INTERNAL_DESCRIPTOR, None));
+        let account = NgAccountBuilder::default()
+            .name("Doomed".to_string())
+            .color("blue".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(vec![Descriptor {
+                internal: INTERNAL_DESCRIPTOR.to_string(),
+                external: None,
+                bdk_persister: Arc::new(Mutex::new(Connection::open(&file).unwrap())),
+            }])
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("doomed".to_string())
+            .build_in_memory()
+            .unwrap();
+        account.set_note("some-tx", "Rent").unwrap();
+        account.persist().unwrap();
+        assert!(file.exists());
+
+        let dir_path = Some(dir.to_string_lossy().to_string());
+        assert!(account.destroy("wrong-id", dir_path.clone()).is_err());
+        assert!(file.exists());
+
+        let report = account.destroy("doomed", dir_path).unwrap();
+        assert_eq!(report.wallets, 1);
+        assert_eq!(report.files, vec![file.display().to_string()]);
+        assert!(report.tables.contains(&"config".to_string()));
+        assert!(report.tables.contains(&"notes".to_string()));
+        assert!(!file.exists());
+        assert!(account.wallets.read().unwrap().is_empty());
+        assert!(account.config.read().unwrap().descriptors.is_empty());
+        assert_eq!(account.meta_storage.get_note("some-tx").unwrap(), None);
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_rejects_wrong_account_id() {
+    fn restore_metadata_from_backup() {
         let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let payload = RemoteUpdate::new(
-            "wrong-account-id".to_string(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            None,
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+        let (txid, update) = confirmed_receive_update(&account, 30_000, 1, 1_700_000_000);
+        account
+            .apply((AddressType::P2wpkh, update.clone()))
+            .unwrap();
+        let output_id = format!("{txid}:0");
+        account.set_note(&txid.to_string(), "Salary").unwrap();
+        account.set_tag(&output_id, "Income").unwrap();
+        account.set_do_not_spend(&output_id, true).unwrap();
+        let backup = NgAccountBackup::deserialize(&account.get_backup_json().unwrap()).unwrap();
 
-        let err = account.update(payload).unwrap_err();
+        let restored = make_test_account();
+        restored.next_address().unwrap();
+        restored.apply((AddressType::P2wpkh, update)).unwrap();
+        restored.restore_metadata(&backup).unwrap();
+
+        let tx = restored.transactions().unwrap().remove(0);
+        assert_eq!(tx.note.as_deref(), Some("Salary"));
+        let utxo = restored.utxos().unwrap().remove(0);
+        assert_eq!(utxo.tag.as_deref(), Some("Income"));
+        assert!(utxo.do_not_spend);
         assert!(
-            err.to_string().contains("account_id mismatch"),
-            "unexpected error: {err}"
+            restored
+                .meta_storage
+                .list_tags()
+                .unwrap()
+                .contains(&"Income".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn tag_do_not_spend_policy() {
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+
+        let utxos = account.utxos().unwrap();
+        let (tagged, untagged) = (utxos[0].get_id(), utxos[1].get_id());
+        account.set_tag(&tagged, "Cold").unwrap();
+        account.set_tag_do_not_spend("cold", true).unwrap();
+
+        let is_locked = |id: &str| {
+            account
+                .utxos()
+                .unwrap()
+                .into_iter()
+                .find(|output| output.get_id() == id)
+                .unwrap()
+                .do_not_spend
+        };
+        assert!(is_locked(&tagged));
+        assert!(!is_locked(&untagged));
+
+        // Outputs tagged later on inherit the policy.
+        account.set_tag(&untagged, "Cold").unwrap();
+        assert!(is_locked(&untagged));
+
+        // Renaming the tag keeps the policy, unlocking releases every output.
+        account.remove_tag("Cold", Some("Vault")).unwrap();
+        assert!(account.get_tag_do_not_spend("vault").unwrap());
+        assert!(!account.get_tag_do_not_spend("cold").unwrap());
+        assert!(is_locked(&tagged));
+
+        account.set_tag_do_not_spend("Vault", false).unwrap();
+        assert!(!is_locked(&tagged));
+        assert!(!is_locked(&untagged));
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn full_scan_request_reports_progress() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+
+        let (_, mut request) = account
+            .full_scan_request_with_progress(
+                AddressType::P2wpkh,
+                Arc::new(move |progress| sender.lock().unwrap().send(progress).unwrap()),
+            )
+            .unwrap();
+        let spks = request.iter_spks(KeychainKind::External).take(6).count();
+        assert_eq!(spks, 6);
+
+        let reports = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(reports.len(), 6);
+        assert_eq!(reports[5].spks_scanned, 6);
+        assert_eq!(reports[5].current_index, Some(5));
+        assert_eq!(reports[5].keychain, Some(KeychainKind::External));
+        assert!(reports.iter().all(|report| !report.finished));
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn change_address_type() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let wallet = account.get_coordinator_wallet();
+        assert_eq!(
+            account.config.read().unwrap().preferred_address_type,
+            AddressType::P2tr
+        );
+        assert_eq!(wallet.address_type, AddressType::P2tr);
+
+        account
+            .set_preferred_address_type(AddressType::P2wpkh)
+            .unwrap();
+        let wallet = account.get_coordinator_wallet();
+        assert_eq!(
+            account.config.read().unwrap().preferred_address_type,
+            AddressType::P2wpkh
+        );
+        assert_eq!(wallet.address_type, AddressType::P2wpkh);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn verify_address() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+
+        // testnet segwit receive address 0
+        let result = account
+            .verify_address(
+                String::from("tb1qp3s35d5579w9mtx4vkx2lngfpnwyjx8jxhveym"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(0));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 0);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 0);
+
+        // testnet segwit receive address 5
+        let result = account
+            .verify_address(
+                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(5));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 4);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 5);
+
+        // ensure the optimization to validate repeat addresses work
+        let result = account
+            .verify_address(
+                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(5));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 0);
+        assert_eq!(result.receive_lower, 5);
+        assert_eq!(result.receive_upper, 5);
+
+        // testnet segwit receive address 0, reset for next tests
+        let result = account
+            .verify_address(
+                String::from("tb1qp3s35d5579w9mtx4vkx2lngfpnwyjx8jxhveym"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(0));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 0);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 0);
+
+        // testnet segwit receive address 30
+        let result = account
+            .verify_address(
+                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, None);
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 25);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 25);
+
+        let result = account
+            .verify_address(
+                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
+                1,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(30));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 29);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 30);
+
+        // test that we resume the search from the last verified address, and the downward search
+        // works
+        // testnet segwit receive address 5
+        let result = account
+            .verify_address(
+                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, None);
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 25);
+        assert_eq!(result.receive_lower, 6);
+        assert_eq!(result.receive_upper, 55);
+
+        let result = account
+            .verify_address(
+                String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4"),
+                1,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(5));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 25);
+        assert_eq!(result.receive_lower, 5);
+        assert_eq!(result.receive_upper, 55);
+
+        // testnet segwit change address 0
+        let result = account
+            .verify_address(
+                String::from("tb1qm2rus4zu75exrlu9rrk0l3ctktkujtetqrjd88"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, None);
+
+        // mainnet segwit receive address 0, should fail network requirement
+        assert!(
+            account
+                .verify_address(
+                    String::from("bc1q99mxpdle2pqs3pkaxcz2wmk8l0avgskyuuc6pl"),
+                    0,
+                    50,
+                )
+                .is_err()
+        );
+
+        // testnet taproot receive address 0
+        let result = account
+            .verify_address(
+                String::from("tb1phv4spu4u6uakttj3mqqcr77la4u6a28j943d3cxjh02a6ny78d0s7tupl5"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index.unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn verify_address_2() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+
+        // multi-attempt address verification (address 30 fails on first attempt, succeeds on second)
+        let result = account
+            .verify_address(
+                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, None);
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 25);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 25);
+
+        let result = account
+            .verify_address(
+                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
+                1,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(30));
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 29);
+        assert_eq!(result.receive_lower, 0);
+        assert_eq!(result.receive_upper, 30);
+
+        // change address verification
+        let result = account
+            .verify_address(
+                String::from("tb1qm2rus4zu75exrlu9rrk0l3ctktkujtetqrjd88"),
+                0,
+                50,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, None);
+
+        // network validation (mainnet address should fail on testnet account)
+        assert!(
+            account
+                .verify_address(
+                    String::from("bc1q99mxpdle2pqs3pkaxcz2wmk8l0avgskyuuc6pl"),
+                    0,
+                    50,
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn autocomplete_seedword() {
+        let suggestions = bip39::get_seedword_suggestions("fa", 3);
+        assert_eq!(suggestions, ["fabric", "face", "faculty"]);
+
+        let suggestions = bip39::get_seedword_suggestions("xy", 3);
+        assert_eq!(suggestions, Vec::<&str>::new());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn get_external_pubkey() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let descriptors = account.get_external_public_descriptors();
+        assert_eq!(descriptors[0],
+            (AddressType::P2tr, "tr([20a6ab53/86'/1'/0']tpubDC8wiq86H9ZMiscQMoG1LcvQayTKs9Ef32n4fpVV8JR2FfCDgbTE2yECxi2Bgtkb7UEUheRyeprMtWRFdMXWQq8bx6ugwdTaMp6s2bNYjSV/0/*)#taglzc2a".to_string())
+        );
+
+        assert_eq!(descriptors[1],
+                   (AddressType::P2wpkh, "wpkh([20a6ab53/84'/1'/0']tpubDC4BKZc39XVBnaTSKLw9ks63KuuEFKdRB17PZMx6GfgxaMHhV79e3zSoVT2TDe9yxwyzm1YHMS8JFNQYWoTvkLJNHa5mTyA5Gkx8NwWVkvU/0/*)#m2myh9ws".to_string())
+        );
+    }
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn test_bip329_entries_have_valid_structure() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+
+        let result = account.get_bip329_data().unwrap();
+        assert!(!result.is_empty());
+
+        for entry in &result {
+            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
+
+            // All types should have "type" and "ref"
+            assert!(json.get("type").is_some());
+            assert!(json.get("ref").is_some());
+
+            match json.get("type").unwrap().as_str().unwrap() {
+                "xpub" => {
+                    assert!(
+                        json.get("ref")
+                            .unwrap()
+                            .as_str()
+                            .unwrap()
+                            .starts_with("tpub")
+                    );
+                    assert!(json.get("label").is_some() || json.get("label").is_none());
+                }
+                "output" => {
+                    assert!(json.get("ref").unwrap().as_str().unwrap().contains(':'));
+                    assert!(json.get("spendable").is_some());
+                }
+                "tx" => {
+                    assert!(json.get("origin").is_some());
+                }
+                other => panic!("Unexpected BIP329 type: {other}"),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn test_bip329_contains_expected_testnet_xpub() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let data = account.get_bip329_data().expect("should export BIP-329");
+
+        let expected_xpub = "tpubDC8wiq86H9ZMiscQMoG1LcvQayTKs9Ef32n4fpVV8JR2FfCDgbTE2yECxi2Bgtkb7UEUheRyeprMtWRFdMXWQq8bx6ugwdTaMp6s2bNYjSV";
+
+        let maybe_entry = data.iter().find(|entry| {
+            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
+            json.get("type") == Some(&serde_json::Value::String("xpub".to_string()))
+                && json.get("ref") == Some(&serde_json::Value::String(expected_xpub.to_string()))
+        });
+
+        assert!(
+            maybe_entry.is_some(),
+            "Expected hardcoded xpub not found in BIP-329 export"
+        );
+    }
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn test_bip329_export_contains_tx_and_output_notes() {
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+
+        let txid = account.transactions().unwrap()[0].tx_id.clone();
+
+        // Simulate adding notes
+        account.set_note(&txid, "Funding tx").unwrap();
+
+        let output_id = format!("{}:{}", txid, 0); // assuming vout = 0
+
+        // Set a tag for the output
+        account.set_tag(&output_id, "important").unwrap();
+
+        let bip329_data = account.get_bip329_data().unwrap();
+
+        let has_tx_note = bip329_data.iter().any(|entry| {
+            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
+            json.get("type") == Some(&"tx".into())
+                && json.get("label") == Some(&"Funding tx".into())
+        });
+
+        let has_output_note = bip329_data.iter().any(|entry| {
+            let json: serde_json::Value = serde_json::from_str(entry).unwrap();
+            json.get("type") == Some(&"output".into())
+                && json.get("label") == Some(&"important".into())
+        });
+
+        assert!(has_tx_note, "Missing tx note in BIP-329 export");
+        assert!(has_output_note, "Missing output note in BIP-329 export");
+    }
+
+    // -------------------------------------------------------------------------
+    // SFT-7011: RemoteUpdate authenticity, replay-protection, account-binding
+    #[test]
+    #[cfg(feature = "testing")]
+    fn new_wallet_test_scan_security() {
+        let descriptors = vec![
+            Descriptor {
+                internal: FUNDED_INTERNAL_DESCRIPTOR.to_string(),
+                external: Some(FUNDED_EXTERNAL_DESCRIPTOR.to_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            },
+            Descriptor {
+                internal: FUNDED_INTERNAL_DESCRIPTOR_TR.to_string(),
+                external: Some(FUNDED_EXTERNAL_DESCRIPTOR_TR.to_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            },
+        ];
+
+        let account = NgAccountBuilder::default()
+            .name("Passport Prime".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2tr)
+            .index(0)
+            .descriptors(descriptors)
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("1234567890".to_string())
+            .build_in_memory()
+            .unwrap();
+
+        // --- Step 1: fund the account and build a valid payload ---------------
+        let updates = AccountFixture::default().updates(&account).unwrap();
+
+        let valid_payload = {
+            let cfg = account.config.read().unwrap();
+            RemoteUpdate::new(
+                cfg.id.clone(),
+                cfg.network,
+                cfg.descriptor_hash(),
+                cfg.last_remote_sequence + 1,
+                None,
+                updates,
+            )
+            .serialize()
+        };
+
+        account.update(valid_payload.clone()).unwrap();
+        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+        assert!(account.balance().unwrap().total().to_sat() > 0);
+        assert!(!account.transactions().unwrap().is_empty());
+
+        let applied = account.last_applied_update().unwrap().unwrap();
+        assert_eq!(applied.sequence, 1);
+
+        //replay the exact same payload — must be skipped
+        let balance = account.balance().unwrap();
+        account.update(valid_payload).unwrap();
+        // State must not have changed after a skipped replay.
+        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+        assert_eq!(account.balance().unwrap(), balance);
+        assert_eq!(
+            account.last_applied_update().unwrap(),
+            Some(applied.clone())
+        );
+
+        //replay the sequence with different content — must be rejected
+        let replay_payload = {
+            let cfg = account.config.read().unwrap();
+            RemoteUpdate::new(
+                cfg.id.clone(),
+                cfg.network,
+                cfg.descriptor_hash(),
+                1,
+                Some(cfg.clone()),
+                vec![],
+            )
+            .serialize()
+        };
+        let replay_err = account.update(replay_payload).unwrap_err();
+        assert!(
+            replay_err.to_string().contains("not newer"),
+            "replay should be rejected, got: {replay_err}"
+        );
+        // State must not have changed after a failed replay.
+        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+        assert_eq!(account.last_applied_update().unwrap(), Some(applied));
+
+        // wrong account_id — must be rejectedd
+        let wrong_id_payload = {
+            let cfg = account.config.read().unwrap();
+            RemoteUpdate::new(
+                "attacker-account".to_string(),
+                cfg.network,
+                cfg.descriptor_hash(),
+                cfg.last_remote_sequence + 1,
+                None,
+                vec![],
+            )
+            .serialize()
+        };
+        let wrong_id_err = account.update(wrong_id_payload).unwrap_err();
+        assert!(
+            wrong_id_err.to_string().contains("account_id mismatch"),
+            "wrong account_id should be rejected, got: {wrong_id_err}"
+        );
+
+        //  metadata trying to change preferred_address_type
+        let tampered_meta_payload = {
+            let cfg = account.config.read().unwrap();
+            let mut meta = cfg.clone();
+            // Attempt to silently flip the coordinator wallet to P2wpkh.
+            meta.preferred_address_type = AddressType::P2wpkh;
+            RemoteUpdate::new(
+                cfg.id.clone(),
+                cfg.network,
+                cfg.descriptor_hash(),
+                cfg.last_remote_sequence + 1,
+                Some(meta),
+                vec![],
+            )
+            .serialize()
+        };
+        let meta_err = account.update(tampered_meta_payload).unwrap_err();
+        assert!(
+            meta_err.to_string().contains("preferred_address_type"),
+            "preferred_address_type tamper should be rejected, got: {meta_err}"
+        );
+        // Coordinator wallet must still be P2tr.
+        assert_eq!(
+            account.config.read().unwrap().preferred_address_type,
+            AddressType::P2tr
+        );
+    }
+
+    #[cfg(feature = "envoy")]
+    fn make_test_account() -> NgAccount<bdk_wallet::rusqlite::Connection> {
+        let descriptors = vec![Descriptor {
+            internal: INTERNAL_DESCRIPTOR.to_string(),
+            external: None,
+            bdk_persister: Arc::new(Mutex::new(
+                bdk_wallet::rusqlite::Connection::open_in_memory().unwrap(),
+            )),
+        }];
+        NgAccountBuilder::default()
+            .name("Test".to_string())
+            .color("blue".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(descriptors)
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("account-id-abc".to_string())
+            .build_in_memory()
+            .unwrap()
+    }
+
+    // Update confirming a payment of `sats` to the next address of `account`
+    // in a block at `height`, built on top of the account's genesis block.
+    #[cfg(feature = "envoy")]
+    fn confirmed_receive_update(
+        account: &NgAccount<bdk_wallet::rusqlite::Connection>,
+        sats: u64,
+        height: u32,
+        time: u64,
+    ) -> (bdk_wallet::bitcoin::Txid, Update) {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{
+            BlockHash, OutPoint, Transaction, TxIn, Txid, absolute, transaction,
+        };
+        use bdk_wallet::chain::{BlockId, CheckPoint, ConfirmationBlockTime, TxUpdate};
+
+        let address = account.next_address().unwrap()[0].0.address.clone();
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([height as u8; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        let (_, genesis) = account.chain_tip();
+        let block = BlockId {
+            height,
+            hash: BlockHash::from_byte_array([height as u8; 32]),
+        };
+
+        let mut tx_update = TxUpdate::default();
+        tx_update.txs = vec![Arc::new(tx)];
+        tx_update.anchors = [(
+            ConfirmationBlockTime {
+                block_id: block,
+                confirmation_time: time,
+            },
+            txid,
+        )]
+        .into();
+        let chain = CheckPoint::from_block_ids([
+            BlockId {
+                height: 0,
+                hash: genesis,
+            },
+            block,
+        ])
+        .unwrap();
+        let update = Update {
+            tx_update,
+            chain: Some(chain),
+            ..Default::default()
+        };
+        (txid, update)
+    }
+
+    #[cfg(feature = "envoy")]
+    fn valid_payload(
+        account: &NgAccount<bdk_wallet::rusqlite::Connection>,
+        seq_offset: u64,
+    ) -> Vec<u8> {
+        let cfg = account.config.read().unwrap();
+        RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + seq_offset,
+            None,
+            vec![],
+        )
+        .serialize()
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_wrong_account_id() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let payload = RemoteUpdate::new(
+            "wrong-account-id".to_string(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            None,
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("account_id mismatch"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_wrong_network() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            Network::Bitcoin,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            None,
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("network mismatch"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_wrong_descriptor_hash() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let bad_hash = [0xdeu8; 32];
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            bad_hash,
+            cfg.last_remote_sequence + 1,
+            None,
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("descriptor hash mismatch"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_replay() {
+        let account = make_test_account();
+
+        // First update succeeds and advances the sequence to 1.
+        let first = valid_payload(&account, 1);
+        account.update(first.clone()).unwrap();
+        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+
+        // The exact payload again is skipped.
+        account.update(first.clone()).unwrap();
+
+        // Another payload with the same sequence is rejected.
+        let replay = {
+            let cfg = account.config.read().unwrap();
+            RemoteUpdate::new(
+                cfg.id.clone(),
+                cfg.network,
+                cfg.descriptor_hash(),
+                1,
+                Some(cfg.clone()),
+                vec![],
+            )
+            .serialize()
+        };
+        let err = account.update(replay).unwrap_err();
+        assert!(
+            err.to_string().contains("not newer"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn applied_updates_are_pruned() {
+        let account = make_test_account();
+        let first = valid_payload(&account, 1);
+        account.update(first.clone()).unwrap();
+        for _ in 0..MAX_APPLIED_UPDATES {
+            account.update(valid_payload(&account, 1)).unwrap();
+        }
+        assert_eq!(
+            account.meta_storage.list_applied_updates().unwrap().len(),
+            MAX_APPLIED_UPDATES
+        );
+        let last = account.last_applied_update().unwrap().unwrap();
+        assert_eq!(last.sequence, MAX_APPLIED_UPDATES as u64 + 1);
+
+        // forgotten, the first update is no longer skipped
+        let err = account.update(first).unwrap_err();
+        assert!(
+            err.to_string().contains("not newer"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_stale_sequence() {
+        let account = make_test_account();
+
+        // Advance to sequence 5.
+        account.update(valid_payload(&account, 5)).unwrap();
+
+        // Sequence 3 is now stale.
+        let cfg = account.config.read().unwrap();
+        let stale = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            3,
+            None,
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(stale).unwrap_err();
+        assert!(
+            err.to_string().contains("not newer"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_metadata_preferred_address_type_change() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let mut tampered_meta = cfg.clone();
+        tampered_meta.preferred_address_type = AddressType::P2tr;
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            Some(tampered_meta),
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("preferred_address_type"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_metadata_wrong_account_id() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let mut tampered_meta = cfg.clone();
+        tampered_meta.id = "attacker-account".to_string();
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            Some(tampered_meta),
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("metadata account_id mismatch"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_rejects_metadata_wrong_network() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let mut tampered_meta = cfg.clone();
+        tampered_meta.network = Network::Bitcoin;
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            Some(tampered_meta),
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        let err = account.update(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("metadata network mismatch"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn remote_update_allows_safe_metadata_fields() {
+        let account = make_test_account();
+        let cfg = account.config.read().unwrap();
+        let mut safe_meta = cfg.clone();
+        safe_meta.name = "Updated Name".to_string();
+        safe_meta.color = "green".to_string();
+        safe_meta.archived = true;
+        safe_meta.date_synced = Some("2026-01-01".to_string());
+        let payload = RemoteUpdate::new(
+            cfg.id.clone(),
+            cfg.network,
+            cfg.descriptor_hash(),
+            cfg.last_remote_sequence + 1,
+            Some(safe_meta),
+            vec![],
+        )
+        .serialize();
+        drop(cfg);
+
+        account.update(payload).unwrap();
+
+        let cfg = account.config.read().unwrap();
+        assert_eq!(cfg.name, "Updated Name");
+        assert_eq!(cfg.color, "green");
+        assert!(cfg.archived);
+        assert_eq!(cfg.date_synced.as_deref(), Some("2026-01-01"));
+        assert_eq!(cfg.last_remote_sequence, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn passphrase_wallet_is_a_sibling() {
+        let mnemonic = "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy";
+        let seed = Mnemonic::parse(mnemonic).unwrap().to_seed("");
+        let descriptors: Vec<_> = get_descriptors(&seed, Network::Signet, 0)
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.bip == "84")
+            .map(|d| Descriptor {
+                internal: d.change_descriptor_xprv().into_string(),
+                external: Some(d.descriptor_xprv().into_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            })
+            .collect();
+        let account = NgAccountBuilder::default()
+            .name("Main".to_string())
+            .color("red".to_string())
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(descriptors)
+            .network(Network::Signet)
+            .id("main".to_string())
+            .build_in_memory()
+            .unwrap();
+
+        let persisters = vec![Arc::new(Mutex::new(Connection::open_in_memory().unwrap()))];
+        let hidden = account
+            .with_passphrase(mnemonic, "hidden", persisters)
+            .unwrap()
+            .build_in_memory()
+            .unwrap();
+        let main_config = account.config.read().unwrap().clone();
+        let hidden_config = hidden.config.read().unwrap().clone();
+        assert!(hidden_config.id.starts_with("main-"));
+        assert!(hidden_config.seed_has_passphrase);
+        assert!(hidden_config.has_private_descriptors());
+        assert_ne!(
+            account.next_address().unwrap()[0].0.address,
+            hidden.next_address().unwrap()[0].0.address
+        );
+        assert!(main_config.is_passphrase_sibling(&hidden_config));
+        assert!(!main_config.is_passphrase_sibling(&main_config));
+
+        // another mnemonic is rejected once the seed id is known
+        let other =
+            "axis minimum please frozen option smooth alone identify term fatigue crisp entry";
+        let persisters = vec![Arc::new(Mutex::new(Connection::open_in_memory().unwrap()))];
+        assert!(account.with_passphrase(other, "", persisters).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn duress_account_is_isolated() {
+        use ngwallet::db::RedbMetaStorage;
+        use ngwallet::duress::duress_xprv;
+
+        let dir = std::env::temp_dir().join("ngwallet_duress_account");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_path = Some(dir.to_string_lossy().to_string());
+
+        let mnemonic = "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy";
+        let seed = Mnemonic::parse(mnemonic).unwrap().to_seed("");
+        let descriptors: Vec<_> = get_descriptors(&seed, Network::Signet, 0)
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.bip == "84")
+            .map(|d| Descriptor {
+                internal: d.change_descriptor_xprv().into_string(),
+                external: Some(d.descriptor_xprv().into_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            })
+            .collect();
+        let account = NgAccountBuilder::default()
+            .name("Main".to_string())
+            .color("red".to_string())
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(descriptors)
+            .network(Network::Signet)
+            .id("main".to_string())
+            .seed_id(Some(ngwallet::passphrase::seed_id(mnemonic).unwrap()))
+            .build_from_file(dir_path.clone())
+            .unwrap();
+        account.set_note("some-tx", "Rent").unwrap();
+
+        let persisters = vec![Arc::new(Mutex::new(Connection::open_in_memory().unwrap()))];
+        let duress = account
+            .duress_account(mnemonic, "", "decoy".to_string(), persisters)
+            .unwrap();
+        assert!(duress.namespace.starts_with("meta-"));
+        let decoy = duress
+            .builder
+            .name("Savings".to_string())
+            .color("blue".to_string())
+            .build_in_namespace(dir_path.clone(), &duress.namespace)
+            .unwrap();
+
+        let decoy_config = decoy.config.read().unwrap().clone();
+        assert_eq!(decoy_config.seed_id, None);
+        assert!(!decoy_config.seed_has_passphrase);
+        assert!(decoy_config.has_private_descriptors());
+        assert_ne!(
+            account.next_address().unwrap()[0].0.address,
+            decoy.next_address().unwrap()[0].0.address
+        );
+        assert_ne!(
+            decoy.meta_storage.get_note("some-tx").unwrap(),
+            Some("Rent".to_string())
+        );
+        assert_eq!(
+            account.meta_storage.get_config().unwrap().unwrap().id,
+            "main"
+        );
+
+        // the same keys every time, unrelated to the passphrase wallets
+        let xprv = duress_xprv(mnemonic, "", Network::Signet).unwrap();
+        assert_eq!(xprv, duress_xprv(mnemonic, "", Network::Signet).unwrap());
+        assert_ne!(
+            xprv,
+            duress_xprv(mnemonic, "hidden", Network::Signet).unwrap()
+        );
+
+        assert!(RedbMetaStorage::from_file_in_namespace(dir_path.clone(), "account").is_err());
+        assert!(RedbMetaStorage::from_file_in_namespace(dir_path, "../main").is_err());
+        let other =
+            "axis minimum please frozen option smooth alone identify term fatigue crisp entry";
+        let persisters = vec![Arc::new(Mutex::new(Connection::open_in_memory().unwrap()))];
+        assert!(
+            account
+                .duress_account(other, "", "decoy".to_string(), persisters)
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn multipath_descriptor_account() {
+        use ngwallet::account::get_persister_file_name;
+        use ngwallet::utils::split_multipath_descriptor;
+
+        let multipath = FUNDED_INTERNAL_DESCRIPTOR
+            .split('#')
+            .next()
+            .unwrap()
+            .replace("/0/*", "/<0;1>/*");
+        let (external, internal) = split_multipath_descriptor(&multipath).unwrap().unwrap();
+        assert_eq!(external, FUNDED_INTERNAL_DESCRIPTOR);
+        assert_eq!(internal, FUNDED_EXTERNAL_DESCRIPTOR);
+        assert_eq!(
+            get_persister_file_name(&multipath, None),
+            get_persister_file_name(&internal, Some(&external))
+        );
+
+        let account = NgAccountBuilder::default()
+            .name("Multipath".to_string())
+            .color("blue".to_string())
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(vec![Descriptor {
+                internal: multipath.clone(),
+                external: None,
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            }])
+            .network(Network::Signet)
+            .id("multipath".to_string())
+            .build_in_memory()
+            .unwrap();
+        {
+            let wallets = account.wallets.read().unwrap();
+            let wallet = wallets[0].bdk_wallet.lock().unwrap();
+            assert_eq!(
+                wallet.public_descriptor(KeychainKind::External).to_string(),
+                external
+            );
+            assert_eq!(
+                wallet.public_descriptor(KeychainKind::Internal).to_string(),
+                internal
+            );
+        }
+        assert_eq!(
+            account.config.read().unwrap().descriptors[0].internal,
+            multipath
+        );
+
+        let sparrow: serde_json::Value =
+            serde_json::from_str(&account.export_sparrow_json().unwrap()).unwrap();
+        let exported = sparrow["descriptor"].as_str().unwrap();
+        assert!(exported.contains("/<0;1>/*"));
+        assert_eq!(
+            split_multipath_descriptor(exported).unwrap(),
+            Some((external, internal))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn sign_whitelisted_enforces_the_whitelist() {
+        use ngwallet::error::{PolicyError, error_code};
+        use ngwallet::policy::{SignedWhitelist, WhitelistEntry};
+
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+        let authority = account.next_address().unwrap()[0].0.address.to_string();
+        let whitelist = |version: u64, entries: Vec<WhitelistEntry>| {
+            let message = SignedWhitelist::message_for(version, &entries);
+            SignedWhitelist {
+                authority: authority.clone(),
+                version,
+                entries,
+                signature: account
+                    .sign_message(&authority, &message)
+                    .unwrap()
+                    .signature,
+            }
+        };
+
+        let destination = "tb1qydjtc47ru9c055gv7adpfs8uzw8dhy0p52fj3y";
+        let draft = account
+            .compose_psbt(TransactionParams {
+                address: destination.to_string(),
+                amount: 1000,
+                fee_rate: FeeRateSatPerKvb(1000),
+                selected_outputs: vec![],
+                note: None,
+                tag: None,
+                do_not_spend_change: false,
+                ordering: OutputOrdering::Bip69,
+                change_address: None,
+                spend_path: SpendPath::Primary,
+                foreign_inputs: vec![],
+                fee_sharing: FeeSharing::Ours,
+                spend_from_tag: None,
+                embed_memo: false,
+            })
+            .unwrap();
+
+        // nothing is signed without a whitelist
+        let error = account
+            .sign_whitelisted(&draft.psbt, SignOptions::default())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PolicyError>(),
+            Some(PolicyError::NoWhitelist)
+        ));
+
+        let other = "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w";
+        account
+            .set_whitelist(&whitelist(
+                1,
+                vec![WhitelistEntry::Address(other.to_string())],
+            ))
+            .unwrap();
+        let psbt = Psbt::deserialize(&draft.psbt).unwrap();
+        let paid = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|output| output.value == Amount::from_sat(1000))
+            .unwrap();
+        let error = account
+            .sign_whitelisted(&draft.psbt, SignOptions::default())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(5004));
+        // the change output belongs to the account and is allowed
+        match error.downcast_ref::<PolicyError>() {
+            Some(PolicyError::NotWhitelisted(outputs)) => assert_eq!(outputs, &vec![paid]),
+            other => panic!("expected NotWhitelisted, got {other:?}"),
+        }
+
+        let allowed = whitelist(
+            2,
+            vec![
+                WhitelistEntry::Address(other.to_string()),
+                WhitelistEntry::Address(destination.to_string()),
+            ],
+        );
+        account.set_whitelist(&allowed).unwrap();
+        assert!(
+            account
+                .sign_whitelisted(&draft.psbt, SignOptions::default())
+                .is_ok()
+        );
+
+        // older lists can't be replayed, forged ones are rejected
+        let error = account.set_whitelist(&whitelist(1, vec![])).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PolicyError>(),
+            Some(PolicyError::StaleWhitelist { .. })
+        ));
+        let mut forged = allowed.clone();
+        forged.version = 3;
+        forged
+            .entries
+            .push(WhitelistEntry::Address(authority.clone()));
+        let error = account.set_whitelist(&forged).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PolicyError>(),
+            Some(PolicyError::InvalidWhitelistSignature)
+        ));
+        assert_eq!(account.whitelist().unwrap(), Some(allowed));
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn spending_policy_limits() {
+        use bdk_wallet::bitcoin::ecdsa;
+        use bdk_wallet::bitcoin::opcodes::all::OP_CHECKSIG;
+        use bdk_wallet::bitcoin::script::Builder;
+        use bdk_wallet::bitcoin::secp256k1::SecretKey;
+        use bdk_wallet::bitcoin::sighash::SighashCache;
+        use ngwallet::error::{ComposeError, PolicyViolation, error_code};
+        use ngwallet::policy::SpendingPolicy;
+        use ngwallet::signer::SignerRegistry;
+
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+        let params = |amount: u64| TransactionParams {
+            address: "tb1qydjtc47ru9c055gv7adpfs8uzw8dhy0p52fj3y".to_string(),
+            amount,
+            fee_rate: FeeRateSatPerKvb(1000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+
+        account
+            .set_spending_policy(Some(SpendingPolicy {
+                max_per_tx: Some(5000),
+                max_per_day: Some(8000),
+                cosign_above: Some(2000),
+            }))
+            .unwrap();
+        match account.compose_psbt(params(6000)) {
+            Err(ComposeError::PolicyViolation(PolicyViolation::MaxPerTransaction {
+                amount: 6000,
+                limit: 5000,
+            })) => {}
+            other => panic!("expected MaxPerTransaction, got {other:?}"),
+        }
+
+        // transfers to the account itself aren't limited
+        let own = account.next_address().unwrap()[0].0.address.to_string();
+        assert!(
+            account
+                .compose_psbt(TransactionParams {
+                    address: own,
+                    ..params(6000)
+                })
+                .is_ok()
+        );
+
+        let small = account.compose_psbt(params(1000)).unwrap();
+        assert!(account.sign(&small.psbt, SignOptions::default()).is_ok());
+
+        // a single signer can't sign above the co-signing threshold
+        let large = account.compose_psbt(params(3000)).unwrap();
+        assert!(!large.is_finalized);
+        let unsigned = Psbt::deserialize(&large.psbt).unwrap();
+        assert!(unsigned.inputs.iter().all(|input| {
+            input.partial_sigs.is_empty()
+                && input.tap_key_sig.is_none()
+                && input.final_script_witness.is_none()
+        }));
+        let error = account
+            .sign(&large.psbt, SignOptions::default())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(6002));
+        // nor with the registered signers
+        let error = account
+            .sign_with_signers(&large.psbt, &SignerRegistry::default())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(6002));
+
+        // nor with a signature by the key of a script the spent output
+        // doesn't commit to
+        let secp = Secp256k1::new();
+        let forger = SecretKey::from_slice(&[3; 32]).unwrap();
+        let forger_key = bdk_wallet::bitcoin::PublicKey::new(forger.public_key(&secp));
+        let mut forged = Psbt::deserialize(&large.psbt).unwrap();
+        let message = forged
+            .sighash_msg(0, &mut SighashCache::new(&forged.unsigned_tx), None)
+            .unwrap()
+            .to_secp_msg();
+        forged.inputs[0].witness_script = Some(
+            Builder::new()
+                .push_key(&forger_key)
+                .push_opcode(OP_CHECKSIG)
+                .into_script(),
+        );
+        forged.inputs[0].partial_sigs.insert(
+            forger_key,
+            ecdsa::Signature::sighash_all(secp.sign_ecdsa(&message, &forger)),
+        );
+        let error = account
+            .sign(&forged.serialize(), SignOptions::default())
+            .unwrap_err();
+        assert_eq!(error_code(&error), Some(6002));
+
+        // the OP_RETURN output of a proof of reserves doesn't send anything
+        let proof = account.create_reserve_proof("audit", None).unwrap();
+        assert!(account.sign_reserve_proof(&proof).is_ok());
+
+        account.set_spending_policy(None).unwrap();
+        assert!(account.spending_policy().is_none());
+        assert!(account.compose_psbt(params(6000)).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn payment_templates_compose_drafts() {
+        use ngwallet::templates::{PaymentTemplate, Schedule};
+
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+        let rent = PaymentTemplate {
+            name: "Rent".to_string(),
+            address: "tb1qydjtc47ru9c055gv7adpfs8uzw8dhy0p52fj3y".to_string(),
+            amount: 1500,
+            tag: Some("Housing".to_string()),
+            note: Some("Monthly rent".to_string()),
+            schedule: Some(Schedule::Monthly),
+        };
+        account.save_payment_template(&rent).unwrap();
+        account
+            .save_payment_template(&PaymentTemplate {
+                name: "Allowance".to_string(),
+                amount: 700,
+                note: None,
+                schedule: Some(Schedule::Weekly),
+                ..rent.clone()
+            })
+            .unwrap();
+
+        let names: Vec<_> = account
+            .payment_templates()
+            .unwrap()
+            .into_iter()
+            .map(|template| template.name)
+            .collect();
+        assert_eq!(names, vec!["Allowance", "Rent"]);
+        assert_eq!(
+            account.payment_template("Rent").unwrap(),
+            Some(rent.clone())
+        );
+
+        let draft = account
+            .compose_from_template("Rent", FeeRateSatPerKvb(1000))
+            .unwrap();
+        let psbt = Psbt::deserialize(&draft.psbt).unwrap();
+        assert!(
+            psbt.unsigned_tx
+                .output
+                .iter()
+                .any(|output| output.value == Amount::from_sat(1500))
+        );
+        assert_eq!(draft.transaction.note.as_deref(), Some("Monthly rent"));
+
+        // wrong network addresses are rejected
+        let mainnet = PaymentTemplate {
+            address: "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string(),
+            ..rent.clone()
+        };
+        assert!(account.save_payment_template(&mainnet).is_err());
+
+        account.remove_payment_template("Rent").unwrap();
+        assert!(account.payment_template("Rent").unwrap().is_none());
+        assert!(
+            account
+                .compose_from_template("Rent", FeeRateSatPerKvb(1000))
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn validate_integrity_reports_stale_config() {
+        use ngwallet::account::get_persister_file_name;
+        use ngwallet::integrity::IntegrityIssue;
+
+        let account = make_test_account();
+        let wallet_file = get_persister_file_name(INTERNAL_DESCRIPTOR, None);
+        assert!(account.validate_integrity(&[]).unwrap().is_empty());
+        assert!(
+            account
+                .validate_integrity(&[format!("/data/{wallet_file}")])
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(
+            account
+                .validate_integrity(&[wallet_file.clone(), "abcdef_.sqlite".to_string()])
+                .unwrap(),
+            vec![IntegrityIssue::OrphanPersister {
+                file_name: "abcdef_.sqlite".to_string()
+            }]
+        );
+
+        // a descriptor added to the config without loading its wallet
+        {
+            let mut config = account.config.write().unwrap();
+            let mut taproot = config.descriptors[0].clone();
+            taproot.address_type = AddressType::P2tr;
+            config.descriptors.push(taproot);
+        }
+        assert_eq!(
+            account.validate_integrity(&[]).unwrap(),
+            vec![IntegrityIssue::MissingWallet {
+                address_type: AddressType::P2tr
+            }]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn verification_state_is_reset() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+
+        // testnet segwit receive address 5
+        let address = String::from("tb1qttqxp75y56gvnrr6cy9p8ynvgyjf683ce6d9c4");
+        let result = account.verify_address(address.clone(), 0, 50).unwrap();
+        assert_eq!(result.found_index, Some(5));
+        let info = account
+            .get_address_verification_info(address.clone())
+            .unwrap();
+        assert_eq!(info.receive_start, 5);
+
+        // an unfinished search is resumed, the indexes of the first attempt
+        // are not compared again
+        let address_30 = String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz");
+        let result = account.verify_address(address_30.clone(), 0, 20).unwrap();
+        assert_eq!(result.found_index, None);
+        let result = account.verify_address(address_30.clone(), 0, 20).unwrap();
+        assert_eq!(result.found_index, None);
+        let result = account.verify_address(address_30, 2, 20).unwrap();
+        assert_eq!(result.found_index, Some(30));
+
+        account.reset_verification_state().unwrap();
+        let info = account.get_address_verification_info(address).unwrap();
+        assert_eq!(info.receive_start, 0);
+        assert_eq!(info.change_start, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn revealed_addresses_are_verified_from_the_index() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        account.wallets.write().unwrap()[1]
+            .reveal_addresses_up_to(KeychainKind::External, 40)
+            .unwrap();
+
+        // testnet segwit receive address 30, outside the first chunk
+        let result = account
+            .verify_address(
+                String::from("tb1qsqtlt0q4why79qmf9jddp53nncyrutv90wdjkz"),
+                0,
+                20,
+            )
+            .unwrap();
+        assert_eq!(result.found_index, Some(30));
+        assert_eq!(result.keychain, Some(KeychainKind::External));
+        assert_eq!(result.receive_lower, 30);
+        assert_eq!(result.receive_upper, 30);
+        assert_eq!(result.change_lower, 0);
+        assert_eq!(result.change_upper, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn labels_are_set_in_bulk() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let tx_id = "0101010101010101010101010101010101010101010101010101010101010101";
+        let output_id = format!("{tx_id}:0");
+
+        let results = account
+            .set_notes(vec![
+                (tx_id.to_string(), "Rent".to_string()),
+                ("not a txid".to_string(), "Lost".to_string()),
+            ])
+            .unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(ngwallet::error::AccountError::InvalidTxId(_))
+        ));
+        assert_eq!(
+            account.meta_storage.get_note(tx_id).unwrap(),
+            Some("Rent".to_string())
+        );
+
+        let results = account
+            .set_tags(vec![
+                (output_id.clone(), "Savings".to_string()),
+                (format!("{tx_id}:1"), "savings".to_string()),
+                (tx_id.to_string(), "Spending".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(
+            results.iter().map(Result::is_ok).collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+        assert_eq!(
+            account.meta_storage.get_tag(&output_id).unwrap(),
+            Some("Savings".to_string())
+        );
+        assert_eq!(account.list_tags().unwrap(), vec!["Savings".to_string()]);
+
+        let results = account
+            .set_do_not_spend_many(vec![(output_id.clone(), true)])
+            .unwrap();
+        assert!(results[0].is_ok());
+        assert!(account.meta_storage.get_do_not_spend(&output_id).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn tag_infos_are_kept_and_backed_up() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        account
+            .save_tag_info(&TagInfo {
+                color: Some("#f7931a".to_string()),
+                ..TagInfo::new("Savings")
+            })
+            .unwrap();
+        account
+            .reorder_tags(&["Rent".to_string(), "savings".to_string()])
+            .unwrap();
+
+        let infos = account.tag_infos().unwrap();
+        assert_eq!(
+            infos
+                .iter()
+                .map(|info| info.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Rent", "Savings"]
+        );
+        assert_eq!(infos[1].sort_order, 1);
+        assert_eq!(infos[1].color.as_deref(), Some("#f7931a"));
+
+        // renamed tags keep their info
+        account.remove_tag("SAVINGS", Some("Reserve")).unwrap();
+        assert_eq!(account.tag_info("savings").unwrap(), None);
+        let reserve = account.tag_info("reserve").unwrap().unwrap();
+        assert_eq!(reserve.name, "Reserve");
+        assert_eq!(reserve.color.as_deref(), Some("#f7931a"));
+
+        let backup = NgAccountBackup::deserialize(&account.get_backup_json().unwrap()).unwrap();
+        assert_eq!(backup.tag_infos, account.tag_infos().unwrap());
+
+        let restored = utils::tests_util::get_ng_hot_wallet();
+        restored.restore_metadata(&backup).unwrap();
+        assert_eq!(restored.tag_infos().unwrap(), backup.tag_infos);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn address_ownership_is_proven() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let addresses = account.next_address().unwrap();
+        let statement = "Withdrawal of 2024-05-01 to the account of Alice";
+        let list: Vec<String> = addresses
+            .iter()
+            .map(|(info, _)| info.address.to_string())
+            .collect();
+
+        let proof = account.prove_address_ownership(&list, statement).unwrap();
+        assert_eq!(proof.network, Network::Signet);
+        assert_eq!(proof.addresses.len(), addresses.len());
+        for ((info, address_type), proven) in addresses.iter().zip(&proof.addresses) {
+            assert_eq!(proven.address, info.address.to_string());
+            assert_eq!(proven.address_type, *address_type);
+            assert_eq!(proven.keychain, KeychainKind::External);
+            assert_eq!(proven.keys.len(), 1);
+            assert!(
+                proven.keys[0]
+                    .derivation_path
+                    .ends_with(&format!("/0/{}", info.index))
+            );
+            assert!(!proven.keys[0].xpub.contains("prv"));
+        }
+        assert!(proof.verify().unwrap().is_empty());
+
+        // the proof survives a round trip through JSON
+        let json = serde_json::to_string(&proof).unwrap();
+        let mut tampered: ngwallet::ownership::OwnershipProof =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(tampered, proof);
+        tampered.statement = "Withdrawal to the account of Mallory".to_string();
+        assert_eq!(tampered.verify().unwrap(), list);
+
+        // someone else's address
+        let foreign = "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string();
+        assert!(
+            account
+                .prove_address_ownership(&[foreign], statement)
+                .is_err()
+        );
+        assert!(account.prove_address_ownership(&[], statement).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn account_is_paired_from_ur_parts() {
+        use ngwallet::pairing::PairingPayload;
+
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let payload = account.pairing_payload().unwrap();
+        assert_eq!(payload.fingerprint, "20A6AB53");
+        assert_eq!(payload.descriptors.len(), 2);
+        for descriptor in &payload.descriptors {
+            assert_no_private_material("internal", &descriptor.internal);
+            assert_no_private_material("external", descriptor.external.as_ref().unwrap());
+        }
+
+        // scanned out of order, from a small fragment length
+        let mut parts = payload.to_ur_parts(60).unwrap();
+        assert!(parts.len() > 1);
+        assert!(PairingPayload::from_ur_parts(&parts[1..]).is_err());
+        parts.reverse();
+        let scanned = PairingPayload::from_ur_parts(&parts).unwrap();
+        assert_eq!(scanned, payload);
+        // the same key each time the account is paired
+        assert_eq!(
+            account.pairing_payload().unwrap().sync_key,
+            payload.sync_key
+        );
+        assert!(!format!("{payload:?}").contains(&format!("{:?}", payload.sync_key)));
+
+        let persisters = || {
+            vec![
+                Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+                Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            ]
+        };
+        let paired = scanned
+            .clone()
+            .account_builder("paired".to_string(), persisters())
+            .unwrap()
+            .build_in_memory()
+            .unwrap();
+        let paired_config = paired.config.read().unwrap().clone();
+        assert_eq!(paired_config.name, "Passport Prime");
+        assert_eq!(paired_config.preferred_address_type, AddressType::P2tr);
+        assert!(!paired_config.has_private_descriptors());
+        let addresses = |account: &NgAccount<Connection>| {
+            account
+                .next_address()
+                .unwrap()
+                .into_iter()
+                .map(|(info, address_type)| (info.address, address_type))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(addresses(&paired), addresses(&account));
+        paired
+            .apply_metadata_sync(&account.metadata_sync(1).unwrap())
+            .unwrap();
+
+        // descriptors of another master key
+        let mut foreign = scanned.clone();
+        foreign.fingerprint = "B032EF5F".to_string();
+        assert!(
+            foreign
+                .account_builder("foreign".to_string(), persisters())
+                .is_err()
+        );
+
+        // private descriptors
+        let mut private = scanned;
+        private.descriptors[0].internal = INTERNAL_DESCRIPTOR_2.to_string();
+        private.descriptors[0].external = None;
+        assert!(
+            private
+                .account_builder("private".to_string(), persisters())
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn multisig_is_migrated_to_a_new_quorum() {
+        use bdk_wallet::bitcoin::NetworkKind;
+        use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+        use ngwallet::config::{MultiSigDetails, MultiSigSigner};
+        use ngwallet::store::InMemoryMetaStorage;
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let signer = |seed: u8| {
+            let key = Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap();
+            let xpub = Xpub::from_priv(&secp, &key.derive_priv(&secp, &path).unwrap());
+            MultiSigSigner::new(&path, &key.fingerprint(&secp), &xpub)
+        };
+        let multisig = MultiSigDetails::new(
+            2,
+            3,
+            AddressType::P2wsh,
+            Some(NetworkKind::Test),
+            (1..=3).map(signer).collect(),
+        )
+        .unwrap();
+        let (external, _) = multisig
+            .to_descriptor(KeychainKind::External, &secp, None)
+            .unwrap();
+        let (internal, _) = multisig
+            .to_descriptor(KeychainKind::Internal, &secp, None)
+            .unwrap();
+
+        let mut account = NgAccountBuilder::default()
+            .name("Multisig".to_string())
+            .color("red".to_string())
+            .descriptors(vec![Descriptor {
+                internal: internal.to_string(),
+                external: Some(external.to_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            }])
+            .network(Network::Signet)
+            .id("old".to_string())
+            .multisig(multisig.clone())
+            .build_in_memory()
+            .unwrap();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+        let utxo = account.utxos().unwrap()[0].clone();
+        account.set_tag(&utxo.get_id(), "Savings").unwrap();
+
+        // the second key was lost
+        let lost = signer(2).get_fingerprint();
+        let rotated = multisig.rotate_signer(lost, signer(4)).unwrap();
+        assert!(
+            account
+                .plan_migration(
+                    multisig,
+                    "same".to_string(),
+                    Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+                    Arc::new(InMemoryMetaStorage::default()),
+                    FeeRateSatPerKvb(2_000),
+                )
+                .is_err()
+        );
+        let plan = account
+            .plan_migration(
+                rotated.clone(),
+                "new".to_string(),
+                Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+                Arc::new(InMemoryMetaStorage::default()),
+                FeeRateSatPerKvb(2_000),
+            )
+            .unwrap();
+
+        let new_config = plan.account.config.read().unwrap().clone();
+        assert_eq!(new_config.multisig, Some(rotated));
+        assert_eq!(new_config.migrated_from.as_deref(), Some("old"));
+        assert_eq!(
+            account.config.read().unwrap().migrated_to.as_deref(),
+            Some("new")
+        );
+
+        assert_eq!(plan.sweeps.len(), 1);
+        let sweep = &plan.sweeps[0].transaction;
+        assert_eq!(sweep.inputs.len(), 1);
+        assert_eq!(sweep.outputs.len(), 1);
+        assert!(sweep.outputs[0].amount < utxo.amount);
+        assert!(
+            plan.account
+                .owns_address(&sweep.outputs[0].address)
+                .unwrap()
+        );
+        let new_output = format!("{}:{}", sweep.tx_id, sweep.outputs[0].vout);
+        assert_eq!(
+            plan.account.get_tag(&new_output).unwrap().as_deref(),
+            Some("Savings")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn export_multisig_setup_files() {
+        use ngwallet::config::MultiSigDetails;
+
+        let config = "Name: Shared
+Policy: 2 of 2
+Derivation: m/48'/1'/0'/2'
+Format: P2WSH
+
+AB88DE89: tpubDFUc8ddWCzA8kC195Zn6UitBcBGXbPbtjktU2dk2Deprnf6sR15GAyHLQKUjAPa3gqD74g7Eea3NSqkb9FfYRZzEm2MTbCtTDZAKSHezJwb
+662A42E4: tpubDFGqX4Ge633XixPNo4uF5h6sPkv32bwJrknDmmPGMq8Tn3Pu9QgWfk5hUiDe7gvv2eaFeaHXgjiZwKvnP3AhusoaWBK3qTv8cznyHxxGoSF";
+        let (multisig, name) = MultiSigDetails::from_config(config).unwrap();
+
+        let specter: serde_json::Value =
+            serde_json::from_str(&multisig.to_specter_json(&name).unwrap()).unwrap();
+        assert_eq!(specter["label"], "Shared");
+        assert_eq!(
+            specter["descriptor"],
+            "wsh(sortedmulti(2,[ab88de89/48'/1'/0'/2']tpubDFUc8ddWCzA8kC195Zn6UitBcBGXbPbtjktU2dk2Deprnf6sR15GAyHLQKUjAPa3gqD74g7Eea3NSqkb9FfYRZzEm2MTbCtTDZAKSHezJwb/0/*,[662a42e4/48'/1'/0'/2']tpubDFGqX4Ge633XixPNo4uF5h6sPkv32bwJrknDmmPGMq8Tn3Pu9QgWfk5hUiDe7gvv2eaFeaHXgjiZwKvnP3AhusoaWBK3qTv8cznyHxxGoSF/0/*))#7atlaq2g"
+        );
+        assert_eq!(specter["devices"].as_array().unwrap().len(), 2);
+
+        let sparrow: serde_json::Value =
+            serde_json::from_str(&multisig.to_sparrow_json(&name).unwrap()).unwrap();
+        let descriptor = sparrow["descriptor"].as_str().unwrap();
+        assert!(descriptor.starts_with("wsh(sortedmulti(2,"));
+        assert_eq!(descriptor.matches("/<0;1>/*").count(), 2);
+        assert_eq!(
+            MultiSigDetails::from_descriptor(descriptor).unwrap().0,
+            multisig
+        );
+
+        // Keystone reads the Coldcard format behind its own header
+        let keystone = multisig.to_keystone_config(&name);
+        assert!(keystone.starts_with("# Keystone Multisig setup file"));
+        assert_eq!(
+            MultiSigDetails::from_config(&keystone).unwrap(),
+            (multisig.clone(), name.clone())
+        );
+        let parts = multisig.to_keystone_ur_parts(&name, 100).unwrap();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.starts_with("ur:bytes/")));
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn cosigner_signs_health_check_challenge() {
+        use bdk_wallet::bitcoin::NetworkKind;
+        use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+        use ngwallet::config::{MultiSigDetails, MultiSigSigner};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let keys: Vec<Xpriv> = (1..=3)
+            .map(|seed| Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap())
+            .collect();
+        let multisig = MultiSigDetails::new(
+            2,
+            3,
+            AddressType::P2wsh,
+            Some(NetworkKind::Test),
+            keys.iter()
+                .map(|key| {
+                    let xpub = Xpub::from_priv(&secp, &key.derive_priv(&secp, &path).unwrap());
+                    MultiSigSigner::new(&path, &key.fingerprint(&secp), &xpub)
+                })
+                .collect(),
+        )
+        .unwrap();
+        let (external, _) = multisig
+            .to_descriptor(KeychainKind::External, &secp, None)
+            .unwrap();
+        let (internal, _) = multisig
+            .to_descriptor(KeychainKind::Internal, &secp, None)
+            .unwrap();
+        let account = NgAccountBuilder::default()
+            .name("Multisig".to_string())
+            .color("red".to_string())
+            .descriptors(vec![Descriptor {
+                internal: internal.to_string(),
+                external: Some(external.to_string()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            }])
+            .network(Network::Signet)
+            .id("multisig".to_string())
+            .multisig(multisig)
+            .build_in_memory()
+            .unwrap();
+
+        let fingerprint = keys[1].fingerprint(&secp).to_string();
+        let challenge = "Health check 2026-10-16";
+        let psbt = account.cosigner_challenge(&fingerprint, challenge).unwrap();
+        let signed_by = |key: &Xpriv| {
+            let mut psbt = Psbt::deserialize(&psbt).unwrap();
+            psbt.sign(key, &secp).unwrap();
+            psbt.serialize()
+        };
+
+        assert!(
+            account
+                .verify_cosigner(&fingerprint, challenge, &signed_by(&keys[1]))
+                .unwrap()
+        );
+        // unsigned, or signed by another cosigner
+        assert!(
+            !account
+                .verify_cosigner(&fingerprint, challenge, &psbt)
+                .unwrap()
+        );
+        assert!(
+            !account
+                .verify_cosigner(&fingerprint, challenge, &signed_by(&keys[0]))
+                .unwrap()
+        );
+        // an old signature doesn't answer a new challenge
+        assert!(
+            !account
+                .verify_cosigner(
+                    &fingerprint,
+                    "Health check 2026-10-17",
+                    &signed_by(&keys[1])
+                )
+                .unwrap()
+        );
+
+        let stranger = Xpriv::new_master(Network::Testnet, &[4; 32]).unwrap();
+        assert!(
+            account
+                .cosigner_challenge(&stranger.fingerprint(&secp).to_string(), challenge)
+                .is_err()
         );
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_rejects_wrong_network() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            Network::Bitcoin,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            None,
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+    fn preferred_address_type_is_synced_first() {
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let preferred = account.config.read().unwrap().preferred_address_type;
+        let order = account.sync_order().unwrap();
+        assert_eq!(order.len(), 2);
+        assert_eq!(order[0], preferred);
 
-        let err = account.update(payload).unwrap_err();
-        assert!(
-            err.to_string().contains("network mismatch"),
-            "unexpected error: {err}"
+        // the preferred address type can't be deferred
+        account
+            .set_deferred_address_types(vec![preferred, order[1]])
+            .unwrap();
+        assert_eq!(account.sync_order().unwrap(), order);
+        assert_eq!(
+            account.config.read().unwrap().deferred_address_types,
+            vec![preferred, order[1]]
         );
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_rejects_wrong_descriptor_hash() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let bad_hash = [0xdeu8; 32];
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            bad_hash,
-            cfg.last_remote_sequence + 1,
-            None,
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+    fn sync_plan_lists_stale_wallets() {
+        use std::time::Duration;
 
-        let err = account.update(payload).unwrap_err();
+        let account = utils::tests_util::get_ng_hot_wallet();
+        let order = account.sync_order().unwrap();
+        let plan = account.sync_plan(Duration::from_secs(3600)).unwrap();
+        assert_eq!(plan.stale(), order);
         assert!(
-            err.to_string().contains("descriptor hash mismatch"),
-            "unexpected error: {err}"
+            plan.wallets
+                .iter()
+                .all(|wallet| wallet.checkpoint.is_none())
         );
+
+        account.apply((order[0], Update::default())).unwrap();
+        let checkpoint = account.sync_checkpoint(order[0]).unwrap().unwrap();
+        assert_eq!(checkpoint.height, account.chain_tip().0);
+
+        let plan = account.sync_plan(Duration::from_secs(3600)).unwrap();
+        assert_eq!(plan.stale(), vec![order[1]]);
+        assert!(!plan.is_fresh());
+        // everything is stale without a time to live
+        let plan = account.sync_plan(Duration::ZERO).unwrap();
+        assert_eq!(plan.stale(), order);
     }
 
     #[test]
-    #[cfg(feature = "envoy")]
-    fn remote_update_rejects_replay() {
-        let account = make_test_account();
+    #[cfg(feature = "testing")]
+    fn wallets_are_opened_lazily() {
+        use ngwallet::store::{InMemoryMetaStorage, MetaStorage};
 
-        // First update succeeds and advances the sequence to 1.
-        let first = valid_payload(&account, 1);
-        account.update(first).unwrap();
-        assert_eq!(account.config.read().unwrap().last_remote_sequence, 1);
+        let seed = Mnemonic::parse(
+            "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy",
+        )
+        .unwrap()
+        .to_seed("");
+        let descriptors: Vec<Descriptor<Connection>> = get_descriptors(&seed, Network::Signet, 0)
+            .unwrap()
+            .into_iter()
+            .filter(|d| ["49", "84", "86"].contains(&d.bip.as_str()))
+            .map(|d| Descriptor {
+                internal: d.descriptor_xpub(),
+                external: Some(d.change_descriptor_xpub()),
+                bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+            })
+            .collect();
+        let reopen = || {
+            descriptors
+                .iter()
+                .map(|d| Descriptor {
+                    internal: d.internal.clone(),
+                    external: d.external.clone(),
+                    bdk_persister: d.bdk_persister.clone(),
+                })
+                .collect::<Vec<_>>()
+        };
 
-        // Replaying the same sequence is rejected.
-        let replay = valid_payload(&account, 0); // 1 + 0 = sequence 1 again
-        let err = account.update(replay).unwrap_err();
-        assert!(
-            err.to_string().contains("not newer"),
-            "unexpected error: {err}"
-        );
+        let meta: Arc<dyn MetaStorage> = Arc::new(InMemoryMetaStorage::default());
+        let account = NgAccountBuilder::default()
+            .name("Lazy".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(reopen())
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("lazy".to_string())
+            .build(meta.clone())
+            .unwrap();
+        for update in AccountFixture::default().updates(&account).unwrap() {
+            account.apply(update).unwrap();
+        }
+        account.persist().unwrap();
+        let balance = account.balance().unwrap();
+        drop(account);
+
+        let account = NgAccount::open_account_lazy(reopen(), meta, 1).unwrap();
+        assert_eq!(account.wallets.read().unwrap().len(), 1);
+        assert_eq!(account.address_types().unwrap().len(), 3);
+        assert!(account.is_wallet_open(AddressType::P2wpkh).unwrap());
+        assert!(!account.is_wallet_open(AddressType::P2tr).unwrap());
+
+        // calls on one address type open its wallet only
+        account.sync_request(AddressType::P2tr).unwrap();
+        assert!(account.is_wallet_open(AddressType::P2tr).unwrap());
+        assert!(!account.is_wallet_open(AddressType::P2sh).unwrap());
+        // beyond the capacity the least recently used wallet is closed
+        account.open_wallet(AddressType::P2sh).unwrap();
+        assert!(account.is_wallet_open(AddressType::P2sh).unwrap());
+        assert!(!account.is_wallet_open(AddressType::P2tr).unwrap());
+
+        // calls over the whole account open them all
+        assert_eq!(account.balance().unwrap(), balance);
+        assert_eq!(account.wallets.read().unwrap().len(), 3);
+        assert_eq!(account.transactions().unwrap().len(), 6);
+        // and they stay open until a single one is opened again
+        account.open_wallet(AddressType::P2tr).unwrap();
+        assert_eq!(account.wallets.read().unwrap().len(), 2);
+        assert!(!account.is_wallet_open(AddressType::P2sh).unwrap());
     }
 
     #[test]
-    #[cfg(feature = "envoy")]
-    fn remote_update_rejects_stale_sequence() {
-        let account = make_test_account();
-
-        // Advance to sequence 5.
-        account.update(valid_payload(&account, 5)).unwrap();
+    #[cfg(feature = "testing")]
+    fn snapshot_is_stored_on_persist() {
+        use ngwallet::db::RedbMetaStorage;
+        use ngwallet::snapshot::AccountSnapshot;
+        use ngwallet::store::MetaStorage;
 
-        // Sequence 3 is now stale.
-        let cfg = account.config.read().unwrap();
-        let stale = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            3,
-            None,
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+        let account = AccountFixture::default()
+            .unconfirmed_txs(1)
+            .build()
+            .unwrap();
+        let snapshot = account.stored_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.account_id, "fixture");
+        assert_eq!(snapshot.balance, account.balance().unwrap().into());
+        assert_eq!(snapshot.last_tx_summaries.len(), 6);
+        assert_eq!(
+            snapshot
+                .last_tx_summaries
+                .iter()
+                .filter(|tx| !tx.is_confirmed)
+                .count(),
+            2
+        );
+        assert!(
+            snapshot
+                .last_tx_summaries
+                .iter()
+                .all(|tx| tx.amount == 100_000 && tx.fee == FIXTURE_FEE)
+        );
 
-        let err = account.update(stale).unwrap_err();
+        // the receive address is not revealed
+        let next = account.next_address().unwrap();
         assert!(
-            err.to_string().contains("not newer"),
-            "unexpected error: {err}"
+            next.iter()
+                .any(|(address, _)| address.address.to_string() == snapshot.next_receive_address)
         );
+
+        // stored in the metadata database, readable without the wallets
+        let dir = std::env::temp_dir().join("ngwallet_snapshot");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_path = Some(dir.to_string_lossy().to_string());
+        let storage = RedbMetaStorage::from_file(dir_path.clone()).unwrap();
+        assert_eq!(AccountSnapshot::from_storage(&storage).unwrap(), None);
+        storage
+            .set_account_snapshot(&serde_json::to_string(&snapshot).unwrap())
+            .unwrap();
+        drop(storage);
+        assert_eq!(AccountSnapshot::load(dir_path).unwrap(), Some(snapshot));
     }
 
     #[test]
-    #[cfg(feature = "envoy")]
-    fn remote_update_rejects_metadata_preferred_address_type_change() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let mut tampered_meta = cfg.clone();
-        tampered_meta.preferred_address_type = AddressType::P2tr;
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            Some(tampered_meta),
-            vec![],
+    #[cfg(feature = "testing")]
+    fn rotated_descriptor_replaces_wallet() {
+        use ngwallet::rotation::DescriptorStatus;
+        use ngwallet::store::MetaStorage;
+
+        let mnemonic = Mnemonic::parse(
+            "addict hold sand engage ostrich cousin swarm away puzzle huge rookie fancy",
         )
-        .serialize();
-        drop(cfg);
+        .unwrap();
+        let watch_only = |passphrase: &str| {
+            get_descriptors(&mnemonic.to_seed(passphrase), Network::Signet, 0)
+                .unwrap()
+                .into_iter()
+                .filter(|d| ["84", "86"].contains(&d.bip.as_str()))
+                .map(|d| Descriptor::<Connection> {
+                    internal: d.descriptor_xpub(),
+                    external: Some(d.change_descriptor_xpub()),
+                    bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+                })
+                .collect::<Vec<_>>()
+        };
+        let account = NgAccountBuilder::default()
+            .name("Passport".to_string())
+            .color("red".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(watch_only(""))
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Signet)
+            .id("passport".to_string())
+            .build_in_memory()
+            .unwrap();
+        let fixture = AccountFixture::default();
+        for update in fixture.updates(&account).unwrap() {
+            account.apply(update).unwrap();
+        }
+        let tx_id = account.transactions().unwrap()[0].tx_id.clone();
+        account.set_note(&tx_id, "Before the reset").unwrap();
+        assert!(
+            account
+                .sync_checkpoint(AddressType::P2wpkh)
+                .unwrap()
+                .is_some()
+        );
 
-        let err = account.update(payload).unwrap_err();
+        let old = watch_only("").remove(0);
+        let new = watch_only("reset").remove(0);
+        assert_eq!(
+            account.descriptor_status(&old.internal).unwrap(),
+            DescriptorStatus::Unchanged
+        );
+        assert_eq!(
+            account.descriptor_status(&new.internal).unwrap(),
+            DescriptorStatus::Rotated
+        );
         assert!(
-            err.to_string().contains("preferred_address_type"),
-            "unexpected error: {err}"
+            account
+                .replace_descriptor(
+                    AddressType::P2tr,
+                    new.internal.clone(),
+                    new.external.clone(),
+                    new.bdk_persister.clone(),
+                )
+                .is_err()
+        );
+
+        account
+            .replace_descriptor(
+                AddressType::P2wpkh,
+                new.internal.clone(),
+                new.external.clone(),
+                new.bdk_persister.clone(),
+            )
+            .unwrap();
+        assert_eq!(
+            account.descriptor_status(&new.internal).unwrap(),
+            DescriptorStatus::Unchanged
+        );
+        // only the taproot wallet keeps its funds until the new one is scanned
+        assert_eq!(
+            account.balance().unwrap().total().to_sat(),
+            fixture.wallet_balance()
+        );
+        assert!(
+            account
+                .sync_checkpoint(AddressType::P2wpkh)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            account.meta_storage.get_note(&tx_id).unwrap(),
+            Some("Before the reset".to_string())
         );
+
+        let config = account.meta_storage.get_config().unwrap().unwrap();
+        assert_eq!(config.descriptors[0].internal, new.internal);
+        assert_eq!(config.retired_descriptors.len(), 1);
+        assert_eq!(config.retired_descriptors[0].internal, old.internal);
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_rejects_metadata_wrong_account_id() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let mut tampered_meta = cfg.clone();
-        tampered_meta.id = "attacker-account".to_string();
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            Some(tampered_meta),
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+    fn tx_size_is_estimated_without_composing() {
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_to_wallet(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Bip69,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
 
-        let err = account.update(payload).unwrap_err();
+        let estimate = account.estimate_tx_size(&params).unwrap();
+        assert_eq!(estimate.per_input_breakdown.len(), 1);
+        assert!(estimate.has_change);
+        assert_eq!(estimate.vbytes, estimate.weight.div_ceil(4));
+
+        // within a few sats of the fee of the composed transaction
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        let fee = estimate.fee(params.fee_rate);
         assert!(
-            err.to_string().contains("metadata account_id mismatch"),
-            "unexpected error: {err}"
+            fee.abs_diff(draft.transaction.fee) <= 4,
+            "{fee} vs {}",
+            draft.transaction.fee
         );
+
+        let too_much = TransactionParams {
+            amount: 100_000_000,
+            ..params
+        };
+        assert!(account.estimate_tx_size(&too_much).is_err());
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_rejects_metadata_wrong_network() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let mut tampered_meta = cfg.clone();
-        tampered_meta.network = Network::Bitcoin;
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            Some(tampered_meta),
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+    fn transactions_have_a_type() {
+        use ngwallet::transaction::TxType;
 
-        let err = account.update(payload).unwrap_err();
-        assert!(
-            err.to_string().contains("metadata network mismatch"),
-            "unexpected error: {err}"
-        );
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_wallet_with_unconfirmed(&mut account);
+
+        let transactions = account.transactions().unwrap();
+        let (unconfirmed, confirmed): (Vec<_>, Vec<_>) =
+            transactions.iter().partition(|tx| tx.confirmations == 0);
+        // the unconfirmed transaction pays 800 sats outside of the account
+        assert_eq!(unconfirmed.len(), 1);
+        assert_eq!(unconfirmed[0].tx_type, TxType::Outgoing);
+        assert!(confirmed.iter().all(|tx| tx.tx_type == TxType::Incoming));
     }
 
     #[test]
     #[cfg(feature = "envoy")]
-    fn remote_update_allows_safe_metadata_fields() {
-        let account = make_test_account();
-        let cfg = account.config.read().unwrap();
-        let mut safe_meta = cfg.clone();
-        safe_meta.name = "Updated Name".to_string();
-        safe_meta.color = "green".to_string();
-        safe_meta.archived = true;
-        safe_meta.date_synced = Some("2026-01-01".to_string());
-        let payload = RemoteUpdate::new(
-            cfg.id.clone(),
-            cfg.network,
-            cfg.descriptor_hash(),
-            cfg.last_remote_sequence + 1,
-            Some(safe_meta),
-            vec![],
-        )
-        .serialize();
-        drop(cfg);
+    fn inputs_carry_their_previous_output() {
+        use ngwallet::transaction::KeyChain;
 
-        account.update(payload).unwrap();
+        let mut account = utils::tests_util::get_ng_hot_wallet();
+        utils::tests_util::add_funds_wallet_with_unconfirmed(&mut account);
 
-        let cfg = account.config.read().unwrap();
-        assert_eq!(cfg.name, "Updated Name");
-        assert_eq!(cfg.color, "green");
-        assert!(cfg.archived);
-        assert_eq!(cfg.date_synced.as_deref(), Some("2026-01-01"));
-        assert_eq!(cfg.last_remote_sequence, 1);
+        let spend = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.confirmations == 0)
+            .unwrap();
+        let funding = account
+            .get_coordinator_wallet()
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.tx_id == spend.inputs[0].tx_id)
+            .unwrap();
+        let input = &spend.inputs[0];
+        assert!(input.is_mine);
+        assert_eq!(input.keychain, Some(KeyChain::External));
+        assert_eq!(input.amount, 76_000);
+        assert_eq!(
+            input.address.as_deref(),
+            Some(funding.outputs[input.vout as usize].address.as_str())
+        );
     }
 }
@@ -0,0 +1,127 @@
+//! PSBT validation against the vectors in `tests/psbt_vectors`.
+//! After a deliberate change of the validation, run the tests with
+//! `NGWALLET_BLESS_PSBT_VECTORS=1` to store the new outcomes as expected.
+
+#[cfg(test)]
+#[cfg(feature = "std")]
+mod tests {
+    use bdk_wallet::bitcoin::Psbt;
+    use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+    use bdk_wallet::descriptor::ExtendedDescriptor;
+    use ngwallet::psbt::vectors::{DetailsSnapshot, Outcome, TestVector};
+    use ngwallet::psbt::{OutputKind, validate_watch_only};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    fn vector_files() -> Vec<PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/psbt_vectors");
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn read_vector(file: &Path) -> TestVector {
+        serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn psbt_vectors() {
+        let secp = Secp256k1::new();
+        let bless = std::env::var_os("NGWALLET_BLESS_PSBT_VECTORS").is_some();
+        let files = vector_files();
+        assert!(!files.is_empty());
+
+        let mut failures = vec![];
+        for file in files {
+            let mut vector = read_vector(&file);
+            let outcome = vector.run(&secp).unwrap();
+            if outcome == vector.expected {
+                continue;
+            }
+            if bless {
+                vector.expected = outcome;
+                let json = serde_json::to_string_pretty(&vector).unwrap();
+                fs::write(&file, json + "\n").unwrap();
+            } else {
+                failures.push(format!(
+                    "{}: expected {:?}, got {:?}",
+                    vector.name, vector.expected, outcome
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn wrong_snapshot_fails() {
+        let secp = Secp256k1::new();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/psbt_vectors");
+        let mut vector = read_vector(&dir.join("p2wpkh_valid.json"));
+        assert!(vector.passes(&secp).unwrap());
+
+        let Outcome::Details(details) = &mut vector.expected else {
+            panic!("p2wpkh_valid should validate");
+        };
+        details.fee += 1;
+        assert!(!vector.passes(&secp).unwrap());
+
+        vector.psbt = "not a psbt".to_string();
+        assert!(vector.run(&secp).is_err());
+    }
+
+    #[test]
+    fn watch_only_validation_matches_the_master_key() {
+        let secp = Secp256k1::new();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/psbt_vectors");
+        for name in ["p2wpkh_valid", "p2tr_key_path", "p2wsh_multisig"] {
+            let vector = read_vector(&dir.join(format!("{name}.json")));
+            let Outcome::Details(expected) = &vector.expected else {
+                panic!("{name} should validate");
+            };
+            // the account descriptors the PSBT spends from
+            let descriptors: Vec<ExtendedDescriptor> = expected
+                .descriptors
+                .iter()
+                .map(|descriptor| ExtendedDescriptor::from_str(descriptor).unwrap())
+                .collect();
+            let psbt = Psbt::from_str(&vector.psbt).unwrap();
+
+            let details = validate_watch_only(&secp, &descriptors, &psbt, vector.network).unwrap();
+            assert_eq!(&DetailsSnapshot::from(&details), expected, "{name}");
+        }
+
+        // the change key doesn't derive from the account xpub
+        let valid = read_vector(&dir.join("p2wpkh_valid.json"));
+        let Outcome::Details(expected) = &valid.expected else {
+            panic!("p2wpkh_valid should validate");
+        };
+        let descriptors = vec![ExtendedDescriptor::from_str(&expected.descriptors[0]).unwrap()];
+        let fraudulent = read_vector(&dir.join("fraudulent_change_key.json"));
+        let psbt = Psbt::from_str(&fraudulent.psbt).unwrap();
+        let result = validate_watch_only(&secp, &descriptors, &psbt, fraudulent.network);
+        assert_eq!(result.unwrap_err().to_string(), "fraudulent key");
+
+        // keys outside of the account can't be checked, so the change on a
+        // BIP-0044 path is paid to someone else
+        let suspicious = read_vector(&dir.join("suspicious_change_path.json"));
+        let psbt = Psbt::from_str(&suspicious.psbt).unwrap();
+        let details = validate_watch_only(&secp, &descriptors, &psbt, suspicious.network).unwrap();
+        assert_eq!(
+            details.outputs[1].kind,
+            OutputKind::External(details.outputs[1].to_address().unwrap().clone())
+        );
+
+        // none of the inputs are ours
+        let multisig = read_vector(&dir.join("p2wsh_multisig.json"));
+        let psbt = Psbt::from_str(&multisig.psbt).unwrap();
+        assert!(validate_watch_only(&secp, &descriptors, &psbt, multisig.network).is_err());
+    }
+}
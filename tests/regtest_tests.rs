@@ -0,0 +1,171 @@
+//! Tests against a local regtest node, see `ngwallet::regtest`.
+//! Ignored by default, run them with `just test-regtest` once bitcoind and
+//! electrs are up.
+
+mod utils;
+
+#[cfg(test)]
+#[cfg(feature = "testing")]
+mod tests {
+    use crate::utils;
+    use bdk_wallet::KeychainKind;
+    use bdk_wallet::bitcoin::{Amount, Network};
+    use bdk_wallet::rusqlite::Connection;
+    use ngwallet::account::{Descriptor, NgAccount};
+    use ngwallet::config::{AddressType, NgAccountBuilder};
+    use ngwallet::regtest::RegtestNode;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    const INTERNAL_DESCRIPTOR: &str = "wpkh(tprv8ZgxMBicQKsPeLx4U7UmbcYU5VhS4BRxv86o1gNqNqxEEJL47F9ZZhvBi1EVbKPmmFYnTEZ6uArarK6zZyrZf7mSyWZRAuNKQp4dHfxBdMM/84'/1'/0'/1/*)";
+    const EXTERNAL_DESCRIPTOR: &str = "wpkh(tprv8ZgxMBicQKsPeLx4U7UmbcYU5VhS4BRxv86o1gNqNqxEEJL47F9ZZhvBi1EVbKPmmFYnTEZ6uArarK6zZyrZf7mSyWZRAuNKQp4dHfxBdMM/84'/1'/0'/0/*)";
+
+    fn make_account(id: &str) -> NgAccount<Connection> {
+        let descriptors = vec![Descriptor {
+            internal: INTERNAL_DESCRIPTOR.to_string(),
+            external: Some(EXTERNAL_DESCRIPTOR.to_string()),
+            bdk_persister: Arc::new(Mutex::new(Connection::open_in_memory().unwrap())),
+        }];
+        NgAccountBuilder::default()
+            .name("Regtest".to_string())
+            .color("blue".to_string())
+            .seed_has_passphrase(false)
+            .device_serial(None)
+            .date_added(None)
+            .preferred_address_type(AddressType::P2wpkh)
+            .index(0)
+            .descriptors(descriptors)
+            .date_synced(None)
+            .account_path(None)
+            .network(Network::Regtest)
+            .id(id.to_string())
+            .build_in_memory()
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a local regtest node"]
+    fn funded_account_is_synced() {
+        let node = RegtestNode::from_env().unwrap();
+        let account = make_account("regtest-funded");
+        node.sync(&account).unwrap();
+        let before = account.balance().unwrap().total();
+
+        let amount = Amount::from_sat(1_000_000);
+        let txid = node.fund(&account, amount).unwrap();
+        node.sync(&account).unwrap();
+        let balance = account.balance().unwrap();
+        assert_eq!(balance.total(), before + amount);
+        let tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.tx_id == txid.to_string())
+            .unwrap();
+        assert!(!tx.is_confirmed);
+
+        node.mine_blocks(1).unwrap();
+        node.sync(&account).unwrap();
+        let tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.tx_id == txid.to_string())
+            .unwrap();
+        assert!(tx.is_confirmed);
+        assert_eq!(tx.amount, amount.to_sat() as i64);
+        assert_eq!(account.balance().unwrap().confirmed, balance.total());
+    }
+
+    #[test]
+    #[ignore = "needs a local regtest node"]
+    fn blocks_are_mined() {
+        let node = RegtestNode::from_env().unwrap();
+        let height = node.height().unwrap();
+        let hashes = node.mine_blocks(3).unwrap();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(node.height().unwrap(), height + 3);
+    }
+
+    #[test]
+    #[ignore = "needs a local regtest node"]
+    fn only_regtest_accounts_are_funded() {
+        let node = RegtestNode::from_env().unwrap();
+        let account = utils::tests_util::get_ng_hot_wallet();
+        assert!(node.fund(&account, Amount::from_sat(10_000)).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a local regtest node"]
+    fn indices_are_repaired_past_the_gap() {
+        let node = RegtestNode::from_env().unwrap();
+        let account = make_account("regtest-repair");
+        node.sync(&account).unwrap();
+
+        // paid far past the gap limit, as if the backup's indexes were stale
+        let index = 100;
+        let address = account
+            .get_coordinator_wallet()
+            .bdk_wallet
+            .lock()
+            .unwrap()
+            .peek_address(KeychainKind::External, index)
+            .address;
+        node.call(
+            "sendtoaddress",
+            json!({
+                "address": address.to_string(),
+                "amount": "0.001",
+                "fee_rate": 2,
+            }),
+        )
+        .unwrap();
+        node.mine_blocks(1).unwrap();
+        node.sync(&account).unwrap();
+        assert!(
+            account
+                .get_derivation_index()
+                .iter()
+                .all(|(_, _, revealed)| *revealed < index)
+        );
+
+        let repair = account
+            .repair_indices(60, node.electrum_server(), None, None)
+            .unwrap();
+        assert!(repair.new_transactions >= 1);
+        assert!(repair.new_utxos >= 1);
+        assert!(account.get_derivation_index().contains(&(
+            AddressType::P2wpkh,
+            KeychainKind::External,
+            index
+        )));
+    }
+
+    #[test]
+    #[ignore = "needs a local regtest node"]
+    fn foreign_inputs_are_fetched() {
+        let node = RegtestNode::from_env().unwrap();
+        let account = make_account("regtest-inputs");
+        let txid = node.fund(&account, Amount::from_sat(500_000)).unwrap();
+        node.mine_blocks(1).unwrap();
+        node.sync(&account).unwrap();
+
+        account
+            .fetch_foreign_inputs(node.electrum_server(), None, None)
+            .unwrap();
+        let tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.tx_id == txid.to_string())
+            .unwrap();
+        // the node's coins funded the account
+        assert!(!tx.inputs.is_empty());
+        for input in &tx.inputs {
+            assert!(!input.is_mine);
+            assert_eq!(input.keychain, None);
+            assert!(input.address.is_some());
+            assert!(input.amount > 0);
+        }
+    }
+}
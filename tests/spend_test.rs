@@ -5,13 +5,16 @@ mod utils;
 mod psbt_security_tests {
     use bdk_wallet::bitcoin::absolute::LockTime;
     use bdk_wallet::bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv, Xpub};
+    use bdk_wallet::bitcoin::opcodes::all::OP_CHECKSIG;
     use bdk_wallet::bitcoin::psbt;
     use bdk_wallet::bitcoin::psbt::Psbt;
+    use bdk_wallet::bitcoin::script::Builder;
     use bdk_wallet::bitcoin::secp256k1::{All, PublicKey, Secp256k1};
+    use bdk_wallet::bitcoin::taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo};
     use bdk_wallet::bitcoin::transaction::Version;
     use bdk_wallet::bitcoin::{
-        Address, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction,
-        TxIn, TxOut, Txid, Witness,
+        Address, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, TapLeafHash,
+        Transaction, TxIn, TxOut, Txid, Witness,
     };
     use ngwallet::psbt::{Error, validate};
     use std::str::FromStr;
@@ -360,18 +363,152 @@ mod psbt_security_tests {
             "P2SH-P2WSH witness_script hash mismatch must be rejected"
         );
     }
+
+    // P2TR script path spends
+
+    /// A taproot input spending `<leaf key> OP_CHECKSIG` from a tree built on
+    /// `internal path`, the leaf key being derived from `leaf path`.
+    fn p2tr_script_path_input(
+        secp: &Secp256k1<All>,
+        internal_path: &str,
+        leaf_path: &str,
+    ) -> (psbt::Input, TaprootSpendInfo) {
+        let (internal_pk, internal_path, fp) = derive_test_key(secp, internal_path);
+        let (leaf_pk, leaf_path, _) = derive_test_key(secp, leaf_path);
+        let internal_key = internal_pk.x_only_public_key().0;
+        let leaf_key = leaf_pk.x_only_public_key().0;
+
+        let script = Builder::new()
+            .push_x_only_key(&leaf_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, script.clone())
+            .unwrap()
+            .finalize(secp, internal_key)
+            .unwrap();
+        let leaf = (script, LeafVersion::TapScript);
+        let control_block = spend_info.control_block(&leaf).unwrap();
+        let leaf_hash = TapLeafHash::from_script(&leaf.0, leaf.1);
+
+        let mut inp = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2tr_tweaked(spend_info.output_key()),
+            }),
+            tap_internal_key: Some(internal_key),
+            tap_merkle_root: spend_info.merkle_root(),
+            ..Default::default()
+        };
+        inp.tap_scripts.insert(control_block, leaf);
+        inp.tap_key_origins
+            .insert(internal_key, (vec![], (fp, internal_path)));
+        inp.tap_key_origins
+            .insert(leaf_key, (vec![leaf_hash], (fp, leaf_path)));
+        (inp, spend_info)
+    }
+
+    #[test]
+    fn psbt_accepts_p2tr_script_path_spend() {
+        let secp = test_secp();
+        let master = test_master_key();
+        let (inp, _) = p2tr_script_path_input(&secp, "m/86'/1'/0'/0/0", "m/86'/1'/0'/0/1");
+
+        let fake_txid =
+            Txid::from_str("2424242424242424242424242424242424242424242424242424242424242424")
+                .unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(dummy_unsigned_tx(fake_txid)).unwrap();
+        psbt.inputs = vec![inp];
+
+        let result = validate(&secp, &master, &psbt, Network::Testnet);
+        assert!(
+            result.is_ok(),
+            "script path spend must be accepted, got: {result:?}"
+        );
+        let details = result.unwrap();
+        assert_eq!(details.inputs.len(), 1);
+        // the descriptor of a script path spend isn't known
+        assert!(details.descriptors.is_empty());
+    }
+
+    #[test]
+    fn psbt_rejects_p2tr_control_block_of_another_tree() {
+        let secp = test_secp();
+        let master = test_master_key();
+        let (mut inp, _) = p2tr_script_path_input(&secp, "m/86'/1'/0'/0/0", "m/86'/1'/0'/0/1");
+        let (other, _) = p2tr_script_path_input(&secp, "m/86'/1'/0'/0/0", "m/86'/1'/0'/0/2");
+
+        // the leaf of the other tree isn't committed to by the output key
+        inp.tap_scripts = other.tap_scripts;
+        inp.tap_key_origins = other.tap_key_origins;
+
+        let fake_txid =
+            Txid::from_str("4242424242424242424242424242424242424242424242424242424242424242")
+                .unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(dummy_unsigned_tx(fake_txid)).unwrap();
+        psbt.inputs = vec![inp];
+
+        assert!(
+            matches!(
+                validate(&secp, &master, &psbt, Network::Testnet),
+                Err(Error::InvalidTapScript { index: 0 })
+            ),
+            "a leaf not committed to by the output key must be rejected"
+        );
+    }
+
+    #[test]
+    fn psbt_rejects_p2tr_key_not_in_leaf() {
+        let secp = test_secp();
+        let master = test_master_key();
+        let (mut inp, spend_info) =
+            p2tr_script_path_input(&secp, "m/86'/1'/0'/0/0", "m/86'/1'/0'/0/1");
+
+        // claims a key of ours is in the leaf while it isn't
+        let (pk, path, fp) = derive_test_key(&secp, "m/86'/1'/0'/0/3");
+        let leaf_hashes = spend_info
+            .script_map()
+            .keys()
+            .map(|(script, version)| TapLeafHash::from_script(script, *version))
+            .collect();
+        inp.tap_key_origins
+            .insert(pk.x_only_public_key().0, (leaf_hashes, (fp, path)));
+
+        let fake_txid =
+            Txid::from_str("4343434343434343434343434343434343434343434343434343434343434343")
+                .unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(dummy_unsigned_tx(fake_txid)).unwrap();
+        psbt.inputs = vec![inp];
+
+        assert!(
+            matches!(
+                validate(&secp, &master, &psbt, Network::Testnet),
+                Err(Error::FraudulentInput { index: 0 })
+            ),
+            "a key missing from the leaf it claims must be rejected"
+        );
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "envoy")]
 mod spend_tests {
     use crate::utils::tests_util;
+    use bdk_wallet::bitcoin::psbt::{self, Psbt};
+    use bdk_wallet::bitcoin::{Address, Amount, OutPoint, TxOut, Txid, Weight};
     use bdk_wallet::rusqlite::Connection;
     use ngwallet::account::NgAccount;
+    #[cfg(feature = "envoy")]
+    use ngwallet::fee_rate::FeeEstimator;
+    use ngwallet::fee_rate::FeeRateSatPerKwu;
+    use ngwallet::psbt::memo::PsbtMemo;
     use ngwallet::rbf::BumpFeeError;
     use ngwallet::send::{
-        DraftTransaction, FeeRateSatPerKvb, TransactionComposeError, TransactionParams,
+        BroadcastEndpoint, DraftTransaction, FeeRateSatPerKvb, FeeSharing, OutputOrdering,
+        SpendPath, TRANSFER_TAG, TransactionComposeError, TransactionParams, TxWarning,
     };
+    use std::str::FromStr;
+    use std::sync::Mutex;
 
     use crate::utils::tests_util::get_ng_hot_wallet;
 
@@ -387,11 +524,124 @@ mod spend_tests {
             note: Some("not a note".to_string()),
             tag: Some("hello".to_string()),
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
-        let draft = account.get_max_fee(params.clone()).unwrap();
-        assert_eq!(draft.max_fee_rate, FeeRateSatPerKvb(553_828)); // 138_457 sat/kwu * 4 = sat/kvB
+        let draft = account.get_max_fee(params.clone(), None).unwrap();
+        // 98_997 sats of fee over the 717 wu of the P2TR and P2WPKH inputs
+        // and the recipient, less the rounding of the fee of each part
+        assert_eq!(draft.max_fee_rate, FeeRateSatPerKvb(552_276));
         assert_eq!(draft.min_fee_rate, FeeRateSatPerKvb(1000)); // 1 sat/vB in sat/kvB
+        assert_eq!(draft.recommended_fee_rate, None);
         check_draft_tx_match_params(draft.draft_transaction.clone(), params.clone());
+        check_max_fee_rate_is_tight(&account, params);
+    }
+
+    #[test]
+    fn test_max_fee_calc_mixed_inputs() {
+        let mut account = get_ng_hot_wallet();
+        // the P2TR output is spent to a P2SH-P2WPKH change output
+        tests_util::add_funds_wallet_with_unconfirmed(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 2003,
+            fee_rate: FeeRateSatPerKvb(1000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        let utxos = account.utxos().unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert!(utxos.iter().any(|utxo| utxo.address.starts_with('2')));
+        assert!(utxos.iter().any(|utxo| utxo.address.starts_with("tb1q")));
+        check_max_fee_rate_is_tight(&account, params);
+    }
+
+    // Composing at the maximum fee rate spends everything, one sat/kwu
+    // above it, the finest rate BDK tells apart, doesn't fit.
+    fn check_max_fee_rate_is_tight(account: &NgAccount<Connection>, params: TransactionParams) {
+        let max_fee_rate = account
+            .get_max_fee(params.clone(), None)
+            .unwrap()
+            .max_fee_rate;
+        let draft = account
+            .compose_psbt(TransactionParams {
+                fee_rate: max_fee_rate,
+                ..params.clone()
+            })
+            .unwrap();
+        assert_eq!(draft.transaction.inputs.len(), 2);
+        assert_eq!(draft.transaction.outputs.len(), 1);
+
+        let above =
+            FeeRateSatPerKvb::from(FeeRateSatPerKwu(FeeRateSatPerKwu::from(max_fee_rate).0 + 1));
+        assert!(matches!(
+            account.compose_psbt(TransactionParams {
+                fee_rate: above,
+                ..params
+            }),
+            Err(TransactionComposeError::CreateTxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_recommended_fee_rate() {
+        struct FixedEstimate(FeeRateSatPerKvb);
+        impl FeeEstimator for FixedEstimate {
+            fn estimate(&self, _target_blocks: u16) -> anyhow::Result<FeeRateSatPerKvb> {
+                Ok(self.0)
+            }
+        }
+
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 2003,
+            fee_rate: FeeRateSatPerKvb(1000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+
+        let result = account
+            .get_max_fee(
+                params.clone(),
+                Some(&FixedEstimate(FeeRateSatPerKvb(5_000))),
+            )
+            .unwrap();
+        assert_eq!(result.recommended_fee_rate, Some(FeeRateSatPerKvb(5_000)));
+
+        // estimates are kept between the minimum and the maximum
+        let result = account
+            .get_max_fee(params.clone(), Some(&FixedEstimate(FeeRateSatPerKvb(100))))
+            .unwrap();
+        assert_eq!(result.recommended_fee_rate, Some(result.min_fee_rate));
+        let result = account
+            .get_max_fee(params, Some(&FixedEstimate(FeeRateSatPerKvb(u64::MAX / 8))))
+            .unwrap();
+        assert_eq!(result.recommended_fee_rate, Some(result.max_fee_rate));
     }
 
     #[test]
@@ -406,11 +656,221 @@ mod spend_tests {
             note: Some("not a note".to_string()),
             tag: Some("hello".to_string()),
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
         let draft = account.compose_psbt(params.clone()).unwrap();
         check_draft_tx_match_params(draft, params.clone());
     }
 
+    #[test]
+    fn test_broadcast_to_all_unreachable() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        let draft = account.compose_psbt(params).unwrap();
+        assert!(draft.is_finalized);
+
+        assert!(NgAccount::<Connection>::broadcast_to_all(&draft, &[], None).is_err());
+
+        // nothing listens on these ports, the same server listed twice
+        // is reported twice
+        let endpoint = |url: &str| BroadcastEndpoint::Electrum {
+            url: url.to_string(),
+            validate_domain: Some(false),
+        };
+        let endpoints = [
+            endpoint("tcp://127.0.0.1:1"),
+            endpoint("tcp://127.0.0.1:1"),
+            endpoint("tcp://127.0.0.1:2"),
+        ];
+        let report = NgAccount::<Connection>::broadcast_to_all(&draft, &endpoints, None).unwrap();
+        assert!(!report.is_success());
+        assert_eq!(report.accepted_by, None);
+        assert_eq!(report.errors.keys().copied().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_compose_warns_about_used_own_address() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000), // 2 sat/vB in sat/kvB
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        assert!(draft.warnings.is_empty());
+
+        let used_address = account.utxos().unwrap()[0].address.clone();
+        let draft = account
+            .compose_psbt(TransactionParams {
+                address: used_address.clone(),
+                ..params
+            })
+            .unwrap();
+        assert_eq!(
+            draft.warnings,
+            vec![TxWarning::UsedOwnAddress {
+                address: used_address
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compose_sends_change_to_other_account() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+        let other_account = tests_util::get_ng_watch_only_account();
+        let change_address = other_account.transfer_address().unwrap();
+        assert!(other_account.owns_address(&change_address).unwrap());
+        assert!(!account.owns_address(&change_address).unwrap());
+
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000), // 2 sat/vB in sat/kvB
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: Some(change_address.clone()),
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        let outputs = &draft.transaction.outputs;
+        assert_eq!(outputs.len(), 2);
+        let transfer = outputs
+            .iter()
+            .find(|output| output.address == change_address)
+            .expect("change sent to the other account");
+        assert_eq!(transfer.tag.as_deref(), Some(TRANSFER_TAG));
+        assert!(transfer.keychain.is_none());
+
+        let own_address = account.utxos().unwrap()[0].address.clone();
+        assert!(matches!(
+            account.compose_psbt(TransactionParams {
+                change_address: Some(own_address),
+                ..params
+            }),
+            Err(TransactionComposeError::Error(_))
+        ));
+    }
+
+    #[test]
+    fn test_compose_with_foreign_input() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+        let recipient = "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w";
+        let foreign_outpoint = OutPoint::new(
+            Txid::from_str("1111111111111111111111111111111111111111111111111111111111111111")
+                .unwrap(),
+            0,
+        );
+        let foreign_input = |value: u64| psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")
+                    .unwrap()
+                    .assume_checked()
+                    .script_pubkey(),
+            }),
+            ..Default::default()
+        };
+        let params = TransactionParams {
+            address: recipient.to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            // a P2WPKH signature and key
+            foreign_inputs: vec![(
+                foreign_outpoint,
+                foreign_input(10_000),
+                Weight::from_wu(108),
+            )],
+            fee_sharing: FeeSharing::ByInputWeight,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        let contribution = draft.foreign_contribution.unwrap();
+        assert_eq!(contribution.amount, 10_000);
+        // 273 wu at 2 sat/vB
+        assert_eq!(contribution.fee, 137);
+        assert_eq!(draft.transaction.amount, -4000);
+        let psbt = Psbt::deserialize(&draft.psbt).unwrap();
+        assert!(
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == foreign_outpoint)
+        );
+        let recipient_script = Address::from_str(recipient)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey();
+        let paid = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|output| output.script_pubkey == recipient_script)
+            .unwrap();
+        assert_eq!(paid.value.to_sat(), 4000 + 10_000 - 137);
+
+        // the foreign input can't pay its share of the fee
+        let params = TransactionParams {
+            foreign_inputs: vec![(foreign_outpoint, foreign_input(100), Weight::from_wu(108))],
+            ..params
+        };
+        assert!(matches!(
+            account.compose_psbt(params),
+            Err(TransactionComposeError::Error(_))
+        ));
+    }
+
     #[test]
     #[cfg(feature = "envoy")]
     fn test_check_compose_increment_index() {
@@ -425,6 +885,13 @@ mod spend_tests {
             note: Some("not a note".to_string()),
             tag: Some("hello".to_string()),
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
         let draft = account.compose_psbt(params.clone()).unwrap();
         check_draft_tx_match_params(draft, params.clone());
@@ -463,6 +930,13 @@ mod spend_tests {
             note: Some("not a note".to_string()),
             tag: Some("hello".to_string()),
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
 
         let draft = account.compose_psbt(params.clone()).unwrap();
@@ -496,12 +970,126 @@ mod spend_tests {
             .find(|tx| tx.confirmations == 0)
             .unwrap();
         let rbf_max_result = account
-            .get_max_bump_fee(vec![], unconfirmed_tx.clone())
+            .get_max_bump_fee(vec![], unconfirmed_tx.clone(), None)
             .expect("Failed to get max bump fee");
 
-        assert_eq!(rbf_max_result.max_fee_rate, FeeRateSatPerKvb(126_896)); // 31_724 sat/kwu * 4 = sat/kvB
+        // the 76_000 sats input and the confirmed 25_000 sats, less the
+        // 800 sats recipient
+        assert_eq!(rbf_max_result.max_fee, 100_200);
+        assert_eq!(
+            rbf_max_result.min_fee,
+            unconfirmed_tx.fee + unconfirmed_tx.vsize as u64
+        );
+        assert!(rbf_max_result.draft_transaction.transaction.fee >= rbf_max_result.min_fee);
         assert!(unconfirmed_tx.fee_rate < rbf_max_result.min_fee_rate);
-        //
+        assert!(rbf_max_result.min_fee_rate < rbf_max_result.max_fee_rate);
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn test_rbf_spending_policy() {
+        use ngwallet::error::error_code;
+        use ngwallet::policy::SpendingPolicy;
+
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_wallet_with_unconfirmed(&mut account);
+        let unconfirmed_tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.confirmations == 0)
+            .unwrap();
+        account
+            .set_spending_policy(Some(SpendingPolicy {
+                max_per_tx: Some(5000),
+                max_per_day: Some(5000),
+                cosign_above: None,
+            }))
+            .unwrap();
+
+        // bumping the fee sends the same, the replaced transaction
+        // doesn't count twice
+        let bounds = account
+            .get_max_bump_fee(vec![], unconfirmed_tx.clone(), None)
+            .unwrap();
+        assert!(bounds.draft_transaction.is_finalized);
+
+        // draining every input to a foreign address
+        let foreign =
+            Address::from_str("tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w")
+                .unwrap()
+                .assume_checked();
+        match account.get_rbf_draft_tx(
+            vec![],
+            unconfirmed_tx.clone(),
+            FeeRateSatPerKwu::from(bounds.min_fee_rate),
+            None,
+            Some(foreign),
+            None,
+            None,
+        ) {
+            Err(BumpFeeError::SigningRefused(error)) => {
+                assert_eq!(error_code(&error), Some(6000));
+            }
+            other => panic!("expected SigningRefused, got {other:?}"),
+        }
+
+        // cancelling pays the account back
+        assert!(account.compose_cancellation_tx(unconfirmed_tx).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "envoy")]
+    fn test_suggest_fee_bumps() {
+        struct FixedEstimate(FeeRateSatPerKvb);
+        impl FeeEstimator for FixedEstimate {
+            fn estimate(&self, _target_blocks: u16) -> anyhow::Result<FeeRateSatPerKvb> {
+                Ok(self.0)
+            }
+        }
+
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_wallet_with_unconfirmed(&mut account);
+        let unconfirmed_tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.confirmations == 0)
+            .unwrap();
+
+        // the unconfirmed transaction pays 1 sat/vB
+        let suggestions = account
+            .suggest_fee_bumps(6, &FixedEstimate(FeeRateSatPerKvb(500)))
+            .unwrap();
+        assert!(suggestions.is_empty());
+
+        let suggestions = account
+            .suggest_fee_bumps(2, &FixedEstimate(FeeRateSatPerKvb(5_000)))
+            .unwrap();
+        assert_eq!(suggestions.len(), 1);
+        let suggestion = &suggestions[0];
+        assert_eq!(suggestion.tx_id, unconfirmed_tx.tx_id);
+        assert_eq!(suggestion.bumped_fee_rate, FeeRateSatPerKvb(5_000));
+        assert!(suggestion.draft_transaction.transaction.fee > unconfirmed_tx.fee);
+        assert_eq!(
+            suggestion.draft_transaction.transaction.address,
+            unconfirmed_tx.address
+        );
+
+        // a locked change output keeps the transaction as it is
+        let change = account
+            .utxos()
+            .unwrap()
+            .into_iter()
+            .find(|utxo| utxo.tx_id == unconfirmed_tx.tx_id)
+            .unwrap();
+        account
+            .set_do_not_spend(change.get_id().as_str(), true)
+            .unwrap();
+        let suggestions = account
+            .suggest_fee_bumps(2, &FixedEstimate(FeeRateSatPerKvb(5_000)))
+            .unwrap();
+        assert!(suggestions.is_empty());
     }
 
     //
@@ -517,6 +1105,159 @@ mod spend_tests {
     // the caller passes it explicitly in `selected_outputs`. Both the send
     // and RBF paths share this policy and must surface a dedicated error.
 
+    #[test]
+    fn test_logs_carry_no_private_keys() {
+        struct Capture(Mutex<Vec<String>>);
+        impl log::Log for Capture {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{record:?} {}", record.args()));
+            }
+            fn flush(&self) {}
+        }
+        static LOGS: Capture = Capture(Mutex::new(vec![]));
+        log::set_logger(&LOGS).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_wallet_with_unconfirmed(&mut account);
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        account.get_max_fee(params.clone(), None).unwrap();
+        account.compose_psbt(params).unwrap();
+        let unconfirmed_tx = account
+            .transactions()
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.confirmations == 0)
+            .unwrap();
+        let _ = account.get_max_bump_fee(vec![], unconfirmed_tx, None);
+        log::info!("{account:?}");
+
+        let logs = LOGS.0.lock().unwrap();
+        assert!(!logs.is_empty());
+        for line in logs.iter() {
+            assert!(
+                !line.contains("xprv") && !line.contains("tprv"),
+                "private key in the logs: {line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compose_psbt_rejects_dust_amount() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 200,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: None,
+            tag: None,
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
+        };
+        // 330 sats for a taproot output at the default dust relay fee
+        match account.compose_psbt(params.clone()) {
+            Err(TransactionComposeError::DustAmount { amount, dust_limit }) => {
+                assert_eq!((amount, dust_limit), (200, 330));
+            }
+            other => panic!("expected DustAmount, got {other:?}"),
+        }
+        assert!(matches!(
+            account.get_max_fee(params.clone(), None),
+            Err(TransactionComposeError::DustAmount { .. })
+        ));
+
+        account
+            .set_dust_relay_fee(Some(FeeRateSatPerKvb(10_000)))
+            .unwrap();
+        let params = TransactionParams {
+            amount: 1000,
+            ..params
+        };
+        match account.compose_psbt(params) {
+            Err(TransactionComposeError::DustAmount { amount, dust_limit }) => {
+                assert_eq!((amount, dust_limit), (1000, 1100));
+            }
+            other => panic!("expected DustAmount, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_compose_psbt_embeds_memo() {
+        let mut account = get_ng_hot_wallet();
+        tests_util::add_funds_to_wallet(&mut account);
+
+        let params = TransactionParams {
+            address: "tb1pspfcrvz538vvj9f9gfkd85nu5ty98zw9y5e302kha6zurv6vg07s8z7a8w".to_string(),
+            amount: 4000,
+            fee_rate: FeeRateSatPerKvb(2000),
+            selected_outputs: vec![],
+            note: Some("Rent".to_string()),
+            tag: Some("Savings".to_string()),
+            do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: true,
+        };
+        let draft = account.compose_psbt(params.clone()).unwrap();
+        let memo = PsbtMemo::read(&Psbt::deserialize(&draft.psbt).unwrap());
+        assert_eq!(memo.note.as_deref(), Some("Rent"));
+        assert_eq!(memo.change_tag.as_deref(), Some("Savings"));
+        assert_eq!(
+            account.get_bitcoin_tx_from_psbt(&draft.psbt).unwrap().note,
+            Some("Rent".to_string())
+        );
+
+        // a draft rebuilt without the metadata gets it from the PSBT
+        let mut bare = draft.clone();
+        bare.transaction.note = None;
+        bare.change_out_put_tag = None;
+        let decoded = NgAccount::<Connection>::decode_psbt(bare, &draft.psbt).unwrap();
+        assert_eq!(decoded.transaction.note.as_deref(), Some("Rent"));
+        assert_eq!(decoded.change_out_put_tag.as_deref(), Some("Savings"));
+
+        let draft = account
+            .compose_psbt(TransactionParams {
+                embed_memo: false,
+                ..params
+            })
+            .unwrap();
+        assert!(PsbtMemo::read(&Psbt::deserialize(&draft.psbt).unwrap()).is_empty());
+    }
+
     #[test]
     fn test_compose_psbt_rejects_locked_selected_utxo() {
         let mut account = get_ng_hot_wallet();
@@ -542,6 +1283,13 @@ mod spend_tests {
             note: None,
             tag: None,
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
         match account.compose_psbt(params) {
             Err(TransactionComposeError::LockedUtxoSelected(ids)) => {
@@ -584,6 +1332,13 @@ mod spend_tests {
             note: None,
             tag: None,
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
         match account.compose_psbt(params) {
             Err(TransactionComposeError::LockedUtxoSelected(ids)) => {
@@ -617,8 +1372,15 @@ mod spend_tests {
             note: None,
             tag: None,
             do_not_spend_change: false,
+            ordering: OutputOrdering::Shuffle,
+            change_address: None,
+            spend_path: SpendPath::Primary,
+            foreign_inputs: vec![],
+            fee_sharing: FeeSharing::Ours,
+            spend_from_tag: None,
+            embed_memo: false,
         };
-        match account.get_max_fee(params) {
+        match account.get_max_fee(params, None) {
             Err(TransactionComposeError::LockedUtxoSelected(ids)) => {
                 assert_eq!(ids, vec![locked_live.get_id()]);
             }
@@ -656,7 +1418,7 @@ mod spend_tests {
             .find(|tx| tx.confirmations == 0)
             .expect("expected an unconfirmed tx for RBF");
 
-        match account.get_max_bump_fee(vec![locked_live.clone()], unconfirmed_tx) {
+        match account.get_max_bump_fee(vec![locked_live.clone()], unconfirmed_tx, None) {
             Err(BumpFeeError::LockedUtxoSelected(ids)) => {
                 assert_eq!(ids, vec![locked_live.get_id()]);
             }
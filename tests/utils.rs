@@ -139,7 +139,9 @@ pub mod tests_util {
             .duration_since(UNIX_EPOCH)
             .expect("")
             .as_secs();
-        insert_seen_at(&mut wallet, txid, last_seen)
+        insert_seen_at(&mut wallet, txid, last_seen);
+        drop(wallet);
+        ngwallet.refresh();
     }
 
     fn fill_with_txes<P: WalletPersister>(index: usize, ngwallet: &&NgWallet<P>) {
@@ -188,6 +190,8 @@ pub mod tests_util {
                 confirmation_time: 100,
             },
         );
+        drop(wallet);
+        ngwallet.refresh();
     }
 
     // Anchors the tx at the tip so `tip_height - block_height + 1 == 1`.
@@ -230,6 +234,8 @@ pub mod tests_util {
                 confirmation_time: 100,
             },
         );
+        drop(wallet);
+        ngwallet.refresh();
     }
 }
 //creates a new account with the descriptors,in memory db's